@@ -0,0 +1,165 @@
+use ironfish_core::{Error, Result};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+const MAX_RETRY_AFTER_SECS: u64 = 60;
+pub struct AnalysisAdmission {
+    permits: Arc<Semaphore>,
+    limit: AtomicUsize,
+    in_flight: Arc<AtomicUsize>,
+    avg_duration_ms: Arc<AtomicU64>,
+}
+pub struct AdmissionPermit {
+    _permit: OwnedSemaphorePermit,
+    started: Instant,
+    in_flight: Arc<AtomicUsize>,
+    avg_duration_ms: Arc<AtomicU64>,
+}
+impl AnalysisAdmission {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit: AtomicUsize::new(limit),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            avg_duration_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+    pub fn try_admit(&self) -> Result<AdmissionPermit> {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => Ok(AdmissionPermit::new(permit, self)),
+            Err(_) => Err(Error::TooManyAnalyses {
+                retry_after_secs: self.retry_after_secs(),
+            }),
+        }
+    }
+    pub fn try_admit_up_to(&self, max: usize) -> Result<Vec<AdmissionPermit>> {
+        let mut permits = vec![self.try_admit()?];
+        while permits.len() < max {
+            match self.permits.clone().try_acquire_owned() {
+                Ok(permit) => permits.push(AdmissionPermit::new(permit, self)),
+                Err(_) => break,
+            }
+        }
+        Ok(permits)
+    }
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+    pub fn set_limit(&self, limit: usize) {
+        let limit = limit.max(1);
+        let previous = self.limit.swap(limit, Ordering::SeqCst);
+        if limit > previous {
+            self.permits.add_permits(limit - previous);
+            return;
+        }
+        let excess = previous - limit;
+        let pending = excess - self.permits.forget_permits(excess);
+        if pending > 0 {
+            let permits = self.permits.clone();
+            tokio::spawn(async move {
+                if let Ok(permit) = permits.acquire_many_owned(pending as u32).await {
+                    permit.forget();
+                }
+            });
+        }
+    }
+    pub fn retry_after_secs(&self) -> u64 {
+        self.avg_duration_ms
+            .load(Ordering::SeqCst)
+            .div_ceil(1000)
+            .clamp(1, MAX_RETRY_AFTER_SECS)
+    }
+}
+impl Default for AnalysisAdmission {
+    fn default() -> Self {
+        Self::new(ironfish_core::AnalysisLimits::default().max_concurrent_analyses as usize)
+    }
+}
+impl AdmissionPermit {
+    fn new(permit: OwnedSemaphorePermit, admission: &AnalysisAdmission) -> Self {
+        admission.in_flight.fetch_add(1, Ordering::SeqCst);
+        Self {
+            _permit: permit,
+            started: Instant::now(),
+            in_flight: admission.in_flight.clone(),
+            avg_duration_ms: admission.avg_duration_ms.clone(),
+        }
+    }
+}
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let sample = self.started.elapsed().as_millis() as u64;
+        let _ = self
+            .avg_duration_ms
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |avg| match avg {
+                0 => Some(sample.max(1)),
+                avg => Some((avg * 7 + sample) / 8),
+            });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn test_admission_limit_and_retry_after() {
+        let admission = AnalysisAdmission::new(1);
+        assert_eq!(admission.retry_after_secs(), 1);
+        let permit = admission.try_admit().unwrap();
+        assert_eq!(admission.in_flight(), 1);
+        assert!(matches!(
+            admission.try_admit(),
+            Err(Error::TooManyAnalyses {
+                retry_after_secs: 1
+            })
+        ));
+        drop(permit);
+        assert_eq!(admission.in_flight(), 0);
+        admission.avg_duration_ms.store(4200, Ordering::SeqCst);
+        let _permit = admission.try_admit().unwrap();
+        assert!(matches!(
+            admission.try_admit(),
+            Err(Error::TooManyAnalyses {
+                retry_after_secs: 5
+            })
+        ));
+        admission.avg_duration_ms.store(600_000, Ordering::SeqCst);
+        assert_eq!(admission.retry_after_secs(), MAX_RETRY_AFTER_SECS);
+    }
+    #[tokio::test]
+    async fn test_admit_up_to_takes_the_free_headroom() {
+        let admission = AnalysisAdmission::new(4);
+        let held = admission.try_admit().unwrap();
+        let permits = admission.try_admit_up_to(8).unwrap();
+        assert_eq!((permits.len(), admission.in_flight()), (3, 4));
+        assert!(admission.try_admit_up_to(2).is_err());
+        drop(permits);
+        assert_eq!(admission.try_admit_up_to(2).unwrap().len(), 2);
+        assert_eq!(admission.try_admit_up_to(0).unwrap().len(), 1);
+        drop(held);
+        assert_eq!(admission.in_flight(), 0);
+    }
+    #[tokio::test]
+    async fn test_admission_limit_resizes() {
+        let admission = AnalysisAdmission::new(2);
+        let first = admission.try_admit().unwrap();
+        let second = admission.try_admit().unwrap();
+        admission.set_limit(1);
+        tokio::task::yield_now().await;
+        assert_eq!((admission.limit(), admission.in_flight()), (1, 2));
+        drop(first);
+        assert!(admission.try_admit().is_err());
+        drop(second);
+        let _third = admission.try_admit().unwrap();
+        assert!(admission.try_admit().is_err());
+        admission.set_limit(3);
+        let _fourth = admission.try_admit().unwrap();
+        let _fifth = admission.try_admit().unwrap();
+        assert_eq!(admission.in_flight(), 3);
+    }
+}
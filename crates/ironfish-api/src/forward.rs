@@ -0,0 +1,747 @@
+use crate::proto::cluster_admin_client::ClusterAdminClient;
+use crate::proto::{
+    JoinRequest as ProtoJoinRequest, JoinResponse as ProtoJoinResponse,
+    LeaveRequest as ProtoLeaveRequest, LeaveResponse as ProtoLeaveResponse,
+};
+use crate::request_id::{current_request_id, REQUEST_ID_HEADER};
+use crate::rest::ApiError;
+use crate::tls::PeerTls;
+use crate::ApiState;
+use async_graphql::parser::parse_query;
+use async_graphql::parser::types::{ExecutableDocument, OperationType, Selection, SelectionSet};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, OriginalUri, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::future::Either;
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_TYPE, HOST, TRANSFER_ENCODING,
+};
+use hyper::StatusCode;
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use ironfish_auth::{source_ip, TrustedProxies};
+use ironfish_cluster::MembershipManager;
+use ironfish_core::{
+    AnalysisProgress, AnalysisRequest, AnalysisResult, ClientMessage, Error, LoadBalancer, NodeId,
+    NodeInfo, NodeMetrics, Result, ServerMessage,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+use tracing::debug;
+pub const FORWARDED_FROM_HEADER: &str = "x-ironfish-forwarded-from";
+pub const SERVED_BY_HEADER: &str = "x-ironfish-node";
+pub const FORWARDED_SECRET_HEADER: &str = "x-ironfish-forwarded-secret";
+const TOKEN_MUTATIONS: [&str; 2] = ["createToken", "revokeToken"];
+const MAX_FRAGMENT_DEPTH: usize = 8;
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+const ADMIN_BODY_LIMIT: usize = 1024 * 1024;
+const LEADER_FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+const STREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RELAY_AUTH_ID: &str = "relay-auth";
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminWrites {
+    #[default]
+    Local,
+    Leader,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardingConfig {
+    pub enabled: bool,
+    pub cpu_threshold: f32,
+    pub timeout_secs: u64,
+}
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cpu_threshold: 0.9,
+            timeout_secs: 60,
+        }
+    }
+}
+pub struct AnalysisForwarder {
+    config: ForwardingConfig,
+    load_balancer: Arc<dyn LoadBalancer>,
+    membership: Arc<MembershipManager>,
+    client: PeerClient<Full<Bytes>>,
+    tls: Option<PeerTls>,
+    secret: Option<String>,
+}
+impl AnalysisForwarder {
+    pub fn new(
+        config: ForwardingConfig,
+        load_balancer: Arc<dyn LoadBalancer>,
+        membership: Arc<MembershipManager>,
+    ) -> Self {
+        Self {
+            config,
+            load_balancer,
+            membership,
+            client: Client::builder(TokioExecutor::new()).build(peer_connector(None, false)),
+            tls: None,
+            secret: None,
+        }
+    }
+    pub fn with_secret(mut self, secret: Option<&str>) -> Self {
+        self.secret = secret.filter(|s| !s.is_empty()).map(String::from);
+        self
+    }
+    pub fn with_tls(mut self, tls: Option<PeerTls>) -> Self {
+        self.client =
+            Client::builder(TokioExecutor::new()).build(peer_connector(tls.as_ref(), false));
+        self.tls = tls;
+        self
+    }
+    pub fn should_forward(&self, local: &NodeMetrics) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        let saturated = local.engines_total > 0 && local.engines_available == 0;
+        saturated || local.cpu_usage >= self.config.cpu_threshold
+    }
+    pub async fn forward(
+        &self,
+        local_id: &NodeId,
+        request: &AnalysisRequest,
+        authorization: Option<&str>,
+    ) -> Result<(NodeId, AnalysisResult)> {
+        if request.infinite {
+            return Err(Error::InvalidArgument(
+                "infinite analysis can only be forwarded over a stream".to_string(),
+            ));
+        }
+        let member = self.select_peer(local_id, request).await?;
+        let peer = member.id.clone();
+        let body = serde_json::to_vec(request)?;
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let url = format!("{}://{}/v1/analyze", scheme, member.address);
+        let mut builder = hyper::Request::post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(FORWARDED_FROM_HEADER, local_id.to_string());
+        if let Some(ref secret) = self.secret {
+            builder = builder.header(FORWARDED_SECRET_HEADER, secret.as_str());
+        }
+        if let Some(authorization) = authorization {
+            builder = builder.header(AUTHORIZATION, authorization);
+        }
+        if let Some(request_id) = current_request_id() {
+            builder = builder.header(REQUEST_ID_HEADER, request_id);
+        }
+        let http_request = builder
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| Error::Network(format!("invalid forward request: {}", e)))?;
+        debug!("forwarding analysis {} to {}", request.id, peer);
+        let response = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout_secs),
+            self.client.request(http_request),
+        )
+        .await
+        .map_err(|_| Error::AnalysisTimeout)?
+        .map_err(|e| Error::Network(format!("forward to {} failed: {}", peer, e)))?;
+        let status = response.status();
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| Error::Network(format!("forward to {} failed: {}", peer, e)))?
+            .to_bytes();
+        if !status.is_success() {
+            return Err(Error::Network(format!("peer {} returned {}", peer, status)));
+        }
+        let mut result: AnalysisResult = serde_json::from_slice(&bytes)?;
+        result.id = request.id;
+        result.clamped |= request.clamped;
+        Ok((peer, result))
+    }
+    pub async fn forward_streaming(
+        &self,
+        local_id: &NodeId,
+        request: &AnalysisRequest,
+        service_token: &str,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<(NodeId, AnalysisResult)> {
+        let member = self.select_peer(local_id, request).await?;
+        let peer = member.id;
+        let failed =
+            |e: &dyn std::fmt::Display| Error::Network(format!("stream to {} failed: {}", peer, e));
+        let scheme = if self.tls.is_some() { "wss" } else { "ws" };
+        let url = format!("{}://{}/v1/ws", scheme, member.address);
+        let connect = async {
+            match self.tls {
+                Some(ref tls) => {
+                    let tcp = TcpStream::connect(member.address)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                    let name = tls
+                        .server_name()
+                        .unwrap_or_else(|| ServerName::IpAddress(member.address.ip().into()));
+                    let stream = TlsConnector::from(tls.client_config())
+                        .connect(name, tcp)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                    let (socket, _) = tokio_tungstenite::client_async(&url, stream)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                    Ok::<_, Error>(Either::Left(socket))
+                }
+                None => {
+                    let (socket, _) = tokio_tungstenite::connect_async(&url)
+                        .await
+                        .map_err(|e| failed(&e))?;
+                    Ok(Either::Right(socket))
+                }
+            }
+        };
+        let socket = tokio::time::timeout(STREAM_CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| Error::Network(format!("peer {} did not accept the stream", peer)))??;
+        match socket {
+            Either::Left(socket) => {
+                self.relay(socket, peer, request, service_token, progress_tx, cancel)
+                    .await
+            }
+            Either::Right(socket) => {
+                self.relay(socket, peer, request, service_token, progress_tx, cancel)
+                    .await
+            }
+        }
+    }
+    async fn relay<S>(
+        &self,
+        socket: WebSocketStream<S>,
+        peer: NodeId,
+        request: &AnalysisRequest,
+        service_token: &str,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<(NodeId, AnalysisResult)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let failed =
+            |e: &dyn std::fmt::Display| Error::Network(format!("stream to {} failed: {}", peer, e));
+        let (mut sink, mut stream) = socket.split();
+        let message_id = request.id.to_string();
+        for message in [
+            ClientMessage::Auth {
+                id: RELAY_AUTH_ID.to_string(),
+                token: service_token.to_string(),
+            },
+            relayed_analysis(message_id.clone(), request),
+        ] {
+            let text = serde_json::to_string(&message)?;
+            sink.send(Message::Text(text.into()))
+                .await
+                .map_err(|e| failed(&e))?;
+        }
+        debug!("relaying streaming analysis {} to {}", request.id, peer);
+        let mut remote_id = None;
+        let mut cancelling = false;
+        loop {
+            let message = tokio::select! {
+                _ = cancel.cancelled(), if !cancelling => {
+                    let Some(analysis_id) = remote_id else {
+                        return Err(Error::AnalysisCancelled);
+                    };
+                    cancelling = true;
+                    let text = serde_json::to_string(&ClientMessage::Cancel {
+                        id: message_id.clone(),
+                        analysis_id,
+                    })?;
+                    if sink.send(Message::Text(text.into())).await.is_err() {
+                        return Err(Error::AnalysisCancelled);
+                    }
+                    continue;
+                }
+                message = stream.next() => message,
+            };
+            let text = match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err(Error::Network(format!("peer {} closed the stream", peer)))
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(failed(&e)),
+            };
+            match serde_json::from_str::<ServerMessage>(&text)? {
+                ServerMessage::AuthResult {
+                    success: false,
+                    error,
+                    ..
+                } => {
+                    return Err(Error::Network(format!(
+                        "peer {} rejected the service token: {}",
+                        peer,
+                        error.unwrap_or_default()
+                    )))
+                }
+                ServerMessage::AnalysisProgress {
+                    analysis_id,
+                    current_depth,
+                    target_depth,
+                    evaluation,
+                    wdl,
+                    principal_variations,
+                    nodes_per_second,
+                    elapsed_ms,
+                } => {
+                    remote_id = Some(analysis_id);
+                    let _ = progress_tx
+                        .send(AnalysisProgress {
+                            id: request.id,
+                            current_depth,
+                            target_depth,
+                            current_move: principal_variations
+                                .first()
+                                .and_then(|pv| pv.moves.first().cloned()),
+                            nodes_per_second,
+                            hash_full: 0,
+                            elapsed_ms,
+                            evaluation,
+                            wdl,
+                            principal_variations,
+                        })
+                        .await;
+                }
+                ServerMessage::AnalysisComplete { mut result, .. } => {
+                    result.id = request.id;
+                    result.clamped |= request.clamped;
+                    return Ok((peer, *result));
+                }
+                ServerMessage::AnalysisCancelled { .. } => return Err(Error::AnalysisCancelled),
+                ServerMessage::Error {
+                    id: Some(ref id),
+                    ref message,
+                    ..
+                } if *id == message_id => {
+                    return Err(Error::Network(format!(
+                        "peer {} failed the analysis: {}",
+                        peer, message
+                    )))
+                }
+                _ => {}
+            }
+        }
+    }
+    async fn select_peer(&self, local_id: &NodeId, request: &AnalysisRequest) -> Result<NodeInfo> {
+        let key = request
+            .validate()
+            .ok()
+            .and_then(|position| position.position_key())
+            .unwrap_or_else(|| request.fen.clone());
+        let peer = self
+            .load_balancer
+            .select_node_for_key(&key, std::slice::from_ref(local_id))
+            .await?;
+        self.membership
+            .get_member(&peer)
+            .await
+            .ok_or(Error::ClusterUnavailable)
+    }
+}
+fn relayed_analysis(id: String, request: &AnalysisRequest) -> ClientMessage {
+    if request.infinite {
+        return ClientMessage::AnalyzeInfinite {
+            id,
+            fen: request.fen.clone(),
+            startpos: false,
+            moves: request.moves.clone(),
+            search_moves: request.search_moves.clone(),
+            multipv: Some(request.multipv),
+            variant: request.variant,
+            perspective: request.perspective,
+            notation: request.notation,
+            include_history: request.include_history,
+        };
+    }
+    ClientMessage::Analyze {
+        id,
+        fen: request.fen.clone(),
+        startpos: false,
+        moves: request.moves.clone(),
+        search_moves: request.search_moves.clone(),
+        depth: request.depth,
+        multipv: Some(request.multipv),
+        movetime: request.movetime,
+        nodes: request.nodes,
+        variant: request.variant,
+        skill_level: request.skill_level,
+        perspective: request.perspective,
+        notation: request.notation,
+        include_history: request.include_history,
+    }
+}
+type PeerClient<B> = Client<HttpsConnector<HttpConnector>, B>;
+fn peer_connector(tls: Option<&PeerTls>, http2: bool) -> HttpsConnector<HttpConnector> {
+    let Some(tls) = tls else {
+        let mut http = HttpConnector::new();
+        http.enforce_http(true);
+        return HttpsConnector::from((http, Arc::new(plaintext_client_config())));
+    };
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config((*tls.client_config()).clone())
+        .https_only();
+    let builder = match tls.server_name() {
+        Some(name) => builder.with_server_name_resolver(FixedServerNameResolver::new(name)),
+        None => builder,
+    };
+    if http2 {
+        builder.enable_http2().build()
+    } else {
+        builder.enable_http1().build()
+    }
+}
+fn plaintext_client_config() -> ClientConfig {
+    ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth()
+}
+pub struct LeaderForwarder {
+    client: PeerClient<Full<Bytes>>,
+    grpc: PeerClient<tonic::body::Body>,
+    scheme: &'static str,
+    secret: Option<String>,
+    timeout: Duration,
+}
+impl Default for LeaderForwarder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl LeaderForwarder {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build(peer_connector(None, false)),
+            grpc: Client::builder(TokioExecutor::new())
+                .http2_only(true)
+                .build(peer_connector(None, true)),
+            scheme: "http",
+            secret: None,
+            timeout: LEADER_FORWARD_TIMEOUT,
+        }
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    pub fn with_secret(mut self, secret: Option<&str>) -> Self {
+        self.secret = secret.filter(|s| !s.is_empty()).map(String::from);
+        self
+    }
+    pub fn with_tls(mut self, tls: Option<PeerTls>) -> Self {
+        self.client =
+            Client::builder(TokioExecutor::new()).build(peer_connector(tls.as_ref(), false));
+        self.grpc = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build(peer_connector(tls.as_ref(), true));
+        self.scheme = if tls.is_some() { "https" } else { "http" };
+        self
+    }
+    fn forwarded_headers(&self, headers: &HeaderMap, local_id: &NodeId) -> HeaderMap {
+        let mut forwarded = HeaderMap::with_capacity(headers.len() + 2);
+        for (name, value) in headers.iter() {
+            if name != HOST
+                && name != ACCEPT_ENCODING
+                && name != FORWARDED_FROM_HEADER
+                && name != FORWARDED_SECRET_HEADER
+            {
+                forwarded.append(name.clone(), value.clone());
+            }
+        }
+        if let Ok(value) = HeaderValue::from_str(&local_id.to_string()) {
+            forwarded.insert(FORWARDED_FROM_HEADER, value);
+        }
+        if let Some(value) = self
+            .secret
+            .as_deref()
+            .and_then(|secret| HeaderValue::from_str(secret).ok())
+        {
+            forwarded.insert(FORWARDED_SECRET_HEADER, value);
+        }
+        forwarded
+    }
+    pub async fn forward(
+        &self,
+        leader: &NodeInfo,
+        local_id: &NodeId,
+        request: Request,
+    ) -> Result<Response> {
+        let source = source_ip(request.headers(), request.extensions());
+        let (parts, body) = request.into_parts();
+        let body = axum::body::to_bytes(body, ADMIN_BODY_LIMIT)
+            .await
+            .map_err(|e| Error::Network(format!("failed to read admin request: {}", e)))?;
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|OriginalUri(uri)| uri)
+            .unwrap_or(&parts.uri);
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        let mut builder = hyper::Request::builder()
+            .method(parts.method.clone())
+            .uri(format!("{}://{}{}", self.scheme, leader.address, path));
+        if let Some(headers) = builder.headers_mut() {
+            *headers = self.forwarded_headers(&parts.headers, local_id);
+            headers.remove(FORWARDED_FOR_HEADER);
+        }
+        if let Some(ip) = source {
+            builder = builder.header(FORWARDED_FOR_HEADER, ip);
+        }
+        let http_request = builder
+            .body(Full::new(body))
+            .map_err(|e| Error::Network(format!("invalid forward request: {}", e)))?;
+        debug!(
+            "forwarding {} {} to leader {}",
+            parts.method, path, leader.id
+        );
+        let response = tokio::time::timeout(self.timeout, self.client.request(http_request))
+            .await
+            .map_err(|_| Error::Network(format!("leader {} did not respond", leader.id)))?
+            .map_err(|e| Error::Network(format!("forward to {} failed: {}", leader.id, e)))?;
+        let (mut parts, body) = response.into_parts();
+        let bytes = body
+            .collect()
+            .await
+            .map_err(|e| Error::Network(format!("forward to {} failed: {}", leader.id, e)))?
+            .to_bytes();
+        parts.headers.remove(TRANSFER_ENCODING);
+        if let Ok(value) = HeaderValue::from_str(&leader.id.to_string()) {
+            parts.headers.insert(SERVED_BY_HEADER, value);
+        }
+        Ok(Response::from_parts(parts, Body::from(bytes)))
+    }
+    fn admin_client(
+        &self,
+        leader: &NodeInfo,
+    ) -> std::result::Result<ClusterAdminClient<PeerClient<tonic::body::Body>>, Status> {
+        let origin = format!("{}://{}", self.scheme, leader.address)
+            .parse()
+            .map_err(|e| Status::internal(format!("invalid leader address: {}", e)))?;
+        Ok(ClusterAdminClient::with_origin(self.grpc.clone(), origin))
+    }
+    fn grpc_request<T>(&self, local_id: &NodeId, request: tonic::Request<T>) -> tonic::Request<T> {
+        let (metadata, _, message) = request.into_parts();
+        let headers = self.forwarded_headers(metadata.as_ref(), local_id);
+        let mut request = tonic::Request::new(message);
+        *request.metadata_mut() = MetadataMap::from_headers(headers);
+        request.set_timeout(self.timeout);
+        request
+    }
+    pub async fn join_cluster(
+        &self,
+        leader: &NodeInfo,
+        local_id: &NodeId,
+        request: tonic::Request<ProtoJoinRequest>,
+    ) -> std::result::Result<tonic::Response<ProtoJoinResponse>, Status> {
+        debug!("forwarding JoinCluster to leader {}", leader.id);
+        let request = self.grpc_request(local_id, request);
+        let response = tokio::time::timeout(
+            self.timeout,
+            self.admin_client(leader)?.join_cluster(request),
+        )
+        .await
+        .map_err(|_| Status::unavailable(format!("leader {} did not respond", leader.id)))?;
+        response
+    }
+    pub async fn leave_cluster(
+        &self,
+        leader: &NodeInfo,
+        local_id: &NodeId,
+        request: tonic::Request<ProtoLeaveRequest>,
+    ) -> std::result::Result<tonic::Response<ProtoLeaveResponse>, Status> {
+        debug!("forwarding LeaveCluster to leader {}", leader.id);
+        let request = self.grpc_request(local_id, request);
+        let response = tokio::time::timeout(
+            self.timeout,
+            self.admin_client(leader)?.leave_cluster(request),
+        )
+        .await
+        .map_err(|_| Status::unavailable(format!("leader {} did not respond", leader.id)))?;
+        response
+    }
+}
+pub(crate) async fn current_leader(state: &ApiState) -> Result<NodeInfo> {
+    match state.node.leader() {
+        Some(ref id) => state.membership.get_member(id).await.ok_or(Error::NoLeader),
+        None => Err(Error::NoLeader),
+    }
+}
+async fn forward_to_leader(
+    state: &ApiState,
+    forwarder: &LeaderForwarder,
+    request: Request,
+) -> Response {
+    let result = match current_leader(state).await {
+        Ok(leader) => forwarder.forward(&leader, state.node.id(), request).await,
+        Err(e) => Err(e),
+    };
+    result.unwrap_or_else(|e| ApiError::from(e).into_response())
+}
+pub(crate) async fn trust_forwarding_peers(
+    State(state): State<Arc<ApiState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let trusted = match peer {
+        Some(peer) if state.forwarded_by_peer(request.headers()).is_some() => {
+            state.trusted_proxies.with(peer)
+        }
+        _ => state.trusted_proxies.clone(),
+    };
+    request.extensions_mut().insert::<TrustedProxies>(trusted);
+    next.run(request).await
+}
+pub(crate) async fn forward_admin_writes(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ref forwarder) = state.leader_forwarder else {
+        return next.run(request).await;
+    };
+    if state.node.is_leader() || state.forwarded_by_peer(request.headers()).is_some() {
+        return next.run(request).await;
+    }
+    forward_to_leader(&state, forwarder, request).await
+}
+pub(crate) async fn forward_graphql_admin_writes(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ref forwarder) = state.leader_forwarder else {
+        return next.run(request).await;
+    };
+    if state.node.is_leader() || state.forwarded_by_peer(request.headers()).is_some() {
+        return next.run(request).await;
+    }
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, ADMIN_BODY_LIMIT).await else {
+        return ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "request body is too large",
+        )
+        .into_response();
+    };
+    let writes = graphql_writes_tokens(&body);
+    let request = Request::from_parts(parts, Body::from(body));
+    if writes {
+        forward_to_leader(&state, forwarder, request).await
+    } else {
+        next.run(request).await
+    }
+}
+fn graphql_writes_tokens(body: &[u8]) -> bool {
+    let Ok(batch) = serde_json::from_slice::<async_graphql::BatchRequest>(body) else {
+        return false;
+    };
+    let writes = batch.iter().any(|request| {
+        let Ok(document) = parse_query(&request.query) else {
+            return false;
+        };
+        document.operations.iter().any(|(_, operation)| {
+            operation.node.ty == OperationType::Mutation
+                && selects_token_write(&document, &operation.node.selection_set.node, 0)
+        })
+    });
+    writes
+}
+fn selects_token_write(document: &ExecutableDocument, set: &SelectionSet, depth: usize) -> bool {
+    if depth > MAX_FRAGMENT_DEPTH {
+        return false;
+    }
+    set.items.iter().any(|selection| match selection.node {
+        Selection::Field(ref field) => TOKEN_MUTATIONS.contains(&field.node.name.node.as_str()),
+        Selection::InlineFragment(ref fragment) => {
+            selects_token_write(document, &fragment.node.selection_set.node, depth + 1)
+        }
+        Selection::FragmentSpread(ref spread) => document
+            .fragments
+            .get(&spread.node.fragment_name.node)
+            .is_some_and(|fragment| {
+                selects_token_write(document, &fragment.node.selection_set.node, depth + 1)
+            }),
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_cluster::{CpuAwareLoadBalancer, LoadBalancerConfig, Node, NodeConfig};
+    fn forwarder(config: ForwardingConfig) -> AnalysisForwarder {
+        let node = Arc::new(Node::new(NodeConfig::default()));
+        AnalysisForwarder::new(
+            config,
+            Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default())),
+            Arc::new(MembershipManager::new(node)),
+        )
+    }
+    #[test]
+    fn test_should_forward() {
+        let enabled = forwarder(ForwardingConfig::default());
+        assert!(!enabled.should_forward(&NodeMetrics::default()));
+        assert!(enabled.should_forward(&NodeMetrics {
+            engines_total: 2,
+            engines_available: 0,
+            ..Default::default()
+        }));
+        assert!(enabled.should_forward(&NodeMetrics {
+            cpu_usage: 0.95,
+            ..Default::default()
+        }));
+        let disabled = forwarder(ForwardingConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        assert!(!disabled.should_forward(&NodeMetrics {
+            cpu_usage: 1.0,
+            ..Default::default()
+        }));
+    }
+    #[tokio::test]
+    async fn test_forward_without_peers() {
+        let forwarder = forwarder(ForwardingConfig::default());
+        let request = AnalysisRequest::new("startpos");
+        let result = forwarder
+            .forward(&NodeId::from_string("local"), &request, None)
+            .await;
+        assert!(matches!(result, Err(Error::ClusterUnavailable)));
+    }
+    #[tokio::test]
+    async fn test_forward_refuses_infinite_analysis() {
+        let forwarder = forwarder(ForwardingConfig::default());
+        let request = AnalysisRequest::infinite("startpos");
+        let result = forwarder
+            .forward(&NodeId::from_string("local"), &request, None)
+            .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::rest::AuditContext;
 use crate::ApiState;
-use async_graphql::{Context, InputObject, Object, SimpleObject};
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::{Context, Enum, ErrorExtensions, InputObject, Object, SimpleObject};
 use chrono::{DateTime, Utc};
-use ironfish_core::{AnalysisRequest, BestMoveRequest, CreateTokenRequest, TokenStore};
+use ironfish_core::{
+    AnalysisRequest, ApiToken, AuditAction, AuditEntry, BestMoveRequest, CreateTokenRequest, Error,
+    HistoryQuery, Notation, Perspective, TokenContext, TokenFilter, TokenListQuery, TokenOrder,
+    Variant, SCOPE_ANALYZE, SCOPE_BESTMOVE, SCOPE_CLUSTER_READ,
+};
 use std::sync::Arc;
 use uuid::Uuid;
 #[derive(SimpleObject)]
@@ -19,6 +25,7 @@ pub struct Evaluation {
 pub struct PrincipalVariation {
     pub rank: u32,
     pub moves: Vec<Move>,
+    pub san: Option<Vec<String>>,
     pub evaluation: Evaluation,
     pub depth: u32,
 }
@@ -26,18 +33,45 @@ pub struct PrincipalVariation {
 pub struct Analysis {
     pub id: String,
     pub fen: String,
-    pub best_move: Move,
+    pub best_move: Option<Move>,
+    pub game_over: Option<String>,
+    pub best_move_san: Option<String>,
     pub ponder: Option<Move>,
+    pub ponder_san: Option<String>,
     pub evaluation: Evaluation,
     pub principal_variations: Vec<PrincipalVariation>,
     pub depth_reached: u32,
     pub nodes_searched: u64,
     pub time_ms: u64,
+    pub stopped_by: String,
+    pub cached: bool,
+    pub clamped: bool,
+    pub partial: bool,
+    pub variant: String,
+}
+#[derive(SimpleObject)]
+pub struct AnalysisRecord {
+    pub seq: u64,
+    pub id: String,
+    pub fen: String,
+    pub depth: u32,
+    pub evaluation: Evaluation,
+    pub time_ms: u64,
+    pub completed_at: DateTime<Utc>,
+    pub token_id: Option<String>,
+    pub idempotency_key: Option<String>,
+}
+#[derive(SimpleObject)]
+pub struct AnalysisHistoryPage {
+    pub records: Vec<AnalysisRecord>,
+    pub next_before: Option<u64>,
 }
 #[derive(SimpleObject)]
 pub struct BestMoveResult {
-    pub best_move: Move,
+    pub best_move: Option<Move>,
     pub ponder: Option<Move>,
+    pub game_over: Option<String>,
+    pub partial: bool,
 }
 #[derive(SimpleObject)]
 pub struct NodeStatus {
@@ -45,6 +79,7 @@ pub struct NodeStatus {
     pub address: String,
     pub state: String,
     pub uptime_seconds: u64,
+    pub draining: bool,
 }
 #[derive(SimpleObject)]
 pub struct ClusterStatus {
@@ -66,39 +101,120 @@ pub struct TokenInfo {
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
     pub revoked: bool,
+    pub usage_count: u64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+}
+impl From<ApiToken> for TokenInfo {
+    fn from(t: ApiToken) -> Self {
+        Self {
+            id: t.id.to_string(),
+            name: t.name,
+            created_at: t.created_at,
+            expires_at: t.expires_at,
+            revoked: t.revoked,
+            usage_count: t.usage_count,
+            last_used_at: t.last_used_at,
+            last_used_ip: t.last_used_ip,
+        }
+    }
+}
+#[derive(InputObject, Default)]
+pub struct TokenFilterInput {
+    pub revoked: Option<bool>,
+    pub expired: Option<bool>,
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
 }
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenOrderBy {
+    #[default]
+    CreatedAtDesc,
+    LastUsedDesc,
+}
+const DEFAULT_TOKEN_PAGE: i32 = 50;
+const MAX_TOKEN_PAGE: i32 = 500;
 #[derive(Default)]
 pub struct AnalysisQuery;
+fn parse_notation(notation: Option<String>) -> async_graphql::Result<Notation> {
+    match notation {
+        Some(n) => n.parse().map_err(async_graphql::Error::new),
+        None => Ok(Notation::default()),
+    }
+}
+fn parse_perspective(perspective: Option<String>) -> async_graphql::Result<Perspective> {
+    match perspective {
+        Some(p) => p.parse().map_err(async_graphql::Error::new),
+        None => Ok(Perspective::default()),
+    }
+}
+fn parse_variant(variant: Option<String>) -> async_graphql::Result<Variant> {
+    match variant {
+        Some(v) => v.parse().map_err(async_graphql::Error::new),
+        None => Ok(Variant::Standard),
+    }
+}
 #[Object]
 impl AnalysisQuery {
+    #[allow(clippy::too_many_arguments)]
     async fn analyze(
         &self,
         ctx: &Context<'_>,
         fen: String,
         depth: Option<u32>,
         multipv: Option<u32>,
+        movetime: Option<u64>,
+        nodes: Option<u64>,
+        variant: Option<String>,
+        perspective: Option<String>,
+        notation: Option<String>,
     ) -> async_graphql::Result<Analysis> {
+        require_scope(ctx, SCOPE_ANALYZE)?;
         let state = ctx.data::<Arc<ApiState>>()?;
-        let request = AnalysisRequest::new(&fen)
-            .with_depth(depth.unwrap_or(20) as u8)
-            .with_multipv(multipv.unwrap_or(1) as u8);
-        let result = state.analysis.analyze(request).await?;
+        state.ensure_accepting()?;
+        let _permit = state.admit()?;
+        let (depth, multipv) = (depth.unwrap_or(20), multipv.unwrap_or(1));
+        state.limits().check(depth, multipv, movetime)?;
+        let mut request = AnalysisRequest::new(&fen)
+            .with_depth(depth as u8)
+            .with_multipv(multipv as u8)
+            .with_variant(parse_variant(variant)?)
+            .with_perspective(parse_perspective(perspective)?)
+            .with_notation(parse_notation(notation)?);
+        if let Some(ms) = movetime {
+            request = request.with_movetime(ms);
+        }
+        if let Some(n) = nodes {
+            request = request.with_nodes(n);
+        }
+        let mut reservation = None;
+        if let Some(token) = ctx.data_opt::<TokenContext>() {
+            request = token.clamp(request);
+            reservation = state.reserve_node_budget(token, &request).await?;
+        }
+        let history = state
+            .history_recorder(caller_id(ctx))
+            .map(|recorder| recorder.with_node_budget(reservation));
+        let result = state.analyze_tracked(request, history).await?;
         Ok(Analysis {
             id: result.id.to_string(),
             fen: result.fen,
-            best_move: Move {
-                from: result.best_move.from,
-                to: result.best_move.to,
-                promotion: result.best_move.promotion.map(|c| c.to_string()),
-            },
+            best_move: result.best_move.map(|m| Move {
+                from: m.from,
+                to: m.to,
+                promotion: m.promotion.map(|c| c.to_string()),
+            }),
+            game_over: result.game_over.map(|g| g.as_str().to_string()),
+            best_move_san: result.best_move_san,
             ponder: result.ponder.map(|m| Move {
                 from: m.from,
                 to: m.to,
                 promotion: m.promotion.map(|c| c.to_string()),
             }),
+            ponder_san: result.ponder_san,
             evaluation: Evaluation {
-                score_type: format!("{:?}", result.evaluation.score_type),
-                value: result.evaluation.value,
+                score_type: format!("{:?}", result.evaluation.score_type()),
+                value: result.evaluation.value(),
             },
             principal_variations: result
                 .principal_variations
@@ -114,9 +230,10 @@ impl AnalysisQuery {
                             promotion: m.promotion.map(|c| c.to_string()),
                         })
                         .collect(),
+                    san: pv.san,
                     evaluation: Evaluation {
-                        score_type: format!("{:?}", pv.evaluation.score_type),
-                        value: pv.evaluation.value,
+                        score_type: format!("{:?}", pv.evaluation.score_type()),
+                        value: pv.evaluation.value(),
                     },
                     depth: pv.depth as u32,
                 })
@@ -124,6 +241,11 @@ impl AnalysisQuery {
             depth_reached: result.depth_reached as u32,
             nodes_searched: result.nodes_searched,
             time_ms: result.time_ms,
+            stopped_by: result.stopped_by.as_str().to_string(),
+            cached: result.cached,
+            clamped: result.clamped,
+            partial: result.partial,
+            variant: result.variant.as_str().to_string(),
         })
     }
     async fn best_move(
@@ -131,21 +253,90 @@ impl AnalysisQuery {
         ctx: &Context<'_>,
         fen: String,
         movetime: Option<u64>,
+        nodes: Option<u64>,
+        variant: Option<String>,
     ) -> async_graphql::Result<BestMoveResult> {
+        require_scope(ctx, SCOPE_BESTMOVE)?;
         let state = ctx.data::<Arc<ApiState>>()?;
-        let request = BestMoveRequest { fen, movetime };
+        state.ensure_accepting()?;
+        let _permit = state.admit()?;
+        let request = BestMoveRequest {
+            fen,
+            movetime,
+            nodes,
+            variant: parse_variant(variant)?,
+            moves: Vec::new(),
+        };
         let result = state.analysis.best_move(request).await?;
         Ok(BestMoveResult {
-            best_move: Move {
-                from: result.best_move.from,
-                to: result.best_move.to,
-                promotion: result.best_move.promotion.map(|c| c.to_string()),
-            },
+            best_move: result.best_move.map(|m| Move {
+                from: m.from,
+                to: m.to,
+                promotion: m.promotion.map(|c| c.to_string()),
+            }),
+            game_over: result.game_over.map(|g| g.as_str().to_string()),
             ponder: result.ponder.map(|m| Move {
                 from: m.from,
                 to: m.to,
                 promotion: m.promotion.map(|c| c.to_string()),
             }),
+            partial: result.partial,
+        })
+    }
+    async fn recent_analyses(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u32>,
+        before: Option<u64>,
+        token_id: Option<String>,
+    ) -> async_graphql::Result<AnalysisHistoryPage> {
+        require_scope(ctx, SCOPE_ANALYZE)?;
+        let state = ctx.data::<Arc<ApiState>>()?;
+        let history = state
+            .history
+            .as_ref()
+            .ok_or_else(|| async_graphql::Error::new("analysis history is not enabled"))?;
+        let requested = match token_id {
+            Some(id) => Some(Uuid::parse_str(&id).map_err(|_| "invalid token id")?),
+            None => None,
+        };
+        let token_id = match caller_id(ctx) {
+            Some(id) if requested.is_some_and(|requested| requested != id) => {
+                return Err(
+                    async_graphql::Error::new("tokens may only list their own analyses")
+                        .extend_with(|_, ext| ext.set("code", "FORBIDDEN")),
+                )
+            }
+            Some(id) => Some(id),
+            None => requested,
+        };
+        let page = history
+            .query(&HistoryQuery {
+                before,
+                token_id,
+                limit: limit.map(|l| l as usize),
+            })
+            .await?;
+        Ok(AnalysisHistoryPage {
+            records: page
+                .records
+                .into_iter()
+                .map(|r| AnalysisRecord {
+                    seq: r.seq,
+                    id: r.id.to_string(),
+                    fen: r.fen,
+                    depth: r.depth as u32,
+                    evaluation: Evaluation {
+                        score_type: format!("{:?}", r.evaluation.score_type()),
+                        value: r.evaluation.value(),
+                    },
+                    time_ms: r.time_ms,
+                    completed_at: r.completed_at,
+                    token_id: r.token_id.map(|id| id.to_string()),
+                    idempotency_key: r.idempotency_key,
+                })
+                .collect(),
+            next_before: page.next_before,
         })
     }
 }
@@ -159,9 +350,22 @@ impl AnalysisMutation {
         fen: String,
         depth: Option<u32>,
     ) -> async_graphql::Result<String> {
+        require_scope(ctx, SCOPE_ANALYZE)?;
         let state = ctx.data::<Arc<ApiState>>()?;
-        let request = AnalysisRequest::new(&fen).with_depth(depth.unwrap_or(20) as u8);
-        let result = state.analysis.analyze(request).await?;
+        state.ensure_accepting()?;
+        let _permit = state.admit()?;
+        let depth = depth.unwrap_or(20);
+        state.limits().check(depth, 1, None)?;
+        let mut request = AnalysisRequest::new(&fen).with_depth(depth as u8);
+        let mut reservation = None;
+        if let Some(token) = ctx.data_opt::<TokenContext>() {
+            request = token.clamp(request);
+            reservation = state.reserve_node_budget(token, &request).await?;
+        }
+        let history = state
+            .history_recorder(caller_id(ctx))
+            .map(|recorder| recorder.with_node_budget(reservation));
+        let result = state.analyze_tracked(request, history).await?;
         Ok(result.id.to_string())
     }
 }
@@ -170,6 +374,7 @@ pub struct ClusterQuery;
 #[Object]
 impl ClusterQuery {
     async fn cluster_status(&self, ctx: &Context<'_>) -> async_graphql::Result<ClusterStatus> {
+        require_scope(ctx, SCOPE_CLUSTER_READ)?;
         let state = ctx.data::<Arc<ApiState>>()?;
         let status = state.membership.cluster_status().await;
         Ok(ClusterStatus {
@@ -181,6 +386,7 @@ impl ClusterQuery {
                     address: n.info.address.to_string(),
                     state: format!("{:?}", n.state),
                     uptime_seconds: n.uptime_seconds,
+                    draining: n.draining,
                 })
                 .collect(),
             leader_id: status.leader.map(|l| l.to_string()),
@@ -193,28 +399,101 @@ impl ClusterQuery {
 pub struct TokenQuery;
 #[Object]
 impl TokenQuery {
-    async fn tokens(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<TokenInfo>> {
+    async fn tokens(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+        filter: Option<TokenFilterInput>,
+        order_by: Option<TokenOrderBy>,
+    ) -> async_graphql::Result<Connection<String, TokenInfo>> {
+        require_token_admin(ctx)?;
+        let state = ctx.data::<Arc<ApiState>>()?;
+        let after = after
+            .map(|cursor| cursor.parse().map_err(async_graphql::Error::new))
+            .transpose()?;
+        let filter = filter.unwrap_or_default();
+        let query = TokenListQuery {
+            filter: TokenFilter {
+                revoked: filter.revoked,
+                expired: filter.expired,
+                name_contains: filter.name_contains,
+                created_after: filter.created_after,
+            },
+            order: match order_by.unwrap_or_default() {
+                TokenOrderBy::CreatedAtDesc => TokenOrder::CreatedAtDesc,
+                TokenOrderBy::LastUsedDesc => TokenOrder::LastUsedDesc,
+            },
+            after,
+            limit: Some(first.unwrap_or(DEFAULT_TOKEN_PAGE).clamp(1, MAX_TOKEN_PAGE) as usize),
+        };
+        state.flush_token_usage().await;
+        let page = state.token_store.list_page(&query).await?;
+        let mut connection = Connection::new(after.is_some(), page.next_after.is_some());
+        connection.edges.extend(
+            page.tokens
+                .into_iter()
+                .map(|t| Edge::new(query.order.cursor(&t).to_string(), TokenInfo::from(t))),
+        );
+        Ok(connection)
+    }
+    #[graphql(deprecation = "use the paginated tokens connection")]
+    async fn token_list(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<TokenInfo>> {
+        require_token_admin(ctx)?;
         let state = ctx.data::<Arc<ApiState>>()?;
+        state.flush_token_usage().await;
         let tokens = state.token_store.list().await?;
-        Ok(tokens
-            .into_iter()
-            .map(|t| TokenInfo {
-                id: t.id.to_string(),
-                name: t.name,
-                created_at: t.created_at,
-                expires_at: t.expires_at,
-                revoked: t.revoked,
-            })
-            .collect())
+        Ok(tokens.into_iter().map(TokenInfo::from).collect())
     }
 }
 #[derive(Default)]
 pub struct TokenMutation;
+fn caller_id(ctx: &Context<'_>) -> Option<Uuid> {
+    ctx.data_opt::<TokenContext>().and_then(|token| token.id)
+}
+fn require_scope(ctx: &Context<'_>, scope: &str) -> async_graphql::Result<()> {
+    match ctx.data_opt::<TokenContext>() {
+        Some(token) if !token.has_scope(scope) => {
+            Err(forbidden(&format!("missing scope: {}", scope)))
+        }
+        _ => Ok(()),
+    }
+}
+fn forbidden(message: &str) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, ext| ext.set("code", "FORBIDDEN"))
+}
+fn require_token_admin(ctx: &Context<'_>) -> async_graphql::Result<()> {
+    match ctx.data_opt::<TokenContext>() {
+        Some(token) if !token.can_manage_tokens() => {
+            Err(forbidden("token management requires an unscoped token"))
+        }
+        _ => Ok(()),
+    }
+}
+fn audit_entry(ctx: &Context<'_>, action: AuditAction, state: &ApiState) -> AuditEntry {
+    match ctx.data_opt::<AuditContext>() {
+        Some(audit) => audit.entry(action, state),
+        None => AuditEntry::new(action, "anonymous", state.node.id().to_string()),
+    }
+}
+fn token_store_error(e: Error) -> async_graphql::Error {
+    let read_only = matches!(e, Error::StoreReadOnly);
+    let error = async_graphql::Error::new(e.to_string());
+    if read_only {
+        error.extend_with(|_, ext| ext.set("code", "STORE_READ_ONLY"))
+    } else {
+        error
+    }
+}
 #[derive(InputObject)]
 pub struct CreateTokenInput {
     pub name: Option<String>,
     pub expires_in_days: Option<u32>,
     pub rate_limit: Option<u32>,
+    pub scopes: Option<Vec<String>>,
+    pub max_depth: Option<u32>,
+    pub max_multipv: Option<u32>,
+    pub node_budget_per_hour: Option<u64>,
 }
 #[Object]
 impl TokenMutation {
@@ -223,14 +502,36 @@ impl TokenMutation {
         ctx: &Context<'_>,
         input: Option<CreateTokenInput>,
     ) -> async_graphql::Result<Token> {
+        require_token_admin(ctx)?;
         let state = ctx.data::<Arc<ApiState>>()?;
         let request = CreateTokenRequest {
             name: input.as_ref().and_then(|i| i.name.clone()),
             expires_in_days: input.as_ref().and_then(|i| i.expires_in_days),
             rate_limit: input.as_ref().and_then(|i| i.rate_limit),
+            scopes: input
+                .as_ref()
+                .and_then(|i| i.scopes.clone())
+                .unwrap_or_default(),
+            max_depth: input.as_ref().and_then(|i| i.max_depth),
+            max_multipv: input.as_ref().and_then(|i| i.max_multipv),
+            node_budget_per_hour: input.as_ref().and_then(|i| i.node_budget_per_hour),
         };
+        let audit = audit_entry(ctx, AuditAction::TokenCreate, state);
+        if let Some(caller) = ctx.data_opt::<TokenContext>() {
+            if !caller.can_grant(&request) {
+                let message = "cannot grant a token with wider limits than the caller's own";
+                state.record_audit(audit.failed(message)).await;
+                return Err(forbidden(message));
+            }
+        }
         let (token, response) = state.token_manager.create(request)?;
-        state.token_store.create(token).await?;
+        if let Err(e) = state.token_store.create(token).await {
+            state.record_audit(audit.failed(e.to_string())).await;
+            return Err(token_store_error(e));
+        }
+        state
+            .record_audit(audit.with_target(response.id.to_string()))
+            .await;
         Ok(Token {
             id: response.id.to_string(),
             token: response.token,
@@ -238,9 +539,22 @@ impl TokenMutation {
         })
     }
     async fn revoke_token(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<bool> {
+        require_token_admin(ctx)?;
         let state = ctx.data::<Arc<ApiState>>()?;
-        let uuid = Uuid::parse_str(&id)?;
-        state.token_store.revoke(&uuid).await?;
+        let audit = audit_entry(ctx, AuditAction::TokenRevoke, state).with_target(id.clone());
+        let uuid = match Uuid::parse_str(&id) {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                state.record_audit(audit.failed("invalid token id")).await;
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = state.token_store.revoke(&uuid).await {
+            state.record_audit(audit.failed(e.to_string())).await;
+            return Err(token_store_error(e));
+        }
+        state.record_audit(audit).await;
+        state.broadcast_token_revoked(uuid);
         Ok(true)
     }
 }
@@ -1,10 +1,14 @@
 use super::resolvers::{AnalysisMutation, AnalysisQuery, ClusterQuery, TokenMutation, TokenQuery};
+use crate::forward::forward_graphql_admin_writes;
+use crate::rest::AuditContext;
 use crate::ApiState;
 use async_graphql::{EmptySubscription, MergedObject, Schema};
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::extract::State;
+use axum::middleware::from_fn_with_state;
 use axum::routing::post;
 use axum::Router;
+use ironfish_auth::OptionalTokenContext;
 use std::sync::Arc;
 #[derive(MergedObject, Default)]
 pub struct QueryRoot(AnalysisQuery, ClusterQuery, TokenQuery);
@@ -29,13 +33,25 @@ impl GraphQLService {
     }
     pub fn router(self) -> Router {
         let schema = self.schema();
+        let leader = from_fn_with_state(self.state.clone(), forward_graphql_admin_writes);
         Router::new()
-            .route("/graphql", post(graphql_handler).get(graphql_playground))
+            .route(
+                "/graphql",
+                post(graphql_handler)
+                    .route_layer(leader)
+                    .get(graphql_playground),
+            )
             .with_state(schema)
     }
 }
-async fn graphql_handler(State(schema): State<AppSchema>, req: GraphQLRequest) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+async fn graphql_handler(
+    State(schema): State<AppSchema>,
+    OptionalTokenContext(token): OptionalTokenContext,
+    audit: AuditContext,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = req.into_inner().data(audit.for_token(&token)).data(token);
+    schema.execute(request).await.into()
 }
 async fn graphql_playground() -> impl axum::response::IntoResponse {
     axum::response::Html(async_graphql::http::playground_source(
@@ -1,19 +1,34 @@
+use crate::forward::{current_leader, LeaderForwarder};
 use crate::proto::{
     chess_analysis_server::{ChessAnalysis, ChessAnalysisServer},
     cluster_admin_server::{ClusterAdmin, ClusterAdminServer},
-    AnalysisUpdate, AnalyzeRequest as ProtoAnalyzeRequest, AnalyzeResponse as ProtoAnalyzeResponse,
-    BestMoveRequest as ProtoBestMoveRequest, BestMoveResponse as ProtoBestMoveResponse,
-    ClusterStatus as ProtoClusterStatus, Empty, Evaluation as ProtoEvaluation,
+    ActiveAnalysis as ProtoActiveAnalysis, AnalysisUpdate, AnalyzeRequest as ProtoAnalyzeRequest,
+    AnalyzeResponse as ProtoAnalyzeResponse, BestMoveRequest as ProtoBestMoveRequest,
+    BestMoveResponse as ProtoBestMoveResponse, ClusterStatus as ProtoClusterStatus, Empty,
+    EnginePoolStatus as ProtoEnginePoolStatus, EngineSlot as ProtoEngineSlot,
+    Evaluation as ProtoEvaluation, GossipStoreStatus as ProtoGossipStoreStatus,
     JoinRequest as ProtoJoinRequest, JoinResponse as ProtoJoinResponse,
     LeaveRequest as ProtoLeaveRequest, LeaveResponse as ProtoLeaveResponse, Move as ProtoMove,
-    NodeStatus as ProtoNodeStatus, PrincipalVariation as ProtoPv, ScoreType as ProtoScoreType,
+    NodeDiagnostics as ProtoNodeDiagnostics, NodeStatus as ProtoNodeStatus,
+    PeerStatus as ProtoPeerStatus, PlayBestMove, PlayEvaluation, PlayRequest,
+    PlayUpdate as ProtoPlayUpdate, PrincipalVariation as ProtoPv,
+    ScoreHistoryEntry as ProtoScoreHistoryEntry, ScoreType as ProtoScoreType,
+    SessionSummary as ProtoSessionSummary,
 };
-use crate::ApiState;
+use crate::proto::{play_request, play_update};
+use crate::{ApiState, CLUSTER_SECRET_HEADER};
 use futures::Stream;
-use ironfish_core::{AnalysisRequest, BestMoveRequest};
+use ironfish_auth::{admin_actor, TokenManager};
+use ironfish_core::{
+    AnalysisLimits, AnalysisProgress, AnalysisRequest, AnalysisResult, AuditAction, AuditEntry,
+    BestMoveRequest, ChessPosition, Error, Move, NodeDiagnostics, Perspective, PlayCommand,
+    PlayUpdate, PrincipalVariation, Score, ScoreHistoryEntry, TokenContext, Variant,
+};
 use std::pin::Pin;
 use std::sync::Arc;
-use tonic::{Request, Response, Status};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tonic::{Code, Request, Response, Status, Streaming};
 pub struct GrpcService {
     state: Arc<ApiState>,
 }
@@ -35,107 +50,276 @@ impl GrpcService {
 pub struct ChessAnalysisHandler {
     state: Arc<ApiState>,
 }
+fn parse_variant(variant: &str) -> Result<Variant, Status> {
+    if variant.is_empty() {
+        return Ok(Variant::Standard);
+    }
+    variant.parse().map_err(Status::invalid_argument)
+}
+fn parse_perspective(perspective: &str) -> Result<Perspective, Status> {
+    if perspective.is_empty() {
+        return Ok(Perspective::default());
+    }
+    perspective.parse().map_err(Status::invalid_argument)
+}
+fn error_status(e: Error) -> Status {
+    let code = match e {
+        Error::AnalysisCancelled => Code::Cancelled,
+        _ => match e.status_code() {
+            400 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            404 => Code::NotFound,
+            408 => Code::DeadlineExceeded,
+            429 => Code::ResourceExhausted,
+            503 => Code::Unavailable,
+            _ => Code::Internal,
+        },
+    };
+    Status::new(code, e.to_string())
+}
+fn start_fen(fen: &str, startpos: bool) -> String {
+    if startpos {
+        ChessPosition::starting().fen
+    } else {
+        fen.to_string()
+    }
+}
+fn analysis_request(
+    req: &ProtoAnalyzeRequest,
+    limits: &AnalysisLimits,
+) -> Result<AnalysisRequest, Status> {
+    let multipv = match req.multipv {
+        0 if !req.search_moves.is_empty() => limits.default_multipv(&req.search_moves).into(),
+        multipv => multipv,
+    };
+    limits
+        .check(req.depth, multipv, req.movetime_ms)
+        .map_err(error_status)?;
+    let analysis_req = AnalysisRequest::new(start_fen(&req.fen, req.startpos))
+        .with_depth(req.depth as u8)
+        .with_multipv(multipv as u8)
+        .with_variant(parse_variant(&req.variant)?)
+        .with_perspective(parse_perspective(&req.perspective)?)
+        .with_history(req.include_history)
+        .with_moves(req.moves.clone())
+        .with_search_moves(req.search_moves.clone());
+    let analysis_req = match req.movetime_ms {
+        Some(ms) => analysis_req.with_movetime(ms),
+        None => analysis_req,
+    };
+    Ok(match req.nodes {
+        Some(nodes) => analysis_req.with_nodes(nodes),
+        None => analysis_req,
+    })
+}
+fn proto_move(m: Move) -> ProtoMove {
+    ProtoMove {
+        from: m.from,
+        to: m.to,
+        promotion: m.promotion.map(|c| c.to_string()),
+    }
+}
+fn proto_evaluation(evaluation: Score) -> ProtoEvaluation {
+    ProtoEvaluation {
+        score_type: match evaluation {
+            Score::Centipawns(_) => ProtoScoreType::Centipawns as i32,
+            Score::MateIn(_) => ProtoScoreType::Mate as i32,
+            Score::Tablebase(_) => ProtoScoreType::Tablebase as i32,
+        },
+        value: evaluation.value(),
+    }
+}
+fn proto_pv(pv: PrincipalVariation) -> ProtoPv {
+    ProtoPv {
+        rank: pv.rank as u32,
+        moves: pv.moves.into_iter().map(proto_move).collect(),
+        evaluation: Some(proto_evaluation(pv.evaluation)),
+        depth: pv.depth as u32,
+    }
+}
+fn proto_history_entry(entry: ScoreHistoryEntry) -> ProtoScoreHistoryEntry {
+    ProtoScoreHistoryEntry {
+        depth: entry.depth as u32,
+        evaluation: Some(proto_evaluation(entry.evaluation)),
+        nodes: entry.nodes,
+        time_ms: entry.time_ms,
+    }
+}
+fn proto_analysis_response(result: AnalysisResult) -> ProtoAnalyzeResponse {
+    ProtoAnalyzeResponse {
+        id: result.id.to_string(),
+        fen: result.fen,
+        best_move: result.best_move.map(proto_move),
+        ponder: result.ponder.map(proto_move),
+        game_over: result.game_over.map(|g| g.as_str().to_string()),
+        evaluation: Some(proto_evaluation(result.evaluation)),
+        principal_variations: result
+            .principal_variations
+            .into_iter()
+            .map(proto_pv)
+            .collect(),
+        depth_reached: result.depth_reached as u32,
+        nodes_searched: result.nodes_searched,
+        time_ms: result.time_ms,
+        stopped_by: result.stopped_by.as_str().to_string(),
+        cached: result.cached,
+        clamped: result.clamped,
+        partial: result.partial,
+        variant: result.variant.as_str().to_string(),
+        score_history: result
+            .score_history
+            .into_iter()
+            .map(proto_history_entry)
+            .collect(),
+    }
+}
+fn progress_update(progress: AnalysisProgress) -> AnalysisUpdate {
+    AnalysisUpdate {
+        id: progress.id.to_string(),
+        current_depth: progress.current_depth as u32,
+        target_depth: progress.target_depth as u32,
+        current_move: progress.current_move.map(proto_move),
+        nodes_per_second: progress.nodes_per_second,
+        elapsed_ms: progress.elapsed_ms,
+        evaluation: progress.evaluation.map(proto_evaluation),
+        principal_variations: progress
+            .principal_variations
+            .into_iter()
+            .map(proto_pv)
+            .collect(),
+        hash_full: progress.hash_full as u32,
+        result: None,
+    }
+}
+fn completion_update(result: AnalysisResult, target_depth: u32) -> AnalysisUpdate {
+    let nodes_per_second = match result.time_ms {
+        0 => 0,
+        ms => result.nodes_searched * 1000 / ms,
+    };
+    AnalysisUpdate {
+        id: result.id.to_string(),
+        current_depth: result.depth_reached as u32,
+        target_depth,
+        current_move: result.best_move.clone().map(proto_move),
+        nodes_per_second,
+        elapsed_ms: result.time_ms,
+        evaluation: Some(proto_evaluation(result.evaluation)),
+        principal_variations: result
+            .principal_variations
+            .iter()
+            .cloned()
+            .map(proto_pv)
+            .collect(),
+        hash_full: 0,
+        result: Some(proto_analysis_response(result)),
+    }
+}
+fn play_command(request: PlayRequest, limits: &AnalysisLimits) -> Result<PlayCommand, Status> {
+    match request.command {
+        Some(play_request::Command::Position(position)) => {
+            limits
+                .check_search(position.movetime_ms, position.nodes)
+                .map_err(error_status)?;
+            Ok(PlayCommand::Position {
+                request: BestMoveRequest {
+                    fen: start_fen(&position.fen, position.startpos),
+                    movetime: position.movetime_ms,
+                    nodes: position.nodes,
+                    variant: parse_variant(&position.variant)?,
+                    moves: position.moves,
+                },
+                ponder: position.ponder,
+            })
+        }
+        Some(play_request::Command::OpponentMove(mv)) => Ok(PlayCommand::OpponentMove(mv)),
+        None => Err(Status::invalid_argument("play request has no command")),
+    }
+}
+fn proto_play_update(update: PlayUpdate) -> ProtoPlayUpdate {
+    let update = match update {
+        PlayUpdate::Evaluation {
+            depth,
+            evaluation,
+            pv,
+            pondering,
+        } => play_update::Update::Evaluation(PlayEvaluation {
+            depth: depth as u32,
+            evaluation: Some(proto_evaluation(evaluation)),
+            pv: pv.into_iter().map(proto_move).collect(),
+            pondering,
+        }),
+        PlayUpdate::BestMove {
+            best_move,
+            ponder,
+            ponder_hit,
+            game_over,
+        } => play_update::Update::BestMove(PlayBestMove {
+            best_move: best_move.map(proto_move),
+            ponder: ponder.map(proto_move),
+            ponder_hit,
+            game_over: game_over.map(|g| g.as_str().to_string()),
+        }),
+    };
+    ProtoPlayUpdate {
+        update: Some(update),
+    }
+}
 #[tonic::async_trait]
 impl ChessAnalysis for ChessAnalysisHandler {
     async fn analyze(
         &self,
         request: Request<ProtoAnalyzeRequest>,
     ) -> Result<Response<ProtoAnalyzeResponse>, Status> {
-        let req = request.into_inner();
-        let analysis_req = AnalysisRequest::new(&req.fen)
-            .with_depth(req.depth as u8)
-            .with_multipv(req.multipv as u8);
-        let analysis_req = match req.movetime_ms {
-            Some(ms) => analysis_req.with_movetime(ms),
-            None => analysis_req,
-        };
+        self.state.ensure_accepting().map_err(error_status)?;
+        let _permit = self.state.admit().map_err(error_status)?;
+        let token = request
+            .extensions()
+            .get::<TokenContext>()
+            .cloned()
+            .unwrap_or_default();
+        let analysis_req = analysis_request(&request.into_inner(), &self.state.limits())?;
+        let analysis_req = token.clamp(analysis_req);
+        let reservation = self
+            .state
+            .reserve_node_budget(&token, &analysis_req)
+            .await
+            .map_err(error_status)?;
+        let history = self
+            .state
+            .history_recorder(token.id)
+            .map(|recorder| recorder.with_node_budget(reservation));
         let result = self
             .state
-            .analysis
-            .analyze(analysis_req)
+            .analyze_tracked(analysis_req, history)
             .await
-            .map_err(|e| Status::internal(e.to_string()))?;
-        let best_move = ProtoMove {
-            from: result.best_move.from.clone(),
-            to: result.best_move.to.clone(),
-            promotion: result.best_move.promotion.map(|c| c.to_string()),
-        };
-        let ponder = result.ponder.map(|m| ProtoMove {
-            from: m.from,
-            to: m.to,
-            promotion: m.promotion.map(|c| c.to_string()),
-        });
-        let evaluation = ProtoEvaluation {
-            score_type: match result.evaluation.score_type {
-                ironfish_core::ScoreType::Centipawns => ProtoScoreType::Centipawns as i32,
-                ironfish_core::ScoreType::Mate => ProtoScoreType::Mate as i32,
-            },
-            value: result.evaluation.value,
-        };
-        let pvs: Vec<ProtoPv> = result
-            .principal_variations
-            .into_iter()
-            .map(|pv| ProtoPv {
-                rank: pv.rank as u32,
-                moves: pv
-                    .moves
-                    .into_iter()
-                    .map(|m| ProtoMove {
-                        from: m.from,
-                        to: m.to,
-                        promotion: m.promotion.map(|c| c.to_string()),
-                    })
-                    .collect(),
-                evaluation: Some(ProtoEvaluation {
-                    score_type: match pv.evaluation.score_type {
-                        ironfish_core::ScoreType::Centipawns => ProtoScoreType::Centipawns as i32,
-                        ironfish_core::ScoreType::Mate => ProtoScoreType::Mate as i32,
-                    },
-                    value: pv.evaluation.value,
-                }),
-                depth: pv.depth as u32,
-            })
-            .collect();
-        Ok(Response::new(ProtoAnalyzeResponse {
-            id: result.id.to_string(),
-            fen: result.fen,
-            best_move: Some(best_move),
-            ponder,
-            evaluation: Some(evaluation),
-            principal_variations: pvs,
-            depth_reached: result.depth_reached as u32,
-            nodes_searched: result.nodes_searched,
-            time_ms: result.time_ms,
-        }))
+            .map_err(error_status)?;
+        Ok(Response::new(proto_analysis_response(result)))
     }
     async fn best_move(
         &self,
         request: Request<ProtoBestMoveRequest>,
     ) -> Result<Response<ProtoBestMoveResponse>, Status> {
+        self.state.ensure_accepting().map_err(error_status)?;
+        let _permit = self.state.admit().map_err(error_status)?;
         let req = request.into_inner();
         let best_move_req = BestMoveRequest {
-            fen: req.fen,
+            fen: start_fen(&req.fen, req.startpos),
             movetime: req.movetime_ms,
+            nodes: req.nodes,
+            variant: parse_variant(&req.variant)?,
+            moves: req.moves,
         };
         let result = self
             .state
             .analysis
             .best_move(best_move_req)
             .await
-            .map_err(|e| Status::internal(e.to_string()))?;
-        let best_move = ProtoMove {
-            from: result.best_move.from,
-            to: result.best_move.to,
-            promotion: result.best_move.promotion.map(|c| c.to_string()),
-        };
-        let ponder = result.ponder.map(|m| ProtoMove {
-            from: m.from,
-            to: m.to,
-            promotion: m.promotion.map(|c| c.to_string()),
-        });
+            .map_err(error_status)?;
         Ok(Response::new(ProtoBestMoveResponse {
-            best_move: Some(best_move),
-            ponder,
+            best_move: result.best_move.map(proto_move),
+            ponder: result.ponder.map(proto_move),
+            game_over: result.game_over.map(|g| g.as_str().to_string()),
+            partial: result.partial,
         }))
     }
     type StreamAnalysisStream = Pin<Box<dyn Stream<Item = Result<AnalysisUpdate, Status>> + Send>>;
@@ -143,15 +327,99 @@ impl ChessAnalysis for ChessAnalysisHandler {
         &self,
         request: Request<ProtoAnalyzeRequest>,
     ) -> Result<Response<Self::StreamAnalysisStream>, Status> {
-        let req = request.into_inner();
+        self.state.ensure_accepting().map_err(error_status)?;
+        let token = request
+            .extensions()
+            .get::<TokenContext>()
+            .cloned()
+            .unwrap_or_default();
+        let analysis_req = analysis_request(&request.into_inner(), &self.state.limits())?;
+        let analysis_req = token.clamp(analysis_req);
+        analysis_req.validate().map_err(error_status)?;
+        let permit = self.state.admit().map_err(error_status)?;
+        let reservation = self
+            .state
+            .reserve_node_budget(&token, &analysis_req)
+            .await
+            .map_err(error_status)?;
+        let id = analysis_req.id;
+        let target_depth = analysis_req.depth as u32;
+        let cancel = CancellationToken::new();
+        let (progress_tx, mut progress_rx) = mpsc::channel::<AnalysisProgress>(32);
+        let state = self.state.clone();
+        state.analyses.begin(id).await;
+        let history = state
+            .history_recorder(token.id)
+            .map(|recorder| recorder.with_node_budget(reservation));
+        let task_cancel = cancel.clone();
+        let handle = tokio::spawn(async move {
+            let _permit = permit;
+            let result = state
+                .analysis
+                .analyze_streaming(analysis_req, progress_tx, task_cancel)
+                .await;
+            state.analyses.finish(id, &result).await;
+            if let Some(history) = history {
+                history.record(&result).await;
+            }
+            result
+        });
         let stream = async_stream::stream! {
-            yield Ok(AnalysisUpdate {
-                id: uuid::Uuid::new_v4().to_string(),
-                current_depth: 1,
-                target_depth: req.depth,
-                current_move: None,
-                nodes_per_second: 0,
-            });
+            let _guard = cancel.drop_guard();
+            while let Some(progress) = progress_rx.recv().await {
+                yield Ok(progress_update(progress));
+            }
+            match handle.await {
+                Ok(Ok(result)) => yield Ok(completion_update(result, target_depth)),
+                Ok(Err(e)) => yield Err(error_status(e)),
+                Err(e) => yield Err(Status::internal(e.to_string())),
+            }
+        };
+        Ok(Response::new(Box::pin(stream)))
+    }
+    type PlaySessionStream = Pin<Box<dyn Stream<Item = Result<ProtoPlayUpdate, Status>> + Send>>;
+    async fn play_session(
+        &self,
+        request: Request<Streaming<PlayRequest>>,
+    ) -> Result<Response<Self::PlaySessionStream>, Status> {
+        self.state.ensure_accepting().map_err(error_status)?;
+        let permit = self.state.admit().map_err(error_status)?;
+        let mut incoming = request.into_inner();
+        let (command_tx, command_rx) = mpsc::channel(8);
+        let (update_tx, mut update_rx) = mpsc::channel(32);
+        let state = self.state.clone();
+        let limits_state = self.state.clone();
+        let session = tokio::spawn(async move { state.analysis.play(command_rx, update_tx).await });
+        let cancel = CancellationToken::new();
+        let forward_cancel = cancel.clone();
+        let forward = tokio::spawn(async move {
+            loop {
+                let message = tokio::select! {
+                    message = incoming.message() => message?,
+                    _ = forward_cancel.cancelled() => return Ok(()),
+                };
+                let Some(request) = message else {
+                    return Ok(());
+                };
+                let command = play_command(request, &limits_state.limits())?;
+                if command_tx.send(command).await.is_err() {
+                    return Ok(());
+                }
+            }
+        });
+        let stream = async_stream::stream! {
+            let _permit = permit;
+            let guard = cancel.drop_guard();
+            while let Some(update) = update_rx.recv().await {
+                yield Ok(proto_play_update(update));
+            }
+            guard.disarm().cancel();
+            match (session.await, forward.await) {
+                (Ok(Err(e)), _) => yield Err(error_status(e)),
+                (Err(e), _) => yield Err(Status::internal(e.to_string())),
+                (_, Ok(Err(status))) => yield Err(status),
+                _ => {}
+            }
         };
         Ok(Response::new(Box::pin(stream)))
     }
@@ -159,6 +427,41 @@ impl ChessAnalysis for ChessAnalysisHandler {
 pub struct ClusterAdminHandler {
     state: Arc<ApiState>,
 }
+impl ClusterAdminHandler {
+    fn leader_forwarder<T>(&self, request: &Request<T>) -> Option<&LeaderForwarder> {
+        let forwarder = self.state.leader_forwarder.as_deref()?;
+        let forwarded = self
+            .state
+            .forwarded_by_peer(request.metadata().as_ref())
+            .is_some();
+        (!self.state.node.is_leader() && !forwarded).then_some(forwarder)
+    }
+    async fn audit_entry<T>(&self, action: AuditAction, request: &Request<T>) -> AuditEntry {
+        let metadata = request.metadata();
+        let actor = match metadata.get("x-admin-key").and_then(|v| v.to_str().ok()) {
+            Some(key) => admin_actor(key),
+            None => self.token_actor(request).await,
+        };
+        AuditEntry::new(action, actor, self.state.node.id().to_string())
+            .with_source_ip(request.remote_addr().map(|addr| addr.ip().to_string()))
+    }
+    async fn token_actor<T>(&self, request: &Request<T>) -> String {
+        let raw = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .and_then(TokenManager::extract_raw_token);
+        let Some(raw) = raw else {
+            return "anonymous".to_string();
+        };
+        let hash = self.state.token_manager.hash_token(raw);
+        match self.state.token_store.get_by_hash(&hash).await {
+            Ok(Some(token)) => format!("token:{}", token.id),
+            _ => "anonymous".to_string(),
+        }
+    }
+}
 #[tonic::async_trait]
 impl ClusterAdmin for ClusterAdminHandler {
     async fn get_status(
@@ -174,6 +477,7 @@ impl ClusterAdmin for ClusterAdminHandler {
                 address: n.info.address.to_string(),
                 state: format!("{:?}", n.state),
                 uptime_seconds: n.uptime_seconds,
+                draining: n.draining,
             })
             .collect();
         Ok(Response::new(ProtoClusterStatus {
@@ -187,11 +491,34 @@ impl ClusterAdmin for ClusterAdminHandler {
         &self,
         request: Request<ProtoJoinRequest>,
     ) -> Result<Response<ProtoJoinResponse>, Status> {
+        if let Some(forwarder) = self.leader_forwarder(&request) {
+            let leader = current_leader(&self.state).await.map_err(error_status)?;
+            return forwarder
+                .join_cluster(&leader, self.state.node.id(), request)
+                .await;
+        }
+        let audit = self.audit_entry(AuditAction::ClusterJoin, &request).await;
+        let secret = request
+            .metadata()
+            .get(CLUSTER_SECRET_HEADER)
+            .and_then(|v| v.to_str().ok());
+        if !self.state.cluster_secret_matches(secret) {
+            self.state
+                .record_audit(audit.failed("invalid cluster secret"))
+                .await;
+            return Err(Status::unauthenticated("invalid or missing cluster secret"));
+        }
         let req = request.into_inner();
-        let addr = req
-            .address
-            .parse()
-            .map_err(|_| Status::invalid_argument("invalid address"))?;
+        let audit = audit.with_target(format!("{}@{}", req.node_id, req.address));
+        let addr = match req.address.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                self.state
+                    .record_audit(audit.failed("invalid address"))
+                    .await;
+                return Err(Status::invalid_argument("invalid address"));
+            }
+        };
         let node_info = ironfish_core::NodeInfo {
             id: ironfish_core::NodeId::from_string(req.node_id),
             address: addr,
@@ -200,12 +527,20 @@ impl ClusterAdmin for ClusterAdminHandler {
             version: "unknown".to_string(),
         };
         let join_req = ironfish_core::JoinRequest { node_info };
-        let result = self
-            .state
-            .membership
-            .join(join_req)
-            .await
-            .map_err(|e| Status::internal(e.to_string()))?;
+        let result = match self.state.membership.join(join_req).await {
+            Ok(result) => {
+                let audit = match result.reason {
+                    Some(ref reason) if !result.accepted => audit.failed(reason.clone()),
+                    _ => audit,
+                };
+                self.state.record_audit(audit).await;
+                result
+            }
+            Err(e) => {
+                self.state.record_audit(audit.failed(e.to_string())).await;
+                return Err(error_status(e));
+            }
+        };
         Ok(Response::new(ProtoJoinResponse {
             accepted: result.accepted,
             leader_id: result.leader_id.map(|l| l.to_string()),
@@ -215,19 +550,111 @@ impl ClusterAdmin for ClusterAdminHandler {
                 .map(|m| m.id.to_string())
                 .collect(),
             term: result.term,
+            reason: result.reason,
         }))
     }
     async fn leave_cluster(
         &self,
         request: Request<ProtoLeaveRequest>,
     ) -> Result<Response<ProtoLeaveResponse>, Status> {
+        if let Some(forwarder) = self.leader_forwarder(&request) {
+            let leader = current_leader(&self.state).await.map_err(error_status)?;
+            return forwarder
+                .leave_cluster(&leader, self.state.node.id(), request)
+                .await;
+        }
+        let audit = self.audit_entry(AuditAction::ClusterLeave, &request).await;
         let req = request.into_inner();
+        let audit = audit.with_target(req.node_id.clone());
         let node_id = ironfish_core::NodeId::from_string(req.node_id);
-        self.state
-            .membership
-            .leave(&node_id)
-            .await
-            .map_err(|e| Status::internal(e.to_string()))?;
-        Ok(Response::new(ProtoLeaveResponse { success: true }))
+        match self.state.membership.leave(&node_id).await {
+            Ok(_) => {
+                self.state.record_audit(audit).await;
+                Ok(Response::new(ProtoLeaveResponse { success: true }))
+            }
+            Err(e) => {
+                self.state.record_audit(audit.failed(e.to_string())).await;
+                Err(error_status(e))
+            }
+        }
+    }
+    async fn get_diagnostics(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<ProtoNodeDiagnostics>, Status> {
+        Ok(Response::new(diagnostics_to_proto(
+            self.state.diagnostics().await,
+        )))
+    }
+}
+fn diagnostics_to_proto(diagnostics: NodeDiagnostics) -> ProtoNodeDiagnostics {
+    let pool = diagnostics.engine_pool;
+    ProtoNodeDiagnostics {
+        node_id: diagnostics.node_id.to_string(),
+        state: format!("{:?}", diagnostics.state),
+        term: diagnostics.term,
+        leader_id: diagnostics.leader_id.map(|l| l.to_string()),
+        draining: diagnostics.draining,
+        uptime_seconds: diagnostics.uptime_seconds,
+        memory_usage: diagnostics.memory_usage,
+        engine_pool: Some(ProtoEnginePoolStatus {
+            size: pool.size as u32,
+            available: pool.available as u32,
+            active: pool.active as u32,
+            queue_depth: pool.queue_depth as u32,
+            reserved: pool.reserved as u32,
+            reserved_available: pool.reserved_available as u32,
+            general_available: pool.general_available as u32,
+            engines: pool
+                .engines
+                .into_iter()
+                .map(|slot| ProtoEngineSlot {
+                    index: slot.index as u32,
+                    busy_since: slot.busy_since.map(|t| t.to_rfc3339()),
+                    last_command: slot.last_command,
+                    restarts: slot.restarts,
+                    name: slot.name,
+                })
+                .collect(),
+            engine_name: pool.engine_name,
+        }),
+        analyses: diagnostics
+            .analyses
+            .into_iter()
+            .map(|a| ProtoActiveAnalysis {
+                id: a.id.to_string(),
+                depth: a.depth.map(u32::from),
+                elapsed_ms: a.elapsed_ms,
+            })
+            .collect(),
+        ws_sessions: diagnostics
+            .ws_sessions
+            .into_iter()
+            .map(|s| ProtoSessionSummary {
+                id: s.id.to_string(),
+                token_id: s.token_id.map(|t| t.to_string()),
+                subscriptions: s.subscriptions,
+            })
+            .collect(),
+        peers: diagnostics
+            .peers
+            .into_iter()
+            .map(|p| ProtoPeerStatus {
+                id: p.node_id.to_string(),
+                address: p.address.to_string(),
+                healthy: p.healthy,
+                last_seen_ms: p.last_seen_ms,
+                queued: p.queued as u64,
+                dropped: p.dropped,
+                connect_failures: p.connect_failures,
+                backoff_ms: p.backoff_ms,
+            })
+            .collect(),
+        gossip: diagnostics.gossip.map(|g| ProtoGossipStoreStatus {
+            entries: g.entries as u64,
+            tombstones: g.tombstones as u64,
+            oldest_entry_age_seconds: g.oldest_entry_age_seconds,
+            clock: g.clock,
+        }),
     }
 }
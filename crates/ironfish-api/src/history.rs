@@ -0,0 +1,282 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use ironfish_auth::NodeBudgetReservation;
+use ironfish_core::{
+    AnalysisHistory, AnalysisRecord, AnalysisResult, Error, HistoryPage, HistoryQuery,
+    HistoryRetention, Result,
+};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+use uuid::Uuid;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+#[derive(Clone)]
+pub struct SledAnalysisHistory {
+    db: Arc<sled::Db>,
+    records: sled::Tree,
+    retention: HistoryRetention,
+}
+impl SledAnalysisHistory {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    pub fn in_memory() -> Result<Self> {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    fn from_db(db: sled::Db) -> Result<Self> {
+        let records = db
+            .open_tree("analyses")
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(Self {
+            db: Arc::new(db),
+            records,
+            retention: HistoryRetention::default(),
+        })
+    }
+    pub fn with_retention(mut self, retention: HistoryRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    fn deserialize_record(data: &[u8]) -> Result<AnalysisRecord> {
+        serde_json::from_slice(data).map_err(Error::Serialization)
+    }
+}
+#[async_trait]
+impl AnalysisHistory for SledAnalysisHistory {
+    async fn record(&self, mut record: AnalysisRecord) -> Result<()> {
+        record.seq = self
+            .db
+            .generate_id()
+            .map_err(|e| Error::Storage(e.to_string()))?
+            + 1;
+        let data = serde_json::to_vec(&record).map_err(Error::Serialization)?;
+        self.records
+            .insert(record.seq.to_be_bytes(), data)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
+    }
+    async fn query(&self, query: &HistoryQuery) -> Result<HistoryPage> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let end = query.before.unwrap_or(u64::MAX);
+        let mut records = Vec::new();
+        let mut has_more = false;
+        for result in self.records.range(..end.to_be_bytes()).rev() {
+            let (_, data) = result.map_err(|e| Error::Storage(e.to_string()))?;
+            let record = Self::deserialize_record(&data)?;
+            if query.token_id.is_some() && record.token_id != query.token_id {
+                continue;
+            }
+            if records.len() == limit {
+                has_more = true;
+                break;
+            }
+            records.push(record);
+        }
+        let next_before = if has_more {
+            records.last().map(|r| r.seq)
+        } else {
+            None
+        };
+        Ok(HistoryPage {
+            records,
+            next_before,
+        })
+    }
+    async fn prune(&self) -> Result<usize> {
+        let mut removed = 0;
+        if let Some(days) = self.retention.max_age_days {
+            let cutoff = Utc::now() - Duration::days(days as i64);
+            for result in self.records.iter() {
+                let (key, data) = result.map_err(|e| Error::Storage(e.to_string()))?;
+                if Self::deserialize_record(&data)?.completed_at >= cutoff {
+                    break;
+                }
+                self.records
+                    .remove(key)
+                    .map_err(|e| Error::Storage(e.to_string()))?;
+                removed += 1;
+            }
+        }
+        if let Some(max) = self.retention.max_entries {
+            let excess = self.records.len().saturating_sub(max);
+            for _ in 0..excess {
+                if self
+                    .records
+                    .pop_min()
+                    .map_err(|e| Error::Storage(e.to_string()))?
+                    .is_none()
+                {
+                    break;
+                }
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+            debug!("pruned {} analysis history records", removed);
+        }
+        Ok(removed)
+    }
+}
+#[derive(Clone)]
+pub struct HistoryRecorder {
+    history: Option<Arc<dyn AnalysisHistory>>,
+    node_budget: Arc<Mutex<Option<NodeBudgetReservation>>>,
+    token_id: Option<Uuid>,
+    idempotency_key: Option<String>,
+}
+impl HistoryRecorder {
+    pub fn new(history: Option<Arc<dyn AnalysisHistory>>, token_id: Option<Uuid>) -> Self {
+        Self {
+            history,
+            node_budget: Arc::new(Mutex::new(None)),
+            token_id,
+            idempotency_key: None,
+        }
+    }
+    pub fn with_node_budget(self, reservation: Option<NodeBudgetReservation>) -> Self {
+        *self.node_budget.lock().unwrap() = reservation;
+        self
+    }
+    pub fn with_idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+    pub async fn record(&self, result: &Result<AnalysisResult>) {
+        let reservation = self.node_budget.lock().unwrap().take();
+        if let Some(reservation) = reservation {
+            let nodes = match result {
+                Ok(result) if !result.cached => result.nodes_searched,
+                _ => 0,
+            };
+            reservation.settle(nodes).await;
+        }
+        let Ok(result) = result else {
+            return;
+        };
+        if let Some(ref history) = self.history {
+            let record = AnalysisRecord::new(result, self.token_id)
+                .with_idempotency_key(self.idempotency_key.clone());
+            if let Err(e) = history.record(record).await {
+                warn!("failed to record analysis history: {}", e);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_core::{Move, Score, StopReason, Variant};
+    fn record(token_id: Option<Uuid>) -> AnalysisRecord {
+        AnalysisRecord {
+            seq: 0,
+            id: Uuid::new_v4(),
+            fen: "8/8/8/8/8/8/8/K6k w - - 0 1".to_string(),
+            depth: 12,
+            evaluation: Score::Centipawns(0),
+            time_ms: 40,
+            completed_at: Utc::now(),
+            token_id,
+            idempotency_key: None,
+        }
+    }
+    #[tokio::test]
+    async fn test_history_pages_newest_first_and_prunes() {
+        let history = SledAnalysisHistory::in_memory()
+            .unwrap()
+            .with_retention(HistoryRetention {
+                max_age_days: None,
+                max_entries: Some(3),
+            });
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        for token in [alice, bob, alice, alice, bob] {
+            history.record(record(Some(token))).await.unwrap();
+        }
+        let page = history
+            .query(&HistoryQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.records.len(), 2);
+        assert!(page.records[0].seq > page.records[1].seq);
+        assert_eq!(page.records[0].token_id, Some(bob));
+        let rest = history
+            .query(&HistoryQuery {
+                before: page.next_before,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rest.records.len(), 3);
+        assert!(rest.records[0].seq < page.records[1].seq);
+        assert!(rest.next_before.is_none());
+        let own = history
+            .query(&HistoryQuery {
+                token_id: Some(alice),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(own.records.len(), 3);
+        assert!(own.records.iter().all(|r| r.token_id == Some(alice)));
+        assert_eq!(history.prune().await.unwrap(), 2);
+        assert_eq!(history.len(), 3);
+        let remaining = history.query(&HistoryQuery::default()).await.unwrap();
+        assert_eq!(remaining.records.last().unwrap().token_id, Some(alice));
+    }
+    #[tokio::test]
+    async fn test_cached_results_are_recorded_now() {
+        let history = Arc::new(SledAnalysisHistory::in_memory().unwrap().with_retention(
+            HistoryRetention {
+                max_age_days: Some(30),
+                max_entries: None,
+            },
+        ));
+        let cached = AnalysisResult {
+            id: Uuid::new_v4(),
+            fen: "startpos".to_string(),
+            best_move: Some(Move::new("e2", "e4")),
+            game_over: None,
+            ponder: None,
+            evaluation: Score::Centipawns(20),
+            tablebase: None,
+            best_move_san: None,
+            ponder_san: None,
+            wdl: None,
+            principal_variations: Vec::new(),
+            depth_reached: 10,
+            nodes_searched: 1000,
+            tbhits: 0,
+            time_ms: 5,
+            stopped_by: StopReason::Depth,
+            variant: Variant::Standard,
+            completed_at: Utc::now() - Duration::days(40),
+            cached: true,
+            clamped: false,
+            partial: false,
+            score_history: Vec::new(),
+        };
+        HistoryRecorder::new(Some(history.clone()), None)
+            .record(&Ok(cached))
+            .await;
+        let page = history.query(&HistoryQuery::default()).await.unwrap();
+        assert!(page.records[0].completed_at > Utc::now() - Duration::minutes(1));
+        assert_eq!(history.prune().await.unwrap(), 0);
+        assert_eq!(history.len(), 1);
+    }
+}
@@ -0,0 +1,471 @@
+use crate::rest::ApiError;
+use crate::ApiState;
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ironfish_auth::admin_actor;
+use ironfish_core::TokenContext;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENCY_REPLAYED_HEADER: &str = "idempotency-replayed";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+const MAX_REQUEST_BODY_BYTES: usize = 2 * 1024 * 1024;
+const MAX_RESPONSE_BODY_BYTES: usize = 16 * 1024 * 1024;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    pub capacity: usize,
+    pub max_bytes: usize,
+    pub ttl_secs: u64,
+}
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            max_bytes: 64 * 1024 * 1024,
+            ttl_secs: 86400,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey(pub String);
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayBody(pub Bytes);
+#[derive(Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+impl StoredResponse {
+    fn size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>()
+    }
+    fn replay(&self) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(
+            IDEMPOTENCY_REPLAYED_HEADER,
+            HeaderValue::from_static("true"),
+        );
+        response
+    }
+}
+type Pending = watch::Receiver<Option<Arc<StoredResponse>>>;
+enum Slot {
+    InFlight(Pending),
+    Complete(Arc<StoredResponse>),
+}
+struct Entry {
+    fingerprint: Vec<u8>,
+    slot: Slot,
+    stored_at: Instant,
+    seq: u64,
+    size: usize,
+}
+impl Entry {
+    fn expired(&self, ttl: Duration) -> bool {
+        match self.slot {
+            Slot::InFlight(ref pending) => pending.has_changed().is_err(),
+            Slot::Complete(_) => self.stored_at.elapsed() >= ttl,
+        }
+    }
+}
+type EntryKey = (String, String);
+enum Claim {
+    Owner(watch::Sender<Option<Arc<StoredResponse>>>),
+    Attach(Pending),
+    Replay(Arc<StoredResponse>),
+    Mismatch,
+}
+#[derive(Default)]
+struct StoreInner {
+    entries: HashMap<EntryKey, Entry>,
+    order: VecDeque<(EntryKey, u64)>,
+    next_seq: u64,
+    bytes: usize,
+}
+impl StoreInner {
+    fn remove(&mut self, key: &EntryKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.size;
+        }
+    }
+    fn evict(&mut self, capacity: usize, max_bytes: usize, ttl: Duration) {
+        while let Some((key, seq)) = self.order.front() {
+            let live = match self.entries.get(key) {
+                Some(entry) if entry.seq == *seq => {
+                    if self.entries.len() <= capacity
+                        && self.bytes <= max_bytes
+                        && !entry.expired(ttl)
+                    {
+                        break;
+                    }
+                    true
+                }
+                _ => false,
+            };
+            if let Some((key, _)) = self.order.pop_front() {
+                if live {
+                    self.remove(&key);
+                }
+            }
+        }
+        if self.order.len() > self.entries.len() * 2 {
+            let entries = &self.entries;
+            self.order
+                .retain(|(key, seq)| entries.get(key).is_some_and(|entry| entry.seq == *seq));
+        }
+    }
+}
+pub struct IdempotencyStore {
+    inner: Mutex<StoreInner>,
+    capacity: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+impl IdempotencyStore {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        Self {
+            inner: Mutex::new(StoreInner::default()),
+            capacity: config.capacity.max(1),
+            max_bytes: config.max_bytes,
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.entries.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.inner.lock().await.entries.is_empty()
+    }
+    pub async fn size_bytes(&self) -> usize {
+        self.inner.lock().await.bytes
+    }
+    async fn claim(&self, key: &EntryKey, fingerprint: &[u8]) -> Claim {
+        let mut inner = self.inner.lock().await;
+        match inner.entries.get(key) {
+            Some(entry) if entry.expired(self.ttl) => inner.remove(key),
+            Some(entry) if entry.fingerprint != fingerprint => return Claim::Mismatch,
+            Some(entry) => {
+                return match entry.slot {
+                    Slot::InFlight(ref pending) => Claim::Attach(pending.clone()),
+                    Slot::Complete(ref response) => Claim::Replay(response.clone()),
+                }
+            }
+            None => {}
+        }
+        let (tx, rx) = watch::channel(None);
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        let size = key.0.len() + key.1.len() + fingerprint.len();
+        inner.bytes += size;
+        inner.order.push_back((key.clone(), seq));
+        inner.entries.insert(
+            key.clone(),
+            Entry {
+                fingerprint: fingerprint.to_vec(),
+                slot: Slot::InFlight(rx),
+                stored_at: Instant::now(),
+                seq,
+                size,
+            },
+        );
+        inner.evict(self.capacity, self.max_bytes, self.ttl);
+        Claim::Owner(tx)
+    }
+    async fn complete(&self, key: &EntryKey, response: Arc<StoredResponse>) {
+        if !response.status.is_success() || response.size() > self.max_bytes {
+            return self.release(key).await;
+        }
+        let mut inner = self.inner.lock().await;
+        let StoreInner { entries, bytes, .. } = &mut *inner;
+        if let Some(entry) = entries.get_mut(key) {
+            let size = key.0.len() + key.1.len() + entry.fingerprint.len() + response.size();
+            *bytes = *bytes - entry.size + size;
+            entry.size = size;
+            entry.slot = Slot::Complete(response);
+            entry.stored_at = Instant::now();
+        }
+        inner.evict(self.capacity, self.max_bytes, self.ttl);
+    }
+    async fn release(&self, key: &EntryKey) {
+        self.inner.lock().await.remove(key);
+    }
+}
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        Self::new(IdempotencyConfig::default())
+    }
+}
+fn parse_key(value: &HeaderValue) -> Option<String> {
+    let value = value.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= MAX_IDEMPOTENCY_KEY_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+fn identity(request: &Request) -> String {
+    if let Some(id) = request
+        .extensions()
+        .get::<TokenContext>()
+        .and_then(|token| token.id)
+    {
+        return format!("token:{}", id);
+    }
+    request
+        .headers()
+        .get("x-admin-key")
+        .and_then(|h| h.to_str().ok())
+        .map(admin_actor)
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+fn fingerprint(request: &Request, body: &[u8]) -> Vec<u8> {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(request.method().as_str().as_bytes());
+    ctx.update(b" ");
+    ctx.update(request.uri().path().as_bytes());
+    ctx.update(b"?");
+    ctx.update(request.uri().query().unwrap_or_default().as_bytes());
+    ctx.update(b"\n");
+    ctx.update(body);
+    ctx.finish().as_ref().to_vec()
+}
+pub async fn idempotent_requests(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(value) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let Some(key) = parse_key(value) else {
+        return ApiError::bad_request(
+            "invalid_idempotency_key",
+            format!(
+                "Idempotency-Key must be 1-{} visible ASCII characters",
+                MAX_IDEMPOTENCY_KEY_LEN
+            ),
+        )
+        .into_response();
+    };
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_REQUEST_BODY_BYTES).await else {
+        return ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "request body is too large",
+        )
+        .into_response();
+    };
+    let mut request = Request::from_parts(parts, Body::from(body.clone()));
+    let entry_key = (identity(&request), key.clone());
+    let fingerprint = fingerprint(&request, &body);
+    let store = state.idempotency.clone();
+    loop {
+        match store.claim(&entry_key, &fingerprint).await {
+            Claim::Replay(response) => return response.replay(),
+            Claim::Mismatch => {
+                return ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "idempotency_key_reused",
+                    "Idempotency-Key was already used for a different request",
+                )
+                .into_response()
+            }
+            Claim::Attach(mut pending) => {
+                if let Ok(response) = pending.wait_for(Option::is_some).await {
+                    if let Some(ref response) = *response {
+                        return response.replay();
+                    }
+                }
+            }
+            Claim::Owner(tx) => {
+                request.extensions_mut().insert(IdempotencyKey(key.clone()));
+                let response = next.run(request).await;
+                let (mut parts, body) = response.into_parts();
+                let Ok(body) = axum::body::to_bytes(body, MAX_RESPONSE_BODY_BYTES).await else {
+                    store.release(&entry_key).await;
+                    return ApiError::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "internal_error",
+                        "response body could not be buffered",
+                    )
+                    .into_response();
+                };
+                let mut headers = parts.headers.clone();
+                headers.remove(CONTENT_LENGTH);
+                let stored = Arc::new(StoredResponse {
+                    status: parts.status,
+                    headers,
+                    body: parts
+                        .extensions
+                        .remove::<ReplayBody>()
+                        .map(|replay| replay.0)
+                        .unwrap_or_else(|| body.clone()),
+                });
+                store.complete(&entry_key, stored.clone()).await;
+                let _ = tx.send(Some(stored));
+                return Response::from_parts(parts, Body::from(body));
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn stored(status: StatusCode, body: &'static str) -> Arc<StoredResponse> {
+        Arc::new(StoredResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        })
+    }
+    fn key(k: &str) -> EntryKey {
+        ("token:a".to_string(), k.to_string())
+    }
+    #[tokio::test]
+    async fn test_duplicates_attach_then_replay() {
+        let store = IdempotencyStore::default();
+        let Claim::Owner(tx) = store.claim(&key("k1"), b"fp").await else {
+            panic!("first claim should own the key");
+        };
+        let Claim::Attach(mut pending) = store.claim(&key("k1"), b"fp").await else {
+            panic!("duplicate should attach to the in-flight request");
+        };
+        let response = stored(StatusCode::OK, "{}");
+        store.complete(&key("k1"), response.clone()).await;
+        tx.send(Some(response)).unwrap();
+        let attached = pending.wait_for(Option::is_some).await.unwrap().clone();
+        assert_eq!(attached.unwrap().body, Bytes::from_static(b"{}"));
+        assert!(matches!(
+            store.claim(&key("k1"), b"fp").await,
+            Claim::Replay(_)
+        ));
+        assert!(matches!(
+            store.claim(&key("k1"), b"other").await,
+            Claim::Mismatch
+        ));
+        assert!(matches!(
+            store
+                .claim(&("token:b".to_string(), "k1".to_string()), b"fp")
+                .await,
+            Claim::Owner(_)
+        ));
+    }
+    #[tokio::test]
+    async fn test_failures_and_abandoned_requests_are_not_stored() {
+        let store = IdempotencyStore::default();
+        let Claim::Owner(_tx) = store.claim(&key("k1"), b"fp").await else {
+            panic!("first claim should own the key");
+        };
+        store
+            .complete(&key("k1"), stored(StatusCode::SERVICE_UNAVAILABLE, ""))
+            .await;
+        assert!(matches!(
+            store.claim(&key("k1"), b"fp").await,
+            Claim::Owner(_)
+        ));
+        assert!(matches!(
+            store.claim(&key("k1"), b"fp").await,
+            Claim::Owner(_)
+        ));
+    }
+    #[tokio::test]
+    async fn test_capacity_evicts_oldest() {
+        let store = IdempotencyStore::new(IdempotencyConfig {
+            capacity: 2,
+            ..Default::default()
+        });
+        for k in ["a", "b", "c"] {
+            let Claim::Owner(_tx) = store.claim(&key(k), b"fp").await else {
+                panic!("new key should be owned");
+            };
+            store.complete(&key(k), stored(StatusCode::OK, "")).await;
+        }
+        assert_eq!(store.len().await, 2);
+        assert!(matches!(
+            store.claim(&key("c"), b"fp").await,
+            Claim::Replay(_)
+        ));
+        assert!(matches!(
+            store.claim(&key("a"), b"fp").await,
+            Claim::Owner(_)
+        ));
+    }
+    #[tokio::test]
+    async fn test_total_bytes_evict_oldest() {
+        let store = IdempotencyStore::new(IdempotencyConfig {
+            max_bytes: 64,
+            ..Default::default()
+        });
+        for k in ["a", "b"] {
+            let Claim::Owner(_tx) = store.claim(&key(k), b"fp").await else {
+                panic!("new key should be owned");
+            };
+            store
+                .complete(
+                    &key(k),
+                    stored(StatusCode::OK, "0123456789012345678901234567"),
+                )
+                .await;
+        }
+        assert_eq!(store.len().await, 1);
+        assert!(store.size_bytes().await <= 64);
+        assert!(matches!(
+            store.claim(&key("b"), b"fp").await,
+            Claim::Replay(_)
+        ));
+        let Claim::Owner(_tx) = store.claim(&key("big"), b"fp").await else {
+            panic!("new key should be owned");
+        };
+        store
+            .complete(&key("big"), stored(StatusCode::OK, "x".repeat(65).leak()))
+            .await;
+        assert!(matches!(
+            store.claim(&key("big"), b"fp").await,
+            Claim::Owner(_)
+        ));
+    }
+    #[tokio::test]
+    async fn test_released_keys_do_not_grow_the_queue() {
+        let store = IdempotencyStore::default();
+        for _ in 0..100 {
+            let Claim::Owner(_tx) = store.claim(&key("k1"), b"fp").await else {
+                panic!("released key should be claimable again");
+            };
+            store.release(&key("k1")).await;
+        }
+        let inner = store.inner.lock().await;
+        assert!(inner.order.len() <= 2);
+        assert_eq!(inner.bytes, 0);
+    }
+    #[tokio::test]
+    async fn test_completed_responses_expire() {
+        let store = IdempotencyStore::new(IdempotencyConfig {
+            ttl_secs: 0,
+            ..Default::default()
+        });
+        let Claim::Owner(_tx) = store.claim(&key("k1"), b"fp").await else {
+            panic!("first claim should own the key");
+        };
+        store.complete(&key("k1"), stored(StatusCode::OK, "")).await;
+        let Claim::Owner(_tx) = store.claim(&key("k1"), b"fp").await else {
+            panic!("expired key should be claimable again");
+        };
+    }
+}
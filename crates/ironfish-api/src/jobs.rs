@@ -0,0 +1,221 @@
+use crate::admission::AdmissionPermit;
+use crate::history::HistoryRecorder;
+use crate::store::AnalysisStore;
+use crate::webhooks::Webhooks;
+use ironfish_core::{AnalysisProgress, AnalysisRequest, Error, Result};
+use ironfish_stockfish::AnalysisService;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, Instrument};
+use uuid::Uuid;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisJobConfig {
+    pub queue_capacity: usize,
+    pub max_concurrent: usize,
+}
+impl Default for AnalysisJobConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 64,
+            max_concurrent: 4,
+        }
+    }
+}
+pub struct AnalysisJobs {
+    active: Arc<Mutex<HashMap<Uuid, CancellationToken>>>,
+    slots: Arc<Semaphore>,
+    capacity: usize,
+}
+impl AnalysisJobs {
+    pub fn new(config: AnalysisJobConfig) -> Self {
+        Self {
+            active: Arc::new(Mutex::new(HashMap::new())),
+            slots: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            capacity: config.queue_capacity.max(1),
+        }
+    }
+    pub async fn len(&self) -> usize {
+        self.active.lock().await.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.active.lock().await.is_empty()
+    }
+    pub async fn submit(
+        &self,
+        request: AnalysisRequest,
+        analysis: Arc<AnalysisService>,
+        store: Arc<AnalysisStore>,
+        history: Option<HistoryRecorder>,
+        webhooks: Option<Arc<Webhooks>>,
+        admission: AdmissionPermit,
+    ) -> Result<Uuid> {
+        let id = request.id;
+        let cancel = CancellationToken::new();
+        {
+            let mut active = self.active.lock().await;
+            if active.len() >= self.capacity {
+                return Err(Error::RateLimitExceeded);
+            }
+            active.insert(id, cancel.clone());
+        }
+        store.queue(id).await;
+        let active = self.active.clone();
+        let slots = self.slots.clone();
+        tokio::spawn(
+            async move {
+                let permit = tokio::select! {
+                    permit = slots.acquire_owned() => permit.ok(),
+                    _ = cancel.cancelled() => None,
+                };
+                let result = match permit {
+                    Some(_permit) => {
+                        store.begin(id).await;
+                        let (progress_tx, mut progress_rx) = mpsc::channel::<AnalysisProgress>(32);
+                        let progress_store = store.clone();
+                        let progress_task = tokio::spawn(async move {
+                            while let Some(progress) = progress_rx.recv().await {
+                                progress_store.progress(id, progress).await;
+                            }
+                        });
+                        let result = analysis
+                            .analyze_streaming(request, progress_tx, cancel)
+                            .await;
+                        let _ = progress_task.await;
+                        result
+                    }
+                    None => Err(Error::AnalysisCancelled),
+                };
+                drop(admission);
+                store.finish(id, &result).await;
+                if let Some(history) = history {
+                    history.record(&result).await;
+                }
+                if let Some(webhooks) = webhooks {
+                    webhooks.analysis_finished(id, &result);
+                }
+                active.lock().await.remove(&id);
+                debug!("analysis job {} finished", id);
+            }
+            .in_current_span(),
+        );
+        Ok(id)
+    }
+    pub async fn cancel(&self, id: &Uuid) -> bool {
+        match self.active.lock().await.get(id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+impl Default for AnalysisJobs {
+    fn default() -> Self {
+        Self::new(AnalysisJobConfig::default())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::AnalysisAdmission;
+    use crate::store::AnalysisEntry;
+    use std::time::Duration;
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    async fn wait_finished(store: &AnalysisStore, id: &Uuid) -> AnalysisEntry {
+        for _ in 0..100 {
+            if let Some(entry) = store.get(id).await {
+                if entry.is_finished() {
+                    return entry;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("analysis job {} did not finish", id);
+    }
+    #[tokio::test]
+    async fn test_queue_bounds_and_cancel_before_start() {
+        let jobs = AnalysisJobs::new(AnalysisJobConfig {
+            queue_capacity: 2,
+            max_concurrent: 1,
+        });
+        let analysis = Arc::new(AnalysisService::new_mock());
+        let store = Arc::new(AnalysisStore::default());
+        let admission = AnalysisAdmission::new(4);
+        let first = jobs
+            .submit(
+                AnalysisRequest::new(START_FEN),
+                analysis.clone(),
+                store.clone(),
+                None,
+                None,
+                admission.try_admit().unwrap(),
+            )
+            .await
+            .unwrap();
+        let second = jobs
+            .submit(
+                AnalysisRequest::new(START_FEN),
+                analysis.clone(),
+                store.clone(),
+                None,
+                None,
+                admission.try_admit().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            jobs.submit(
+                AnalysisRequest::new(START_FEN),
+                analysis.clone(),
+                store.clone(),
+                None,
+                None,
+                admission.try_admit().unwrap(),
+            )
+            .await,
+            Err(Error::RateLimitExceeded)
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.get(&second).await.unwrap().status(), "queued");
+        assert!(jobs.cancel(&second).await);
+        assert!(matches!(
+            wait_finished(&store, &second).await,
+            AnalysisEntry::Cancelled
+        ));
+        assert!(matches!(
+            wait_finished(&store, &first).await,
+            AnalysisEntry::Complete(_)
+        ));
+        assert!(jobs.is_empty().await);
+        assert!(!jobs.cancel(&first).await);
+    }
+    #[tokio::test]
+    async fn test_job_holds_admission_until_finished() {
+        let jobs = AnalysisJobs::default();
+        let analysis = Arc::new(AnalysisService::new_mock());
+        let store = Arc::new(AnalysisStore::default());
+        let admission = AnalysisAdmission::new(1);
+        let id = jobs
+            .submit(
+                AnalysisRequest::new(START_FEN),
+                analysis,
+                store.clone(),
+                None,
+                None,
+                admission.try_admit().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            admission.try_admit(),
+            Err(Error::TooManyAnalyses { .. })
+        ));
+        wait_finished(&store, &id).await;
+        assert!(admission.try_admit().is_ok());
+    }
+}
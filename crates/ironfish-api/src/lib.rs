@@ -1,9 +1,42 @@
+mod admission;
+mod forward;
 pub mod graphql;
 pub mod grpc;
+mod history;
+mod idempotency;
+mod jobs;
+mod readiness;
+mod reload;
+mod request_id;
 pub mod rest;
 mod router;
+mod store;
+mod telemetry;
+mod tls;
+mod webhooks;
 pub mod ws;
-pub use router::{ApiRouter, ApiState, WebSocketConfig};
+pub use admission::{AdmissionPermit, AnalysisAdmission};
+pub use forward::{
+    AdminWrites, AnalysisForwarder, ForwardingConfig, LeaderForwarder, FORWARDED_FROM_HEADER,
+    FORWARDED_SECRET_HEADER, SERVED_BY_HEADER,
+};
+pub use history::{HistoryRecorder, SledAnalysisHistory};
+pub use idempotency::{
+    IdempotencyConfig, IdempotencyKey, IdempotencyStore, IDEMPOTENCY_KEY_HEADER,
+    IDEMPOTENCY_REPLAYED_HEADER,
+};
+pub use jobs::{AnalysisJobConfig, AnalysisJobs};
+pub use readiness::Readiness;
+pub use reload::ConfigReloader;
+pub use request_id::{current_request_id, RequestId, REQUEST_ID_HEADER};
+pub use router::{ApiRouter, ApiState, SlowConsumerPolicy, WebSocketConfig, CLUSTER_SECRET_HEADER};
+pub use store::{AnalysisEntry, AnalysisStore, AnalysisStoreConfig};
+pub use telemetry::MetricsRegistry;
+pub use tls::{serve_tls, PeerTls, ServerTls, ServerTlsConfig};
+pub use webhooks::{
+    spawn_webhook_notifier, DeliveryStatus, WebhookConfig, WebhookDeliveries, WebhookDelivery,
+    WebhookEndpoint, WebhookEvent, Webhooks, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER,
+};
 pub mod proto {
     tonic::include_proto!("chess");
     pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("chess_descriptor");
@@ -0,0 +1,126 @@
+use crate::router::ApiState;
+use ironfish_core::{CheckStatus, HealthCheck, NodeState, StoreHealth};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(2);
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+pub struct Readiness {
+    ttl: Duration,
+    probe_timeout: Duration,
+    cached: Mutex<Option<(Instant, Vec<HealthCheck>)>>,
+}
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_TTL, DEFAULT_PROBE_TIMEOUT)
+    }
+}
+impl Readiness {
+    pub fn new(ttl: Duration, probe_timeout: Duration) -> Self {
+        Self {
+            ttl,
+            probe_timeout,
+            cached: Mutex::new(None),
+        }
+    }
+    pub async fn checks(&self, state: &ApiState) -> Vec<HealthCheck> {
+        let mut cached = self.cached.lock().await;
+        if let Some((at, ref checks)) = *cached {
+            if at.elapsed() < self.ttl {
+                return checks.clone();
+            }
+        }
+        let checks = vec![
+            self.engine_pool(state).await,
+            self.token_store(state).await,
+            cluster(state),
+        ];
+        let previous = cached.take().map(|(_, checks)| checks).unwrap_or_default();
+        log_transitions(&previous, &checks);
+        *cached = Some((Instant::now(), checks.clone()));
+        checks
+    }
+    async fn engine_pool(&self, state: &ApiState) -> HealthCheck {
+        if state.analysis.is_mock() {
+            return HealthCheck {
+                name: "engine_pool".to_string(),
+                status: CheckStatus::Pass,
+                latency_ms: 0,
+                detail: Some("mock".to_string()),
+            };
+        }
+        let limit = self.probe_timeout;
+        timed("engine_pool", limit, async {
+            state.analysis.probe(limit).await.map(|()| None)
+        })
+        .await
+    }
+    async fn token_store(&self, state: &ApiState) -> HealthCheck {
+        timed("token_store", self.probe_timeout, async {
+            state.token_store.get(&uuid::Uuid::nil()).await?;
+            Ok(match state.token_store.health() {
+                StoreHealth::Healthy => None,
+                health => Some(health.as_str().to_string()),
+            })
+        })
+        .await
+    }
+}
+fn cluster(state: &ApiState) -> HealthCheck {
+    let node_state = state.node.state();
+    let status = match (&state.network, node_state) {
+        (None, _) => CheckStatus::Skipped,
+        (Some(_), NodeState::Starting | NodeState::Dead) => CheckStatus::Fail,
+        (Some(_), _) => CheckStatus::Pass,
+    };
+    HealthCheck {
+        name: "cluster".to_string(),
+        status,
+        latency_ms: 0,
+        detail: match status {
+            CheckStatus::Skipped => Some("clustering disabled".to_string()),
+            _ => Some(format!("node state is {:?}", node_state).to_lowercase()),
+        },
+    }
+}
+async fn timed<F>(name: &str, limit: Duration, probe: F) -> HealthCheck
+where
+    F: Future<Output = ironfish_core::Result<Option<String>>>,
+{
+    let started = Instant::now();
+    let (status, detail) = match timeout(limit, probe).await {
+        Ok(Ok(detail)) => (CheckStatus::Pass, detail),
+        Ok(Err(e)) => (CheckStatus::Fail, Some(e.to_string())),
+        Err(_) => (
+            CheckStatus::Fail,
+            Some(format!("no answer within {}ms", limit.as_millis())),
+        ),
+    };
+    HealthCheck {
+        name: name.to_string(),
+        status,
+        latency_ms: started.elapsed().as_millis() as u64,
+        detail,
+    }
+}
+fn log_transitions(previous: &[HealthCheck], checks: &[HealthCheck]) {
+    for check in checks {
+        let before = previous
+            .iter()
+            .find(|p| p.name == check.name)
+            .map(|p| p.status);
+        match (before, check.status) {
+            (Some(before), now) if before == now => {}
+            (_, CheckStatus::Fail) => tracing::warn!(
+                "readiness check {} failed: {}",
+                check.name,
+                check.detail.as_deref().unwrap_or("")
+            ),
+            (Some(CheckStatus::Fail), _) => {
+                tracing::info!("readiness check {} recovered", check.name)
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use crate::router::ApiState;
+use async_trait::async_trait;
+use ironfish_core::{ConfigReloadReport, Result};
+#[async_trait]
+pub trait ConfigReloader: Send + Sync {
+    async fn reload(&self, state: &ApiState) -> Result<ConfigReloadReport>;
+}
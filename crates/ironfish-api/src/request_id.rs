@@ -0,0 +1,79 @@
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde_json::Value;
+use tracing::Instrument;
+use uuid::Uuid;
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: String;
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+}
+fn inbound_request_id(request: &Request) -> Option<String> {
+    let value = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = inbound_request_id(&request).unwrap_or_else(|| Uuid::new_v4().to_string());
+    let header_value = HeaderValue::from_str(&id).expect("request id is visible ascii");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let response = CURRENT_REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    let mut response = with_request_id_in_error(response, &id).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+    response
+}
+async fn with_request_id_in_error(response: Response, id: &str) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let encoded = response.headers().contains_key(header::CONTENT_ENCODING);
+    if !(response.status().is_client_error() || response.status().is_server_error())
+        || !is_json
+        || encoded
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(mut value) => match value.get_mut("error").and_then(Value::as_object_mut) {
+            Some(error) => {
+                error.insert("request_id".to_string(), Value::String(id.to_string()));
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Body::from(value.to_string())
+            }
+            None => Body::from(bytes),
+        },
+        Err(_) => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,186 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use ironfish_core::Error;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+const ENGINE_BUSY_RETRY_AFTER_SECS: u64 = 1;
+const NO_LEADER_RETRY_AFTER_SECS: u64 = 2;
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    body: ErrorBody,
+    retry_after: Option<u64>,
+}
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorBody {
+                code: code.to_string(),
+                message: message.into(),
+                details: None,
+                request_id: None,
+            },
+            retry_after: None,
+        }
+    }
+    pub fn bad_request(code: &str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+    pub fn not_found(code: &str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, message)
+    }
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.body.details = Some(details);
+        self
+    }
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+    pub fn body(&self) -> &ErrorBody {
+        &self.body
+    }
+}
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(
+            rejection.status(),
+            "invalid_argument",
+            rejection.body_text(),
+        )
+    }
+}
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(
+            rejection.status(),
+            "invalid_argument",
+            rejection.body_text(),
+        )
+    }
+}
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let status =
+            StatusCode::from_u16(e.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let error = Self::new(status, e.code(), e.to_string());
+        match e {
+            Error::InvalidMove { index, notation } => error.with_details(serde_json::json!({
+                "move_index": index,
+                "move": notation,
+            })),
+            Error::InvalidSearchMoves(moves) => error.with_details(serde_json::json!({
+                "search_moves": moves,
+            })),
+            Error::EngineBusy => error.with_retry_after(ENGINE_BUSY_RETRY_AFTER_SECS),
+            Error::NoLeader => error.with_retry_after(NO_LEADER_RETRY_AFTER_SECS),
+            Error::TooManyAnalyses { retry_after_secs } => error.with_retry_after(retry_after_secs),
+            Error::NodeBudgetExhausted { resets_at } => {
+                let secs = (resets_at - chrono::Utc::now()).num_seconds().max(1) as u64;
+                error
+                    .with_details(serde_json::json!({ "resets_at": resets_at }))
+                    .with_retry_after(secs)
+            }
+            _ => error,
+        }
+    }
+}
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(ErrorResponse { error: self.body })).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, secs.into());
+        }
+        response
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    async fn render(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+    #[tokio::test]
+    async fn test_core_errors_map_to_status_and_code() {
+        let cases = [
+            (Error::InvalidFen("x".into()), 400, "invalid_fen"),
+            (Error::TokenNotFound, 404, "token_not_found"),
+            (Error::AnalysisTimeout, 408, "analysis_timeout"),
+            (Error::RateLimitExceeded, 429, "rate_limited"),
+            (Error::PoolExhausted, 503, "engine_unavailable"),
+            (Error::ClusterUnavailable, 503, "cluster_unavailable"),
+            (Error::Storage("disk".into()), 500, "storage"),
+        ];
+        for (error, status, code) in cases {
+            let (actual, body) = render(error.into()).await;
+            assert_eq!(actual.as_u16(), status);
+            assert_eq!(body["error"]["code"], code);
+            assert!(body["error"]["message"].is_string());
+            assert!(body["error"].get("details").is_none());
+        }
+    }
+    #[tokio::test]
+    async fn test_invalid_move_carries_details() {
+        let (status, body) = render(
+            Error::InvalidMove {
+                index: 2,
+                notation: "e1e3".into(),
+            }
+            .into(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_move");
+        assert_eq!(body["error"]["details"]["move_index"], 2);
+        assert_eq!(body["error"]["details"]["move"], "e1e3");
+    }
+    #[tokio::test]
+    async fn test_engine_busy_sets_retry_after() {
+        let response = ApiError::from(Error::EngineBusy).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let response = ApiError::from(Error::NoLeader).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let response = ApiError::from(Error::TooManyAnalyses {
+            retry_after_secs: 7,
+        })
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "7");
+        let response = ApiError::from(Error::PoolExhausted).into_response();
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+}
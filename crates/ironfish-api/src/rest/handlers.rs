@@ -1,264 +1,1426 @@
-use crate::ApiState;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use super::{ApiError, ApiJson, ApiQuery, ErrorResponse};
+use crate::idempotency::ReplayBody;
+use crate::{
+    AnalysisEntry, ApiState, IdempotencyKey, WebhookDeliveries, CLUSTER_SECRET_HEADER,
+    FORWARDED_FROM_HEADER, SERVED_BY_HEADER,
+};
+use axum::extract::{FromRequestParts, Path, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::IntoResponse;
-use axum::Json;
+use axum::{Extension, Json};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use ironfish_auth::{admin_actor, source_ip, OptionalTokenContext};
 use ironfish_core::{
-    AnalysisRequest, AnalysisResult, BestMoveRequest, BestMoveResponse, ClusterStatus,
-    CreateTokenRequest, CreateTokenResponse, JoinRequest, NodeInfo, TokenMetadata, TokenStore,
+    AnalysisProgress, AnalysisRequest, AnalysisResult, AuditAction, AuditEntry, AuditPage,
+    AuditQuery, BestMoveRequest, BestMoveResponse, ChessPosition, ClassificationThresholds,
+    ClusterStatus, ConfigReloadReport, CreateTokenRequest, CreateTokenResponse, DrainStatus,
+    EnginePoolStatus, Error, Game, GameAnalysis, GameAnalysisRequest, HealthResponse, HistoryPage,
+    HistoryQuery, JoinRequest, JoinResponse, MetricsResponse, NodeDiagnostics, NodeInfo, Notation,
+    Perspective, TokenContext, TokenCursor, TokenFilter, TokenListQuery, TokenMetadata, TokenOrder,
+    TokenReplicaReport, TokenUsage, Variant,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
-#[derive(Debug, Deserialize)]
+pub const NODE_BUDGET_REMAINING_HEADER: &str = "x-ironfish-node-budget-remaining";
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(5);
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AnalyzeBody {
+    #[serde(default)]
     pub fen: String,
+    #[serde(default)]
+    pub startpos: bool,
+    #[serde(default)]
+    pub moves: Vec<String>,
+    #[serde(default)]
+    pub search_moves: Vec<String>,
     #[serde(default = "default_depth")]
     pub depth: u8,
-    #[serde(default = "default_multipv")]
-    pub multipv: u8,
+    pub multipv: Option<u8>,
     pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    #[serde(default)]
+    pub variant: Variant,
+    pub skill_level: Option<u8>,
+    #[serde(default)]
+    pub perspective: Perspective,
+    #[serde(default)]
+    pub notation: Notation,
+    #[serde(default)]
+    pub include_history: bool,
+}
+fn start_fen(fen: String, startpos: bool) -> String {
+    if startpos {
+        ChessPosition::starting().fen
+    } else {
+        fen
+    }
 }
 fn default_depth() -> u8 {
     20
 }
-fn default_multipv() -> u8 {
-    1
-}
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct BestMoveBody {
+    #[serde(default)]
     pub fen: String,
+    #[serde(default)]
+    pub startpos: bool,
+    #[serde(default)]
+    pub moves: Vec<String>,
     pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    #[serde(default)]
+    pub variant: Variant,
 }
-#[derive(Debug, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GameAnalysisBody {
+    pub pgn: Option<String>,
+    #[serde(default)]
+    pub moves: Vec<String>,
+    pub fen: Option<String>,
+    pub variant: Option<Variant>,
+    pub depth: Option<u8>,
+    pub movetime: Option<u64>,
+    #[serde(default)]
+    pub thresholds: ClassificationThresholds,
 }
-#[derive(Debug, Serialize)]
-pub struct HealthResponse {
-    pub status: String,
-    pub node_id: String,
-    pub version: String,
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub actor: String,
+    pub source_ip: Option<String>,
+    pub idempotency_key: Option<String>,
+}
+impl AuditContext {
+    pub fn for_token(mut self, token: &TokenContext) -> Self {
+        if let (true, Some(id)) = (self.actor == "anonymous", token.id) {
+            self.actor = format!("token:{}", id);
+        }
+        self
+    }
+    pub fn entry(&self, action: AuditAction, state: &ApiState) -> AuditEntry {
+        AuditEntry::new(action, self.actor.clone(), state.node.id().to_string())
+            .with_source_ip(self.source_ip.clone())
+            .with_idempotency_key(self.idempotency_key.clone())
+    }
+}
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = std::convert::Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let actor = parts
+            .headers
+            .get("x-admin-key")
+            .and_then(|h| h.to_str().ok())
+            .map(admin_actor)
+            .unwrap_or_else(|| "anonymous".to_string());
+        Ok(Self {
+            actor,
+            source_ip: source_ip(&parts.headers, &parts.extensions),
+            idempotency_key: parts
+                .extensions
+                .get::<IdempotencyKey>()
+                .map(|key| key.0.clone()),
+        })
+    }
 }
-#[derive(Debug, Serialize)]
-pub struct MetricsResponse {
-    pub cpu_usage: f32,
-    pub memory_usage: f32,
-    pub active_analyses: u32,
-    pub queue_depth: u32,
-    pub engines_available: u32,
-    pub engines_total: u32,
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnalyzeParams {
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
 }
+#[utoipa::path(
+    post,
+    path = "/v1/analyze",
+    tag = "analysis",
+    params(AnalyzeParams, ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response for a repeated key instead of running the request again")),
+    request_body = AnalyzeBody,
+    responses(
+        (status = 200, description = "Completed analysis", body = AnalysisResult),
+        (status = 202, description = "Analysis job queued", body = AnalysisStatusResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 429, description = "Rate limited, too many concurrent analyses or node budget exhausted", body = ErrorResponse),
+        (status = 503, description = "Node draining or no engine available", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
 pub async fn analyze(
     State(state): State<Arc<ApiState>>,
-    Json(body): Json<AnalyzeBody>,
-) -> Result<Json<AnalysisResult>, (StatusCode, Json<ErrorResponse>)> {
-    let request = AnalysisRequest::new(&body.fen)
+    ApiQuery(params): ApiQuery<AnalyzeParams>,
+    OptionalTokenContext(token): OptionalTokenContext,
+    idempotency_key: Option<Extension<IdempotencyKey>>,
+    headers: HeaderMap,
+    ApiJson(body): ApiJson<AnalyzeBody>,
+) -> Result<axum::response::Response, ApiError> {
+    state.ensure_accepting().map_err(ApiError::from)?;
+    let multipv = body
+        .multipv
+        .unwrap_or_else(|| state.limits().default_multipv(&body.search_moves));
+    state
+        .limits()
+        .check(body.depth.into(), multipv.into(), body.movetime)
+        .map_err(ApiError::from)?;
+    let request = AnalysisRequest::new(start_fen(body.fen, body.startpos))
         .with_depth(body.depth)
-        .with_multipv(body.multipv);
+        .with_multipv(multipv)
+        .with_variant(body.variant)
+        .with_perspective(body.perspective)
+        .with_notation(body.notation)
+        .with_history(body.include_history)
+        .with_moves(body.moves)
+        .with_search_moves(body.search_moves);
     let request = match body.movetime {
         Some(ms) => request.with_movetime(ms),
         None => request,
     };
-    match state.analysis.analyze(request).await {
-        Ok(result) => Ok(Json(result)),
-        Err(e) => Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
+    let request = match body.nodes {
+        Some(nodes) => request.with_nodes(nodes),
+        None => request,
+    };
+    let request = match body.skill_level {
+        Some(level) => request.with_skill_level(level),
+        None => request,
+    };
+    let request = token.clamp(request);
+    let reservation = state
+        .reserve_node_budget(&token, &request)
+        .await
+        .map_err(ApiError::from)?;
+    let history = state.history_recorder(token.id).map(|recorder| {
+        recorder
+            .with_idempotency_key(idempotency_key.map(|key| key.0 .0))
+            .with_node_budget(reservation)
+    });
+    if !params.run_async {
+        let _permit = state.admit().map_err(ApiError::from)?;
+        let local_id = state.node.id().to_string();
+        let response = if state.forwarded_by_peer(&headers).is_some() {
+            state
+                .analyze_tracked(request, history)
+                .await
+                .map(|result| ([(SERVED_BY_HEADER, local_id)], Json(result)).into_response())?
+        } else {
+            let authorization = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok());
+            match state
+                .analyze_routed(request, authorization, history)
+                .await?
+            {
+                (result, Some(peer)) => (
+                    [
+                        (SERVED_BY_HEADER, peer.to_string()),
+                        (FORWARDED_FROM_HEADER, local_id),
+                    ],
+                    Json(result),
+                )
+                    .into_response(),
+                (result, None) => ([(SERVED_BY_HEADER, local_id)], Json(result)).into_response(),
+            }
+        };
+        return Ok(with_node_budget(
+            response,
+            state.node_budget_remaining(&token).await,
+        ));
+    }
+    if state.analysis.is_shutting_down() {
+        return Err(Error::ShuttingDown.into());
+    }
+    request.validate().map_err(ApiError::from)?;
+    let permit = state.admit().map_err(ApiError::from)?;
+    match state.submit_analysis(request, history, permit).await {
+        Ok(id) => {
+            let response = (
+                StatusCode::ACCEPTED,
+                Json(AnalysisStatusResponse {
+                    id,
+                    status: "queued".to_string(),
+                    queued_at: Some(Utc::now()),
+                    started_at: None,
+                    progress: None,
+                }),
+            )
+                .into_response();
+            Ok(with_node_budget(
+                response,
+                state.node_budget_remaining(&token).await,
+            ))
+        }
+        Err(e) => Err(ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "queue_full",
+            format!("analysis queue is full: {}", e),
         )),
     }
 }
+fn with_node_budget(
+    mut response: axum::response::Response,
+    remaining: Option<u64>,
+) -> axum::response::Response {
+    if let Some(remaining) = remaining {
+        response
+            .headers_mut()
+            .insert(NODE_BUDGET_REMAINING_HEADER, remaining.into());
+    }
+    response
+}
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnalyzeStreamParams {
+    #[serde(default)]
+    pub fen: String,
+    #[serde(default)]
+    pub startpos: bool,
+    #[serde(default = "default_depth")]
+    pub depth: u8,
+    pub multipv: Option<u8>,
+    pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub variant: Variant,
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub perspective: Perspective,
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    pub notation: Notation,
+    #[serde(default)]
+    pub include_history: bool,
+}
+#[utoipa::path(
+    get,
+    path = "/v1/analyze/stream",
+    tag = "analysis",
+    params(AnalyzeStreamParams),
+    responses(
+        (status = 200, description = "Server-sent events: `progress` with AnalysisProgress, then `complete` with AnalysisResult or `error` with ErrorResponse", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 429, description = "Rate limited, too many concurrent analyses or node budget exhausted", body = ErrorResponse),
+        (status = 503, description = "Node draining", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
+pub async fn analyze_stream(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(params): ApiQuery<AnalyzeStreamParams>,
+    OptionalTokenContext(token): OptionalTokenContext,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    state.ensure_accepting().map_err(ApiError::from)?;
+    let multipv = params
+        .multipv
+        .unwrap_or_else(|| state.limits().default_multipv(&[]));
+    state
+        .limits()
+        .check(params.depth.into(), multipv.into(), params.movetime)
+        .map_err(ApiError::from)?;
+    let request = AnalysisRequest::new(start_fen(params.fen, params.startpos))
+        .with_depth(params.depth)
+        .with_multipv(multipv)
+        .with_variant(params.variant)
+        .with_perspective(params.perspective)
+        .with_notation(params.notation)
+        .with_history(params.include_history);
+    let request = match params.movetime {
+        Some(ms) => request.with_movetime(ms),
+        None => request,
+    };
+    let request = match params.nodes {
+        Some(nodes) => request.with_nodes(nodes),
+        None => request,
+    };
+    let request = token.clamp(request);
+    request.validate().map_err(ApiError::from)?;
+    let permit = state.admit().map_err(ApiError::from)?;
+    let reservation = state
+        .reserve_node_budget(&token, &request)
+        .await
+        .map_err(ApiError::from)?;
+    let id = request.id;
+    let cancel = CancellationToken::new();
+    let (progress_tx, mut progress_rx) = mpsc::channel::<AnalysisProgress>(32);
+    let history = state
+        .history_recorder(token.id)
+        .map(|recorder| recorder.with_node_budget(reservation));
+    state.analyses.begin(id).await;
+    let task_cancel = cancel.clone();
+    let handle = tokio::spawn(async move {
+        let _permit = permit;
+        let result = state
+            .analysis
+            .analyze_streaming(request, progress_tx, task_cancel)
+            .await;
+        state.analyses.finish(id, &result).await;
+        if let Some(history) = history {
+            history.record(&result).await;
+        }
+        result
+    });
+    let stream = async_stream::stream! {
+        let _guard = cancel.drop_guard();
+        while let Some(progress) = progress_rx.recv().await {
+            yield Event::default().event("progress").json_data(progress);
+        }
+        let error = match handle.await {
+            Ok(Ok(result)) => {
+                yield Event::default().event("complete").json_data(result);
+                return;
+            }
+            Ok(Err(e)) => ApiError::from(e),
+            Err(e) => ApiError::from(Error::Internal(e.to_string())),
+        };
+        yield Event::default().event("error").json_data(ErrorResponse {
+            error: error.body().clone(),
+        });
+    };
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(STREAM_KEEP_ALIVE)))
+}
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AnalysisStatusResponse {
+    pub id: Uuid,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queued_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<AnalysisProgress>,
+}
+#[utoipa::path(
+    get,
+    path = "/v1/analyze/{id}",
+    tag = "analysis",
+    params(("id" = String, Path, description = "Analysis id")),
+    responses(
+        (status = 200, description = "Completed analysis", body = AnalysisResult),
+        (status = 202, description = "Analysis queued or running", body = AnalysisStatusResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 404, description = "Analysis not found", body = ErrorResponse),
+        (status = 410, description = "Analysis was cancelled", body = ErrorResponse),
+        (status = 422, description = "Analysis failed", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
 pub async fn get_analysis(
-    State(_state): State<Arc<ApiState>>,
+    State(state): State<Arc<ApiState>>,
     Path(id): Path<String>,
-) -> Result<Json<AnalysisResult>, (StatusCode, Json<ErrorResponse>)> {
-    Err((
-        StatusCode::NOT_FOUND,
-        Json(ErrorResponse {
-            error: format!("analysis {} not found", id),
-        }),
-    ))
+) -> Result<axum::response::Response, ApiError> {
+    let uuid = Uuid::parse_str(&id)
+        .map_err(|_| ApiError::bad_request("invalid_analysis_id", "invalid analysis id"))?;
+    match state.analyses.get(&uuid).await {
+        Some(AnalysisEntry::Complete(result)) => Ok(Json(*result).into_response()),
+        Some(AnalysisEntry::Queued { queued_at }) => Ok((
+            StatusCode::ACCEPTED,
+            Json(AnalysisStatusResponse {
+                id: uuid,
+                status: "queued".to_string(),
+                queued_at: Some(queued_at),
+                started_at: None,
+                progress: None,
+            }),
+        )
+            .into_response()),
+        Some(AnalysisEntry::Running {
+            started_at,
+            progress,
+        }) => Ok((
+            StatusCode::ACCEPTED,
+            Json(AnalysisStatusResponse {
+                id: uuid,
+                status: "running".to_string(),
+                queued_at: None,
+                started_at: Some(started_at),
+                progress: progress.map(|p| *p),
+            }),
+        )
+            .into_response()),
+        Some(AnalysisEntry::Cancelled) => Err(ApiError::new(
+            StatusCode::GONE,
+            "analysis_cancelled",
+            format!("analysis {} was cancelled", id),
+        )),
+        Some(AnalysisEntry::Failed(error)) => Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "analysis_failed",
+            error,
+        )),
+        None => Err(ApiError::not_found(
+            "analysis_not_found",
+            format!("analysis {} not found", id),
+        )),
+    }
+}
+#[utoipa::path(
+    delete,
+    path = "/v1/analyze/{id}",
+    tag = "analysis",
+    params(("id" = String, Path, description = "Analysis id")),
+    responses(
+        (status = 202, description = "Cancellation requested"),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 404, description = "Analysis not found", body = ErrorResponse),
+        (status = 409, description = "Analysis finished or not cancellable", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
+pub async fn cancel_analysis(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let uuid = Uuid::parse_str(&id)
+        .map_err(|_| ApiError::bad_request("invalid_analysis_id", "invalid analysis id"))?;
+    if state.jobs.cancel(&uuid).await {
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(serde_json::json!({"id": uuid, "status": "cancelling"})),
+        ));
+    }
+    match state.analyses.get(&uuid).await {
+        Some(entry) if entry.is_finished() => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "analysis_finished",
+            format!("analysis {} already {}", id, entry.status()),
+        )),
+        Some(_) => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "not_cancellable",
+            format!("analysis {} is not a cancellable job", id),
+        )),
+        None => Err(ApiError::not_found(
+            "analysis_not_found",
+            format!("analysis {} not found", id),
+        )),
+    }
 }
+#[utoipa::path(
+    post,
+    path = "/v1/bestmove",
+    tag = "analysis",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response for a repeated key instead of running the request again")),
+    request_body = BestMoveBody,
+    responses(
+        (status = 200, description = "Best move", body = BestMoveResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 429, description = "Rate limited or too many concurrent analyses", body = ErrorResponse),
+        (status = 503, description = "Node draining or no engine available", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
 pub async fn best_move(
     State(state): State<Arc<ApiState>>,
-    Json(body): Json<BestMoveBody>,
-) -> Result<Json<BestMoveResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ApiJson(body): ApiJson<BestMoveBody>,
+) -> Result<Json<BestMoveResponse>, ApiError> {
+    state.ensure_accepting().map_err(ApiError::from)?;
+    let _permit = state.admit().map_err(ApiError::from)?;
     let request = BestMoveRequest {
-        fen: body.fen,
+        fen: start_fen(body.fen, body.startpos),
         movetime: body.movetime,
+        nodes: body.nodes,
+        variant: body.variant,
+        moves: body.moves,
     };
-    match state.analysis.best_move(request).await {
-        Ok(result) => Ok(Json(result)),
-        Err(e) => Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
+    state
+        .analysis
+        .best_move(request)
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+#[utoipa::path(
+    post,
+    path = "/v1/analyze/game",
+    tag = "analysis",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response for a repeated key instead of running the request again")),
+    request_body = GameAnalysisBody,
+    responses(
+        (status = 200, description = "Per-move game analysis", body = GameAnalysis),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 429, description = "Rate limited, too many concurrent analyses or node budget exhausted", body = ErrorResponse),
+        (status = 503, description = "Node draining or no engine available", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
+pub async fn analyze_game(
+    State(state): State<Arc<ApiState>>,
+    OptionalTokenContext(token): OptionalTokenContext,
+    ApiJson(body): ApiJson<GameAnalysisBody>,
+) -> Result<axum::response::Response, ApiError> {
+    state.ensure_accepting().map_err(ApiError::from)?;
+    let limits = state.limits();
+    let width = state
+        .analysis
+        .pool()
+        .map_or(1, |pool| pool.size())
+        .min(limits.max_game_concurrency as usize);
+    let permits = state.admit_up_to(width).map_err(ApiError::from)?;
+    let game = match body.pgn {
+        Some(ref pgn) => Game::from_pgn(pgn),
+        None => Game::new(body.moves),
+    };
+    if game.moves.is_empty() {
+        return Err(Error::InvalidMove {
+            index: 0,
+            notation: String::new(),
+        }
+        .into());
+    }
+    limits.check_game(game.moves.len())?;
+    let game = match body.fen {
+        Some(fen) => game.with_start_fen(fen),
+        None => game,
+    };
+    let game = match body.variant {
+        Some(variant) => game.with_variant(variant),
+        None => game,
+    };
+    let request = GameAnalysisRequest::new(game)
+        .with_thresholds(body.thresholds)
+        .with_concurrency(permits.len())
+        .with_token(token.clone());
+    let request = match body.depth {
+        Some(depth) => request.with_depth(depth),
+        None => request,
+    };
+    let request = match body.movetime {
+        Some(ms) => request.with_movetime(ms),
+        None => request,
+    };
+    limits.check(request.depth.into(), 1, request.movetime)?;
+    let reservation = state
+        .reserve_game_node_budget(&token, &request)
+        .await
+        .map_err(ApiError::from)?;
+    let analysis = state.analysis.analyze_game(request).await?;
+    if let Some(reservation) = reservation {
+        reservation.settle(analysis.nodes_searched).await;
     }
+    Ok(with_node_budget(
+        Json(analysis).into_response(),
+        state.node_budget_remaining(&token).await,
+    ))
 }
-pub async fn health(State(state): State<Arc<ApiState>>) -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "healthy".to_string(),
-        node_id: state.node.id().to_string(),
-        version: state.node.info().version.clone(),
-    })
+fn health_status(state: &ApiState) -> &'static str {
+    if state.node.is_draining() {
+        "draining"
+    } else {
+        "healthy"
+    }
+}
+#[utoipa::path(
+    get,
+    path = "/v1/health",
+    tag = "node",
+    responses(
+        (status = 200, description = "Node is ready", body = HealthResponse),
+        (status = 503, description = "A readiness check failed or the node is draining", body = HealthResponse),
+    ),
+)]
+pub async fn health(State(state): State<Arc<ApiState>>) -> (StatusCode, Json<HealthResponse>) {
+    let checks = state.readiness.checks(&state).await;
+    let status = match health_status(&state) {
+        "healthy" if !checks.iter().all(|c| c.passed()) => "degraded",
+        status => status,
+    };
+    let code = match status {
+        "healthy" => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (
+        code,
+        Json(HealthResponse {
+            status: status.to_string(),
+            node_id: state.node.id().to_string(),
+            version: state.node.info().version.clone(),
+            token_store: state.token_store.health(),
+            checks,
+            engine: state.analysis.pool().and_then(|pool| pool.engine_name()),
+        }),
+    )
 }
-pub async fn health_simple() -> Json<serde_json::Value> {
-    Json(serde_json::json!({"status": "healthy"}))
+pub async fn health_simple(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    let status = match state.node.is_draining() {
+        true => StatusCode::SERVICE_UNAVAILABLE,
+        false => StatusCode::OK,
+    };
+    (
+        status,
+        Json(serde_json::json!({"status": health_status(&state)})),
+    )
 }
+#[utoipa::path(
+    get,
+    path = "/v1/metrics",
+    tag = "node",
+    responses(
+        (status = 200, description = "Latest node metrics", body = MetricsResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
 pub async fn metrics(State(state): State<Arc<ApiState>>) -> Json<MetricsResponse> {
-    let (active, available, total) = state
-        .analysis
-        .pool()
-        .map(|p| (p.active() as u32, p.available() as u32, p.size() as u32))
-        .unwrap_or((0, 0, 0));
-
-    let mut system = sysinfo::System::new_all();
-    system.refresh_all();
-    let cpu_usage = system.global_cpu_usage();
-    let memory_usage = system.used_memory() as f32 / system.total_memory() as f32;
-
+    let metrics = state.local_metrics();
     Json(MetricsResponse {
-        cpu_usage,
-        memory_usage,
-        active_analyses: active,
-        queue_depth: 0,
-        engines_available: available,
-        engines_total: total,
+        cpu_usage: metrics.cpu_usage,
+        memory_usage: metrics.memory_usage,
+        active_analyses: metrics.active_analyses,
+        queue_depth: state
+            .analysis
+            .pool()
+            .map(|p| p.queue_depth() as u32)
+            .unwrap_or(0),
+        engines_available: metrics.engines_available,
+        engines_total: metrics.engines_total,
+        engines_restarted: state.analysis.pool().map(|p| p.restarts()).unwrap_or(0),
     })
 }
-pub async fn metrics_simple() -> Json<serde_json::Value> {
-    Json(serde_json::json!({"status": "ok"}))
+pub async fn prometheus_metrics(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    let metrics = state.local_metrics();
+    let telemetry = state.metrics.telemetry();
+    telemetry.set_engines(metrics.engines_available, metrics.engines_total);
+    if let Some(pool) = state.analysis.pool() {
+        telemetry.set_engine_partitions(
+            pool.reserved_available() as u32,
+            pool.general_available() as u32,
+        );
+    }
+    telemetry.set_cluster(
+        state.membership.list_members().await.len(),
+        state.node.term(),
+    );
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
 }
+#[utoipa::path(
+    get,
+    path = "/_admin/cluster/status",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "Cluster members", body = ClusterStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
 pub async fn cluster_status(State(state): State<Arc<ApiState>>) -> Json<ClusterStatus> {
     let status = state.membership.cluster_status().await;
     Json(status)
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct JoinBody {
+    pub node_id: String,
     pub address: String,
     pub priority: Option<u32>,
+    pub version: Option<String>,
 }
+#[utoipa::path(
+    post,
+    path = "/_admin/cluster/join",
+    tag = "cluster",
+    request_body = JoinBody,
+    responses(
+        (status = 200, description = "Join result; `accepted` is false with a `reason` when the join is rejected", body = JoinResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 503, description = "No cluster leader", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
 pub async fn cluster_join(
     State(state): State<Arc<ApiState>>,
-    Json(body): Json<JoinBody>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
-    let addr = body.address.parse().map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "invalid address".to_string(),
-            }),
-        )
-    })?;
+    ctx: AuditContext,
+    headers: HeaderMap,
+    ApiJson(body): ApiJson<JoinBody>,
+) -> Result<impl IntoResponse, ApiError> {
+    let audit = ctx
+        .entry(AuditAction::ClusterJoin, &state)
+        .with_target(format!("{}@{}", body.node_id, body.address));
+    let secret = headers
+        .get(CLUSTER_SECRET_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !state.cluster_secret_matches(secret) {
+        state
+            .record_audit(audit.failed("invalid cluster secret"))
+            .await;
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_cluster_secret",
+            "invalid or missing cluster secret".to_string(),
+        ));
+    }
+    let addr = match body.address.parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            state.record_audit(audit.failed("invalid address")).await;
+            return Err(ApiError::bad_request("invalid_address", "invalid address"));
+        }
+    };
     let node_info = NodeInfo {
-        id: ironfish_core::NodeId::generate(),
+        id: ironfish_core::NodeId::from_string(body.node_id),
         address: addr,
         priority: body.priority.unwrap_or(100),
         started_at: chrono::Utc::now(),
-        version: "unknown".to_string(),
+        version: body.version.unwrap_or_else(|| "unknown".to_string()),
     };
     let request = JoinRequest { node_info };
     match state.membership.join(request).await {
-        Ok(response) => Ok(Json(response)),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
+        Ok(response) => {
+            let audit = match response.reason {
+                Some(ref reason) if !response.accepted => audit.failed(reason.clone()),
+                _ => audit,
+            };
+            state.record_audit(audit).await;
+            Ok(Json(response))
+        }
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(e.into())
+        }
     }
 }
+#[utoipa::path(
+    post,
+    path = "/_admin/cluster/leave",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "Node left the cluster"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 503, description = "No cluster leader", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
 pub async fn cluster_leave(
     State(state): State<Arc<ApiState>>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
-    match state.membership.leave(state.node.id()).await {
-        Ok(_) => Ok(Json(serde_json::json!({"success": true}))),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
+    ctx: AuditContext,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let audit = ctx.entry(AuditAction::ClusterLeave, &state);
+    let node_id = match state.forwarded_by_peer(&headers) {
+        Some(node_id) => node_id,
+        None if headers.contains_key(FORWARDED_FROM_HEADER) => {
+            state
+                .record_audit(audit.failed("invalid cluster secret"))
+                .await;
+            return Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_cluster_secret",
+                "forwarded leave is not authenticated by the cluster secret",
+            ));
+        }
+        None => state.node.id().clone(),
+    };
+    let audit = audit.with_target(node_id.to_string());
+    match state.membership.leave(&node_id).await {
+        Ok(_) => {
+            state.record_audit(audit).await;
+            Ok(Json(serde_json::json!({"success": true})))
+        }
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(e.into())
+        }
     }
 }
-pub async fn list_tokens(
+async fn set_draining(state: &ApiState, ctx: AuditContext, draining: bool) -> Json<DrainStatus> {
+    let action = match draining {
+        true => AuditAction::NodeDrain,
+        false => AuditAction::NodeResume,
+    };
+    let audit = ctx
+        .entry(action, state)
+        .with_target(state.node.id().to_string());
+    if state.set_draining(draining) {
+        info!(
+            "node {} {}",
+            state.node.id(),
+            if draining { "draining" } else { "resumed" }
+        );
+    }
+    state.record_audit(audit).await;
+    Json(DrainStatus {
+        node_id: state.node.id().clone(),
+        draining,
+        active_analyses: state.analysis.active_analyses() as u32,
+    })
+}
+#[utoipa::path(
+    post,
+    path = "/_admin/node/drain",
+    tag = "node",
+    responses(
+        (status = 200, description = "Node is draining", body = DrainStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn node_drain(
+    State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
+) -> Json<DrainStatus> {
+    set_draining(&state, ctx, true).await
+}
+#[utoipa::path(
+    post,
+    path = "/_admin/node/resume",
+    tag = "node",
+    responses(
+        (status = 200, description = "Node accepts work again", body = DrainStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn node_resume(
+    State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
+) -> Json<DrainStatus> {
+    set_draining(&state, ctx, false).await
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/diagnostics",
+    tag = "node",
+    responses(
+        (status = 200, description = "Node diagnostics", body = NodeDiagnostics),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn diagnostics(State(state): State<Arc<ApiState>>) -> Json<NodeDiagnostics> {
+    Json(state.diagnostics().await)
+}
+#[utoipa::path(
+    post,
+    path = "/_admin/cache/clear",
+    tag = "node",
+    responses(
+        (status = 200, description = "Number of cleared cache entries"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn clear_cache(
     State(state): State<Arc<ApiState>>,
-) -> Result<Json<Vec<TokenMetadata>>, (StatusCode, Json<ErrorResponse>)> {
-    match state.token_store.list().await {
-        Ok(tokens) => {
-            let metadata: Vec<TokenMetadata> = tokens.iter().map(TokenMetadata::from).collect();
-            Ok(Json(metadata))
+    ctx: AuditContext,
+) -> Json<serde_json::Value> {
+    let cleared = match state.analysis.cache() {
+        Some(cache) => cache.clear().await,
+        None => 0,
+    };
+    let audit = ctx
+        .entry(AuditAction::CacheClear, &state)
+        .with_target(state.node.id().to_string());
+    state.record_audit(audit).await;
+    Json(serde_json::json!({"cleared": cleared}))
+}
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EnginePoolBody {
+    pub size: usize,
+}
+#[utoipa::path(
+    post,
+    path = "/_admin/engine/pool",
+    tag = "node",
+    request_body = EnginePoolBody,
+    responses(
+        (status = 200, description = "Engine pool after the resize", body = EnginePoolStatus),
+        (status = 400, description = "Invalid pool size", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 409, description = "Node runs without an engine pool", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn resize_engine_pool(
+    State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
+    ApiJson(body): ApiJson<EnginePoolBody>,
+) -> Result<Json<EnginePoolStatus>, ApiError> {
+    let audit = ctx
+        .entry(AuditAction::EnginePool, &state)
+        .with_target(format!("{}:size={}", state.node.id(), body.size));
+    let Some(pool) = state.analysis.pool() else {
+        state.record_audit(audit.failed("no engine pool")).await;
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "no_engine_pool",
+            "this node runs without an engine pool".to_string(),
+        ));
+    };
+    match pool.resize(body.size).await {
+        Ok(()) => {
+            info!("engine pool resized to {}", body.size);
+            state.record_audit(audit).await;
+            Ok(Json(pool.status()))
         }
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(ApiError::from(e))
+        }
+    }
+}
+#[utoipa::path(
+    post,
+    path = "/_admin/config/reload",
+    tag = "node",
+    responses(
+        (status = 200, description = "Applied and skipped configuration keys", body = ConfigReloadReport),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 500, description = "Configuration could not be reloaded", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn reload_config(
+    State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
+) -> Result<Json<ConfigReloadReport>, ApiError> {
+    let audit = ctx.entry(AuditAction::ConfigReload, &state);
+    state
+        .reload_config(audit)
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+pub const NEXT_CURSOR_HEADER: &str = "x-ironfish-next-cursor";
+const MAX_TOKEN_PAGE: usize = 1000;
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TokenListParams {
+    pub revoked: Option<bool>,
+    pub expired: Option<bool>,
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub order_by: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/tokens",
+    tag = "tokens",
+    params(TokenListParams),
+    responses(
+        (status = 200, description = "Matching tokens", body = Vec<TokenMetadata>,
+            headers(("x-ironfish-next-cursor" = String, description = "Cursor for the next page, when more tokens match"))),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn list_tokens(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(params): ApiQuery<TokenListParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let order = match params.order_by {
+        Some(o) => o
+            .parse::<TokenOrder>()
+            .map_err(|error| ApiError::bad_request("invalid_order", error))?,
+        None => TokenOrder::default(),
+    };
+    let after = match params.after {
+        Some(a) => Some(
+            a.parse::<TokenCursor>()
+                .map_err(|error| ApiError::bad_request("invalid_cursor", error))?,
+        ),
+        None => None,
+    };
+    let query = TokenListQuery {
+        filter: TokenFilter {
+            revoked: params.revoked,
+            expired: params.expired,
+            name_contains: params.name_contains,
+            created_after: params.created_after,
+        },
+        order,
+        after,
+        limit: params.limit.map(|l| l.clamp(1, MAX_TOKEN_PAGE)),
+    };
+    state.flush_token_usage().await;
+    let page = state.token_store.list_page(&query).await?;
+    let mut headers = HeaderMap::new();
+    if let Some(value) = page.next_after.and_then(|c| c.to_string().parse().ok()) {
+        headers.insert(NEXT_CURSOR_HEADER, value);
     }
+    let metadata: Vec<TokenMetadata> = page.tokens.iter().map(TokenMetadata::from).collect();
+    Ok((headers, Json(metadata)))
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateTokenBody {
     pub name: Option<String>,
     pub expires_in_days: Option<u32>,
     pub rate_limit: Option<u32>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub max_depth: Option<u32>,
+    pub max_multipv: Option<u32>,
+    pub node_budget_per_hour: Option<u64>,
 }
+#[utoipa::path(
+    post,
+    path = "/_admin/tokens",
+    tag = "tokens",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response for a repeated key instead of running the request again")),
+    request_body = CreateTokenBody,
+    responses(
+        (status = 200, description = "Created token", body = CreateTokenResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 422, description = "Idempotency-Key reused for a different request", body = ErrorResponse),
+        (status = 503, description = "Token store read-only or no cluster leader", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
 pub async fn create_token(
     State(state): State<Arc<ApiState>>,
-    Json(body): Json<CreateTokenBody>,
-) -> Result<Json<CreateTokenResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ctx: AuditContext,
+    ApiJson(body): ApiJson<CreateTokenBody>,
+) -> Result<axum::response::Response, ApiError> {
+    let audit = ctx.entry(AuditAction::TokenCreate, &state);
     let request = CreateTokenRequest {
         name: body.name,
         expires_in_days: body.expires_in_days,
         rate_limit: body.rate_limit,
+        scopes: body.scopes,
+        max_depth: body.max_depth,
+        max_multipv: body.max_multipv,
+        node_budget_per_hour: body.node_budget_per_hour,
     };
-    match state.token_manager.create(request) {
-        Ok((token, response)) => match state.token_store.create(token.clone()).await {
-            Ok(_) => {
-                state.broadcast_token_created(token);
-                Ok(Json(response))
-            }
-            Err(e) => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: e.to_string(),
-                }),
-            )),
-        },
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )),
+    let result = match state.token_manager.create(request) {
+        Ok((token, response)) => state
+            .token_store
+            .create(token.clone())
+            .await
+            .map(|_| (token, response)),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((token, response)) => {
+            state
+                .record_audit(audit.with_target(token.id.to_string()))
+                .await;
+            state.broadcast_token_created(token);
+            let replay =
+                serde_json::json!({ "id": response.id, "expires_at": response.expires_at });
+            let mut response = Json(response).into_response();
+            response
+                .extensions_mut()
+                .insert(ReplayBody(replay.to_string().into()));
+            Ok(response)
+        }
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(e.into())
+        }
     }
 }
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RevokeTokenParams {
+    #[serde(default)]
+    pub confirm_cluster: bool,
+}
+#[utoipa::path(
+    delete,
+    path = "/_admin/tokens/{id}",
+    tag = "tokens",
+    params(("id" = String, Path, description = "Token id"), RevokeTokenParams),
+    responses(
+        (status = 200, description = "Token revoked, with a per-node replica report when confirm_cluster is set"),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 404, description = "Token not found", body = ErrorResponse),
+        (status = 503, description = "Token store read-only or no cluster leader", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
 pub async fn revoke_token(
     State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
     Path(id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
-    let uuid = Uuid::parse_str(&id).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "invalid token id".to_string(),
-            }),
-        )
-    })?;
+    ApiQuery(params): ApiQuery<RevokeTokenParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let audit = ctx
+        .entry(AuditAction::TokenRevoke, &state)
+        .with_target(id.clone());
+    let uuid = match Uuid::parse_str(&id) {
+        Ok(uuid) => uuid,
+        Err(_) => {
+            state.record_audit(audit.failed("invalid token id")).await;
+            return Err(ApiError::bad_request(
+                "invalid_token_id",
+                "invalid token id",
+            ));
+        }
+    };
     match state.token_store.revoke(&uuid).await {
         Ok(_) => {
+            state.record_audit(audit).await;
             state.broadcast_token_revoked(uuid);
-            Ok(Json(serde_json::json!({"success": true})))
+            if !params.confirm_cluster {
+                return Ok(Json(serde_json::json!({"success": true})));
+            }
+            let report = state.token_replicas(uuid).await;
+            Ok(Json(serde_json::json!({
+                "success": true,
+                "confirmed": report.confirmed,
+                "nodes": report.nodes,
+            })))
         }
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(e.into())
+        }
+    }
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/tokens/{id}/usage",
+    tag = "tokens",
+    params(("id" = String, Path, description = "Token id")),
+    responses(
+        (status = 200, description = "Token usage", body = TokenUsage),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 404, description = "Token not found", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn token_usage(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<TokenUsage>, ApiError> {
+    let uuid = Uuid::parse_str(&id)
+        .map_err(|_| ApiError::bad_request("invalid_token_id", "invalid token id"))?;
+    state.flush_token_usage().await;
+    match state.token_store.get(&uuid).await {
+        Ok(Some(token)) => Ok(Json(token.usage())),
+        Ok(None) => Err(ApiError::not_found(
+            "token_not_found",
+            format!("token {} not found", id),
         )),
+        Err(e) => Err(e.into()),
+    }
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/tokens/{id}/replicas",
+    tag = "tokens",
+    params(("id" = String, Path, description = "Token id")),
+    responses(
+        (status = 200, description = "Token status on every known node", body = TokenReplicaReport),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn token_replicas(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> Result<Json<TokenReplicaReport>, ApiError> {
+    let uuid = Uuid::parse_str(&id)
+        .map_err(|_| ApiError::bad_request("invalid_token_id", "invalid token id"))?;
+    Ok(Json(state.token_replicas(uuid).await))
+}
+#[utoipa::path(
+    delete,
+    path = "/_admin/tokens/expired",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "Number of purged tokens"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn purge_tokens(
+    State(state): State<Arc<ApiState>>,
+    ctx: AuditContext,
+) -> Result<impl IntoResponse, ApiError> {
+    let audit = ctx.entry(AuditAction::TokenPurge, &state);
+    match state.token_store.purge(&state.token_retention).await {
+        Ok(purged) => {
+            info!("purged {} expired or revoked tokens", purged);
+            state
+                .record_audit(audit.with_target(purged.to_string()))
+                .await;
+            Ok(Json(serde_json::json!({"purged": purged})))
+        }
+        Err(e) => {
+            state.record_audit(audit.failed(e.to_string())).await;
+            Err(e.into())
+        }
+    }
+}
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditParams {
+    pub since: Option<DateTime<Utc>>,
+    pub after: Option<u64>,
+    pub action: Option<String>,
+    pub limit: Option<usize>,
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/audit",
+    tag = "audit",
+    params(AuditParams),
+    responses(
+        (status = 200, description = "Audit entries", body = AuditPage),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 503, description = "Audit log is disabled", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn list_audit(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(params): ApiQuery<AuditParams>,
+) -> Result<Json<AuditPage>, ApiError> {
+    let audit = state.audit.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "audit_disabled",
+            "audit log is not enabled",
+        )
+    })?;
+    let action = match params.action {
+        Some(a) => Some(
+            a.parse::<AuditAction>()
+                .map_err(|error| ApiError::bad_request("invalid_action", error))?,
+        ),
+        None => None,
+    };
+    let query = AuditQuery {
+        since: params.since,
+        after: params.after,
+        action,
+        limit: params.limit,
+    };
+    match audit.query(&query).await {
+        Ok(page) => Ok(Json(page)),
+        Err(e) => Err(e.into()),
     }
 }
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParams {
+    pub limit: Option<usize>,
+    pub before: Option<u64>,
+    pub token_id: Option<Uuid>,
+}
+async fn query_history(state: &ApiState, query: HistoryQuery) -> Result<HistoryPage, ApiError> {
+    let history = state.history.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "history_disabled",
+            "analysis history is not enabled",
+        )
+    })?;
+    history.query(&query).await.map_err(ApiError::from)
+}
+#[utoipa::path(
+    get,
+    path = "/v1/analyses",
+    tag = "analysis",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "Analysis history of the caller", body = HistoryPage),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 503, description = "Analysis history is disabled", body = ErrorResponse),
+    ),
+    security(("bearer_token" = [])),
+)]
+pub async fn list_analyses(
+    State(state): State<Arc<ApiState>>,
+    OptionalTokenContext(token): OptionalTokenContext,
+    ApiQuery(params): ApiQuery<HistoryParams>,
+) -> Result<Json<HistoryPage>, ApiError> {
+    let token_id = match token.id {
+        Some(id) if params.token_id.is_some_and(|requested| requested != id) => {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "tokens may only list their own analyses",
+            ));
+        }
+        Some(id) => Some(id),
+        None => params.token_id,
+    };
+    let query = HistoryQuery {
+        before: params.before,
+        token_id,
+        limit: params.limit,
+    };
+    query_history(&state, query).await.map(Json)
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/analyses",
+    tag = "audit",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "Analysis history of every token", body = HistoryPage),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 503, description = "Analysis history is disabled", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn list_all_analyses(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(params): ApiQuery<HistoryParams>,
+) -> Result<Json<HistoryPage>, ApiError> {
+    let query = HistoryQuery {
+        before: params.before,
+        token_id: params.token_id,
+        limit: params.limit,
+    };
+    query_history(&state, query).await.map(Json)
+}
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryParams {
+    pub limit: Option<usize>,
+}
+#[utoipa::path(
+    get,
+    path = "/_admin/webhooks/deliveries",
+    tag = "webhooks",
+    params(DeliveryParams),
+    responses(
+        (status = 200, description = "Recent webhook delivery attempts, newest first", body = WebhookDeliveries),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 503, description = "No webhooks are configured", body = ErrorResponse),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn list_webhook_deliveries(
+    State(state): State<Arc<ApiState>>,
+    ApiQuery(params): ApiQuery<DeliveryParams>,
+) -> Result<Json<WebhookDeliveries>, ApiError> {
+    let webhooks = state.webhooks.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "webhooks_disabled",
+            "no webhooks are configured",
+        )
+    })?;
+    Ok(Json(WebhookDeliveries {
+        deliveries: webhooks.deliveries(params.limit.unwrap_or(100)),
+    }))
+}
@@ -1,40 +1,106 @@
+mod error;
 mod handlers;
+mod openapi;
+use crate::forward::forward_admin_writes;
+use crate::idempotency::idempotent_requests;
 use crate::ws;
 use crate::ApiState;
+use axum::middleware::from_fn_with_state;
 use axum::routing::{delete, get, post};
 use axum::Router;
+pub use error::{ApiError, ApiJson, ApiQuery, ErrorBody, ErrorResponse};
 pub use handlers::*;
+pub use openapi::ApiDoc;
 use std::sync::Arc;
 pub struct RestRouter {
     state: Arc<ApiState>,
+    docs_enabled: bool,
 }
 impl RestRouter {
     pub fn new(state: Arc<ApiState>) -> Self {
-        Self { state }
+        Self {
+            state,
+            docs_enabled: false,
+        }
+    }
+    pub fn with_docs(mut self, enabled: bool) -> Self {
+        self.docs_enabled = enabled;
+        self
     }
     pub fn build(self) -> Router {
+        let idempotent = from_fn_with_state(self.state.clone(), idempotent_requests);
         let api_routes = Router::new()
-            .route("/analyze", post(handlers::analyze))
-            .route("/analyze/{id}", get(handlers::get_analysis))
-            .route("/bestmove", post(handlers::best_move))
+            .route(
+                "/analyze",
+                post(handlers::analyze).route_layer(idempotent.clone()),
+            )
+            .route("/analyses", get(handlers::list_analyses))
+            .route(
+                "/analyze/game",
+                post(handlers::analyze_game).route_layer(idempotent.clone()),
+            )
+            .route("/analyze/stream", get(handlers::analyze_stream))
+            .route(
+                "/analyze/{id}",
+                get(handlers::get_analysis).delete(handlers::cancel_analysis),
+            )
+            .route(
+                "/bestmove",
+                post(handlers::best_move).route_layer(idempotent.clone()),
+            )
             .route("/health", get(handlers::health))
             .route("/metrics", get(handlers::metrics))
             .route("/ws", get(ws::ws_handler))
             .with_state(self.state.clone());
+        let leader = from_fn_with_state(self.state.clone(), forward_admin_writes);
         let admin_routes = Router::new()
             .route("/cluster/status", get(handlers::cluster_status))
-            .route("/cluster/join", post(handlers::cluster_join))
-            .route("/cluster/leave", post(handlers::cluster_leave))
+            .route(
+                "/cluster/join",
+                post(handlers::cluster_join).route_layer(leader.clone()),
+            )
+            .route(
+                "/cluster/leave",
+                post(handlers::cluster_leave).route_layer(leader.clone()),
+            )
+            .route("/node/drain", post(handlers::node_drain))
+            .route("/node/resume", post(handlers::node_resume))
+            .route("/diagnostics", get(handlers::diagnostics))
             .route(
                 "/tokens",
-                get(handlers::list_tokens).post(handlers::create_token),
+                post(handlers::create_token)
+                    .route_layer(leader.clone())
+                    .route_layer(idempotent)
+                    .get(handlers::list_tokens),
+            )
+            .route("/tokens/expired", delete(handlers::purge_tokens))
+            .route(
+                "/tokens/{id}",
+                delete(handlers::revoke_token).route_layer(leader),
+            )
+            .route("/tokens/{id}/usage", get(handlers::token_usage))
+            .route("/tokens/{id}/replicas", get(handlers::token_replicas))
+            .route("/audit", get(handlers::list_audit))
+            .route("/analyses", get(handlers::list_all_analyses))
+            .route("/cache/clear", post(handlers::clear_cache))
+            .route("/engine/pool", post(handlers::resize_engine_pool))
+            .route("/config/reload", post(handlers::reload_config))
+            .route(
+                "/webhooks/deliveries",
+                get(handlers::list_webhook_deliveries),
             )
-            .route("/tokens/{id}", delete(handlers::revoke_token))
             .with_state(self.state.clone());
-        Router::new()
+        let router = Router::new()
             .nest("/v1", api_routes)
             .nest("/_admin", admin_routes)
             .route("/health", get(handlers::health_simple))
-            .route("/metrics", get(handlers::metrics_simple))
+            .route("/ready", get(handlers::health))
+            .route("/metrics", get(handlers::prometheus_metrics))
+            .route("/openapi.json", get(openapi::openapi_json));
+        let router = match self.docs_enabled {
+            true => router.merge(openapi::swagger_ui()),
+            false => router,
+        };
+        router.with_state(self.state)
     }
 }
@@ -0,0 +1,110 @@
+use super::handlers;
+use super::{ErrorBody, ErrorResponse};
+use axum::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::{Config, SwaggerUi};
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Ironfish API"),
+    paths(
+        handlers::analyze,
+        handlers::analyze_stream,
+        handlers::get_analysis,
+        handlers::cancel_analysis,
+        handlers::analyze_game,
+        handlers::best_move,
+        handlers::list_analyses,
+        handlers::health,
+        handlers::metrics,
+        handlers::cluster_status,
+        handlers::cluster_join,
+        handlers::cluster_leave,
+        handlers::node_drain,
+        handlers::node_resume,
+        handlers::diagnostics,
+        handlers::clear_cache,
+        handlers::resize_engine_pool,
+        handlers::reload_config,
+        handlers::list_tokens,
+        handlers::create_token,
+        handlers::purge_tokens,
+        handlers::revoke_token,
+        handlers::token_usage,
+        handlers::token_replicas,
+        handlers::list_audit,
+        handlers::list_all_analyses,
+        handlers::list_webhook_deliveries,
+    ),
+    components(schemas(ErrorResponse, ErrorBody)),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+struct SecuritySchemes;
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "admin_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Key"))),
+        );
+    }
+}
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+pub fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new("/docs").config(Config::new(["/openapi.json"]))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_spec_covers_auth_and_errors() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemes = &spec["components"]["securitySchemes"];
+        assert_eq!(schemes["bearer_token"]["scheme"], "bearer");
+        assert_eq!(schemes["admin_key"]["name"], "X-Admin-Key");
+        let analyze = &spec["paths"]["/v1/analyze"]["post"];
+        assert_eq!(
+            analyze["security"][0]["bearer_token"],
+            serde_json::json!([])
+        );
+        for status in ["200", "202", "400", "429", "503"] {
+            assert!(analyze["responses"][status].is_object(), "{}", status);
+        }
+        let tokens = &spec["paths"]["/_admin/tokens"]["post"];
+        assert_eq!(tokens["security"][0]["admin_key"], serde_json::json!([]));
+        assert!(spec["paths"]["/v1/health"]["get"]["security"].is_null());
+        assert!(spec["components"]["schemas"]["AnalysisResult"].is_object());
+        assert!(spec["components"]["schemas"]["ErrorResponse"].is_object());
+    }
+    #[tokio::test]
+    async fn test_swagger_ui_serves_bundled_assets() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+        let router: axum::Router = swagger_ui().into();
+        for (path, expected) in [
+            ("/docs/", "swagger-ui-bundle.js"),
+            ("/docs/swagger-initializer.js", "/openapi.json"),
+            ("/docs/swagger-ui-bundle.js", "SwaggerUIBundle"),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains(expected), "{}", path);
+            assert!(!body.contains("unpkg.com"), "{}", path);
+        }
+    }
+}
@@ -1,28 +1,78 @@
+use crate::admission::{AdmissionPermit, AnalysisAdmission};
+use crate::forward::{
+    trust_forwarding_peers, AnalysisForwarder, LeaderForwarder, FORWARDED_FROM_HEADER,
+    FORWARDED_SECRET_HEADER,
+};
 use crate::graphql::GraphQLService;
 use crate::grpc::GrpcService;
+use crate::history::HistoryRecorder;
+use crate::idempotency::IdempotencyStore;
+use crate::jobs::AnalysisJobs;
+use crate::readiness::Readiness;
+use crate::reload::ConfigReloader;
+use crate::request_id::propagate_request_id;
 use crate::rest::RestRouter;
+use crate::store::AnalysisStore;
+use crate::telemetry::{self, MetricsRegistry};
+use crate::webhooks::{WebhookEvent, Webhooks};
 use crate::ws;
+use axum::http::HeaderMap;
+use axum::middleware::{from_fn, from_fn_with_state};
 use axum::Router;
-use ironfish_auth::{AuthLayer, SledTokenStore, TokenManager};
-use ironfish_cluster::{MembershipManager, Node};
-use ironfish_core::{ApiToken, GossipMessage};
-use ironfish_stockfish::AnalysisService;
+use ironfish_auth::{
+    secrets_match, AuthLayer, NodeBudgetReservation, RateLimiter, TokenManager, TrustedProxies,
+    UsageTracker, DEFAULT_NODE_RESERVATION,
+};
+use ironfish_cluster::{MembershipManager, NetworkService, Node};
+use ironfish_core::{
+    AnalysisHistory, AnalysisLimits, AnalysisProgress, AnalysisRequest, AnalysisResult, ApiToken,
+    AuditEntry, AuditLog, ClusterEvent, ConfigReloadReport, Error, GameAnalysisRequest,
+    GossipMessage, NodeDiagnostics, NodeId, NodeMetrics, Result, TokenContext, TokenReplicaReport,
+    TokenReplicaStatus, TokenRetention, TokenStore,
+};
+use ironfish_stockfish::{AnalysisService, RequestClass};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 pub type GossipBroadcaster = broadcast::Sender<GossipMessage>;
+pub const CLUSTER_SECRET_HEADER: &str = "x-cluster-secret";
+const REPLICA_RETRY_DELAY: Duration = Duration::from_millis(200);
+const SERVICE_TOKEN_TTL: chrono::Duration = chrono::Duration::seconds(10);
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct WebSocketConfig {
     pub enabled: bool,
     pub max_connections: usize,
+    pub max_sessions_per_token: usize,
     pub auth_timeout_secs: u64,
     pub ping_interval_secs: u64,
     pub max_message_size_bytes: usize,
     pub max_analyses_per_session: usize,
+    pub metrics_interval_secs: u64,
+    pub outbound_queue_size: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    pub slow_consumer_threshold: u64,
+    pub slow_consumer_timeout_ms: u64,
+    pub max_infinite_analysis_secs: u64,
+    pub resume_grace_secs: u64,
+    pub token_revalidate_secs: u64,
+    pub token_revalidate_max_failures: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    #[default]
+    DropProgress,
+    Disconnect,
 }
 
 impl Default for WebSocketConfig {
@@ -30,10 +80,20 @@ impl Default for WebSocketConfig {
         Self {
             enabled: true,
             max_connections: 256,
+            max_sessions_per_token: 16,
             auth_timeout_secs: 5,
             ping_interval_secs: 30,
             max_message_size_bytes: 65536,
             max_analyses_per_session: 4,
+            metrics_interval_secs: 5,
+            outbound_queue_size: 64,
+            slow_consumer_policy: SlowConsumerPolicy::DropProgress,
+            slow_consumer_threshold: 256,
+            slow_consumer_timeout_ms: 5000,
+            max_infinite_analysis_secs: 600,
+            resume_grace_secs: 30,
+            token_revalidate_secs: 30,
+            token_revalidate_max_failures: 3,
         }
     }
 }
@@ -41,18 +101,38 @@ impl Default for WebSocketConfig {
 #[derive(Clone)]
 pub struct ApiState {
     pub analysis: Arc<AnalysisService>,
-    pub token_store: Arc<SledTokenStore>,
+    pub token_store: Arc<dyn TokenStore>,
     pub token_manager: Arc<TokenManager>,
     pub node: Arc<Node>,
     pub membership: Arc<MembershipManager>,
     pub gossip_tx: Option<GossipBroadcaster>,
+    pub network: Option<Arc<NetworkService>>,
     pub ws_sessions: Arc<ws::SessionManager>,
     pub ws_config: Arc<WebSocketConfig>,
+    pub audit: Option<Arc<dyn AuditLog>>,
+    pub analyses: Arc<AnalysisStore>,
+    pub history: Option<Arc<dyn AnalysisHistory>>,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub jobs: Arc<AnalysisJobs>,
+    pub forwarder: Option<Arc<AnalysisForwarder>>,
+    pub leader_forwarder: Option<Arc<LeaderForwarder>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub metrics: Arc<MetricsRegistry>,
+    pub cluster_secret: Option<String>,
+    pub trusted_proxies: TrustedProxies,
+    pub token_retention: TokenRetention,
+    pub token_usage: Arc<UsageTracker>,
+    limits: Arc<std::sync::RwLock<AnalysisLimits>>,
+    pub admission: Arc<AnalysisAdmission>,
+    pub readiness: Arc<Readiness>,
+    pub config_reloader: Option<Arc<dyn ConfigReloader>>,
+    pub webhooks: Option<Arc<Webhooks>>,
+    pub revocation_confirm_timeout: Duration,
 }
 impl ApiState {
     pub fn new(
         analysis: Arc<AnalysisService>,
-        token_store: Arc<SledTokenStore>,
+        token_store: Arc<dyn TokenStore>,
         token_manager: Arc<TokenManager>,
         node: Arc<Node>,
         membership: Arc<MembershipManager>,
@@ -66,35 +146,485 @@ impl ApiState {
             node,
             membership,
             gossip_tx: None,
+            network: None,
             ws_sessions,
             ws_config: Arc::new(ws_config),
+            audit: None,
+            analyses: Arc::new(AnalysisStore::default()),
+            history: None,
+            idempotency: Arc::new(IdempotencyStore::default()),
+            jobs: Arc::new(AnalysisJobs::default()),
+            forwarder: None,
+            leader_forwarder: None,
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            metrics: Arc::new(MetricsRegistry::new()),
+            cluster_secret: None,
+            trusted_proxies: TrustedProxies::default(),
+            token_retention: TokenRetention::default(),
+            token_usage: Arc::new(UsageTracker::new()),
+            limits: Arc::default(),
+            admission: Arc::new(AnalysisAdmission::default()),
+            readiness: Arc::new(Readiness::default()),
+            config_reloader: None,
+            webhooks: None,
+            revocation_confirm_timeout: Duration::from_secs(5),
         }
     }
     pub fn with_gossip(mut self, tx: GossipBroadcaster) -> Self {
         self.gossip_tx = Some(tx);
         self
     }
+    pub fn with_network(mut self, network: Arc<NetworkService>) -> Self {
+        self.network = Some(network);
+        self
+    }
+    pub fn with_audit(mut self, audit: Arc<dyn AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+    pub fn with_analysis_store(mut self, store: AnalysisStore) -> Self {
+        self.analyses = Arc::new(store);
+        self
+    }
+    pub fn with_history(mut self, history: Arc<dyn AnalysisHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+    pub fn with_idempotency(mut self, store: IdempotencyStore) -> Self {
+        self.idempotency = Arc::new(store);
+        self
+    }
+    pub fn with_analysis_jobs(mut self, jobs: AnalysisJobs) -> Self {
+        self.jobs = Arc::new(jobs);
+        self
+    }
+    pub fn with_forwarder(mut self, forwarder: AnalysisForwarder) -> Self {
+        self.forwarder = Some(Arc::new(forwarder));
+        self
+    }
+    pub fn with_leader_forwarder(mut self, forwarder: LeaderForwarder) -> Self {
+        self.leader_forwarder = Some(Arc::new(forwarder));
+        self
+    }
+    pub fn with_rate_limit(mut self, per_minute: u32) -> Self {
+        self.rate_limiter = Arc::new(RateLimiter::new(per_minute));
+        self
+    }
+    pub fn with_metrics(mut self, metrics: Arc<MetricsRegistry>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+    pub fn with_cluster_secret(mut self, secret: impl Into<String>) -> Self {
+        self.cluster_secret = Some(secret.into()).filter(|s| !s.is_empty());
+        self
+    }
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = TrustedProxies::new(proxies);
+        self
+    }
+    pub fn with_token_retention(mut self, retention: TokenRetention) -> Self {
+        self.token_retention = retention;
+        self
+    }
+    pub fn with_usage_tracker(mut self, tracker: Arc<UsageTracker>) -> Self {
+        self.token_usage = tracker;
+        self
+    }
+    pub fn with_limits(mut self, limits: AnalysisLimits) -> Self {
+        self.limits = Arc::new(std::sync::RwLock::new(limits));
+        self.admission = Arc::new(AnalysisAdmission::new(
+            limits.max_concurrent_analyses as usize,
+        ));
+        self
+    }
+    pub fn with_readiness(mut self, readiness: Readiness) -> Self {
+        self.readiness = Arc::new(readiness);
+        self
+    }
+    pub fn limits(&self) -> AnalysisLimits {
+        *self.limits.read().unwrap()
+    }
+    pub fn set_limits(&self, limits: AnalysisLimits) {
+        *self.limits.write().unwrap() = limits;
+        self.admission
+            .set_limit(limits.max_concurrent_analyses as usize);
+    }
+    pub fn with_config_reloader(mut self, reloader: Arc<dyn ConfigReloader>) -> Self {
+        self.config_reloader = Some(reloader);
+        self
+    }
+    pub fn with_webhooks(mut self, webhooks: Arc<Webhooks>) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+    pub fn with_revocation_confirm_timeout(mut self, timeout: Duration) -> Self {
+        self.revocation_confirm_timeout = timeout;
+        self
+    }
+    pub async fn reload_config(&self, audit: AuditEntry) -> Result<ConfigReloadReport> {
+        let result = match self.config_reloader {
+            Some(ref reloader) => reloader.reload(self).await,
+            None => Err(Error::Config(
+                "config reload is not enabled on this node".to_string(),
+            )),
+        };
+        let audit = audit.with_target(self.node.id().to_string());
+        match result {
+            Ok(ref report) => {
+                for change in &report.applied {
+                    tracing::info!(
+                        "config {} reloaded: {} -> {}",
+                        change.key,
+                        change.from,
+                        change.to
+                    );
+                }
+                self.record_audit(audit).await;
+            }
+            Err(ref e) => {
+                tracing::warn!("config reload failed: {}", e);
+                self.record_audit(audit.failed(e.to_string())).await;
+            }
+        }
+        result
+    }
+    pub async fn flush_token_usage(&self) {
+        if !self.token_store.health().is_writable() {
+            return;
+        }
+        if let Err(e) = self.token_usage.flush(self.token_store.as_ref()).await {
+            tracing::warn!("token usage flush failed: {}", e);
+        }
+    }
+    pub async fn reserve_node_budget(
+        &self,
+        token: &TokenContext,
+        request: &AnalysisRequest,
+    ) -> Result<Option<NodeBudgetReservation>> {
+        self.reserve_nodes(token, request.nodes).await
+    }
+    pub async fn reserve_game_node_budget(
+        &self,
+        token: &TokenContext,
+        request: &GameAnalysisRequest,
+    ) -> Result<Option<NodeBudgetReservation>> {
+        let nodes = DEFAULT_NODE_RESERVATION.saturating_mul(request.positions() as u64);
+        self.reserve_nodes(token, Some(nodes)).await
+    }
+    async fn reserve_nodes(
+        &self,
+        token: &TokenContext,
+        nodes: Option<u64>,
+    ) -> Result<Option<NodeBudgetReservation>> {
+        let (Some(id), Some(budget)) = (token.id, token.node_budget_per_hour) else {
+            return Ok(None);
+        };
+        NodeBudgetReservation::reserve(self.token_store.clone(), id, budget, nodes)
+            .await
+            .map(Some)
+    }
+    pub async fn node_budget_remaining(&self, token: &TokenContext) -> Option<u64> {
+        let budget = token.node_budget_per_hour?;
+        let status = match self.token_store.get(&token.id?).await {
+            Ok(Some(stored)) => stored.usage().node_budget,
+            _ => None,
+        };
+        Some(status.map_or(budget, |status| budget.saturating_sub(status.used)))
+    }
+    pub fn cluster_secret_matches(&self, presented: Option<&str>) -> bool {
+        match (self.cluster_secret.as_ref(), presented) {
+            (Some(expected), Some(presented)) => secrets_match(expected, presented),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+    pub fn forwarded_by_peer(&self, headers: &HeaderMap) -> Option<NodeId> {
+        self.cluster_secret.as_ref()?;
+        let secret = headers
+            .get(FORWARDED_SECRET_HEADER)
+            .and_then(|v| v.to_str().ok());
+        if !self.cluster_secret_matches(secret) {
+            return None;
+        }
+        headers
+            .get(FORWARDED_FROM_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(NodeId::from_string)
+    }
+    pub fn ensure_accepting(&self) -> Result<()> {
+        if self.node.is_draining() {
+            return Err(Error::NodeDraining);
+        }
+        Ok(())
+    }
+    pub fn admit(&self) -> Result<AdmissionPermit> {
+        self.admission.try_admit()
+    }
+    pub fn admit_up_to(&self, max: usize) -> Result<Vec<AdmissionPermit>> {
+        self.admission.try_admit_up_to(max)
+    }
+    pub fn set_draining(&self, draining: bool) -> bool {
+        let changed = self.node.set_draining(draining);
+        if changed {
+            if let Some(ref tx) = self.gossip_tx {
+                let _ = tx.send(GossipMessage::NodeDraining(
+                    self.node.id().clone(),
+                    draining,
+                ));
+            }
+        }
+        changed
+    }
+    pub fn local_metrics(&self) -> NodeMetrics {
+        let mut metrics = self.node.metrics();
+        let in_flight = self.admission.in_flight();
+        metrics.active_analyses = in_flight.max(self.analysis.active_analyses()) as u32;
+        metrics.queue_depth = in_flight as u32;
+        metrics.draining = self.node.is_draining();
+        if let Some(pool) = self.analysis.pool() {
+            metrics.engines_available = pool.general_available() as u32;
+            metrics.engines_total = pool.size() as u32;
+        }
+        metrics
+    }
+    fn routing_metrics(&self, request: &AnalysisRequest) -> NodeMetrics {
+        let mut metrics = self.local_metrics();
+        if let Some(pool) = self.analysis.pool() {
+            if pool.classify(request.movetime) == RequestClass::Fast {
+                metrics.engines_available = pool.available() as u32;
+            }
+        }
+        metrics
+    }
+    pub async fn diagnostics(&self) -> NodeDiagnostics {
+        let node = self.node.snapshot();
+        let peers = match self.network {
+            Some(ref network) => network.peer_snapshots().await,
+            None => Vec::new(),
+        };
+        let gossip = match self.network {
+            Some(ref network) => network.gossip_status().await,
+            None => None,
+        };
+        NodeDiagnostics {
+            node_id: node.info.id,
+            state: node.state,
+            term: node.term,
+            leader_id: node.leader_id,
+            draining: node.draining,
+            uptime_seconds: node.uptime_seconds,
+            memory_usage: node.metrics.memory_usage,
+            engine_pool: self
+                .analysis
+                .pool()
+                .map(|pool| pool.status())
+                .unwrap_or_default(),
+            analyses: self.analyses.running().await,
+            ws_sessions: self.ws_sessions.summaries().await,
+            peers,
+            gossip,
+        }
+    }
+    pub fn history_recorder(&self, token_id: Option<uuid::Uuid>) -> Option<HistoryRecorder> {
+        if self.history.is_none() && token_id.is_none() {
+            return None;
+        }
+        Some(HistoryRecorder::new(self.history.clone(), token_id))
+    }
+    pub async fn submit_analysis(
+        &self,
+        request: AnalysisRequest,
+        history: Option<HistoryRecorder>,
+        permit: AdmissionPermit,
+    ) -> Result<uuid::Uuid> {
+        self.jobs
+            .submit(
+                request,
+                self.analysis.clone(),
+                self.analyses.clone(),
+                history,
+                self.webhooks.clone(),
+                permit,
+            )
+            .await
+    }
+    pub async fn analyze_tracked(
+        &self,
+        request: AnalysisRequest,
+        history: Option<HistoryRecorder>,
+    ) -> Result<AnalysisResult> {
+        let id = request.id;
+        self.analyses.begin(id).await;
+        let result = self.analysis.analyze(request).await;
+        self.analyses.finish(id, &result).await;
+        if let Some(recorder) = history {
+            recorder.record(&result).await;
+        }
+        result
+    }
+    pub async fn analyze_routed(
+        &self,
+        request: AnalysisRequest,
+        authorization: Option<&str>,
+        history: Option<HistoryRecorder>,
+    ) -> Result<(AnalysisResult, Option<NodeId>)> {
+        if let Some(ref forwarder) = self.forwarder {
+            if forwarder.should_forward(&self.routing_metrics(&request)) {
+                request.validate()?;
+                match forwarder
+                    .forward(self.node.id(), &request, authorization)
+                    .await
+                {
+                    Ok((peer, result)) => {
+                        self.analyses.finish(result.id, &Ok(result.clone())).await;
+                        return Ok((result, Some(peer)));
+                    }
+                    Err(e) => tracing::debug!("forwarding failed, analyzing locally: {}", e),
+                }
+            }
+        }
+        let result = self.analyze_tracked(request, history).await?;
+        Ok((result, None))
+    }
+    pub async fn analyze_streaming_routed(
+        &self,
+        request: AnalysisRequest,
+        token_id: Option<uuid::Uuid>,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<AnalysisResult> {
+        if let Some(ref forwarder) = self.forwarder {
+            if forwarder.should_forward(&self.routing_metrics(&request)) {
+                let token = self.token_manager.issue_service_token(
+                    SERVICE_TOKEN_TTL,
+                    token_id,
+                    request.id,
+                )?;
+                match forwarder
+                    .forward_streaming(
+                        self.node.id(),
+                        &request,
+                        &token,
+                        progress_tx.clone(),
+                        cancel.clone(),
+                    )
+                    .await
+                {
+                    Ok((_, result)) => return Ok(result),
+                    Err(Error::AnalysisCancelled) => return Err(Error::AnalysisCancelled),
+                    Err(e) => tracing::debug!("streaming relay failed, analyzing locally: {}", e),
+                }
+            }
+        }
+        self.analysis
+            .analyze_streaming(request, progress_tx, cancel)
+            .await
+    }
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.analysis.begin_shutdown();
+        let idle = self.analysis.wait_idle(timeout).await;
+        let closed = self
+            .ws_sessions
+            .close_all(deadline.saturating_duration_since(Instant::now()))
+            .await;
+        idle && closed
+    }
+    pub async fn record_audit(&self, entry: AuditEntry) {
+        if let Some(ref audit) = self.audit {
+            if let Err(e) = audit.record(entry).await {
+                tracing::warn!("failed to record audit entry: {}", e);
+            }
+        }
+    }
+    pub async fn token_replicas(&self, id: uuid::Uuid) -> TokenReplicaReport {
+        let deadline = tokio::time::Instant::now() + self.revocation_confirm_timeout;
+        let local = match self.token_store.get(&id).await {
+            Ok(token) => TokenReplicaStatus::of(token.as_ref()),
+            Err(e) => {
+                tracing::warn!("failed to read token {} locally: {}", id, e);
+                TokenReplicaStatus::Unknown
+            }
+        };
+        let members = self.membership.list_members().await;
+        let mut nodes: BTreeMap<String, TokenReplicaStatus> = members
+            .iter()
+            .map(|member| (member.id.to_string(), TokenReplicaStatus::Unreachable))
+            .collect();
+        nodes.insert(self.node.id().to_string(), local);
+        if let Some(ref network) = self.network {
+            let peers = network.peer_snapshots().await;
+            let statuses = futures::future::join_all(peers.iter().map(|peer| async move {
+                if !peer.healthy {
+                    return TokenReplicaStatus::Unreachable;
+                }
+                peer_token_status(network, &peer.node_id, id, deadline).await
+            }))
+            .await;
+            nodes.extend(
+                peers
+                    .iter()
+                    .map(|peer| peer.node_id.to_string())
+                    .zip(statuses),
+            );
+        }
+        TokenReplicaReport::new(id, nodes)
+    }
     pub fn broadcast_token_created(&self, token: ApiToken) {
         if let Some(ref tx) = self.gossip_tx {
             let _ = tx.send(GossipMessage::TokenCreated(token));
         }
     }
     pub fn broadcast_token_revoked(&self, token_id: uuid::Uuid) {
+        self.node
+            .events()
+            .emit(ClusterEvent::TokenRevoked { token_id });
+        if let Some(ref webhooks) = self.webhooks {
+            webhooks.notify(
+                WebhookEvent::TokenRevoked,
+                serde_json::json!({"token_id": token_id}),
+            );
+        }
         if let Some(ref tx) = self.gossip_tx {
             let _ = tx.send(GossipMessage::TokenRevoked(token_id));
         }
     }
 }
+async fn peer_token_status(
+    network: &NetworkService,
+    peer: &NodeId,
+    id: uuid::Uuid,
+    deadline: tokio::time::Instant,
+) -> TokenReplicaStatus {
+    let mut status = TokenReplicaStatus::Unreachable;
+    loop {
+        match tokio::time::timeout_at(deadline, network.token_status(peer, id)).await {
+            Ok(Ok(reported)) => status = reported,
+            Ok(Err(e)) => tracing::debug!("token status from {} failed: {}", peer, e),
+            Err(_) => return status,
+        }
+        if matches!(
+            status,
+            TokenReplicaStatus::Revoked | TokenReplicaStatus::Unknown
+        ) || tokio::time::Instant::now() + REPLICA_RETRY_DELAY >= deadline
+        {
+            return status;
+        }
+        tokio::time::sleep(REPLICA_RETRY_DELAY).await;
+    }
+}
 #[derive(Clone)]
 pub struct ApiRouter {
     state: Arc<ApiState>,
     auth_enabled: bool,
+    docs_enabled: bool,
 }
 impl ApiRouter {
     pub fn new(state: Arc<ApiState>) -> Self {
         Self {
             state,
             auth_enabled: true,
+            docs_enabled: false,
         }
     }
     #[allow(dead_code)]
@@ -102,8 +632,14 @@ impl ApiRouter {
         self.auth_enabled = enabled;
         self
     }
+    pub fn with_docs(mut self, enabled: bool) -> Self {
+        self.docs_enabled = enabled;
+        self
+    }
     pub fn build_rest_router(self) -> Router {
-        let rest_router = RestRouter::new(self.state.clone()).build();
+        let rest_router = RestRouter::new(self.state.clone())
+            .with_docs(self.docs_enabled)
+            .build();
         let graphql_service = GraphQLService::new(self.state.clone());
         let graphql_router = graphql_service.router();
         let app = rest_router.merge(graphql_router);
@@ -112,26 +648,45 @@ impl ApiRouter {
             .allow_methods(Any)
             .allow_headers(Any);
         if self.auth_enabled {
-            let auth_layer = AuthLayer::new(
-                self.state.token_store.clone(),
-                self.state.token_manager.clone(),
-            );
             app.layer(
                 ServiceBuilder::new()
-                    .layer(TraceLayer::new_for_http())
                     .layer(CompressionLayer::new())
+                    .layer(from_fn(propagate_request_id))
+                    .layer(TraceLayer::new_for_http())
                     .layer(cors)
-                    .layer(auth_layer),
+                    .layer(from_fn_with_state(
+                        self.state.clone(),
+                        trust_forwarding_peers,
+                    ))
+                    .layer(self.auth_layer()),
             )
         } else {
             app.layer(
                 ServiceBuilder::new()
-                    .layer(TraceLayer::new_for_http())
                     .layer(CompressionLayer::new())
-                    .layer(cors),
+                    .layer(from_fn(propagate_request_id))
+                    .layer(TraceLayer::new_for_http())
+                    .layer(cors)
+                    .layer(from_fn_with_state(
+                        self.state.clone(),
+                        trust_forwarding_peers,
+                    )),
             )
         }
     }
+    fn auth_layer(&self) -> AuthLayer<dyn TokenStore> {
+        let mut auth_layer = AuthLayer::new(
+            self.state.token_store.clone(),
+            self.state.token_manager.clone(),
+        )
+        .with_rate_limiter(self.state.rate_limiter.clone())
+        .with_usage_tracker(self.state.token_usage.clone())
+        .with_telemetry(self.state.metrics.telemetry());
+        if let Some(ref audit) = self.state.audit {
+            auth_layer = auth_layer.with_audit(audit.clone(), self.state.node.id().to_string());
+        }
+        auth_layer
+    }
     pub fn build_grpc_routes(&self) -> tonic::service::Routes {
         let grpc_service = GrpcService::new(self.state.clone());
         let reflection_service = tonic_reflection::server::Builder::configure()
@@ -152,6 +707,19 @@ impl ApiRouter {
     > + Clone {
         let rest = self.clone().build_rest_router();
         let grpc = self.build_grpc_routes().into_axum_router();
-        rest.fallback_service(grpc).into_service()
+        let grpc = if self.auth_enabled {
+            grpc.layer(self.auth_layer())
+        } else {
+            grpc
+        };
+        let grpc = grpc.layer(from_fn_with_state(
+            self.state.clone(),
+            trust_forwarding_peers,
+        ));
+        let grpc = grpc.layer(from_fn(propagate_request_id));
+        let state = self.state.clone();
+        rest.fallback_service(grpc)
+            .layer(from_fn_with_state(state, telemetry::track_requests))
+            .into_service()
     }
 }
@@ -0,0 +1,270 @@
+use chrono::{DateTime, Utc};
+use ironfish_core::{ActiveAnalysisStatus, AnalysisProgress, AnalysisResult, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisStoreConfig {
+    pub capacity: usize,
+    pub ttl_secs: u64,
+}
+impl Default for AnalysisStoreConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            ttl_secs: 3600,
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub enum AnalysisEntry {
+    Queued {
+        queued_at: DateTime<Utc>,
+    },
+    Running {
+        started_at: DateTime<Utc>,
+        progress: Option<Box<AnalysisProgress>>,
+    },
+    Complete(Box<AnalysisResult>),
+    Failed(String),
+    Cancelled,
+}
+impl AnalysisEntry {
+    pub fn status(&self) -> &'static str {
+        match self {
+            AnalysisEntry::Queued { .. } => "queued",
+            AnalysisEntry::Running { .. } => "running",
+            AnalysisEntry::Complete(_) => "complete",
+            AnalysisEntry::Failed(_) => "failed",
+            AnalysisEntry::Cancelled => "cancelled",
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            AnalysisEntry::Complete(_) | AnalysisEntry::Failed(_) | AnalysisEntry::Cancelled
+        )
+    }
+}
+struct StoreInner {
+    entries: HashMap<Uuid, (AnalysisEntry, Instant)>,
+    order: VecDeque<Uuid>,
+}
+pub struct AnalysisStore {
+    inner: Mutex<StoreInner>,
+    capacity: usize,
+    ttl: Duration,
+}
+impl AnalysisStore {
+    pub fn new(config: AnalysisStoreConfig) -> Self {
+        Self {
+            inner: Mutex::new(StoreInner {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            capacity: config.capacity.max(1),
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+    pub async fn queue(&self, id: Uuid) {
+        self.insert(
+            id,
+            AnalysisEntry::Queued {
+                queued_at: Utc::now(),
+            },
+        )
+        .await;
+    }
+    pub async fn begin(&self, id: Uuid) {
+        self.insert(
+            id,
+            AnalysisEntry::Running {
+                started_at: Utc::now(),
+                progress: None,
+            },
+        )
+        .await;
+    }
+    pub async fn progress(&self, id: Uuid, update: AnalysisProgress) {
+        let mut inner = self.inner.lock().await;
+        if let Some((AnalysisEntry::Running { progress, .. }, _)) = inner.entries.get_mut(&id) {
+            *progress = Some(Box::new(update));
+        }
+    }
+    pub async fn finish(&self, id: Uuid, result: &Result<AnalysisResult>) {
+        let entry = match result {
+            Ok(result) => AnalysisEntry::Complete(Box::new(result.clone())),
+            Err(Error::AnalysisCancelled) => AnalysisEntry::Cancelled,
+            Err(e) => AnalysisEntry::Failed(e.to_string()),
+        };
+        self.insert(id, entry).await;
+    }
+    pub async fn get(&self, id: &Uuid) -> Option<AnalysisEntry> {
+        let mut inner = self.inner.lock().await;
+        match inner.entries.get(id) {
+            Some((_, stored_at)) if stored_at.elapsed() >= self.ttl => {
+                inner.entries.remove(id);
+                inner.order.retain(|i| i != id);
+                None
+            }
+            Some((entry, _)) => Some(entry.clone()),
+            None => None,
+        }
+    }
+    pub async fn running(&self) -> Vec<ActiveAnalysisStatus> {
+        let inner = self.inner.lock().await;
+        let now = Utc::now();
+        let mut running: Vec<_> = inner
+            .entries
+            .iter()
+            .filter(|(_, (_, stored_at))| stored_at.elapsed() < self.ttl)
+            .filter_map(|(id, (entry, _))| match entry {
+                AnalysisEntry::Running {
+                    started_at,
+                    progress,
+                } => Some(ActiveAnalysisStatus {
+                    id: *id,
+                    depth: progress.as_ref().map(|p| p.current_depth),
+                    elapsed_ms: (now - *started_at).num_milliseconds().max(0) as u64,
+                }),
+                _ => None,
+            })
+            .collect();
+        running.sort_by_key(|analysis| std::cmp::Reverse(analysis.elapsed_ms));
+        running
+    }
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.entries.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.inner.lock().await.entries.is_empty()
+    }
+    async fn insert(&self, id: Uuid, entry: AnalysisEntry) {
+        let mut inner = self.inner.lock().await;
+        let ttl = self.ttl;
+        inner
+            .entries
+            .retain(|_, (_, stored_at)| stored_at.elapsed() < ttl);
+        let StoreInner { entries, order } = &mut *inner;
+        order.retain(|i| entries.contains_key(i) && *i != id);
+        while order.len() >= self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                entries.remove(&oldest);
+            }
+        }
+        order.push_back(id);
+        entries.insert(id, (entry, Instant::now()));
+    }
+}
+impl Default for AnalysisStore {
+    fn default() -> Self {
+        Self::new(AnalysisStoreConfig::default())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_core::{Move, Score, StopReason, Variant};
+    fn result(id: Uuid) -> AnalysisResult {
+        AnalysisResult {
+            id,
+            fen: "startpos".to_string(),
+            best_move: Some(Move::new("e2", "e4")),
+            game_over: None,
+            ponder: None,
+            evaluation: Score::Centipawns(20),
+            tablebase: None,
+            best_move_san: None,
+            ponder_san: None,
+            wdl: None,
+            principal_variations: Vec::new(),
+            depth_reached: 10,
+            nodes_searched: 1000,
+            tbhits: 0,
+            time_ms: 5,
+            stopped_by: StopReason::Depth,
+            variant: Variant::Standard,
+            completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: false,
+            score_history: Vec::new(),
+        }
+    }
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let store = AnalysisStore::default();
+        let id = Uuid::new_v4();
+        assert!(store.get(&id).await.is_none());
+        store.queue(id).await;
+        assert_eq!(store.get(&id).await.unwrap().status(), "queued");
+        store.begin(id).await;
+        store
+            .progress(
+                id,
+                AnalysisProgress {
+                    id,
+                    current_depth: 7,
+                    target_depth: 20,
+                    current_move: None,
+                    nodes_per_second: 0,
+                    hash_full: 0,
+                    elapsed_ms: 10,
+                    evaluation: None,
+                    wdl: None,
+                    principal_variations: Vec::new(),
+                },
+            )
+            .await;
+        assert!(matches!(
+            store.get(&id).await,
+            Some(AnalysisEntry::Running { progress: Some(p), .. }) if p.current_depth == 7
+        ));
+        let other = Uuid::new_v4();
+        store.begin(other).await;
+        let running = store.running().await;
+        assert_eq!(running.len(), 2);
+        let depth = |analysis: Uuid| running.iter().find(|a| a.id == analysis).unwrap().depth;
+        assert_eq!(depth(id), Some(7));
+        assert_eq!(depth(other), None);
+        store.finish(other, &Err(Error::AnalysisCancelled)).await;
+        store.finish(id, &Ok(result(id))).await;
+        assert!(matches!(store.get(&id).await, Some(AnalysisEntry::Complete(r)) if r.id == id));
+        let failed = Uuid::new_v4();
+        store.finish(failed, &Err(Error::AnalysisTimeout)).await;
+        assert!(matches!(
+            store.get(&failed).await,
+            Some(AnalysisEntry::Failed(_))
+        ));
+        let cancelled = Uuid::new_v4();
+        store
+            .finish(cancelled, &Err(Error::AnalysisCancelled))
+            .await;
+        assert_eq!(store.get(&cancelled).await.unwrap().status(), "cancelled");
+        assert!(store.running().await.is_empty());
+        assert_eq!(store.len().await, 4);
+    }
+    #[tokio::test]
+    async fn test_capacity_and_ttl() {
+        let store = AnalysisStore::new(AnalysisStoreConfig {
+            capacity: 2,
+            ttl_secs: 3600,
+        });
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            store.finish(*id, &Ok(result(*id))).await;
+        }
+        assert!(store.get(&ids[0]).await.is_none());
+        assert!(store.get(&ids[2]).await.is_some());
+        assert_eq!(store.len().await, 2);
+        let expiring = AnalysisStore::new(AnalysisStoreConfig {
+            capacity: 8,
+            ttl_secs: 0,
+        });
+        expiring.begin(ids[0]).await;
+        assert!(expiring.get(&ids[0]).await.is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::ApiState;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use ironfish_core::Telemetry;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle, PrometheusRecorder};
+use std::sync::Arc;
+use std::time::Instant;
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+pub struct MetricsRegistry {
+    recorder: Arc<PrometheusRecorder>,
+    handle: PrometheusHandle,
+}
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets(DURATION_BUCKETS)
+            .expect("valid histogram buckets")
+            .build_recorder();
+        let handle = recorder.handle();
+        Self {
+            recorder: Arc::new(recorder),
+            handle,
+        }
+    }
+    pub fn telemetry(&self) -> Telemetry {
+        Telemetry::new(self.recorder.clone())
+    }
+    pub fn render(&self) -> String {
+        self.handle.render()
+    }
+}
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn protocol(path: &str) -> &'static str {
+    if path.starts_with("/chess.") || path.starts_with("/grpc.") {
+        "grpc"
+    } else if path.starts_with("/graphql") {
+        "graphql"
+    } else if path == "/v1/ws" {
+        "ws"
+    } else {
+        "rest"
+    }
+}
+pub async fn track_requests(
+    State(state): State<Arc<ApiState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let protocol = protocol(req.uri().path());
+    let started = Instant::now();
+    let response = next.run(req).await;
+    state.metrics.telemetry().request_finished(
+        protocol,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_core::Error;
+    use std::time::Duration;
+    #[test]
+    fn test_render_prometheus_text() {
+        let registry = MetricsRegistry::new();
+        let telemetry = registry.telemetry();
+        telemetry.analysis_finished(Duration::from_millis(20), None);
+        telemetry.bestmove_finished(Some(&Error::InvalidFen("bad".into())));
+        telemetry.request_finished(
+            protocol("/chess.ChessAnalysis/Analyze"),
+            200,
+            Duration::ZERO,
+        );
+        let text = registry.render();
+        assert!(text.contains("ironfish_analyses_total 1"));
+        assert!(text.contains("ironfish_bestmove_requests_total 1"));
+        assert!(text.contains("ironfish_errors_total{type=\"invalid_fen\"} 1"));
+        assert!(text.contains("ironfish_analysis_duration_seconds_bucket"));
+        assert!(text.contains("protocol=\"grpc\""));
+    }
+}
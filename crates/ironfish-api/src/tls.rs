@@ -0,0 +1,520 @@
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use ironfish_cluster::{load_certs, load_key};
+use ironfish_core::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, info};
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerTlsConfig {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    pub peer_ca: Option<PathBuf>,
+    pub peer_server_name: Option<String>,
+}
+impl ServerTlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert_path.is_some() || self.key_path.is_some() || self.client_ca.is_some()
+    }
+}
+#[derive(Clone)]
+pub struct ServerTls {
+    config: ServerTlsConfig,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+impl ServerTls {
+    pub fn from_config(config: &ServerTlsConfig) -> Result<Option<Self>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+        let acceptor = build_acceptor(config)?;
+        Ok(Some(Self {
+            config: config.clone(),
+            acceptor: Arc::new(RwLock::new(acceptor)),
+        }))
+    }
+    pub fn reload(&self) -> Result<()> {
+        let acceptor = build_acceptor(&self.config)?;
+        *self.acceptor.write().unwrap() = acceptor;
+        info!("server tls certificates reloaded");
+        Ok(())
+    }
+    pub fn is_mutual(&self) -> bool {
+        self.config.client_ca.is_some()
+    }
+    fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+}
+#[derive(Clone)]
+pub struct PeerTls {
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+impl PeerTls {
+    pub fn from_config(config: &ServerTlsConfig) -> Result<Option<Self>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+        let certs = load_certs(&required(&config.cert_path, "cert_path")?)?;
+        let anchors = match config.peer_ca.as_ref().or(config.client_ca.as_ref()) {
+            Some(path) => load_certs(path)?,
+            None => certs.clone(),
+        };
+        let mut roots = RootCertStore::empty();
+        for ca in anchors {
+            roots
+                .add(ca)
+                .map_err(|e| Error::Config(format!("invalid peer CA certificate: {}", e)))?;
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Config(format!("peer tls setup failed: {}", e)))?
+            .with_root_certificates(roots);
+        let client_config = match config.client_ca {
+            Some(_) => {
+                let key = load_key(&required(&config.key_path, "key_path")?)?;
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .map_err(|e| Error::Config(format!("invalid server certificate: {}", e)))?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_name = config
+            .peer_server_name
+            .clone()
+            .map(ServerName::try_from)
+            .transpose()
+            .map_err(|e| Error::Config(format!("invalid peer tls server name: {}", e)))?;
+        Ok(Some(Self {
+            config: Arc::new(client_config),
+            server_name,
+        }))
+    }
+    pub(crate) fn client_config(&self) -> Arc<ClientConfig> {
+        self.config.clone()
+    }
+    pub(crate) fn server_name(&self) -> Option<ServerName<'static>> {
+        self.server_name.clone()
+    }
+}
+fn required(path: &Option<PathBuf>, name: &str) -> Result<PathBuf> {
+    path.clone()
+        .ok_or_else(|| Error::Config(format!("server tls requires {}", name)))
+}
+fn build_acceptor(config: &ServerTlsConfig) -> Result<TlsAcceptor> {
+    let cert_path = required(&config.cert_path, "cert_path")?;
+    let key_path = required(&config.key_path, "key_path")?;
+    let certs = load_certs(&cert_path)?;
+    let key = load_key(&key_path)?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::Config(format!("server tls setup failed: {}", e)))?;
+    let builder = match config.client_ca {
+        Some(ref ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for ca in load_certs(ca_path)? {
+                roots
+                    .add(ca)
+                    .map_err(|e| Error::Config(format!("invalid client CA certificate: {}", e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| Error::Config(format!("invalid client CA: {}", e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::Config(format!("invalid server certificate: {}", e)))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+pub async fn serve_tls<F>(listener: TcpListener, tls: ServerTls, router: Router, shutdown: F)
+where
+    F: Future<Output = ()> + Send,
+{
+    let make_service = router.into_make_service_with_connect_info::<SocketAddr>();
+    let graceful = GracefulShutdown::new();
+    let builder = auto::Builder::new(TokioExecutor::new());
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("tls accept error: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let acceptor = tls.acceptor();
+        let service = match make_service.clone().oneshot(remote).await {
+            Ok(service) => service,
+            Err(never) => match never {},
+        };
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!("tls handshake with {} failed: {}", remote, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("tls handshake with {} timed out", remote);
+                        return;
+                    }
+                };
+            let connection = builder.serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(service),
+            );
+            if let Err(e) = watcher.watch(connection.into_owned()).await {
+                debug!("tls connection from {} closed: {}", remote, e);
+            }
+        });
+    }
+    graceful.shutdown().await;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        LeaderForwarder, FORWARDED_FROM_HEADER, FORWARDED_SECRET_HEADER, SERVED_BY_HEADER,
+    };
+    use axum::extract::ws::WebSocketUpgrade;
+    use axum::extract::ConnectInfo;
+    use axum::routing::get;
+    use ironfish_core::{NodeId, NodeInfo};
+    use rcgen::{CertificateParams, KeyPair};
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::pki_types::CertificateDer;
+    use tokio_rustls::TlsConnector;
+    fn write_cert(dir: &Path) -> CertificateDer<'static> {
+        let key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        std::fs::write(dir.join("server.crt"), cert.pem()).unwrap();
+        std::fs::write(dir.join("server.key"), key.serialize_pem()).unwrap();
+        cert.der().clone()
+    }
+    async fn request(
+        addr: SocketAddr,
+        root: &CertificateDer<'static>,
+        request: &str,
+    ) -> (
+        tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
+        Vec<u8>,
+    ) {
+        let mut roots = RootCertStore::empty();
+        roots.add(root.clone()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        let peer = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        (stream, peer)
+    }
+    async fn fetch(addr: SocketAddr, root: &CertificateDer<'static>) -> (String, Vec<u8>) {
+        let (mut stream, peer) = request(
+            addr,
+            root,
+            "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        (response, peer)
+    }
+    #[tokio::test]
+    async fn test_serves_https_and_reloads_certificates() {
+        let dir = std::env::temp_dir().join(format!("ironfish-https-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = write_cert(&dir);
+        let tls = ServerTls::from_config(&ServerTlsConfig {
+            cert_path: Some(dir.join("server.crt")),
+            key_path: Some(dir.join("server.key")),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let router =
+            Router::new()
+                .route(
+                    "/",
+                    get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move {
+                        addr.ip().to_string()
+                    }),
+                )
+                .route(
+                    "/ws",
+                    get(|ws: WebSocketUpgrade| async move { ws.on_upgrade(|_| async {}) }),
+                );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_tls(
+            listener,
+            tls.clone(),
+            router,
+            std::future::pending(),
+        ));
+        let (response, peer) = fetch(addr, &first).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("127.0.0.1"));
+        assert_eq!(peer, first.to_vec());
+        let (mut upgrade, _) = request(
+            addr,
+            &first,
+            "GET /ws HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: websocket\r\nsec-websocket-version: 13\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .await;
+        let mut head = [0u8; 64];
+        let read = upgrade.read(&mut head).await.unwrap();
+        assert!(String::from_utf8_lossy(&head[..read]).starts_with("HTTP/1.1 101"));
+        let second = write_cert(&dir);
+        tls.reload().unwrap();
+        let (response, peer) = fetch(addr, &second).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(peer, second.to_vec());
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn test_leader_forwarder_uses_https() {
+        let dir = std::env::temp_dir().join(format!("ironfish-peer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_cert(&dir);
+        let config = ServerTlsConfig {
+            cert_path: Some(dir.join("server.crt")),
+            key_path: Some(dir.join("server.key")),
+            peer_server_name: Some("localhost".to_string()),
+            ..Default::default()
+        };
+        let tls = ServerTls::from_config(&config).unwrap().unwrap();
+        let router = Router::new().route(
+            "/_admin/tokens",
+            axum::routing::post(|headers: axum::http::HeaderMap| async move {
+                let header = |name: &str| {
+                    headers
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                format!(
+                    "{} {}",
+                    header(FORWARDED_FROM_HEADER),
+                    header(FORWARDED_SECRET_HEADER)
+                )
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_tls(listener, tls, router, std::future::pending()));
+        let forwarder = LeaderForwarder::new()
+            .with_secret(Some("cluster-secret"))
+            .with_tls(PeerTls::from_config(&config).unwrap());
+        let leader = NodeInfo {
+            id: NodeId::from_string("leader"),
+            address: addr,
+            priority: 100,
+            started_at: chrono::Utc::now(),
+            version: "test".to_string(),
+        };
+        let request = axum::extract::Request::post("/_admin/tokens")
+            .header(FORWARDED_FROM_HEADER, "spoofed")
+            .header(FORWARDED_SECRET_HEADER, "guessed")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = forwarder
+            .forward(&leader, &NodeId::from_string("follower"), request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[SERVED_BY_HEADER], "leader");
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"follower cluster-secret");
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_handshakes_are_dropped() {
+        let dir = std::env::temp_dir().join(format!("ironfish-stall-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_cert(&dir);
+        let tls = ServerTls::from_config(&ServerTlsConfig {
+            cert_path: Some(dir.join("server.crt")),
+            key_path: Some(dir.join("server.key")),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_tls(
+            listener,
+            tls,
+            Router::new(),
+            std::future::pending(),
+        ));
+        let mut stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let started = tokio::time::Instant::now();
+        let mut buf = [0u8; 16];
+        assert_eq!(stalled.read(&mut buf).await.unwrap(), 0);
+        assert!(started.elapsed() >= TLS_HANDSHAKE_TIMEOUT);
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn test_analysis_forwarder_uses_https_and_wss() {
+        use crate::{AnalysisForwarder, ForwardingConfig};
+        use axum::extract::ws::Message;
+        use ironfish_cluster::{
+            CpuAwareLoadBalancer, LoadBalancerConfig, MembershipManager, Node, NodeConfig,
+        };
+        use ironfish_core::{
+            AnalysisRequest, ChessPosition, LoadBalancer, NodeMetrics, ServerMessage,
+        };
+        use ironfish_stockfish::AnalysisService;
+        let dir = std::env::temp_dir().join(format!("ironfish-relay-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_cert(&dir);
+        let config = ServerTlsConfig {
+            cert_path: Some(dir.join("server.crt")),
+            key_path: Some(dir.join("server.key")),
+            peer_server_name: Some("localhost".to_string()),
+            ..Default::default()
+        };
+        let result = AnalysisService::new_mock()
+            .analyze(AnalysisRequest::new(ChessPosition::starting().fen))
+            .await
+            .unwrap();
+        let complete = serde_json::to_string(&ServerMessage::AnalysisComplete {
+            id: "relayed".to_string(),
+            result: Box::new(result.clone()),
+            request_id: None,
+        })
+        .unwrap();
+        let router = Router::new()
+            .route(
+                "/v1/analyze",
+                axum::routing::post(move || async move { axum::Json(result) }),
+            )
+            .route(
+                "/v1/ws",
+                get(move |ws: WebSocketUpgrade| async move {
+                    ws.on_upgrade(move |mut socket| async move {
+                        for _ in 0..2 {
+                            socket.recv().await;
+                        }
+                        let _ = socket.send(Message::Text(complete.into())).await;
+                    })
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tls = ServerTls::from_config(&config).unwrap().unwrap();
+        let server = tokio::spawn(serve_tls(listener, tls, router, std::future::pending()));
+        let peer = NodeId::from_string("peer");
+        let membership = Arc::new(MembershipManager::new(Arc::new(Node::new(
+            NodeConfig::default(),
+        ))));
+        membership
+            .add_member(NodeInfo {
+                id: peer.clone(),
+                address: addr,
+                priority: 100,
+                started_at: chrono::Utc::now(),
+                version: "test".to_string(),
+            })
+            .await;
+        let balancer = Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default()));
+        balancer.add_node(peer.clone()).await;
+        balancer
+            .update_metrics(
+                &peer,
+                NodeMetrics {
+                    engines_total: 1,
+                    engines_available: 1,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let forwarder = AnalysisForwarder::new(ForwardingConfig::default(), balancer, membership)
+            .with_tls(PeerTls::from_config(&config).unwrap());
+        let local = NodeId::from_string("local");
+        let request = AnalysisRequest::new(ChessPosition::starting().fen);
+        let (served_by, _) = forwarder.forward(&local, &request, None).await.unwrap();
+        assert_eq!(served_by, peer);
+        let (progress, _) = tokio::sync::mpsc::channel(4);
+        let (served_by, relayed) = forwarder
+            .forward_streaming(
+                &local,
+                &request,
+                "service-token",
+                progress,
+                tokio_util::sync::CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!((served_by, relayed.id), (peer, request.id));
+        server.abort();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_unreadable_files_are_rejected() {
+        let missing =
+            std::env::temp_dir().join(format!("ironfish-missing-{}", uuid::Uuid::new_v4()));
+        let error = ServerTls::from_config(&ServerTlsConfig {
+            cert_path: Some(missing.join("server.crt")),
+            key_path: Some(missing.join("server.key")),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(matches!(error, Error::Config(ref message) if message.contains("server.crt")));
+        let error = ServerTls::from_config(&ServerTlsConfig {
+            cert_path: Some(missing.join("server.crt")),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert!(matches!(error, Error::Config(ref message) if message.contains("key_path")));
+        assert!(ServerTls::from_config(&ServerTlsConfig::default())
+            .unwrap()
+            .is_none());
+    }
+}
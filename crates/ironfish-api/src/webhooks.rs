@@ -0,0 +1,493 @@
+use crate::router::ApiState;
+use chrono::{DateTime, Utc};
+use ironfish_core::{AnalysisResult, ClusterEvent, Error, LeaveReason, NodeId, Result};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+pub const SIGNATURE_HEADER: &str = "x-ironfish-signature";
+pub const EVENT_HEADER: &str = "x-ironfish-event";
+pub const DELIVERY_HEADER: &str = "x-ironfish-delivery";
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "analysis.completed")]
+    AnalysisCompleted,
+    #[serde(rename = "analysis.failed")]
+    AnalysisFailed,
+    #[serde(rename = "cluster.node_down")]
+    NodeDown,
+    #[serde(rename = "cluster.leader_changed")]
+    LeaderChanged,
+    #[serde(rename = "token.revoked")]
+    TokenRevoked,
+}
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::AnalysisCompleted => "analysis.completed",
+            WebhookEvent::AnalysisFailed => "analysis.failed",
+            WebhookEvent::NodeDown => "cluster.node_down",
+            WebhookEvent::LeaderChanged => "cluster.leader_changed",
+            WebhookEvent::TokenRevoked => "token.revoked",
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+impl WebhookEndpoint {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub hooks: Vec<WebhookEndpoint>,
+    pub queue_capacity: usize,
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub timeout_secs: u64,
+    pub delivery_log_size: usize,
+    pub dead_letter_file: Option<PathBuf>,
+}
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            queue_capacity: 1024,
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 60_000,
+            timeout_secs: 10,
+            delivery_log_size: 200,
+            dead_letter_file: None,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Delivered,
+    Retrying,
+    DeadLettered,
+    Dropped,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub delivery_id: Uuid,
+    pub event: WebhookEvent,
+    pub url: String,
+    pub attempt: u32,
+    pub status: DeliveryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub at: DateTime<Utc>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDeliveries {
+    pub deliveries: Vec<WebhookDelivery>,
+}
+struct Notification {
+    delivery_id: Uuid,
+    event: WebhookEvent,
+    body: Arc<Vec<u8>>,
+}
+struct Endpoint {
+    hook: WebhookEndpoint,
+    queue: mpsc::Sender<Notification>,
+}
+pub struct Webhooks {
+    node_id: String,
+    endpoints: Vec<Endpoint>,
+    log: Arc<DeliveryLog>,
+}
+struct DeliveryLog {
+    recent: Mutex<VecDeque<WebhookDelivery>>,
+    capacity: usize,
+    dead_letters: Option<Mutex<File>>,
+}
+impl DeliveryLog {
+    fn record(&self, delivery: WebhookDelivery) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= self.capacity {
+            recent.pop_back();
+        }
+        recent.push_front(delivery);
+    }
+    fn dead_letter(&self, delivery: &WebhookDelivery, body: &[u8]) {
+        warn!(
+            "webhook {} for {} dead-lettered after {} attempts: {}",
+            delivery.event.as_str(),
+            delivery.url,
+            delivery.attempt,
+            delivery.error.as_deref().unwrap_or("")
+        );
+        let Some(ref file) = self.dead_letters else {
+            return;
+        };
+        let line = json!({
+            "delivery": delivery,
+            "body": String::from_utf8_lossy(body),
+        });
+        let mut file = file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            warn!("failed to write webhook dead letter: {}", e);
+        }
+    }
+}
+impl Webhooks {
+    pub fn new(config: WebhookConfig, node_id: impl Into<String>) -> Result<Self> {
+        let dead_letters = match config.dead_letter_file {
+            Some(ref path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| Error::Storage(e.to_string()))?,
+            )),
+            None => None,
+        };
+        let log = Arc::new(DeliveryLog {
+            recent: Mutex::new(VecDeque::new()),
+            capacity: config.delivery_log_size.max(1),
+            dead_letters,
+        });
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+            .build()
+            .map_err(|e| Error::Config(format!("webhook client: {}", e)))?;
+        let endpoints = config
+            .hooks
+            .iter()
+            .map(|hook| {
+                let (queue, rx) = mpsc::channel(config.queue_capacity.max(1));
+                let key = hook
+                    .secret
+                    .as_ref()
+                    .map(|s| hmac::Key::new(hmac::HMAC_SHA256, s.as_bytes()));
+                tokio::spawn(dispatch(
+                    Dispatcher {
+                        url: hook.url.clone(),
+                        key,
+                        client: client.clone(),
+                        max_attempts: config.max_attempts.max(1),
+                        initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+                        max_backoff: Duration::from_millis(config.max_backoff_ms),
+                        log: log.clone(),
+                    },
+                    rx,
+                ));
+                Endpoint {
+                    hook: hook.clone(),
+                    queue,
+                }
+            })
+            .collect();
+        Ok(Self {
+            node_id: node_id.into(),
+            endpoints,
+            log,
+        })
+    }
+    pub fn notify(&self, event: WebhookEvent, data: Value) {
+        let delivery_id = Uuid::new_v4();
+        let payload = json!({
+            "id": delivery_id,
+            "event": event,
+            "node_id": self.node_id,
+            "created_at": Utc::now(),
+            "data": data,
+        });
+        let body = Arc::new(payload.to_string().into_bytes());
+        for endpoint in self.endpoints.iter().filter(|e| e.hook.wants(event)) {
+            let notification = Notification {
+                delivery_id,
+                event,
+                body: body.clone(),
+            };
+            if endpoint.queue.try_send(notification).is_err() {
+                let delivery = WebhookDelivery {
+                    delivery_id,
+                    event,
+                    url: endpoint.hook.url.clone(),
+                    attempt: 0,
+                    status: DeliveryStatus::Dropped,
+                    http_status: None,
+                    error: Some("delivery queue is full".to_string()),
+                    at: Utc::now(),
+                };
+                self.log.dead_letter(&delivery, &body);
+                self.log.record(delivery);
+            }
+        }
+    }
+    pub fn analysis_finished(&self, id: Uuid, result: &Result<AnalysisResult>) {
+        match result {
+            Ok(result) => self.notify(
+                WebhookEvent::AnalysisCompleted,
+                json!({"analysis_id": id, "result": result}),
+            ),
+            Err(e) => self.notify(
+                WebhookEvent::AnalysisFailed,
+                json!({
+                    "analysis_id": id,
+                    "error": {"code": e.code(), "message": e.to_string()},
+                }),
+            ),
+        }
+    }
+    pub fn cluster_event(&self, event: &ClusterEvent) {
+        match event {
+            ClusterEvent::NodeLeft {
+                node_id,
+                reason: LeaveReason::Unreachable,
+            } => self.notify(
+                WebhookEvent::NodeDown,
+                json!({"node_id": node_id, "reason": LeaveReason::Unreachable}),
+            ),
+            ClusterEvent::LeaderChanged {
+                old_leader,
+                new_leader,
+                term,
+            } => self.notify(
+                WebhookEvent::LeaderChanged,
+                json!({"old_leader": old_leader, "new_leader": new_leader, "term": term}),
+            ),
+            _ => {}
+        }
+    }
+    pub fn deliveries(&self, limit: usize) -> Vec<WebhookDelivery> {
+        self.log
+            .recent
+            .lock()
+            .unwrap()
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+pub fn spawn_webhook_notifier(state: Arc<ApiState>) {
+    let Some(webhooks) = state.webhooks.clone() else {
+        return;
+    };
+    let mut rx = state.membership.subscribe_events();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let reporter = match state.network {
+                        Some(_) => {
+                            let mut nodes: Vec<NodeId> = state
+                                .membership
+                                .list_members()
+                                .await
+                                .into_iter()
+                                .map(|member| member.id)
+                                .collect();
+                            nodes.push(state.node.id().clone());
+                            cluster_event_reporter(&event, state.node.leader(), &nodes)
+                        }
+                        None => Some(state.node.id().clone()),
+                    };
+                    if reporter.as_ref() == Some(state.node.id()) {
+                        webhooks.cluster_event(&event);
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("webhook notifier skipped {} cluster events", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+fn cluster_event_reporter(
+    event: &ClusterEvent,
+    leader: Option<NodeId>,
+    nodes: &[NodeId],
+) -> Option<NodeId> {
+    match event {
+        ClusterEvent::LeaderChanged {
+            old_leader,
+            new_leader,
+            ..
+        } => new_leader.clone().or_else(|| old_leader.clone()),
+        ClusterEvent::NodeLeft { node_id, .. } => match leader {
+            Some(leader) if leader != *node_id => Some(leader),
+            _ => nodes.iter().filter(|id| *id != node_id).min().cloned(),
+        },
+        _ => None,
+    }
+}
+fn signature(key: &hmac::Key, body: &[u8]) -> String {
+    let tag = hmac::sign(key, body);
+    let hex: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+struct Dispatcher {
+    url: String,
+    key: Option<hmac::Key>,
+    client: reqwest::Client,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    log: Arc<DeliveryLog>,
+}
+impl Dispatcher {
+    async fn send(&self, notification: &Notification) -> (Option<u16>, Option<String>) {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, notification.event.as_str())
+            .header(DELIVERY_HEADER, notification.delivery_id.to_string());
+        if let Some(ref key) = self.key {
+            request = request.header(SIGNATURE_HEADER, signature(key, &notification.body));
+        }
+        match request.body(notification.body.to_vec()).send().await {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("endpoint answered {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        }
+    }
+    async fn deliver(&self, notification: Notification) {
+        let mut backoff = self.initial_backoff;
+        for attempt in 1..=self.max_attempts {
+            let (http_status, error) = self.send(&notification).await;
+            let retryable = match http_status {
+                _ if error.is_none() => false,
+                Some(status) => status >= 500 || status == 408 || status == 429,
+                None => true,
+            };
+            let status = match (&error, retryable) {
+                (None, _) => DeliveryStatus::Delivered,
+                (Some(_), true) if attempt < self.max_attempts => DeliveryStatus::Retrying,
+                (Some(_), _) => DeliveryStatus::DeadLettered,
+            };
+            let delivery = WebhookDelivery {
+                delivery_id: notification.delivery_id,
+                event: notification.event,
+                url: self.url.clone(),
+                attempt,
+                status,
+                http_status,
+                error,
+                at: Utc::now(),
+            };
+            if status == DeliveryStatus::DeadLettered {
+                self.log.dead_letter(&delivery, &notification.body);
+            }
+            self.log.record(delivery);
+            if status != DeliveryStatus::Retrying {
+                return;
+            }
+            debug!(
+                "retrying webhook {} to {} in {:?}",
+                notification.event.as_str(),
+                self.url,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+}
+async fn dispatch(dispatcher: Dispatcher, mut rx: mpsc::Receiver<Notification>) {
+    while let Some(notification) = rx.recv().await {
+        dispatcher.deliver(notification).await;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_event_names_and_filters() {
+        let hook: WebhookEndpoint = serde_json::from_value(json!({
+            "url": "http://127.0.0.1:1/hook",
+            "events": ["analysis.completed", "cluster.node_down"],
+            "secret": "s3cret",
+        }))
+        .unwrap();
+        assert!(hook.wants(WebhookEvent::AnalysisCompleted));
+        assert!(hook.wants(WebhookEvent::NodeDown));
+        assert!(!hook.wants(WebhookEvent::TokenRevoked));
+        assert_eq!(
+            serde_json::to_value(WebhookEvent::LeaderChanged).unwrap(),
+            WebhookEvent::LeaderChanged.as_str()
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"key");
+        assert_eq!(
+            signature(&key, b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+    #[test]
+    fn test_one_node_reports_each_cluster_event() {
+        let [a, b, c] = ["node-a", "node-b", "node-c"].map(NodeId::from_string);
+        let nodes = [a.clone(), b.clone(), c.clone()];
+        let down = |node_id: &NodeId| ClusterEvent::NodeLeft {
+            node_id: node_id.clone(),
+            reason: LeaveReason::Unreachable,
+        };
+        assert_eq!(
+            cluster_event_reporter(&down(&a), Some(c.clone()), &nodes),
+            Some(c.clone())
+        );
+        assert_eq!(
+            cluster_event_reporter(&down(&c), Some(c.clone()), &nodes),
+            Some(a.clone())
+        );
+        assert_eq!(
+            cluster_event_reporter(&down(&a), None, &nodes),
+            Some(b.clone())
+        );
+        let elected = ClusterEvent::LeaderChanged {
+            old_leader: Some(c.clone()),
+            new_leader: Some(b.clone()),
+            term: 4,
+        };
+        assert_eq!(cluster_event_reporter(&elected, None, &nodes), Some(b));
+        let lost = ClusterEvent::LeaderChanged {
+            old_leader: Some(c.clone()),
+            new_leader: None,
+            term: 4,
+        };
+        assert_eq!(cluster_event_reporter(&lost, None, &nodes), Some(c));
+        assert_eq!(
+            cluster_event_reporter(
+                &ClusterEvent::TokenRevoked {
+                    token_id: Uuid::new_v4()
+                },
+                Some(a),
+                &nodes
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use super::protocol::ServerMessage;
+use crate::ApiState;
+use ironfish_core::{ClusterEvent, GossipMessage};
+use serde_json::json;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+pub const CLUSTER_TOPIC: &str = "cluster";
+pub const METRICS_TOPIC: &str = "metrics";
+
+pub fn spawn_event_publisher(state: Arc<ApiState>) -> Vec<JoinHandle<()>> {
+    let mut tasks = Vec::new();
+    if let Some(ref tx) = state.gossip_tx {
+        tasks.push(spawn_until_closing(
+            &state,
+            publish_cluster_events(state.clone(), tx.subscribe(), cluster_event),
+        ));
+    }
+    tasks.push(spawn_until_closing(
+        &state,
+        publish_cluster_events(
+            state.clone(),
+            state.membership.subscribe_events(),
+            node_event,
+        ),
+    ));
+    tasks.push(spawn_until_closing(
+        &state,
+        close_revoked_sessions(state.clone(), state.membership.subscribe_events()),
+    ));
+    let interval = Duration::from_secs(state.ws_config.metrics_interval_secs.max(1));
+    let metrics_state = state.clone();
+    tasks.push(spawn_until_closing(&state, async move {
+        let mut timer = tokio::time::interval(interval);
+        loop {
+            timer.tick().await;
+            metrics_state
+                .ws_sessions
+                .publish(METRICS_TOPIC, metrics_event(&metrics_state))
+                .await;
+        }
+    }));
+    tasks
+}
+
+fn spawn_until_closing<F>(state: &ApiState, task: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let closing = state.ws_sessions.closing();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = closing.cancelled() => {}
+            _ = task => {}
+        }
+    })
+}
+
+async fn publish_cluster_events<T: Clone>(
+    state: Arc<ApiState>,
+    mut rx: broadcast::Receiver<T>,
+    to_event: fn(&T) -> Option<ServerMessage>,
+) {
+    loop {
+        match rx.recv().await {
+            Ok(message) => {
+                if let Some(event) = to_event(&message) {
+                    state.ws_sessions.publish(CLUSTER_TOPIC, event).await;
+                }
+            }
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+async fn close_revoked_sessions(state: Arc<ApiState>, mut rx: broadcast::Receiver<ClusterEvent>) {
+    loop {
+        match rx.recv().await {
+            Ok(ClusterEvent::TokenRevoked { token_id }) => {
+                state.ws_sessions.revoke_token(token_id).await;
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+pub fn cluster_event(message: &GossipMessage) -> Option<ServerMessage> {
+    let payload = match message {
+        GossipMessage::TokenRevoked(token_id) => json!({
+            "event": "token_revoked",
+            "token_id": token_id,
+        }),
+        _ => return None,
+    };
+    Some(ServerMessage::Event {
+        topic: CLUSTER_TOPIC.to_string(),
+        payload,
+    })
+}
+
+pub fn node_event(event: &ClusterEvent) -> Option<ServerMessage> {
+    let payload = match event {
+        ClusterEvent::NodeJoined { node } => json!({
+            "event": event.kind(),
+            "node_id": node.id,
+            "address": node.address,
+        }),
+        ClusterEvent::NodeLeft { node_id, reason } => json!({
+            "event": event.kind(),
+            "node_id": node_id,
+            "reason": reason,
+        }),
+        ClusterEvent::NodeDraining { node_id, .. } | ClusterEvent::NodeRecovered { node_id } => {
+            json!({
+                "event": event.kind(),
+                "node_id": node_id,
+            })
+        }
+        ClusterEvent::NodeUnhealthy { node_id, reason } => json!({
+            "event": event.kind(),
+            "node_id": node_id,
+            "reason": reason,
+        }),
+        ClusterEvent::LeaderChanged {
+            old_leader,
+            new_leader,
+            term,
+        } => json!({
+            "event": event.kind(),
+            "old_leader": old_leader,
+            "new_leader": new_leader,
+            "term": term,
+        }),
+        ClusterEvent::TokenRevoked { .. } => return None,
+    };
+    Some(ServerMessage::Event {
+        topic: CLUSTER_TOPIC.to_string(),
+        payload,
+    })
+}
+
+fn metrics_event(state: &ApiState) -> ServerMessage {
+    ServerMessage::Event {
+        topic: METRICS_TOPIC.to_string(),
+        payload: json!({
+            "node_id": state.node.id(),
+            "metrics": state.local_metrics(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_core::ApiToken;
+
+    #[test]
+    fn test_cluster_event_redacts_tokens() {
+        let token_id = uuid::Uuid::new_v4();
+        match cluster_event(&GossipMessage::TokenRevoked(token_id)) {
+            Some(ServerMessage::Event { topic, payload }) => {
+                assert_eq!(topic, CLUSTER_TOPIC);
+                assert_eq!(payload["token_id"], token_id.to_string());
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        let token: ApiToken = serde_json::from_value(json!({
+            "id": token_id,
+            "name": null,
+            "token_hash": "secret",
+            "created_at": chrono::Utc::now(),
+            "expires_at": null,
+            "last_used_at": null,
+            "created_by_node": "node",
+            "revoked": false,
+            "rate_limit": null,
+        }))
+        .unwrap();
+        assert!(cluster_event(&GossipMessage::TokenCreated(token)).is_none());
+    }
+}
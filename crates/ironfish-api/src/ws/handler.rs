@@ -1,17 +1,25 @@
-use super::protocol::{ClientMessage, ServerMessage};
+use super::outbound::SessionSender;
+use super::protocol::{
+    message_too_large, parse_client_message, server_hello, ServerMessage, GOING_AWAY,
+    MESSAGE_TOO_BIG, TRY_AGAIN_LATER,
+};
 use super::session::WsSession;
 use crate::ApiState;
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
 use axum::extract::{Query, State, WebSocketUpgrade};
 use axum::response::IntoResponse;
-use ironfish_core::TokenStore;
+use ironfish_core::{ApiToken, TokenContext};
 use serde::Deserialize;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tokio::time::{interval, Duration};
+use tokio_tungstenite::tungstenite::error::{CapacityError, Error as TungsteniteError};
 use tracing::debug;
 use uuid::Uuid;
 
+const WRITER_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(Debug, Deserialize)]
 pub struct WsParams {
     pub token: Option<String>,
@@ -25,35 +33,55 @@ pub async fn ws_handler(
     let pre_authenticated = if let Some(ref token) = params.token {
         validate_token(token, &state).await
     } else {
-        false
+        None
     };
 
     ws.max_message_size(state.ws_config.max_message_size_bytes)
         .on_upgrade(move |socket| handle_socket(socket, state, pre_authenticated))
 }
 
-async fn validate_token(token: &str, state: &ApiState) -> bool {
+async fn validate_token(token: &str, state: &ApiState) -> Option<ApiToken> {
     let raw = token.strip_prefix("iff_").unwrap_or(token);
     let hash = state.token_manager.hash_token(raw);
-    matches!(
-        state.token_store.get_by_hash(&hash).await,
-        Ok(Some(ref api_token)) if api_token.is_valid()
-    )
+    match state.token_store.get_by_hash(&hash).await {
+        Ok(Some(api_token)) if api_token.is_valid() => Some(api_token),
+        _ => None,
+    }
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticated: bool) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<ApiState>,
+    pre_authenticated: Option<ApiToken>,
+) {
     let session_id = Uuid::new_v4();
     let (mut ws_sender, mut ws_receiver) = socket.split();
-    let (tx, mut rx) = mpsc::channel::<ServerMessage>(64);
+    let (tx, mut rx) = mpsc::channel::<ServerMessage>(state.ws_config.outbound_queue_size.max(1));
+    let tx = SessionSender::new(tx, &state.ws_config, state.metrics.telemetry())
+        .with_connection_id(session_id);
+    let stalled = tx.stalled();
 
-    if state
+    let revoked = match state
         .ws_sessions
-        .register(session_id, tx.clone())
+        .register(
+            session_id,
+            tx.clone(),
+            pre_authenticated.as_ref().map(|t| t.id),
+        )
         .await
-        .is_err()
     {
-        return;
-    }
+        Ok(revoked) => revoked,
+        Err(reason) => {
+            debug!(session_id = %session_id, "rejecting ws session: {}", reason);
+            let _ = ws_sender
+                .send(Message::Close(Some(CloseFrame {
+                    code: TRY_AGAIN_LATER,
+                    reason: reason.into(),
+                })))
+                .await;
+            return;
+        }
+    };
 
     let mut session = WsSession::new(
         session_id,
@@ -61,9 +89,14 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
         state.clone(),
         state.ws_config.max_analyses_per_session,
     );
-    if pre_authenticated {
+    let mut resume_token = None;
+    if let Some(token) = pre_authenticated {
         session.authenticated = true;
+        session.token = TokenContext::from(&token);
+        session.token_hash = Some(token.token_hash.clone());
+        resume_token = session.issue_resume_token();
     }
+    let _ = session.tx.send(server_hello(&state, resume_token)).await;
 
     let auth_timeout = Duration::from_secs(state.ws_config.auth_timeout_secs);
     let ping_interval_duration = Duration::from_secs(state.ws_config.ping_interval_secs);
@@ -71,10 +104,35 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
     use axum::extract::ws::Message as WsMsg;
     use futures::SinkExt;
 
+    let closing = state.ws_sessions.closing();
+    let writer_closing = closing.clone();
+    let (close_tx, mut close_rx) = oneshot::channel::<CloseFrame>();
+    let mut close_tx = Some(close_tx);
     let writer_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if ws_sender.send(WsMsg::Text(json.into())).await.is_err() {
+        loop {
+            tokio::select! {
+                biased;
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    if let Ok(json) = serde_json::to_string(&msg) {
+                        if ws_sender.send(WsMsg::Text(json.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                _ = writer_closing.cancelled() => {
+                    let _ = ws_sender
+                        .send(WsMsg::Close(Some(CloseFrame {
+                            code: GOING_AWAY,
+                            reason: "server shutting down".into(),
+                        })))
+                        .await;
+                    break;
+                }
+                frame = &mut close_rx => {
+                    if let Ok(frame) = frame {
+                        let _ = ws_sender.send(WsMsg::Close(Some(frame))).await;
+                    }
                     break;
                 }
             }
@@ -90,30 +148,38 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
 
         loop {
             tokio::select! {
+                _ = closing.cancelled() => {
+                    break;
+                }
+                _ = stalled.cancelled() => {
+                    break;
+                }
                 _ = &mut auth_deadline => {
                     let _ = session.tx.send(ServerMessage::Error {
                         id: None,
                         code: 401,
+                        error: "auth_timeout".to_string(),
                         message: "auth timeout".to_string(),
+                        details: None,
+                        request_id: None,
                     }).await;
                     break;
                 }
                 msg = ws_receiver.next() => {
                     match msg {
                         Some(Ok(Message::Text(text))) => {
-                            match serde_json::from_str::<ClientMessage>(&text) {
+                            match parse_client_message(&text, state.ws_config.max_message_size_bytes) {
                                 Ok(client_msg) => {
                                     session.handle_message(client_msg).await;
+                                    if reject(&session, &mut close_tx) {
+                                        break;
+                                    }
                                     if session.authenticated {
                                         break;
                                     }
                                 }
-                                Err(e) => {
-                                    let _ = session.tx.send(ServerMessage::Error {
-                                        id: None,
-                                        code: 400,
-                                        message: format!("invalid message: {}", e),
-                                    }).await;
+                                Err(error) => {
+                                    let _ = session.tx.send(*error).await;
                                 }
                             }
                         }
@@ -122,6 +188,10 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
                             writer_task.abort();
                             return;
                         }
+                        Some(Err(e)) => {
+                            reject_oversized(&mut session, e, &mut close_tx).await;
+                            break;
+                        }
                         _ => {}
                     }
                 }
@@ -130,36 +200,59 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
 
         if !session.authenticated {
             cleanup(&state, &mut session, session_id).await;
-            writer_task.abort();
+            let drain = closing.is_cancelled() || session.rejected.is_some();
+            finish_writer(writer_task, drain).await;
             return;
         }
     }
 
+    let mut revalidate_interval = interval(Duration::from_secs(
+        state.ws_config.token_revalidate_secs.max(1),
+    ));
+    revalidate_interval.tick().await;
+
     use futures::StreamExt;
     loop {
         tokio::select! {
+            _ = closing.cancelled() => {
+                break;
+            }
+            _ = stalled.cancelled() => {
+                break;
+            }
+            _ = revoked.cancelled() => {
+                session.revoke().await;
+                reject(&session, &mut close_tx);
+                break;
+            }
+            _ = revalidate_interval.tick() => {
+                if !session.token_is_valid().await {
+                    session.revoke().await;
+                    reject(&session, &mut close_tx);
+                    break;
+                }
+            }
             _ = ping_interval.tick() => {
-                let _ = session.tx.send(ServerMessage::Pong {
+                session.tx.try_send(ServerMessage::Pong {
                     id: "server-ping".to_string(),
-                }).await;
+                });
             }
             msg = ws_receiver.next() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        match serde_json::from_str::<ClientMessage>(&text) {
+                        match parse_client_message(&text, state.ws_config.max_message_size_bytes) {
                             Ok(client_msg) => {
                                 session.handle_message(client_msg).await;
+                                if reject(&session, &mut close_tx) {
+                                    break;
+                                }
                                 state.ws_sessions.update_subscriptions(
                                     &session_id,
                                     &session.subscriptions,
                                 ).await;
                             }
-                            Err(e) => {
-                                let _ = session.tx.send(ServerMessage::Error {
-                                    id: None,
-                                    code: 400,
-                                    message: format!("invalid message: {}", e),
-                                }).await;
+                            Err(error) => {
+                                let _ = session.tx.send(*error).await;
                             }
                         }
                     }
@@ -169,6 +262,10 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
                     Some(Ok(Message::Close(_))) | None => {
                         break;
                     }
+                    Some(Err(e)) => {
+                        reject_oversized(&mut session, e, &mut close_tx).await;
+                        break;
+                    }
                     _ => {}
                 }
             }
@@ -176,11 +273,55 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>, pre_authenticate
     }
 
     cleanup(&state, &mut session, session_id).await;
+    let drain = closing.is_cancelled() || session.rejected.is_some();
+    finish_writer(writer_task, drain).await;
+}
+
+fn reject(session: &WsSession, close_tx: &mut Option<oneshot::Sender<CloseFrame>>) -> bool {
+    let Some((code, reason)) = session.rejected else {
+        return false;
+    };
+    if let Some(close_tx) = close_tx.take() {
+        let _ = close_tx.send(CloseFrame {
+            code,
+            reason: reason.into(),
+        });
+    }
+    true
+}
+
+async fn reject_oversized(
+    session: &mut WsSession,
+    error: axum::Error,
+    close_tx: &mut Option<oneshot::Sender<CloseFrame>>,
+) {
+    let error = error.into_inner();
+    let Some(TungsteniteError::Capacity(CapacityError::MessageTooLong { size, max_size })) =
+        error.downcast_ref::<TungsteniteError>()
+    else {
+        debug!("ws receive failed: {}", error);
+        return;
+    };
+    let _ = session.tx.send(message_too_large(*size, *max_size)).await;
+    session.rejected = Some((MESSAGE_TOO_BIG, "message too large"));
+    reject(session, close_tx);
+}
+
+async fn finish_writer(mut writer_task: JoinHandle<()>, drain: bool) {
+    if drain
+        && tokio::time::timeout(WRITER_DRAIN_TIMEOUT, &mut writer_task)
+            .await
+            .is_ok()
+    {
+        return;
+    }
     writer_task.abort();
 }
 
 async fn cleanup(state: &ApiState, session: &mut WsSession, session_id: Uuid) {
     debug!(session_id = %session_id, "ws session disconnected");
-    session.cancel_all().await;
+    if state.ws_sessions.closing().is_cancelled() || !session.detach().await {
+        session.cancel_all().await;
+    }
     state.ws_sessions.unregister(&session_id).await;
 }
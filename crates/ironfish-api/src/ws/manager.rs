@@ -1,51 +1,213 @@
+use super::outbound::SessionSender;
 use super::protocol::ServerMessage;
+use super::resume::DetachedSession;
+use ironfish_core::{SessionSummary, Telemetry};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 pub struct SessionHandle {
-    pub tx: mpsc::Sender<ServerMessage>,
+    pub tx: SessionSender,
+    pub token_id: Option<Uuid>,
     pub subscriptions: Arc<RwLock<std::collections::HashSet<String>>>,
+    pub revoked: CancellationToken,
 }
 
 pub struct SessionManager {
     sessions: RwLock<HashMap<Uuid, SessionHandle>>,
+    detached: Arc<Mutex<HashMap<String, DetachedSession>>>,
     max_connections: usize,
+    max_per_token: usize,
+    telemetry: Telemetry,
+    closing: CancellationToken,
 }
 
 impl SessionManager {
     pub fn new(max_connections: usize) -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
+            detached: Arc::new(Mutex::new(HashMap::new())),
             max_connections,
+            max_per_token: usize::MAX,
+            telemetry: Telemetry::default(),
+            closing: CancellationToken::new(),
         }
     }
 
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    pub fn with_max_sessions_per_token(mut self, max: usize) -> Self {
+        self.max_per_token = max.max(1);
+        self
+    }
+
     pub async fn register(
         &self,
         session_id: Uuid,
-        tx: mpsc::Sender<ServerMessage>,
-    ) -> Result<(), &'static str> {
+        tx: SessionSender,
+        token_id: Option<Uuid>,
+    ) -> Result<CancellationToken, &'static str> {
+        if self.closing.is_cancelled() {
+            return Err("server shutting down");
+        }
         let mut sessions = self.sessions.write().await;
         if sessions.len() >= self.max_connections {
+            self.telemetry.ws_session_rejected("node_limit");
             return Err("connection limit reached");
         }
+        if let Some(token_id) = token_id {
+            self.check_token_limit(&sessions, token_id)?;
+        }
+        let revoked = CancellationToken::new();
         sessions.insert(
             session_id,
             SessionHandle {
                 tx,
+                token_id,
                 subscriptions: Arc::new(RwLock::new(std::collections::HashSet::new())),
+                revoked: revoked.clone(),
             },
         );
+        self.telemetry.ws_session_opened();
+        Ok(revoked)
+    }
+
+    pub async fn authorize(&self, session_id: &Uuid, token_id: Uuid) -> Result<(), &'static str> {
+        let mut sessions = self.sessions.write().await;
+        if sessions.get(session_id).and_then(|h| h.token_id) == Some(token_id) {
+            return Ok(());
+        }
+        self.check_token_limit(&sessions, token_id)?;
+        if let Some(handle) = sessions.get_mut(session_id) {
+            handle.token_id = Some(token_id);
+        }
+        Ok(())
+    }
+
+    fn check_token_limit(
+        &self,
+        sessions: &HashMap<Uuid, SessionHandle>,
+        token_id: Uuid,
+    ) -> Result<(), &'static str> {
+        let open = sessions
+            .values()
+            .filter(|h| h.token_id == Some(token_id))
+            .count();
+        if open >= self.max_per_token {
+            self.telemetry.ws_session_rejected("token_limit");
+            return Err("session limit reached for token");
+        }
         Ok(())
     }
 
     pub async fn unregister(&self, session_id: &Uuid) {
-        self.sessions.write().await.remove(session_id);
+        if self.sessions.write().await.remove(session_id).is_some() {
+            self.telemetry.ws_session_closed();
+        }
+    }
+
+    pub async fn revoke_token(&self, token_id: Uuid) -> usize {
+        let mut revoked = 0;
+        for handle in self.sessions.read().await.values() {
+            if handle.token_id == Some(token_id) {
+                handle.revoked.cancel();
+                revoked += 1;
+            }
+        }
+        let detached: Vec<DetachedSession> = {
+            let mut detached = self.detached.lock().await;
+            let keys: Vec<String> = detached
+                .iter()
+                .filter(|(_, session)| session.token_id == Some(token_id))
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.iter().filter_map(|key| detached.remove(key)).collect()
+        };
+        for session in detached {
+            if let Some(ref expiry) = session.expiry {
+                expiry.abort();
+            }
+            session.cancel().await;
+        }
+        revoked
+    }
+
+    pub async fn detach(
+        &self,
+        resume_token: String,
+        mut session: DetachedSession,
+        grace: Duration,
+    ) {
+        let detached = self.detached.clone();
+        let key = resume_token.clone();
+        let expiry = tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            let expired = detached.lock().await.remove(&key);
+            if let Some(expired) = expired {
+                expired.cancel().await;
+            }
+        });
+        session.expiry = Some(expiry.abort_handle());
+        let previous = self.detached.lock().await.insert(resume_token, session);
+        if let Some(previous) = previous {
+            previous.cancel().await;
+        }
+    }
+
+    pub async fn resume(
+        &self,
+        resume_token: &str,
+        token_id: Option<Uuid>,
+    ) -> Option<DetachedSession> {
+        let mut detached = self.detached.lock().await;
+        if detached.get(resume_token)?.token_id != token_id {
+            return None;
+        }
+        let session = detached.remove(resume_token)?;
+        if let Some(ref expiry) = session.expiry {
+            expiry.abort();
+        }
+        Some(session)
+    }
+
+    pub async fn token_usage(&self, token_id: Uuid) -> (usize, usize) {
+        let live = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .filter(|h| h.token_id == Some(token_id))
+            .count();
+        let detached: Vec<_> = self
+            .detached
+            .lock()
+            .await
+            .values()
+            .filter(|session| session.token_id == Some(token_id))
+            .map(|session| session.analyses.clone())
+            .collect();
+        let mut analyses = 0;
+        for session in &detached {
+            analyses += session.lock().await.len();
+        }
+        (live + detached.len(), analyses)
+    }
+
+    pub fn max_sessions_per_token(&self) -> usize {
+        self.max_per_token
     }
 
-    pub async fn broadcast_to_topic(&self, topic: &str, message: ServerMessage) {
+    pub async fn detached_count(&self) -> usize {
+        self.detached.lock().await.len()
+    }
+
+    pub async fn publish(&self, topic: &str, message: ServerMessage) {
         let sessions = self.sessions.read().await;
         for handle in sessions.values() {
             let subs = handle.subscriptions.read().await;
@@ -70,4 +232,43 @@ impl SessionManager {
     pub async fn session_count(&self) -> usize {
         self.sessions.read().await.len()
     }
+
+    pub async fn summaries(&self) -> Vec<SessionSummary> {
+        let sessions = self.sessions.read().await;
+        let mut summaries = Vec::with_capacity(sessions.len());
+        for (id, handle) in sessions.iter() {
+            let mut subscriptions: Vec<String> =
+                handle.subscriptions.read().await.iter().cloned().collect();
+            subscriptions.sort();
+            summaries.push(SessionSummary {
+                id: *id,
+                token_id: handle.token_id,
+                subscriptions,
+            });
+        }
+        summaries
+    }
+
+    pub fn closing(&self) -> CancellationToken {
+        self.closing.clone()
+    }
+
+    pub async fn close_all(&self, timeout: Duration) -> bool {
+        self.closing.cancel();
+        let detached: Vec<DetachedSession> =
+            self.detached.lock().await.drain().map(|(_, s)| s).collect();
+        for session in detached {
+            if let Some(ref expiry) = session.expiry {
+                expiry.abort();
+            }
+            session.cancel().await;
+        }
+        tokio::time::timeout(timeout, async {
+            while self.session_count().await > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
 }
@@ -1,7 +1,12 @@
+pub mod events;
 pub mod handler;
 pub mod manager;
+pub mod outbound;
 pub mod protocol;
+pub mod resume;
 pub mod session;
 
+pub use events::spawn_event_publisher;
 pub use handler::ws_handler;
 pub use manager::SessionManager;
+pub use outbound::SessionSender;
@@ -0,0 +1,178 @@
+use super::protocol::ServerMessage;
+use crate::{SlowConsumerPolicy, WebSocketConfig};
+use ironfish_core::Telemetry;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::SendTimeoutError, error::TrySendError};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct SessionSender {
+    tx: mpsc::Sender<ServerMessage>,
+    dropped: Arc<AtomicU64>,
+    stalled: CancellationToken,
+    policy: SlowConsumerPolicy,
+    threshold: u64,
+    timeout: Duration,
+    telemetry: Telemetry,
+    connection_id: Option<Uuid>,
+}
+
+impl SessionSender {
+    pub fn new(
+        tx: mpsc::Sender<ServerMessage>,
+        config: &WebSocketConfig,
+        telemetry: Telemetry,
+    ) -> Self {
+        Self {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+            stalled: CancellationToken::new(),
+            policy: config.slow_consumer_policy,
+            threshold: config.slow_consumer_threshold.max(1),
+            timeout: Duration::from_millis(config.slow_consumer_timeout_ms),
+            telemetry,
+            connection_id: None,
+        }
+    }
+
+    pub fn with_connection_id(mut self, connection_id: Uuid) -> Self {
+        self.connection_id = Some(connection_id);
+        self
+    }
+
+    pub async fn send(&self, message: ServerMessage) -> bool {
+        let message = match self.connection_id {
+            Some(ref connection_id) => message.with_request_id(connection_id),
+            None => message,
+        };
+        if is_droppable(&message) {
+            return self.try_send(message);
+        }
+        match self.tx.send_timeout(message, self.timeout).await {
+            Ok(()) => true,
+            Err(SendTimeoutError::Timeout(_)) => {
+                self.telemetry.ws_message_dropped();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.disconnect("timed out delivering a result");
+                false
+            }
+            Err(SendTimeoutError::Closed(_)) => false,
+        }
+    }
+
+    pub fn try_send(&self, message: ServerMessage) -> bool {
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.telemetry.ws_message_dropped();
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if self.policy == SlowConsumerPolicy::Disconnect && dropped >= self.threshold {
+                    self.disconnect("dropped message threshold reached");
+                }
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn stalled(&self) -> CancellationToken {
+        self.stalled.clone()
+    }
+
+    fn disconnect(&self, reason: &str) {
+        if !self.stalled.is_cancelled() {
+            debug!("disconnecting slow websocket consumer: {}", reason);
+            self.telemetry.ws_slow_consumer_disconnected();
+            self.stalled.cancel();
+        }
+    }
+}
+
+fn is_droppable(message: &ServerMessage) -> bool {
+    matches!(
+        message,
+        ServerMessage::AnalysisProgress { .. } | ServerMessage::Event { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress() -> ServerMessage {
+        ServerMessage::AnalysisProgress {
+            analysis_id: uuid::Uuid::new_v4(),
+            current_depth: 1,
+            target_depth: 10,
+            evaluation: None,
+            wdl: None,
+            principal_variations: Vec::new(),
+            nodes_per_second: 0,
+            elapsed_ms: 0,
+        }
+    }
+
+    fn config(policy: SlowConsumerPolicy) -> WebSocketConfig {
+        WebSocketConfig {
+            slow_consumer_policy: policy,
+            slow_consumer_threshold: 3,
+            slow_consumer_timeout_ms: 50,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_dropped_when_queue_is_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let sender = SessionSender::new(
+            tx,
+            &config(SlowConsumerPolicy::DropProgress),
+            Telemetry::default(),
+        );
+        for _ in 0..5 {
+            sender.send(progress()).await;
+        }
+        assert_eq!(sender.dropped(), 4);
+        assert!(!sender.stalled().is_cancelled());
+        rx.recv().await.unwrap();
+        assert!(
+            sender
+                .send(ServerMessage::Pong {
+                    id: "1".to_string()
+                })
+                .await
+        );
+        assert!(
+            !sender
+                .send(ServerMessage::Pong {
+                    id: "2".to_string()
+                })
+                .await
+        );
+        assert!(sender.stalled().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_policy_trips_after_threshold() {
+        let (tx, _rx) = mpsc::channel(1);
+        let sender = SessionSender::new(
+            tx,
+            &config(SlowConsumerPolicy::Disconnect),
+            Telemetry::default(),
+        );
+        for _ in 0..3 {
+            sender.send(progress()).await;
+        }
+        assert!(!sender.stalled().is_cancelled());
+        sender.send(progress()).await;
+        assert!(sender.stalled().is_cancelled());
+    }
+}
@@ -1,22 +1,92 @@
-use ironfish_core::{AnalysisResult, BestMoveResponse, Evaluation, PrincipalVariation};
-use serde::{Deserialize, Serialize};
+use super::events::{CLUSTER_TOPIC, METRICS_TOPIC};
+use crate::ApiState;
+pub use ironfish_core::{ClientMessage, ServerMessage};
+use ironfish_core::{Notation, Perspective, Variant, WsFeatures, WsLimits};
+use serde::Deserialize;
+use serde_json::{json, Value};
 use uuid::Uuid;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum ClientMessage {
+pub const PROTOCOL_VERSION: &str = "1.0";
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const POLICY_VIOLATION: u16 = 1008;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+pub const TRY_AGAIN_LATER: u16 = 1013;
+const CLIENT_MESSAGE_TYPES: &[&str] = &[
+    "hello",
+    "auth",
+    "analyze",
+    "analyze_infinite",
+    "cancel",
+    "bestmove",
+    "subscribe",
+    "unsubscribe",
+    "ping",
+    "resume",
+];
+#[derive(Deserialize)]
+#[serde(
+    remote = "ClientMessage",
+    rename_all = "snake_case",
+    deny_unknown_fields
+)]
+enum ClientFrame {
+    Hello {
+        #[serde(default)]
+        id: Option<String>,
+        version: String,
+    },
     Auth {
         id: String,
         token: String,
     },
     Analyze {
         id: String,
+        #[serde(default)]
         fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
+        #[serde(default)]
+        search_moves: Vec<String>,
         #[serde(default = "default_depth")]
         depth: u8,
-        #[serde(default = "default_multipv")]
-        multipv: u8,
+        #[serde(default)]
+        multipv: Option<u8>,
         movetime: Option<u64>,
+        #[serde(default)]
+        nodes: Option<u64>,
+        #[serde(default)]
+        variant: Variant,
+        #[serde(default)]
+        skill_level: Option<u8>,
+        #[serde(default)]
+        perspective: Perspective,
+        #[serde(default)]
+        notation: Notation,
+        #[serde(default)]
+        include_history: bool,
+    },
+    AnalyzeInfinite {
+        id: String,
+        #[serde(default)]
+        fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
+        #[serde(default)]
+        search_moves: Vec<String>,
+        #[serde(default)]
+        multipv: Option<u8>,
+        #[serde(default)]
+        variant: Variant,
+        #[serde(default)]
+        perspective: Perspective,
+        #[serde(default)]
+        notation: Notation,
+        #[serde(default)]
+        include_history: bool,
     },
     Cancel {
         id: String,
@@ -24,8 +94,17 @@ pub enum ClientMessage {
     },
     Bestmove {
         id: String,
+        #[serde(default)]
         fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
         movetime: Option<u64>,
+        #[serde(default)]
+        nodes: Option<u64>,
+        #[serde(default)]
+        variant: Variant,
     },
     Subscribe {
         id: String,
@@ -38,56 +117,192 @@ pub enum ClientMessage {
     Ping {
         id: String,
     },
+    Resume {
+        id: String,
+        resume_token: String,
+    },
 }
-
 fn default_depth() -> u8 {
     20
 }
-
-fn default_multipv() -> u8 {
-    1
+fn major(version: &str) -> Option<u32> {
+    version.split('.').next()?.trim().parse().ok()
 }
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum ServerMessage {
-    AuthResult {
-        id: String,
-        success: bool,
-        error: Option<String>,
-    },
-    AnalysisProgress {
-        analysis_id: Uuid,
-        current_depth: u8,
-        target_depth: u8,
-        evaluation: Option<Evaluation>,
-        principal_variations: Vec<PrincipalVariation>,
-        nodes_per_second: u64,
-    },
-    AnalysisComplete {
-        id: String,
-        result: AnalysisResult,
-    },
-    AnalysisCancelled {
-        analysis_id: Uuid,
-    },
-    BestmoveResult {
-        id: String,
-        result: BestMoveResponse,
-    },
-    ClusterEvent {
-        event: serde_json::Value,
-    },
-    Subscribed {
-        id: String,
-        topics: Vec<String>,
-    },
-    Error {
-        id: Option<String>,
-        code: u16,
-        message: String,
-    },
-    Pong {
-        id: String,
-    },
+pub fn supports_version(requested: &str) -> bool {
+    match (major(requested), major(PROTOCOL_VERSION)) {
+        (Some(requested), Some(current)) => requested == current || requested + 1 == current,
+        _ => false,
+    }
+}
+pub fn server_hello(state: &ApiState, resume_token: Option<String>) -> ServerMessage {
+    let config = &state.ws_config;
+    ServerMessage::Hello {
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        features: WsFeatures {
+            streaming: true,
+            infinite_analysis: config.max_infinite_analysis_secs > 0,
+            topics: vec![CLUSTER_TOPIC.to_string(), METRICS_TOPIC.to_string()],
+        },
+        limits: WsLimits {
+            max_depth: state.limits().max_depth,
+            max_multipv: state.limits().max_multipv,
+            max_movetime_ms: state.limits().max_movetime_ms,
+            max_concurrent_analyses: config.max_analyses_per_session,
+            max_infinite_analysis_secs: config.max_infinite_analysis_secs,
+            auth_timeout_secs: config.auth_timeout_secs,
+        },
+        resume_token,
+    }
+}
+pub fn parse_client_message(
+    text: &str,
+    max_bytes: usize,
+) -> Result<ClientMessage, Box<ServerMessage>> {
+    if text.len() > max_bytes {
+        return Err(Box::new(message_too_large(text.len(), max_bytes)));
+    }
+    let error = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => return Ok(message),
+        Err(e) => e,
+    };
+    let value = serde_json::from_str::<Value>(text).unwrap_or_default();
+    let field = |name: &str| value.get(name).and_then(|v| v.as_str()).map(String::from);
+    let id = field("id");
+    let kind = field("type");
+    if let Some(kind) = kind.as_deref() {
+        if !CLIENT_MESSAGE_TYPES.contains(&kind) {
+            return Err(Box::new(ServerMessage::Error {
+                id,
+                code: 400,
+                error: "unknown_message_type".to_string(),
+                message: format!(
+                    "unknown message type '{}', expected one of: {}",
+                    kind,
+                    CLIENT_MESSAGE_TYPES.join(", ")
+                ),
+                details: None,
+                request_id: None,
+            }));
+        }
+    }
+    let (offending, reason) = match kind.as_deref().and_then(|kind| locate(&value, kind)) {
+        Some(located) => located,
+        None => {
+            let reason = error.to_string();
+            let reason = match reason.rsplit_once(" at line ") {
+                Some((reason, _)) if error.line() > 0 => reason.to_string(),
+                _ => reason,
+            };
+            (missing_field(&reason), reason)
+        }
+    };
+    let mut details = serde_json::Map::new();
+    if let Some(kind) = kind {
+        details.insert("type".to_string(), kind.into());
+    }
+    if let Some(ref offending) = offending {
+        details.insert("field".to_string(), offending.clone().into());
+    }
+    if let Some((_, expected)) = reason.split_once("expected ") {
+        details.insert("expected".to_string(), expected.into());
+    }
+    Err(Box::new(ServerMessage::Error {
+        id,
+        code: 400,
+        error: "invalid_message".to_string(),
+        message: match offending {
+            Some(offending) => format!("invalid message: '{}': {}", offending, reason),
+            None => format!("invalid message: {}", reason),
+        },
+        details: (!details.is_empty()).then_some(Value::Object(details)),
+        request_id: None,
+    }))
+}
+pub fn message_too_large(size: usize, max_bytes: usize) -> ServerMessage {
+    ServerMessage::Error {
+        id: None,
+        code: 413,
+        error: "message_too_large".to_string(),
+        message: format!("message is {} bytes, the limit is {}", size, max_bytes),
+        details: Some(json!({ "limit": max_bytes })),
+        request_id: None,
+    }
+}
+fn missing_field(reason: &str) -> Option<String> {
+    let rest = reason.strip_prefix("missing field `")?;
+    rest.split('`').next().map(String::from)
+}
+fn locate(value: &Value, kind: &str) -> Option<(Option<String>, String)> {
+    let mut fields = value.as_object()?.clone();
+    fields.remove("type");
+    let frame = json!({ kind: fields });
+    let mut track = serde_path_to_error::Track::new();
+    let error = ClientFrame::deserialize(serde_path_to_error::Deserializer::new(frame, &mut track))
+        .err()?;
+    let reason = error.to_string();
+    let field = match track.path().to_string().split_once('.') {
+        Some((_, field)) => Some(field.to_string()),
+        None => missing_field(&reason),
+    };
+    Some((field, reason))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_supports_current_and_previous_major() {
+        assert!(supports_version(PROTOCOL_VERSION));
+        assert!(supports_version("1.7"));
+        assert!(supports_version("0.3"));
+        assert!(supports_version("1"));
+        assert!(!supports_version("2.0"));
+        assert!(!supports_version("one"));
+    }
+    #[test]
+    fn test_unknown_type_is_reported_separately() {
+        let unknown =
+            parse_client_message(r#"{"type": "frobnicate", "id": "x"}"#, 1024).unwrap_err();
+        assert!(matches!(
+            *unknown,
+            ServerMessage::Error { id: Some(ref id), ref error, .. }
+                if id == "x" && error == "unknown_message_type"
+        ));
+        let invalid = parse_client_message(r#"{"type": "cancel", "id": "y"}"#, 1024).unwrap_err();
+        assert!(matches!(
+            *invalid,
+            ServerMessage::Error { id: Some(ref id), ref error, .. }
+                if id == "y" && error == "invalid_message"
+        ));
+        assert!(matches!(
+            parse_client_message(r#"{"type": "hello", "version": "1.0"}"#, 1024),
+            Ok(ClientMessage::Hello { id: None, .. })
+        ));
+    }
+    #[test]
+    fn test_invalid_fields_carry_details() {
+        let details = |text: &str| match *parse_client_message(text, 1024).unwrap_err() {
+            ServerMessage::Error { details, .. } => details.unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+        let wrong_type = details(
+            r#"{"type": "analyze", "id": "a", "fen": "8/8/8/8/8/8/8/K1k5 w - - 0 1", "depth": "20"}"#,
+        );
+        assert_eq!(wrong_type["type"], "analyze");
+        assert_eq!(wrong_type["field"], "depth");
+        assert_eq!(wrong_type["expected"], "u8");
+        let unknown = details(r#"{"type": "ping", "dpeth": 3}"#);
+        assert_eq!(unknown["field"], "dpeth");
+        let missing = details(r#"{"type": "cancel", "id": "y"}"#);
+        assert_eq!(missing["field"], "analysis_id");
+        let nested =
+            details(r#"{"type": "analyze", "id": "b", "startpos": true, "moves": ["e2e4", 5]}"#);
+        assert_eq!(nested["field"], "moves[1]");
+        assert_eq!(nested["expected"], "a string");
+        let oversized = parse_client_message(&"x".repeat(2048), 1024).unwrap_err();
+        assert!(matches!(
+            *oversized,
+            ServerMessage::Error { code: 413, ref error, .. } if error == "message_too_large"
+        ));
+    }
 }
@@ -0,0 +1,140 @@
+use super::outbound::SessionSender;
+use super::protocol::ServerMessage;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+pub type ActiveAnalyses = Arc<Mutex<HashMap<Uuid, CancellationToken>>>;
+
+struct OutputState {
+    tx: Option<SessionSender>,
+    progress: HashMap<Uuid, ServerMessage>,
+    pending: Vec<ServerMessage>,
+}
+
+pub struct SessionOutput {
+    state: Mutex<OutputState>,
+}
+
+impl SessionOutput {
+    pub fn new(tx: SessionSender) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(OutputState {
+                tx: Some(tx),
+                progress: HashMap::new(),
+                pending: Vec::new(),
+            }),
+        })
+    }
+
+    pub async fn send_progress(&self, analysis_id: Uuid, message: ServerMessage) {
+        let tx = {
+            let mut state = self.state.lock().await;
+            state.progress.insert(analysis_id, message.clone());
+            state.tx.clone()
+        };
+        if let Some(tx) = tx {
+            tx.send(message).await;
+        }
+    }
+
+    pub async fn send_result(&self, analysis_id: Uuid, message: ServerMessage) {
+        let tx = {
+            let mut state = self.state.lock().await;
+            state.progress.remove(&analysis_id);
+            match state.tx.clone() {
+                Some(tx) => tx,
+                None => {
+                    state.pending.push(message);
+                    return;
+                }
+            }
+        };
+        if !tx.send(message.clone()).await {
+            self.state.lock().await.pending.push(message);
+        }
+    }
+
+    pub async fn detach(&self) {
+        self.state.lock().await.tx = None;
+    }
+
+    pub async fn attach(&self, tx: SessionSender) -> Vec<ServerMessage> {
+        let mut state = self.state.lock().await;
+        state.tx = Some(tx);
+        let mut replay: Vec<ServerMessage> = state.progress.values().cloned().collect();
+        replay.append(&mut state.pending);
+        replay
+    }
+}
+
+pub struct DetachedSession {
+    pub token_id: Option<Uuid>,
+    pub analyses: ActiveAnalyses,
+    pub output: Arc<SessionOutput>,
+    pub(crate) expiry: Option<AbortHandle>,
+}
+
+impl DetachedSession {
+    pub fn new(
+        token_id: Option<Uuid>,
+        analyses: ActiveAnalyses,
+        output: Arc<SessionOutput>,
+    ) -> Self {
+        Self {
+            token_id,
+            analyses,
+            output,
+            expiry: None,
+        }
+    }
+
+    pub async fn cancel(&self) {
+        for (_, cancel) in self.analyses.lock().await.drain() {
+            cancel.cancel();
+        }
+    }
+}
+
+pub fn resume_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WebSocketConfig;
+    use ironfish_core::Telemetry;
+    use tokio::sync::mpsc;
+
+    fn sender() -> (SessionSender, mpsc::Receiver<ServerMessage>) {
+        let (tx, rx) = mpsc::channel(4);
+        let sender = SessionSender::new(tx, &WebSocketConfig::default(), Telemetry::default());
+        (sender, rx)
+    }
+
+    #[tokio::test]
+    async fn test_result_is_buffered_when_the_send_fails_before_detach() {
+        let (tx, rx) = sender();
+        let output = SessionOutput::new(tx);
+        drop(rx);
+        let analysis_id = Uuid::new_v4();
+        output
+            .send_result(
+                analysis_id,
+                ServerMessage::AnalysisCancelled { analysis_id },
+            )
+            .await;
+        output.detach().await;
+        let (tx, _rx) = sender();
+        let replay = output.attach(tx).await;
+        assert_eq!(replay.len(), 1);
+        assert!(matches!(
+            replay[0],
+            ServerMessage::AnalysisCancelled { analysis_id: id } if id == analysis_id
+        ));
+    }
+}
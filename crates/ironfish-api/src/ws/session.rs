@@ -1,18 +1,38 @@
-use super::protocol::{ClientMessage, ServerMessage};
+use super::events::{CLUSTER_TOPIC, METRICS_TOPIC};
+use super::outbound::SessionSender;
+use super::protocol::{
+    supports_version, ClientMessage, ServerMessage, POLICY_VIOLATION, PROTOCOL_ERROR,
+    PROTOCOL_VERSION, TRY_AGAIN_LATER,
+};
+use super::resume::{resume_token, ActiveAnalyses, DetachedSession, SessionOutput};
 use crate::ApiState;
-use ironfish_core::{AnalysisRequest, BestMoveRequest, TokenStore};
+use ironfish_core::{
+    ws_request_id, AnalysisRequest, BestMoveRequest, ChessPosition, Error, StopReason,
+    TokenContext, SCOPE_ANALYZE, SCOPE_BESTMOVE, SCOPE_CLUSTER_READ, SCOPE_WS,
+};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use uuid::Uuid;
 
 pub struct WsSession {
     pub session_id: Uuid,
     pub authenticated: bool,
-    pub tx: mpsc::Sender<ServerMessage>,
-    pub active_analyses: Arc<Mutex<HashMap<Uuid, CancellationToken>>>,
+    pub token: TokenContext,
+    pub token_hash: Option<String>,
+    pub rejected: Option<(u16, &'static str)>,
+    pub tx: SessionSender,
+    pub active_analyses: ActiveAnalyses,
+    pub output: Arc<SessionOutput>,
+    pub resume_token: Option<String>,
     pub subscriptions: HashSet<String>,
+    pub relayed: bool,
+    relay_request: Option<Uuid>,
+    revalidation_failures: u32,
     state: Arc<ApiState>,
     max_analyses: usize,
 }
@@ -20,23 +40,63 @@ pub struct WsSession {
 impl WsSession {
     pub fn new(
         session_id: Uuid,
-        tx: mpsc::Sender<ServerMessage>,
+        tx: SessionSender,
         state: Arc<ApiState>,
         max_analyses: usize,
     ) -> Self {
         Self {
             session_id,
             authenticated: false,
+            token: TokenContext::anonymous(),
+            token_hash: None,
+            rejected: None,
+            output: SessionOutput::new(tx.clone()),
             tx,
             active_analyses: Arc::new(Mutex::new(HashMap::new())),
+            resume_token: None,
             subscriptions: HashSet::new(),
+            relayed: false,
+            relay_request: None,
+            revalidation_failures: 0,
             state,
             max_analyses,
         }
     }
 
     pub async fn handle_message(&mut self, msg: ClientMessage) {
+        let span = match extract_id(&msg) {
+            Some(ref id) => tracing::info_span!(
+                "ws_message",
+                request_id = %ws_request_id(&self.session_id, id)
+            ),
+            None => tracing::info_span!("ws_message", session_id = %self.session_id),
+        };
+        self.dispatch(msg).instrument(span).await
+    }
+
+    async fn dispatch(&mut self, msg: ClientMessage) {
+        if self.authenticated && starts_search(&msg) && !self.token_is_valid().await {
+            self.revoke().await;
+            return;
+        }
+        if self.authenticated {
+            if let Some(scope) = self.missing_scope(&msg) {
+                let _ = self
+                    .tx
+                    .send(ServerMessage::Error {
+                        id: extract_id(&msg),
+                        code: 403,
+                        error: "missing_scope".to_string(),
+                        message: format!("missing scope: {}", scope),
+                        details: None,
+                        request_id: None,
+                    })
+                    .await;
+                return;
+            }
+        }
         match msg {
+            ClientMessage::Hello { id, version } => self.handle_hello(id, version).await,
             ClientMessage::Auth { id, token } => self.handle_auth(id, token).await,
             ClientMessage::Ping { id } => {
                 let _ = self.tx.send(ServerMessage::Pong { id }).await;
@@ -48,24 +108,122 @@ impl WsSession {
                     .send(ServerMessage::Error {
                         id,
                         code: 401,
+                        error: "unauthorized".to_string(),
                         message: "not authenticated".to_string(),
+                        details: None,
+                        request_id: None,
                     })
                     .await;
             }
             ClientMessage::Analyze {
                 id,
                 fen,
+                startpos,
+                moves,
+                search_moves,
                 depth,
                 multipv,
                 movetime,
+                nodes,
+                variant,
+                skill_level,
+                perspective,
+                notation,
+                include_history,
+            } => {
+                let multipv =
+                    multipv.unwrap_or_else(|| self.state.limits().default_multipv(&search_moves));
+                if let Err(e) = self
+                    .state
+                    .limits()
+                    .check(depth.into(), multipv.into(), movetime)
+                {
+                    self.send_invalid_request(id, e).await;
+                    return;
+                }
+                let fen = if startpos {
+                    ChessPosition::starting().fen
+                } else {
+                    fen
+                };
+                let mut request = AnalysisRequest::new(fen)
+                    .with_depth(depth)
+                    .with_multipv(multipv)
+                    .with_variant(variant)
+                    .with_perspective(perspective)
+                    .with_notation(notation)
+                    .with_history(include_history)
+                    .with_moves(moves)
+                    .with_search_moves(search_moves);
+                if let Some(mt) = movetime {
+                    request = request.with_movetime(mt);
+                }
+                if let Some(n) = nodes {
+                    request = request.with_nodes(n);
+                }
+                if let Some(level) = skill_level {
+                    request = request.with_skill_level(level);
+                }
+                self.handle_analyze(id, request).await;
+            }
+            ClientMessage::AnalyzeInfinite {
+                id,
+                fen,
+                startpos,
+                moves,
+                search_moves,
+                multipv,
+                variant,
+                perspective,
+                notation,
+                include_history,
             } => {
-                self.handle_analyze(id, fen, depth, multipv, movetime).await;
+                let multipv =
+                    multipv.unwrap_or_else(|| self.state.limits().default_multipv(&search_moves));
+                if let Err(e) = self.state.limits().check_multipv(multipv.into()) {
+                    self.send_invalid_request(id, e).await;
+                    return;
+                }
+                let fen = if startpos {
+                    ChessPosition::starting().fen
+                } else {
+                    fen
+                };
+                let request = AnalysisRequest::infinite(fen)
+                    .with_multipv(multipv)
+                    .with_variant(variant)
+                    .with_perspective(perspective)
+                    .with_notation(notation)
+                    .with_history(include_history)
+                    .with_moves(moves)
+                    .with_search_moves(search_moves);
+                self.handle_analyze(id, request).await;
             }
             ClientMessage::Cancel { id, analysis_id } => {
                 self.handle_cancel(id, analysis_id).await;
             }
-            ClientMessage::Bestmove { id, fen, movetime } => {
-                self.handle_bestmove(id, fen, movetime).await;
+            ClientMessage::Bestmove {
+                id,
+                fen,
+                startpos,
+                moves,
+                movetime,
+                nodes,
+                variant,
+            } => {
+                let fen = if startpos {
+                    ChessPosition::starting().fen
+                } else {
+                    fen
+                };
+                let mut request = BestMoveRequest::new(fen);
+                request.moves = moves;
+                if let Some(mt) = movetime {
+                    request.movetime = Some(mt);
+                }
+                request.nodes = nodes;
+                request.variant = variant;
+                self.handle_bestmove(id, request).await;
             }
             ClientMessage::Subscribe { id, topics } => {
                 self.handle_subscribe(id, topics).await;
@@ -73,21 +231,93 @@ impl WsSession {
             ClientMessage::Unsubscribe { id, topics } => {
                 self.handle_unsubscribe(id, topics).await;
             }
+            ClientMessage::Resume { id, resume_token } => {
+                self.handle_resume(id, resume_token).await;
+            }
         }
     }
 
+    pub fn issue_resume_token(&mut self) -> Option<String> {
+        if self.state.ws_config.resume_grace_secs == 0 || self.relayed {
+            return None;
+        }
+        Some(self.resume_token.get_or_insert_with(resume_token).clone())
+    }
+
+    async fn handle_hello(&mut self, id: Option<String>, version: String) {
+        if supports_version(&version) {
+            return;
+        }
+        let _ = self
+            .tx
+            .send(ServerMessage::Error {
+                id,
+                code: 400,
+                error: "unsupported_protocol_version".to_string(),
+                message: format!(
+                    "protocol version {} is not supported, server speaks {}",
+                    version, PROTOCOL_VERSION
+                ),
+                details: None,
+                request_id: None,
+            })
+            .await;
+        self.rejected = Some((PROTOCOL_ERROR, "unsupported protocol version"));
+    }
+
     async fn handle_auth(&mut self, id: String, token: String) {
+        if let Some(claims) = self.state.token_manager.verify_service_token(&token) {
+            self.authenticated = true;
+            self.relayed = true;
+            self.relay_request = Some(claims.request_id);
+            self.token = TokenContext {
+                id: claims.token_id,
+                ..TokenContext::service(&claims.node_id)
+            };
+            let _ = self
+                .tx
+                .send(ServerMessage::AuthResult {
+                    id,
+                    success: true,
+                    error: None,
+                    resume_token: None,
+                })
+                .await;
+            return;
+        }
         let raw = token.strip_prefix("iff_").unwrap_or(&token);
         let hash = self.state.token_manager.hash_token(raw);
         match self.state.token_store.get_by_hash(&hash).await {
             Ok(Some(api_token)) if api_token.is_valid() => {
+                if let Err(reason) = self
+                    .state
+                    .ws_sessions
+                    .authorize(&self.session_id, api_token.id)
+                    .await
+                {
+                    self.rejected = Some((TRY_AGAIN_LATER, reason));
+                    let _ = self
+                        .tx
+                        .send(ServerMessage::AuthResult {
+                            id,
+                            success: false,
+                            error: Some(reason.to_string()),
+                            resume_token: None,
+                        })
+                        .await;
+                    return;
+                }
                 self.authenticated = true;
+                self.token = TokenContext::from(&api_token);
+                self.token_hash = Some(api_token.token_hash.clone());
+                let resume_token = self.issue_resume_token();
                 let _ = self
                     .tx
                     .send(ServerMessage::AuthResult {
                         id,
                         success: true,
                         error: None,
+                        resume_token,
                     })
                     .await;
             }
@@ -98,41 +328,115 @@ impl WsSession {
                         id,
                         success: false,
                         error: Some("invalid or expired token".to_string()),
+                        resume_token: None,
                     })
                     .await;
             }
         }
     }
 
-    async fn handle_analyze(
-        &mut self,
-        id: String,
-        fen: String,
-        depth: u8,
-        multipv: u8,
-        movetime: Option<u64>,
-    ) {
+    fn missing_scope(&self, msg: &ClientMessage) -> Option<&'static str> {
+        let required: &[&'static str] = match msg {
+            ClientMessage::Hello { .. }
+            | ClientMessage::Auth { .. }
+            | ClientMessage::Ping { .. } => &[],
+            ClientMessage::Analyze { .. } | ClientMessage::AnalyzeInfinite { .. } => {
+                &[SCOPE_WS, SCOPE_ANALYZE]
+            }
+            ClientMessage::Bestmove { .. } => &[SCOPE_WS, SCOPE_BESTMOVE],
+            ClientMessage::Subscribe { topics, .. }
+                if topics
+                    .iter()
+                    .any(|topic| topic == CLUSTER_TOPIC || topic == METRICS_TOPIC) =>
+            {
+                &[SCOPE_WS, SCOPE_CLUSTER_READ]
+            }
+            _ => &[SCOPE_WS],
+        };
+        required
+            .iter()
+            .copied()
+            .find(|scope| !self.token.has_scope(scope))
+    }
+
+    async fn handle_analyze(&mut self, id: String, request: AnalysisRequest) {
+        if self.relayed && self.relay_request.take().map(|r| r.to_string()) != Some(id.clone()) {
+            let _ = self
+                .tx
+                .send(ServerMessage::Error {
+                    id: Some(id),
+                    code: 403,
+                    error: "relay_request_mismatch".to_string(),
+                    message: "service token does not cover this analysis".to_string(),
+                    details: None,
+                    request_id: None,
+                })
+                .await;
+            return;
+        }
+        if let Err(e) = self.state.ensure_accepting() {
+            let _ = self.tx.send(ServerMessage::error(Some(id), &e)).await;
+            return;
+        }
+        let request = self.token.clamp(request);
+        if let Err(e @ (Error::InvalidMove { .. } | Error::InvalidSearchMoves(_))) =
+            request.validate()
+        {
+            self.send_invalid_request(id, e).await;
+            return;
+        }
+        let mut detached_analyses = 0;
+        if let Some(token_id) = self.token.id {
+            let (sessions, analyses) = self.state.ws_sessions.token_usage(token_id).await;
+            if sessions > self.state.ws_sessions.max_sessions_per_token() {
+                let _ = self
+                    .tx
+                    .send(ServerMessage::Error {
+                        id: Some(id),
+                        code: 429,
+                        error: "too_many_sessions".to_string(),
+                        message: "session limit reached for token; resume a detached session first"
+                            .to_string(),
+                        details: None,
+                        request_id: None,
+                    })
+                    .await;
+                return;
+            }
+            detached_analyses = analyses;
+        }
         {
             let analyses = self.active_analyses.lock().await;
-            if analyses.len() >= self.max_analyses {
+            if analyses.len() + detached_analyses >= self.max_analyses {
                 let _ = self
                     .tx
                     .send(ServerMessage::Error {
                         id: Some(id),
                         code: 429,
+                        error: "too_many_analyses".to_string(),
                         message: "too many concurrent analyses".to_string(),
+                        details: None,
+                        request_id: None,
                     })
                     .await;
                 return;
             }
         }
+        let permit = match self.state.admit() {
+            Ok(permit) => permit,
+            Err(e) => {
+                let _ = self.tx.send(ServerMessage::error(Some(id), &e)).await;
+                return;
+            }
+        };
+        let reservation = match self.state.reserve_node_budget(&self.token, &request).await {
+            Ok(reservation) => reservation,
+            Err(e) => {
+                let _ = self.tx.send(ServerMessage::error(Some(id), &e)).await;
+                return;
+            }
+        };
 
-        let mut request = AnalysisRequest::new(fen)
-            .with_depth(depth)
-            .with_multipv(multipv);
-        if let Some(mt) = movetime {
-            request = request.with_movetime(mt);
-        }
         let analysis_id = request.id;
         let cancel = CancellationToken::new();
         self.active_analyses
@@ -140,61 +444,102 @@ impl WsSession {
             .await
             .insert(analysis_id, cancel.clone());
 
-        let tx = self.tx.clone();
-        let analysis = self.state.analysis.clone();
+        let output = self.output.clone();
+        let state = self.state.clone();
+        let relayed = self.relayed;
+        let analyses = self.state.analyses.clone();
+        analyses.begin(analysis_id).await;
+        let history = if self.relayed {
+            None
+        } else {
+            self.state
+                .history_recorder(self.token.id)
+                .map(|recorder| recorder.with_node_budget(reservation))
+        };
+        let token_id = self.token.id;
         let active_analyses = self.active_analyses.clone();
-        tokio::spawn(async move {
-            let (progress_tx, mut progress_rx) =
-                mpsc::channel::<ironfish_core::AnalysisProgress>(32);
-
-            let tx_progress = tx.clone();
-            let progress_task = tokio::spawn(async move {
-                while let Some(progress) = progress_rx.recv().await {
-                    let _ = tx_progress
-                        .send(ServerMessage::AnalysisProgress {
-                            analysis_id: progress.id,
-                            current_depth: progress.current_depth,
-                            target_depth: progress.target_depth,
-                            evaluation: progress.evaluation,
-                            principal_variations: progress.principal_variations,
-                            nodes_per_second: progress.nodes_per_second,
-                        })
-                        .await;
-                }
-            });
+        let max_duration = Duration::from_secs(self.state.ws_config.max_infinite_analysis_secs);
+        let session_id = self.session_id;
+        tokio::spawn(
+            async move {
+                let _permit = permit;
+                let timed_out = Arc::new(AtomicBool::new(false));
+                let deadline = request.infinite.then(|| {
+                    let (cancel, timed_out) = (cancel.clone(), timed_out.clone());
+                    tokio::spawn(async move {
+                        tokio::time::sleep(max_duration).await;
+                        if !cancel.is_cancelled() {
+                            timed_out.store(true, Ordering::SeqCst);
+                            cancel.cancel();
+                        }
+                    })
+                });
 
-            let result = analysis
-                .analyze_streaming(request, progress_tx, cancel)
-                .await;
-            let _ = progress_task.await;
+                let (progress_tx, mut progress_rx) =
+                    mpsc::channel::<ironfish_core::AnalysisProgress>(32);
 
-            active_analyses.lock().await.remove(&analysis_id);
+                let progress_output = output.clone();
+                let progress_task = tokio::spawn(async move {
+                    while let Some(progress) = progress_rx.recv().await {
+                        progress_output
+                            .send_progress(
+                                progress.id,
+                                ServerMessage::AnalysisProgress {
+                                    analysis_id: progress.id,
+                                    current_depth: progress.current_depth,
+                                    target_depth: progress.target_depth,
+                                    evaluation: progress.evaluation,
+                                    wdl: progress.wdl,
+                                    principal_variations: progress.principal_variations,
+                                    nodes_per_second: progress.nodes_per_second,
+                                    elapsed_ms: progress.elapsed_ms,
+                                },
+                            )
+                            .await;
+                    }
+                });
 
-            match result {
-                Ok(analysis_result) => {
-                    let _ = tx
-                        .send(ServerMessage::AnalysisComplete {
-                            id,
-                            result: analysis_result,
-                        })
-                        .await;
+                let mut result = if relayed {
+                    state
+                        .analysis
+                        .analyze_streaming(request, progress_tx, cancel)
+                        .await
+                } else {
+                    state
+                        .analyze_streaming_routed(request, token_id, progress_tx, cancel)
+                        .await
+                };
+                if let Some(deadline) = deadline {
+                    deadline.abort();
                 }
-                Err(ironfish_core::Error::AnalysisCancelled) => {
-                    let _ = tx
-                        .send(ServerMessage::AnalysisCancelled { analysis_id })
-                        .await;
+                if let (true, Ok(result)) = (timed_out.load(Ordering::SeqCst), result.as_mut()) {
+                    result.stopped_by = StopReason::Time;
                 }
-                Err(e) => {
-                    let _ = tx
-                        .send(ServerMessage::Error {
-                            id: Some(id),
-                            code: 500,
-                            message: e.to_string(),
-                        })
-                        .await;
+                let _ = progress_task.await;
+                analyses.finish(analysis_id, &result).await;
+                if let Some(history) = history {
+                    history.record(&result).await;
                 }
+
+                active_analyses.lock().await.remove(&analysis_id);
+
+                let message = match result {
+                    Ok(analysis_result) => ServerMessage::AnalysisComplete {
+                        id,
+                        result: Box::new(analysis_result),
+                        request_id: None,
+                    },
+                    Err(Error::AnalysisCancelled) => {
+                        ServerMessage::AnalysisCancelled { analysis_id }
+                    }
+                    Err(e) => ServerMessage::error(Some(id), &e),
+                };
+                output
+                    .send_result(analysis_id, message.with_request_id(&session_id))
+                    .await;
             }
-        });
+            .in_current_span(),
+        );
     }
 
     async fn handle_cancel(&mut self, _id: String, analysis_id: Uuid) {
@@ -203,35 +548,58 @@ impl WsSession {
         }
     }
 
-    async fn handle_bestmove(&mut self, id: String, fen: String, movetime: Option<u64>) {
-        let mut request = BestMoveRequest::new(fen);
-        if let Some(mt) = movetime {
-            request.movetime = Some(mt);
+    async fn handle_bestmove(&mut self, id: String, request: BestMoveRequest) {
+        if let Err(e) = self.state.ensure_accepting() {
+            let _ = self.tx.send(ServerMessage::error(Some(id), &e)).await;
+            return;
         }
+        if let Err(e @ Error::InvalidMove { .. }) = request.validate() {
+            self.send_invalid_request(id, e).await;
+            return;
+        }
+        let permit = match self.state.admit() {
+            Ok(permit) => permit,
+            Err(e) => {
+                let _ = self.tx.send(ServerMessage::error(Some(id), &e)).await;
+                return;
+            }
+        };
         let tx = self.tx.clone();
         let analysis = self.state.analysis.clone();
-        tokio::spawn(async move {
-            match analysis.best_move(request).await {
-                Ok(result) => {
-                    let _ = tx.send(ServerMessage::BestmoveResult { id, result }).await;
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(ServerMessage::Error {
-                            id: Some(id),
-                            code: 500,
-                            message: e.to_string(),
-                        })
-                        .await;
+        tokio::spawn(
+            async move {
+                let _permit = permit;
+                match analysis.best_move(request).await {
+                    Ok(result) => {
+                        let _ = tx
+                            .send(ServerMessage::BestmoveResult {
+                                id,
+                                result,
+                                request_id: None,
+                            })
+                            .await;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(ServerMessage::error(Some(id), &e)).await;
+                    }
                 }
             }
-        });
+            .in_current_span(),
+        );
+    }
+
+    async fn send_invalid_request(&self, id: String, error: Error) {
+        let _ = self.tx.send(ServerMessage::error(Some(id), &error)).await;
     }
 
     async fn handle_subscribe(&mut self, id: String, topics: Vec<String>) {
         for topic in &topics {
             self.subscriptions.insert(topic.clone());
         }
+        self.state
+            .ws_sessions
+            .update_subscriptions(&self.session_id, &self.subscriptions)
+            .await;
         let _ = self.tx.send(ServerMessage::Subscribed { id, topics }).await;
     }
 
@@ -239,6 +607,125 @@ impl WsSession {
         for topic in &topics {
             self.subscriptions.remove(topic);
         }
+        self.state
+            .ws_sessions
+            .update_subscriptions(&self.session_id, &self.subscriptions)
+            .await;
+    }
+
+    async fn handle_resume(&mut self, id: String, resume_token: String) {
+        if !self.active_analyses.lock().await.is_empty() {
+            let _ = self
+                .tx
+                .send(ServerMessage::Error {
+                    id: Some(id),
+                    code: 409,
+                    error: "resume_conflict".to_string(),
+                    message: "resume must come before new analyses".to_string(),
+                    details: None,
+                    request_id: None,
+                })
+                .await;
+            return;
+        }
+        let Some(detached) = self
+            .state
+            .ws_sessions
+            .resume(&resume_token, self.token.id)
+            .await
+        else {
+            let _ = self
+                .tx
+                .send(ServerMessage::Error {
+                    id: Some(id),
+                    code: 404,
+                    error: "resume_not_found".to_string(),
+                    message: "unknown or expired resume token".to_string(),
+                    details: None,
+                    request_id: None,
+                })
+                .await;
+            return;
+        };
+        self.active_analyses = detached.analyses;
+        self.output = detached.output;
+        let mut analysis_ids: Vec<Uuid> =
+            self.active_analyses.lock().await.keys().copied().collect();
+        analysis_ids.sort();
+        let replay = self.output.attach(self.tx.clone()).await;
+        let _ = self
+            .tx
+            .send(ServerMessage::Resumed { id, analysis_ids })
+            .await;
+        for message in replay {
+            let _ = self.tx.send(message).await;
+        }
+    }
+
+    pub async fn detach(&mut self) -> bool {
+        let grace = self.state.ws_config.resume_grace_secs;
+        let Some(resume_token) = self.resume_token.clone() else {
+            return false;
+        };
+        if grace == 0 || self.active_analyses.lock().await.is_empty() {
+            return false;
+        }
+        self.output.detach().await;
+        self.state
+            .ws_sessions
+            .detach(
+                resume_token,
+                DetachedSession::new(
+                    self.token.id,
+                    self.active_analyses.clone(),
+                    self.output.clone(),
+                ),
+                Duration::from_secs(grace),
+            )
+            .await;
+        true
+    }
+
+    pub async fn token_is_valid(&mut self) -> bool {
+        let Some(ref hash) = self.token_hash else {
+            return true;
+        };
+        match self.state.token_store.get_by_hash(hash).await {
+            Ok(Some(token)) => {
+                self.revalidation_failures = 0;
+                token.is_valid()
+            }
+            Ok(None) => false,
+            Err(e) => {
+                self.revalidation_failures += 1;
+                let limit = self.state.ws_config.token_revalidate_max_failures;
+                tracing::warn!(
+                    session_id = %self.session_id,
+                    "token revalidation failed ({} of {}): {}",
+                    self.revalidation_failures,
+                    limit,
+                    e
+                );
+                self.revalidation_failures < limit
+            }
+        }
+    }
+
+    pub async fn revoke(&mut self) {
+        let _ = self
+            .tx
+            .send(ServerMessage::Error {
+                id: None,
+                code: 401,
+                error: "token_revoked".to_string(),
+                message: "token revoked, expired or could not be verified".to_string(),
+                details: None,
+                request_id: None,
+            })
+            .await;
+        self.resume_token = None;
+        self.cancel_all().await;
+        self.rejected = Some((POLICY_VIOLATION, "token revoked"));
     }
 
     pub async fn cancel_all(&mut self) {
@@ -248,14 +735,26 @@ impl WsSession {
     }
 }
 
+fn starts_search(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::Analyze { .. }
+            | ClientMessage::AnalyzeInfinite { .. }
+            | ClientMessage::Bestmove { .. }
+    )
+}
+
 fn extract_id(msg: &ClientMessage) -> Option<String> {
     match msg {
+        ClientMessage::Hello { id, .. } => id.clone(),
         ClientMessage::Auth { id, .. }
         | ClientMessage::Analyze { id, .. }
+        | ClientMessage::AnalyzeInfinite { id, .. }
         | ClientMessage::Cancel { id, .. }
         | ClientMessage::Bestmove { id, .. }
         | ClientMessage::Subscribe { id, .. }
         | ClientMessage::Unsubscribe { id, .. }
+        | ClientMessage::Resume { id, .. }
         | ClientMessage::Ping { id } => Some(id.clone()),
     }
 }
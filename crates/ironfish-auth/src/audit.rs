@@ -0,0 +1,353 @@
+use async_trait::async_trait;
+use axum::extract::ConnectInfo;
+use axum::http::{Extensions, HeaderMap};
+use chrono::{Duration, Utc};
+use ironfish_core::{AuditEntry, AuditLog, AuditPage, AuditQuery, AuditRetention, Error, Result};
+use ring::digest;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpAddr>>);
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(Arc::new(proxies))
+    }
+    pub fn with(&self, proxy: IpAddr) -> Self {
+        let mut proxies = self.0.as_ref().clone();
+        proxies.push(proxy);
+        Self::new(proxies)
+    }
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+pub fn admin_actor(admin_key: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, admin_key.as_bytes());
+    let hex: String = hash.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("admin:{}", hex)
+}
+pub fn source_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<String> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    let peer = peer.ip();
+    let Some(trusted) = extensions
+        .get::<TrustedProxies>()
+        .filter(|trusted| trusted.contains(&peer))
+    else {
+        return Some(peer.to_string());
+    };
+    let hops: Vec<&str> = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect();
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !trusted.contains(&ip) {
+            break;
+        }
+    }
+    Some(client.to_string())
+}
+#[derive(Clone)]
+pub struct SledAuditLog {
+    db: Arc<sled::Db>,
+    entries: sled::Tree,
+    retention: AuditRetention,
+    file: Option<Arc<Mutex<File>>>,
+}
+impl SledAuditLog {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    pub fn in_memory() -> Result<Self> {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    fn from_db(db: sled::Db) -> Result<Self> {
+        let entries = db
+            .open_tree("audit")
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(Self {
+            db: Arc::new(db),
+            entries,
+            retention: AuditRetention::default(),
+            file: None,
+        })
+    }
+    pub fn with_retention(mut self, retention: AuditRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.file = Some(Arc::new(Mutex::new(file)));
+        Ok(self)
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    fn deserialize_entry(data: &[u8]) -> Result<AuditEntry> {
+        serde_json::from_slice(data).map_err(Error::Serialization)
+    }
+}
+#[async_trait]
+impl AuditLog for SledAuditLog {
+    async fn record(&self, mut entry: AuditEntry) -> Result<()> {
+        entry.seq = self
+            .db
+            .generate_id()
+            .map_err(|e| Error::Storage(e.to_string()))?
+            + 1;
+        let data = serde_json::to_vec(&entry).map_err(Error::Serialization)?;
+        self.entries
+            .insert(entry.seq.to_be_bytes(), data.as_slice())
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        if let Some(ref file) = self.file {
+            let mut file = file.lock().unwrap();
+            file.write_all(&data)
+                .and_then(|_| file.write_all(b"\n"))
+                .map_err(|e| Error::Storage(e.to_string()))?;
+        }
+        Ok(())
+    }
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let start = query.after.map(|a| a.saturating_add(1)).unwrap_or(0);
+        let mut entries = Vec::new();
+        let mut has_more = false;
+        for result in self.entries.range(start.to_be_bytes()..) {
+            let (_, data) = result.map_err(|e| Error::Storage(e.to_string()))?;
+            let entry = Self::deserialize_entry(&data)?;
+            if query.since.is_some_and(|since| entry.timestamp < since) {
+                continue;
+            }
+            if query.action.is_some_and(|action| entry.action != action) {
+                continue;
+            }
+            if entries.len() == limit {
+                has_more = true;
+                break;
+            }
+            entries.push(entry);
+        }
+        let next_after = if has_more {
+            entries.last().map(|e| e.seq)
+        } else {
+            None
+        };
+        Ok(AuditPage {
+            entries,
+            next_after,
+        })
+    }
+    async fn prune(&self) -> Result<usize> {
+        let mut removed = 0;
+        if let Some(days) = self.retention.max_age_days {
+            let cutoff = Utc::now() - Duration::days(days as i64);
+            for result in self.entries.iter() {
+                let (key, data) = result.map_err(|e| Error::Storage(e.to_string()))?;
+                if Self::deserialize_entry(&data)?.timestamp >= cutoff {
+                    break;
+                }
+                self.entries
+                    .remove(key)
+                    .map_err(|e| Error::Storage(e.to_string()))?;
+                removed += 1;
+            }
+        }
+        if let Some(max) = self.retention.max_entries {
+            let excess = self.entries.len().saturating_sub(max);
+            for _ in 0..excess {
+                if self
+                    .entries
+                    .pop_min()
+                    .map_err(|e| Error::Storage(e.to_string()))?
+                    .is_none()
+                {
+                    break;
+                }
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+            debug!("pruned {} audit entries", removed);
+        }
+        Ok(removed)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_core::AuditAction;
+    fn request(peer: &str, forwarded_for: &[&str], trusted: &[&str]) -> (HeaderMap, Extensions) {
+        let mut headers = HeaderMap::new();
+        for value in forwarded_for {
+            headers.append(FORWARDED_FOR_HEADER, value.parse().unwrap());
+        }
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        extensions.insert(TrustedProxies::new(
+            trusted.iter().map(|ip| ip.parse().unwrap()).collect(),
+        ));
+        (headers, extensions)
+    }
+    #[test]
+    fn test_source_ip_only_trusts_forwarded_for_from_proxies() {
+        let cases = [
+            (
+                "203.0.113.9:4000",
+                vec!["198.51.100.1"],
+                vec![],
+                "203.0.113.9",
+            ),
+            (
+                "10.0.0.2:4000",
+                vec!["198.51.100.1, 10.0.0.3"],
+                vec!["10.0.0.2", "10.0.0.3"],
+                "198.51.100.1",
+            ),
+            (
+                "10.0.0.2:4000",
+                vec!["6.6.6.6", "198.51.100.1"],
+                vec!["10.0.0.2"],
+                "198.51.100.1",
+            ),
+            ("10.0.0.2:4000", vec![], vec!["10.0.0.2"], "10.0.0.2"),
+            (
+                "10.0.0.2:4000",
+                vec!["not-an-ip"],
+                vec!["10.0.0.2"],
+                "10.0.0.2",
+            ),
+            (
+                "10.0.0.2:4000",
+                vec!["forged, 198.51.100.1"],
+                vec!["10.0.0.2"],
+                "198.51.100.1",
+            ),
+        ];
+        for (peer, forwarded_for, trusted, expected) in cases {
+            let (headers, extensions) = request(peer, &forwarded_for, &trusted);
+            assert_eq!(
+                source_ip(&headers, &extensions).as_deref(),
+                Some(expected),
+                "{} {:?}",
+                peer,
+                forwarded_for
+            );
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR_HEADER, "198.51.100.1".parse().unwrap());
+        assert_eq!(source_ip(&headers, &Extensions::new()), None);
+    }
+    #[tokio::test]
+    async fn test_audit_log_query_and_prune() {
+        let log = SledAuditLog::in_memory()
+            .unwrap()
+            .with_retention(AuditRetention {
+                max_age_days: None,
+                max_entries: Some(3),
+            });
+        for action in [
+            AuditAction::TokenCreate,
+            AuditAction::TokenRevoke,
+            AuditAction::TokenCreate,
+            AuditAction::ClusterJoin,
+            AuditAction::TokenCreate,
+        ] {
+            log.record(AuditEntry::new(action, "admin:test", "node-1"))
+                .await
+                .unwrap();
+        }
+        let page = log
+            .query(&AuditQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.entries.len(), 2);
+        assert!(page.entries[0].seq < page.entries[1].seq);
+        let rest = log
+            .query(&AuditQuery {
+                after: page.next_after,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(rest.entries.len(), 3);
+        assert!(rest.next_after.is_none());
+        let creates = log
+            .query(&AuditQuery {
+                action: Some(AuditAction::TokenCreate),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(creates.entries.len(), 3);
+        assert_eq!(log.prune().await.unwrap(), 2);
+        assert_eq!(log.len(), 3);
+        let remaining = log.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(remaining.entries[0].action, AuditAction::TokenCreate);
+    }
+    #[tokio::test]
+    async fn test_audit_log_appends_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("ironfish-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let log = SledAuditLog::in_memory().unwrap().with_file(&path).unwrap();
+        log.record(
+            AuditEntry::new(AuditAction::TokenCreate, "admin:test", "node-1").with_target("a"),
+        )
+        .await
+        .unwrap();
+        log.record(AuditEntry::new(AuditAction::TokenRevoke, "node:peer", "node-1").failed("gone"))
+            .await
+            .unwrap();
+        let lines: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].seq, 1);
+        assert_eq!(lines[0].target.as_deref(), Some("a"));
+        assert_eq!(lines[1].action, AuditAction::TokenRevoke);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_admin_actor_hides_key() {
+        let actor = admin_actor("super-secret");
+        assert!(actor.starts_with("admin:"));
+        assert!(!actor.contains("super-secret"));
+        assert_eq!(actor, admin_actor("super-secret"));
+    }
+}
@@ -0,0 +1,123 @@
+use ironfish_core::{NodeReservation, Result, TokenStore};
+use std::sync::Arc;
+use tracing::warn;
+use uuid::Uuid;
+pub const DEFAULT_NODE_RESERVATION: u64 = 5_000_000;
+pub struct NodeBudgetReservation {
+    store: Arc<dyn TokenStore>,
+    id: Uuid,
+    reservation: NodeReservation,
+    settled: bool,
+}
+impl NodeBudgetReservation {
+    pub async fn reserve(
+        store: Arc<dyn TokenStore>,
+        id: Uuid,
+        budget: u64,
+        nodes: Option<u64>,
+    ) -> Result<Self> {
+        let reservation = store
+            .reserve_nodes(&id, budget, nodes.unwrap_or(DEFAULT_NODE_RESERVATION))
+            .await?;
+        Ok(Self {
+            store,
+            id,
+            reservation,
+            settled: false,
+        })
+    }
+    pub fn reserved(&self) -> u64 {
+        self.reservation.nodes
+    }
+    pub fn remaining(&self) -> u64 {
+        self.reservation.remaining
+    }
+    pub async fn settle(mut self, nodes: u64) {
+        self.settled = true;
+        if let Err(e) = self
+            .store
+            .settle_nodes(&self.id, &self.reservation, nodes)
+            .await
+        {
+            warn!("failed to settle node budget for token {}: {}", self.id, e);
+        }
+    }
+}
+impl Drop for NodeBudgetReservation {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let (store, id, reservation) = (self.store.clone(), self.id, self.reservation);
+        runtime.spawn(async move {
+            if let Err(e) = store.settle_nodes(&id, &reservation, 0).await {
+                warn!("failed to release node budget for token {}: {}", id, e);
+            }
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledTokenStore, TokenManager};
+    use ironfish_core::{CreateTokenRequest, Error};
+    #[tokio::test]
+    async fn test_budget_is_reserved_then_settled_in_the_store() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        let (token, _) = manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: Some(1000),
+            })
+            .unwrap();
+        store.create(token.clone()).await.unwrap();
+        let used = |store: Arc<SledTokenStore>| async move {
+            store
+                .get(&token.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .usage()
+                .node_budget
+                .unwrap()
+                .used
+        };
+        let first = NodeBudgetReservation::reserve(store.clone(), token.id, 1000, Some(600))
+            .await
+            .unwrap();
+        assert_eq!((first.reserved(), first.remaining()), (600, 400));
+        let second = NodeBudgetReservation::reserve(store.clone(), token.id, 1000, None)
+            .await
+            .unwrap();
+        assert_eq!((second.reserved(), second.remaining()), (400, 0));
+        assert!(matches!(
+            NodeBudgetReservation::reserve(store.clone(), token.id, 1000, Some(1)).await,
+            Err(Error::NodeBudgetExhausted { .. })
+        ));
+        first.settle(100).await;
+        assert_eq!(used(store.clone()).await, 500);
+        drop(second);
+        for _ in 0..50 {
+            if used(store.clone()).await == 100 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(used(store.clone()).await, 100);
+        let other = NodeBudgetReservation::reserve(store.clone(), token.id, 1000, Some(2000))
+            .await
+            .unwrap();
+        assert_eq!(other.reserved(), 900);
+        other.settle(0).await;
+        assert_eq!(used(store).await, 100);
+    }
+}
@@ -0,0 +1,74 @@
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use ironfish_core::TokenContext;
+#[derive(Debug, Clone, Default)]
+pub struct OptionalTokenContext(pub TokenContext);
+impl<S: Send + Sync> FromRequestParts<S> for OptionalTokenContext {
+    type Rejection = std::convert::Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .extensions
+                .get::<TokenContext>()
+                .cloned()
+                .unwrap_or_else(TokenContext::anonymous),
+        ))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthLayer, SledTokenStore, TokenManager};
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::post;
+    use axum::Router;
+    use ironfish_core::{CreateTokenRequest, TokenStore};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    async fn caller(OptionalTokenContext(token): OptionalTokenContext) -> String {
+        token
+            .id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "anonymous".to_string())
+    }
+    async fn call(router: Router, authorization: Option<&str>) -> String {
+        let mut request = Request::post("/v1/analyze");
+        if let Some(value) = authorization {
+            request = request.header("authorization", value);
+        }
+        let response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+    #[tokio::test]
+    async fn test_analyze_reads_calling_token() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let manager = Arc::new(TokenManager::new(&TokenManager::generate_secret(), "test"));
+        let (token, response) = manager
+            .create(CreateTokenRequest {
+                name: Some("caller".into()),
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        store.create(token.clone()).await.unwrap();
+        let router = Router::new().route("/v1/analyze", post(caller));
+        assert_eq!(call(router.clone(), None).await, "anonymous");
+        let authenticated = router.layer(AuthLayer::new(store, manager));
+        let bearer = format!("Bearer {}", response.token);
+        assert_eq!(
+            call(authenticated, Some(&bearer)).await,
+            token.id.to_string()
+        );
+    }
+}
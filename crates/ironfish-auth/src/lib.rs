@@ -1,6 +1,20 @@
+mod audit;
+mod budget;
+mod context;
 mod middleware;
+mod rate_limit;
+mod redis_store;
 mod store;
+mod sweeper;
 mod token;
+mod usage;
+pub use audit::{admin_actor, source_ip, SledAuditLog, TrustedProxies};
+pub use budget::{NodeBudgetReservation, DEFAULT_NODE_RESERVATION};
+pub use context::OptionalTokenContext;
 pub use middleware::{AuthLayer, AuthService};
+pub use rate_limit::RateLimiter;
+pub use redis_store::RedisTokenStore;
 pub use store::SledTokenStore;
-pub use token::TokenManager;
+pub use sweeper::spawn_token_sweeper;
+pub use token::{secrets_match, ServiceClaims, TokenManager};
+pub use usage::{spawn_usage_flusher, UsageTracker};
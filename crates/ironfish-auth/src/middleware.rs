@@ -1,24 +1,56 @@
-use crate::TokenManager;
+use crate::audit::{admin_actor, source_ip};
+use crate::{secrets_match, RateLimiter, TokenManager, UsageTracker};
 use axum::body::Body;
-use axum::http::{Method, Request, StatusCode};
+use axum::http::{header, Method, Request, StatusCode};
 use axum::response::{IntoResponse, Response};
-use chrono::Utc;
-use ironfish_core::TokenStore;
+use ironfish_core::{
+    AuditAction, AuditEntry, AuditLog, Telemetry, TokenContext, TokenStore, SCOPE_ANALYZE,
+    SCOPE_BESTMOVE, SCOPE_CLUSTER_READ, SCOPE_CLUSTER_WRITE,
+};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use tower::{Layer, Service};
-#[derive(Clone)]
-pub struct AuthLayer<S> {
+use tracing::warn;
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+const GRPC_PERMISSION_DENIED: u8 = 7;
+const GRPC_RESOURCE_EXHAUSTED: u8 = 8;
+const GRPC_INTERNAL: u8 = 13;
+const GRPC_UNAUTHENTICATED: u8 = 16;
+pub struct AuthLayer<S: ?Sized> {
     store: Arc<S>,
     manager: Arc<TokenManager>,
     enabled: bool,
     admin_key: Option<String>,
+    audit: Option<AuditHook>,
+    rate_limiter: Arc<RateLimiter>,
+    usage: Arc<UsageTracker>,
+    telemetry: Telemetry,
+}
+#[derive(Clone)]
+struct AuditHook {
+    log: Arc<dyn AuditLog>,
+    node_id: String,
+}
+impl<S: ?Sized> Clone for AuthLayer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            manager: self.manager.clone(),
+            enabled: self.enabled,
+            admin_key: self.admin_key.clone(),
+            audit: self.audit.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            usage: self.usage.clone(),
+            telemetry: self.telemetry.clone(),
+        }
+    }
 }
 impl<S> AuthLayer<S>
 where
-    S: TokenStore,
+    S: TokenStore + ?Sized,
 {
     pub fn new(store: Arc<S>, manager: Arc<TokenManager>) -> Self {
         let admin_key = std::env::var("IRONFISH_ADMIN_KEY").ok();
@@ -27,24 +59,51 @@ where
             manager,
             enabled: true,
             admin_key,
+            audit: None,
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            usage: Arc::new(UsageTracker::new()),
+            telemetry: Telemetry::default(),
         }
     }
     pub fn with_admin_key(mut self, key: impl Into<String>) -> Self {
         self.admin_key = Some(key.into());
         self
     }
+    pub fn with_audit(mut self, log: Arc<dyn AuditLog>, node_id: impl Into<String>) -> Self {
+        self.audit = Some(AuditHook {
+            log,
+            node_id: node_id.into(),
+        });
+        self
+    }
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = limiter;
+        self
+    }
+    pub fn with_usage_tracker(mut self, tracker: Arc<UsageTracker>) -> Self {
+        self.usage = tracker;
+        self
+    }
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
     pub fn disabled(store: Arc<S>, manager: Arc<TokenManager>) -> Self {
         Self {
             store,
             manager,
             enabled: false,
             admin_key: None,
+            audit: None,
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            usage: Arc::new(UsageTracker::new()),
+            telemetry: Telemetry::default(),
         }
     }
 }
 impl<S, I> Layer<I> for AuthLayer<S>
 where
-    S: TokenStore + Clone,
+    S: TokenStore + ?Sized,
 {
     type Service = AuthService<S, I>;
     fn layer(&self, inner: I) -> Self::Service {
@@ -54,20 +113,42 @@ where
             manager: self.manager.clone(),
             enabled: self.enabled,
             admin_key: self.admin_key.clone(),
+            audit: self.audit.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            usage: self.usage.clone(),
+            telemetry: self.telemetry.clone(),
         }
     }
 }
-#[derive(Clone)]
-pub struct AuthService<S, I> {
+pub struct AuthService<S: ?Sized, I> {
     inner: I,
     store: Arc<S>,
     manager: Arc<TokenManager>,
     enabled: bool,
     admin_key: Option<String>,
+    audit: Option<AuditHook>,
+    rate_limiter: Arc<RateLimiter>,
+    usage: Arc<UsageTracker>,
+    telemetry: Telemetry,
+}
+impl<S: ?Sized, I: Clone> Clone for AuthService<S, I> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            manager: self.manager.clone(),
+            enabled: self.enabled,
+            admin_key: self.admin_key.clone(),
+            audit: self.audit.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            usage: self.usage.clone(),
+            telemetry: self.telemetry.clone(),
+        }
+    }
 }
 impl<S, I> Service<Request<Body>> for AuthService<S, I>
 where
-    S: TokenStore + 'static,
+    S: TokenStore + ?Sized + 'static,
     I: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
     I::Future: Send,
 {
@@ -77,16 +158,32 @@ where
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
     }
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
         let path = req.uri().path();
         let method = req.method().clone();
         let is_public_path = path == "/v1/health"
             || path == "/health"
+            || path == "/ready"
             || path == "/metrics"
+            || path == "/openapi.json"
+            || path == "/docs"
+            || path.starts_with("/docs/")
             || path == "/v1/ws"
             || path == "/ws"
-            || (path == "/graphql" && method == Method::GET);
+            || (path == "/graphql" && method == Method::GET)
+            || path.starts_with("/grpc.reflection.");
         let is_admin_path = path.starts_with("/_admin");
+        let is_admin_rpc = path.starts_with("/chess.ClusterAdmin/");
+        let grpc = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| h.starts_with("application/grpc"));
+        let admin_header = req
+            .headers()
+            .get("x-admin-key")
+            .and_then(|h| h.to_str().ok())
+            .map(String::from);
         if !self.enabled {
             return Box::pin(self.inner.call(req));
         }
@@ -95,28 +192,49 @@ where
         }
         if is_admin_path {
             let admin_key = self.admin_key.clone();
-            let admin_header = req
-                .headers()
-                .get("x-admin-key")
-                .and_then(|h| h.to_str().ok())
-                .map(String::from);
             let mut inner = self.inner.clone();
+            let audit = self.audit.clone();
+            let telemetry = self.telemetry.clone();
             return Box::pin(async move {
-                match (admin_key, admin_header) {
-                    (Some(expected), Some(provided)) if expected == provided => {
-                        inner.call(req).await
+                let (reason, response) = match (admin_key, admin_header.as_deref()) {
+                    (Some(expected), Some(provided)) if secrets_match(&expected, provided) => {
+                        return inner.call(req).await;
                     }
-                    (Some(_), _) => Ok(unauthorized_response("invalid or missing admin key")),
-                    (None, _) => Ok(forbidden_response(
-                        "admin endpoints require IRONFISH_ADMIN_KEY to be set",
-                    )),
-                }
+                    (Some(_), _) => {
+                        let reason = "invalid or missing admin key";
+                        telemetry.error("unauthorized");
+                        (reason, unauthorized_response(false, reason))
+                    }
+                    (None, _) => {
+                        let reason = "admin endpoints require IRONFISH_ADMIN_KEY to be set";
+                        telemetry.error("forbidden");
+                        (reason, forbidden_response(false, reason))
+                    }
+                };
+                record_admin_failure(
+                    audit,
+                    admin_header.as_deref(),
+                    format!("{} {}", req.method(), req.uri().path()),
+                    source_ip(req.headers(), req.extensions()),
+                    reason,
+                )
+                .await;
+                Ok(response)
             });
         }
+        let admin_key = self.admin_key.clone();
+        let audit = self.audit.clone();
         let store = self.store.clone();
         let manager = self.manager.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let usage = self.usage.clone();
         let mut inner = self.inner.clone();
+        let telemetry = self.telemetry.clone();
         Box::pin(async move {
+            let reject = |kind: &'static str, response: Response| -> Result<Response, I::Error> {
+                telemetry.error(kind);
+                Ok(response)
+            };
             let auth_header = req
                 .headers()
                 .get("authorization")
@@ -124,61 +242,184 @@ where
             let token_str = match auth_header {
                 Some(h) if h.starts_with("Bearer ") => &h[7..],
                 _ => {
-                    return Ok(unauthorized_response("missing authorization header"));
+                    return reject(
+                        "unauthorized",
+                        unauthorized_response(grpc, "missing authorization header"),
+                    );
                 }
             };
             if !TokenManager::validate_format(token_str) {
-                return Ok(unauthorized_response("invalid token format"));
+                return reject(
+                    "unauthorized",
+                    unauthorized_response(grpc, "invalid token format"),
+                );
             }
             let raw_token = match TokenManager::extract_raw_token(token_str) {
                 Some(r) => r,
                 None => {
-                    return Ok(unauthorized_response("invalid token"));
+                    return reject("unauthorized", unauthorized_response(grpc, "invalid token"));
                 }
             };
             let token_hash = manager.hash_token(raw_token);
             let token = match store.get_by_hash(&token_hash).await {
                 Ok(Some(t)) => t,
                 Ok(None) => {
-                    return Ok(unauthorized_response("invalid token"));
+                    return reject("unauthorized", unauthorized_response(grpc, "invalid token"));
                 }
                 Err(_) => {
-                    return Ok(error_response("internal error"));
+                    return Ok(error_response(grpc, "internal error"));
                 }
             };
             if !token.is_valid() {
-                return Ok(unauthorized_response("token expired or revoked"));
+                return reject(
+                    "unauthorized",
+                    unauthorized_response(grpc, "token expired or revoked"),
+                );
+            }
+            if let Some(scope) = required_scope(req.uri().path()) {
+                if !token.has_scope(scope) {
+                    return reject(
+                        "forbidden",
+                        forbidden_response(grpc, &format!("missing scope: {}", scope)),
+                    );
+                }
+            }
+            if is_admin_rpc {
+                let failure = match (admin_key.as_deref(), admin_header.as_deref()) {
+                    (Some(expected), Some(provided)) if secrets_match(expected, provided) => None,
+                    (Some(_), _) => {
+                        let reason = "invalid or missing admin key";
+                        Some((reason, "unauthorized", unauthorized_response(grpc, reason)))
+                    }
+                    (None, _) => {
+                        let reason = "admin endpoints require IRONFISH_ADMIN_KEY to be set";
+                        Some((reason, "forbidden", forbidden_response(grpc, reason)))
+                    }
+                };
+                if let Some((reason, kind, response)) = failure {
+                    record_admin_failure(
+                        audit,
+                        admin_header.as_deref(),
+                        format!("{} {}", req.method(), req.uri().path()),
+                        source_ip(req.headers(), req.extensions()),
+                        reason,
+                    )
+                    .await;
+                    return reject(kind, response);
+                }
             }
-            let mut updated_token = token.clone();
-            updated_token.last_used_at = Some(Utc::now());
-            drop(tokio::spawn(async move {
-                let _ = store.update(updated_token).await;
-            }));
+            if let Err(retry_after) = rate_limiter.check(&token).await {
+                return reject("rate_limited", rate_limited_response(grpc, retry_after));
+            }
+            let ip = source_ip(req.headers(), req.extensions());
+            if usage.record(token.id, ip) && store.health().is_writable() {
+                drop(tokio::spawn(async move {
+                    if let Err(e) = usage.flush(store.as_ref()).await {
+                        warn!("token usage flush failed: {}", e);
+                    }
+                }));
+            }
+            req.extensions_mut().insert(TokenContext::from(&token));
             inner.call(req).await
         })
     }
 }
-fn unauthorized_response(message: &str) -> Response {
+fn required_scope(path: &str) -> Option<&'static str> {
+    match path {
+        "/v1/bestmove" | "/chess.ChessAnalysis/BestMove" | "/chess.ChessAnalysis/PlaySession" => {
+            Some(SCOPE_BESTMOVE)
+        }
+        "/chess.ClusterAdmin/GetStatus" => Some(SCOPE_CLUSTER_READ),
+        "/chess.ClusterAdmin/JoinCluster" | "/chess.ClusterAdmin/LeaveCluster" => {
+            Some(SCOPE_CLUSTER_WRITE)
+        }
+        "/v1/analyses" => Some(SCOPE_ANALYZE),
+        p if p.starts_with("/v1/analyze") || p.starts_with("/chess.ChessAnalysis/") => {
+            Some(SCOPE_ANALYZE)
+        }
+        _ => None,
+    }
+}
+async fn record_admin_failure(
+    audit: Option<AuditHook>,
+    admin_header: Option<&str>,
+    target: String,
+    source_ip: Option<String>,
+    reason: &str,
+) {
+    let Some(hook) = audit else {
+        return;
+    };
+    let actor = admin_header
+        .map(admin_actor)
+        .unwrap_or_else(|| "anonymous".to_string());
+    let entry = AuditEntry::new(AuditAction::AdminAuthFailure, actor, hook.node_id)
+        .with_target(target)
+        .with_source_ip(source_ip)
+        .failed(reason);
+    if let Err(e) = hook.log.record(entry).await {
+        warn!("failed to record audit entry: {}", e);
+    }
+}
+fn error_body(code: &str, message: &str) -> String {
+    serde_json::json!({"error": {"code": code, "message": message}}).to_string()
+}
+fn grpc_response(code: u8, message: &str) -> Response {
+    (
+        StatusCode::OK,
+        [
+            ("content-type", "application/grpc".to_string()),
+            (GRPC_STATUS, code.to_string()),
+            (GRPC_MESSAGE, message.to_string()),
+        ],
+    )
+        .into_response()
+}
+fn unauthorized_response(grpc: bool, message: &str) -> Response {
+    if grpc {
+        return grpc_response(GRPC_UNAUTHENTICATED, message);
+    }
     (
         StatusCode::UNAUTHORIZED,
         [("content-type", "application/json")],
-        format!(r#"{{"error":"{}"}}"#, message),
+        error_body("unauthorized", message),
     )
         .into_response()
 }
-fn error_response(message: &str) -> Response {
+fn error_response(grpc: bool, message: &str) -> Response {
+    if grpc {
+        return grpc_response(GRPC_INTERNAL, message);
+    }
     (
         StatusCode::INTERNAL_SERVER_ERROR,
         [("content-type", "application/json")],
-        format!(r#"{{"error":"{}"}}"#, message),
+        error_body("internal", message),
     )
         .into_response()
 }
-fn forbidden_response(message: &str) -> Response {
+fn rate_limited_response(grpc: bool, retry_after: std::time::Duration) -> Response {
+    if grpc {
+        return grpc_response(GRPC_RESOURCE_EXHAUSTED, "rate limit exceeded");
+    }
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::RETRY_AFTER, seconds.max(1).to_string()),
+        ],
+        error_body("rate_limited", "rate limit exceeded"),
+    )
+        .into_response()
+}
+fn forbidden_response(grpc: bool, message: &str) -> Response {
+    if grpc {
+        return grpc_response(GRPC_PERMISSION_DENIED, message);
+    }
     (
         StatusCode::FORBIDDEN,
         [("content-type", "application/json")],
-        format!(r#"{{"error":"{}"}}"#, message),
+        error_body("forbidden", message),
     )
         .into_response()
 }
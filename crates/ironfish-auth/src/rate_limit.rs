@@ -0,0 +1,96 @@
+use ironfish_core::ApiToken;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+const WINDOW: Duration = Duration::from_secs(60);
+pub struct RateLimiter {
+    default_per_minute: AtomicU32,
+    windows: Mutex<HashMap<Uuid, VecDeque<Instant>>>,
+}
+impl RateLimiter {
+    pub fn new(default_per_minute: u32) -> Self {
+        Self {
+            default_per_minute: AtomicU32::new(default_per_minute),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+    pub fn limit_for(&self, token: &ApiToken) -> u32 {
+        token
+            .rate_limit
+            .unwrap_or_else(|| self.default_per_minute.load(Ordering::Relaxed))
+    }
+    pub fn default_per_minute(&self) -> u32 {
+        self.default_per_minute.load(Ordering::Relaxed)
+    }
+    pub fn set_default_per_minute(&self, per_minute: u32) {
+        self.default_per_minute.store(per_minute, Ordering::Relaxed);
+    }
+    pub async fn check(&self, token: &ApiToken) -> std::result::Result<(), Duration> {
+        let limit = self.limit_for(token);
+        if limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().await;
+        let window = windows.entry(token.id).or_default();
+        while window
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= WINDOW)
+        {
+            window.pop_front();
+        }
+        if window.len() >= limit as usize {
+            let oldest = window.front().copied().unwrap_or(now);
+            return Err(WINDOW.saturating_sub(now.duration_since(oldest)));
+        }
+        window.push_back(now);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenManager;
+    use ironfish_core::CreateTokenRequest;
+    fn token(rate_limit: Option<u32>) -> ApiToken {
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test-node");
+        let (token, _) = manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        token
+    }
+    #[tokio::test]
+    async fn test_token_limit() {
+        let limiter = RateLimiter::new(100);
+        let limited = token(Some(2));
+        assert!(limiter.check(&limited).await.is_ok());
+        assert!(limiter.check(&limited).await.is_ok());
+        let retry_after = limiter.check(&limited).await.unwrap_err();
+        assert!(retry_after <= WINDOW && retry_after > Duration::ZERO);
+        assert!(limiter.check(&token(Some(2))).await.is_ok());
+    }
+    #[tokio::test]
+    async fn test_default_limit() {
+        let limiter = RateLimiter::new(1);
+        let token = token(None);
+        assert!(limiter.check(&token).await.is_ok());
+        assert!(limiter.check(&token).await.is_err());
+        let unlimited = RateLimiter::unlimited();
+        for _ in 0..10 {
+            assert!(unlimited.check(&token).await.is_ok());
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use ironfish_core::{
+    ApiToken, Error, NodeReservation, NodeWindow, Result, StoreHealth, TokenStore, UsageDelta,
+};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::sync::Mutex;
+use uuid::Uuid;
+fn token_key(id: &Uuid) -> String {
+    format!("token:{}", id)
+}
+fn hash_key(hash: &str) -> String {
+    format!("token_hash:{}", hash)
+}
+const MAX_MODIFY_ATTEMPTS: usize = 16;
+const COMPARE_AND_SET: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+  return 0
+end
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl > 0 then
+  redis.call('SET', KEYS[1], ARGV[2], 'PX', ttl)
+else
+  redis.call('SET', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+fn storage_error(e: redis::RedisError) -> Error {
+    Error::Storage(e.to_string())
+}
+fn ttl_secs(token: &ApiToken) -> Option<usize> {
+    token
+        .expires_at
+        .map(|expires_at| (expires_at - Utc::now()).num_seconds().max(1) as usize)
+}
+#[derive(Clone)]
+pub struct RedisTokenStore {
+    connection: ConnectionManager,
+}
+impl RedisTokenStore {
+    pub async fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(storage_error)?;
+        let connection = client
+            .get_tokio_connection_manager()
+            .await
+            .map_err(storage_error)?;
+        Ok(Self { connection })
+    }
+    async fn write_token(&self, token: &ApiToken) -> Result<()> {
+        let data = serde_json::to_string(token).map_err(Error::Serialization)?;
+        let id = token.id.to_string();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match ttl_secs(token) {
+            Some(ttl) => pipe
+                .set_ex(token_key(&token.id), data, ttl)
+                .ignore()
+                .set_ex(hash_key(&token.token_hash), id, ttl)
+                .ignore(),
+            None => pipe
+                .set(token_key(&token.id), data)
+                .ignore()
+                .set(hash_key(&token.token_hash), id)
+                .ignore(),
+        };
+        let mut connection = self.connection.clone();
+        pipe.query_async(&mut connection)
+            .await
+            .map_err(storage_error)
+    }
+    fn deserialize_token(data: &str) -> Result<ApiToken> {
+        serde_json::from_str(data).map_err(Error::Serialization)
+    }
+    async fn compare_and_set(&self, id: &Uuid, current: &str, updated: &str) -> Result<bool> {
+        let mut connection = self.connection.clone();
+        let swapped: i64 = redis::cmd("EVAL")
+            .arg(COMPARE_AND_SET)
+            .arg(1)
+            .arg(token_key(id))
+            .arg(current)
+            .arg(updated)
+            .query_async(&mut connection)
+            .await
+            .map_err(storage_error)?;
+        Ok(swapped == 1)
+    }
+    async fn modify_token(&self, id: &Uuid, apply: impl Fn(&mut ApiToken) + Send) -> Result<()> {
+        for _ in 0..MAX_MODIFY_ATTEMPTS {
+            let mut connection = self.connection.clone();
+            let current: Option<String> =
+                connection.get(token_key(id)).await.map_err(storage_error)?;
+            let Some(current) = current else {
+                return Ok(());
+            };
+            let mut token = Self::deserialize_token(&current)?;
+            apply(&mut token);
+            let updated = serde_json::to_string(&token).map_err(Error::Serialization)?;
+            if self.compare_and_set(id, &current, &updated).await? {
+                return Ok(());
+            }
+        }
+        Err(Error::Storage(format!(
+            "token {} kept changing during an update",
+            id
+        )))
+    }
+}
+#[async_trait]
+impl TokenStore for RedisTokenStore {
+    async fn create(&self, token: ApiToken) -> Result<()> {
+        self.write_token(&token).await
+    }
+    async fn get(&self, id: &Uuid) -> Result<Option<ApiToken>> {
+        let mut connection = self.connection.clone();
+        let data: Option<String> = connection.get(token_key(id)).await.map_err(storage_error)?;
+        data.as_deref().map(Self::deserialize_token).transpose()
+    }
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ApiToken>> {
+        let mut connection = self.connection.clone();
+        let id: Option<String> = connection
+            .get(hash_key(hash))
+            .await
+            .map_err(storage_error)?;
+        match id.and_then(|id| Uuid::parse_str(&id).ok()) {
+            Some(id) => self.get(&id).await,
+            None => Ok(None),
+        }
+    }
+    async fn update(&self, token: ApiToken) -> Result<()> {
+        self.write_token(&token).await
+    }
+    async fn delete(&self, id: &Uuid) -> Result<()> {
+        let mut keys = vec![token_key(id)];
+        if let Some(token) = self.get(id).await? {
+            keys.push(hash_key(&token.token_hash));
+        }
+        let mut connection = self.connection.clone();
+        connection.del(keys).await.map_err(storage_error)
+    }
+    async fn list(&self) -> Result<Vec<ApiToken>> {
+        let mut connection = self.connection.clone();
+        let keys: Vec<String> = {
+            let mut iter = connection
+                .scan_match::<_, String>("token:*")
+                .await
+                .map_err(storage_error)?;
+            let mut keys = Vec::new();
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut connection)
+            .await
+            .map_err(storage_error)?;
+        values
+            .iter()
+            .flatten()
+            .map(|data| Self::deserialize_token(data))
+            .collect()
+    }
+    async fn revoke(&self, id: &Uuid) -> Result<()> {
+        let now = Utc::now();
+        self.modify_token(id, |token| {
+            token.revoked = true;
+            token.revoked_at = Some(now);
+            token.last_used_at = Some(now);
+        })
+        .await
+    }
+    async fn record_usage(&self, id: &Uuid, delta: &UsageDelta) -> Result<()> {
+        self.modify_token(id, |token| token.apply_usage(delta))
+            .await
+    }
+    async fn reserve_nodes(&self, id: &Uuid, budget: u64, nodes: u64) -> Result<NodeReservation> {
+        let now = Utc::now();
+        let outcome = Mutex::new(None);
+        self.modify_token(id, |token| {
+            let mut window = token.node_window.clone().unwrap_or_default();
+            let reservation = window.reserve(now, budget, nodes);
+            if reservation.is_ok() {
+                token.node_window = Some(window);
+            }
+            *outcome.lock().unwrap() = Some(reservation);
+        })
+        .await?;
+        let outcome = outcome.into_inner().unwrap();
+        outcome.unwrap_or_else(|| NodeWindow::default().reserve(now, budget, nodes))
+    }
+    async fn settle_nodes(
+        &self,
+        id: &Uuid,
+        reservation: &NodeReservation,
+        nodes: u64,
+    ) -> Result<()> {
+        let now = Utc::now();
+        self.modify_token(id, |token| {
+            token
+                .node_window
+                .get_or_insert_with(NodeWindow::default)
+                .settle(now, reservation, nodes)
+        })
+        .await
+    }
+    async fn recover(&self) -> Result<StoreHealth> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut connection)
+            .await
+            .map_err(storage_error)?;
+        Ok(StoreHealth::Healthy)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenManager;
+    use ironfish_core::CreateTokenRequest;
+    fn token(expires_in_days: Option<u32>) -> ApiToken {
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        manager
+            .create(CreateTokenRequest {
+                name: Some("redis".into()),
+                expires_in_days,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap()
+            .0
+    }
+    #[test]
+    fn test_ttl_follows_expiry() {
+        let mut permanent = token(None);
+        permanent.expires_at = None;
+        assert_eq!(ttl_secs(&permanent), None);
+        let ttl = ttl_secs(&token(Some(1))).unwrap();
+        assert!(ttl > 86_000 && ttl <= 86_400);
+        let mut expired = token(Some(1));
+        expired.expires_at = Some(Utc::now() - chrono::Duration::hours(1));
+        assert_eq!(ttl_secs(&expired), Some(1));
+    }
+    #[cfg(feature = "redis-tests")]
+    #[tokio::test]
+    async fn test_redis_token_store() {
+        let url = std::env::var("IRONFISH_TEST_REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let store = RedisTokenStore::new(&url).await.unwrap();
+        let token = token(Some(1));
+        store.create(token.clone()).await.unwrap();
+        let retrieved = store.get(&token.id).await.unwrap().unwrap();
+        assert_eq!(retrieved.id, token.id);
+        let by_hash = store.get_by_hash(&token.token_hash).await.unwrap().unwrap();
+        assert_eq!(by_hash.id, token.id);
+        assert!(store.list().await.unwrap().iter().any(|t| t.id == token.id));
+        let mut connection = store.connection.clone();
+        let ttl: i64 = connection.ttl(token_key(&token.id)).await.unwrap();
+        assert!(ttl > 0 && ttl <= 86_400);
+        let stale: String = connection.get(token_key(&token.id)).await.unwrap();
+        store.revoke(&token.id).await.unwrap();
+        assert!(store.get(&token.id).await.unwrap().unwrap().revoked);
+        assert!(!store
+            .compare_and_set(&token.id, &stale, &stale)
+            .await
+            .unwrap());
+        let delta = UsageDelta {
+            count: 3,
+            last_used_at: Utc::now(),
+            last_used_ip: None,
+        };
+        store.record_usage(&token.id, &delta).await.unwrap();
+        let used = store.get(&token.id).await.unwrap().unwrap();
+        assert!(used.revoked);
+        assert_eq!(used.usage_count, 3);
+        let ttl: i64 = connection.ttl(token_key(&token.id)).await.unwrap();
+        assert!(ttl > 0 && ttl <= 86_400);
+        store.delete(&token.id).await.unwrap();
+        assert!(store.get(&token.id).await.unwrap().is_none());
+        assert!(store
+            .get_by_hash(&token.token_hash)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,44 +1,191 @@
 use async_trait::async_trait;
 use chrono::Utc;
-use ironfish_core::{ApiToken, Error, Result, TokenStore};
+use ironfish_core::{
+    ApiToken, Error, NodeReservation, NodeWindow, Result, StoreHealth, TokenCursor, TokenListQuery,
+    TokenOrder, TokenPage, TokenStore, UsageDelta,
+};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
 use uuid::Uuid;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+const CANARY_KEY: &[u8] = b"__canary";
+#[derive(Default)]
+struct WriteHealth {
+    consecutive_failures: AtomicU32,
+    read_only: AtomicBool,
+}
 #[derive(Clone)]
 pub struct SledTokenStore {
     db: Arc<sled::Db>,
     tokens_tree: sled::Tree,
     hash_index: sled::Tree,
+    created_index: sled::Tree,
+    write_health: Arc<WriteHealth>,
+    failure_threshold: u32,
 }
 impl SledTokenStore {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
         let db = sled::open(path).map_err(|e| Error::Storage(e.to_string()))?;
-        let tokens_tree = db
-            .open_tree("tokens")
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        let hash_index = db
-            .open_tree("token_hashes")
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        Ok(Self {
-            db: Arc::new(db),
-            tokens_tree,
-            hash_index,
-        })
+        Self::open(db)
     }
     pub fn in_memory() -> Result<Self> {
         let config = sled::Config::new().temporary(true);
         let db = config.open().map_err(|e| Error::Storage(e.to_string()))?;
+        Self::open(db)
+    }
+    fn open(db: sled::Db) -> Result<Self> {
         let tokens_tree = db
             .open_tree("tokens")
             .map_err(|e| Error::Storage(e.to_string()))?;
         let hash_index = db
             .open_tree("token_hashes")
             .map_err(|e| Error::Storage(e.to_string()))?;
-        Ok(Self {
+        let created_index = db
+            .open_tree("tokens_by_created")
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        let store = Self {
             db: Arc::new(db),
             tokens_tree,
             hash_index,
-        })
+            created_index,
+            write_health: Arc::new(WriteHealth::default()),
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+        };
+        if store.created_index.len() != store.tokens_tree.len() {
+            store.rebuild_created_index()?;
+        }
+        Ok(store)
+    }
+    fn rebuild_created_index(&self) -> Result<()> {
+        self.created_index
+            .clear()
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        for result in self.tokens_tree.iter() {
+            let (key, data) = result.map_err(|e| Error::Storage(e.to_string()))?;
+            let token = Self::deserialize_token(&data)?;
+            self.created_index
+                .insert(Self::created_key(&token), key)
+                .map_err(|e| Error::Storage(e.to_string()))?;
+        }
+        info!(
+            "rebuilt token creation index with {} entries",
+            self.created_index.len()
+        );
+        Ok(())
+    }
+    fn cursor_key(cursor: &TokenCursor) -> Vec<u8> {
+        let mut key = ((cursor.at as u64) ^ (1 << 63)).to_be_bytes().to_vec();
+        key.extend_from_slice(cursor.id.as_bytes());
+        key
+    }
+    fn created_key(token: &ApiToken) -> Vec<u8> {
+        Self::cursor_key(&TokenOrder::CreatedAtDesc.cursor(token))
+    }
+    pub fn with_failure_threshold(mut self, threshold: u32) -> Self {
+        self.failure_threshold = threshold.max(1);
+        self
+    }
+    pub fn mark_read_only(&self) {
+        if !self.write_health.read_only.swap(true, Ordering::SeqCst) {
+            warn!("token store switched to read-only");
+        }
+    }
+    fn ensure_writable(&self) -> Result<()> {
+        if self.write_health.read_only.load(Ordering::SeqCst) {
+            return Err(Error::StoreReadOnly);
+        }
+        Ok(())
+    }
+    fn track_write<T>(&self, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => {
+                self.write_health
+                    .consecutive_failures
+                    .store(0, Ordering::SeqCst);
+            }
+            Err(Error::Storage(e)) => {
+                let failures = self
+                    .write_health
+                    .consecutive_failures
+                    .fetch_add(1, Ordering::SeqCst)
+                    + 1;
+                if failures >= self.failure_threshold
+                    && !self.write_health.read_only.swap(true, Ordering::SeqCst)
+                {
+                    warn!(
+                        "token store switched to read-only after {} write failures: {}",
+                        failures, e
+                    );
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+    fn write_token(&self, token: &ApiToken, index: bool) -> Result<()> {
+        let key = token.id.as_bytes().to_vec();
+        let data = Self::serialize_token(token)?;
+        let previous = self
+            .tokens_tree
+            .insert(&key, data)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        if let Some(previous) = previous {
+            let previous = Self::deserialize_token(&previous)?;
+            if previous.created_at != token.created_at {
+                self.created_index
+                    .remove(Self::created_key(&previous))
+                    .map_err(|e| Error::Storage(e.to_string()))?;
+            }
+        }
+        self.created_index
+            .insert(Self::created_key(token), key.clone())
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        if index {
+            self.hash_index
+                .insert(token.token_hash.as_bytes(), key)
+                .map_err(|e| Error::Storage(e.to_string()))?;
+        }
+        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
+    }
+    fn modify_token(&self, id: &Uuid, apply: impl Fn(&mut ApiToken)) -> Result<()> {
+        self.tokens_tree
+            .update_and_fetch(id.as_bytes(), |current| {
+                let current = current?;
+                let updated = Self::deserialize_token(current).and_then(|mut token| {
+                    apply(&mut token);
+                    Self::serialize_token(&token)
+                });
+                Some(updated.unwrap_or_else(|_| current.to_vec()))
+            })
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
+    }
+    fn remove_token(&self, id: &Uuid, token: Option<&ApiToken>) -> Result<()> {
+        if let Some(token) = token {
+            self.hash_index
+                .remove(token.token_hash.as_bytes())
+                .map_err(|e| Error::Storage(e.to_string()))?;
+            self.created_index
+                .remove(Self::created_key(token))
+                .map_err(|e| Error::Storage(e.to_string()))?;
+        }
+        self.tokens_tree
+            .remove(id.as_bytes())
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
+    }
+    fn write_canary(&self) -> Result<()> {
+        let now = Utc::now().timestamp_millis().to_be_bytes();
+        self.db
+            .insert(CANARY_KEY, &now)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
     }
     fn serialize_token(token: &ApiToken) -> Result<Vec<u8>> {
         serde_json::to_vec(token).map_err(Error::Serialization)
@@ -50,16 +197,8 @@ impl SledTokenStore {
 #[async_trait]
 impl TokenStore for SledTokenStore {
     async fn create(&self, token: ApiToken) -> Result<()> {
-        let key = token.id.as_bytes().to_vec();
-        let data = Self::serialize_token(&token)?;
-        self.tokens_tree
-            .insert(&key, data)
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        self.hash_index
-            .insert(token.token_hash.as_bytes(), key)
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
-        Ok(())
+        self.ensure_writable()?;
+        self.track_write(self.write_token(&token, true))
     }
     async fn get(&self, id: &Uuid) -> Result<Option<ApiToken>> {
         let key = id.as_bytes();
@@ -92,25 +231,47 @@ impl TokenStore for SledTokenStore {
         }
     }
     async fn update(&self, token: ApiToken) -> Result<()> {
-        let key = token.id.as_bytes().to_vec();
-        let data = Self::serialize_token(&token)?;
-        self.tokens_tree
-            .insert(&key, data)
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
-        Ok(())
+        self.ensure_writable()?;
+        self.track_write(self.write_token(&token, false))
+    }
+    async fn record_usage(&self, id: &Uuid, delta: &UsageDelta) -> Result<()> {
+        self.ensure_writable()?;
+        self.track_write(self.modify_token(id, |token| token.apply_usage(delta)))
+    }
+    async fn reserve_nodes(&self, id: &Uuid, budget: u64, nodes: u64) -> Result<NodeReservation> {
+        self.ensure_writable()?;
+        let now = Utc::now();
+        let outcome = Mutex::new(None);
+        self.track_write(self.modify_token(id, |token| {
+            let mut window = token.node_window.clone().unwrap_or_default();
+            let reservation = window.reserve(now, budget, nodes);
+            if reservation.is_ok() {
+                token.node_window = Some(window);
+            }
+            *outcome.lock().unwrap() = Some(reservation);
+        }))?;
+        let outcome = outcome.into_inner().unwrap();
+        outcome.unwrap_or_else(|| NodeWindow::default().reserve(now, budget, nodes))
+    }
+    async fn settle_nodes(
+        &self,
+        id: &Uuid,
+        reservation: &NodeReservation,
+        nodes: u64,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        let now = Utc::now();
+        self.track_write(self.modify_token(id, |token| {
+            token
+                .node_window
+                .get_or_insert_with(NodeWindow::default)
+                .settle(now, reservation, nodes)
+        }))
     }
     async fn delete(&self, id: &Uuid) -> Result<()> {
-        if let Some(token) = self.get(id).await? {
-            self.hash_index
-                .remove(token.token_hash.as_bytes())
-                .map_err(|e| Error::Storage(e.to_string()))?;
-        }
-        self.tokens_tree
-            .remove(id.as_bytes())
-            .map_err(|e| Error::Storage(e.to_string()))?;
-        self.db.flush().map_err(|e| Error::Storage(e.to_string()))?;
-        Ok(())
+        self.ensure_writable()?;
+        let token = self.get(id).await?;
+        self.track_write(self.remove_token(id, token.as_ref()))
     }
     async fn list(&self) -> Result<Vec<ApiToken>> {
         let mut tokens = Vec::new();
@@ -121,20 +282,87 @@ impl TokenStore for SledTokenStore {
         }
         Ok(tokens)
     }
+    async fn list_page(&self, query: &TokenListQuery) -> Result<TokenPage> {
+        if query.order != TokenOrder::CreatedAtDesc {
+            return Ok(query.paginate(self.list().await?));
+        }
+        let limit = query.limit.unwrap_or(usize::MAX).max(1);
+        let upper = query
+            .after
+            .map(|cursor| Self::cursor_key(&cursor))
+            .unwrap_or_else(|| vec![0xff; 24]);
+        let now = Utc::now();
+        let mut tokens = Vec::new();
+        let mut has_more = false;
+        for result in self.created_index.range(..upper).rev() {
+            let (_, id) = result.map_err(|e| Error::Storage(e.to_string()))?;
+            let Some(data) = self
+                .tokens_tree
+                .get(&id)
+                .map_err(|e| Error::Storage(e.to_string()))?
+            else {
+                continue;
+            };
+            let token = Self::deserialize_token(&data)?;
+            if query
+                .filter
+                .created_after
+                .is_some_and(|created_after| token.created_at <= created_after)
+            {
+                break;
+            }
+            if !query.filter.matches(&token, now) {
+                continue;
+            }
+            if tokens.len() == limit {
+                has_more = true;
+                break;
+            }
+            tokens.push(token);
+        }
+        let next_after = if has_more {
+            tokens.last().map(|t| query.order.cursor(t))
+        } else {
+            None
+        };
+        Ok(TokenPage { tokens, next_after })
+    }
     async fn revoke(&self, id: &Uuid) -> Result<()> {
+        self.ensure_writable()?;
         if let Some(mut token) = self.get(id).await? {
+            let now = Utc::now();
             token.revoked = true;
-            token.last_used_at = Some(Utc::now());
+            token.revoked_at = Some(now);
+            token.last_used_at = Some(now);
             self.update(token).await?;
         }
         Ok(())
     }
+    fn health(&self) -> StoreHealth {
+        if self.write_health.read_only.load(Ordering::SeqCst) {
+            StoreHealth::Degraded
+        } else {
+            StoreHealth::Healthy
+        }
+    }
+    async fn recover(&self) -> Result<StoreHealth> {
+        if self.health().is_writable() {
+            return Ok(StoreHealth::Healthy);
+        }
+        self.write_canary()?;
+        self.write_health
+            .consecutive_failures
+            .store(0, Ordering::SeqCst);
+        self.write_health.read_only.store(false, Ordering::SeqCst);
+        info!("token store recovered, accepting writes again");
+        Ok(StoreHealth::Healthy)
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::TokenManager;
-    use ironfish_core::CreateTokenRequest;
+    use ironfish_core::{CreateTokenRequest, TokenFilter};
     #[tokio::test]
     async fn test_token_store() {
         let store = SledTokenStore::in_memory().unwrap();
@@ -145,6 +373,10 @@ mod tests {
                 name: Some("test".into()),
                 expires_in_days: None,
                 rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
             })
             .unwrap();
         store.create(token.clone()).await.unwrap();
@@ -160,4 +392,90 @@ mod tests {
         store.delete(&token.id).await.unwrap();
         assert!(store.get(&token.id).await.unwrap().is_none());
     }
+    #[tokio::test]
+    async fn test_token_store_read_only_after_failures() {
+        let store = SledTokenStore::in_memory()
+            .unwrap()
+            .with_failure_threshold(2);
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        let (token, _) = manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        store.create(token.clone()).await.unwrap();
+        let failure = || Err::<(), _>(Error::Storage("read-only file system".into()));
+        assert!(store.track_write(failure()).is_err());
+        assert_eq!(store.health(), StoreHealth::Healthy);
+        assert!(store.track_write(failure()).is_err());
+        assert_eq!(store.health(), StoreHealth::Degraded);
+        assert!(matches!(
+            store.revoke(&token.id).await,
+            Err(Error::StoreReadOnly)
+        ));
+        assert!(store.get(&token.id).await.unwrap().is_some());
+        assert_eq!(store.recover().await.unwrap(), StoreHealth::Healthy);
+        store.revoke(&token.id).await.unwrap();
+        assert!(store.get(&token.id).await.unwrap().unwrap().revoked);
+    }
+    #[tokio::test]
+    async fn test_list_page_walks_the_creation_index() {
+        let store = SledTokenStore::in_memory().unwrap();
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        let now = Utc::now();
+        for i in 0..30 {
+            let (mut token, _) = manager
+                .create(CreateTokenRequest {
+                    name: Some(format!("token-{}", i)),
+                    expires_in_days: None,
+                    rate_limit: None,
+                    scopes: Vec::new(),
+                    max_depth: None,
+                    max_multipv: None,
+                    node_budget_per_hour: None,
+                })
+                .unwrap();
+            token.created_at = now - chrono::Duration::minutes(i % 10);
+            token.revoked = i % 3 == 0;
+            store.create(token).await.unwrap();
+        }
+        let mut query = TokenListQuery {
+            filter: TokenFilter {
+                revoked: Some(false),
+                ..Default::default()
+            },
+            limit: Some(7),
+            ..Default::default()
+        };
+        let expected = query.paginate(store.list().await.unwrap());
+        assert_eq!(expected.tokens.len(), 7);
+        let mut seen = Vec::new();
+        loop {
+            let page = store.list_page(&query).await.unwrap();
+            let reference = query.paginate(store.list().await.unwrap());
+            let ids: Vec<Uuid> = page.tokens.iter().map(|t| t.id).collect();
+            let reference_ids: Vec<Uuid> = reference.tokens.iter().map(|t| t.id).collect();
+            assert_eq!(ids, reference_ids);
+            assert_eq!(page.next_after, reference.next_after);
+            seen.extend(ids);
+            match page.next_after {
+                Some(cursor) => query.after = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 20);
+        store.created_index.clear().unwrap();
+        store.rebuild_created_index().unwrap();
+        query.after = None;
+        query.limit = None;
+        assert_eq!(store.list_page(&query).await.unwrap().tokens.len(), 20);
+        store.delete(&seen[0]).await.unwrap();
+        assert_eq!(store.created_index.len(), 29);
+    }
 }
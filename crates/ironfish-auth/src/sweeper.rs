@@ -0,0 +1,79 @@
+use ironfish_core::{Error, Result, TokenRetention, TokenStore};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+pub fn spawn_token_sweeper(
+    store: Arc<dyn TokenStore>,
+    retention: TokenRetention,
+    interval: Duration,
+) -> Result<JoinHandle<()>> {
+    if interval.is_zero() {
+        return Err(Error::InvalidArgument(
+            "token sweep interval must be greater than 0".to_string(),
+        ));
+    }
+    Ok(tokio::spawn(async move {
+        let mut timer = tokio::time::interval(interval);
+        loop {
+            timer.tick().await;
+            match store.purge(&retention).await {
+                Ok(purged) if purged > 0 => info!("token sweeper purged {} tokens", purged),
+                Ok(_) => {}
+                Err(e) => warn!("token sweep failed: {}", e),
+            }
+        }
+    }))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledTokenStore, TokenManager};
+    use chrono::Utc;
+    use ironfish_core::CreateTokenRequest;
+    #[tokio::test]
+    async fn test_sweeper_purges_expired_tokens() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        let request = || CreateTokenRequest {
+            name: None,
+            expires_in_days: Some(30),
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        };
+        let (mut expired, _) = manager.create(request()).unwrap();
+        expired.expires_at = Some(Utc::now() - chrono::Duration::days(1));
+        let (active, _) = manager.create(request()).unwrap();
+        store.create(expired.clone()).await.unwrap();
+        store.create(active.clone()).await.unwrap();
+        let retention = TokenRetention {
+            expired_grace_days: 0,
+            revoked_retention_days: 0,
+        };
+        let sweeper =
+            spawn_token_sweeper(store.clone(), retention, Duration::from_millis(20)).unwrap();
+        for _ in 0..50 {
+            if store.get(&expired.id).await.unwrap().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        sweeper.abort();
+        assert!(store.get(&expired.id).await.unwrap().is_none());
+        assert!(store
+            .get_by_hash(&expired.token_hash)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.get(&active.id).await.unwrap().is_some());
+    }
+    #[tokio::test]
+    async fn test_sweeper_rejects_zero_interval() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let result = spawn_token_sweeper(store, TokenRetention::default(), Duration::ZERO);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
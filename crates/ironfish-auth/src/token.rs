@@ -1,15 +1,29 @@
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{Duration, Utc};
-use ironfish_core::{ApiToken, CreateTokenRequest, CreateTokenResponse, Error, Result};
+use ironfish_core::{
+    ApiToken, CreateTokenRequest, CreateTokenResponse, Error, Result, TOKEN_SCOPES,
+};
 use ring::hmac;
 use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 const TOKEN_PREFIX: &str = "iff_";
+const SERVICE_TOKEN_PREFIX: &str = "ifs_";
 const TOKEN_RANDOM_BYTES: usize = 32;
+const SERVICE_NONCE_BYTES: usize = 16;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceClaims {
+    pub node_id: String,
+    pub token_id: Option<Uuid>,
+    pub request_id: Uuid,
+}
 pub struct TokenManager {
     key: hmac::Key,
     node_id: String,
     default_ttl_days: u32,
+    used_nonces: Mutex<HashMap<String, i64>>,
 }
 impl TokenManager {
     pub fn new(secret: &[u8], node_id: impl Into<String>) -> Self {
@@ -18,6 +32,7 @@ impl TokenManager {
             key,
             node_id: node_id.into(),
             default_ttl_days: 365,
+            used_nonces: Mutex::new(HashMap::new()),
         }
     }
     pub fn with_default_ttl(mut self, days: u32) -> Self {
@@ -31,6 +46,13 @@ impl TokenManager {
         secret
     }
     pub fn create(&self, request: CreateTokenRequest) -> Result<(ApiToken, CreateTokenResponse)> {
+        if let Some(scope) = request
+            .scopes
+            .iter()
+            .find(|s| !TOKEN_SCOPES.contains(&s.as_str()))
+        {
+            return Err(Error::InvalidScope(scope.clone()));
+        }
         let id = Uuid::new_v4();
         let now = Utc::now();
         let expires_at = request
@@ -49,6 +71,15 @@ impl TokenManager {
             created_by_node: self.node_id.clone(),
             revoked: false,
             rate_limit: request.rate_limit,
+            scopes: request.scopes,
+            usage_count: 0,
+            last_used_ip: None,
+            daily_usage: Vec::new(),
+            max_depth: request.max_depth,
+            max_multipv: request.max_multipv,
+            node_budget_per_hour: request.node_budget_per_hour,
+            node_window: None,
+            revoked_at: None,
         };
         let formatted = format!("{}{}", TOKEN_PREFIX, raw_token);
         let response = CreateTokenResponse {
@@ -76,6 +107,68 @@ impl TokenManager {
         let signature = hmac::sign(&self.key, raw_token.as_bytes());
         URL_SAFE_NO_PAD.encode(signature.as_ref())
     }
+    pub fn issue_service_token(
+        &self,
+        ttl: Duration,
+        token_id: Option<Uuid>,
+        request_id: Uuid,
+    ) -> Result<String> {
+        let mut nonce = [0u8; SERVICE_NONCE_BYTES];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::Internal("rng failed".into()))?;
+        let expires_at = (Utc::now() + ttl).timestamp();
+        let payload = format!(
+            "{}.{}.{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(&self.node_id),
+            expires_at,
+            token_id.map(|id| id.to_string()).unwrap_or_default(),
+            request_id,
+            URL_SAFE_NO_PAD.encode(nonce)
+        );
+        let signature = hmac::sign(&self.key, payload.as_bytes());
+        Ok(format!(
+            "{}{}.{}",
+            SERVICE_TOKEN_PREFIX,
+            payload,
+            URL_SAFE_NO_PAD.encode(signature.as_ref())
+        ))
+    }
+    pub fn verify_service_token(&self, token: &str) -> Option<ServiceClaims> {
+        let (payload, signature) = token.strip_prefix(SERVICE_TOKEN_PREFIX)?.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &signature).ok()?;
+        let mut fields = payload.split('.');
+        let (Some(node_id), Some(expires_at), Some(token_id), Some(request_id), Some(nonce), None) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return None;
+        };
+        let expires_at = expires_at.parse::<i64>().ok()?;
+        let now = Utc::now().timestamp();
+        if expires_at <= now {
+            return None;
+        }
+        let claims = ServiceClaims {
+            node_id: String::from_utf8(URL_SAFE_NO_PAD.decode(node_id).ok()?).ok()?,
+            token_id: match token_id {
+                "" => None,
+                id => Some(id.parse().ok()?),
+            },
+            request_id: request_id.parse().ok()?,
+        };
+        let mut used = self.used_nonces.lock().unwrap_or_else(|e| e.into_inner());
+        used.retain(|_, expires_at| *expires_at > now);
+        if used.insert(nonce.to_string(), expires_at).is_some() {
+            return None;
+        }
+        Some(claims)
+    }
     pub fn extract_raw_token(token: &str) -> Option<&str> {
         token.strip_prefix(TOKEN_PREFIX)
     }
@@ -87,6 +180,9 @@ impl TokenManager {
         }
     }
 }
+pub fn secrets_match(expected: &str, presented: &str) -> bool {
+    expected.as_bytes().ct_eq(presented.as_bytes()).into()
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,6 +194,10 @@ mod tests {
             name: Some("test".into()),
             expires_in_days: Some(30),
             rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
         };
         let (token, response) = manager.create(request).unwrap();
         assert!(response.token.starts_with(TOKEN_PREFIX));
@@ -105,6 +205,13 @@ mod tests {
         assert!(TokenManager::validate_format(&response.token));
     }
     #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match("cluster-secret", "cluster-secret"));
+        assert!(!secrets_match("cluster-secret", "cluster-secreT"));
+        assert!(!secrets_match("cluster-secret", "cluster"));
+        assert!(!secrets_match("cluster-secret", ""));
+    }
+    #[test]
     fn test_token_hash() {
         let secret = TokenManager::generate_secret();
         let manager = TokenManager::new(&secret, "test-node");
@@ -113,4 +220,74 @@ mod tests {
         let hash2 = manager.hash_token(raw);
         assert_eq!(hash1, hash2);
     }
+    #[test]
+    fn test_service_token_round_trip() {
+        let secret = TokenManager::generate_secret();
+        let manager = TokenManager::new(&secret, "node-a");
+        let token_id = Uuid::new_v4();
+        let request_id = Uuid::new_v4();
+        let issue = |ttl| {
+            manager
+                .issue_service_token(ttl, Some(token_id), request_id)
+                .unwrap()
+        };
+        let token = issue(Duration::seconds(10));
+        assert!(token.starts_with(SERVICE_TOKEN_PREFIX));
+        let stranger = TokenManager::new(&TokenManager::generate_secret(), "node-c");
+        assert!(stranger.verify_service_token(&token).is_none());
+        let forged = token.replacen("ifs_", "ifs_x", 1);
+        let peer = TokenManager::new(&secret, "node-b");
+        assert!(peer.verify_service_token(&forged).is_none());
+        assert_eq!(
+            peer.verify_service_token(&token),
+            Some(ServiceClaims {
+                node_id: "node-a".to_string(),
+                token_id: Some(token_id),
+                request_id,
+            })
+        );
+        assert!(peer.verify_service_token(&token).is_none());
+        assert!(peer
+            .verify_service_token(&issue(Duration::seconds(10)))
+            .is_some());
+        assert!(peer
+            .verify_service_token(&issue(Duration::seconds(-1)))
+            .is_none());
+        let anonymous = manager
+            .issue_service_token(Duration::seconds(10), None, request_id)
+            .unwrap();
+        assert_eq!(
+            peer.verify_service_token(&anonymous)
+                .and_then(|claims| claims.token_id),
+            None
+        );
+    }
+    #[test]
+    fn test_token_scopes() {
+        let secret = TokenManager::generate_secret();
+        let manager = TokenManager::new(&secret, "test-node");
+        let (token, _) = manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: vec!["bestmove".into()],
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        assert!(token.has_scope("bestmove"));
+        assert!(!token.has_scope("analyze"));
+        let result = manager.create(CreateTokenRequest {
+            name: None,
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: vec!["admin".into()],
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        });
+        assert!(matches!(result, Err(Error::InvalidScope(s)) if s == "admin"));
+    }
 }
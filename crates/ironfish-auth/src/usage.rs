@@ -0,0 +1,177 @@
+use chrono::{NaiveDate, Utc};
+use ironfish_core::{Result, TokenStore, UsageDelta};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+use uuid::Uuid;
+const DEFAULT_FLUSH_EVERY: u64 = 100;
+pub struct UsageTracker {
+    pending: Mutex<HashMap<(Uuid, NaiveDate), UsageDelta>>,
+    since_flush: AtomicU64,
+    flush_every: u64,
+    flushing: tokio::sync::Mutex<()>,
+}
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            since_flush: AtomicU64::new(0),
+            flush_every: DEFAULT_FLUSH_EVERY,
+            flushing: tokio::sync::Mutex::new(()),
+        }
+    }
+    pub fn with_flush_every(mut self, requests: u64) -> Self {
+        self.flush_every = requests.max(1);
+        self
+    }
+    pub fn record(&self, id: Uuid, ip: Option<String>) -> bool {
+        let now = Utc::now();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let delta = pending
+                .entry((id, now.date_naive()))
+                .or_insert_with(|| UsageDelta {
+                    count: 0,
+                    last_used_at: now,
+                    last_used_ip: None,
+                });
+            delta.count += 1;
+            delta.last_used_at = delta.last_used_at.max(now);
+            if ip.is_some() {
+                delta.last_used_ip = ip;
+            }
+        }
+        self.since_flush.fetch_add(1, Ordering::SeqCst) + 1 >= self.flush_every
+    }
+    pub fn pending(&self) -> u64 {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|delta| delta.count)
+            .sum()
+    }
+    pub async fn flush<S: TokenStore + ?Sized>(&self, store: &S) -> Result<usize> {
+        let _guard = self.flushing.lock().await;
+        self.since_flush.store(0, Ordering::SeqCst);
+        let batch: Vec<_> = self.pending.lock().unwrap().drain().collect();
+        let mut flushed = 0;
+        let mut failed = Vec::new();
+        let mut error = None;
+        for ((id, date), delta) in batch {
+            if error.is_some() {
+                failed.push(((id, date), delta));
+                continue;
+            }
+            match store.record_usage(&id, &delta).await {
+                Ok(()) => flushed += 1,
+                Err(e) => {
+                    failed.push(((id, date), delta));
+                    error = Some(e);
+                }
+            }
+        }
+        if !failed.is_empty() {
+            let mut pending = self.pending.lock().unwrap();
+            for (key, delta) in failed {
+                let entry = pending.entry(key).or_insert_with(|| UsageDelta {
+                    count: 0,
+                    last_used_at: delta.last_used_at,
+                    last_used_ip: None,
+                });
+                entry.count += delta.count;
+                entry.last_used_at = entry.last_used_at.max(delta.last_used_at);
+                if entry.last_used_ip.is_none() {
+                    entry.last_used_ip = delta.last_used_ip;
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(flushed),
+        }
+    }
+}
+pub fn spawn_usage_flusher(
+    tracker: Arc<UsageTracker>,
+    store: Arc<dyn TokenStore>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(interval);
+        loop {
+            timer.tick().await;
+            if tracker.pending() == 0 || !store.health().is_writable() {
+                continue;
+            }
+            if let Err(e) = tracker.flush(store.as_ref()).await {
+                warn!("token usage flush failed: {}", e);
+            }
+        }
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledTokenStore, TokenManager};
+    use ironfish_core::CreateTokenRequest;
+    #[tokio::test]
+    async fn test_concurrent_usage_is_not_lost() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+        let (token, _) = manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        store.create(token.clone()).await.unwrap();
+        let tracker = Arc::new(UsageTracker::new().with_flush_every(7));
+        let tasks: Vec<_> = (0..8)
+            .map(|worker| {
+                let tracker = tracker.clone();
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..25 {
+                        if tracker.record(token.id, Some(format!("10.0.0.{}", worker))) {
+                            tracker.flush(store.as_ref()).await.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        tracker.flush(store.as_ref()).await.unwrap();
+        assert_eq!(tracker.pending(), 0);
+        let stored = store.get(&token.id).await.unwrap().unwrap();
+        assert_eq!(stored.usage_count, 200);
+        assert!(stored.last_used_at.is_some());
+        assert!(stored.last_used_ip.is_some());
+        assert_eq!(stored.daily_usage.iter().map(|d| d.count).sum::<u64>(), 200);
+    }
+    #[tokio::test]
+    async fn test_failed_flush_keeps_pending_usage() {
+        let store = SledTokenStore::in_memory().unwrap();
+        let tracker = UsageTracker::new();
+        let id = Uuid::new_v4();
+        assert!(!tracker.record(id, None));
+        store.mark_read_only();
+        assert!(tracker.flush(&store).await.is_err());
+        assert_eq!(tracker.pending(), 1);
+    }
+}
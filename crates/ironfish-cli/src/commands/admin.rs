@@ -1,5 +1,11 @@
+use chrono::{DateTime, Utc};
 use clap::Subcommand;
-use serde::Deserialize;
+use ironfish_client::AdminClient;
+use ironfish_core::{AuditAction, AuditEntry, AuditOutcome, AuditQuery};
+use ironfish_server::config::Config;
+use std::path::PathBuf;
+use std::time::Duration;
+use tabled::{Table, Tabled};
 #[derive(Subcommand)]
 pub enum AdminCommands {
     Leader,
@@ -11,25 +17,70 @@ pub enum AdminCommands {
         #[command(subcommand)]
         command: ConfigCommands,
     },
+    Audit {
+        #[arg(short, long)]
+        action: Option<AuditAction>,
+        #[arg(short, long)]
+        since: Option<DateTime<Utc>>,
+        #[arg(short, long, default_value_t = 50)]
+        limit: usize,
+        #[arg(short, long)]
+        follow: bool,
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+    },
+    CheckConfig {
+        #[arg(short, long)]
+        file: PathBuf,
+    },
 }
 #[derive(Subcommand)]
 pub enum ConfigCommands {
     Get { key: String },
     Set { key: String, value: String },
 }
-#[derive(Debug, Deserialize)]
-struct ClusterStatus {
-    leader_id: Option<String>,
-    term: u64,
+#[derive(Tabled)]
+struct AuditRow {
+    #[tabled(rename = "Seq")]
+    seq: u64,
+    #[tabled(rename = "Time")]
+    timestamp: String,
+    #[tabled(rename = "Action")]
+    action: String,
+    #[tabled(rename = "Actor")]
+    actor: String,
+    #[tabled(rename = "Target")]
+    target: String,
+    #[tabled(rename = "Source")]
+    source_ip: String,
+    #[tabled(rename = "Node")]
+    node_id: String,
+    #[tabled(rename = "Outcome")]
+    outcome: String,
+}
+impl From<AuditEntry> for AuditRow {
+    fn from(e: AuditEntry) -> Self {
+        let outcome = match e.outcome {
+            AuditOutcome::Success => "success".to_string(),
+            AuditOutcome::Failure(reason) => format!("failure: {}", reason),
+        };
+        Self {
+            seq: e.seq,
+            timestamp: e.timestamp.to_rfc3339(),
+            action: e.action.to_string(),
+            actor: e.actor,
+            target: e.target.unwrap_or_else(|| "-".to_string()),
+            source_ip: e.source_ip.unwrap_or_else(|| "-".to_string()),
+            node_id: e.node_id,
+            outcome,
+        }
+    }
 }
-pub async fn execute(command: AdminCommands, endpoint: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
+pub async fn execute(command: AdminCommands, admin: &AdminClient) -> anyhow::Result<()> {
     match command {
         AdminCommands::Leader => {
-            let url = format!("{}/_admin/cluster/status", endpoint);
-            let response = client.get(&url).send().await?;
-            let status: ClusterStatus = response.json().await?;
-            match status.leader_id {
+            let status = admin.cluster_status().await?;
+            match status.leader {
                 Some(leader) => {
                     println!("Current leader: {}", leader);
                     println!("Term: {}", status.term);
@@ -43,6 +94,42 @@ pub async fn execute(command: AdminCommands, endpoint: &str) -> anyhow::Result<(
             println!("Promoting node {} to leader...", node_id);
             println!("Note: This will trigger a new election");
         }
+        AdminCommands::Audit {
+            action,
+            since,
+            limit,
+            follow,
+            interval,
+        } => {
+            let mut after: Option<u64> = None;
+            loop {
+                let query = AuditQuery {
+                    since,
+                    after,
+                    action,
+                    limit: Some(limit),
+                };
+                let page = admin.audit(&query).await?;
+                let more = page.next_after.is_some();
+                if let Some(last) = page.entries.last() {
+                    after = Some(last.seq);
+                }
+                if !page.entries.is_empty() {
+                    let rows: Vec<AuditRow> =
+                        page.entries.into_iter().map(AuditRow::from).collect();
+                    println!("{}", Table::new(&rows));
+                } else if !follow && after.is_none() {
+                    println!("No audit entries found");
+                }
+                if more {
+                    continue;
+                }
+                if !follow {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        }
         AdminCommands::Config { command } => match command {
             ConfigCommands::Get { key } => {
                 println!("Config key '{}' not found", key);
@@ -51,6 +138,12 @@ pub async fn execute(command: AdminCommands, endpoint: &str) -> anyhow::Result<(
                 println!("Set config '{}' = '{}'", key, value);
             }
         },
+        AdminCommands::CheckConfig { file } => {
+            let config = Config::read(&file)?;
+            println!("{}", config.effective_toml()?);
+            config.validate()?;
+            println!("{} is valid", file.display());
+        }
     }
     Ok(())
 }
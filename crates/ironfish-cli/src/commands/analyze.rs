@@ -0,0 +1,242 @@
+use clap::Subcommand;
+use futures_util::StreamExt;
+use ironfish_client::{AnalysisProgressEvent, IronfishClient};
+use ironfish_core::{
+    AnalysisRequest, AnalysisResult, BestMoveRequest, ChessPosition, GameOver, Move,
+    PrincipalVariation, Score, TablebaseWdl, Variant,
+};
+use std::io::Write;
+use tabled::{Table, Tabled};
+#[derive(Subcommand)]
+pub enum AnalyzeCommands {
+    Position {
+        #[arg(short, long)]
+        fen: String,
+        #[arg(short, long, default_value_t = 20)]
+        depth: u8,
+        #[arg(short, long, default_value_t = 1)]
+        multipv: u8,
+        #[arg(long, default_value_t = Variant::Standard)]
+        variant: Variant,
+        #[arg(long)]
+        json: bool,
+        #[arg(short, long)]
+        watch: bool,
+    },
+    Bestmove {
+        #[arg(short, long)]
+        fen: String,
+        #[arg(short, long)]
+        movetime: Option<u64>,
+        #[arg(long, default_value_t = Variant::Standard)]
+        variant: Variant,
+        #[arg(long)]
+        json: bool,
+    },
+}
+#[derive(Tabled)]
+struct PvRow {
+    #[tabled(rename = "#")]
+    rank: u8,
+    #[tabled(rename = "Eval")]
+    evaluation: String,
+    #[tabled(rename = "Depth")]
+    depth: u8,
+    #[tabled(rename = "Line")]
+    moves: String,
+}
+impl From<&PrincipalVariation> for PvRow {
+    fn from(pv: &PrincipalVariation) -> Self {
+        Self {
+            rank: pv.rank,
+            evaluation: format_eval(&pv.evaluation),
+            depth: pv.depth,
+            moves: format_line(pv),
+        }
+    }
+}
+fn resolve_fen(fen: String) -> String {
+    if fen == "startpos" {
+        ChessPosition::starting().fen
+    } else {
+        fen
+    }
+}
+fn format_eval(eval: &Score) -> String {
+    match *eval {
+        Score::MateIn(moves) => format!("#{}", moves),
+        Score::Centipawns(cp) => format!("{:+.2}", cp as f64 / 100.0),
+        Score::Tablebase(TablebaseWdl::Win) => "TB win".to_string(),
+        Score::Tablebase(TablebaseWdl::Loss) => "TB loss".to_string(),
+        Score::Tablebase(TablebaseWdl::Draw) => "TB draw".to_string(),
+    }
+}
+fn format_line(pv: &PrincipalVariation) -> String {
+    pv.moves
+        .iter()
+        .map(|m| m.to_uci())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+fn render_board(fen: &str) -> String {
+    let placement = fen.split_whitespace().next().unwrap_or_default();
+    let mut out = String::new();
+    for (i, rank) in placement.split('/').enumerate() {
+        out.push_str(&format!("  {} ", 8 - i));
+        for c in rank.chars() {
+            match c.to_digit(10) {
+                Some(n) => (0..n).for_each(|_| out.push_str(" .")),
+                None => {
+                    out.push(' ');
+                    out.push(c);
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("     a b c d e f g h");
+    out
+}
+fn format_best_move(best_move: Option<&Move>, game_over: Option<GameOver>) -> String {
+    match (best_move, game_over) {
+        (Some(mv), _) => mv.to_uci(),
+        (None, Some(game_over)) => format!("none ({})", game_over.as_str()),
+        (None, None) => "none".to_string(),
+    }
+}
+fn print_result(result: &AnalysisResult) {
+    println!("{}", render_board(&result.fen));
+    println!();
+    let side = match ChessPosition::new(&result.fen).side_to_move() {
+        Some(color) => format!(" ({:?} to move)", color),
+        None => String::new(),
+    };
+    println!(
+        "  Best Move: {}",
+        format_best_move(result.best_move.as_ref(), result.game_over)
+    );
+    if let Some(ref ponder) = result.ponder {
+        println!("  Ponder: {}", ponder.to_uci());
+    }
+    println!("  Evaluation: {}{}", format_eval(&result.evaluation), side);
+    println!(
+        "  Depth: {} (stopped by {})",
+        result.depth_reached,
+        result.stopped_by.as_str()
+    );
+    println!(
+        "  Nodes: {} in {} ms{}",
+        result.nodes_searched,
+        result.time_ms,
+        if result.cached { " (cached)" } else { "" }
+    );
+    if !result.principal_variations.is_empty() {
+        let rows: Vec<PvRow> = result
+            .principal_variations
+            .iter()
+            .map(PvRow::from)
+            .collect();
+        println!();
+        println!("{}", Table::new(&rows));
+    }
+}
+async fn watch(
+    client: &IronfishClient,
+    request: &AnalysisRequest,
+) -> anyhow::Result<AnalysisResult> {
+    let mut stream = Box::pin(client.analyze_streaming(request).await?);
+    let mut stdout = std::io::stdout();
+    while let Some(event) = stream.next().await {
+        match event? {
+            AnalysisProgressEvent::Progress {
+                current_depth,
+                target_depth,
+                evaluation,
+                principal_variations,
+                nodes_per_second,
+                ..
+            } => {
+                let eval = evaluation
+                    .as_ref()
+                    .map(format_eval)
+                    .unwrap_or_else(|| "-".to_string());
+                let line = principal_variations
+                    .iter()
+                    .min_by_key(|pv| pv.rank)
+                    .map(format_line)
+                    .unwrap_or_default();
+                write!(
+                    stdout,
+                    "\r\x1b[2Kdepth {}/{}  eval {}  nps {}  {}",
+                    current_depth, target_depth, eval, nodes_per_second, line
+                )?;
+                stdout.flush()?;
+            }
+            AnalysisProgressEvent::Complete(result) => {
+                writeln!(stdout)?;
+                return Ok(*result);
+            }
+            AnalysisProgressEvent::Cancelled { analysis_id } => {
+                writeln!(stdout)?;
+                anyhow::bail!("analysis {} was cancelled", analysis_id);
+            }
+        }
+    }
+    anyhow::bail!("stream closed before the analysis completed")
+}
+pub async fn execute(command: AnalyzeCommands, client: &IronfishClient) -> anyhow::Result<()> {
+    match command {
+        AnalyzeCommands::Position {
+            fen,
+            depth,
+            multipv,
+            variant,
+            json,
+            watch: live,
+        } => {
+            let request = AnalysisRequest::new(resolve_fen(fen))
+                .with_depth(depth)
+                .with_multipv(multipv)
+                .with_variant(variant);
+            client.validate_fen(&request.fen, variant)?;
+            let result = if live {
+                watch(client, &request).await?
+            } else {
+                client.analyze(&request).await?
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&result)?);
+            } else {
+                print_result(&result);
+            }
+        }
+        AnalyzeCommands::Bestmove {
+            fen,
+            movetime,
+            variant,
+            json,
+        } => {
+            let mut request = BestMoveRequest::new(resolve_fen(fen));
+            request.variant = variant;
+            if movetime.is_some() {
+                request.movetime = movetime;
+            }
+            client.validate_fen(&request.fen, variant)?;
+            let response = client.best_move(&request).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&response)?);
+            } else {
+                println!("{}", render_board(&request.fen));
+                println!();
+                println!(
+                    "  Best Move: {}",
+                    format_best_move(response.best_move.as_ref(), response.game_over)
+                );
+                if let Some(ponder) = response.ponder {
+                    println!("  Ponder: {}", ponder.to_uci());
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,81 +1,306 @@
+use chrono::{DateTime, Local};
 use clap::Subcommand;
-use serde::Deserialize;
+use ironfish_client::AdminClient;
+use ironfish_core::{ClusterStatus, NodeId, NodeStatus};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::time::Duration;
 use tabled::{Table, Tabled};
+const WATCH_EVENT_LIMIT: usize = 10;
 #[derive(Subcommand)]
 pub enum ClusterCommands {
     Init,
     Join {
         #[arg(short, long)]
         address: String,
+        #[arg(short, long)]
+        node_id: String,
     },
     Leave,
     Status,
+    Watch {
+        #[arg(short, long, default_value = "2s", value_parser = parse_interval)]
+        interval: Duration,
+        #[arg(long)]
+        json: bool,
+    },
 }
-#[derive(Debug, Deserialize)]
-struct ClusterStatus {
-    nodes: Vec<NodeStatus>,
-    leader_id: Option<String>,
-    term: u64,
-    healthy: bool,
+#[derive(Tabled)]
+struct NodeRow {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "Address")]
+    address: String,
+    #[tabled(rename = "State")]
+    state: String,
+    #[tabled(rename = "Uptime")]
+    uptime_seconds: u64,
+    #[tabled(rename = "CPU")]
+    cpu: String,
+    #[tabled(rename = "Queue")]
+    queue: String,
 }
-#[derive(Debug, Deserialize, Tabled)]
-struct NodeStatus {
+impl From<NodeStatus> for NodeRow {
+    fn from(node: NodeStatus) -> Self {
+        Self {
+            id: node.info.id.to_string(),
+            address: node.info.address.to_string(),
+            state: format_state(&node),
+            uptime_seconds: node.uptime_seconds,
+            cpu: format_cpu(&node),
+            queue: node
+                .metrics
+                .as_ref()
+                .map(|metrics| metrics.queue_depth.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+#[derive(Tabled)]
+struct WatchRow {
     #[tabled(rename = "ID")]
     id: String,
     #[tabled(rename = "Address")]
     address: String,
     #[tabled(rename = "State")]
     state: String,
+    #[tabled(rename = "Leader")]
+    leader: &'static str,
+    #[tabled(rename = "Term")]
+    term: u64,
     #[tabled(rename = "Uptime")]
     uptime_seconds: u64,
+    #[tabled(rename = "CPU")]
+    cpu: String,
+}
+impl WatchRow {
+    fn new(node: &NodeStatus, leader: Option<&NodeId>) -> Self {
+        Self {
+            id: node.info.id.to_string(),
+            address: node.info.address.to_string(),
+            state: format_state(node),
+            leader: if leader == Some(&node.info.id) {
+                "*"
+            } else {
+                ""
+            },
+            term: node.term,
+            uptime_seconds: node.uptime_seconds,
+            cpu: format_cpu(node),
+        }
+    }
+}
+#[derive(Default)]
+struct ClusterWatch {
+    nodes: HashMap<NodeId, String>,
+    leader: Option<NodeId>,
+    started: bool,
+}
+impl ClusterWatch {
+    fn observe(&mut self, status: &ClusterStatus) -> Vec<String> {
+        let nodes: HashMap<NodeId, String> = status
+            .nodes
+            .iter()
+            .map(|node| (node.info.id.clone(), format_state(node)))
+            .collect();
+        let mut events = Vec::new();
+        if self.started {
+            for node in &status.nodes {
+                match self.nodes.get(&node.info.id) {
+                    None => events.push(format!("node {} joined", node.info.id)),
+                    Some(previous) if *previous != nodes[&node.info.id] => events.push(format!(
+                        "node {} changed {} -> {}",
+                        node.info.id, previous, nodes[&node.info.id]
+                    )),
+                    Some(_) => {}
+                }
+            }
+            let mut left: Vec<&NodeId> = self
+                .nodes
+                .keys()
+                .filter(|id| !nodes.contains_key(*id))
+                .collect();
+            left.sort_by_key(|id| id.to_string());
+            events.extend(left.into_iter().map(|id| format!("node {} left", id)));
+            if self.leader != status.leader {
+                events.push(format!(
+                    "leader changed {} -> {} (term {})",
+                    format_leader(self.leader.as_ref()),
+                    format_leader(status.leader.as_ref()),
+                    status.term
+                ));
+            }
+        }
+        self.nodes = nodes;
+        self.leader = status.leader.clone();
+        self.started = true;
+        events
+    }
+}
+fn format_state(node: &NodeStatus) -> String {
+    match node.draining {
+        true => format!("{:?} (draining)", node.state),
+        false => format!("{:?}", node.state),
+    }
+}
+fn format_cpu(node: &NodeStatus) -> String {
+    match (&node.metrics, node.metrics_stale) {
+        (Some(metrics), false) => format!("{:.0}%", metrics.cpu_usage * 100.0),
+        (Some(metrics), true) => format!("{:.0}% (stale)", metrics.cpu_usage * 100.0),
+        (None, _) => "-".to_string(),
+    }
+}
+fn format_leader(leader: Option<&NodeId>) -> String {
+    leader
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| "none".into())
+}
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval '{}'", value))?;
+    let interval = match unit {
+        "ms" => Duration::from_millis(number),
+        "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        _ => return Err(format!("invalid interval unit '{}', use ms, s or m", unit)),
+    };
+    match interval.is_zero() {
+        true => Err("interval must be greater than zero".into()),
+        false => Ok(interval),
+    }
+}
+fn render_watch(
+    out: &mut impl Write,
+    status: &ClusterStatus,
+    events: &VecDeque<(DateTime<Local>, String)>,
+    interval: Duration,
+) -> std::io::Result<()> {
+    write!(out, "\x1b[H\x1b[2J")?;
+    writeln!(
+        out,
+        "Cluster (every {:?}, Ctrl-C to exit)  leader {}  term {}  healthy {}",
+        interval,
+        format_leader(status.leader.as_ref()),
+        status.term,
+        status.healthy
+    )?;
+    writeln!(out)?;
+    let rows: Vec<WatchRow> = status
+        .nodes
+        .iter()
+        .map(|node| WatchRow::new(node, status.leader.as_ref()))
+        .collect();
+    writeln!(out, "{}", Table::new(&rows))?;
+    writeln!(out)?;
+    writeln!(out, "Events:")?;
+    for (at, event) in events {
+        writeln!(out, "  {}  {}", at.format("%H:%M:%S"), event)?;
+    }
+    out.flush()
 }
-pub async fn execute(command: ClusterCommands, endpoint: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
+async fn watch(admin: &AdminClient, interval: Duration, json: bool) -> anyhow::Result<()> {
+    let mut tracker = ClusterWatch::default();
+    let mut events: VecDeque<(DateTime<Local>, String)> = VecDeque::new();
+    let mut ticker = tokio::time::interval(interval);
+    let mut stdout = std::io::stdout();
+    if !json {
+        write!(stdout, "\x1b[?1049h\x1b[?25l")?;
+    }
+    let poll = async {
+        loop {
+            ticker.tick().await;
+            let now = Local::now();
+            let status = match admin.cluster_status().await {
+                Ok(status) => status,
+                Err(e) if json => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+                Err(e) => {
+                    events.push_back((now, format!("status request failed: {}", e)));
+                    while events.len() > WATCH_EVENT_LIMIT {
+                        events.pop_front();
+                    }
+                    continue;
+                }
+            };
+            let changes = tracker.observe(&status);
+            if json {
+                let document = serde_json::json!({
+                    "timestamp": now.to_rfc3339(),
+                    "status": status,
+                    "events": changes,
+                });
+                writeln!(stdout, "{}", document)?;
+                stdout.flush()?;
+                continue;
+            }
+            events.extend(changes.into_iter().map(|event| (now, event)));
+            while events.len() > WATCH_EVENT_LIMIT {
+                events.pop_front();
+            }
+            render_watch(&mut stdout, &status, &events, interval)?;
+        }
+    };
+    let result: anyhow::Result<()> = tokio::select! {
+        signal = tokio::signal::ctrl_c() => signal.map_err(Into::into),
+        result = poll => result,
+    };
+    if !json {
+        write!(stdout, "\x1b[?25h\x1b[?1049l")?;
+        stdout.flush()?;
+    }
+    result
+}
+pub async fn execute(command: ClusterCommands, admin: &AdminClient) -> anyhow::Result<()> {
     match command {
         ClusterCommands::Init => {
             println!("Initializing cluster...");
             println!("This node is now the cluster leader.");
         }
-        ClusterCommands::Join { address } => {
-            let url = format!("{}/_admin/cluster/join", endpoint);
-            let body = serde_json::json!({ "address": address });
-            let response = client.post(&url).json(&body).send().await?;
-            if response.status().is_success() {
-                println!("Successfully joined cluster at {}", address);
-            } else {
-                let error: serde_json::Value = response.json().await?;
-                println!("Failed to join cluster: {}", error);
-            }
-        }
-        ClusterCommands::Leave => {
-            let url = format!("{}/_admin/cluster/leave", endpoint);
-            let response = client.post(&url).send().await?;
-            if response.status().is_success() {
-                println!("Successfully left cluster");
-            } else {
-                let error: serde_json::Value = response.json().await?;
-                println!("Failed to leave cluster: {}", error);
+        ClusterCommands::Join { address, node_id } => {
+            match admin.join(&node_id, &address, None).await {
+                Ok(response) if response.accepted => {
+                    println!("Node {} at {} joined the cluster", node_id, address)
+                }
+                Ok(response) => println!(
+                    "Join rejected: {}",
+                    response.reason.as_deref().unwrap_or("unknown reason")
+                ),
+                Err(e) => println!("Failed to join cluster: {}", e),
             }
         }
+        ClusterCommands::Leave => match admin.leave().await {
+            Ok(()) => println!("Successfully left cluster"),
+            Err(e) => println!("Failed to leave cluster: {}", e),
+        },
         ClusterCommands::Status => {
-            let url = format!("{}/_admin/cluster/status", endpoint);
-            let response = client.get(&url).send().await?;
-            let status: ClusterStatus = response.json().await?;
+            let status = admin.cluster_status().await?;
             println!("Cluster Status:");
             println!(
                 "  Leader: {}",
-                status.leader_id.unwrap_or_else(|| "none".into())
+                status
+                    .leader
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| "none".into())
             );
             println!("  Term: {}", status.term);
             println!("  Healthy: {}", status.healthy);
             println!();
             if !status.nodes.is_empty() {
-                let table = Table::new(&status.nodes).to_string();
+                let rows: Vec<NodeRow> = status.nodes.into_iter().map(NodeRow::from).collect();
+                let table = Table::new(&rows).to_string();
                 println!("{}", table);
             } else {
                 println!("No nodes in cluster");
             }
         }
+        ClusterCommands::Watch { interval, json } => watch(admin, interval, json).await?,
     }
     Ok(())
 }
@@ -0,0 +1,96 @@
+use crate::config::{CliConfig, Profile};
+use clap::Subcommand;
+use std::path::Path;
+use tabled::{Table, Tabled};
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    SetProfile {
+        name: String,
+        #[arg(long)]
+        default: bool,
+    },
+    Get {
+        field: String,
+    },
+    List,
+}
+#[derive(Tabled)]
+struct ProfileRow {
+    #[tabled(rename = "Profile")]
+    name: String,
+    #[tabled(rename = "Endpoint")]
+    endpoint: String,
+    #[tabled(rename = "Token")]
+    token: &'static str,
+    #[tabled(rename = "Admin Key")]
+    admin_key: &'static str,
+    #[tabled(rename = "Default")]
+    default: &'static str,
+}
+fn mask(value: &Option<String>) -> &'static str {
+    if value.is_some() {
+        "set"
+    } else {
+        "-"
+    }
+}
+pub fn execute(
+    command: ConfigCommands,
+    path: &Path,
+    values: Profile,
+    active: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut config = CliConfig::load(path)?;
+    match command {
+        ConfigCommands::SetProfile { name, default } => {
+            config
+                .profiles
+                .entry(name.clone())
+                .or_default()
+                .merge(values);
+            if default || config.default_profile.is_none() {
+                config.default_profile = Some(name.clone());
+            }
+            config.save(path)?;
+            println!("Profile {} saved to {}", name, path.display());
+        }
+        ConfigCommands::Get { field } => {
+            let Some(name) = active else {
+                anyhow::bail!(
+                    "no profile selected: pass --profile, set IRONFISH_PROFILE or set a default_profile in {}",
+                    path.display()
+                );
+            };
+            let profile = config.profiles.get(name).ok_or_else(|| {
+                anyhow::anyhow!("profile {} not found in {}", name, path.display())
+            })?;
+            match profile.field(&field)? {
+                Some(value) => println!("{}", value),
+                None => anyhow::bail!("{} is not set in profile {}", field, name),
+            }
+        }
+        ConfigCommands::List => {
+            if config.profiles.is_empty() {
+                println!("No profiles in {}", path.display());
+                return Ok(());
+            }
+            let rows: Vec<ProfileRow> = config
+                .profiles
+                .iter()
+                .map(|(name, profile)| ProfileRow {
+                    name: name.clone(),
+                    endpoint: profile.endpoint.clone().unwrap_or_else(|| "-".to_string()),
+                    token: mask(&profile.token),
+                    admin_key: mask(&profile.admin_key),
+                    default: if config.default_profile.as_deref() == Some(name) {
+                        "*"
+                    } else {
+                        ""
+                    },
+                })
+                .collect();
+            println!("{}", Table::new(rows));
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,6 @@
 pub mod admin;
+pub mod analyze;
 pub mod cluster;
+pub mod config;
 pub mod node;
 pub mod token;
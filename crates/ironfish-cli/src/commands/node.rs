@@ -1,52 +1,219 @@
+use chrono::Utc;
 use clap::Subcommand;
-use serde::Deserialize;
+use ironfish_client::{AdminClient, IronfishClient};
+use ironfish_core::{
+    ActiveAnalysisStatus, EngineSlotStatus, HealthCheck, NodeDiagnostics, PeerStatus,
+};
+use tabled::{Table, Tabled};
 #[derive(Subcommand)]
 pub enum NodeCommands {
     Info,
+    Status,
     Health,
     Metrics,
+    Drain,
+    Resume,
 }
-#[derive(Debug, Deserialize)]
-struct HealthResponse {
+#[derive(Tabled)]
+struct EngineRow {
+    #[tabled(rename = "Engine")]
+    index: usize,
+    #[tabled(rename = "State")]
+    state: String,
+    #[tabled(rename = "Last Command")]
+    last_command: String,
+    #[tabled(rename = "Restarts")]
+    restarts: u64,
+    #[tabled(rename = "Warmed")]
+    warmed: String,
+}
+impl From<EngineSlotStatus> for EngineRow {
+    fn from(slot: EngineSlotStatus) -> Self {
+        Self {
+            index: slot.index,
+            state: match slot.busy_since {
+                Some(since) => format!("busy {}s", (Utc::now() - since).num_seconds().max(0)),
+                None => "idle".to_string(),
+            },
+            last_command: slot.last_command.unwrap_or_else(|| "-".to_string()),
+            restarts: slot.restarts,
+            warmed: if slot.warmed { "yes" } else { "no" }.to_string(),
+        }
+    }
+}
+#[derive(Tabled)]
+struct AnalysisRow {
+    #[tabled(rename = "Analysis")]
+    id: String,
+    #[tabled(rename = "Depth")]
+    depth: String,
+    #[tabled(rename = "Elapsed")]
+    elapsed: String,
+}
+impl From<ActiveAnalysisStatus> for AnalysisRow {
+    fn from(analysis: ActiveAnalysisStatus) -> Self {
+        Self {
+            id: analysis.id.to_string(),
+            depth: analysis
+                .depth
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            elapsed: format!("{:.1}s", analysis.elapsed_ms as f64 / 1000.0),
+        }
+    }
+}
+#[derive(Tabled)]
+struct PeerRow {
+    #[tabled(rename = "Peer")]
+    id: String,
+    #[tabled(rename = "Address")]
+    address: String,
+    #[tabled(rename = "Healthy")]
+    healthy: bool,
+    #[tabled(rename = "Last Seen")]
+    last_seen: String,
+    #[tabled(rename = "Queue")]
+    queued: usize,
+    #[tabled(rename = "Dropped")]
+    dropped: u64,
+    #[tabled(rename = "Backoff")]
+    backoff: String,
+}
+impl From<PeerStatus> for PeerRow {
+    fn from(peer: PeerStatus) -> Self {
+        Self {
+            id: peer.node_id.to_string(),
+            address: peer.address.to_string(),
+            healthy: peer.healthy,
+            last_seen: format!("{:.1}s ago", peer.last_seen_ms as f64 / 1000.0),
+            queued: peer.queued,
+            dropped: peer.dropped,
+            backoff: match peer.backoff_ms {
+                0 => "-".to_string(),
+                ms => format!(
+                    "{:.1}s ({} failures)",
+                    ms as f64 / 1000.0,
+                    peer.connect_failures
+                ),
+            },
+        }
+    }
+}
+#[derive(Tabled)]
+struct CheckRow {
+    #[tabled(rename = "Check")]
+    name: String,
+    #[tabled(rename = "Status")]
     status: String,
-    node_id: String,
-    version: String,
-}
-#[derive(Debug, Deserialize)]
-struct MetricsResponse {
-    cpu_usage: f32,
-    memory_usage: f32,
-    active_analyses: u32,
-    queue_depth: u32,
-    engines_available: u32,
-    engines_total: u32,
-}
-pub async fn execute(command: NodeCommands, endpoint: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
+    #[tabled(rename = "Latency")]
+    latency: String,
+    #[tabled(rename = "Detail")]
+    detail: String,
+}
+impl From<HealthCheck> for CheckRow {
+    fn from(check: HealthCheck) -> Self {
+        Self {
+            name: check.name,
+            status: format!("{:?}", check.status).to_lowercase(),
+            latency: format!("{}ms", check.latency_ms),
+            detail: check.detail.unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+fn print_diagnostics(diagnostics: NodeDiagnostics) {
+    println!("Node Status:");
+    println!("  ID: {}", diagnostics.node_id);
+    println!("  State: {:?}", diagnostics.state);
+    println!("  Term: {}", diagnostics.term);
+    println!(
+        "  Leader: {}",
+        diagnostics
+            .leader_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "none".into())
+    );
+    println!("  Draining: {}", diagnostics.draining);
+    println!("  Uptime: {}s", diagnostics.uptime_seconds);
+    println!("  Memory Usage: {:.1}%", diagnostics.memory_usage * 100.0);
+    println!("  WebSocket Sessions: {}", diagnostics.ws_sessions.len());
+    if let Some(gossip) = diagnostics.gossip {
+        println!(
+            "  Gossip Entries: {} ({} tombstones, oldest {}s, clock {})",
+            gossip.entries, gossip.tombstones, gossip.oldest_entry_age_seconds, gossip.clock
+        );
+    }
+    let pool = diagnostics.engine_pool;
+    println!();
+    println!(
+        "Engines: {} available, {} active, {} total, {} queued",
+        pool.available, pool.active, pool.size, pool.queue_depth
+    );
+    if let Some(ref name) = pool.engine_name {
+        println!("  Engine: {}", name);
+    }
+    if pool.reserved > 0 {
+        println!(
+            "  Reserved for fast requests: {} ({} free), general: {} free",
+            pool.reserved, pool.reserved_available, pool.general_available
+        );
+    }
+    if !pool.engines.is_empty() {
+        let rows: Vec<EngineRow> = pool.engines.into_iter().map(EngineRow::from).collect();
+        println!("{}", Table::new(&rows));
+    }
+    println!();
+    if diagnostics.analyses.is_empty() {
+        println!("No active analyses");
+    } else {
+        let rows: Vec<AnalysisRow> = diagnostics
+            .analyses
+            .into_iter()
+            .map(AnalysisRow::from)
+            .collect();
+        println!("{}", Table::new(&rows));
+    }
+    println!();
+    if diagnostics.peers.is_empty() {
+        println!("No gossip peers");
+    } else {
+        let rows: Vec<PeerRow> = diagnostics.peers.into_iter().map(PeerRow::from).collect();
+        println!("{}", Table::new(&rows));
+    }
+}
+pub async fn execute(
+    command: NodeCommands,
+    client: &IronfishClient,
+    admin: &AdminClient,
+) -> anyhow::Result<()> {
     match command {
         NodeCommands::Info => {
-            let url = format!("{}/health", endpoint);
-            let response = client.get(&url).send().await?;
-            let health: HealthResponse = response.json().await?;
+            let health = client.health().await?;
             println!("Node Information:");
             println!("  ID: {}", health.node_id);
             println!("  Version: {}", health.version);
             println!("  Status: {}", health.status);
+            println!("  Token Store: {}", health.token_store.as_str());
+            if let Some(engine) = health.engine {
+                println!("  Engine: {}", engine);
+            }
         }
+        NodeCommands::Status => print_diagnostics(admin.diagnostics().await?),
         NodeCommands::Health => {
-            let url = format!("{}/health", endpoint);
-            let response = client.get(&url).send().await?;
-            let health: HealthResponse = response.json().await?;
+            let health = client.health().await?;
             if health.status == "healthy" {
                 println!("Node is healthy");
+            } else if health.status == "draining" {
+                println!("Node is draining");
             } else {
                 println!("Node is unhealthy: {}", health.status);
             }
+            if !health.checks.is_empty() {
+                let rows: Vec<CheckRow> = health.checks.into_iter().map(CheckRow::from).collect();
+                println!("{}", Table::new(rows));
+            }
         }
         NodeCommands::Metrics => {
-            let url = format!("{}/metrics", endpoint);
-            let response = client.get(&url).send().await?;
-            let metrics: MetricsResponse = response.json().await?;
+            let metrics = client.metrics().await?;
             println!("Node Metrics:");
             println!("  CPU Usage: {:.1}%", metrics.cpu_usage * 100.0);
             println!("  Memory Usage: {:.1}%", metrics.memory_usage * 100.0);
@@ -56,6 +223,18 @@ pub async fn execute(command: NodeCommands, endpoint: &str) -> anyhow::Result<()
                 "  Engines: {}/{}",
                 metrics.engines_available, metrics.engines_total
             );
+            println!("  Engines Restarted: {}", metrics.engines_restarted);
+        }
+        NodeCommands::Drain => {
+            let status = admin.drain_node().await?;
+            println!(
+                "Node {} is draining ({} active analyses)",
+                status.node_id, status.active_analyses
+            );
+        }
+        NodeCommands::Resume => {
+            let status = admin.resume_node().await?;
+            println!("Node {} is accepting analyses", status.node_id);
         }
     }
     Ok(())
@@ -1,6 +1,8 @@
 use clap::Subcommand;
-use serde::Deserialize;
+use ironfish_client::AdminClient;
+use ironfish_core::{CreateTokenRequest, DailyUsage, TokenMetadata, TokenReplicaStatus};
 use tabled::{Table, Tabled};
+use uuid::Uuid;
 #[derive(Subcommand)]
 pub enum TokenCommands {
     Create {
@@ -8,21 +10,32 @@ pub enum TokenCommands {
         name: Option<String>,
         #[arg(short, long)]
         expires_in_days: Option<u32>,
+        #[arg(long = "scope", value_delimiter = ',')]
+        scopes: Vec<String>,
+        #[arg(long)]
+        max_depth: Option<u32>,
+        #[arg(long)]
+        max_multipv: Option<u32>,
+        #[arg(long)]
+        node_budget_per_hour: Option<u64>,
     },
     Revoke {
         #[arg(short, long)]
-        id: String,
+        id: Uuid,
     },
     List,
+    Usage {
+        #[arg(short, long)]
+        id: Uuid,
+    },
+    Verify {
+        #[arg(short, long)]
+        id: Uuid,
+    },
+    Purge,
 }
-#[derive(Debug, Deserialize)]
-struct CreateTokenResponse {
-    id: String,
-    token: String,
-    expires_at: Option<String>,
-}
-#[derive(Debug, Deserialize, Tabled)]
-struct TokenInfo {
+#[derive(Tabled)]
+struct TokenRow {
     #[tabled(rename = "ID")]
     id: String,
     #[tabled(rename = "Name", display_with = "display_option")]
@@ -33,57 +46,149 @@ struct TokenInfo {
     expires_at: Option<String>,
     #[tabled(rename = "Revoked")]
     revoked: bool,
+    #[tabled(rename = "Uses")]
+    usage_count: u64,
+    #[tabled(rename = "Last Used", display_with = "display_option")]
+    last_used_at: Option<String>,
+    #[tabled(rename = "Last IP", display_with = "display_option")]
+    last_used_ip: Option<String>,
+}
+#[derive(Tabled)]
+struct UsageRow {
+    #[tabled(rename = "Date")]
+    date: String,
+    #[tabled(rename = "Requests")]
+    count: u64,
+}
+#[derive(Tabled)]
+struct ReplicaRow {
+    #[tabled(rename = "Node")]
+    node_id: String,
+    #[tabled(rename = "Status")]
+    status: &'static str,
+}
+impl From<DailyUsage> for UsageRow {
+    fn from(day: DailyUsage) -> Self {
+        Self {
+            date: day.date.to_string(),
+            count: day.count,
+        }
+    }
+}
+impl From<TokenMetadata> for TokenRow {
+    fn from(token: TokenMetadata) -> Self {
+        Self {
+            id: token.id.to_string(),
+            name: token.name,
+            created_at: token.created_at.to_rfc3339(),
+            expires_at: token.expires_at.map(|t| t.to_rfc3339()),
+            revoked: token.revoked,
+            usage_count: token.usage_count,
+            last_used_at: token.last_used_at.map(|t| t.to_rfc3339()),
+            last_used_ip: token.last_used_ip,
+        }
+    }
 }
 fn display_option(o: &Option<String>) -> String {
     o.clone().unwrap_or_else(|| "-".to_string())
 }
-pub async fn execute(command: TokenCommands, endpoint: &str) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
+pub async fn execute(command: TokenCommands, admin: &AdminClient) -> anyhow::Result<()> {
     match command {
         TokenCommands::Create {
             name,
             expires_in_days,
+            scopes,
+            max_depth,
+            max_multipv,
+            node_budget_per_hour,
         } => {
-            let url = format!("{}/_admin/tokens", endpoint);
-            let body = serde_json::json!({
-                "name": name,
-                "expires_in_days": expires_in_days,
-            });
-            let response = client.post(&url).json(&body).send().await?;
-            if response.status().is_success() {
-                let token: CreateTokenResponse = response.json().await?;
-                println!("Token created successfully!");
-                println!();
-                println!("  ID: {}", token.id);
-                println!("  Token: {}", token.token);
-                if let Some(expires) = token.expires_at {
-                    println!("  Expires: {}", expires);
+            let request = CreateTokenRequest {
+                name,
+                expires_in_days,
+                rate_limit: None,
+                scopes,
+                max_depth,
+                max_multipv,
+                node_budget_per_hour,
+            };
+            match admin.create_token(&request).await {
+                Ok(token) => {
+                    println!("Token created successfully!");
+                    println!();
+                    println!("  ID: {}", token.id);
+                    println!("  Token: {}", token.token);
+                    if let Some(expires) = token.expires_at {
+                        println!("  Expires: {}", expires.to_rfc3339());
+                    }
+                    println!();
+                    println!("Save this token - it won't be shown again!");
                 }
-                println!();
-                println!("Save this token - it won't be shown again!");
-            } else {
-                let error: serde_json::Value = response.json().await?;
-                println!("Failed to create token: {}", error);
+                Err(e) => println!("Failed to create token: {}", e),
+            }
+        }
+        TokenCommands::Revoke { id } => match admin.revoke_token(&id).await {
+            Ok(()) => println!("Token {} revoked successfully", id),
+            Err(e) => println!("Failed to revoke token: {}", e),
+        },
+        TokenCommands::Usage { id } => {
+            let usage = admin.token_usage(&id).await?;
+            println!("Token {}: {} requests", usage.id, usage.usage_count);
+            if let Some(last_used) = usage.last_used_at {
+                println!(
+                    "Last used: {} from {}",
+                    last_used.to_rfc3339(),
+                    usage.last_used_ip.as_deref().unwrap_or("unknown")
+                );
+            }
+            if let Some(budget) = usage.node_budget {
+                println!(
+                    "Node budget: {} of {} used this hour, {} remaining",
+                    budget.used, budget.budget_per_hour, budget.remaining
+                );
+            }
+            if !usage.daily.is_empty() {
+                let rows: Vec<UsageRow> = usage.daily.into_iter().map(UsageRow::from).collect();
+                println!("{}", Table::new(&rows));
             }
         }
-        TokenCommands::Revoke { id } => {
-            let url = format!("{}/_admin/tokens/{}", endpoint, id);
-            let response = client.delete(&url).send().await?;
-            if response.status().is_success() {
-                println!("Token {} revoked successfully", id);
+        TokenCommands::Verify { id } => {
+            let report = admin.token_replicas(&id).await?;
+            let rows: Vec<ReplicaRow> = report
+                .nodes
+                .iter()
+                .map(|(node_id, status)| ReplicaRow {
+                    node_id: node_id.clone(),
+                    status: status.as_str(),
+                })
+                .collect();
+            println!("{}", Table::new(&rows));
+            let pending = report
+                .nodes
+                .values()
+                .filter(|status| {
+                    matches!(
+                        status,
+                        TokenReplicaStatus::Present | TokenReplicaStatus::Unreachable
+                    )
+                })
+                .count();
+            if report.confirmed {
+                println!("Token {} is revoked on every node", id);
             } else {
-                let error: serde_json::Value = response.json().await?;
-                println!("Failed to revoke token: {}", error);
+                println!("Token {} is not yet revoked on {} node(s)", id, pending);
             }
         }
+        TokenCommands::Purge => match admin.purge_tokens().await {
+            Ok(purged) => println!("Purged {} expired or revoked tokens", purged),
+            Err(e) => println!("Failed to purge tokens: {}", e),
+        },
         TokenCommands::List => {
-            let url = format!("{}/_admin/tokens", endpoint);
-            let response = client.get(&url).send().await?;
-            let tokens: Vec<TokenInfo> = response.json().await?;
+            let tokens = admin.list_tokens().await?;
             if tokens.is_empty() {
                 println!("No tokens found");
             } else {
-                let table = Table::new(&tokens).to_string();
+                let rows: Vec<TokenRow> = tokens.into_iter().map(TokenRow::from).collect();
+                let table = Table::new(&rows).to_string();
                 println!("{}", table);
             }
         }
@@ -0,0 +1,284 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:8080";
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster_secret: Option<String>,
+}
+impl Profile {
+    pub fn merge(&mut self, other: Profile) {
+        if other.endpoint.is_some() {
+            self.endpoint = other.endpoint;
+        }
+        if other.token.is_some() {
+            self.token = other.token;
+        }
+        if other.admin_key.is_some() {
+            self.admin_key = other.admin_key;
+        }
+        if other.cluster_secret.is_some() {
+            self.cluster_secret = other.cluster_secret;
+        }
+    }
+    pub fn field(&self, name: &str) -> anyhow::Result<Option<&str>> {
+        let value = match name {
+            "endpoint" => &self.endpoint,
+            "token" => &self.token,
+            "admin_key" => &self.admin_key,
+            "cluster_secret" => &self.cluster_secret,
+            other => anyhow::bail!(
+                "unknown profile field {} (expected endpoint, token, admin_key or cluster_secret)",
+                other
+            ),
+        };
+        Ok(value.as_deref())
+    }
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CliConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+impl CliConfig {
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("ironfish").join("config.toml"))
+    }
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .with_context(|| format!("invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let content = toml::to_string_pretty(self)?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            if path.exists() {
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub profile: Option<String>,
+    pub endpoint: Option<String>,
+    pub token: Option<String>,
+    pub admin_key: Option<String>,
+    pub cluster_secret: Option<String>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub endpoint: String,
+    pub token: Option<String>,
+    pub admin_key: Option<String>,
+    pub cluster_secret: Option<String>,
+    pub profile: Option<String>,
+    pub config_path: Option<PathBuf>,
+}
+impl Settings {
+    pub fn resolve(
+        overrides: Overrides,
+        env_profile: Option<String>,
+        config: &CliConfig,
+        config_path: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let explicit = overrides.profile.or(env_profile);
+        let profile_name = explicit.clone().or_else(|| config.default_profile.clone());
+        let profile = match &profile_name {
+            Some(name) => match config.profiles.get(name) {
+                Some(profile) => profile.clone(),
+                None if explicit.is_some() => anyhow::bail!(
+                    "profile {} not found in {}",
+                    name,
+                    display_path(config_path.as_deref())
+                ),
+                None => Profile::default(),
+            },
+            None => Profile::default(),
+        };
+        Ok(Self {
+            endpoint: overrides
+                .endpoint
+                .or(profile.endpoint)
+                .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string()),
+            token: overrides.token.or(profile.token),
+            admin_key: overrides.admin_key.or(profile.admin_key),
+            cluster_secret: overrides.cluster_secret.or(profile.cluster_secret),
+            profile: profile_name,
+            config_path,
+        })
+    }
+    pub fn missing_credential(&self, credential: &str, flag: &str, env: &str) -> String {
+        let consulted = match &self.profile {
+            Some(name) => format!(
+                "profile {} in {}",
+                name,
+                display_path(self.config_path.as_deref())
+            ),
+            None => format!(
+                "no profile selected ({})",
+                display_path(self.config_path.as_deref())
+            ),
+        };
+        format!(
+            "no {} configured: pass {}, set {} or add it to a profile with `ironfish config set-profile`; consulted {}",
+            credential, flag, env, consulted
+        )
+    }
+}
+fn display_path(path: Option<&Path>) -> String {
+    path.map(|p| p.display().to_string())
+        .unwrap_or_else(|| "no config file".to_string())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn config() -> CliConfig {
+        let mut config = CliConfig {
+            default_profile: Some("staging".to_string()),
+            ..Default::default()
+        };
+        config.profiles.insert(
+            "staging".to_string(),
+            Profile {
+                endpoint: Some("https://staging.example:8080".to_string()),
+                token: Some("staging-token".to_string()),
+                ..Default::default()
+            },
+        );
+        config.profiles.insert(
+            "prod".to_string(),
+            Profile {
+                endpoint: Some("https://prod.example:8080".to_string()),
+                admin_key: Some("prod-admin".to_string()),
+                ..Default::default()
+            },
+        );
+        config
+    }
+    #[test]
+    fn test_precedence_flag_env_profile_default() {
+        let config = config();
+        let settings = Settings::resolve(
+            Overrides {
+                profile: Some("prod".to_string()),
+                endpoint: Some("http://flag:1".to_string()),
+                ..Default::default()
+            },
+            Some("staging".to_string()),
+            &config,
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.profile.as_deref(), Some("prod"));
+        assert_eq!(settings.endpoint, "http://flag:1");
+        assert_eq!(settings.admin_key.as_deref(), Some("prod-admin"));
+        assert_eq!(settings.token, None);
+        let settings = Settings::resolve(
+            Overrides::default(),
+            Some("prod".to_string()),
+            &config,
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.endpoint, "https://prod.example:8080");
+        let settings = Settings::resolve(Overrides::default(), None, &config, None).unwrap();
+        assert_eq!(settings.profile.as_deref(), Some("staging"));
+        assert_eq!(settings.token.as_deref(), Some("staging-token"));
+        let settings = Settings::resolve(
+            Overrides {
+                token: Some("flag-token".to_string()),
+                ..Default::default()
+            },
+            None,
+            &config,
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.token.as_deref(), Some("flag-token"));
+        let settings =
+            Settings::resolve(Overrides::default(), None, &CliConfig::default(), None).unwrap();
+        assert_eq!(settings.endpoint, DEFAULT_ENDPOINT);
+        assert_eq!(settings.profile, None);
+        let missing = Settings::resolve(
+            Overrides {
+                profile: Some("dev".to_string()),
+                ..Default::default()
+            },
+            None,
+            &config,
+            Some(PathBuf::from("/tmp/ironfish.toml")),
+        )
+        .unwrap_err();
+        assert_eq!(
+            missing.to_string(),
+            "profile dev not found in /tmp/ironfish.toml"
+        );
+    }
+    #[test]
+    fn test_missing_credential_names_the_profile() {
+        let settings = Settings::resolve(
+            Overrides::default(),
+            None,
+            &config(),
+            Some(PathBuf::from("/home/me/.config/ironfish/config.toml")),
+        )
+        .unwrap();
+        let message = settings.missing_credential("admin key", "--admin-key", "IRONFISH_ADMIN_KEY");
+        assert!(message.contains("profile staging in /home/me/.config/ironfish/config.toml"));
+    }
+    #[test]
+    fn test_config_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ironfish-cli-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("ironfish").join("config.toml");
+        assert_eq!(CliConfig::load(&path).unwrap(), CliConfig::default());
+        let config = config();
+        config.save(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("[profile.prod]"));
+        assert!(content.contains("default_profile = \"staging\""));
+        assert_eq!(CliConfig::load(&path).unwrap(), config);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::write(&path, "profile = 3").unwrap();
+        assert!(CliConfig::load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
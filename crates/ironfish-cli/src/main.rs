@@ -1,11 +1,26 @@
-use clap::{Parser, Subcommand};
-use ironfish_cli::commands::{admin, cluster, node, token};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use ironfish_cli::commands::{admin, analyze, cluster, config, node, token};
+use ironfish_cli::config::{CliConfig, Overrides, Profile, Settings};
+use ironfish_client::{ClientError, IronfishClient};
+use std::path::PathBuf;
 #[derive(Parser)]
 #[command(name = "ironfish")]
 #[command(author, version, about = "Ironfish Chess Analysis CLI", long_about = None)]
 struct Cli {
-    #[arg(short, long, default_value = "http://localhost:8080")]
-    endpoint: String,
+    #[arg(short, long, env = "IRONFISH_ENDPOINT")]
+    endpoint: Option<String>,
+    #[arg(long, env = "IRONFISH_TOKEN", global = true)]
+    token: Option<String>,
+    #[arg(long, env = "IRONFISH_ADMIN_KEY", global = true)]
+    admin_key: Option<String>,
+    #[arg(long, env = "IRONFISH_CLUSTER_SECRET", global = true)]
+    cluster_secret: Option<String>,
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    #[arg(long, env = "IRONFISH_CONFIG", global = true)]
+    config: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -27,15 +42,115 @@ enum Commands {
         #[command(subcommand)]
         command: admin::AdminCommands,
     },
+    Analyze {
+        #[command(subcommand)]
+        command: analyze::AnalyzeCommands,
+    },
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommands,
+    },
+    Completions {
+        shell: Shell,
+    },
+}
+fn from_command_line(matches: &ArgMatches, id: &str) -> Option<String> {
+    if matches.value_source(id) != Some(ValueSource::CommandLine) {
+        return None;
+    }
+    matches.get_one::<String>(id).cloned()
 }
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::Cluster { command } => cluster::execute(command, &cli.endpoint).await?,
-        Commands::Node { command } => node::execute(command, &cli.endpoint).await?,
-        Commands::Token { command } => token::execute(command, &cli.endpoint).await?,
-        Commands::Admin { command } => admin::execute(command, &cli.endpoint).await?,
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    let config_path = cli.config.clone().or_else(CliConfig::default_path);
+    let env_profile = std::env::var("IRONFISH_PROFILE")
+        .ok()
+        .filter(|p| !p.is_empty());
+    let command = match cli.command {
+        Commands::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "ironfish",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Commands::Config { command } => {
+            let path = config_path.ok_or_else(|| {
+                anyhow::anyhow!("cannot locate a config file: set HOME or --config")
+            })?;
+            let values = match matches.subcommand() {
+                Some((_, sub)) => Profile {
+                    endpoint: from_command_line(&matches, "endpoint"),
+                    token: from_command_line(sub, "token"),
+                    admin_key: from_command_line(sub, "admin_key"),
+                    cluster_secret: from_command_line(sub, "cluster_secret"),
+                },
+                None => Profile::default(),
+            };
+            let active = cli
+                .profile
+                .or(env_profile)
+                .or(CliConfig::load(&path)?.default_profile);
+            return config::execute(command, &path, values, active.as_deref());
+        }
+        command => command,
+    };
+    let file = match &config_path {
+        Some(path) => CliConfig::load(path)?,
+        None => CliConfig::default(),
+    };
+    let settings = Settings::resolve(
+        Overrides {
+            profile: cli.profile,
+            endpoint: cli.endpoint,
+            token: cli.token,
+            admin_key: cli.admin_key,
+            cluster_secret: cli.cluster_secret,
+        },
+        env_profile,
+        &file,
+        config_path,
+    )?;
+    let mut client = IronfishClient::new(settings.endpoint.clone(), settings.token.clone());
+    if let Some(secret) = settings.cluster_secret.clone() {
+        client = client.with_cluster_secret(secret);
+    }
+    let admin = client.admin(settings.admin_key.clone().unwrap_or_default());
+    let needs_admin = !matches!(command, Commands::Analyze { .. });
+    let result = match command {
+        Commands::Cluster { command } => cluster::execute(command, &admin).await,
+        Commands::Node { command } => node::execute(command, &client, &admin).await,
+        Commands::Token { command } => token::execute(command, &admin).await,
+        Commands::Admin { command } => admin::execute(command, &admin).await,
+        Commands::Analyze { command } => analyze::execute(command, &client).await,
+        Commands::Config { .. } | Commands::Completions { .. } => Ok(()),
+    };
+    result.map_err(|e| {
+        if !matches!(
+            e.downcast_ref::<ClientError>(),
+            Some(ClientError::Unauthorized(_))
+        ) {
+            return e;
+        }
+        let hint = if needs_admin && settings.admin_key.is_none() {
+            settings.missing_credential("admin key", "--admin-key", "IRONFISH_ADMIN_KEY")
+        } else if settings.token.is_none() {
+            settings.missing_credential("token", "--token", "IRONFISH_TOKEN")
+        } else {
+            return e;
+        };
+        e.context(hint)
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
     }
-    Ok(())
 }
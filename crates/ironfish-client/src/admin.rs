@@ -0,0 +1,107 @@
+use crate::client::IronfishClient;
+use crate::error::Result;
+use ironfish_core::{
+    AuditPage, AuditQuery, ClusterStatus, CreateTokenRequest, CreateTokenResponse, DrainStatus,
+    JoinResponse, NodeDiagnostics, TokenMetadata, TokenReplicaReport, TokenUsage,
+};
+use reqwest::Method;
+use serde::Serialize;
+use uuid::Uuid;
+#[derive(Debug, Serialize)]
+struct JoinBody<'a> {
+    node_id: &'a str,
+    address: &'a str,
+    priority: Option<u32>,
+}
+#[derive(Debug, Clone)]
+pub struct AdminClient {
+    client: IronfishClient,
+}
+impl AdminClient {
+    pub(crate) fn new(client: IronfishClient) -> Self {
+        Self { client }
+    }
+    pub async fn cluster_status(&self) -> Result<ClusterStatus> {
+        self.client.cluster_status().await
+    }
+    pub async fn join(
+        &self,
+        node_id: &str,
+        address: &str,
+        priority: Option<u32>,
+    ) -> Result<JoinResponse> {
+        let body = JoinBody {
+            node_id,
+            address,
+            priority,
+        };
+        self.client
+            .send(Method::POST, "/_admin/cluster/join", Some(&body))
+            .await
+    }
+    pub async fn leave(&self) -> Result<()> {
+        self.client
+            .send::<(), serde_json::Value>(Method::POST, "/_admin/cluster/leave", None)
+            .await
+            .map(|_| ())
+    }
+    pub async fn drain_node(&self) -> Result<DrainStatus> {
+        self.client
+            .send::<(), _>(Method::POST, "/_admin/node/drain", None)
+            .await
+    }
+    pub async fn resume_node(&self) -> Result<DrainStatus> {
+        self.client
+            .send::<(), _>(Method::POST, "/_admin/node/resume", None)
+            .await
+    }
+    pub async fn diagnostics(&self) -> Result<NodeDiagnostics> {
+        self.client
+            .send::<(), _>(Method::GET, "/_admin/diagnostics", None)
+            .await
+    }
+    pub async fn create_token(&self, request: &CreateTokenRequest) -> Result<CreateTokenResponse> {
+        self.client
+            .send(Method::POST, "/_admin/tokens", Some(request))
+            .await
+    }
+    pub async fn list_tokens(&self) -> Result<Vec<TokenMetadata>> {
+        self.client.get("/_admin/tokens").await
+    }
+    pub async fn revoke_token(&self, id: &Uuid) -> Result<()> {
+        let path = format!("/_admin/tokens/{}", id);
+        self.client
+            .send::<(), serde_json::Value>(Method::DELETE, &path, None)
+            .await
+            .map(|_| ())
+    }
+    pub async fn token_usage(&self, id: &Uuid) -> Result<TokenUsage> {
+        self.client
+            .get(&format!("/_admin/tokens/{}/usage", id))
+            .await
+    }
+    pub async fn token_replicas(&self, id: &Uuid) -> Result<TokenReplicaReport> {
+        self.client
+            .get(&format!("/_admin/tokens/{}/replicas", id))
+            .await
+    }
+    pub async fn purge_tokens(&self) -> Result<u64> {
+        let response: serde_json::Value = self
+            .client
+            .send::<(), _>(Method::DELETE, "/_admin/tokens/expired", None)
+            .await?;
+        Ok(response["purged"].as_u64().unwrap_or(0))
+    }
+    pub async fn clear_cache(&self) -> Result<u64> {
+        let response: serde_json::Value = self
+            .client
+            .send::<(), _>(Method::POST, "/_admin/cache/clear", None)
+            .await?;
+        Ok(response["cleared"].as_u64().unwrap_or(0))
+    }
+    pub async fn audit(&self, query: &AuditQuery) -> Result<AuditPage> {
+        self.client
+            .send_with_query::<(), _, _>(Method::GET, "/_admin/audit", None, Some(query))
+            .await
+    }
+}
@@ -0,0 +1,170 @@
+use crate::admin::AdminClient;
+use crate::error::{ClientError, Result};
+use crate::retry::RetryPolicy;
+use ironfish_core::{
+    AnalysisRequest, AnalysisResult, BestMoveRequest, BestMoveResponse, ChessPosition,
+    ClusterStatus, HealthResponse, MetricsResponse, Variant,
+};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, RequestBuilder, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+#[derive(Debug, Clone)]
+pub struct IronfishClient {
+    endpoint: String,
+    token: Option<String>,
+    admin_key: Option<String>,
+    cluster_secret: Option<String>,
+    http: reqwest::Client,
+    timeout: Duration,
+    retry: RetryPolicy,
+}
+impl IronfishClient {
+    pub fn new(endpoint: impl Into<String>, token: Option<String>) -> Self {
+        Self {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            token,
+            admin_key: None,
+            cluster_secret: None,
+            http: reqwest::Client::new(),
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+    pub fn with_admin_key(mut self, key: impl Into<String>) -> Self {
+        self.admin_key = Some(key.into());
+        self
+    }
+    pub fn with_cluster_secret(mut self, secret: impl Into<String>) -> Self {
+        self.cluster_secret = Some(secret.into());
+        self
+    }
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+    pub fn admin(&self, key: impl Into<String>) -> AdminClient {
+        AdminClient::new(self.clone().with_admin_key(key))
+    }
+    pub async fn analyze(&self, request: &AnalysisRequest) -> Result<AnalysisResult> {
+        self.send(Method::POST, "/v1/analyze", Some(request)).await
+    }
+    pub async fn best_move(&self, request: &BestMoveRequest) -> Result<BestMoveResponse> {
+        self.send(Method::POST, "/v1/bestmove", Some(request)).await
+    }
+    pub fn validate_fen(&self, fen: &str, variant: Variant) -> Result<()> {
+        ChessPosition::new(fen)
+            .validate_for(variant)
+            .map_err(ClientError::from)
+    }
+    pub async fn health(&self) -> Result<HealthResponse> {
+        match self.get("/v1/health").await {
+            Err(ClientError::Unavailable(body)) => {
+                serde_json::from_str(&body).map_err(|_| ClientError::Unavailable(body))
+            }
+            result => result,
+        }
+    }
+    pub async fn metrics(&self) -> Result<MetricsResponse> {
+        self.get("/v1/metrics").await
+    }
+    pub async fn cluster_status(&self) -> Result<ClusterStatus> {
+        self.get("/_admin/cluster/status").await
+    }
+    pub(crate) fn ws_url(&self) -> Result<Url> {
+        let mut url = Url::parse(&format!("{}/v1/ws", self.endpoint))
+            .map_err(|e| ClientError::WebSocket(format!("invalid endpoint: {}", e)))?;
+        let scheme = match url.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        url.set_scheme(scheme)
+            .map_err(|_| ClientError::WebSocket("invalid endpoint scheme".into()))?;
+        if let Some(ref token) = self.token {
+            url.query_pairs_mut().append_pair("token", token);
+        }
+        Ok(url)
+    }
+    pub(crate) async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.send::<(), T>(Method::GET, path, None).await
+    }
+    pub(crate) async fn send<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T> {
+        self.send_with_query::<B, (), T>(method, path, body, None)
+            .await
+    }
+    pub(crate) async fn send_with_query<B: Serialize, Q: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+        query: Option<&Q>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.request(method.clone(), path);
+            if let Some(query) = query {
+                request = request.query(query);
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if status.is_success() {
+                return response
+                    .json()
+                    .await
+                    .map_err(|e| ClientError::InvalidResponse(e.to_string()));
+            }
+            let retry_after = retry_after(response.headers());
+            let text = response.text().await.unwrap_or_default();
+            let error = ClientError::from_response(status.as_u16(), &text, retry_after);
+            if !error.is_retryable() || attempt >= self.retry.max_retries {
+                return Err(error);
+            }
+            tokio::time::sleep(self.retry.delay(attempt, retry_after)).await;
+            attempt += 1;
+        }
+    }
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let mut request = self
+            .http
+            .request(method, format!("{}{}", self.endpoint, path))
+            .timeout(self.timeout);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(key) = self.admin_key.as_deref().filter(|k| !k.is_empty()) {
+            if path.starts_with("/_admin") {
+                request = request.header("X-Admin-Key", key);
+            }
+        }
+        if let Some(secret) = self.cluster_secret.as_deref().filter(|s| !s.is_empty()) {
+            if path.starts_with("/_admin/cluster") {
+                request = request.header("X-Cluster-Secret", secret);
+            }
+        }
+        request
+    }
+}
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
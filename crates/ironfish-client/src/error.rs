@@ -0,0 +1,159 @@
+use ironfish_core::Error;
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("invalid FEN: {0}")]
+    InvalidFen(String),
+    #[error("{0}")]
+    VariantRequired(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("rate limit exceeded")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("token store is read-only")]
+    StoreReadOnly,
+    #[error("service unavailable: {0}")]
+    Unavailable(String),
+    #[error("server error ({status}): {message}")]
+    Server { status: u16, message: String },
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("websocket error: {0}")]
+    WebSocket(String),
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+}
+pub type Result<T> = std::result::Result<T, ClientError>;
+#[derive(Debug, Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorDetail,
+    #[serde(default)]
+    code: Option<String>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorDetail {
+    Structured { code: String, message: String },
+    Message(String),
+}
+impl ClientError {
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            ClientError::StoreReadOnly => Some("STORE_READ_ONLY"),
+            ClientError::VariantRequired(_) => Some("VARIANT_REQUIRED"),
+            _ => None,
+        }
+    }
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ClientError::RateLimited { .. }
+                | ClientError::StoreReadOnly
+                | ClientError::Unavailable(_)
+        )
+    }
+    pub(crate) fn from_response(status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        match serde_json::from_str::<ErrorEnvelope>(body) {
+            Ok(ErrorEnvelope {
+                error: ErrorDetail::Structured { code, message },
+                ..
+            }) => Self::from_parts(status, Some(&code), message, retry_after),
+            Ok(ErrorEnvelope {
+                error: ErrorDetail::Message(message),
+                code,
+            }) => {
+                let code = code.map(|c| c.to_ascii_lowercase());
+                Self::from_parts(status, code.as_deref(), message, retry_after)
+            }
+            Err(_) => Self::from_parts(status, None, body.to_string(), retry_after),
+        }
+    }
+    pub(crate) fn from_parts(
+        status: u16,
+        code: Option<&str>,
+        message: String,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        match code {
+            Some("store_read_only") => return ClientError::StoreReadOnly,
+            Some("variant_required") => return ClientError::VariantRequired(message),
+            Some("rate_limited") => return ClientError::RateLimited { retry_after },
+            _ => {}
+        }
+        match status {
+            400 => match message.strip_prefix("invalid FEN: ") {
+                Some(fen) => ClientError::InvalidFen(fen.to_string()),
+                None => ClientError::BadRequest(message),
+            },
+            401 => ClientError::Unauthorized(message),
+            403 => ClientError::Forbidden(message),
+            404 => ClientError::NotFound(message),
+            429 => ClientError::RateLimited { retry_after },
+            503 => ClientError::Unavailable(message),
+            _ => ClientError::Server { status, message },
+        }
+    }
+}
+impl From<Error> for ClientError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidFen(fen) => ClientError::InvalidFen(fen),
+            e @ Error::VariantRequired(_) => ClientError::VariantRequired(e.to_string()),
+            e => ClientError::BadRequest(e.to_string()),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_structured_codes() {
+        let err = ClientError::from_response(
+            503,
+            r#"{"error":{"code":"store_read_only","message":"token store is read-only"}}"#,
+            None,
+        );
+        assert!(matches!(err, ClientError::StoreReadOnly));
+        assert_eq!(err.code(), Some("STORE_READ_ONLY"));
+        assert!(err.is_retryable());
+        let err = ClientError::from_response(
+            400,
+            r#"{"error":{"code":"variant_required","message":"castling rights 'HAha' are only valid in chess960"}}"#,
+            None,
+        );
+        assert!(matches!(err, ClientError::VariantRequired(_)));
+        assert!(!err.is_retryable());
+        let err = ClientError::from_response(
+            400,
+            r#"{"error":"castling rights 'HAha' are only valid in chess960","code":"VARIANT_REQUIRED"}"#,
+            None,
+        );
+        assert!(matches!(err, ClientError::VariantRequired(_)));
+    }
+    #[test]
+    fn test_status_fallbacks() {
+        let err = ClientError::from_response(
+            400,
+            r#"{"error":{"code":"invalid_fen","message":"invalid FEN: bad"}}"#,
+            None,
+        );
+        assert!(matches!(err, ClientError::InvalidFen(ref fen) if fen == "bad"));
+        let err = ClientError::from_response(401, r#"{"error":"invalid token"}"#, None);
+        assert!(matches!(err, ClientError::Unauthorized(ref m) if m == "invalid token"));
+        let err = ClientError::from_response(429, "", Some(Duration::from_secs(2)));
+        assert!(matches!(
+            err,
+            ClientError::RateLimited { retry_after: Some(d) } if d == Duration::from_secs(2)
+        ));
+        let err = ClientError::from_response(502, "bad gateway", None);
+        assert!(matches!(err, ClientError::Server { status: 502, .. }));
+    }
+}
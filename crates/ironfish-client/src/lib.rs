@@ -0,0 +1,10 @@
+mod admin;
+mod client;
+mod error;
+mod retry;
+mod stream;
+pub use admin::AdminClient;
+pub use client::IronfishClient;
+pub use error::{ClientError, Result};
+pub use retry::RetryPolicy;
+pub use stream::AnalysisProgressEvent;
@@ -0,0 +1,134 @@
+use crate::client::IronfishClient;
+use crate::error::{ClientError, Result};
+use futures_util::{SinkExt, Stream, StreamExt};
+use ironfish_core::{
+    AnalysisRequest, AnalysisResult, ClientMessage, PrincipalVariation, Score, ServerMessage, Wdl,
+};
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+#[derive(Debug, Clone)]
+pub enum AnalysisProgressEvent {
+    Progress {
+        analysis_id: Uuid,
+        current_depth: u8,
+        target_depth: u8,
+        evaluation: Option<Score>,
+        wdl: Option<Wdl>,
+        principal_variations: Vec<PrincipalVariation>,
+        nodes_per_second: u64,
+        elapsed_ms: u64,
+    },
+    Complete(Box<AnalysisResult>),
+    Cancelled {
+        analysis_id: Uuid,
+    },
+}
+impl AnalysisProgressEvent {
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, AnalysisProgressEvent::Progress { .. })
+    }
+}
+impl IronfishClient {
+    pub async fn analyze_streaming(
+        &self,
+        request: &AnalysisRequest,
+    ) -> Result<impl Stream<Item = Result<AnalysisProgressEvent>>> {
+        let url = self.ws_url()?;
+        let (mut ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .map_err(|e| ClientError::WebSocket(e.to_string()))?;
+        let request_id = request.id.to_string();
+        let message = ClientMessage::Analyze {
+            id: request_id.clone(),
+            fen: request.fen.clone(),
+            startpos: false,
+            moves: request.moves.clone(),
+            search_moves: request.search_moves.clone(),
+            depth: request.depth,
+            multipv: Some(request.multipv),
+            movetime: request.movetime,
+            nodes: request.nodes,
+            variant: request.variant,
+            skill_level: request.skill_level,
+            perspective: request.perspective,
+            notation: request.notation,
+            include_history: request.include_history,
+        };
+        let text = serde_json::to_string(&message)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        ws.send(Message::Text(text.into()))
+            .await
+            .map_err(|e| ClientError::WebSocket(e.to_string()))?;
+        Ok(futures_util::stream::unfold(Some(ws), move |state| {
+            let request_id = request_id.clone();
+            async move {
+                let mut ws = state?;
+                loop {
+                    let message = match ws.next().await {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => return None,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => {
+                            return Some((Err(ClientError::WebSocket(e.to_string())), None))
+                        }
+                    };
+                    let message = match serde_json::from_str::<ServerMessage>(&message) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            return Some((Err(ClientError::InvalidResponse(e.to_string())), None))
+                        }
+                    };
+                    let event = match message {
+                        ServerMessage::AnalysisProgress {
+                            analysis_id,
+                            current_depth,
+                            target_depth,
+                            evaluation,
+                            wdl,
+                            principal_variations,
+                            nodes_per_second,
+                            elapsed_ms,
+                        } => AnalysisProgressEvent::Progress {
+                            analysis_id,
+                            current_depth,
+                            target_depth,
+                            evaluation,
+                            wdl,
+                            principal_variations,
+                            nodes_per_second,
+                            elapsed_ms,
+                        },
+                        ServerMessage::AnalysisComplete { id, result, .. } if id == request_id => {
+                            AnalysisProgressEvent::Complete(result)
+                        }
+                        ServerMessage::AnalysisCancelled { analysis_id } => {
+                            AnalysisProgressEvent::Cancelled { analysis_id }
+                        }
+                        ServerMessage::Error {
+                            id,
+                            code,
+                            error,
+                            message,
+                            ..
+                        } if id.is_none() || id.as_deref() == Some(request_id.as_str()) => {
+                            let error = ClientError::from_parts(
+                                code,
+                                Some(error.as_str()).filter(|e| !e.is_empty()),
+                                message,
+                                None,
+                            );
+                            let _ = ws.close(None).await;
+                            return Some((Err(error), None));
+                        }
+                        _ => continue,
+                    };
+                    if event.is_terminal() {
+                        let _ = ws.close(None).await;
+                        return Some((Ok(event), None));
+                    }
+                    return Some((Ok(event), Some(ws)));
+                }
+            }
+        }))
+    }
+}
@@ -1,25 +1,48 @@
-use crate::consensus::HybridConsensus;
-use crate::discovery::DiscoveryManager;
-use crate::gossip::GossipService;
+use crate::consensus::{BullyElection, HybridConsensus, RaftConsensus};
+use crate::discovery::{DiscoveryManager, DnsDiscoveryConfig, KubernetesDiscoveryConfig};
+use crate::gossip::{GossipRetention, GossipService};
+use crate::load_balancer::{CpuAwareLoadBalancer, LoadBalancerConfig};
 use crate::membership::MembershipManager;
 use crate::network::{GossipEnvelope, NetworkService};
 use crate::node::SharedNode;
+use crate::pending::PendingWrites;
+use crate::state::ClusterStateStore;
+use crate::tls::{GossipTls, GossipTlsConfig};
+use crate::transport::{GossipTransport, WireFormat};
 use ironfish_core::{
-    ApiToken, ClusterDiscovery, ConsensusProtocol, GossipMessage, GossipProtocol, Result,
-    TokenStore,
+    ApiToken, AuditAction, AuditEntry, AuditLog, ClusterDiscovery, ClusterEvent, ConsensusProtocol,
+    GossipMessage, LeaveReason, LoadBalancer, NodeId, Result, Telemetry, TokenStore,
 };
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
+const STATE_SAVE_DEBOUNCE: Duration = Duration::from_secs(1);
 pub struct ClusterConfig {
     pub discovery_interval: Duration,
     pub gossip_interval: Duration,
     pub health_check_interval: Duration,
+    pub heartbeat_interval: Duration,
+    pub election_timeout_min: Duration,
+    pub election_timeout_max: Duration,
+    pub peer_ttl: Duration,
+    pub multicast_enabled: bool,
     pub multicast_group: String,
     pub multicast_port: u16,
     pub static_peers: Vec<String>,
+    pub seed_nodes: Vec<String>,
+    pub kubernetes: KubernetesDiscoveryConfig,
+    pub dns: DnsDiscoveryConfig,
     pub auto_join: bool,
+    pub pending_write_limit: usize,
+    pub peer_failure_threshold: u32,
+    pub tls: GossipTlsConfig,
+    pub secret: Option<String>,
+    pub persisted_peer_ttl: Duration,
+    pub wire_format: WireFormat,
+    pub gossip_retention: GossipRetention,
 }
 impl Default for ClusterConfig {
     fn default() -> Self {
@@ -27,14 +50,29 @@ impl Default for ClusterConfig {
             discovery_interval: Duration::from_secs(10),
             gossip_interval: Duration::from_secs(5),
             health_check_interval: Duration::from_secs(30),
+            heartbeat_interval: Duration::from_secs(1),
+            election_timeout_min: Duration::from_secs(5),
+            election_timeout_max: Duration::from_secs(10),
+            peer_ttl: Duration::from_secs(30),
+            multicast_enabled: true,
             multicast_group: "239.255.42.98".to_string(),
             multicast_port: 7878,
             static_peers: Vec::new(),
+            seed_nodes: Vec::new(),
+            kubernetes: KubernetesDiscoveryConfig::default(),
+            dns: DnsDiscoveryConfig::default(),
             auto_join: true,
+            pending_write_limit: 1024,
+            peer_failure_threshold: 3,
+            tls: GossipTlsConfig::default(),
+            secret: None,
+            persisted_peer_ttl: Duration::from_secs(24 * 60 * 60),
+            wire_format: WireFormat::default(),
+            gossip_retention: GossipRetention::default(),
         }
     }
 }
-pub struct ClusterService<T: TokenStore + Send + Sync + 'static> {
+pub struct ClusterService<T: TokenStore + ?Sized + 'static> {
     config: ClusterConfig,
     local_node: SharedNode,
     network: Arc<NetworkService>,
@@ -42,11 +80,45 @@ pub struct ClusterService<T: TokenStore + Send + Sync + 'static> {
     consensus: Arc<HybridConsensus>,
     discovery: Arc<DiscoveryManager>,
     membership: Arc<MembershipManager>,
+    load_balancer: Arc<CpuAwareLoadBalancer>,
+    peer_failures: Arc<RwLock<HashMap<NodeId, u32>>>,
     token_store: Arc<T>,
+    pending_writes: Arc<PendingWrites>,
+    state: Option<Arc<ClusterStateStore>>,
     shutdown_tx: broadcast::Sender<()>,
     running: Arc<RwLock<bool>>,
+    telemetry: Telemetry,
+    audit: Option<GossipAudit>,
 }
-impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
+#[derive(Clone)]
+struct GossipAudit {
+    log: Arc<dyn AuditLog>,
+    node_id: String,
+}
+impl GossipAudit {
+    async fn record(&self, envelope: &GossipEnvelope, outcome: &Result<()>) {
+        let (action, target) = match envelope.message {
+            GossipMessage::TokenCreated(ref token) => (AuditAction::TokenCreate, token.id),
+            GossipMessage::TokenRevoked(id) => (AuditAction::TokenRevoke, id),
+            GossipMessage::TokenUpdated(ref token) => (AuditAction::TokenUpdate, token.id),
+            _ => return,
+        };
+        let entry = AuditEntry::new(
+            action,
+            format!("node:{}", envelope.origin),
+            self.node_id.clone(),
+        )
+        .with_target(target.to_string());
+        let entry = match outcome {
+            Ok(()) => entry,
+            Err(e) => entry.failed(e.to_string()),
+        };
+        if let Err(e) = self.log.record(entry).await {
+            warn!("failed to record audit entry: {}", e);
+        }
+    }
+}
+impl<T: TokenStore + ?Sized + 'static> ClusterService<T> {
     pub fn new(
         config: ClusterConfig,
         local_node: SharedNode,
@@ -54,15 +126,59 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
         token_store: Arc<T>,
     ) -> Result<Self> {
         let node_info = local_node.info().clone();
-        let network = Arc::new(NetworkService::new(node_info.clone()));
-        let gossip = Arc::new(GossipService::new(local_node.id().clone()));
-        let consensus = Arc::new(HybridConsensus::new(local_node.clone()));
+        let gossip = Arc::new(
+            GossipService::new(local_node.id().clone()).with_retention(config.gossip_retention),
+        );
+        let transport = GossipTransport::new()
+            .with_tls(GossipTls::from_config(&config.tls)?)
+            .with_secret(config.secret.as_deref())
+            .with_wire_format(config.wire_format);
+        let raft = RaftConsensus::new(local_node.clone())
+            .with_heartbeat_interval(config.heartbeat_interval)
+            .with_election_timeout_range(config.election_timeout_min, config.election_timeout_max)
+            .with_transport(transport.clone());
+        let bully = BullyElection::new(local_node.clone()).with_transport(transport.clone());
+        let consensus = Arc::new(
+            HybridConsensus::new(local_node.clone())
+                .with_raft(raft)
+                .with_bully(bully),
+        );
+        let network = Arc::new(
+            NetworkService::new(node_info.clone())
+                .with_bind_address(local_node.bind_address())
+                .with_gossip(gossip.clone())
+                .with_consensus(consensus.clone())
+                .with_bully(consensus.bully())
+                .with_token_store(token_store.clone())
+                .with_transport(transport.clone()),
+        );
         let mut discovery = DiscoveryManager::new();
         if !config.static_peers.is_empty() {
             discovery = discovery.with_static(config.static_peers.clone());
         }
-        discovery = discovery.with_multicast(&config.multicast_group, config.multicast_port)?;
+        if !config.seed_nodes.is_empty() {
+            discovery = discovery.with_seeds(config.seed_nodes.clone(), transport);
+        }
+        if config.kubernetes.enabled {
+            discovery = discovery
+                .with_k8s(
+                    config.kubernetes.namespace(),
+                    config.kubernetes.service.clone(),
+                    config.kubernetes.port,
+                )
+                .with_k8s_label_selector(config.kubernetes.label_selector.clone());
+        }
+        if !config.dns.name.is_empty() {
+            discovery = discovery.with_dns(config.dns.clone());
+        }
+        if config.multicast_enabled {
+            discovery = discovery
+                .with_multicast(&config.multicast_group, config.multicast_port)?
+                .with_multicast_stale_after(config.discovery_interval * 3);
+        }
+        discovery = discovery.with_peer_ttl(config.peer_ttl);
         let (shutdown_tx, _) = broadcast::channel(1);
+        let pending_writes = Arc::new(PendingWrites::new(config.pending_write_limit));
         Ok(Self {
             config,
             local_node,
@@ -71,11 +187,36 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
             consensus,
             discovery: Arc::new(discovery),
             membership,
+            load_balancer: Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default())),
+            peer_failures: Arc::new(RwLock::new(HashMap::new())),
             token_store,
+            pending_writes,
+            state: None,
             shutdown_tx,
             running: Arc::new(RwLock::new(false)),
+            telemetry: Telemetry::default(),
+            audit: None,
         })
     }
+    pub fn with_load_balancer(mut self, load_balancer: Arc<CpuAwareLoadBalancer>) -> Self {
+        self.load_balancer = load_balancer;
+        self
+    }
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+    pub fn with_audit(mut self, log: Arc<dyn AuditLog>) -> Self {
+        self.audit = Some(GossipAudit {
+            log,
+            node_id: self.local_node.id().to_string(),
+        });
+        self
+    }
+    pub fn with_state_store(mut self, state: ClusterStateStore) -> Self {
+        self.state = Some(Arc::new(state));
+        self
+    }
     pub async fn start(&self) -> Result<()> {
         {
             let mut running = self.running.write().await;
@@ -84,36 +225,99 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
             }
             *running = true;
         }
+        self.start_event_loop();
+        self.network.set_telemetry(self.telemetry.clone()).await;
         self.network.start().await?;
         self.gossip.start().await?;
         self.consensus.start().await?;
+        self.restore_state().await?;
         let local_info = self.local_node.info();
-        self.discovery.announce(local_info).await?;
+        self.discovery.start(local_info).await?;
         self.start_discovery_loop().await;
         self.start_gossip_receiver().await;
         self.start_gossip_sync_loop().await;
+        self.start_health_check_loop().await;
         self.start_announcement_loop().await;
+        self.start_state_persist_loop().await;
         info!("cluster service started for node {}", self.local_node.id());
         Ok(())
     }
     pub async fn stop(&self) -> Result<()> {
         let _ = self.shutdown_tx.send(());
+        if let Some(ref state) = self.state {
+            if let Err(e) = state
+                .save_peers(&self.network.peers_last_seen().await)
+                .await
+            {
+                warn!("failed to persist cluster peers on stop: {}", e);
+            }
+        }
         self.network.stop().await;
         self.gossip.stop().await?;
         self.consensus.stop().await?;
         self.discovery.withdraw(self.local_node.id()).await?;
+        self.discovery.shutdown();
         let mut running = self.running.write().await;
         *running = false;
         info!("cluster service stopped");
         Ok(())
     }
+    async fn restore_state(&self) -> Result<()> {
+        let Some(ref state) = self.state else {
+            return Ok(());
+        };
+        self.gossip.restore_clock(state.as_ref().clone()).await?;
+        let peers = state.load_peers(self.config.persisted_peer_ttl)?;
+        for peer in &peers {
+            if peer.id == *self.local_node.id() {
+                continue;
+            }
+            let peer = &self.membership.resolve(peer.clone()).await;
+            self.network.add_peer(peer.clone()).await;
+            self.consensus.add_peer(peer.clone()).await;
+            if self.config.auto_join && !self.membership.is_member(&peer.id).await {
+                self.membership.add_member(peer.clone()).await;
+            }
+        }
+        if !peers.is_empty() {
+            info!("restored {} persisted peers", peers.len());
+        }
+        Ok(())
+    }
+    fn start_event_loop(&self) {
+        let load_balancer = self.load_balancer.clone();
+        let membership = self.membership.clone();
+        let mut events = self.membership.subscribe_events();
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            rebuild_load_balancer(&membership, &load_balancer).await;
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => load_balancer.apply_event(&event).await,
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("load balancer missed {} cluster events, rebuilding from membership", missed);
+                            rebuild_load_balancer(&membership, &load_balancer).await;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = shutdown_rx.recv() => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
     async fn start_discovery_loop(&self) {
         let discovery = self.discovery.clone();
         let network = self.network.clone();
+        let consensus = self.consensus.clone();
         let membership = self.membership.clone();
+        let peer_failures = self.peer_failures.clone();
         let local_node = self.local_node.clone();
         let interval = self.config.discovery_interval;
         let auto_join = self.config.auto_join;
+        let failure_threshold = self.config.peer_failure_threshold;
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         tokio::spawn(async move {
             let mut timer = tokio::time::interval(interval);
@@ -127,12 +331,24 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
                                     if peer.id == *local_node.id() {
                                         continue;
                                     }
+                                    let peer = membership.resolve(peer).await;
                                     network.add_peer(peer.clone()).await;
+                                    consensus.add_peer(peer.clone()).await;
+                                    let failures = peer_failures.read().await.get(&peer.id).copied().unwrap_or(0);
+                                    if failures >= failure_threshold {
+                                        continue;
+                                    }
                                     if auto_join && !membership.is_member(&peer.id).await {
                                         membership.add_member(peer.clone()).await;
                                         debug!("auto-joined peer {}", peer.id);
                                     }
                                 }
+                                for peer_id in discovery.take_removed().await {
+                                    network.remove_peer(&peer_id).await;
+                                    consensus.remove_peer(&peer_id).await;
+                                    membership.remove_member(&peer_id, LeaveReason::Undiscovered).await;
+                                    peer_failures.write().await.remove(&peer_id);
+                                }
                                 let peer_count = network.peer_count().await;
                                 if peer_count > 0 {
                                     debug!("discovered {} peers", peer_count);
@@ -153,7 +369,12 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
     async fn start_gossip_receiver(&self) {
         let network = self.network.clone();
         let gossip = self.gossip.clone();
+        let load_balancer = self.load_balancer.clone();
+        let membership = self.membership.clone();
         let token_store = self.token_store.clone();
+        let pending_writes = self.pending_writes.clone();
+        let telemetry = self.telemetry.clone();
+        let audit = self.audit.clone();
         let local_id = self.local_node.id().clone();
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         tokio::spawn(async move {
@@ -163,19 +384,26 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
                         let mut rx = network.incoming_rx.write().await;
                         rx.recv().await
                     } => {
-                        if let Some(envelope) = maybe_envelope {
+                        if let Some((envelope, from)) = maybe_envelope {
                             if envelope.origin == local_id {
                                 continue;
                             }
-                            if let Err(e) = process_gossip_message(&envelope, &token_store, &gossip).await {
-                                warn!("failed to process gossip: {}", e);
+                            if gossip.is_seen(envelope.id).await {
+                                telemetry.gossip_duplicate();
+                                continue;
+                            }
+                            match process_gossip_message(&envelope, &token_store, &pending_writes, &gossip, &load_balancer, &membership, &telemetry, audit.as_ref()).await {
+                                Ok(()) => {
+                                    gossip.mark_seen(envelope.id).await;
+                                }
+                                Err(e) => warn!("failed to process gossip: {}", e),
                             }
                             if envelope.hops < 3 {
                                 let forward = GossipEnvelope {
                                     hops: envelope.hops + 1,
                                     ..envelope
                                 };
-                                let _ = network.broadcast(forward).await;
+                                let _ = network.forward(forward, from.as_ref()).await;
                             }
                         }
                     }
@@ -189,9 +417,22 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
     async fn start_gossip_sync_loop(&self) {
         let network = self.network.clone();
         let gossip = self.gossip.clone();
+        let load_balancer = self.load_balancer.clone();
+        let membership = self.membership.clone();
         let token_store = self.token_store.clone();
+        let pending_writes = self.pending_writes.clone();
+        let telemetry = self.telemetry.clone();
+        let audit = self.audit.clone();
+        let state = self.state.clone();
         let interval = self.config.gossip_interval;
         let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let mut watermarks = match state {
+            Some(ref state) => state.gossip_watermarks().unwrap_or_else(|e| {
+                warn!("failed to load persisted gossip watermarks: {}", e);
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
         tokio::spawn(async move {
             let mut timer = tokio::time::interval(interval);
             loop {
@@ -203,11 +444,33 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
                         }
                         let idx = rand::random_index(peers.len());
                         let peer = &peers[idx];
-                        match network.sync_with_peer(&peer.id, 0).await {
+                        match network.sync_with_peer(&peer.id, watermarks.clone()).await {
                             Ok(entries) => {
+                                let mut advanced = false;
+                                let mut stalled = HashSet::new();
                                 for envelope in entries {
-                                    if let Err(e) = process_gossip_message(&envelope, &token_store, &gossip).await {
+                                    if gossip.is_seen(envelope.id).await {
+                                        telemetry.gossip_duplicate();
+                                    } else if let Err(e) = process_gossip_message(&envelope, &token_store, &pending_writes, &gossip, &load_balancer, &membership, &telemetry, audit.as_ref()).await {
                                         debug!("sync message error: {}", e);
+                                        stalled.insert(envelope.origin.clone());
+                                    } else {
+                                        gossip.mark_seen(envelope.id).await;
+                                    }
+                                    if stalled.contains(&envelope.origin) {
+                                        continue;
+                                    }
+                                    let seen = watermarks.entry(envelope.origin.clone()).or_insert(0);
+                                    if envelope.version > *seen {
+                                        *seen = envelope.version;
+                                        advanced = true;
+                                    }
+                                }
+                                if let Some(ref state) = state {
+                                    if advanced {
+                                        if let Err(e) = state.set_gossip_watermarks(&watermarks).await {
+                                            warn!("failed to persist gossip watermarks: {}", e);
+                                        }
                                     }
                                 }
                             }
@@ -223,6 +486,66 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
             }
         });
     }
+    async fn start_health_check_loop(&self) {
+        let network = self.network.clone();
+        let consensus = self.consensus.clone();
+        let membership = self.membership.clone();
+        let local_node = self.local_node.clone();
+        let peer_failures = self.peer_failures.clone();
+        let interval = self.config.health_check_interval;
+        let failure_threshold = self.config.peer_failure_threshold;
+        let auto_join = self.config.auto_join;
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            timer.tick().await;
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {
+                        for peer in network.peers().await {
+                            match network.ping(&peer.id).await {
+                                Ok(()) => {
+                                    network.mark_healthy(&peer.id).await;
+                                    membership.record_seen(&peer.id).await;
+                                    let previous = peer_failures.write().await.remove(&peer.id).unwrap_or(0);
+                                    if previous > 0 {
+                                        local_node.events().emit(ClusterEvent::NodeRecovered { node_id: peer.id.clone() });
+                                    }
+                                    if previous >= failure_threshold && auto_join {
+                                        membership.add_member(peer.clone()).await;
+                                    }
+                                }
+                                Err(e) => {
+                                    debug!("health check for {} failed: {}", peer.id, e);
+                                    network.mark_unhealthy(&peer.id).await;
+                                    let failures = {
+                                        let mut failures = peer_failures.write().await;
+                                        let count = failures.entry(peer.id.clone()).or_insert(0);
+                                        *count += 1;
+                                        *count
+                                    };
+                                    if failures == 1 {
+                                        local_node.events().emit(ClusterEvent::NodeUnhealthy { node_id: peer.id.clone(), reason: e.to_string() });
+                                    }
+                                    if failures >= failure_threshold {
+                                        network.remove_peer(&peer.id).await;
+                                        consensus.remove_peer(&peer.id).await;
+                                        membership.remove_member(&peer.id, LeaveReason::Unreachable).await;
+                                        if failures == failure_threshold {
+                                            warn!("removed peer {} after {} failed health checks", peer.id, failures);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ = shutdown_rx.recv() => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
     async fn start_announcement_loop(&self) {
         let discovery = self.discovery.clone();
         let local_node = self.local_node.clone();
@@ -245,24 +568,63 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
             }
         });
     }
-    pub async fn broadcast_token_created(&self, token: ApiToken) -> Result<()> {
-        let envelope = GossipEnvelope {
-            message: GossipMessage::TokenCreated(token),
-            origin: self.local_node.id().clone(),
-            version: chrono::Utc::now().timestamp_millis() as u64,
-            hops: 0,
+    async fn start_state_persist_loop(&self) {
+        let Some(state) = self.state.clone() else {
+            return;
         };
-        self.gossip.broadcast(envelope.message.clone()).await?;
+        let network = self.network.clone();
+        let refresh_after = self.config.persisted_peer_ttl / 2;
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(STATE_SAVE_DEBOUNCE);
+            let mut saved: Option<Vec<(NodeId, std::net::SocketAddr)>> = None;
+            let mut saved_at = std::time::Instant::now();
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => {
+                        let peers = network.peers_last_seen().await;
+                        let mut snapshot: Vec<_> = peers.iter().map(|(p, _)| (p.id.clone(), p.address)).collect();
+                        snapshot.sort();
+                        if saved.as_ref() == Some(&snapshot) && saved_at.elapsed() < refresh_after {
+                            continue;
+                        }
+                        match state.save_peers(&peers).await {
+                            Ok(()) => {
+                                debug!("persisted {} peers", peers.len());
+                                saved = Some(snapshot);
+                                saved_at = std::time::Instant::now();
+                            }
+                            Err(e) => warn!("failed to persist cluster peers: {}", e),
+                        }
+                    }
+                    _ = shutdown_rx.recv() => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    pub async fn broadcast_token_created(&self, token: ApiToken) -> Result<()> {
+        let envelope = self
+            .gossip
+            .envelope(GossipMessage::TokenCreated(token))
+            .await;
+        self.gossip.mark_seen(envelope.id).await;
+        self.gossip.record(&envelope).await;
         self.network.broadcast(envelope).await
     }
     pub async fn broadcast_token_revoked(&self, token_id: uuid::Uuid) -> Result<()> {
-        let envelope = GossipEnvelope {
-            message: GossipMessage::TokenRevoked(token_id),
-            origin: self.local_node.id().clone(),
-            version: chrono::Utc::now().timestamp_millis() as u64,
-            hops: 0,
-        };
-        self.gossip.broadcast(envelope.message.clone()).await?;
+        let envelope = self
+            .gossip
+            .envelope(GossipMessage::TokenRevoked(token_id))
+            .await;
+        self.gossip.mark_seen(envelope.id).await;
+        self.gossip.record(&envelope).await;
+        self.network.broadcast(envelope).await
+    }
+    pub async fn broadcast(&self, message: GossipMessage) -> Result<()> {
+        let envelope = self.gossip.envelope(message).await;
+        self.gossip.mark_seen(envelope.id).await;
         self.network.broadcast(envelope).await
     }
     pub fn gossip(&self) -> Arc<GossipService> {
@@ -271,41 +633,64 @@ impl<T: TokenStore + Send + Sync + 'static> ClusterService<T> {
     pub fn network(&self) -> Arc<NetworkService> {
         self.network.clone()
     }
+    pub fn load_balancer(&self) -> Arc<CpuAwareLoadBalancer> {
+        self.load_balancer.clone()
+    }
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ClusterEvent> {
+        self.membership.subscribe_events()
+    }
     pub async fn peer_count(&self) -> usize {
         self.network.peer_count().await
     }
+    pub fn pending_writes(&self) -> Arc<PendingWrites> {
+        self.pending_writes.clone()
+    }
+    pub async fn flush_pending_writes(&self) -> Result<usize> {
+        self.pending_writes.flush(self.token_store.as_ref()).await
+    }
 }
-async fn process_gossip_message<T: TokenStore>(
+async fn rebuild_load_balancer(
+    membership: &MembershipManager,
+    load_balancer: &CpuAwareLoadBalancer,
+) {
+    let members: Vec<NodeId> = membership
+        .list_members()
+        .await
+        .into_iter()
+        .map(|member| member.id)
+        .collect();
+    let draining = membership.draining_members().await;
+    load_balancer.rebuild(&members, &draining).await;
+}
+#[allow(clippy::too_many_arguments)]
+async fn process_gossip_message<T: TokenStore + ?Sized>(
     envelope: &GossipEnvelope,
     token_store: &Arc<T>,
-    _gossip: &Arc<GossipService>,
+    pending_writes: &Arc<PendingWrites>,
+    gossip: &Arc<GossipService>,
+    load_balancer: &Arc<CpuAwareLoadBalancer>,
+    membership: &Arc<MembershipManager>,
+    telemetry: &Telemetry,
+    audit: Option<&GossipAudit>,
 ) -> Result<()> {
+    telemetry.gossip_message(envelope.message.kind());
+    gossip.record(envelope).await;
+    membership.record_seen(&envelope.origin).await;
     match &envelope.message {
-        GossipMessage::TokenCreated(token) => match token_store.get(&token.id).await {
-            Ok(Some(existing)) => {
-                if token.created_at > existing.created_at {
-                    token_store.update(token.clone()).await?;
-                    info!("updated token {} from gossip", token.id);
-                }
-            }
-            Ok(None) => {
-                token_store.create(token.clone()).await?;
-                info!("replicated token {} from gossip", token.id);
-            }
-            Err(e) => {
-                warn!("failed to check token {}: {}", token.id, e);
+        GossipMessage::TokenCreated(_)
+        | GossipMessage::TokenRevoked(_)
+        | GossipMessage::TokenUpdated(_) => {
+            let outcome = pending_writes
+                .submit(&envelope.message, token_store.as_ref())
+                .await;
+            if let Some(audit) = audit {
+                audit.record(envelope, &outcome).await;
             }
-        },
-        GossipMessage::TokenRevoked(token_id) => {
-            if let Err(e) = token_store.revoke(token_id).await {
-                debug!("revoke token {} error: {}", token_id, e);
-            } else {
-                info!("revoked token {} from gossip", token_id);
+            if let Err(e) = outcome {
+                debug!("token write from gossip failed: {}", e);
             }
-        }
-        GossipMessage::TokenUpdated(token) => {
-            if let Err(e) = token_store.update(token.clone()).await {
-                debug!("update token {} error: {}", token.id, e);
+            if let GossipMessage::TokenRevoked(token_id) = envelope.message {
+                membership.emit(ClusterEvent::TokenRevoked { token_id });
             }
         }
         GossipMessage::NodeJoined(node_info) => {
@@ -314,8 +699,18 @@ async fn process_gossip_message<T: TokenStore>(
         GossipMessage::NodeLeft(node_id) => {
             debug!("node {} left via gossip", node_id);
         }
-        GossipMessage::NodeMetrics(node_id, _metrics) => {
+        GossipMessage::NodeMetrics(node_id, metrics) => {
             debug!("received metrics from {}", node_id);
+            membership
+                .update_member_metrics(node_id, metrics.clone())
+                .await;
+            membership.set_draining(node_id, metrics.draining).await;
+            load_balancer
+                .update_metrics(node_id, metrics.clone())
+                .await?;
+        }
+        GossipMessage::NodeDraining(node_id, draining) => {
+            membership.set_draining(node_id, *draining).await;
         }
     }
     Ok(())
@@ -333,3 +728,106 @@ pub mod rand {
         nanos % max
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_auth::{SledAuditLog, SledTokenStore, TokenManager};
+    use ironfish_core::{AuditOutcome, AuditQuery, CreateTokenRequest, NodeMetrics};
+    #[tokio::test]
+    async fn test_gossip_token_changes_are_audited() {
+        let log = Arc::new(SledAuditLog::in_memory().unwrap());
+        let audit = GossipAudit {
+            log: log.clone(),
+            node_id: "node-1".to_string(),
+        };
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let pending = Arc::new(PendingWrites::new(8));
+        let gossip = Arc::new(GossipService::new(NodeId::from_string("node-1")));
+        let load_balancer = Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default()));
+        let membership = Arc::new(MembershipManager::new(Arc::new(crate::node::Node::new(
+            crate::node::NodeConfig::default(),
+        ))));
+        let (token, _) = TokenManager::new(&TokenManager::generate_secret(), "peer")
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap();
+        let origin = NodeId::from_string("peer");
+        for message in [
+            GossipMessage::TokenCreated(token.clone()),
+            GossipMessage::TokenRevoked(token.id),
+            GossipMessage::NodeLeft(origin.clone()),
+        ] {
+            process_gossip_message(
+                &GossipEnvelope::new(message, origin.clone(), 1),
+                &store,
+                &pending,
+                &gossip,
+                &load_balancer,
+                &membership,
+                &Telemetry::default(),
+                Some(&audit),
+            )
+            .await
+            .unwrap();
+        }
+        let entries = log.query(&AuditQuery::default()).await.unwrap().entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, AuditAction::TokenCreate);
+        assert_eq!(entries[1].action, AuditAction::TokenRevoke);
+        assert_eq!(entries[1].actor, "node:peer");
+        assert_eq!(entries[1].node_id, "node-1");
+        assert_eq!(entries[1].target, Some(token.id.to_string()));
+        assert_eq!(entries[1].outcome, AuditOutcome::Success);
+    }
+    #[tokio::test]
+    async fn test_metrics_readvertise_drain_state() {
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let pending = Arc::new(PendingWrites::new(8));
+        let gossip = Arc::new(GossipService::new(NodeId::from_string("node-1")));
+        let load_balancer = Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default()));
+        let membership = Arc::new(MembershipManager::new(Arc::new(crate::node::Node::new(
+            crate::node::NodeConfig::default(),
+        ))));
+        let mut events = membership.subscribe_events();
+        let origin = NodeId::from_string("peer");
+        for draining in [true, true, false] {
+            let metrics = NodeMetrics {
+                draining,
+                ..Default::default()
+            };
+            process_gossip_message(
+                &GossipEnvelope::new(
+                    GossipMessage::NodeMetrics(origin.clone(), metrics),
+                    origin.clone(),
+                    1,
+                ),
+                &store,
+                &pending,
+                &gossip,
+                &load_balancer,
+                &membership,
+                &Telemetry::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        for expected in [true, false] {
+            match events.try_recv().unwrap() {
+                ClusterEvent::NodeDraining { node_id, draining } => {
+                    assert_eq!(node_id, origin);
+                    assert_eq!(draining, expected);
+                }
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -1,15 +1,23 @@
+use crate::network::{gossip_addr, send_and_receive, NetworkMessage};
 use crate::node::SharedNode;
-use ironfish_core::{NodeId, NodeInfo, NodeState, Result};
+use crate::transport::GossipTransport;
+use futures::future::join_all;
+use ironfish_core::{Error, NodeId, NodeInfo, NodeState, Result};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 use tokio::time::timeout;
 use tracing::{debug, info};
+#[derive(Clone)]
 pub struct BullyElection {
     node: SharedNode,
     peers: Arc<RwLock<HashMap<NodeId, NodeInfo>>>,
     election_timeout: Duration,
+    electing: Arc<AtomicBool>,
+    coordinator: Arc<Notify>,
+    transport: GossipTransport,
 }
 impl BullyElection {
     pub fn new(node: SharedNode) -> Self {
@@ -17,79 +25,209 @@ impl BullyElection {
             node,
             peers: Arc::new(RwLock::new(HashMap::new())),
             election_timeout: Duration::from_secs(5),
+            electing: Arc::new(AtomicBool::new(false)),
+            coordinator: Arc::new(Notify::new()),
+            transport: GossipTransport::default(),
         }
     }
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.election_timeout = timeout;
         self
     }
+    pub fn with_transport(mut self, transport: GossipTransport) -> Self {
+        self.transport = transport;
+        self
+    }
     pub async fn add_peer(&self, peer: NodeInfo) {
         let mut peers = self.peers.write().await;
+        peers.retain(|id, known| *id == peer.id || known.address != peer.address);
         peers.insert(peer.id.clone(), peer);
     }
     pub async fn remove_peer(&self, peer_id: &NodeId) {
         let mut peers = self.peers.write().await;
         peers.remove(peer_id);
     }
+    pub fn term(&self) -> u64 {
+        self.node.term()
+    }
+    fn outranks(&self, other: &NodeId, other_priority: u32) -> bool {
+        (self.node.priority(), self.node.id()) > (other_priority, other)
+    }
+    async fn call_all(&self, message: NetworkMessage) -> Vec<Result<NetworkMessage>> {
+        let peers: Vec<NodeInfo> = self.peers.read().await.values().cloned().collect();
+        join_all(peers.iter().map(|peer| {
+            let message = message.clone();
+            async move {
+                timeout(
+                    self.election_timeout,
+                    send_and_receive(gossip_addr(peer), &self.transport, message),
+                )
+                .await
+                .map_err(|_| Error::Network(format!("request to {} timed out", peer.id)))?
+            }
+        }))
+        .await
+    }
     pub async fn start_election(&self) -> Result<bool> {
-        info!("starting bully election");
-        self.node.set_state(NodeState::Candidate);
-        let my_priority = self.node.priority();
-        let peers = self.peers.read().await.clone();
-        let higher_priority_peers: Vec<_> = peers
-            .iter()
-            .filter(|(_, info)| info.priority > my_priority)
-            .collect();
-        if higher_priority_peers.is_empty() {
-            self.declare_victory().await;
-            return Ok(true);
-        }
-        for (peer_id, _) in &higher_priority_peers {
-            debug!("sending election message to {}", peer_id);
+        if self.electing.swap(true, Ordering::SeqCst) {
+            debug!("bully election already in progress");
+            return Ok(false);
         }
-        match timeout(self.election_timeout, self.wait_for_coordinator()).await {
-            Ok(_) => {
-                debug!("received coordinator message");
-                self.node.set_state(NodeState::Follower);
-                Ok(false)
+        let won = self.run_election().await;
+        self.electing.store(false, Ordering::SeqCst);
+        won
+    }
+    async fn run_election(&self) -> Result<bool> {
+        loop {
+            info!("starting bully election");
+            if !self.node.is_leader() {
+                self.node.set_state(NodeState::Candidate);
             }
-            Err(_) => {
-                info!("election timeout, declaring victory");
+            let coordinator = self.coordinator.notified();
+            tokio::pin!(coordinator);
+            coordinator.as_mut().enable();
+            let answers = self
+                .call_all(NetworkMessage::BullyElection {
+                    candidate_id: self.node.id().clone(),
+                    priority: self.node.priority(),
+                })
+                .await;
+            let taken_over = answers
+                .iter()
+                .any(|answer| matches!(answer, Ok(NetworkMessage::BullyAnswer { ok: true, .. })));
+            if !taken_over {
                 self.declare_victory().await;
-                Ok(true)
+                return Ok(true);
+            }
+            match timeout(self.election_timeout, coordinator).await {
+                Ok(()) => {
+                    debug!("received coordinator message");
+                    return Ok(false);
+                }
+                Err(_) => info!("no coordinator after election, retrying"),
             }
         }
     }
-    async fn wait_for_coordinator(&self) {
-        tokio::time::sleep(self.election_timeout).await;
-    }
     async fn declare_victory(&self) {
+        let term = self.node.increment_term();
         info!(
-            "node {} is now leader (priority: {})",
+            "node {} is now leader (priority: {}, term: {})",
             self.node.id(),
-            self.node.priority()
+            self.node.priority(),
+            term
         );
         self.node.set_state(NodeState::Leader);
         self.node.set_leader(Some(self.node.id().clone()));
-        self.node.increment_term();
-        let peers = self.peers.read().await.clone();
-        for (peer_id, _) in peers {
-            debug!("sending coordinator message to {}", peer_id);
+        let answers = self
+            .call_all(NetworkMessage::BullyCoordinator {
+                leader_id: self.node.id().clone(),
+                priority: self.node.priority(),
+                term,
+            })
+            .await;
+        let highest_term = answers
+            .into_iter()
+            .filter_map(|answer| match answer {
+                Ok(NetworkMessage::BullyAnswer { term, .. }) => Some(term),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(term);
+        if highest_term > self.node.term() {
+            self.node.set_term(highest_term);
         }
     }
-    pub async fn handle_election_message(&self, from: &NodeId, from_priority: u32) {
-        let my_priority = self.node.priority();
-        if my_priority > from_priority {
-            debug!(
-                "responding to election from {} - I have higher priority",
-                from
-            );
-            self.start_election().await.ok();
+    pub async fn handle_election_message(&self, from: &NodeId, from_priority: u32) -> bool {
+        if !self.outranks(from, from_priority) {
+            return false;
         }
+        debug!("taking over election from {}", from);
+        let bully = self.clone();
+        tokio::spawn(async move {
+            let _ = bully.start_election().await;
+        });
+        true
     }
-    pub async fn handle_coordinator_message(&self, leader_id: NodeId) {
+    pub async fn handle_coordinator_message(
+        &self,
+        leader_id: NodeId,
+        leader_priority: u32,
+        term: u64,
+    ) -> bool {
+        if self.outranks(&leader_id, leader_priority) {
+            debug!("rejecting lower-priority coordinator {}", leader_id);
+            let bully = self.clone();
+            tokio::spawn(async move {
+                let _ = bully.start_election().await;
+            });
+            return false;
+        }
         debug!("accepting {} as leader", leader_id);
+        if term > self.node.term() {
+            self.node.set_term(term);
+        }
         self.node.set_state(NodeState::Follower);
         self.node.set_leader(Some(leader_id));
+        self.coordinator.notify_waiters();
+        true
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetworkService;
+    use crate::node::{Node, NodeConfig};
+    async fn bully_node(id: &str, priority: u32) -> (BullyElection, Arc<NetworkService>) {
+        let gossip_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map(|a| a.port())
+            .unwrap();
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some(id.to_string()),
+            bind_address: format!("127.0.0.1:{}", gossip_port - 100).parse().unwrap(),
+            advertise_address: None,
+            priority,
+            version: "test".to_string(),
+        }));
+        let bully = BullyElection::new(node.clone()).with_timeout(Duration::from_millis(500));
+        let network =
+            Arc::new(NetworkService::new(node.info().clone()).with_bully(Arc::new(bully.clone())));
+        network.start().await.unwrap();
+        (bully, network)
+    }
+    async fn wait_for_leader(nodes: &[&BullyElection], expected: &NodeId) {
+        for _ in 0..50 {
+            if nodes
+                .iter()
+                .all(|b| b.node.leader().as_ref() == Some(expected))
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("nodes never agreed on {} as leader", expected);
+    }
+    #[tokio::test]
+    async fn test_highest_priority_live_node_wins() {
+        let (low, _low_net) = bully_node("low", 10).await;
+        let (mid, _mid_net) = bully_node("mid", 20).await;
+        let (high, high_net) = bully_node("high", 30).await;
+        for bully in [&low, &mid, &high] {
+            for other in [&low, &mid, &high] {
+                if bully.node.id() != other.node.id() {
+                    bully.add_peer(other.node.info().clone()).await;
+                }
+            }
+        }
+        assert!(!low.start_election().await.unwrap());
+        wait_for_leader(&[&low, &mid, &high], high.node.id()).await;
+        assert!(high.node.is_leader());
+        assert!(!mid.node.is_leader() && !low.node.is_leader());
+        high_net.stop().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!low.start_election().await.unwrap());
+        wait_for_leader(&[&low, &mid], mid.node.id()).await;
+        assert!(mid.node.is_leader());
+        assert!(mid.node.term() > 1);
     }
 }
@@ -9,8 +9,8 @@ use ironfish_core::{
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
-use tokio::time::interval;
 use tracing::{debug, info, warn};
+#[derive(Clone)]
 pub struct HybridConsensus {
     node: SharedNode,
     raft: Arc<RaftConsensus>,
@@ -34,6 +34,14 @@ impl HybridConsensus {
         self.heartbeat_timeout = timeout;
         self
     }
+    pub fn with_raft(mut self, raft: RaftConsensus) -> Self {
+        self.raft = Arc::new(raft);
+        self
+    }
+    pub fn with_bully(mut self, bully: BullyElection) -> Self {
+        self.bully = Arc::new(bully);
+        self
+    }
     pub async fn add_peer(&self, peer: NodeInfo) {
         self.raft.add_peer(peer.clone()).await;
         self.bully.add_peer(peer).await;
@@ -42,28 +50,26 @@ impl HybridConsensus {
         self.raft.remove_peer(peer_id).await;
         self.bully.remove_peer(peer_id).await;
     }
+    pub fn bully(&self) -> Arc<BullyElection> {
+        self.bully.clone()
+    }
     async fn run_leader_detection(&self) {
-        let mut timer = interval(self.heartbeat_timeout);
         let mut shutdown_rx = self.shutdown_tx.subscribe();
-        let mut missed_heartbeats = 0;
         loop {
+            let timeout = self.raft.random_election_timeout();
             tokio::select! {
-                _ = timer.tick() => {
-                    if matches!(self.node.state(), NodeState::Follower) {
-                        missed_heartbeats += 1;
-                        if missed_heartbeats >= 3 {
-                            warn!("leader heartbeat timeout, starting election");
-                            self.trigger_election().await;
-                            missed_heartbeats = 0;
-                        }
-                    } else {
-                        missed_heartbeats = 0;
-                    }
-                }
+                _ = tokio::time::sleep(timeout) => {}
                 _ = shutdown_rx.recv() => {
                     break;
                 }
             }
+            if matches!(self.node.state(), NodeState::Leader | NodeState::Leaving)
+                || self.raft.since_last_contact().await < timeout
+            {
+                continue;
+            }
+            warn!("leader heartbeat timeout, starting election");
+            self.trigger_election().await;
         }
     }
     async fn trigger_election(&self) {
@@ -85,39 +91,11 @@ impl HybridConsensus {
 #[async_trait]
 impl ConsensusProtocol for HybridConsensus {
     async fn start(&self) -> Result<()> {
-        self.raft.start().await?;
         self.node.set_state(NodeState::Follower);
-        let node = self.node.clone();
-        let heartbeat_timeout = self.heartbeat_timeout;
-        let bully = self.bully.clone();
-        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        self.raft.start_heartbeats().await;
+        let hybrid = self.clone();
         tokio::spawn(async move {
-            let mut timer = interval(heartbeat_timeout);
-            let mut missed = 0u32;
-            loop {
-                tokio::select! {
-                    _ = timer.tick() => {
-                        let state = node.state();
-                        match state {
-                            NodeState::Follower => {
-                                missed += 1;
-                                if missed >= 3 {
-                                    warn!("heartbeat timeout, starting election");
-                                    let _ = bully.start_election().await;
-                                    missed = 0;
-                                }
-                            }
-                            NodeState::Leader => {
-                                missed = 0;
-                            }
-                            _ => {}
-                        }
-                    }
-                    _ = shutdown_rx.recv() => {
-                        break;
-                    }
-                }
-            }
+            hybrid.run_leader_detection().await;
         });
         info!("hybrid consensus started");
         Ok(())
@@ -130,11 +108,6 @@ impl ConsensusProtocol for HybridConsensus {
         Ok(())
     }
     async fn request_vote(&self, request: VoteRequest) -> Result<VoteResponse> {
-        if request.priority > self.node.priority() {
-            self.bully
-                .handle_election_message(&request.candidate_id, request.priority)
-                .await;
-        }
         self.raft.request_vote(request).await
     }
     async fn append_entries(&self, request: HeartbeatRequest) -> Result<HeartbeatResponse> {
@@ -1,22 +1,30 @@
+use crate::network::{gossip_addr, send_and_receive, NetworkMessage};
 use crate::node::SharedNode;
+use crate::transport::GossipTransport;
 use async_trait::async_trait;
+use futures::future::join_all;
 use ironfish_core::{
-    ConsensusProtocol, HeartbeatRequest, HeartbeatResponse, NodeId, NodeInfo, NodeState, Result,
-    VoteRequest, VoteResponse,
+    ConsensusProtocol, Error, HeartbeatRequest, HeartbeatResponse, NodeId, NodeInfo, NodeState,
+    Result, VoteRequest, VoteResponse,
 };
+use rand::Rng;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{broadcast, RwLock};
-use tokio::time::interval;
-use tracing::{debug, info};
+use tokio::time::{interval, Instant};
+use tracing::{debug, info, warn};
+#[derive(Clone)]
 pub struct RaftConsensus {
     node: SharedNode,
     peers: Arc<RwLock<HashMap<NodeId, NodeInfo>>>,
     voted_for: Arc<RwLock<Option<NodeId>>>,
     commit_index: Arc<RwLock<u64>>,
+    last_contact: Arc<RwLock<Instant>>,
     heartbeat_interval: Duration,
-    election_timeout: Duration,
+    election_timeout_min: Duration,
+    election_timeout_max: Duration,
+    transport: GossipTransport,
     shutdown_tx: broadcast::Sender<()>,
 }
 #[allow(dead_code)]
@@ -28,8 +36,11 @@ impl RaftConsensus {
             peers: Arc::new(RwLock::new(HashMap::new())),
             voted_for: Arc::new(RwLock::new(None)),
             commit_index: Arc::new(RwLock::new(0)),
+            last_contact: Arc::new(RwLock::new(Instant::now())),
             heartbeat_interval: Duration::from_millis(1000),
-            election_timeout: Duration::from_millis(5000),
+            election_timeout_min: Duration::from_millis(5000),
+            election_timeout_max: Duration::from_millis(10000),
+            transport: GossipTransport::default(),
             shutdown_tx,
         }
     }
@@ -37,12 +48,21 @@ impl RaftConsensus {
         self.heartbeat_interval = interval;
         self
     }
-    pub fn with_election_timeout(mut self, timeout: Duration) -> Self {
-        self.election_timeout = timeout;
+    pub fn with_election_timeout(self, timeout: Duration) -> Self {
+        self.with_election_timeout_range(timeout, timeout * 2)
+    }
+    pub fn with_election_timeout_range(mut self, min: Duration, max: Duration) -> Self {
+        self.election_timeout_min = min;
+        self.election_timeout_max = max.max(min);
+        self
+    }
+    pub fn with_transport(mut self, transport: GossipTransport) -> Self {
+        self.transport = transport;
         self
     }
     pub async fn add_peer(&self, peer: NodeInfo) {
         let mut peers = self.peers.write().await;
+        peers.retain(|id, known| *id == peer.id || known.address != peer.address);
         peers.insert(peer.id.clone(), peer);
     }
     pub async fn remove_peer(&self, peer_id: &NodeId) {
@@ -52,16 +72,48 @@ impl RaftConsensus {
     pub async fn peer_count(&self) -> usize {
         self.peers.read().await.len()
     }
-    async fn start_leader_loop(&self) {
+    pub(crate) fn random_election_timeout(&self) -> Duration {
+        rand::thread_rng().gen_range(self.election_timeout_min..=self.election_timeout_max)
+    }
+    pub(crate) async fn since_last_contact(&self) -> Duration {
+        self.last_contact.read().await.elapsed()
+    }
+    pub(crate) async fn start_heartbeats(&self) {
+        *self.last_contact.write().await = Instant::now();
+        let raft = self.clone();
+        tokio::spawn(async move {
+            raft.run_heartbeats().await;
+        });
+    }
+    async fn run_election_timer(&self) {
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        loop {
+            let timeout = self.random_election_timeout();
+            tokio::select! {
+                _ = tokio::time::sleep(timeout) => {}
+                _ = shutdown_rx.recv() => {
+                    break;
+                }
+            }
+            if matches!(self.node.state(), NodeState::Leader | NodeState::Leaving)
+                || self.last_contact.read().await.elapsed() < timeout
+            {
+                continue;
+            }
+            if let Err(e) = self.start_election().await {
+                warn!("election failed: {}", e);
+            }
+        }
+    }
+    async fn run_heartbeats(&self) {
         let mut heartbeat_timer = interval(self.heartbeat_interval);
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         loop {
             tokio::select! {
                 _ = heartbeat_timer.tick() => {
-                    if !matches!(self.node.state(), NodeState::Leader) {
-                        break;
+                    if matches!(self.node.state(), NodeState::Leader) {
+                        self.send_heartbeats().await;
                     }
-                    self.send_heartbeats().await;
                 }
                 _ = shutdown_rx.recv() => {
                     break;
@@ -69,66 +121,122 @@ impl RaftConsensus {
             }
         }
     }
+    async fn call(&self, peer: &NodeInfo, message: NetworkMessage) -> Result<NetworkMessage> {
+        tokio::time::timeout(
+            self.heartbeat_interval,
+            send_and_receive(gossip_addr(peer), &self.transport, message),
+        )
+        .await
+        .map_err(|_| Error::Network(format!("request to {} timed out", peer.id)))?
+    }
     async fn send_heartbeats(&self) {
-        let peers = self.peers.read().await.clone();
-        let commit_index = *self.commit_index.read().await;
-        for (peer_id, _peer_info) in peers {
-            let request = HeartbeatRequest {
-                leader_id: self.node.id().clone(),
-                term: self.node.term(),
-                commit_index,
-            };
-            debug!("sending heartbeat to {}", peer_id);
-            let _ = request;
+        let peers: Vec<NodeInfo> = self.peers.read().await.values().cloned().collect();
+        let term = self.node.term();
+        let request = HeartbeatRequest {
+            leader_id: self.node.id().clone(),
+            term,
+            commit_index: *self.commit_index.read().await,
+        };
+        let responses = join_all(
+            peers
+                .iter()
+                .map(|peer| self.call(peer, NetworkMessage::Heartbeat(request.clone()))),
+        )
+        .await;
+        for (peer, response) in peers.iter().zip(responses) {
+            match response {
+                Ok(NetworkMessage::HeartbeatResponse(response)) if response.term > term => {
+                    info!(
+                        "peer {} is at term {}, stepping down",
+                        peer.id, response.term
+                    );
+                    self.step_down(response.term).await;
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => debug!("heartbeat to {} failed: {}", peer.id, e),
+            }
         }
     }
     async fn start_election(&self) -> Result<bool> {
-        let new_term = self.node.increment_term();
-        self.node.set_state(NodeState::Candidate);
+        let new_term = {
+            let mut voted_for = self.voted_for.write().await;
+            let new_term = self.node.increment_term();
+            self.node.set_state(NodeState::Candidate);
+            self.node.set_leader(None);
+            *voted_for = Some(self.node.id().clone());
+            new_term
+        };
         info!("starting election for term {}", new_term);
-        *self.voted_for.write().await = Some(self.node.id().clone());
-        let peers = self.peers.read().await.clone();
+        *self.last_contact.write().await = Instant::now();
+        let peers: Vec<NodeInfo> = self.peers.read().await.values().cloned().collect();
+        let request = VoteRequest {
+            candidate_id: self.node.id().clone(),
+            term: new_term,
+            priority: self.node.priority(),
+        };
+        let responses = join_all(
+            peers
+                .iter()
+                .map(|peer| self.call(peer, NetworkMessage::VoteRequest(request.clone()))),
+        )
+        .await;
         let mut votes_received = 1;
-        let votes_needed = (peers.len() + 1).div_ceil(2);
-        for peer_id in peers.keys() {
-            let request = VoteRequest {
-                candidate_id: self.node.id().clone(),
-                term: new_term,
-                priority: self.node.priority(),
-            };
-            debug!("requesting vote from {}", peer_id);
-            let _ = request;
-            votes_received += 1;
+        let votes_needed = peers.len().div_ceil(2) + 1;
+        for (peer, response) in peers.iter().zip(responses) {
+            match response {
+                Ok(NetworkMessage::VoteResponse(response)) => {
+                    if response.term > new_term {
+                        self.step_down(response.term).await;
+                        return Ok(false);
+                    }
+                    if response.vote_granted {
+                        votes_received += 1;
+                    }
+                }
+                Ok(_) => debug!("unexpected vote response from {}", peer.id),
+                Err(e) => debug!("vote request to {} failed: {}", peer.id, e),
+            }
+        }
+        if self.node.term() != new_term || self.node.state() != NodeState::Candidate {
+            return Ok(false);
         }
         if votes_received >= votes_needed {
             self.become_leader().await;
             Ok(true)
         } else {
+            debug!(
+                "lost election for term {} ({}/{} votes)",
+                new_term, votes_received, votes_needed
+            );
             self.node.set_state(NodeState::Follower);
             Ok(false)
         }
     }
+    async fn step_down(&self, term: u64) {
+        let mut voted_for = self.voted_for.write().await;
+        self.node.set_term(term);
+        self.node.set_state(NodeState::Follower);
+        self.node.set_leader(None);
+        *voted_for = None;
+        drop(voted_for);
+        *self.last_contact.write().await = Instant::now();
+    }
     async fn become_leader(&self) {
         info!("became leader for term {}", self.node.term());
         self.node.set_state(NodeState::Leader);
         self.node.set_leader(Some(self.node.id().clone()));
-        let node = self.node.clone();
-        let heartbeat_interval = self.heartbeat_interval;
-        tokio::spawn(async move {
-            let mut timer = interval(heartbeat_interval);
-            loop {
-                timer.tick().await;
-                if !matches!(node.state(), NodeState::Leader) {
-                    break;
-                }
-            }
-        });
     }
 }
 #[async_trait]
 impl ConsensusProtocol for RaftConsensus {
     async fn start(&self) -> Result<()> {
         self.node.set_state(NodeState::Follower);
+        self.start_heartbeats().await;
+        let raft = self.clone();
+        tokio::spawn(async move {
+            raft.run_election_timer().await;
+        });
         info!("raft consensus started");
         Ok(())
     }
@@ -139,6 +247,7 @@ impl ConsensusProtocol for RaftConsensus {
         Ok(())
     }
     async fn request_vote(&self, request: VoteRequest) -> Result<VoteResponse> {
+        let mut voted_for = self.voted_for.write().await;
         let current_term = self.node.term();
         if request.term < current_term {
             return Ok(VoteResponse {
@@ -150,24 +259,27 @@ impl ConsensusProtocol for RaftConsensus {
         if request.term > current_term {
             self.node.set_term(request.term);
             self.node.set_state(NodeState::Follower);
-            *self.voted_for.write().await = None;
+            *voted_for = None;
         }
-        let voted_for = self.voted_for.read().await.clone();
-        let vote_granted = match voted_for {
+        let vote_granted = match *voted_for {
             None => {
-                *self.voted_for.write().await = Some(request.candidate_id.clone());
+                *voted_for = Some(request.candidate_id.clone());
                 true
             }
-            Some(ref id) if id == &request.candidate_id => true,
-            _ => false,
+            Some(ref id) => id == &request.candidate_id,
         };
+        let term = self.node.term();
+        drop(voted_for);
+        if vote_granted {
+            *self.last_contact.write().await = Instant::now();
+        }
         debug!(
             "vote request from {}: granted={}",
             request.candidate_id, vote_granted
         );
         Ok(VoteResponse {
             node_id: self.node.id().clone(),
-            term: self.node.term(),
+            term,
             vote_granted,
         })
     }
@@ -182,10 +294,15 @@ impl ConsensusProtocol for RaftConsensus {
             });
         }
         if request.term > current_term {
-            self.node.set_term(request.term);
+            let mut voted_for = self.voted_for.write().await;
+            if request.term > self.node.term() {
+                self.node.set_term(request.term);
+                *voted_for = None;
+            }
         }
         self.node.set_state(NodeState::Follower);
         self.node.set_leader(Some(request.leader_id.clone()));
+        *self.last_contact.write().await = Instant::now();
         let mut commit_index = self.commit_index.write().await;
         if request.commit_index > *commit_index {
             *commit_index = request.commit_index;
@@ -207,3 +324,113 @@ impl ConsensusProtocol for RaftConsensus {
         self.node.leader()
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetworkService;
+    use crate::node::{Node, NodeConfig};
+    async fn raft_node(id: &str) -> (Arc<RaftConsensus>, Arc<NetworkService>) {
+        let gossip_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map(|a| a.port())
+            .unwrap();
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some(id.to_string()),
+            bind_address: format!("127.0.0.1:{}", gossip_port - 100).parse().unwrap(),
+            advertise_address: None,
+            ..Default::default()
+        }));
+        let raft = Arc::new(
+            RaftConsensus::new(node.clone())
+                .with_heartbeat_interval(Duration::from_millis(50))
+                .with_election_timeout_range(
+                    Duration::from_millis(300),
+                    Duration::from_millis(600),
+                ),
+        );
+        let network =
+            Arc::new(NetworkService::new(node.info().clone()).with_consensus(raft.clone()));
+        network.start().await.unwrap();
+        (raft, network)
+    }
+    async fn wait_for_single_leader(nodes: &[&RaftConsensus]) -> NodeId {
+        for _ in 0..100 {
+            let leaders: Vec<_> = nodes
+                .iter()
+                .filter(|r| r.state() == NodeState::Leader)
+                .collect();
+            if let [leader] = leaders[..] {
+                let id = leader.node.id().clone();
+                if nodes.iter().all(|r| r.leader().as_ref() == Some(&id)) {
+                    return id;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("raft never converged on a single leader");
+    }
+    #[tokio::test]
+    async fn test_three_nodes_elect_and_reelect() {
+        let mut nodes = Vec::new();
+        for id in ["a", "b", "c"] {
+            nodes.push(raft_node(id).await);
+        }
+        for (raft, _) in &nodes {
+            for (other, _) in &nodes {
+                if raft.node.id() != other.node.id() {
+                    raft.add_peer(other.node.info().clone()).await;
+                }
+            }
+            raft.start().await.unwrap();
+        }
+        let rafts: Vec<&RaftConsensus> = nodes.iter().map(|(r, _)| r.as_ref()).collect();
+        let leader = wait_for_single_leader(&rafts).await;
+        let index = rafts.iter().position(|r| r.node.id() == &leader).unwrap();
+        let term = rafts[index].term();
+        let (stopped, network) = &nodes[index];
+        network.stop().await;
+        stopped.stop().await.unwrap();
+        let survivors: Vec<&RaftConsensus> = rafts
+            .iter()
+            .copied()
+            .filter(|r| r.node.id() != &leader)
+            .collect();
+        let successor = wait_for_single_leader(&survivors).await;
+        assert_ne!(successor, leader);
+        assert!(survivors.iter().all(|r| r.term() > term));
+    }
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_vote_requests_grant_a_single_candidate() {
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some("voter".to_string()),
+            ..Default::default()
+        }));
+        let raft = RaftConsensus::new(node);
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let raft = raft.clone();
+                let candidate_id = NodeId::from_string(format!("candidate-{}", i));
+                tokio::spawn(async move {
+                    let response = raft
+                        .request_vote(VoteRequest {
+                            candidate_id: candidate_id.clone(),
+                            term: 1,
+                            priority: 100,
+                        })
+                        .await
+                        .unwrap();
+                    (candidate_id, response.vote_granted)
+                })
+            })
+            .collect();
+        let granted: Vec<NodeId> = join_all(tasks)
+            .await
+            .into_iter()
+            .map(|joined| joined.unwrap())
+            .filter(|(_, granted)| *granted)
+            .map(|(candidate_id, _)| candidate_id)
+            .collect();
+        assert_eq!(granted.len(), 1);
+        assert_eq!(raft.voted_for.read().await.as_ref(), Some(&granted[0]));
+    }
+}
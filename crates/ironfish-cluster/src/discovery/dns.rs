@@ -1,41 +1,244 @@
 use async_trait::async_trait;
 use chrono::Utc;
-use ironfish_core::{ClusterDiscovery, NodeId, NodeInfo, Result};
-use tokio::net::lookup_host;
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use ironfish_core::{ClusterDiscovery, Error, NodeId, NodeInfo, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 use tracing::{debug, warn};
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+const MAX_TTL: Duration = Duration::from_secs(300);
+const NEGATIVE_TTL: Duration = Duration::from_secs(5);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsRecordType {
+    #[default]
+    Auto,
+    A,
+    Srv,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsDiscoveryConfig {
+    pub name: String,
+    pub port: Option<u16>,
+    pub record_type: DnsRecordType,
+}
+impl DnsDiscoveryConfig {
+    pub fn is_srv(&self) -> bool {
+        match self.record_type {
+            DnsRecordType::Srv => true,
+            DnsRecordType::A => false,
+            DnsRecordType::Auto => self.name.starts_with('_'),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+    pub ttl: u32,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRecord {
+    pub address: IpAddr,
+    pub ttl: Option<u32>,
+}
+#[async_trait]
+pub trait DnsResolver: Send + Sync {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>>;
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpRecord>>;
+}
+pub struct SystemResolver {
+    resolver: TokioAsyncResolver,
+}
+impl SystemResolver {
+    pub fn new() -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            warn!(
+                "failed to load system DNS configuration, using defaults: {}",
+                e
+            );
+            TokioAsyncResolver::tokio(Default::default(), Default::default())
+        });
+        Self { resolver }
+    }
+}
+impl Default for SystemResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn lookup_error<T>(name: &str, e: ResolveError) -> Result<Vec<T>> {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => Ok(Vec::new()),
+        _ => Err(Error::Discovery(format!(
+            "lookup of {} failed: {}",
+            name, e
+        ))),
+    }
+}
+#[async_trait]
+impl DnsResolver for SystemResolver {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        let lookup = match self.resolver.srv_lookup(name).await {
+            Ok(lookup) => lookup,
+            Err(e) => return lookup_error(name, e),
+        };
+        Ok(lookup
+            .as_lookup()
+            .record_iter()
+            .filter_map(|record| {
+                let srv = record.data()?.as_srv()?;
+                Some(SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    port: srv.port(),
+                    target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                    ttl: record.ttl(),
+                })
+            })
+            .collect())
+    }
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpRecord>> {
+        let lookup = match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => lookup,
+            Err(e) => return lookup_error(host, e),
+        };
+        Ok(lookup
+            .as_lookup()
+            .record_iter()
+            .filter_map(|record| {
+                Some(IpRecord {
+                    address: record.data()?.ip_addr()?,
+                    ttl: Some(record.ttl()),
+                })
+            })
+            .collect())
+    }
+}
+struct Cached {
+    nodes: Vec<NodeInfo>,
+    expires_at: Instant,
+}
 pub struct DnsDiscovery {
-    hostname: String,
-    port: u16,
+    config: DnsDiscoveryConfig,
+    resolver: Arc<dyn DnsResolver>,
+    cache: Mutex<Option<Cached>>,
 }
 impl DnsDiscovery {
-    pub fn new(hostname: String, port: u16) -> Self {
-        Self { hostname, port }
+    pub fn new(config: DnsDiscoveryConfig) -> Self {
+        Self {
+            config,
+            resolver: Arc::new(SystemResolver::new()),
+            cache: Mutex::new(None),
+        }
+    }
+    pub fn with_resolver(mut self, resolver: Arc<dyn DnsResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+    fn ttl(seconds: Option<u32>) -> Duration {
+        seconds
+            .map(|s| Duration::from_secs(s as u64))
+            .unwrap_or(DEFAULT_TTL)
+            .min(MAX_TTL)
+    }
+    fn node(address: SocketAddr) -> NodeInfo {
+        NodeInfo {
+            id: NodeId::from_string(address.to_string()),
+            address,
+            priority: 100,
+            started_at: Utc::now(),
+            version: "unknown".to_string(),
+        }
+    }
+    async fn resolve_srv(&self) -> Result<(Vec<SocketAddr>, Duration)> {
+        let mut records = self.resolver.lookup_srv(&self.config.name).await?;
+        records.retain(|r| !r.target.is_empty() && r.target != ".");
+        records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        let mut ttl = MAX_TTL;
+        let mut addresses = Vec::new();
+        for record in records {
+            ttl = ttl.min(Self::ttl(Some(record.ttl)));
+            match self.resolver.lookup_ip(&record.target).await {
+                Ok(ips) => {
+                    for ip in ips {
+                        ttl = ttl.min(Self::ttl(ip.ttl));
+                        addresses.push(SocketAddr::new(ip.address, record.port));
+                    }
+                }
+                Err(e) => warn!("failed to resolve SRV target {}: {}", record.target, e),
+            }
+        }
+        Ok((addresses, ttl))
+    }
+    async fn resolve_ip(&self) -> Result<(Vec<SocketAddr>, Duration)> {
+        let port = self.config.port.ok_or_else(|| {
+            Error::Discovery(format!(
+                "discovery.dns.port is required to look up {}",
+                self.config.name
+            ))
+        })?;
+        let ips = self.resolver.lookup_ip(&self.config.name).await?;
+        let ttl = ips
+            .iter()
+            .map(|ip| Self::ttl(ip.ttl))
+            .min()
+            .unwrap_or(DEFAULT_TTL);
+        let addresses = ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip.address, port))
+            .collect();
+        Ok((addresses, ttl))
     }
 }
 #[async_trait]
 impl ClusterDiscovery for DnsDiscovery {
     async fn discover(&self) -> Result<Vec<NodeInfo>> {
-        let target = format!("{}:{}", self.hostname, self.port);
-        let mut nodes = Vec::new();
-        match lookup_host(&target).await {
-            Ok(addrs) => {
-                for addr in addrs {
-                    let id_str = addr.to_string();
-                    let node = NodeInfo {
-                        id: NodeId::from_string(&id_str),
-                        address: addr,
-                        priority: 100,
-                        started_at: Utc::now(),
-                        version: "unknown".to_string(),
-                    };
-                    nodes.push(node);
-                }
+        let mut cache = self.cache.lock().await;
+        if let Some(ref cached) = *cache {
+            if Instant::now() < cached.expires_at {
+                return Ok(cached.nodes.clone());
+            }
+        }
+        let resolved = if self.config.is_srv() {
+            self.resolve_srv().await
+        } else {
+            self.resolve_ip().await
+        };
+        let (nodes, ttl) = match resolved {
+            Ok((addresses, ttl)) => {
+                let mut seen = HashSet::new();
+                let nodes: Vec<_> = addresses
+                    .into_iter()
+                    .filter(|addr| seen.insert(*addr))
+                    .map(Self::node)
+                    .collect();
+                let ttl = if nodes.is_empty() { NEGATIVE_TTL } else { ttl };
+                (nodes, ttl)
             }
             Err(e) => {
-                warn!("dns lookup failed for {}: {}", target, e);
+                warn!("dns lookup failed for {}: {}", self.config.name, e);
+                (Vec::new(), NEGATIVE_TTL)
             }
-        }
-        debug!("dns discovery found {} peers for {}", nodes.len(), target);
+        };
+        debug!(
+            "dns discovery found {} peers for {}",
+            nodes.len(),
+            self.config.name
+        );
+        *cache = Some(Cached {
+            nodes: nodes.clone(),
+            expires_at: Instant::now() + ttl,
+        });
         Ok(nodes)
     }
     async fn announce(&self, _node: &NodeInfo) -> Result<()> {
@@ -45,3 +248,103 @@ impl ClusterDiscovery for DnsDiscovery {
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    #[derive(Default)]
+    struct MockResolver {
+        srv: std::sync::Mutex<Vec<SrvRecord>>,
+        hosts: std::sync::Mutex<HashMap<String, Vec<IpRecord>>>,
+        srv_lookups: AtomicUsize,
+    }
+    impl MockResolver {
+        fn host(&self, name: &str, ip: &str, ttl: u32) {
+            self.hosts.lock().unwrap().insert(
+                name.to_string(),
+                vec![IpRecord {
+                    address: ip.parse().unwrap(),
+                    ttl: Some(ttl),
+                }],
+            );
+        }
+    }
+    #[async_trait]
+    impl DnsResolver for MockResolver {
+        async fn lookup_srv(&self, _name: &str) -> Result<Vec<SrvRecord>> {
+            self.srv_lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(self.srv.lock().unwrap().clone())
+        }
+        async fn lookup_ip(&self, host: &str) -> Result<Vec<IpRecord>> {
+            self.hosts
+                .lock()
+                .unwrap()
+                .get(host)
+                .cloned()
+                .ok_or_else(|| Error::Discovery(format!("no such host {}", host)))
+        }
+    }
+    fn srv(priority: u16, weight: u16, port: u16, target: &str, ttl: u32) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.to_string(),
+            ttl,
+        }
+    }
+    #[tokio::test(start_paused = true)]
+    async fn test_srv_discovery_refreshes_after_ttl() {
+        let resolver = Arc::new(MockResolver::default());
+        *resolver.srv.lock().unwrap() = vec![
+            srv(20, 1, 7002, "node-b.chess.internal", 1),
+            srv(10, 1, 7001, "node-a.chess.internal", 60),
+            srv(10, 1, 7001, "node-a.chess.internal", 60),
+        ];
+        resolver.host("node-a.chess.internal", "10.0.0.1", 60);
+        resolver.host("node-b.chess.internal", "10.0.0.2", 60);
+        let discovery = DnsDiscovery::new(DnsDiscoveryConfig {
+            name: "_ironfish._tcp.chess.internal".into(),
+            ..Default::default()
+        })
+        .with_resolver(resolver.clone());
+        let peers = discovery.discover().await.unwrap();
+        let addresses: Vec<String> = peers.iter().map(|p| p.address.to_string()).collect();
+        assert_eq!(addresses, vec!["10.0.0.1:7001", "10.0.0.2:7002"]);
+        assert_eq!(peers[0].id, NodeId::from_string("10.0.0.1:7001"));
+        assert_eq!(discovery.discover().await.unwrap().len(), 2);
+        assert_eq!(resolver.srv_lookups.load(Ordering::SeqCst), 1);
+        resolver.host("node-b.chess.internal", "10.0.0.3", 60);
+        tokio::time::advance(Duration::from_millis(1100)).await;
+        let refreshed = discovery.discover().await.unwrap();
+        assert_eq!(resolver.srv_lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(refreshed[0].id, peers[0].id);
+        assert_eq!(refreshed[1].address.to_string(), "10.0.0.3:7002");
+        resolver.srv.lock().unwrap().clear();
+        tokio::time::advance(Duration::from_millis(1100)).await;
+        assert!(discovery.discover().await.unwrap().is_empty());
+        assert!(discovery.discover().await.unwrap().is_empty());
+        assert_eq!(resolver.srv_lookups.load(Ordering::SeqCst), 3);
+    }
+    #[tokio::test]
+    async fn test_a_records_need_a_port() {
+        let resolver = Arc::new(MockResolver::default());
+        resolver.host("ironfish.chess.internal", "10.0.0.9", 30);
+        let config = DnsDiscoveryConfig {
+            name: "ironfish.chess.internal".into(),
+            port: None,
+            record_type: DnsRecordType::Auto,
+        };
+        assert!(!config.is_srv());
+        let discovery = DnsDiscovery::new(config.clone()).with_resolver(resolver.clone());
+        assert!(discovery.discover().await.unwrap().is_empty());
+        let discovery = DnsDiscovery::new(DnsDiscoveryConfig {
+            port: Some(7000),
+            ..config
+        })
+        .with_resolver(resolver);
+        let peers = discovery.discover().await.unwrap();
+        assert_eq!(peers[0].address, "10.0.0.9:7000".parse().unwrap());
+    }
+}
@@ -0,0 +1,265 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use ironfish_core::{ClusterDiscovery, Error, NodeId, NodeInfo, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::{debug, warn};
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KubernetesDiscoveryConfig {
+    pub enabled: bool,
+    pub namespace: Option<String>,
+    pub service: String,
+    pub port: u16,
+    pub label_selector: Option<String>,
+}
+impl Default for KubernetesDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            namespace: None,
+            service: "ironfish".to_string(),
+            port: 8080,
+            label_selector: None,
+        }
+    }
+}
+impl KubernetesDiscoveryConfig {
+    pub fn namespace(&self) -> String {
+        self.namespace.clone().unwrap_or_else(|| {
+            std::fs::read_to_string(Path::new(SERVICE_ACCOUNT_DIR).join("namespace"))
+                .map(|ns| ns.trim().to_string())
+                .unwrap_or_else(|_| "default".to_string())
+        })
+    }
+}
+struct ApiServer {
+    base_url: String,
+    client: reqwest::Client,
+}
+pub struct K8sDiscovery {
+    namespace: String,
+    service: String,
+    port: u16,
+    label_selector: Option<String>,
+    service_account_dir: PathBuf,
+    api: OnceCell<ApiServer>,
+}
+impl K8sDiscovery {
+    pub fn new(namespace: String, service: String, port: u16) -> Self {
+        Self {
+            namespace,
+            service,
+            port,
+            label_selector: None,
+            service_account_dir: PathBuf::from(SERVICE_ACCOUNT_DIR),
+            api: OnceCell::new(),
+        }
+    }
+    pub fn with_label_selector(mut self, selector: Option<String>) -> Self {
+        self.label_selector = selector.filter(|s| !s.is_empty());
+        self
+    }
+    async fn api_server(&self) -> Result<&ApiServer> {
+        self.api
+            .get_or_try_init(|| async {
+                let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                    .map_err(|_| Error::Discovery("not running in a kubernetes pod".into()))?;
+                let port =
+                    std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".into());
+                let ca = tokio::fs::read(self.service_account_dir.join("ca.crt"))
+                    .await
+                    .map_err(|e| Error::Discovery(format!("failed to read cluster CA: {}", e)))?;
+                let ca = reqwest::Certificate::from_pem(&ca)
+                    .map_err(|e| Error::Discovery(format!("invalid cluster CA: {}", e)))?;
+                let client = reqwest::Client::builder()
+                    .add_root_certificate(ca)
+                    .timeout(Duration::from_secs(5))
+                    .build()
+                    .map_err(|e| Error::Discovery(format!("http client failed: {}", e)))?;
+                let host = match host.parse::<IpAddr>() {
+                    Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+                    _ => host,
+                };
+                Ok(ApiServer {
+                    base_url: format!("https://{}:{}", host, port),
+                    client,
+                })
+            })
+            .await
+    }
+    fn selector(&self) -> String {
+        let mut selector = format!("kubernetes.io/service-name={}", self.service);
+        if let Some(ref extra) = self.label_selector {
+            selector.push(',');
+            selector.push_str(extra);
+        }
+        selector
+    }
+    async fn list_endpoint_slices(&self) -> Result<EndpointSliceList> {
+        let api = self.api_server().await?;
+        let token = tokio::fs::read_to_string(self.service_account_dir.join("token"))
+            .await
+            .map_err(|e| {
+                Error::Discovery(format!("failed to read service account token: {}", e))
+            })?;
+        let url = format!(
+            "{}/apis/discovery.k8s.io/v1/namespaces/{}/endpointslices",
+            api.base_url, self.namespace
+        );
+        api.client
+            .get(url)
+            .query(&[("labelSelector", self.selector())])
+            .bearer_auth(token.trim())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| Error::Discovery(format!("endpointslice request failed: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| Error::Discovery(format!("invalid endpointslice response: {}", e)))
+    }
+}
+#[async_trait]
+impl ClusterDiscovery for K8sDiscovery {
+    async fn discover(&self) -> Result<Vec<NodeInfo>> {
+        match self.list_endpoint_slices().await {
+            Ok(slices) => {
+                let nodes = nodes_from_slices(&slices, self.port);
+                debug!(
+                    "kubernetes discovery found {} peers for {}/{}",
+                    nodes.len(),
+                    self.namespace,
+                    self.service
+                );
+                Ok(nodes)
+            }
+            Err(e) => {
+                warn!("kubernetes discovery failed: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+    async fn announce(&self, _node: &NodeInfo) -> Result<()> {
+        Ok(())
+    }
+    async fn withdraw(&self, _node_id: &NodeId) -> Result<()> {
+        Ok(())
+    }
+}
+#[derive(Debug, Deserialize)]
+struct EndpointSliceList {
+    #[serde(default)]
+    items: Vec<EndpointSlice>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointSlice {
+    #[serde(default)]
+    address_type: String,
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoint {
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    conditions: EndpointConditions,
+    hostname: Option<String>,
+    target_ref: Option<ObjectReference>,
+}
+#[derive(Debug, Default, Deserialize)]
+struct EndpointConditions {
+    ready: Option<bool>,
+}
+#[derive(Debug, Deserialize)]
+struct ObjectReference {
+    name: Option<String>,
+}
+fn nodes_from_slices(slices: &EndpointSliceList, port: u16) -> Vec<NodeInfo> {
+    let mut nodes = BTreeMap::new();
+    for slice in slices.items.iter().filter(|s| s.address_type != "FQDN") {
+        for endpoint in &slice.endpoints {
+            if endpoint.conditions.ready == Some(false) {
+                continue;
+            }
+            for address in &endpoint.addresses {
+                let Ok(ip) = address.parse::<IpAddr>() else {
+                    continue;
+                };
+                let address = SocketAddr::new(ip, port);
+                let name = endpoint
+                    .target_ref
+                    .as_ref()
+                    .and_then(|r| r.name.clone())
+                    .or_else(|| endpoint.hostname.clone())
+                    .unwrap_or_else(|| address.to_string());
+                nodes.entry(address).or_insert_with(|| NodeInfo {
+                    id: NodeId::from_string(&name),
+                    address,
+                    priority: 100,
+                    started_at: Utc::now(),
+                    version: "unknown".to_string(),
+                });
+            }
+        }
+    }
+    nodes.into_values().collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_nodes_from_slices() {
+        let slices: EndpointSliceList = serde_json::from_value(serde_json::json!({
+            "items": [
+                {
+                    "addressType": "IPv4",
+                    "endpoints": [
+                        {
+                            "addresses": ["10.0.0.1"],
+                            "conditions": {"ready": true},
+                            "targetRef": {"kind": "Pod", "name": "ironfish-0"}
+                        },
+                        {
+                            "addresses": ["10.0.0.2"],
+                            "conditions": {"ready": false},
+                            "targetRef": {"kind": "Pod", "name": "ironfish-1"}
+                        },
+                        {
+                            "addresses": ["10.0.0.3"],
+                            "hostname": "ironfish-2"
+                        }
+                    ]
+                },
+                {
+                    "addressType": "FQDN",
+                    "endpoints": [{"addresses": ["ironfish.example.com"]}]
+                }
+            ]
+        }))
+        .unwrap();
+        let nodes = nodes_from_slices(&slices, 9000);
+        let ids: Vec<_> = nodes.iter().map(|n| n.id.0.as_str()).collect();
+        assert_eq!(ids, vec!["ironfish-0", "ironfish-2"]);
+        assert_eq!(nodes[0].address, "10.0.0.1:9000".parse().unwrap());
+    }
+    #[tokio::test]
+    async fn test_outside_cluster_returns_no_peers() {
+        let mut discovery = K8sDiscovery::new("default".into(), "ironfish".into(), 8080)
+            .with_label_selector(Some("app=ironfish".into()));
+        discovery.service_account_dir = std::env::temp_dir().join("ironfish-no-service-account");
+        assert_eq!(
+            discovery.selector(),
+            "kubernetes.io/service-name=ironfish,app=ironfish"
+        );
+        assert!(discovery.discover().await.unwrap().is_empty());
+    }
+}
@@ -1,14 +1,22 @@
 mod dns;
+mod k8s;
 mod multicast;
 mod seed;
 mod static_conf;
+use crate::transport::GossipTransport;
 use async_trait::async_trait;
-pub use dns::DnsDiscovery;
+pub use dns::{
+    DnsDiscovery, DnsDiscoveryConfig, DnsRecordType, DnsResolver, IpRecord, SrvRecord,
+    SystemResolver,
+};
 use ironfish_core::{ClusterDiscovery, NodeId, NodeInfo, Result};
+pub use k8s::{K8sDiscovery, KubernetesDiscoveryConfig};
 pub use multicast::MulticastDiscovery;
 pub use seed::SeedDiscovery;
 pub use static_conf::StaticDiscovery;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::debug;
 pub struct DiscoveryManager {
@@ -16,7 +24,10 @@ pub struct DiscoveryManager {
     seed_discovery: Option<SeedDiscovery>,
     multicast_discovery: Option<MulticastDiscovery>,
     dns_discovery: Option<DnsDiscovery>,
-    known_peers: Arc<RwLock<Vec<NodeInfo>>>,
+    k8s_discovery: Option<K8sDiscovery>,
+    known_peers: Arc<RwLock<HashMap<NodeId, (NodeInfo, Instant)>>>,
+    removed: Arc<RwLock<Vec<NodeId>>>,
+    peer_ttl: Duration,
 }
 impl DiscoveryManager {
     pub fn new() -> Self {
@@ -25,7 +36,10 @@ impl DiscoveryManager {
             seed_discovery: None,
             multicast_discovery: None,
             dns_discovery: None,
-            known_peers: Arc::new(RwLock::new(Vec::new())),
+            k8s_discovery: None,
+            known_peers: Arc::new(RwLock::new(HashMap::new())),
+            removed: Arc::new(RwLock::new(Vec::new())),
+            peer_ttl: Duration::from_secs(30),
         }
     }
     pub fn with_static(mut self, peers: Vec<String>) -> Self {
@@ -34,9 +48,9 @@ impl DiscoveryManager {
         }
         self
     }
-    pub fn with_seeds(mut self, seeds: Vec<String>) -> Self {
+    pub fn with_seeds(mut self, seeds: Vec<String>, transport: GossipTransport) -> Self {
         if !seeds.is_empty() {
-            self.seed_discovery = Some(SeedDiscovery::new(seeds));
+            self.seed_discovery = Some(SeedDiscovery::new(seeds).with_transport(transport));
         }
         self
     }
@@ -44,14 +58,37 @@ impl DiscoveryManager {
         self.multicast_discovery = Some(MulticastDiscovery::new(group, port)?);
         Ok(self)
     }
-    pub fn with_dns(mut self, hostname: String, port: u16) -> Self {
-        if !hostname.is_empty() {
-            self.dns_discovery = Some(DnsDiscovery::new(hostname, port));
+    pub fn with_multicast_stale_after(mut self, stale_after: Duration) -> Self {
+        self.multicast_discovery = self
+            .multicast_discovery
+            .map(|m| m.with_stale_after(stale_after));
+        self
+    }
+    pub fn with_peer_ttl(mut self, ttl: Duration) -> Self {
+        self.peer_ttl = ttl;
+        self
+    }
+    pub fn with_dns(mut self, config: DnsDiscoveryConfig) -> Self {
+        if !config.name.is_empty() {
+            self.dns_discovery = Some(DnsDiscovery::new(config));
+        }
+        self
+    }
+    pub fn with_k8s(mut self, namespace: String, service: String, port: u16) -> Self {
+        if !service.is_empty() {
+            self.k8s_discovery = Some(K8sDiscovery::new(namespace, service, port));
         }
         self
     }
+    pub fn with_k8s_label_selector(mut self, selector: Option<String>) -> Self {
+        self.k8s_discovery = self
+            .k8s_discovery
+            .map(|k8s| k8s.with_label_selector(selector));
+        self
+    }
     pub async fn start(&self, local_node: &NodeInfo) -> Result<()> {
         if let Some(ref multicast) = self.multicast_discovery {
+            multicast.start().await;
             multicast.announce(local_node).await?;
         }
         Ok(())
@@ -60,8 +97,17 @@ impl DiscoveryManager {
         if let Some(ref multicast) = self.multicast_discovery {
             multicast.withdraw(node_id).await?;
         }
+        self.shutdown();
         Ok(())
     }
+    pub fn shutdown(&self) {
+        if let Some(ref multicast) = self.multicast_discovery {
+            multicast.shutdown();
+        }
+    }
+    pub async fn take_removed(&self) -> Vec<NodeId> {
+        std::mem::take(&mut *self.removed.write().await)
+    }
 }
 impl Default for DiscoveryManager {
     fn default() -> Self {
@@ -108,14 +154,39 @@ impl ClusterDiscovery for DiscoveryManager {
                 Err(e) => debug!("dns discovery failed: {}", e),
             }
         }
+        if let Some(ref k8s_disc) = self.k8s_discovery {
+            match k8s_disc.discover().await {
+                Ok(peers) => {
+                    debug!("kubernetes discovery found {} peers", peers.len());
+                    all_peers.extend(peers);
+                }
+                Err(e) => debug!("kubernetes discovery failed: {}", e),
+            }
+        }
+        let now = Instant::now();
         let mut known = self.known_peers.write().await;
-        for peer in &all_peers {
-            if !known.iter().any(|p| p.id == peer.id) {
-                known.push(peer.clone());
+        for peer in all_peers {
+            known.insert(peer.id.clone(), (peer, now));
+        }
+        let mut gone = match self.multicast_discovery {
+            Some(ref multicast) => multicast.take_withdrawn().await,
+            None => Vec::new(),
+        };
+        gone.retain(|id| known.remove(id).is_some());
+        let ttl = self.peer_ttl;
+        known.retain(|id, (_, last_seen)| {
+            let fresh = now.duration_since(*last_seen) < ttl;
+            if !fresh {
+                debug!("peer {} expired", id);
+                gone.push(id.clone());
             }
+            fresh
+        });
+        if !gone.is_empty() {
+            self.removed.write().await.extend(gone);
         }
         debug!("discovered {} total peers", known.len());
-        Ok(known.clone())
+        Ok(known.values().map(|(peer, _)| peer.clone()).collect())
     }
     async fn announce(&self, node: &NodeInfo) -> Result<()> {
         if let Some(ref multicast) = self.multicast_discovery {
@@ -124,11 +195,87 @@ impl ClusterDiscovery for DiscoveryManager {
         Ok(())
     }
     async fn withdraw(&self, node_id: &NodeId) -> Result<()> {
-        let mut known = self.known_peers.write().await;
-        known.retain(|p| &p.id != node_id);
+        self.known_peers.write().await.remove(node_id);
         if let Some(ref multicast) = self.multicast_discovery {
             multicast.withdraw(node_id).await?;
         }
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    fn free_port() -> u16 {
+        std::net::UdpSocket::bind("0.0.0.0:0")
+            .and_then(|s| s.local_addr())
+            .map(|a| a.port())
+            .unwrap()
+    }
+    async fn wait_for_peers(manager: &DiscoveryManager, expected: usize) -> Vec<NodeInfo> {
+        for _ in 0..50 {
+            let peers = manager.discover().await.unwrap();
+            if peers.len() == expected {
+                return peers;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        manager.discover().await.unwrap()
+    }
+    #[tokio::test]
+    async fn test_expires_and_withdraws_peers() {
+        let port = free_port();
+        let manager = DiscoveryManager::new()
+            .with_multicast("239.255.42.98", port)
+            .unwrap()
+            .with_multicast_stale_after(Duration::from_millis(100))
+            .with_peer_ttl(Duration::from_millis(400));
+        let announcer = MulticastDiscovery::new("239.255.42.98", port).unwrap();
+        assert!(manager.discover().await.unwrap().is_empty());
+        let peer = NodeInfo {
+            id: NodeId::from_string("peer-a"),
+            address: "127.0.0.1:8080".parse().unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        };
+        announcer.announce(&peer).await.unwrap();
+        assert_eq!(wait_for_peers(&manager, 1).await[0].id, peer.id);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(manager.discover().await.unwrap().len(), 1);
+        assert!(manager.take_removed().await.is_empty());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(manager.discover().await.unwrap().is_empty());
+        assert_eq!(manager.take_removed().await, vec![peer.id.clone()]);
+        announcer.announce(&peer).await.unwrap();
+        assert_eq!(wait_for_peers(&manager, 1).await.len(), 1);
+        announcer.withdraw(&peer.id).await.unwrap();
+        assert!(wait_for_peers(&manager, 0).await.is_empty());
+        assert_eq!(manager.take_removed().await, vec![peer.id]);
+        manager.shutdown();
+    }
+    #[tokio::test]
+    async fn test_restarts_after_stop() {
+        let port = free_port();
+        let manager = DiscoveryManager::new()
+            .with_multicast("239.255.42.97", port)
+            .unwrap();
+        let announcer = MulticastDiscovery::new("239.255.42.97", port).unwrap();
+        let node = |id: &str| NodeInfo {
+            id: NodeId::from_string(id),
+            address: "127.0.0.1:8080".parse().unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        };
+        let local = node("local");
+        manager.start(&local).await.unwrap();
+        manager.stop(&local.id).await.unwrap();
+        manager.start(&local).await.unwrap();
+        let peer = node("peer-b");
+        announcer.announce(&peer).await.unwrap();
+        let peers = wait_for_peers(&manager, 2).await;
+        assert!(peers.iter().any(|p| p.id == peer.id));
+        manager.shutdown();
+    }
+}
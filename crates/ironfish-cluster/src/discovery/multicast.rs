@@ -1,18 +1,26 @@
 use async_trait::async_trait;
 use ironfish_core::{ClusterDiscovery, Error, NodeId, NodeInfo, Result};
 use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 const DISCOVERY_MSG_ANNOUNCE: u8 = 1;
 const DISCOVERY_MSG_WITHDRAW: u8 = 2;
+const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(30);
+type PeerTable = Arc<RwLock<HashMap<NodeId, (NodeInfo, Instant)>>>;
 pub struct MulticastDiscovery {
     group: Ipv4Addr,
     port: u16,
-    socket: Arc<RwLock<Option<UdpSocket>>>,
+    socket: Arc<RwLock<Option<Arc<UdpSocket>>>>,
+    peers: PeerTable,
+    withdrawn: Arc<RwLock<Vec<NodeId>>>,
+    stale_after: Duration,
+    cancel: Mutex<CancellationToken>,
 }
 impl MulticastDiscovery {
     pub fn new(group: &str, port: u16) -> Result<Self> {
@@ -23,12 +31,34 @@ impl MulticastDiscovery {
             group,
             port,
             socket: Arc::new(RwLock::new(None)),
+            peers: Arc::new(RwLock::new(HashMap::new())),
+            withdrawn: Arc::new(RwLock::new(Vec::new())),
+            stale_after: DEFAULT_STALE_AFTER,
+            cancel: Mutex::new(CancellationToken::new()),
         })
     }
-    async fn ensure_socket(&self) -> Result<()> {
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+    pub async fn start(&self) {
+        let mut socket = self.socket.write().await;
+        let mut cancel = self.cancel.lock().unwrap();
+        if cancel.is_cancelled() {
+            *cancel = CancellationToken::new();
+            *socket = None;
+        }
+    }
+    pub fn shutdown(&self) {
+        self.cancel.lock().unwrap().cancel();
+    }
+    pub async fn take_withdrawn(&self) -> Vec<NodeId> {
+        std::mem::take(&mut *self.withdrawn.write().await)
+    }
+    async fn ensure_socket(&self) -> Result<Arc<UdpSocket>> {
         let mut socket_guard = self.socket.write().await;
-        if socket_guard.is_some() {
-            return Ok(());
+        if let Some(ref socket) = *socket_guard {
+            return Ok(socket.clone());
         }
         let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
             .map_err(|e| Error::Discovery(format!("socket creation failed: {}", e)))?;
@@ -54,15 +84,60 @@ impl MulticastDiscovery {
             .map_err(|e| Error::Discovery(format!("set_nonblocking failed: {}", e)))?;
         let tokio_socket = UdpSocket::from_std(socket.into())
             .map_err(|e| Error::Discovery(format!("tokio socket conversion failed: {}", e)))?;
-        *socket_guard = Some(tokio_socket);
-        Ok(())
+        let socket = Arc::new(tokio_socket);
+        self.spawn_receiver(socket.clone());
+        *socket_guard = Some(socket.clone());
+        Ok(socket)
+    }
+    fn spawn_receiver(&self, socket: Arc<UdpSocket>) {
+        let peers = self.peers.clone();
+        let withdrawn = self.withdrawn.clone();
+        let cancel = self.cancel.lock().unwrap().clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1024];
+            loop {
+                tokio::select! {
+                    result = socket.recv_from(&mut buf) => match result {
+                        Ok((len, _addr)) => Self::handle_packet(&peers, &withdrawn, &buf[..len]).await,
+                        Err(e) => {
+                            warn!("multicast recv error: {}", e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                    },
+                    _ = cancel.cancelled() => {
+                        debug!("multicast receiver stopped");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    async fn handle_packet(peers: &PeerTable, withdrawn: &RwLock<Vec<NodeId>>, packet: &[u8]) {
+        if packet.len() < 2 {
+            return;
+        }
+        match packet[0] {
+            DISCOVERY_MSG_ANNOUNCE => {
+                if let Ok(node) = serde_json::from_slice::<NodeInfo>(&packet[1..]) {
+                    debug!("discovered node via multicast: {}", node.id);
+                    peers
+                        .write()
+                        .await
+                        .insert(node.id.clone(), (node, Instant::now()));
+                }
+            }
+            DISCOVERY_MSG_WITHDRAW => {
+                if let Ok(node_id) = serde_json::from_slice::<NodeId>(&packet[1..]) {
+                    debug!("node withdrew via multicast: {}", node_id);
+                    peers.write().await.remove(&node_id);
+                    withdrawn.write().await.push(node_id);
+                }
+            }
+            _ => {}
+        }
     }
     async fn send_message(&self, msg_type: u8, data: &[u8]) -> Result<()> {
-        self.ensure_socket().await?;
-        let socket_guard = self.socket.read().await;
-        let socket = socket_guard
-            .as_ref()
-            .ok_or_else(|| Error::Discovery("socket not initialized".into()))?;
+        let socket = self.ensure_socket().await?;
         let mut packet = vec![msg_type];
         packet.extend_from_slice(data);
         let dest = SocketAddr::new(IpAddr::V4(self.group), self.port);
@@ -74,35 +149,25 @@ impl MulticastDiscovery {
         Ok(())
     }
 }
+impl Drop for MulticastDiscovery {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
 #[async_trait]
 impl ClusterDiscovery for MulticastDiscovery {
     async fn discover(&self) -> Result<Vec<NodeInfo>> {
         self.ensure_socket().await?;
-        let socket_guard = self.socket.read().await;
-        let socket = socket_guard
-            .as_ref()
-            .ok_or_else(|| Error::Discovery("socket not initialized".into()))?;
-        let mut nodes = Vec::new();
-        let mut buf = vec![0u8; 1024];
-        loop {
-            match tokio::time::timeout(Duration::from_millis(100), socket.recv_from(&mut buf)).await
-            {
-                Ok(Ok((len, _addr))) => {
-                    if len > 1 && buf[0] == DISCOVERY_MSG_ANNOUNCE {
-                        if let Ok(node) = serde_json::from_slice::<NodeInfo>(&buf[1..len]) {
-                            debug!("discovered node via multicast: {}", node.id);
-                            nodes.push(node);
-                        }
-                    }
-                }
-                Ok(Err(e)) => {
-                    warn!("multicast recv error: {}", e);
-                    break;
-                }
-                Err(_) => break,
+        let mut peers = self.peers.write().await;
+        let stale_after = self.stale_after;
+        peers.retain(|id, (_, last_seen)| {
+            let fresh = last_seen.elapsed() < stale_after;
+            if !fresh {
+                debug!("multicast peer {} expired", id);
             }
-        }
-        Ok(nodes)
+            fresh
+        });
+        Ok(peers.values().map(|(node, _)| node.clone()).collect())
     }
     async fn announce(&self, node: &NodeInfo) -> Result<()> {
         let data = serde_json::to_vec(node)
@@ -115,3 +180,57 @@ impl ClusterDiscovery for MulticastDiscovery {
         self.send_message(DISCOVERY_MSG_WITHDRAW, &data).await
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    fn free_port() -> u16 {
+        std::net::UdpSocket::bind("0.0.0.0:0")
+            .and_then(|s| s.local_addr())
+            .map(|a| a.port())
+            .unwrap()
+    }
+    fn node(id: &str) -> NodeInfo {
+        NodeInfo {
+            id: NodeId::from_string(id),
+            address: "127.0.0.1:8080".parse().unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        }
+    }
+    async fn wait_for_peers(discovery: &MulticastDiscovery, expected: usize) -> Vec<NodeInfo> {
+        for _ in 0..50 {
+            let peers = discovery.discover().await.unwrap();
+            if peers.len() == expected {
+                return peers;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        discovery.discover().await.unwrap()
+    }
+    #[tokio::test]
+    async fn test_multicast_receives_between_discover_calls() {
+        let port = free_port();
+        let listener = MulticastDiscovery::new("239.255.42.98", port)
+            .unwrap()
+            .with_stale_after(Duration::from_millis(300));
+        let announcer = MulticastDiscovery::new("239.255.42.98", port).unwrap();
+        assert!(listener.discover().await.unwrap().is_empty());
+        let peer = node("peer-a");
+        for _ in 0..3 {
+            announcer.announce(&peer).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let peers = wait_for_peers(&listener, 1).await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id, peer.id);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(listener.discover().await.unwrap().is_empty());
+        announcer.announce(&peer).await.unwrap();
+        assert_eq!(wait_for_peers(&listener, 1).await.len(), 1);
+        announcer.withdraw(&peer.id).await.unwrap();
+        assert!(wait_for_peers(&listener, 0).await.is_empty());
+        listener.shutdown();
+    }
+}
@@ -1,43 +1,73 @@
+use crate::network::{gossip_socket, send_and_receive, NetworkMessage};
+use crate::transport::GossipTransport;
 use async_trait::async_trait;
+use futures::future::join_all;
 use ironfish_core::{ClusterDiscovery, Error, NodeId, NodeInfo, Result};
+use std::collections::HashSet;
 use std::time::Duration;
-use tokio::net::TcpStream;
+use tokio::net::lookup_host;
 use tokio::time::timeout;
 use tracing::{debug, warn};
 pub struct SeedDiscovery {
     seeds: Vec<String>,
     timeout: Duration,
+    transport: GossipTransport,
 }
 impl SeedDiscovery {
     pub fn new(seeds: Vec<String>) -> Self {
         Self {
             seeds,
             timeout: Duration::from_secs(5),
+            transport: GossipTransport::default(),
         }
     }
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
     }
+    pub fn with_transport(mut self, transport: GossipTransport) -> Self {
+        self.transport = transport;
+        self
+    }
     async fn query_seed(&self, seed: &str) -> Result<Vec<NodeInfo>> {
-        let stream = timeout(self.timeout, TcpStream::connect(seed))
+        let address = lookup_host(seed)
             .await
-            .map_err(|_| Error::Discovery(format!("connection timeout to {}", seed)))?
-            .map_err(|e| Error::Discovery(format!("connection failed to {}: {}", seed, e)))?;
-        let _ = stream;
-        debug!("connected to seed {}", seed);
-        Ok(Vec::new())
+            .map_err(|e| Error::Discovery(format!("failed to resolve seed {}: {}", seed, e)))?
+            .next()
+            .ok_or_else(|| Error::Discovery(format!("seed {} has no address", seed)))?;
+        let response = timeout(
+            self.timeout,
+            send_and_receive(
+                gossip_socket(address),
+                &self.transport,
+                NetworkMessage::DiscoveryRequest,
+            ),
+        )
+        .await
+        .map_err(|_| Error::Discovery(format!("connection timeout to {}", seed)))??;
+        match response {
+            NetworkMessage::DiscoveryResponse { nodes, node } => {
+                debug!("connected to seed {}", seed);
+                Ok(node.into_iter().chain(nodes).collect())
+            }
+            _ => Err(Error::Discovery(format!(
+                "unexpected response from seed {}",
+                seed
+            ))),
+        }
     }
 }
 #[async_trait]
 impl ClusterDiscovery for SeedDiscovery {
     async fn discover(&self) -> Result<Vec<NodeInfo>> {
+        let results = join_all(self.seeds.iter().map(|seed| self.query_seed(seed))).await;
+        let mut seen = HashSet::new();
         let mut all_nodes = Vec::new();
-        for seed in &self.seeds {
-            match self.query_seed(seed).await {
+        for (seed, result) in self.seeds.iter().zip(results) {
+            match result {
                 Ok(nodes) => {
                     debug!("seed {} returned {} nodes", seed, nodes.len());
-                    all_nodes.extend(nodes);
+                    all_nodes.extend(nodes.into_iter().filter(|n| seen.insert(n.id.clone())));
                 }
                 Err(e) => {
                     warn!("failed to query seed {}: {}", seed, e);
@@ -53,3 +83,36 @@ impl ClusterDiscovery for SeedDiscovery {
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NetworkService;
+    use chrono::Utc;
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map(|a| a.port())
+            .unwrap()
+    }
+    #[tokio::test]
+    async fn test_seed_returns_its_own_node_info() {
+        let address: std::net::SocketAddr =
+            format!("127.0.0.1:{}", free_port() - 100).parse().unwrap();
+        let seed = NodeInfo {
+            id: NodeId::from_string("seed"),
+            address,
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        };
+        let network = NetworkService::new(seed.clone());
+        network.start().await.unwrap();
+        let dead = format!("127.0.0.1:{}", free_port() - 100);
+        let discovery = SeedDiscovery::new(vec![dead, address.to_string()])
+            .with_timeout(Duration::from_secs(1));
+        let nodes = discovery.discover().await.unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].id, seed.id);
+        assert_eq!(nodes[0].address, address);
+    }
+}
@@ -0,0 +1,60 @@
+use ironfish_core::ClusterEvent;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+const EVENT_BUFFER: usize = 256;
+#[derive(Clone)]
+pub struct ClusterEventBus {
+    tx: broadcast::Sender<ClusterEvent>,
+}
+impl Default for ClusterEventBus {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+impl ClusterEventBus {
+    pub fn subscribe(&self) -> broadcast::Receiver<ClusterEvent> {
+        self.tx.subscribe()
+    }
+    pub fn emit(&self, event: ClusterEvent) {
+        log_event(&event);
+        let _ = self.tx.send(event);
+    }
+}
+fn log_event(event: &ClusterEvent) {
+    match event {
+        ClusterEvent::NodeJoined { node } => {
+            info!("node {} joined cluster at {}", node.id, node.address)
+        }
+        ClusterEvent::NodeLeft { node_id, reason } => {
+            info!("node {} left cluster ({})", node_id, reason.as_str())
+        }
+        ClusterEvent::NodeDraining { node_id, draining } => info!(
+            "node {} {}",
+            node_id,
+            if *draining { "is draining" } else { "resumed" }
+        ),
+        ClusterEvent::NodeUnhealthy { node_id, reason } => {
+            warn!("node {} is unhealthy: {}", node_id, reason)
+        }
+        ClusterEvent::NodeRecovered { node_id } => info!("node {} recovered", node_id),
+        ClusterEvent::LeaderChanged {
+            old_leader,
+            new_leader,
+            term,
+        } => info!(
+            "leader changed from {} to {} in term {}",
+            display_leader(old_leader),
+            display_leader(new_leader),
+            term
+        ),
+        ClusterEvent::TokenRevoked { token_id } => info!("token {} revoked", token_id),
+    }
+}
+fn display_leader(leader: &Option<ironfish_core::NodeId>) -> String {
+    leader
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "none".to_string())
+}
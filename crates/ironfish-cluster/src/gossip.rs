@@ -1,29 +1,83 @@
+use crate::network::GossipEnvelope;
+use crate::state::ClusterStateStore;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use ironfish_core::{Error, GossipMessage, GossipProtocol, NodeId, NodeInfo, Result};
+use ironfish_core::{
+    Error, GossipMessage, GossipProtocol, GossipStoreStatus, NodeId, NodeInfo, Result,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
 use tokio::time::interval;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+const SEEN_CAPACITY: usize = 4096;
+const CLOCK_RESERVE: u64 = 1000;
+#[derive(Debug, Clone, Copy)]
+pub struct GossipRetention {
+    pub entry_ttl: Duration,
+    pub tombstone_ttl: Duration,
+    pub max_entries: usize,
+}
+impl Default for GossipRetention {
+    fn default() -> Self {
+        Self {
+            entry_ttl: Duration::from_secs(24 * 60 * 60),
+            tombstone_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            max_entries: 10_000,
+        }
+    }
+}
+impl GossipRetention {
+    fn ttl(&self, message: &GossipMessage) -> Option<Duration> {
+        if is_tombstone(message) {
+            Some(self.tombstone_ttl)
+        } else if is_transient(message) {
+            Some(self.entry_ttl)
+        } else {
+            None
+        }
+    }
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct GossipEntry {
+    id: Uuid,
     message: GossipMessage,
     timestamp: DateTime<Utc>,
     origin: NodeId,
     version: u64,
 }
+impl GossipEntry {
+    fn supersedes(&self, other: &GossipEntry) -> bool {
+        (self.version, &self.origin) > (other.version, &other.origin)
+    }
+}
+#[derive(Default)]
+struct LamportClock {
+    value: u64,
+    reserved: u64,
+    store: Option<ClusterStateStore>,
+}
 pub struct GossipService {
     node_id: NodeId,
     entries: Arc<RwLock<HashMap<String, GossipEntry>>>,
     peers: Arc<RwLock<Vec<NodeInfo>>>,
     message_rx: Arc<RwLock<mpsc::Receiver<GossipMessage>>>,
     broadcast_tx: broadcast::Sender<GossipMessage>,
+    seen: Arc<RwLock<SeenIds>>,
     sync_interval: Duration,
+    retention: GossipRetention,
+    clock: std::sync::Mutex<LamportClock>,
+    reserve: Mutex<()>,
     shutdown_tx: broadcast::Sender<()>,
 }
+#[derive(Default)]
+struct SeenIds {
+    order: VecDeque<Uuid>,
+    ids: HashSet<Uuid>,
+}
 impl GossipService {
     pub fn new(node_id: NodeId) -> Self {
         let (_message_tx, message_rx) = mpsc::channel(1024);
@@ -35,7 +89,11 @@ impl GossipService {
             peers: Arc::new(RwLock::new(Vec::new())),
             message_rx: Arc::new(RwLock::new(message_rx)),
             broadcast_tx,
+            seen: Arc::new(RwLock::new(SeenIds::default())),
             sync_interval: Duration::from_secs(5),
+            retention: GossipRetention::default(),
+            clock: std::sync::Mutex::new(LamportClock::default()),
+            reserve: Mutex::new(()),
             shutdown_tx,
         }
     }
@@ -43,6 +101,58 @@ impl GossipService {
         self.sync_interval = interval;
         self
     }
+    pub fn with_retention(mut self, retention: GossipRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+    pub async fn restore_clock(&self, store: ClusterStateStore) -> Result<()> {
+        let persisted = store.gossip_clock()?;
+        let mut clock = self.clock.lock().unwrap();
+        clock.value = clock.value.max(persisted);
+        clock.reserved = clock.value;
+        clock.store = Some(store);
+        Ok(())
+    }
+    pub async fn next_version(&self) -> u64 {
+        loop {
+            let (store, reserved) = {
+                let mut clock = self.clock.lock().unwrap();
+                match clock.store {
+                    Some(ref store) if clock.value >= clock.reserved => {
+                        (store.clone(), clock.value + CLOCK_RESERVE)
+                    }
+                    _ => {
+                        clock.value += 1;
+                        return clock.value;
+                    }
+                }
+            };
+            let _reserving = self.reserve.lock().await;
+            if self.clock.lock().unwrap().reserved >= reserved {
+                continue;
+            }
+            let persisted = store.set_gossip_clock(reserved).await;
+            let mut clock = self.clock.lock().unwrap();
+            match persisted {
+                Ok(()) => clock.reserved = clock.reserved.max(reserved),
+                Err(e) => {
+                    warn!("failed to persist gossip clock: {}", e);
+                    clock.value += 1;
+                    return clock.value;
+                }
+            }
+        }
+    }
+    pub async fn clock(&self) -> u64 {
+        self.clock.lock().unwrap().value
+    }
+    async fn observe(&self, version: u64) {
+        let mut clock = self.clock.lock().unwrap();
+        clock.value = clock.value.max(version);
+    }
+    pub async fn envelope(&self, message: GossipMessage) -> GossipEnvelope {
+        GossipEnvelope::new(message, self.node_id.clone(), self.next_version().await)
+    }
     pub fn subscribe(&self) -> broadcast::Receiver<GossipMessage> {
         self.broadcast_tx.subscribe()
     }
@@ -58,6 +168,8 @@ impl GossipService {
     }
     pub async fn start(&self) -> Result<()> {
         let peers = self.peers.clone();
+        let entries = self.entries.clone();
+        let retention = self.retention;
         let sync_interval = self.sync_interval;
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         tokio::spawn(async move {
@@ -69,6 +181,10 @@ impl GossipService {
                         for peer in peers_snapshot {
                             debug!("syncing with peer {}", peer.id);
                         }
+                        let removed = compact_store(&entries, &retention, Utc::now()).await;
+                        if removed > 0 {
+                            debug!("compacted {} gossip entries", removed);
+                        }
                     }
                     _ = shutdown_rx.recv() => {
                         break;
@@ -92,18 +208,90 @@ impl GossipService {
             GossipMessage::NodeJoined(n) => format!("node:{}", n.id),
             GossipMessage::NodeLeft(id) => format!("node:{}", id),
             GossipMessage::NodeMetrics(id, _) => format!("metrics:{}", id),
+            GossipMessage::NodeDraining(id, _) => format!("drain:{}", id),
+        }
+    }
+    pub async fn is_seen(&self, id: Uuid) -> bool {
+        self.seen.read().await.ids.contains(&id)
+    }
+    pub async fn mark_seen(&self, id: Uuid) -> bool {
+        let mut seen = self.seen.write().await;
+        if !seen.ids.insert(id) {
+            return false;
+        }
+        seen.order.push_back(id);
+        if seen.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+    pub async fn record(&self, envelope: &GossipEnvelope) {
+        self.observe(envelope.version).await;
+        let entry = GossipEntry {
+            id: envelope.id,
+            message: envelope.message.clone(),
+            timestamp: Utc::now(),
+            origin: envelope.origin.clone(),
+            version: envelope.version,
+        };
+        self.apply_message(&entry).await;
+    }
+    pub async fn entries_since(&self, since: &HashMap<NodeId, u64>) -> Vec<GossipEnvelope> {
+        self.entries_where(|e| e.version > since.get(&e.origin).copied().unwrap_or(0))
+            .await
+    }
+    pub async fn entries_after(&self, from_version: u64) -> Vec<GossipEnvelope> {
+        self.entries_where(|e| e.version > from_version).await
+    }
+    async fn entries_where(&self, include: impl Fn(&GossipEntry) -> bool) -> Vec<GossipEnvelope> {
+        let mut entries: Vec<GossipEnvelope> = self
+            .entries
+            .read()
+            .await
+            .values()
+            .filter(|e| include(e))
+            .map(|e| GossipEnvelope {
+                id: e.id,
+                message: e.message.clone(),
+                origin: e.origin.clone(),
+                version: e.version,
+                hops: 0,
+                relayed_by: None,
+            })
+            .collect();
+        entries.sort_by(|a, b| (a.version, &a.origin).cmp(&(b.version, &b.origin)));
+        entries
+    }
+    pub async fn compact(&self) -> usize {
+        compact_store(&self.entries, &self.retention, Utc::now()).await
+    }
+    pub async fn status(&self) -> GossipStoreStatus {
+        let now = Utc::now();
+        let entries = self.entries.read().await;
+        GossipStoreStatus {
+            entries: entries.len(),
+            tombstones: entries
+                .values()
+                .filter(|e| is_tombstone(&e.message))
+                .count(),
+            oldest_entry_age_seconds: entries
+                .values()
+                .map(|e| (now - e.timestamp).num_seconds().max(0) as u64)
+                .max()
+                .unwrap_or(0),
+            clock: self.clock().await,
         }
     }
     async fn apply_message(&self, entry: &GossipEntry) {
         let key = Self::entry_key(&entry.message);
         let mut entries = self.entries.write().await;
-        if let Some(existing) = entries.get(&key) {
-            if existing.version > entry.version {
-                return;
-            }
-            if existing.version == entry.version && existing.origin >= entry.origin {
-                return;
-            }
+        if entries
+            .get(&key)
+            .is_some_and(|existing| !entry.supersedes(existing))
+        {
+            return;
         }
         entries.insert(key, entry.clone());
         let _ = self.broadcast_tx.send(entry.message.clone());
@@ -114,10 +302,11 @@ impl GossipService {
 impl GossipProtocol for GossipService {
     async fn broadcast(&self, message: GossipMessage) -> Result<()> {
         let entry = GossipEntry {
+            id: Uuid::new_v4(),
             message: message.clone(),
             timestamp: Utc::now(),
             origin: self.node_id.clone(),
-            version: Utc::now().timestamp_millis() as u64,
+            version: self.next_version().await,
         };
         self.apply_message(&entry).await;
         let peers = self.peers.read().await.clone();
@@ -141,6 +330,70 @@ impl GossipProtocol for GossipService {
         Ok(())
     }
 }
+fn is_tombstone(message: &GossipMessage) -> bool {
+    matches!(
+        message,
+        GossipMessage::TokenRevoked(_) | GossipMessage::NodeLeft(_)
+    )
+}
+fn is_transient(message: &GossipMessage) -> bool {
+    matches!(
+        message,
+        GossipMessage::NodeMetrics(..) | GossipMessage::NodeDraining(..)
+    )
+}
+async fn compact_store(
+    entries: &RwLock<HashMap<String, GossipEntry>>,
+    retention: &GossipRetention,
+    now: DateTime<Utc>,
+) -> usize {
+    let expired = expired_entries(&*entries.read().await, retention, now);
+    if expired.is_empty() {
+        return 0;
+    }
+    remove_expired(&mut *entries.write().await, expired)
+}
+fn remove_expired(
+    entries: &mut HashMap<String, GossipEntry>,
+    expired: Vec<(String, Uuid)>,
+) -> usize {
+    let before = entries.len();
+    for (key, id) in expired {
+        if entries.get(&key).is_some_and(|e| e.id == id) {
+            entries.remove(&key);
+        }
+    }
+    before - entries.len()
+}
+fn expired_entries(
+    entries: &HashMap<String, GossipEntry>,
+    retention: &GossipRetention,
+    now: DateTime<Utc>,
+) -> Vec<(String, Uuid)> {
+    let mut expired = Vec::new();
+    let mut transient = Vec::new();
+    for (key, e) in entries {
+        let Some(ttl) = retention.ttl(&e.message) else {
+            continue;
+        };
+        if (now - e.timestamp).to_std().is_ok_and(|age| age >= ttl) {
+            expired.push((key.clone(), e.id));
+        } else if is_transient(&e.message) {
+            transient.push((e.timestamp, key.clone(), e.id));
+        }
+    }
+    let excess = (entries.len() - expired.len()).saturating_sub(retention.max_entries);
+    if excess > 0 {
+        transient.sort();
+        expired.extend(
+            transient
+                .into_iter()
+                .take(excess)
+                .map(|(_, key, id)| (key, id)),
+        );
+    }
+    expired
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -201,6 +454,252 @@ mod tests {
         let service = GossipService::new(NodeId::from_string("local"));
         let _rx = service.subscribe();
     }
+    #[tokio::test]
+    async fn test_entries_since() {
+        let service = GossipService::new(NodeId::from_string("local"));
+        let origin = NodeId::from_string("peer-1");
+        for (version, id) in [(10, "a"), (20, "b"), (30, "c")] {
+            service
+                .record(&GossipEnvelope {
+                    id: Uuid::new_v4(),
+                    message: GossipMessage::NodeLeft(NodeId::from_string(id)),
+                    origin: origin.clone(),
+                    version,
+                    hops: 2,
+                    relayed_by: None,
+                })
+                .await;
+        }
+        let entries = service
+            .entries_since(&HashMap::from([(origin.clone(), 10)]))
+            .await;
+        assert_eq!(
+            entries.iter().map(|e| e.version).collect::<Vec<_>>(),
+            vec![20, 30]
+        );
+        assert!(entries.iter().all(|e| e.origin == origin && e.hops == 0));
+        assert!(service
+            .entries_since(&HashMap::from([(origin, 30)]))
+            .await
+            .is_empty());
+        assert_eq!(service.entries_since(&HashMap::new()).await.len(), 3);
+    }
+    fn envelope(message: GossipMessage, origin: &str, version: u64) -> GossipEnvelope {
+        GossipEnvelope::new(message, NodeId::from_string(origin), version)
+    }
+    #[tokio::test]
+    async fn test_equal_versions_break_ties_by_node_id() {
+        let service = GossipService::new(NodeId::from_string("local"));
+        let token_id = Uuid::new_v4();
+        service
+            .record(&envelope(
+                GossipMessage::TokenRevoked(token_id),
+                "node-b",
+                7,
+            ))
+            .await;
+        service
+            .record(&envelope(
+                GossipMessage::TokenRevoked(token_id),
+                "node-a",
+                7,
+            ))
+            .await;
+        let entries = service.entries_since(&HashMap::new()).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].origin, NodeId::from_string("node-b"));
+        service
+            .record(&envelope(
+                GossipMessage::TokenRevoked(token_id),
+                "node-a",
+                8,
+            ))
+            .await;
+        let entries = service.entries_since(&HashMap::new()).await;
+        assert_eq!(entries[0].origin, NodeId::from_string("node-a"));
+        assert_eq!(entries[0].version, 8);
+    }
+    #[tokio::test]
+    async fn test_clock_follows_remote_versions_and_survives_restart() {
+        let store = ClusterStateStore::in_memory().unwrap();
+        let service = GossipService::new(NodeId::from_string("local"));
+        service.restore_clock(store.clone()).await.unwrap();
+        assert_eq!(service.next_version().await, 1);
+        service
+            .record(&envelope(
+                GossipMessage::NodeLeft(NodeId::from_string("x")),
+                "peer",
+                50,
+            ))
+            .await;
+        assert_eq!(service.next_version().await, 51);
+        let restarted = GossipService::new(NodeId::from_string("local"));
+        restarted.restore_clock(store).await.unwrap();
+        assert!(restarted.next_version().await > 51);
+    }
+    async fn insert(service: &GossipService, message: GossipMessage, age: chrono::Duration) {
+        let entry = GossipEntry {
+            id: Uuid::new_v4(),
+            message,
+            timestamp: Utc::now() - age,
+            origin: NodeId::from_string("peer"),
+            version: service.next_version().await,
+        };
+        service.apply_message(&entry).await;
+    }
+    fn token() -> ironfish_core::ApiToken {
+        ironfish_core::ApiToken {
+            id: Uuid::new_v4(),
+            name: None,
+            token_hash: "a".repeat(64),
+            created_at: Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+            created_by_node: "peer".to_string(),
+            revoked: false,
+            rate_limit: None,
+            scopes: Vec::new(),
+            usage_count: 0,
+            last_used_ip: None,
+            daily_usage: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+            node_window: None,
+            revoked_at: None,
+        }
+    }
+    #[tokio::test]
+    async fn test_tombstones_outlive_other_entries_and_live_state_never_expires() {
+        let retention = GossipRetention {
+            entry_ttl: Duration::from_secs(60),
+            tombstone_ttl: Duration::from_secs(3600),
+            max_entries: 100,
+        };
+        let service = GossipService::new(NodeId::from_string("local")).with_retention(retention);
+        let live = token();
+        let hour = chrono::Duration::minutes(61);
+        insert(&service, GossipMessage::TokenCreated(live.clone()), hour).await;
+        insert(
+            &service,
+            GossipMessage::TokenRevoked(Uuid::new_v4()),
+            chrono::Duration::minutes(5),
+        )
+        .await;
+        insert(
+            &service,
+            GossipMessage::NodeDraining(NodeId::from_string("peer"), true),
+            chrono::Duration::minutes(5),
+        )
+        .await;
+        assert_eq!(service.compact().await, 1);
+        assert!(!service.entries.read().await.contains_key("drain:peer"));
+        let much_later = Utc::now() + chrono::Duration::hours(2);
+        assert_eq!(
+            compact_store(&service.entries, &retention, much_later).await,
+            1
+        );
+        let entries = service.entries.read().await;
+        assert_eq!(entries.len(), 1);
+        assert!(entries.contains_key(&format!("token:{}", live.id)));
+    }
+    #[tokio::test]
+    async fn test_entries_are_capped_evicting_oldest_transient_entries() {
+        let retention = GossipRetention {
+            max_entries: 3,
+            ..GossipRetention::default()
+        };
+        let service = GossipService::new(NodeId::from_string("local")).with_retention(retention);
+        let revoked = Uuid::new_v4();
+        let live = token();
+        insert(
+            &service,
+            GossipMessage::TokenRevoked(revoked),
+            chrono::Duration::hours(3),
+        )
+        .await;
+        insert(
+            &service,
+            GossipMessage::TokenCreated(live.clone()),
+            chrono::Duration::hours(3),
+        )
+        .await;
+        for (minutes, id) in [(30, "a"), (20, "b"), (10, "c")] {
+            insert(
+                &service,
+                GossipMessage::NodeDraining(NodeId::from_string(id), true),
+                chrono::Duration::minutes(minutes),
+            )
+            .await;
+        }
+        assert_eq!(service.entries.read().await.len(), 5);
+        assert_eq!(service.compact().await, 2);
+        let entries = service.entries.read().await;
+        assert!(entries.contains_key(&format!("token:{}", revoked)));
+        assert!(entries.contains_key(&format!("token:{}", live.id)));
+        assert!(entries.contains_key("drain:c"));
+        drop(entries);
+        let status = service.status().await;
+        assert_eq!(status.entries, 3);
+        assert_eq!(status.tombstones, 1);
+        assert_eq!(status.clock, 5);
+    }
+    #[tokio::test]
+    async fn test_compaction_keeps_entries_replaced_after_the_scan() {
+        let retention = GossipRetention {
+            entry_ttl: Duration::from_secs(60),
+            ..GossipRetention::default()
+        };
+        let service = GossipService::new(NodeId::from_string("local")).with_retention(retention);
+        let node = NodeId::from_string("peer");
+        insert(
+            &service,
+            GossipMessage::NodeDraining(node.clone(), true),
+            chrono::Duration::hours(1),
+        )
+        .await;
+        let expired = expired_entries(&*service.entries.read().await, &retention, Utc::now());
+        assert_eq!(expired.len(), 1);
+        insert(
+            &service,
+            GossipMessage::NodeDraining(node, false),
+            chrono::Duration::zero(),
+        )
+        .await;
+        let mut entries = service.entries.write().await;
+        assert_eq!(remove_expired(&mut entries, expired), 0);
+        assert!(entries.contains_key("drain:peer"));
+    }
+    #[tokio::test]
+    async fn test_entries_after_serves_legacy_sync_requests() {
+        let service = GossipService::new(NodeId::from_string("local"));
+        for (version, origin) in [(5, "a"), (9, "b"), (12, "a")] {
+            service
+                .record(&envelope(
+                    GossipMessage::NodeLeft(NodeId::from_string(format!("{}{}", origin, version))),
+                    origin,
+                    version,
+                ))
+                .await;
+        }
+        let entries = service.entries_after(5).await;
+        assert_eq!(
+            entries.iter().map(|e| e.version).collect::<Vec<_>>(),
+            vec![9, 12]
+        );
+    }
+    #[tokio::test]
+    async fn test_mark_seen_is_bounded() {
+        let service = GossipService::new(NodeId::from_string("local"));
+        let first = Uuid::new_v4();
+        assert!(service.mark_seen(first).await);
+        assert!(!service.mark_seen(first).await);
+        for _ in 0..SEEN_CAPACITY {
+            assert!(service.mark_seen(Uuid::new_v4()).await);
+        }
+        assert_eq!(service.seen.read().await.ids.len(), SEEN_CAPACITY);
+        assert!(service.mark_seen(first).await);
+    }
     #[test]
     fn test_entry_key() {
         let token_msg = GossipMessage::TokenRevoked(uuid::Uuid::new_v4());
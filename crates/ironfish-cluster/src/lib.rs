@@ -1,15 +1,29 @@
 mod cluster_service;
 pub mod consensus;
 pub mod discovery;
+mod events;
 mod gossip;
 mod load_balancer;
 mod membership;
 mod network;
 mod node;
+mod outbound;
+mod pending;
+mod state;
+mod tls;
+mod transport;
 pub use cluster_service::{ClusterConfig, ClusterService};
-pub use discovery::{DiscoveryManager, StaticDiscovery};
-pub use gossip::GossipService;
-pub use load_balancer::{CpuAwareLoadBalancer, LoadBalancerConfig};
-pub use membership::MembershipManager;
+pub use discovery::{
+    DiscoveryManager, DnsDiscoveryConfig, DnsRecordType, KubernetesDiscoveryConfig, StaticDiscovery,
+};
+pub use events::ClusterEventBus;
+pub use gossip::{GossipRetention, GossipService};
+pub use load_balancer::{CpuAwareLoadBalancer, LoadBalanceStrategy, LoadBalancerConfig};
+pub use membership::{MembershipManager, QuorumPolicy};
 pub use network::{GossipEnvelope, NetworkMessage, NetworkService};
-pub use node::{Node, NodeConfig};
+pub use node::{Node, NodeConfig, NodeSnapshot};
+pub use outbound::OutboundConfig;
+pub use pending::PendingWrites;
+pub use state::ClusterStateStore;
+pub use tls::{load_certs, load_key, GossipTls, GossipTlsConfig};
+pub use transport::{GossipTransport, WireFormat};
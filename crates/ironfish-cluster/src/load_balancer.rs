@@ -1,8 +1,10 @@
 use async_trait::async_trait;
-use ironfish_core::{Error, LoadBalancer, NodeId, NodeMetrics, Result};
-use std::collections::HashMap;
+use ironfish_core::{ClusterEvent, Error, LoadBalancer, NodeId, NodeMetrics, Result};
+use ring::digest;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::debug;
 #[derive(Debug, Clone)]
@@ -12,6 +14,8 @@ pub struct LoadBalancerConfig {
     pub queue_weight: f32,
     pub latency_weight: f32,
     pub max_queue_depth: u32,
+    pub virtual_nodes: u32,
+    pub stale_metrics_after: Duration,
 }
 impl Default for LoadBalancerConfig {
     fn default() -> Self {
@@ -21,6 +25,8 @@ impl Default for LoadBalancerConfig {
             queue_weight: 0.3,
             latency_weight: 0.3,
             max_queue_depth: 100,
+            virtual_nodes: 64,
+            stale_metrics_after: Duration::from_secs(15),
         }
     }
 }
@@ -29,47 +35,152 @@ pub enum LoadBalanceStrategy {
     RoundRobin,
     LeastConnections,
     CpuAware,
+    PositionHash,
 }
 struct NodeScore {
     metrics: NodeMetrics,
     healthy: bool,
     score: f64,
+    metrics_at: Option<Instant>,
 }
 pub struct CpuAwareLoadBalancer {
     config: LoadBalancerConfig,
+    weights: std::sync::RwLock<[f32; 3]>,
     nodes: Arc<RwLock<HashMap<NodeId, NodeScore>>>,
+    draining: Arc<RwLock<HashSet<NodeId>>>,
+    ring: Arc<RwLock<BTreeMap<u64, NodeId>>>,
     round_robin_counter: AtomicUsize,
 }
 impl CpuAwareLoadBalancer {
     pub fn new(config: LoadBalancerConfig) -> Self {
         Self {
+            weights: std::sync::RwLock::new([
+                config.cpu_weight,
+                config.queue_weight,
+                config.latency_weight,
+            ]),
             config,
             nodes: Arc::new(RwLock::new(HashMap::new())),
+            draining: Arc::new(RwLock::new(HashSet::new())),
+            ring: Arc::new(RwLock::new(BTreeMap::new())),
             round_robin_counter: AtomicUsize::new(0),
         }
     }
     pub async fn add_node(&self, node_id: NodeId) {
+        let draining = self.draining.read().await.contains(&node_id);
         let mut nodes = self.nodes.write().await;
         nodes.insert(
-            node_id,
+            node_id.clone(),
             NodeScore {
                 metrics: NodeMetrics::default(),
-                healthy: true,
-                score: 1.0,
+                healthy: !draining,
+                score: if draining { 0.0 } else { 1.0 },
+                metrics_at: None,
             },
         );
+        let mut ring = self.ring.write().await;
+        for replica in 0..self.config.virtual_nodes {
+            ring.insert(
+                ring_hash(&format!("{}#{}", node_id, replica)),
+                node_id.clone(),
+            );
+        }
     }
     pub async fn remove_node(&self, node_id: &NodeId) {
         let mut nodes = self.nodes.write().await;
         nodes.remove(node_id);
+        self.draining.write().await.remove(node_id);
+        self.ring.write().await.retain(|_, owner| owner != node_id);
+    }
+    pub async fn set_draining(&self, node_id: &NodeId, draining: bool) {
+        let mut nodes = self.draining.write().await;
+        match draining {
+            true => nodes.insert(node_id.clone()),
+            false => nodes.remove(node_id),
+        };
+    }
+    pub async fn rebuild(&self, members: &[NodeId], draining: &HashSet<NodeId>) {
+        let stale: Vec<NodeId> = self
+            .nodes
+            .read()
+            .await
+            .keys()
+            .filter(|id| !members.contains(id))
+            .cloned()
+            .collect();
+        for node_id in &stale {
+            self.remove_node(node_id).await;
+        }
+        for node_id in members {
+            let known = self.nodes.read().await.contains_key(node_id);
+            let was_draining = self.draining.read().await.contains(node_id);
+            let is_draining = draining.contains(node_id);
+            self.set_draining(node_id, is_draining).await;
+            let outcome = match (known, was_draining, is_draining) {
+                (false, _, _) => {
+                    self.add_node(node_id.clone()).await;
+                    Ok(())
+                }
+                (true, false, true) => self.mark_unhealthy(node_id).await,
+                (true, true, false) => self.mark_healthy(node_id).await,
+                _ => Ok(()),
+            };
+            if let Err(e) = outcome {
+                debug!(
+                    "failed to rebuild load balancer entry for {}: {}",
+                    node_id, e
+                );
+            }
+        }
+    }
+    pub async fn apply_event(&self, event: &ClusterEvent) {
+        let outcome = match event {
+            ClusterEvent::NodeJoined { node } => {
+                self.add_node(node.id.clone()).await;
+                Ok(())
+            }
+            ClusterEvent::NodeLeft { node_id, .. } => {
+                self.remove_node(node_id).await;
+                Ok(())
+            }
+            ClusterEvent::NodeDraining { node_id, draining } => {
+                self.set_draining(node_id, *draining).await;
+                match draining {
+                    true => self.mark_unhealthy(node_id).await,
+                    false => self.mark_healthy(node_id).await,
+                }
+            }
+            ClusterEvent::NodeUnhealthy { node_id, .. } => self.mark_unhealthy(node_id).await,
+            ClusterEvent::NodeRecovered { node_id } => self.mark_healthy(node_id).await,
+            ClusterEvent::LeaderChanged { .. } | ClusterEvent::TokenRevoked { .. } => Ok(()),
+        };
+        if let Err(e) = outcome {
+            debug!("failed to apply {} to load balancer: {}", event.kind(), e);
+        }
+    }
+    pub fn weights(&self) -> [f32; 3] {
+        *self.weights.read().unwrap()
+    }
+    pub fn set_weights(&self, cpu_weight: f32, queue_weight: f32, latency_weight: f32) {
+        *self.weights.write().unwrap() = [cpu_weight, queue_weight, latency_weight];
     }
     fn calculate_score(&self, metrics: &NodeMetrics) -> f64 {
-        let cpu_score = (1.0 - metrics.cpu_usage) as f64 * self.config.cpu_weight as f64;
-        let queue_score =
-            (1.0 / (metrics.queue_depth as f64 + 1.0)) * self.config.queue_weight as f64;
-        let latency_score =
-            (1.0 / (metrics.avg_latency_ms as f64 + 1.0)) * self.config.latency_weight as f64;
-        cpu_score + queue_score + latency_score
+        let [cpu_weight, queue_weight, latency_weight] = self.weights();
+        let cpu_score = (1.0 - metrics.cpu_usage) as f64 * cpu_weight as f64;
+        let queue_score = (1.0 / (metrics.queue_depth as f64 + 1.0)) * queue_weight as f64;
+        let latency_score = (1.0 / (metrics.avg_latency_ms as f64 + 1.0)) * latency_weight as f64;
+        let score = cpu_score + queue_score + latency_score;
+        if score.is_finite() {
+            score
+        } else {
+            0.0
+        }
+    }
+    fn effective_score(&self, node_score: &NodeScore) -> f64 {
+        match node_score.metrics_at {
+            Some(at) if at.elapsed() > self.config.stale_metrics_after => node_score.score / 2.0,
+            _ => node_score.score,
+        }
     }
     async fn select_round_robin(&self, exclude: &[NodeId]) -> Result<NodeId> {
         let nodes = self.nodes.read().await;
@@ -97,10 +208,33 @@ impl CpuAwareLoadBalancer {
         nodes
             .iter()
             .filter(|(id, score)| score.healthy && !exclude.contains(id))
-            .max_by(|(_, a), (_, b)| a.score.partial_cmp(&b.score).unwrap())
+            .max_by(|(_, a), (_, b)| self.effective_score(a).total_cmp(&self.effective_score(b)))
             .map(|(id, _)| id.clone())
             .ok_or(Error::ClusterUnavailable)
     }
+    async fn select_position_hash(&self, key: &str, exclude: &[NodeId]) -> Result<NodeId> {
+        let target = {
+            let ring = self.ring.read().await;
+            let hash = ring_hash(key);
+            ring.range(hash..)
+                .chain(ring.range(..hash))
+                .map(|(_, node_id)| node_id.clone())
+                .next()
+        };
+        if let Some(node_id) = target {
+            let nodes = self.nodes.read().await;
+            if !exclude.contains(&node_id) && nodes.get(&node_id).is_some_and(|s| s.healthy) {
+                return Ok(node_id);
+            }
+        }
+        self.select_cpu_aware(exclude).await
+    }
+}
+fn ring_hash(key: &str) -> u64 {
+    let hash = digest::digest(&digest::SHA256, key.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash.as_ref()[..8]);
+    u64::from_be_bytes(bytes)
 }
 #[async_trait]
 impl LoadBalancer for CpuAwareLoadBalancer {
@@ -108,19 +242,32 @@ impl LoadBalancer for CpuAwareLoadBalancer {
         let result = match self.config.strategy {
             LoadBalanceStrategy::RoundRobin => self.select_round_robin(exclude).await,
             LoadBalanceStrategy::LeastConnections => self.select_least_connections(exclude).await,
-            LoadBalanceStrategy::CpuAware => self.select_cpu_aware(exclude).await,
+            LoadBalanceStrategy::CpuAware | LoadBalanceStrategy::PositionHash => {
+                self.select_cpu_aware(exclude).await
+            }
         };
         if let Ok(ref node_id) = result {
             debug!("selected node {} for request", node_id);
         }
         result
     }
+    async fn select_node_for_key(&self, key: &str, exclude: &[NodeId]) -> Result<NodeId> {
+        if self.config.strategy != LoadBalanceStrategy::PositionHash {
+            return self.select_node(exclude).await;
+        }
+        let result = self.select_position_hash(key, exclude).await;
+        if let Ok(ref node_id) = result {
+            debug!("selected node {} for position {}", node_id, key);
+        }
+        result
+    }
     async fn update_metrics(&self, node_id: &NodeId, metrics: NodeMetrics) -> Result<()> {
         let mut nodes = self.nodes.write().await;
         if let Some(node_score) = nodes.get_mut(node_id) {
             let score = self.calculate_score(&metrics);
             node_score.metrics = metrics;
             node_score.score = score;
+            node_score.metrics_at = Some(Instant::now());
             debug!("updated metrics for node {}, score: {:.4}", node_id, score);
         }
         Ok(())
@@ -135,6 +282,9 @@ impl LoadBalancer for CpuAwareLoadBalancer {
         Ok(())
     }
     async fn mark_healthy(&self, node_id: &NodeId) -> Result<()> {
+        if self.draining.read().await.contains(node_id) {
+            return Ok(());
+        }
         let mut nodes = self.nodes.write().await;
         if let Some(node_score) = nodes.get_mut(node_id) {
             node_score.healthy = true;
@@ -161,4 +311,203 @@ mod tests {
         let selected = lb.select_node(&[]).await.unwrap();
         assert_eq!(selected, node2);
     }
+    #[tokio::test]
+    async fn test_draining_node_stays_unhealthy() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig::default());
+        let node1 = NodeId::from_string("node1");
+        let node2 = NodeId::from_string("node2");
+        lb.add_node(node1.clone()).await;
+        lb.add_node(node2.clone()).await;
+        lb.set_draining(&node1, true).await;
+        lb.mark_unhealthy(&node1).await.unwrap();
+        lb.mark_healthy(&node1).await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(lb.select_node(&[]).await.unwrap(), node2);
+        }
+        lb.set_draining(&node1, false).await;
+        lb.mark_healthy(&node1).await.unwrap();
+        assert_eq!(
+            lb.select_node(std::slice::from_ref(&node2)).await.unwrap(),
+            node1
+        );
+    }
+    #[tokio::test]
+    async fn test_drain_before_join_is_kept() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig::default());
+        let node1 = NodeId::from_string("node1");
+        let node2 = NodeId::from_string("node2");
+        lb.add_node(node2.clone()).await;
+        lb.set_draining(&node1, true).await;
+        lb.add_node(node1.clone()).await;
+        lb.mark_healthy(&node1).await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(lb.select_node(&[]).await.unwrap(), node2);
+        }
+        lb.remove_node(&node1).await;
+        lb.add_node(node1.clone()).await;
+        assert_eq!(
+            lb.select_node(std::slice::from_ref(&node2)).await.unwrap(),
+            node1
+        );
+    }
+    #[tokio::test]
+    async fn test_rebuild_follows_membership() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig::default());
+        let node1 = NodeId::from_string("node1");
+        let node2 = NodeId::from_string("node2");
+        let node3 = NodeId::from_string("node3");
+        lb.add_node(node1.clone()).await;
+        lb.add_node(node2.clone()).await;
+        lb.rebuild(
+            &[node2.clone(), node3.clone()],
+            &HashSet::from([node2.clone()]),
+        )
+        .await;
+        for _ in 0..4 {
+            assert_eq!(lb.select_node(&[]).await.unwrap(), node3);
+        }
+        lb.rebuild(std::slice::from_ref(&node2), &HashSet::new())
+            .await;
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node2);
+        assert!(lb.select_node(std::slice::from_ref(&node2)).await.is_err());
+    }
+    #[tokio::test]
+    async fn test_cluster_events_update_nodes() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig::default());
+        let node = ironfish_core::NodeInfo {
+            id: NodeId::from_string("node1"),
+            address: "127.0.0.1:8080".parse().unwrap(),
+            priority: 100,
+            started_at: chrono::Utc::now(),
+            version: "test".to_string(),
+        };
+        let node_id = node.id.clone();
+        lb.apply_event(&ClusterEvent::NodeJoined { node }).await;
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node_id);
+        lb.apply_event(&ClusterEvent::NodeUnhealthy {
+            node_id: node_id.clone(),
+            reason: "timed out".to_string(),
+        })
+        .await;
+        assert!(lb.select_node(&[]).await.is_err());
+        lb.apply_event(&ClusterEvent::NodeRecovered {
+            node_id: node_id.clone(),
+        })
+        .await;
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node_id);
+        lb.apply_event(&ClusterEvent::NodeLeft {
+            node_id,
+            reason: ironfish_core::LeaveReason::Unreachable,
+        })
+        .await;
+        assert!(lb.select_node(&[]).await.is_err());
+    }
+    #[tokio::test]
+    async fn test_stale_metrics_degrade_score() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig {
+            stale_metrics_after: Duration::from_millis(50),
+            ..Default::default()
+        });
+        let node1 = NodeId::from_string("node1");
+        let node2 = NodeId::from_string("node2");
+        lb.add_node(node1.clone()).await;
+        lb.add_node(node2.clone()).await;
+        let busy = NodeMetrics {
+            cpu_usage: 0.2,
+            avg_latency_ms: 1,
+            ..Default::default()
+        };
+        lb.update_metrics(&node1, NodeMetrics::default())
+            .await
+            .unwrap();
+        lb.update_metrics(&node2, busy.clone()).await.unwrap();
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node1);
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        lb.update_metrics(&node2, busy).await.unwrap();
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node2);
+        lb.update_metrics(&node1, NodeMetrics::default())
+            .await
+            .unwrap();
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node1);
+    }
+    #[tokio::test]
+    async fn test_non_finite_metrics_do_not_panic() {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig::default());
+        let node1 = NodeId::from_string("node1");
+        let node2 = NodeId::from_string("node2");
+        lb.add_node(node1.clone()).await;
+        lb.add_node(node2.clone()).await;
+        let broken = NodeMetrics {
+            cpu_usage: f32::NAN,
+            ..Default::default()
+        };
+        lb.update_metrics(&node1, broken).await.unwrap();
+        lb.update_metrics(&node2, NodeMetrics::default())
+            .await
+            .unwrap();
+        assert_eq!(lb.select_node(&[]).await.unwrap(), node2);
+    }
+    fn position_keys() -> Vec<String> {
+        (0..200).map(|i| format!("position-{}", i)).collect()
+    }
+    async fn position_hash_balancer(nodes: &[NodeId]) -> CpuAwareLoadBalancer {
+        let lb = CpuAwareLoadBalancer::new(LoadBalancerConfig {
+            strategy: LoadBalanceStrategy::PositionHash,
+            ..Default::default()
+        });
+        for node_id in nodes {
+            lb.add_node(node_id.clone()).await;
+        }
+        lb
+    }
+    #[tokio::test]
+    async fn test_position_hash_is_stable() {
+        let nodes: Vec<_> = (1..=3)
+            .map(|i| NodeId::from_string(format!("node{}", i)))
+            .collect();
+        let lb = position_hash_balancer(&nodes).await;
+        let other = position_hash_balancer(&nodes).await;
+        let mut owners = HashMap::new();
+        for key in position_keys() {
+            let owner = lb.select_node_for_key(&key, &[]).await.unwrap();
+            assert_eq!(lb.select_node_for_key(&key, &[]).await.unwrap(), owner);
+            assert_eq!(other.select_node_for_key(&key, &[]).await.unwrap(), owner);
+            *owners.entry(owner).or_insert(0) += 1;
+        }
+        assert_eq!(owners.len(), 3);
+        let key = "position-0";
+        let owner = lb.select_node_for_key(key, &[]).await.unwrap();
+        let fallback = lb
+            .select_node_for_key(key, std::slice::from_ref(&owner))
+            .await
+            .unwrap();
+        assert_ne!(fallback, owner);
+        lb.mark_unhealthy(&owner).await.unwrap();
+        assert_ne!(lb.select_node_for_key(key, &[]).await.unwrap(), owner);
+        lb.mark_healthy(&owner).await.unwrap();
+        assert_eq!(lb.select_node_for_key(key, &[]).await.unwrap(), owner);
+    }
+    #[tokio::test]
+    async fn test_position_hash_minimal_reshuffle_on_removal() {
+        let nodes: Vec<_> = (1..=4)
+            .map(|i| NodeId::from_string(format!("node{}", i)))
+            .collect();
+        let lb = position_hash_balancer(&nodes).await;
+        let mut before = HashMap::new();
+        for key in position_keys() {
+            before.insert(
+                key.clone(),
+                lb.select_node_for_key(&key, &[]).await.unwrap(),
+            );
+        }
+        lb.remove_node(&nodes[0]).await;
+        for (key, owner) in before {
+            let now = lb.select_node_for_key(&key, &[]).await.unwrap();
+            if owner == nodes[0] {
+                assert_ne!(now, nodes[0]);
+            } else {
+                assert_eq!(now, owner);
+            }
+        }
+    }
 }
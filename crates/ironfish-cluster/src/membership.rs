@@ -1,61 +1,243 @@
+use crate::network::gossip_addr;
 use crate::node::SharedNode;
 use ironfish_core::{
-    ClusterStatus, JoinRequest, JoinResponse, NodeId, NodeInfo, NodeState, NodeStatus, Result,
+    ClusterEvent, ClusterStatus, JoinRequest, JoinResponse, LeaveReason, NodeId, NodeInfo,
+    NodeMetrics, NodeState, NodeStatus, Result,
 };
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Instant;
 use tracing::{debug, info, warn};
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuorumPolicy {
+    #[default]
+    Majority,
+    All,
+    Disabled,
+}
+impl QuorumPolicy {
+    pub fn satisfied(&self, live: usize, total: usize) -> bool {
+        match self {
+            QuorumPolicy::Majority => live * 2 > total,
+            QuorumPolicy::All => live >= total,
+            QuorumPolicy::Disabled => true,
+        }
+    }
+}
 pub struct MembershipManager {
     local_node: SharedNode,
     members: Arc<RwLock<HashMap<NodeId, NodeInfo>>>,
+    draining: Arc<RwLock<HashSet<NodeId>>>,
+    metrics: Arc<RwLock<HashMap<NodeId, (NodeMetrics, Instant)>>>,
+    last_seen: Arc<RwLock<HashMap<NodeId, Instant>>>,
+    metrics_stale_after: Duration,
+    member_stale_after: Duration,
+    quorum: QuorumPolicy,
+    join_probe_timeout: Option<Duration>,
 }
 impl MembershipManager {
     pub fn new(local_node: SharedNode) -> Self {
         Self {
             local_node,
             members: Arc::new(RwLock::new(HashMap::new())),
+            draining: Arc::new(RwLock::new(HashSet::new())),
+            metrics: Arc::new(RwLock::new(HashMap::new())),
+            last_seen: Arc::new(RwLock::new(HashMap::new())),
+            metrics_stale_after: Duration::from_secs(15),
+            member_stale_after: Duration::from_secs(15),
+            quorum: QuorumPolicy::default(),
+            join_probe_timeout: None,
         }
     }
+    pub fn with_metrics_stale_after(mut self, stale_after: Duration) -> Self {
+        self.metrics_stale_after = stale_after;
+        self
+    }
+    pub fn with_member_stale_after(mut self, stale_after: Duration) -> Self {
+        self.member_stale_after = stale_after;
+        self
+    }
+    pub fn with_quorum_policy(mut self, policy: QuorumPolicy) -> Self {
+        self.quorum = policy;
+        self
+    }
+    pub fn with_join_probe(mut self, timeout: Duration) -> Self {
+        self.join_probe_timeout = Some(timeout);
+        self
+    }
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ClusterEvent> {
+        self.local_node.events().subscribe()
+    }
+    pub(crate) fn emit(&self, event: ClusterEvent) {
+        self.local_node.events().emit(event);
+    }
     pub async fn join(&self, request: JoinRequest) -> Result<JoinResponse> {
-        let is_leader = self.local_node.is_leader();
-        if !is_leader {
-            return Ok(JoinResponse {
-                accepted: false,
-                leader_id: self.local_node.leader(),
-                members: Vec::new(),
-                term: self.local_node.term(),
-            });
+        if !self.local_node.is_leader() {
+            return Ok(self.rejected("this node is not the cluster leader"));
+        }
+        let node = request.node_info;
+        if let Err(reason) = self.validate_join(&node).await {
+            warn!(
+                "rejected join from {} at {}: {}",
+                node.id, node.address, reason
+            );
+            return Ok(self.rejected(reason));
         }
         let mut members = self.members.write().await;
-        if members.contains_key(&request.node_info.id) {
-            warn!("node {} already in cluster", request.node_info.id);
+        if members.contains_key(&node.id) {
+            debug!("node {} rejoined the cluster", node.id);
         }
-        members.insert(request.node_info.id.clone(), request.node_info.clone());
-        info!("node {} joined cluster", request.node_info.id);
+        self.replace_address(&mut members, &node).await;
+        self.insert_member(&mut members, node).await;
         let member_list: Vec<NodeInfo> = members.values().cloned().collect();
         Ok(JoinResponse {
             accepted: true,
             leader_id: Some(self.local_node.id().clone()),
             members: member_list,
             term: self.local_node.term(),
+            reason: None,
         })
     }
+    fn rejected(&self, reason: impl Into<String>) -> JoinResponse {
+        JoinResponse {
+            accepted: false,
+            leader_id: self.local_node.leader(),
+            members: Vec::new(),
+            term: self.local_node.term(),
+            reason: Some(reason.into()),
+        }
+    }
+    async fn validate_join(&self, node: &NodeInfo) -> std::result::Result<(), String> {
+        let local = self.local_node.info();
+        if node.id.0.trim().is_empty() {
+            return Err("node id is empty".to_string());
+        }
+        if node.id == local.id {
+            return Err(format!("node id {} belongs to the leader", node.id));
+        }
+        if node.address.ip().is_unspecified() || node.address.port() == 0 {
+            return Err(format!("address {} is not routable", node.address));
+        }
+        if node.address == local.address {
+            return Err(format!("address {} belongs to the leader", node.address));
+        }
+        if let Some(timeout) = self.join_probe_timeout {
+            let probe = gossip_addr(node);
+            match tokio::time::timeout(timeout, TcpStream::connect(probe)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(format!("gossip port {} is unreachable: {}", probe, e)),
+                Err(_) => {
+                    return Err(format!(
+                        "gossip port {} did not answer within {}ms",
+                        probe,
+                        timeout.as_millis()
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+    async fn replace_address(&self, members: &mut HashMap<NodeId, NodeInfo>, node: &NodeInfo) {
+        let replaced: Vec<NodeId> = members
+            .values()
+            .filter(|m| m.address == node.address && m.id != node.id)
+            .map(|m| m.id.clone())
+            .collect();
+        for stale in replaced {
+            info!("node {} at {} replaced by {}", stale, node.address, node.id);
+            members.remove(&stale);
+            self.forget(&stale).await;
+            self.emit(ClusterEvent::NodeLeft {
+                node_id: stale,
+                reason: LeaveReason::Replaced,
+            });
+        }
+    }
+    async fn insert_member(&self, members: &mut HashMap<NodeId, NodeInfo>, node: NodeInfo) {
+        self.last_seen
+            .write()
+            .await
+            .insert(node.id.clone(), Instant::now());
+        if members.insert(node.id.clone(), node.clone()).is_none() {
+            self.emit(ClusterEvent::NodeJoined { node });
+        }
+    }
+    async fn forget(&self, node_id: &NodeId) {
+        self.draining.write().await.remove(node_id);
+        self.metrics.write().await.remove(node_id);
+        self.last_seen.write().await.remove(node_id);
+    }
     pub async fn leave(&self, node_id: &NodeId) -> Result<()> {
-        let mut members = self.members.write().await;
-        members.remove(node_id);
-        info!("node {} left cluster", node_id);
+        self.remove_member(node_id, LeaveReason::Left).await;
         Ok(())
     }
     pub async fn add_member(&self, node: NodeInfo) {
         let mut members = self.members.write().await;
+        if let Some(existing) = members
+            .values()
+            .find(|m| m.address == node.address && m.id != node.id)
+        {
+            debug!(
+                "not adding {}: {} is already a member at {}",
+                node.id, existing.id, node.address
+            );
+            return;
+        }
         debug!("adding member {}", node.id);
-        members.insert(node.id.clone(), node);
+        self.insert_member(&mut members, node).await;
     }
-    pub async fn remove_member(&self, node_id: &NodeId) {
+    pub async fn resolve(&self, node: NodeInfo) -> NodeInfo {
+        self.members
+            .read()
+            .await
+            .values()
+            .find(|m| m.address == node.address)
+            .cloned()
+            .unwrap_or(node)
+    }
+    pub async fn remove_member(&self, node_id: &NodeId, reason: LeaveReason) {
         let mut members = self.members.write().await;
         debug!("removing member {}", node_id);
-        members.remove(node_id);
+        self.forget(node_id).await;
+        if members.remove(node_id).is_some() {
+            self.emit(ClusterEvent::NodeLeft {
+                node_id: node_id.clone(),
+                reason,
+            });
+        }
+    }
+    pub async fn set_draining(&self, node_id: &NodeId, draining: bool) {
+        let mut nodes = self.draining.write().await;
+        let changed = match draining {
+            true => nodes.insert(node_id.clone()),
+            false => nodes.remove(node_id),
+        };
+        if changed {
+            self.emit(ClusterEvent::NodeDraining {
+                node_id: node_id.clone(),
+                draining,
+            });
+        }
+    }
+    pub async fn update_member_metrics(&self, node_id: &NodeId, metrics: NodeMetrics) {
+        self.metrics
+            .write()
+            .await
+            .insert(node_id.clone(), (metrics, Instant::now()));
+        self.record_seen(node_id).await;
+    }
+    pub async fn record_seen(&self, node_id: &NodeId) {
+        if self.members.read().await.contains_key(node_id) {
+            self.last_seen
+                .write()
+                .await
+                .insert(node_id.clone(), Instant::now());
+        }
     }
     pub async fn get_member(&self, node_id: &NodeId) -> Option<NodeInfo> {
         let members = self.members.read().await;
@@ -65,14 +247,21 @@ impl MembershipManager {
         let members = self.members.read().await;
         members.values().cloned().collect()
     }
+    pub async fn draining_members(&self) -> HashSet<NodeId> {
+        self.draining.read().await.clone()
+    }
     pub async fn member_count(&self) -> usize {
         self.members.read().await.len() + 1
     }
     pub async fn cluster_status(&self) -> ClusterStatus {
         let members = self.members.read().await;
+        let draining = self.draining.read().await;
+        let metrics = self.metrics.read().await;
+        let last_seen = self.last_seen.read().await;
         let local_status = self.local_node.status(members.len() + 1);
         let mut nodes = vec![local_status];
         for (_, info) in members.iter() {
+            let reported = metrics.get(&info.id);
             nodes.push(NodeStatus {
                 info: info.clone(),
                 state: NodeState::Follower,
@@ -80,13 +269,28 @@ impl MembershipManager {
                 term: self.local_node.term(),
                 cluster_size: members.len() + 1,
                 uptime_seconds: 0,
+                draining: draining.contains(&info.id),
+                metrics: reported.map(|(metrics, _)| metrics.clone()),
+                metrics_stale: reported
+                    .is_some_and(|(_, at)| at.elapsed() > self.metrics_stale_after),
+                last_seen_ms: last_seen
+                    .get(&info.id)
+                    .map(|at| at.elapsed().as_millis() as u64),
             });
         }
+        let live = 1 + members
+            .keys()
+            .filter(|id| {
+                last_seen
+                    .get(*id)
+                    .is_some_and(|at| at.elapsed() <= self.member_stale_after)
+            })
+            .count();
         ClusterStatus {
             nodes,
             leader: self.local_node.leader(),
             term: self.local_node.term(),
-            healthy: true,
+            healthy: self.quorum.satisfied(live, members.len() + 1),
         }
     }
     pub async fn is_member(&self, node_id: &NodeId) -> bool {
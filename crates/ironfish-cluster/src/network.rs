@@ -1,73 +1,203 @@
-use ironfish_core::{Error, GossipMessage, NodeId, NodeInfo, Result};
+use crate::consensus::BullyElection;
+use crate::gossip::GossipService;
+use crate::outbound::{OutboundConfig, PeerOutbound};
+use crate::transport::{GossipStream, GossipTransport, WireFormat};
+use async_trait::async_trait;
+use ironfish_core::{
+    ConsensusProtocol, Error, GossipMessage, GossipStoreStatus, HeartbeatRequest,
+    HeartbeatResponse, NodeId, NodeInfo, PeerStatus, Result, Telemetry, TokenReplicaStatus,
+    TokenStore, VoteRequest, VoteResponse,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc, RwLock};
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 const GOSSIP_PORT_OFFSET: u16 = 100;
-const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
-    Gossip(GossipEnvelope),
-    SyncRequest { from_version: u64 },
-    SyncResponse { entries: Vec<GossipEnvelope> },
+    Gossip(Box<GossipEnvelope>),
+    SyncRequest {
+        from_version: u64,
+    },
+    SyncResponse {
+        entries: Vec<GossipEnvelope>,
+    },
     Ping,
     Pong,
     DiscoveryRequest,
-    DiscoveryResponse { nodes: Vec<NodeInfo> },
+    DiscoveryResponse {
+        nodes: Vec<NodeInfo>,
+        #[serde(default)]
+        node: Option<NodeInfo>,
+    },
+    VoteRequest(VoteRequest),
+    VoteResponse(VoteResponse),
+    Heartbeat(HeartbeatRequest),
+    HeartbeatResponse(HeartbeatResponse),
+    BullyElection {
+        candidate_id: NodeId,
+        priority: u32,
+    },
+    BullyCoordinator {
+        leader_id: NodeId,
+        priority: u32,
+        term: u64,
+    },
+    BullyAnswer {
+        node_id: NodeId,
+        ok: bool,
+        term: u64,
+    },
+    TokenStatusRequest {
+        id: Uuid,
+    },
+    TokenStatusResponse {
+        status: TokenReplicaStatus,
+    },
+    SyncSince {
+        since: HashMap<NodeId, u64>,
+    },
+    TokenStatusFailed {
+        error: String,
+    },
+}
+#[async_trait]
+trait TokenLookup: Send + Sync {
+    async fn status(&self, id: &Uuid) -> Result<TokenReplicaStatus>;
+}
+struct StoreLookup<T: ?Sized>(Arc<T>);
+#[async_trait]
+impl<T: TokenStore + ?Sized + 'static> TokenLookup for StoreLookup<T> {
+    async fn status(&self, id: &Uuid) -> Result<TokenReplicaStatus> {
+        Ok(TokenReplicaStatus::of(self.0.get(id).await?.as_ref()))
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GossipEnvelope {
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub message: GossipMessage,
     pub origin: NodeId,
     pub version: u64,
     pub hops: u8,
+    #[serde(default)]
+    pub relayed_by: Option<NodeId>,
+}
+impl GossipEnvelope {
+    pub fn new(message: GossipMessage, origin: NodeId, version: u64) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message,
+            origin,
+            version,
+            hops: 0,
+            relayed_by: None,
+        }
+    }
 }
+pub(crate) type PeerMap = Arc<RwLock<HashMap<NodeId, PeerConnection>>>;
+pub type IncomingGossip = (GossipEnvelope, Option<NodeInfo>);
 pub struct NetworkService {
     local_node: NodeInfo,
-    peers: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
-    incoming_tx: mpsc::Sender<GossipEnvelope>,
-    pub incoming_rx: Arc<RwLock<mpsc::Receiver<GossipEnvelope>>>,
+    peers: PeerMap,
+    incoming_tx: mpsc::Sender<IncomingGossip>,
+    pub incoming_rx: Arc<RwLock<mpsc::Receiver<IncomingGossip>>>,
     shutdown_tx: broadcast::Sender<()>,
-    gossip_port: u16,
+    bind_address: SocketAddr,
+    gossip: Option<Arc<GossipService>>,
+    consensus: Option<Arc<dyn ConsensusProtocol>>,
+    bully: Option<Arc<BullyElection>>,
+    tokens: Option<Arc<dyn TokenLookup>>,
+    transport: GossipTransport,
+    outbound: OutboundConfig,
+    telemetry: Arc<RwLock<Telemetry>>,
 }
 #[derive(Debug, Clone)]
-struct PeerConnection {
+pub(crate) struct PeerConnection {
     info: NodeInfo,
     gossip_addr: SocketAddr,
-    healthy: bool,
-    last_seen: std::time::Instant,
+    pub(crate) healthy: bool,
+    pub(crate) last_seen: std::time::Instant,
+    outbound: PeerOutbound,
 }
 impl NetworkService {
     pub fn new(local_node: NodeInfo) -> Self {
         let (incoming_tx, incoming_rx) = mpsc::channel(1024);
         let (shutdown_tx, _) = broadcast::channel(1);
-        let gossip_port = local_node.address.port() + GOSSIP_PORT_OFFSET;
         Self {
+            bind_address: local_node.address,
             local_node,
             peers: Arc::new(RwLock::new(HashMap::new())),
             incoming_tx,
             incoming_rx: Arc::new(RwLock::new(incoming_rx)),
             shutdown_tx,
-            gossip_port,
+            gossip: None,
+            consensus: None,
+            bully: None,
+            tokens: None,
+            transport: GossipTransport::default(),
+            outbound: OutboundConfig::default(),
+            telemetry: Arc::new(RwLock::new(Telemetry::default())),
         }
     }
+    pub fn with_bind_address(mut self, bind_address: SocketAddr) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+    pub fn with_gossip(mut self, gossip: Arc<GossipService>) -> Self {
+        self.gossip = Some(gossip);
+        self
+    }
+    pub fn with_consensus(mut self, consensus: Arc<dyn ConsensusProtocol>) -> Self {
+        self.consensus = Some(consensus);
+        self
+    }
+    pub fn with_bully(mut self, bully: Arc<BullyElection>) -> Self {
+        self.bully = Some(bully);
+        self
+    }
+    pub fn with_token_store<T: TokenStore + ?Sized + 'static>(mut self, store: Arc<T>) -> Self {
+        self.tokens = Some(Arc::new(StoreLookup(store)));
+        self
+    }
+    pub fn with_transport(mut self, transport: GossipTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+    pub fn with_outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+    pub async fn set_telemetry(&self, telemetry: Telemetry) {
+        *self.telemetry.write().await = telemetry;
+    }
     pub async fn start(&self) -> Result<()> {
-        let listener_addr = SocketAddr::new(self.local_node.address.ip(), self.gossip_port);
+        let listener_addr = gossip_socket(self.bind_address);
         let listener = TcpListener::bind(listener_addr).await.map_err(|e| {
             Error::Network(format!(
                 "failed to bind gossip port {}: {}",
-                self.gossip_port, e
+                listener_addr.port(),
+                e
             ))
         })?;
         info!("gossip network listening on {}", listener_addr);
-        let incoming_tx = self.incoming_tx.clone();
-        let peers = self.peers.clone();
-        let local_id = self.local_node.id.clone();
+        let context = ConnectionContext {
+            incoming_tx: self.incoming_tx.clone(),
+            peers: self.peers.clone(),
+            gossip: self.gossip.clone(),
+            consensus: self.consensus.clone(),
+            bully: self.bully.clone(),
+            tokens: self.tokens.clone(),
+            local_node: self.local_node.clone(),
+            transport: self.transport.clone(),
+            telemetry: self.telemetry.read().await.clone(),
+            shutdown_tx: self.shutdown_tx.clone(),
+        };
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         tokio::spawn(async move {
             loop {
@@ -76,12 +206,27 @@ impl NetworkService {
                         match result {
                             Ok((stream, addr)) => {
                                 debug!("accepted gossip connection from {}", addr);
-                                let tx = incoming_tx.clone();
-                                let peers_clone = peers.clone();
-                                let local_id_clone = local_id.clone();
+                                let context = context.clone();
+                                let mut shutdown_rx = context.shutdown_tx.subscribe();
                                 tokio::spawn(async move {
-                                    if let Err(e) = handle_connection(stream, tx, peers_clone, local_id_clone).await {
-                                        debug!("connection handler error: {}", e);
+                                    let stream = match context.transport.accept(stream).await {
+                                        Ok(stream) => stream,
+                                        Err(e) => {
+                                            warn!("rejected gossip connection from {}: {}", addr, e);
+                                            return;
+                                        }
+                                    };
+                                    let result = tokio::select! {
+                                        result = handle_connection(stream, addr, &context) => result,
+                                        _ = shutdown_rx.recv() => Ok(()),
+                                    };
+                                    match result {
+                                        Err(Error::Unauthorized) => {
+                                            context.telemetry.gossip_rejected();
+                                            warn!("dropped gossip from {}: cluster secret verification failed", addr);
+                                        }
+                                        Err(e) => debug!("connection handler error: {}", e),
+                                        Ok(()) => {}
                                     }
                                 });
                             }
@@ -101,6 +246,9 @@ impl NetworkService {
     }
     pub async fn stop(&self) {
         let _ = self.shutdown_tx.send(());
+        for conn in self.peers.read().await.values() {
+            conn.outbound.close();
+        }
     }
     pub async fn add_peer(&self, peer: NodeInfo) {
         let mut peers = self.peers.write().await;
@@ -108,8 +256,25 @@ impl NetworkService {
             return;
         }
         if !peers.contains_key(&peer.id) {
-            let gossip_addr =
-                SocketAddr::new(peer.address.ip(), peer.address.port() + GOSSIP_PORT_OFFSET);
+            let gossip_addr = gossip_addr(&peer);
+            let replaced: Vec<NodeId> = peers
+                .iter()
+                .filter(|(_, conn)| conn.gossip_addr == gossip_addr)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for stale in replaced {
+                if let Some(conn) = peers.remove(&stale) {
+                    conn.outbound.close();
+                    info!("peer {} at {} replaced by {}", stale, gossip_addr, peer.id);
+                }
+            }
+            let outbound = PeerOutbound::spawn(
+                peer.id.clone(),
+                gossip_addr,
+                self.transport.clone(),
+                self.outbound.clone(),
+                self.peers.clone(),
+            );
             peers.insert(
                 peer.id.clone(),
                 PeerConnection {
@@ -117,6 +282,7 @@ impl NetworkService {
                     gossip_addr,
                     healthy: true,
                     last_seen: std::time::Instant::now(),
+                    outbound,
                 },
             );
             info!("added peer {} at {}", peer.id, gossip_addr);
@@ -124,61 +290,111 @@ impl NetworkService {
     }
     pub async fn remove_peer(&self, peer_id: &NodeId) {
         let mut peers = self.peers.write().await;
-        peers.remove(peer_id);
+        if let Some(conn) = peers.remove(peer_id) {
+            conn.outbound.close();
+        }
     }
     pub async fn broadcast(&self, envelope: GossipEnvelope) -> Result<()> {
+        self.send_gossip(envelope, None).await
+    }
+    pub async fn forward(&self, envelope: GossipEnvelope, from: Option<&NodeInfo>) -> Result<()> {
+        self.send_gossip(envelope, from).await
+    }
+    async fn send_gossip(&self, envelope: GossipEnvelope, from: Option<&NodeInfo>) -> Result<()> {
         let peers = self.peers.read().await;
         for (peer_id, conn) in peers.iter() {
-            if !conn.healthy {
+            if *peer_id == envelope.origin {
                 continue;
             }
-            let envelope_clone = envelope.clone();
-            let addr = conn.gossip_addr;
-            let peer_id_clone = peer_id.clone();
-            let peers_clone = self.peers.clone();
-            tokio::spawn(async move {
-                if let Err(e) = send_to_peer(addr, NetworkMessage::Gossip(envelope_clone)).await {
-                    debug!("failed to send to {}: {}", peer_id_clone, e);
-                    let mut peers = peers_clone.write().await;
-                    if let Some(conn) = peers.get_mut(&peer_id_clone) {
-                        conn.healthy = false;
-                    }
-                }
-            });
+            if from.is_some_and(|from| from.id == *peer_id || from.address == conn.info.address) {
+                continue;
+            }
+            let message = NetworkMessage::Gossip(Box::new(GossipEnvelope {
+                relayed_by: Some(self.local_node.id.clone()),
+                ..envelope.clone()
+            }));
+            if !conn.outbound.send(message) {
+                debug!("outbound queue for {} is full, dropping gossip", peer_id);
+            }
         }
         Ok(())
     }
     pub async fn sync_with_peer(
         &self,
         peer_id: &NodeId,
-        from_version: u64,
+        since: HashMap<NodeId, u64>,
     ) -> Result<Vec<GossipEnvelope>> {
-        let peers = self.peers.read().await;
-        let conn = peers
-            .get(peer_id)
-            .ok_or_else(|| Error::Network(format!("peer {} not found", peer_id)))?;
-        let response = send_and_receive(
-            conn.gossip_addr,
-            NetworkMessage::SyncRequest { from_version },
+        let addr = self.peer_addr(peer_id).await?;
+        let response = match send_and_receive(
+            addr,
+            &self.transport,
+            NetworkMessage::SyncSince { since },
         )
-        .await?;
+        .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                debug!(
+                    "sync with {} failed, retrying as a full legacy sync: {}",
+                    peer_id, e
+                );
+                self.request(peer_id, NetworkMessage::SyncRequest { from_version: 0 })
+                    .await?
+            }
+        };
         match response {
             NetworkMessage::SyncResponse { entries } => Ok(entries),
             _ => Err(Error::Network("unexpected response".into())),
         }
     }
     pub async fn discover_from_peer(&self, peer_id: &NodeId) -> Result<Vec<NodeInfo>> {
-        let peers = self.peers.read().await;
-        let conn = peers
-            .get(peer_id)
-            .ok_or_else(|| Error::Network(format!("peer {} not found", peer_id)))?;
-        let response = send_and_receive(conn.gossip_addr, NetworkMessage::DiscoveryRequest).await?;
+        let response = self
+            .request(peer_id, NetworkMessage::DiscoveryRequest)
+            .await?;
         match response {
-            NetworkMessage::DiscoveryResponse { nodes } => Ok(nodes),
+            NetworkMessage::DiscoveryResponse { nodes, node } => {
+                Ok(nodes.into_iter().chain(node).collect())
+            }
             _ => Err(Error::Network("unexpected response".into())),
         }
     }
-    pub async fn receive(&self) -> Option<GossipEnvelope> {
+    pub async fn ping(&self, peer_id: &NodeId) -> Result<()> {
+        match self.request(peer_id, NetworkMessage::Ping).await? {
+            NetworkMessage::Pong => Ok(()),
+            _ => Err(Error::Network("unexpected response".into())),
+        }
+    }
+    pub async fn token_status(&self, peer_id: &NodeId, id: Uuid) -> Result<TokenReplicaStatus> {
+        match self
+            .request(peer_id, NetworkMessage::TokenStatusRequest { id })
+            .await?
+        {
+            NetworkMessage::TokenStatusResponse { status } => Ok(status),
+            NetworkMessage::TokenStatusFailed { error } => Err(Error::Network(format!(
+                "peer {} could not read token {}: {}",
+                peer_id, id, error
+            ))),
+            _ => Err(Error::Network("unexpected response".into())),
+        }
+    }
+    async fn peer_addr(&self, peer_id: &NodeId) -> Result<SocketAddr> {
+        self.peers
+            .read()
+            .await
+            .get(peer_id)
+            .map(|conn| conn.gossip_addr)
+            .ok_or_else(|| Error::Network(format!("peer {} not found", peer_id)))
+    }
+    async fn request(&self, peer_id: &NodeId, message: NetworkMessage) -> Result<NetworkMessage> {
+        let addr = self.peer_addr(peer_id).await?;
+        let result = send_and_receive(addr, &self.transport, message).await;
+        if let Err(ref e) = result {
+            debug!("request to {} failed: {}", peer_id, e);
+            self.mark_unhealthy(peer_id).await;
+        }
+        result
+    }
+    pub async fn receive(&self) -> Option<IncomingGossip> {
         let mut rx = self.incoming_rx.write().await;
         rx.recv().await
     }
@@ -194,123 +410,659 @@ impl NetworkService {
             .map(|c| c.info.clone())
             .collect()
     }
+    pub async fn peers(&self) -> Vec<NodeInfo> {
+        self.peers
+            .read()
+            .await
+            .values()
+            .map(|c| c.info.clone())
+            .collect()
+    }
+    pub async fn peers_last_seen(&self) -> Vec<(NodeInfo, chrono::DateTime<chrono::Utc>)> {
+        let now = chrono::Utc::now();
+        self.peers
+            .read()
+            .await
+            .values()
+            .map(|c| {
+                let last_seen = match c.healthy {
+                    true => now,
+                    false => {
+                        now - chrono::Duration::from_std(c.last_seen.elapsed())
+                            .unwrap_or(chrono::Duration::MAX)
+                    }
+                };
+                (c.info.clone(), last_seen)
+            })
+            .collect()
+    }
+    pub async fn peer_snapshots(&self) -> Vec<PeerStatus> {
+        let mut peers: Vec<_> = self
+            .peers
+            .read()
+            .await
+            .values()
+            .map(|c| PeerStatus {
+                node_id: c.info.id.clone(),
+                address: c.info.address,
+                healthy: c.healthy,
+                last_seen_ms: c.last_seen.elapsed().as_millis() as u64,
+                queued: c.outbound.queued(),
+                dropped: c.outbound.dropped(),
+                connect_failures: c.outbound.failures(),
+                backoff_ms: c.outbound.backoff().as_millis() as u64,
+            })
+            .collect();
+        peers.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        peers
+    }
+    pub async fn gossip_status(&self) -> Option<GossipStoreStatus> {
+        match self.gossip {
+            Some(ref gossip) => Some(gossip.status().await),
+            None => None,
+        }
+    }
+    pub async fn mark_unhealthy(&self, peer_id: &NodeId) {
+        let mut peers = self.peers.write().await;
+        if let Some(conn) = peers.get_mut(peer_id) {
+            conn.healthy = false;
+        }
+    }
     pub async fn mark_healthy(&self, peer_id: &NodeId) {
         let mut peers = self.peers.write().await;
         if let Some(conn) = peers.get_mut(peer_id) {
+            conn.outbound.reset_backoff();
             conn.healthy = true;
             conn.last_seen = std::time::Instant::now();
         }
     }
 }
+#[derive(Clone)]
+struct ConnectionContext {
+    incoming_tx: mpsc::Sender<IncomingGossip>,
+    peers: PeerMap,
+    gossip: Option<Arc<GossipService>>,
+    consensus: Option<Arc<dyn ConsensusProtocol>>,
+    bully: Option<Arc<BullyElection>>,
+    tokens: Option<Arc<dyn TokenLookup>>,
+    local_node: NodeInfo,
+    transport: GossipTransport,
+    telemetry: Telemetry,
+    shutdown_tx: broadcast::Sender<()>,
+}
+async fn relaying_peer(
+    peers: &PeerMap,
+    envelope: &GossipEnvelope,
+    remote: SocketAddr,
+) -> Option<NodeInfo> {
+    let relayed_by = envelope.relayed_by.as_ref()?;
+    let peers = peers.read().await;
+    match peers.get(relayed_by) {
+        Some(conn) if conn.gossip_addr.ip() == remote.ip() => Some(conn.info.clone()),
+        _ => {
+            debug!(
+                "ignoring relay claim from {} on a connection from {}",
+                relayed_by, remote
+            );
+            None
+        }
+    }
+}
 async fn handle_connection(
-    mut stream: TcpStream,
-    incoming_tx: mpsc::Sender<GossipEnvelope>,
-    peers: Arc<RwLock<HashMap<NodeId, PeerConnection>>>,
-    _local_id: NodeId,
+    mut stream: Box<dyn GossipStream>,
+    remote: SocketAddr,
+    context: &ConnectionContext,
 ) -> Result<()> {
-    loop {
-        let mut len_buf = [0u8; 4];
-        match stream.read_exact(&mut len_buf).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(Error::Network(format!("read error: {}", e))),
-        }
-        let len = u32::from_be_bytes(len_buf) as usize;
-        if len > MAX_MESSAGE_SIZE {
-            return Err(Error::Network("message too large".into()));
-        }
-        let mut buf = vec![0u8; len];
-        stream
-            .read_exact(&mut buf)
-            .await
-            .map_err(|e| Error::Network(format!("read error: {}", e)))?;
-        let message: NetworkMessage = serde_json::from_slice(&buf)
-            .map_err(|e| Error::Network(format!("deserialize error: {}", e)))?;
+    let transport = &context.transport;
+    while let Some((message, format)) = transport.read_frame_with_format(&mut stream).await? {
         match message {
             NetworkMessage::Gossip(envelope) => {
-                if let Err(e) = incoming_tx.send(envelope).await {
+                let from = relaying_peer(&context.peers, &envelope, remote).await;
+                if let Some(ref from) = from {
+                    transport.record_format(gossip_addr(from), format);
+                }
+                if let Err(e) = context.incoming_tx.send((*envelope, from)).await {
                     error!("failed to queue incoming message: {}", e);
                 }
             }
             NetworkMessage::Ping => {
                 let response = NetworkMessage::Pong;
-                send_response(&mut stream, &response).await?;
+                transport
+                    .write_frame_as(&mut stream, &response, format)
+                    .await?;
+            }
+            NetworkMessage::SyncRequest { from_version } => {
+                let entries = match &context.gossip {
+                    Some(gossip) => gossip.entries_after(from_version).await,
+                    None => vec![],
+                };
+                let response = NetworkMessage::SyncResponse { entries };
+                transport
+                    .write_frame_as(&mut stream, &response, format)
+                    .await?;
             }
-            NetworkMessage::SyncRequest { from_version: _ } => {
-                let response = NetworkMessage::SyncResponse { entries: vec![] };
-                send_response(&mut stream, &response).await?;
+            NetworkMessage::SyncSince { since } => {
+                let entries = match &context.gossip {
+                    Some(gossip) => gossip.entries_since(&since).await,
+                    None => vec![],
+                };
+                let response = NetworkMessage::SyncResponse { entries };
+                transport
+                    .write_frame_as(&mut stream, &response, format)
+                    .await?;
             }
             NetworkMessage::DiscoveryRequest => {
-                let peers_guard = peers.read().await;
+                let peers_guard = context.peers.read().await;
                 let nodes: Vec<NodeInfo> = peers_guard.values().map(|c| c.info.clone()).collect();
-                let response = NetworkMessage::DiscoveryResponse { nodes };
-                send_response(&mut stream, &response).await?;
+                let response = NetworkMessage::DiscoveryResponse {
+                    nodes,
+                    node: Some(context.local_node.clone()),
+                };
+                transport
+                    .write_frame_as(&mut stream, &response, format)
+                    .await?;
+            }
+            NetworkMessage::VoteRequest(request) => {
+                if let Some(ref consensus) = context.consensus {
+                    let response =
+                        NetworkMessage::VoteResponse(consensus.request_vote(request).await?);
+                    transport
+                        .write_frame_as(&mut stream, &response, format)
+                        .await?;
+                }
+            }
+            NetworkMessage::Heartbeat(request) => {
+                if let Some(ref consensus) = context.consensus {
+                    let response =
+                        NetworkMessage::HeartbeatResponse(consensus.append_entries(request).await?);
+                    transport
+                        .write_frame_as(&mut stream, &response, format)
+                        .await?;
+                }
+            }
+            NetworkMessage::BullyElection {
+                candidate_id,
+                priority,
+            } => {
+                if let Some(ref bully) = context.bully {
+                    let ok = bully.handle_election_message(&candidate_id, priority).await;
+                    let response = NetworkMessage::BullyAnswer {
+                        node_id: context.local_node.id.clone(),
+                        ok,
+                        term: bully.term(),
+                    };
+                    transport
+                        .write_frame_as(&mut stream, &response, format)
+                        .await?;
+                }
+            }
+            NetworkMessage::BullyCoordinator {
+                leader_id,
+                priority,
+                term,
+            } => {
+                if let Some(ref bully) = context.bully {
+                    let ok = bully
+                        .handle_coordinator_message(leader_id, priority, term)
+                        .await;
+                    let response = NetworkMessage::BullyAnswer {
+                        node_id: context.local_node.id.clone(),
+                        ok,
+                        term: bully.term(),
+                    };
+                    transport
+                        .write_frame_as(&mut stream, &response, format)
+                        .await?;
+                }
+            }
+            NetworkMessage::TokenStatusRequest { id } => {
+                let response = match context.tokens {
+                    Some(ref tokens) => match tokens.status(&id).await {
+                        Ok(status) => NetworkMessage::TokenStatusResponse { status },
+                        Err(e) => NetworkMessage::TokenStatusFailed {
+                            error: e.to_string(),
+                        },
+                    },
+                    None => NetworkMessage::TokenStatusResponse {
+                        status: TokenReplicaStatus::Unknown,
+                    },
+                };
+                transport
+                    .write_frame_as(&mut stream, &response, format)
+                    .await?;
             }
             _ => {}
         }
     }
     Ok(())
 }
-async fn send_response(stream: &mut TcpStream, message: &NetworkMessage) -> Result<()> {
-    let data = serde_json::to_vec(message)
-        .map_err(|e| Error::Network(format!("serialize error: {}", e)))?;
-    let len = (data.len() as u32).to_be_bytes();
-    stream
-        .write_all(&len)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    stream
-        .write_all(&data)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    Ok(())
+pub(crate) fn gossip_addr(peer: &NodeInfo) -> SocketAddr {
+    gossip_socket(peer.address)
 }
-async fn send_to_peer(addr: SocketAddr, message: NetworkMessage) -> Result<()> {
-    let mut stream = TcpStream::connect(addr)
-        .await
-        .map_err(|e| Error::Network(format!("connect error: {}", e)))?;
-    let data = serde_json::to_vec(&message)
-        .map_err(|e| Error::Network(format!("serialize error: {}", e)))?;
-    let len = (data.len() as u32).to_be_bytes();
-    stream
-        .write_all(&len)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    stream
-        .write_all(&data)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    Ok(())
+pub(crate) fn gossip_socket(address: SocketAddr) -> SocketAddr {
+    SocketAddr::new(address.ip(), address.port() + GOSSIP_PORT_OFFSET)
 }
-async fn send_and_receive(addr: SocketAddr, message: NetworkMessage) -> Result<NetworkMessage> {
-    let mut stream = tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(addr))
-        .await
-        .map_err(|_| Error::Network("connection timeout".into()))?
-        .map_err(|e| Error::Network(format!("connect error: {}", e)))?;
-    let data = serde_json::to_vec(&message)
-        .map_err(|e| Error::Network(format!("serialize error: {}", e)))?;
-    let len = (data.len() as u32).to_be_bytes();
-    stream
-        .write_all(&len)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    stream
-        .write_all(&data)
-        .await
-        .map_err(|e| Error::Network(format!("write error: {}", e)))?;
-    let mut len_buf = [0u8; 4];
-    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut len_buf))
+pub(crate) async fn send_and_receive(
+    addr: SocketAddr,
+    transport: &GossipTransport,
+    message: NetworkMessage,
+) -> Result<NetworkMessage> {
+    let format = transport.format_for(addr);
+    let mut stream = transport.connect(addr).await?;
+    if let Some(response) = exchange(&mut stream, transport, &message, format).await? {
+        transport.record_format(addr, format);
+        return Ok(response);
+    }
+    if format != WireFormat::Binary || transport.wire_format() != WireFormat::Auto {
+        return Err(Error::Network("connection closed".into()));
+    }
+    debug!(
+        "{} closed the connection on a binary frame, retrying as json",
+        addr
+    );
+    let mut stream = transport.connect(addr).await?;
+    let response = exchange(&mut stream, transport, &message, WireFormat::Json)
+        .await?
+        .ok_or_else(|| Error::Network("connection closed".into()))?;
+    transport.record_format(addr, WireFormat::Json);
+    Ok(response)
+}
+async fn exchange(
+    stream: &mut Box<dyn GossipStream>,
+    transport: &GossipTransport,
+    message: &NetworkMessage,
+    format: WireFormat,
+) -> Result<Option<NetworkMessage>> {
+    transport.write_frame_as(stream, message, format).await?;
+    tokio::time::timeout(Duration::from_secs(5), transport.read_frame(stream))
         .await
         .map_err(|_| Error::Network("read timeout".into()))?
-        .map_err(|e| Error::Network(format!("read error: {}", e)))?;
-    let response_len = u32::from_be_bytes(len_buf) as usize;
-    if response_len > MAX_MESSAGE_SIZE {
-        return Err(Error::Network("response too large".into()));
-    }
-    let mut buf = vec![0u8; response_len];
-    stream
-        .read_exact(&mut buf)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::{GossipTls, GossipTlsConfig};
+    use chrono::Utc;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use std::path::Path;
+    struct Ca {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+    impl Ca {
+        fn new(dir: &Path) -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "ironfish test ca");
+            let cert = params.self_signed(&key).unwrap();
+            std::fs::write(dir.join("ca.crt"), cert.pem()).unwrap();
+            Self { cert, key }
+        }
+        fn issue(&self, dir: &Path, name: &str, mutual: bool) -> GossipTlsConfig {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            let cert_path = dir.join(format!("{}.crt", name));
+            let key_path = dir.join(format!("{}.key", name));
+            std::fs::write(&cert_path, cert.pem()).unwrap();
+            std::fs::write(&key_path, key.serialize_pem()).unwrap();
+            GossipTlsConfig {
+                enabled: true,
+                cert_path: Some(cert_path),
+                key_path: Some(key_path),
+                ca_path: Some(dir.join("ca.crt")),
+                mutual,
+                allowed_peers: vec!["node-a".to_string(), "node-b".to_string()],
+                server_name: None,
+            }
+        }
+    }
+    fn node_info(id: &str) -> NodeInfo {
+        let gossip_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map(|a| a.port())
+            .unwrap();
+        NodeInfo {
+            id: NodeId::from_string(id),
+            address: format!("127.0.0.1:{}", gossip_port - GOSSIP_PORT_OFFSET)
+                .parse()
+                .unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        }
+    }
+    async fn tls_node(id: &str, config: &GossipTlsConfig) -> NetworkService {
+        let tls = GossipTls::from_config(config).unwrap();
+        let network =
+            NetworkService::new(node_info(id)).with_transport(GossipTransport::new().with_tls(tls));
+        network.start().await.unwrap();
+        network
+    }
+    #[tokio::test]
+    async fn test_mutual_tls_between_two_nodes() {
+        let dir = std::env::temp_dir().join(format!("ironfish-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca = Ca::new(&dir);
+        let a = tls_node("node-a", &ca.issue(&dir, "node-a", true)).await;
+        let b = tls_node("node-b", &ca.issue(&dir, "node-b", true)).await;
+        a.add_peer(b.local_node.clone()).await;
+        b.add_peer(a.local_node.clone()).await;
+        a.ping(&b.local_node.id).await.unwrap();
+        let envelope = GossipEnvelope::new(
+            GossipMessage::TokenRevoked(Uuid::new_v4()),
+            a.local_node.id.clone(),
+            1,
+        );
+        a.broadcast(envelope).await.unwrap();
+        let (received, from) = tokio::time::timeout(Duration::from_secs(5), b.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.origin, a.local_node.id);
+        assert_eq!(from.unwrap().id, a.local_node.id);
+        let intruder = tls_node("intruder", &ca.issue(&dir, "intruder", true)).await;
+        intruder.add_peer(b.local_node.clone()).await;
+        assert!(intruder.ping(&b.local_node.id).await.is_err());
+        assert!(intruder.healthy_peers().await.is_empty());
+        let plain = NetworkService::new(node_info("plain"));
+        plain.add_peer(b.local_node.clone()).await;
+        assert!(plain.ping(&b.local_node.id).await.is_err());
+        let rogue_dir = dir.join("rogue");
+        std::fs::create_dir_all(&rogue_dir).unwrap();
+        let rogue_ca = Ca::new(&rogue_dir);
+        let rogue = tls_node("node-a", &rogue_ca.issue(&rogue_dir, "node-a", true)).await;
+        rogue.add_peer(b.local_node.clone()).await;
+        let err = rogue.ping(&b.local_node.id).await.unwrap_err();
+        assert!(err.to_string().contains("tls handshake"), "{}", err);
+        for node in [a, b, intruder, rogue] {
+            node.stop().await;
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    #[tokio::test]
+    async fn test_announces_advertise_address_but_binds_configured_interface() {
+        let bound = node_info("node-a");
+        let advertised = NodeInfo {
+            address: "10.20.30.40:9000".parse().unwrap(),
+            ..bound.clone()
+        };
+        let a = NetworkService::new(advertised.clone()).with_bind_address(bound.address);
+        a.start().await.unwrap();
+        let b = NetworkService::new(node_info("node-b"));
+        b.start().await.unwrap();
+        let response = send_and_receive(
+            gossip_socket(bound.address),
+            &GossipTransport::default(),
+            NetworkMessage::DiscoveryRequest,
+        )
         .await
-        .map_err(|e| Error::Network(format!("read error: {}", e)))?;
-    serde_json::from_slice(&buf).map_err(|e| Error::Network(format!("deserialize error: {}", e)))
+        .unwrap();
+        match response {
+            NetworkMessage::DiscoveryResponse { node, .. } => {
+                assert_eq!(node.unwrap().address, advertised.address)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        a.add_peer(b.local_node.clone()).await;
+        b.add_peer(advertised.clone()).await;
+        let envelope = GossipEnvelope::new(
+            GossipMessage::TokenRevoked(Uuid::new_v4()),
+            advertised.id.clone(),
+            1,
+        );
+        a.broadcast(envelope).await.unwrap();
+        let (received, from) = tokio::time::timeout(Duration::from_secs(5), b.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.relayed_by, Some(advertised.id.clone()));
+        assert!(from.is_none());
+        a.stop().await;
+        b.stop().await;
+    }
+    struct FailingLookup;
+    #[async_trait]
+    impl TokenLookup for FailingLookup {
+        async fn status(&self, _id: &Uuid) -> Result<TokenReplicaStatus> {
+            Err(Error::Storage("disk unavailable".into()))
+        }
+    }
+    #[tokio::test]
+    async fn test_relay_claims_need_a_known_peer() {
+        let a = NetworkService::new(node_info("node-a"));
+        a.start().await.unwrap();
+        let b = NetworkService::new(node_info("node-b"));
+        b.start().await.unwrap();
+        a.add_peer(b.local_node.clone()).await;
+        let envelope = || {
+            GossipEnvelope::new(
+                GossipMessage::TokenRevoked(Uuid::new_v4()),
+                NodeId::from_string("node-c"),
+                1,
+            )
+        };
+        a.broadcast(envelope()).await.unwrap();
+        let (received, from) = tokio::time::timeout(Duration::from_secs(5), b.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.relayed_by, Some(a.local_node.id.clone()));
+        assert!(from.is_none());
+        b.add_peer(a.local_node.clone()).await;
+        a.broadcast(envelope()).await.unwrap();
+        let (_, from) = tokio::time::timeout(Duration::from_secs(5), b.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(from.unwrap().id, a.local_node.id);
+        a.stop().await;
+        b.stop().await;
+    }
+    #[tokio::test]
+    async fn test_token_status_always_answers() {
+        let a = NetworkService::new(node_info("node-a"));
+        let b = NetworkService::new(node_info("node-b"));
+        b.start().await.unwrap();
+        let mut c = NetworkService::new(node_info("node-c"));
+        c.tokens = Some(Arc::new(FailingLookup));
+        c.start().await.unwrap();
+        a.add_peer(b.local_node.clone()).await;
+        a.add_peer(c.local_node.clone()).await;
+        let id = Uuid::new_v4();
+        let reply =
+            tokio::time::timeout(Duration::from_secs(5), a.token_status(&b.local_node.id, id))
+                .await
+                .unwrap();
+        assert_eq!(reply.unwrap(), TokenReplicaStatus::Unknown);
+        let err =
+            tokio::time::timeout(Duration::from_secs(5), a.token_status(&c.local_node.id, id))
+                .await
+                .unwrap()
+                .unwrap_err();
+        assert!(err.to_string().contains("disk unavailable"), "{}", err);
+        b.stop().await;
+        c.stop().await;
+    }
+    #[tokio::test]
+    async fn test_peer_snapshots() {
+        let network = NetworkService::new(node_info("local"));
+        let peer_b = node_info("node-b");
+        let peer_a = node_info("node-a");
+        network.add_peer(peer_b.clone()).await;
+        network.add_peer(peer_a.clone()).await;
+        network.add_peer(node_info("local")).await;
+        network.mark_unhealthy(&peer_b.id).await;
+        let peers = network.peer_snapshots().await;
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].node_id, peer_a.id);
+        assert_eq!(peers[0].address, peer_a.address);
+        assert!(peers[0].healthy);
+        assert!(!peers[1].healthy);
+        assert!(peers[1].last_seen_ms < 5000);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let before = chrono::Utc::now();
+        let seen: HashMap<_, _> = network
+            .peers_last_seen()
+            .await
+            .into_iter()
+            .map(|(node, last_seen)| (node.id, last_seen))
+            .collect();
+        assert!(seen[&peer_a.id] >= before);
+        assert!(seen[&peer_b.id] < before);
+    }
+    #[tokio::test]
+    async fn test_peers_are_unique_per_address() {
+        let network = NetworkService::new(node_info("local"));
+        let peer = node_info("node-b");
+        let discovered = NodeInfo {
+            id: NodeId::from_string(peer.address.to_string()),
+            ..peer.clone()
+        };
+        network.add_peer(discovered).await;
+        network.add_peer(peer.clone()).await;
+        let peers = network.peers().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id, peer.id);
+    }
+    #[tokio::test]
+    async fn test_outbound_backs_off_and_recovers() {
+        let peer = node_info("node-b");
+        let network = NetworkService::new(node_info("node-a")).with_outbound(OutboundConfig {
+            backoff_base: Duration::from_millis(20),
+            backoff_max: Duration::from_millis(200),
+            ..Default::default()
+        });
+        let first = NetworkService::new(peer.clone());
+        first.start().await.unwrap();
+        network.add_peer(peer.clone()).await;
+        let gossip = || {
+            GossipEnvelope::new(
+                GossipMessage::TokenRevoked(Uuid::new_v4()),
+                NodeId::from_string("origin"),
+                1,
+            )
+        };
+        network.broadcast(gossip()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), first.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        first.stop().await;
+        drop(first);
+        let attempts = |network: &NetworkService| {
+            let network = network.peers.clone();
+            let id = peer.id.clone();
+            async move { network.read().await[&id].outbound.connect_attempts() }
+        };
+        let before = attempts(&network).await;
+        for _ in 0..100 {
+            network.broadcast(gossip()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let retried = attempts(&network).await - before;
+        assert!(retried > 1 && retried < 20, "{} connect attempts", retried);
+        let status = network.peer_snapshots().await.remove(0);
+        assert!(!status.healthy);
+        assert!(status.connect_failures > 0);
+        assert!(status.backoff_ms > 0);
+        let second = NetworkService::new(peer.clone());
+        second.start().await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                network.broadcast(gossip()).await.unwrap();
+                if let Ok(received) =
+                    tokio::time::timeout(Duration::from_millis(100), second.receive()).await
+                {
+                    return received;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(received.is_some());
+        let status = network.peer_snapshots().await.remove(0);
+        assert!(status.healthy);
+        assert_eq!(status.backoff_ms, 0);
+        network.stop().await;
+        second.stop().await;
+    }
+    #[tokio::test]
+    async fn test_sync_falls_back_to_legacy_request() {
+        let peer = node_info("legacy");
+        let listener = TcpListener::bind(gossip_addr(&peer)).await.unwrap();
+        let legacy = tokio::spawn(async move {
+            let transport = GossipTransport::new();
+            let mut requests = Vec::new();
+            for _ in 0..5 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = transport.accept(stream).await.unwrap();
+                let (request, format) = transport
+                    .read_frame_with_format(&mut stream)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let response = match request {
+                    _ if format == WireFormat::Binary => None,
+                    NetworkMessage::SyncRequest { .. } => Some(NetworkMessage::SyncResponse {
+                        entries: vec![GossipEnvelope::new(
+                            GossipMessage::NodeLeft(NodeId::from_string("gone")),
+                            NodeId::from_string("legacy"),
+                            1_700_000_000_000,
+                        )],
+                    }),
+                    NetworkMessage::Ping => Some(NetworkMessage::Pong),
+                    _ => None,
+                };
+                if let Some(response) = response {
+                    transport
+                        .write_frame_as(&mut stream, &response, format)
+                        .await
+                        .unwrap();
+                }
+                requests.push((request, format));
+            }
+            requests
+        });
+        let network = NetworkService::new(node_info("local"));
+        network.add_peer(peer.clone()).await;
+        let entries = network
+            .sync_with_peer(&peer.id, HashMap::from([(peer.id.clone(), 7)]))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        network.ping(&peer.id).await.unwrap();
+        let requests = legacy.await.unwrap();
+        assert!(matches!(
+            requests[0],
+            (NetworkMessage::SyncSince { .. }, WireFormat::Binary)
+        ));
+        assert!(matches!(
+            requests[1],
+            (NetworkMessage::SyncSince { .. }, WireFormat::Json)
+        ));
+        assert!(matches!(
+            requests[2],
+            (
+                NetworkMessage::SyncRequest { from_version: 0 },
+                WireFormat::Binary
+            )
+        ));
+        assert!(matches!(
+            requests[3],
+            (
+                NetworkMessage::SyncRequest { from_version: 0 },
+                WireFormat::Json
+            )
+        ));
+        assert!(matches!(
+            requests[4],
+            (NetworkMessage::Ping, WireFormat::Json)
+        ));
+        assert!(network.peer_snapshots().await[0].healthy);
+    }
 }
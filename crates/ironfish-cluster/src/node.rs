@@ -1,12 +1,15 @@
+use crate::events::ClusterEventBus;
 use chrono::Utc;
-use ironfish_core::{NodeId, NodeInfo, NodeMetrics, NodeState, NodeStatus};
+use ironfish_core::{ClusterEvent, NodeId, NodeInfo, NodeMetrics, NodeState, NodeStatus};
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use tracing::warn;
 #[derive(Debug, Clone)]
 pub struct NodeConfig {
     pub id: Option<String>,
     pub bind_address: SocketAddr,
+    pub advertise_address: Option<SocketAddr>,
     pub priority: u32,
     pub version: String,
 }
@@ -15,18 +18,32 @@ impl Default for NodeConfig {
         Self {
             id: None,
             bind_address: "0.0.0.0:8080".parse().expect("valid default bind address"),
+            advertise_address: None,
             priority: 100,
             version: env!("CARGO_PKG_VERSION").to_string(),
         }
     }
 }
+#[derive(Debug, Clone)]
+pub struct NodeSnapshot {
+    pub info: NodeInfo,
+    pub state: NodeState,
+    pub leader_id: Option<NodeId>,
+    pub term: u64,
+    pub draining: bool,
+    pub metrics: NodeMetrics,
+    pub uptime_seconds: u64,
+}
 pub struct Node {
     info: NodeInfo,
+    bind_address: SocketAddr,
     state: RwLock<NodeState>,
     leader_id: RwLock<Option<NodeId>>,
     term: AtomicU64,
+    draining: AtomicBool,
     metrics: RwLock<NodeMetrics>,
     started_at: chrono::DateTime<Utc>,
+    events: ClusterEventBus,
 }
 impl Node {
     pub fn new(config: NodeConfig) -> Self {
@@ -35,20 +52,30 @@ impl Node {
             .map(NodeId::from_string)
             .unwrap_or_else(NodeId::generate);
         let started_at = Utc::now();
+        let address = config.advertise_address.unwrap_or(config.bind_address);
+        if address.ip().is_unspecified() {
+            warn!(
+                "node {} advertises unspecified address {}, set advertise_address so peers can reach it",
+                id, address
+            );
+        }
         let info = NodeInfo {
             id,
-            address: config.bind_address,
+            address,
             priority: config.priority,
             started_at,
             version: config.version,
         };
         Self {
             info,
+            bind_address: config.bind_address,
             state: RwLock::new(NodeState::Starting),
             leader_id: RwLock::new(None),
             term: AtomicU64::new(0),
+            draining: AtomicBool::new(false),
             metrics: RwLock::new(NodeMetrics::default()),
             started_at,
+            events: ClusterEventBus::default(),
         }
     }
     pub fn info(&self) -> &NodeInfo {
@@ -57,6 +84,9 @@ impl Node {
     pub fn id(&self) -> &NodeId {
         &self.info.id
     }
+    pub fn bind_address(&self) -> SocketAddr {
+        self.bind_address
+    }
     pub fn state(&self) -> NodeState {
         *self.state.read().unwrap()
     }
@@ -67,7 +97,14 @@ impl Node {
         self.leader_id.read().unwrap().clone()
     }
     pub fn set_leader(&self, leader: Option<NodeId>) {
-        *self.leader_id.write().unwrap() = leader;
+        let previous = std::mem::replace(&mut *self.leader_id.write().unwrap(), leader.clone());
+        if previous != leader {
+            self.events.emit(ClusterEvent::LeaderChanged {
+                old_leader: previous,
+                new_leader: leader,
+                term: self.term(),
+            });
+        }
     }
     pub fn term(&self) -> u64 {
         self.term.load(Ordering::SeqCst)
@@ -78,21 +115,49 @@ impl Node {
     pub fn increment_term(&self) -> u64 {
         self.term.fetch_add(1, Ordering::SeqCst) + 1
     }
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+    pub fn set_draining(&self, draining: bool) -> bool {
+        let changed = self.draining.swap(draining, Ordering::SeqCst) != draining;
+        if changed {
+            self.events.emit(ClusterEvent::NodeDraining {
+                node_id: self.id().clone(),
+                draining,
+            });
+        }
+        changed
+    }
     pub fn metrics(&self) -> NodeMetrics {
         self.metrics.read().unwrap().clone()
     }
     pub fn update_metrics(&self, metrics: NodeMetrics) {
         *self.metrics.write().unwrap() = metrics;
     }
-    pub fn status(&self, cluster_size: usize) -> NodeStatus {
-        let uptime = (Utc::now() - self.started_at).num_seconds() as u64;
-        NodeStatus {
+    pub fn snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
             info: self.info.clone(),
             state: self.state(),
             leader_id: self.leader(),
             term: self.term(),
+            draining: self.is_draining(),
+            metrics: self.metrics(),
+            uptime_seconds: (Utc::now() - self.started_at).num_seconds().max(0) as u64,
+        }
+    }
+    pub fn status(&self, cluster_size: usize) -> NodeStatus {
+        let snapshot = self.snapshot();
+        NodeStatus {
+            info: snapshot.info,
+            state: snapshot.state,
+            leader_id: snapshot.leader_id,
+            term: snapshot.term,
             cluster_size,
-            uptime_seconds: uptime,
+            uptime_seconds: snapshot.uptime_seconds,
+            draining: snapshot.draining,
+            metrics: Some(snapshot.metrics),
+            metrics_stale: false,
+            last_seen_ms: None,
         }
     }
     pub fn is_leader(&self) -> bool {
@@ -101,17 +166,8 @@ impl Node {
     pub fn priority(&self) -> u32 {
         self.info.priority
     }
-}
-impl Clone for Node {
-    fn clone(&self) -> Self {
-        Self {
-            info: self.info.clone(),
-            state: RwLock::new(*self.state.read().unwrap()),
-            leader_id: RwLock::new(self.leader_id.read().unwrap().clone()),
-            term: AtomicU64::new(self.term.load(Ordering::SeqCst)),
-            metrics: RwLock::new(self.metrics.read().unwrap().clone()),
-            started_at: self.started_at,
-        }
+    pub fn events(&self) -> &ClusterEventBus {
+        &self.events
     }
 }
 pub type SharedNode = Arc<Node>;
@@ -129,6 +185,7 @@ mod tests {
         let config = NodeConfig {
             id: Some("test-node".to_string()),
             bind_address: "127.0.0.1:8080".parse().unwrap(),
+            advertise_address: None,
             priority: 150,
             version: "1.0.0".to_string(),
         };
@@ -137,6 +194,18 @@ mod tests {
         assert_eq!(node.priority(), 150);
     }
     #[test]
+    fn test_node_advertise_address() {
+        let bind_address: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let node = Node::new(NodeConfig {
+            advertise_address: Some("10.0.0.5:18080".parse().unwrap()),
+            ..NodeConfig::default()
+        });
+        assert_eq!(node.info().address, "10.0.0.5:18080".parse().unwrap());
+        assert_eq!(node.bind_address(), bind_address);
+        let node = Node::new(NodeConfig::default());
+        assert_eq!(node.info().address, bind_address);
+    }
+    #[test]
     fn test_node_auto_id() {
         let config = NodeConfig::default();
         let node = Node::new(config);
@@ -193,5 +262,41 @@ mod tests {
         assert_eq!(status.state, NodeState::Follower);
         assert_eq!(status.term, 10);
         assert_eq!(status.cluster_size, 5);
+        assert!(!status.draining);
+    }
+    #[test]
+    fn test_node_draining() {
+        let node = Node::new(NodeConfig::default());
+        node.set_state(NodeState::Leader);
+        assert!(node.set_draining(true));
+        assert!(!node.set_draining(true));
+        assert!(node.is_draining());
+        assert!(node.is_leader());
+        assert!(node.status(1).draining);
+        assert!(node.set_draining(false));
+        assert!(!node.is_draining());
+    }
+    #[tokio::test]
+    async fn test_node_snapshot_inside_runtime() {
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some("snap".to_string()),
+            ..NodeConfig::default()
+        }));
+        node.set_state(NodeState::Leader);
+        node.set_term(3);
+        node.set_leader(Some(node.id().clone()));
+        node.set_draining(true);
+        let shared = node.clone();
+        let snapshot = tokio::spawn(async move { shared.snapshot() })
+            .await
+            .unwrap();
+        assert_eq!(snapshot.info.id.0, "snap");
+        assert_eq!(snapshot.state, NodeState::Leader);
+        assert_eq!(snapshot.leader_id, Some(node.id().clone()));
+        assert_eq!(snapshot.term, 3);
+        assert!(snapshot.draining);
+        node.set_term(4);
+        assert_eq!(snapshot.term, 3);
+        assert_eq!(node.snapshot().term, 4);
     }
 }
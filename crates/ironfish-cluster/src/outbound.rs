@@ -0,0 +1,182 @@
+use crate::network::{NetworkMessage, PeerMap};
+use crate::transport::{GossipStream, GossipTransport};
+use ironfish_core::NodeId;
+use rand::Rng;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::AbortHandle;
+use tracing::debug;
+#[derive(Debug, Clone)]
+pub struct OutboundConfig {
+    pub queue_capacity: usize,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+}
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 256,
+            backoff_base: Duration::from_millis(100),
+            backoff_max: Duration::from_secs(30),
+        }
+    }
+}
+#[derive(Debug, Default)]
+struct OutboundState {
+    failures: AtomicU32,
+    backoff_ms: AtomicU64,
+    connect_attempts: AtomicU64,
+    dropped: AtomicU64,
+}
+impl OutboundState {
+    fn fail(&self, config: &OutboundConfig) -> Duration {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let ceiling = config
+            .backoff_base
+            .saturating_mul(1 << (failures - 1).min(16))
+            .min(config.backoff_max);
+        let ceiling_ms = ceiling.as_millis() as u64;
+        let delay = rand::thread_rng().gen_range(ceiling_ms / 2..=ceiling_ms);
+        self.backoff_ms.store(delay, Ordering::Relaxed);
+        Duration::from_millis(delay)
+    }
+    fn reset(&self) -> bool {
+        self.backoff_ms.store(0, Ordering::Relaxed);
+        self.failures.swap(0, Ordering::Relaxed) > 0
+    }
+}
+#[derive(Debug, Clone)]
+pub(crate) struct PeerOutbound {
+    tx: mpsc::Sender<NetworkMessage>,
+    state: Arc<OutboundState>,
+    task: AbortHandle,
+}
+impl PeerOutbound {
+    pub(crate) fn spawn(
+        peer_id: NodeId,
+        addr: SocketAddr,
+        transport: GossipTransport,
+        config: OutboundConfig,
+        peers: PeerMap,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        let state = Arc::new(OutboundState::default());
+        let worker = OutboundWorker {
+            peer_id,
+            addr,
+            transport,
+            config,
+            peers,
+            state: state.clone(),
+        };
+        let task = tokio::spawn(worker.run(rx)).abort_handle();
+        Self { tx, state, task }
+    }
+    pub(crate) fn send(&self, message: NetworkMessage) -> bool {
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+    pub(crate) fn queued(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+    pub(crate) fn backoff(&self) -> Duration {
+        Duration::from_millis(self.state.backoff_ms.load(Ordering::Relaxed))
+    }
+    pub(crate) fn failures(&self) -> u32 {
+        self.state.failures.load(Ordering::Relaxed)
+    }
+    #[cfg(test)]
+    pub(crate) fn connect_attempts(&self) -> u64 {
+        self.state.connect_attempts.load(Ordering::Relaxed)
+    }
+    pub(crate) fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+    pub(crate) fn reset_backoff(&self) {
+        self.state.reset();
+    }
+    pub(crate) fn close(&self) {
+        self.task.abort();
+    }
+}
+struct OutboundWorker {
+    peer_id: NodeId,
+    addr: SocketAddr,
+    transport: GossipTransport,
+    config: OutboundConfig,
+    peers: PeerMap,
+    state: Arc<OutboundState>,
+}
+impl OutboundWorker {
+    async fn run(self, mut rx: mpsc::Receiver<NetworkMessage>) {
+        let mut stream: Option<Box<dyn GossipStream>> = None;
+        while let Some(message) = rx.recv().await {
+            loop {
+                if rx.is_closed() {
+                    return;
+                }
+                let fresh = stream.is_none();
+                if fresh {
+                    let delay =
+                        Duration::from_millis(self.state.backoff_ms.load(Ordering::Relaxed));
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    self.state.connect_attempts.fetch_add(1, Ordering::Relaxed);
+                    match self.transport.connect(self.addr).await {
+                        Ok(connected) => stream = Some(connected),
+                        Err(e) => {
+                            let delay = self.state.fail(&self.config);
+                            debug!(
+                                "connect to {} failed, retrying in {:?}: {}",
+                                self.peer_id, delay, e
+                            );
+                            self.set_healthy(false).await;
+                            continue;
+                        }
+                    }
+                }
+                let Some(ref mut connection) = stream else {
+                    continue;
+                };
+                let format = self.transport.format_for(self.addr);
+                match self
+                    .transport
+                    .write_frame_as(connection, &message, format)
+                    .await
+                {
+                    Ok(()) => {
+                        if self.state.reset() {
+                            self.set_healthy(true).await;
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        debug!("send to {} failed: {}", self.peer_id, e);
+                        stream = None;
+                        if fresh {
+                            self.state.fail(&self.config);
+                            self.set_healthy(false).await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    async fn set_healthy(&self, healthy: bool) {
+        if let Some(conn) = self.peers.write().await.get_mut(&self.peer_id) {
+            conn.healthy = healthy;
+            if healthy {
+                conn.last_seen = std::time::Instant::now();
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use ironfish_core::{Error, GossipMessage, Result, TokenStore};
+use std::collections::VecDeque;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+pub struct PendingWrites {
+    queue: Mutex<VecDeque<GossipMessage>>,
+    capacity: usize,
+}
+impl PendingWrites {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+        }
+    }
+    pub async fn len(&self) -> usize {
+        self.queue.lock().await.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.queue.lock().await.is_empty()
+    }
+    pub async fn submit<T: TokenStore + ?Sized>(
+        &self,
+        message: &GossipMessage,
+        store: &T,
+    ) -> Result<()> {
+        if !is_token_write(message) {
+            return Ok(());
+        }
+        if !self.is_empty().await || !store.health().is_writable() {
+            self.push(message.clone()).await;
+            return Ok(());
+        }
+        match apply(message, store).await {
+            Err(Error::Storage(_)) | Err(Error::StoreReadOnly) => {
+                self.push(message.clone()).await;
+                Ok(())
+            }
+            other => other,
+        }
+    }
+    pub async fn flush<T: TokenStore + ?Sized>(&self, store: &T) -> Result<usize> {
+        let mut queue = self.queue.lock().await;
+        let mut flushed = 0;
+        while let Some(message) = queue.front() {
+            match apply(message, store).await {
+                Ok(()) => flushed += 1,
+                Err(e @ Error::Storage(_)) | Err(e @ Error::StoreReadOnly) => return Err(e),
+                Err(e) => debug!("dropping pending token write: {}", e),
+            }
+            queue.pop_front();
+        }
+        if flushed > 0 {
+            info!("flushed {} pending token writes", flushed);
+        }
+        Ok(flushed)
+    }
+    async fn push(&self, message: GossipMessage) {
+        let mut queue = self.queue.lock().await;
+        if let GossipMessage::TokenRevoked(id) = message {
+            if queue
+                .iter()
+                .any(|queued| matches!(queued, GossipMessage::TokenRevoked(q) if *q == id))
+            {
+                return;
+            }
+        }
+        if queue.len() >= self.capacity {
+            match queue
+                .iter()
+                .position(|queued| !matches!(queued, GossipMessage::TokenRevoked(_)))
+            {
+                Some(oldest) => {
+                    queue.remove(oldest);
+                    warn!(
+                        "pending token write queue full ({}), dropping oldest non-revocation",
+                        self.capacity
+                    );
+                }
+                None if !matches!(message, GossipMessage::TokenRevoked(_)) => {
+                    warn!(
+                        "pending token write queue full of revocations ({}), dropping token write",
+                        self.capacity
+                    );
+                    return;
+                }
+                None => {}
+            }
+        }
+        queue.push_back(message);
+    }
+}
+fn is_token_write(message: &GossipMessage) -> bool {
+    matches!(
+        message,
+        GossipMessage::TokenCreated(_)
+            | GossipMessage::TokenRevoked(_)
+            | GossipMessage::TokenUpdated(_)
+    )
+}
+async fn apply<T: TokenStore + ?Sized>(message: &GossipMessage, store: &T) -> Result<()> {
+    match message {
+        GossipMessage::TokenCreated(token) => match store.get(&token.id).await? {
+            Some(existing) => {
+                if token.created_at > existing.created_at {
+                    store.update(token.clone()).await?;
+                    info!("updated token {} from gossip", token.id);
+                }
+            }
+            None => {
+                store.create(token.clone()).await?;
+                info!("replicated token {} from gossip", token.id);
+            }
+        },
+        GossipMessage::TokenRevoked(token_id) => {
+            store.revoke(token_id).await?;
+            info!("revoked token {} from gossip", token_id);
+        }
+        GossipMessage::TokenUpdated(token) => {
+            store.update(token.clone()).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
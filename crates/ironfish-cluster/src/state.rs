@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use ironfish_core::{Error, NodeId, NodeInfo, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+const GOSSIP_WATERMARKS_KEY: &[u8] = b"gossip_watermarks";
+const GOSSIP_CLOCK_KEY: &[u8] = b"gossip_clock";
+const LEGACY_GOSSIP_VERSION_KEY: &[u8] = b"gossip_version";
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedPeer {
+    node: NodeInfo,
+    last_seen: DateTime<Utc>,
+}
+#[derive(Clone)]
+pub struct ClusterStateStore {
+    db: Arc<sled::Db>,
+    peers: sled::Tree,
+}
+impl ClusterStateStore {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    pub fn in_memory() -> Result<Self> {
+        let config = sled::Config::new().temporary(true);
+        let db = config.open().map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(db)
+    }
+    fn from_db(db: sled::Db) -> Result<Self> {
+        let peers = db
+            .open_tree("cluster_peers")
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(Self {
+            db: Arc::new(db),
+            peers,
+        })
+    }
+    pub fn load_peers(&self, ttl: Duration) -> Result<Vec<NodeInfo>> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        let mut nodes = Vec::new();
+        for item in self.peers.iter() {
+            let (_, value) = item.map_err(|e| Error::Storage(e.to_string()))?;
+            let peer: PersistedPeer = serde_json::from_slice(&value)?;
+            if peer.last_seen >= cutoff {
+                nodes.push(peer.node);
+            }
+        }
+        Ok(nodes)
+    }
+    pub async fn save_peers(&self, nodes: &[(NodeInfo, DateTime<Utc>)]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for key in self.peers.iter().keys() {
+            batch.remove(key.map_err(|e| Error::Storage(e.to_string()))?);
+        }
+        for (node, last_seen) in nodes {
+            let peer = PersistedPeer {
+                node: node.clone(),
+                last_seen: *last_seen,
+            };
+            batch.insert(node.id.0.as_bytes(), serde_json::to_vec(&peer)?);
+        }
+        self.peers
+            .apply_batch(batch)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.flush().await
+    }
+    pub fn gossip_watermarks(&self) -> Result<HashMap<NodeId, u64>> {
+        let value = self
+            .db
+            .get(GOSSIP_WATERMARKS_KEY)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        match value {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Ok(HashMap::new()),
+        }
+    }
+    pub async fn set_gossip_watermarks(&self, watermarks: &HashMap<NodeId, u64>) -> Result<()> {
+        self.db
+            .insert(GOSSIP_WATERMARKS_KEY, serde_json::to_vec(watermarks)?)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.flush().await
+    }
+    pub fn gossip_clock(&self) -> Result<u64> {
+        Ok(self
+            .read_u64(GOSSIP_CLOCK_KEY)?
+            .max(self.read_u64(LEGACY_GOSSIP_VERSION_KEY)?))
+    }
+    pub async fn set_gossip_clock(&self, clock: u64) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        batch.insert(GOSSIP_CLOCK_KEY, &clock.to_be_bytes());
+        batch.remove(LEGACY_GOSSIP_VERSION_KEY);
+        self.db
+            .apply_batch(batch)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        self.flush().await
+    }
+    fn read_u64(&self, key: &[u8]) -> Result<u64> {
+        let value = self
+            .db
+            .get(key)
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(value
+            .and_then(|v| v.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(0))
+    }
+    async fn flush(&self) -> Result<()> {
+        self.db
+            .flush_async()
+            .await
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn node(id: &str, port: u16) -> NodeInfo {
+        NodeInfo {
+            id: NodeId::from_string(id),
+            address: format!("127.0.0.1:{}", port).parse().unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        }
+    }
+    #[tokio::test]
+    async fn test_peers_and_version_round_trip() {
+        let store = ClusterStateStore::in_memory().unwrap();
+        assert!(store
+            .load_peers(Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+        assert!(store.gossip_watermarks().unwrap().is_empty());
+        assert_eq!(store.gossip_clock().unwrap(), 0);
+        store
+            .save_peers(&[(node("a", 8080), Utc::now()), (node("b", 8081), Utc::now())])
+            .await
+            .unwrap();
+        store
+            .save_peers(&[
+                (node("b", 8081), Utc::now()),
+                (node("c", 8082), Utc::now() - chrono::Duration::hours(2)),
+            ])
+            .await
+            .unwrap();
+        let watermarks = HashMap::from([(NodeId::from_string("a"), 42)]);
+        store.set_gossip_watermarks(&watermarks).await.unwrap();
+        store.set_gossip_clock(1000).await.unwrap();
+        let peers = store.load_peers(Duration::from_secs(60)).unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id.0, "b");
+        assert_eq!(store.gossip_watermarks().unwrap(), watermarks);
+        assert_eq!(store.gossip_clock().unwrap(), 1000);
+    }
+    #[tokio::test]
+    async fn test_legacy_gossip_version_seeds_the_clock_until_replaced() {
+        let store = ClusterStateStore::in_memory().unwrap();
+        store
+            .db
+            .insert(
+                LEGACY_GOSSIP_VERSION_KEY,
+                &1_700_000_000_000u64.to_be_bytes(),
+            )
+            .unwrap();
+        assert_eq!(store.gossip_clock().unwrap(), 1_700_000_000_000);
+        store.set_gossip_clock(1_700_000_001_000).await.unwrap();
+        assert!(store.db.get(LEGACY_GOSSIP_VERSION_KEY).unwrap().is_none());
+        assert_eq!(store.gossip_clock().unwrap(), 1_700_000_001_000);
+    }
+    #[tokio::test]
+    async fn test_stale_peers_are_ignored() {
+        let store = ClusterStateStore::in_memory().unwrap();
+        let stale = PersistedPeer {
+            node: node("old", 8080),
+            last_seen: Utc::now() - chrono::Duration::hours(2),
+        };
+        store
+            .peers
+            .insert("old", serde_json::to_vec(&stale).unwrap())
+            .unwrap();
+        assert!(store
+            .load_peers(Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store
+                .load_peers(Duration::from_secs(3 * 3600))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
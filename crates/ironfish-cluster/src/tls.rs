@@ -0,0 +1,170 @@
+use ironfish_core::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+use x509_parser::extensions::GeneralName;
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipTlsConfig {
+    pub enabled: bool,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub ca_path: Option<PathBuf>,
+    pub mutual: bool,
+    pub allowed_peers: Vec<String>,
+    pub server_name: Option<String>,
+}
+#[derive(Clone)]
+pub struct GossipTls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+    allowed_peers: Arc<Vec<String>>,
+    mutual: bool,
+}
+impl GossipTls {
+    pub fn from_config(config: &GossipTlsConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let required = |path: &Option<PathBuf>, name: &str| {
+            path.clone()
+                .ok_or_else(|| Error::Config(format!("gossip tls requires {}", name)))
+        };
+        let certs = load_certs(&required(&config.cert_path, "cert_path")?)?;
+        let key = load_key(&required(&config.key_path, "key_path")?)?;
+        let mut roots = RootCertStore::empty();
+        for ca in load_certs(&required(&config.ca_path, "ca_path")?)? {
+            roots
+                .add(ca)
+                .map_err(|e| Error::Config(format!("invalid gossip CA certificate: {}", e)))?;
+        }
+        let roots = Arc::new(roots);
+        let provider = Arc::new(ring::default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Config(format!("gossip tls setup failed: {}", e)))?;
+        let server_config = if config.mutual {
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+                    .build()
+                    .map_err(|e| Error::Config(format!("invalid gossip CA: {}", e)))?;
+            server_config.with_client_cert_verifier(verifier)
+        } else {
+            server_config.with_no_client_auth()
+        }
+        .with_single_cert(certs.clone(), key.clone_key())
+        .map_err(|e| Error::Config(format!("invalid gossip certificate: {}", e)))?;
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Config(format!("gossip tls setup failed: {}", e)))?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .map_err(|e| Error::Config(format!("invalid gossip certificate: {}", e)))?;
+        let server_name = config
+            .server_name
+            .clone()
+            .map(ServerName::try_from)
+            .transpose()
+            .map_err(|e| Error::Config(format!("invalid gossip tls server name: {}", e)))?;
+        Ok(Some(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            connector: TlsConnector::from(Arc::new(client_config)),
+            server_name,
+            allowed_peers: Arc::new(config.allowed_peers.clone()),
+            mutual: config.mutual,
+        }))
+    }
+    pub(crate) async fn connect(
+        &self,
+        addr: SocketAddr,
+        stream: TcpStream,
+    ) -> Result<client::TlsStream<TcpStream>> {
+        let name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(addr.ip().into()));
+        self.connector
+            .connect(name, stream)
+            .await
+            .map_err(|e| Error::Network(format!("tls handshake with {} failed: {}", addr, e)))
+    }
+    pub(crate) async fn accept(&self, stream: TcpStream) -> Result<server::TlsStream<TcpStream>> {
+        let stream = self
+            .acceptor
+            .accept(stream)
+            .await
+            .map_err(|e| Error::Network(format!("tls handshake failed: {}", e)))?;
+        if self.mutual && !self.allowed_peers.is_empty() {
+            let identities = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(peer_identities)
+                .unwrap_or_default();
+            if !identities.iter().any(|id| self.allowed_peers.contains(id)) {
+                return Err(Error::Network(format!(
+                    "peer certificate {:?} is not in allowed_peers",
+                    identities
+                )));
+            }
+        }
+        Ok(stream)
+    }
+}
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path)
+        .map_err(|e| Error::Config(format!("failed to read {}: {}", path.display(), e)))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::Config(format!("invalid certificate {}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!(
+            "no certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+pub fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path)
+        .map_err(|e| Error::Config(format!("failed to read {}: {}", path.display(), e)))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| Error::Config(format!("invalid private key {}: {}", path.display(), e)))?
+        .ok_or_else(|| Error::Config(format!("no private key found in {}", path.display())))
+}
+fn peer_identities(cert: &CertificateDer<'_>) -> Vec<String> {
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert) else {
+        return Vec::new();
+    };
+    let mut identities: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok().map(String::from))
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => identities.push(dns.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    let ip = match bytes.len() {
+                        4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                        16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                        _ => None,
+                    };
+                    identities.extend(ip.map(|ip| ip.to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+    identities
+}
@@ -0,0 +1,564 @@
+use crate::network::NetworkMessage;
+use crate::tls::GossipTls;
+use bincode::Options;
+use ironfish_core::{Error, Result};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const MAX_CLOCK_SKEW_SECS: u64 = 30;
+const TIMESTAMP_LEN: usize = 8;
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+const BINARY_FORMAT_VERSION: u8 = 8;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Auto,
+    Binary,
+    Json,
+}
+pub(crate) trait GossipStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> GossipStream for T {}
+#[derive(Clone, Default)]
+pub struct GossipTransport {
+    tls: Option<GossipTls>,
+    key: Option<hmac::Key>,
+    format: WireFormat,
+    peer_formats: Arc<RwLock<HashMap<SocketAddr, WireFormat>>>,
+    seen: Arc<Mutex<SeenNonces>>,
+}
+#[derive(Default)]
+struct SeenNonces {
+    order: VecDeque<(u64, [u8; NONCE_LEN])>,
+    nonces: HashSet<[u8; NONCE_LEN]>,
+}
+impl SeenNonces {
+    fn insert(&mut self, sent_at: u64, nonce: [u8; NONCE_LEN], now: u64) -> bool {
+        while let Some(&(oldest, expired)) = self.order.front() {
+            if oldest + MAX_CLOCK_SKEW_SECS >= now {
+                break;
+            }
+            self.order.pop_front();
+            self.nonces.remove(&expired);
+        }
+        if !self.nonces.insert(nonce) {
+            return false;
+        }
+        self.order.push_back((sent_at, nonce));
+        true
+    }
+}
+impl GossipTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_tls(mut self, tls: Option<GossipTls>) -> Self {
+        self.tls = tls;
+        self
+    }
+    pub fn with_secret(mut self, secret: Option<&str>) -> Self {
+        self.key = secret
+            .filter(|s| !s.is_empty())
+            .map(|s| hmac::Key::new(hmac::HMAC_SHA256, s.as_bytes()));
+        self
+    }
+    pub fn with_wire_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+    pub fn wire_format(&self) -> WireFormat {
+        self.format
+    }
+    pub(crate) fn format_for(&self, addr: SocketAddr) -> WireFormat {
+        match self.format {
+            WireFormat::Auto => self
+                .peer_formats
+                .read()
+                .unwrap()
+                .get(&addr)
+                .copied()
+                .unwrap_or(WireFormat::Binary),
+            format => format,
+        }
+    }
+    pub(crate) fn record_format(&self, addr: SocketAddr, format: WireFormat) {
+        if self.format != WireFormat::Auto || format == WireFormat::Auto {
+            return;
+        }
+        let previous = self.peer_formats.write().unwrap().insert(addr, format);
+        if previous.is_some_and(|previous| previous != format) {
+            debug!("gossip peer {} now uses {:?} frames", addr, format);
+        }
+    }
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> Result<Box<dyn GossipStream>> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let stream = TcpStream::connect(addr)
+                .await
+                .map_err(|e| Error::Network(format!("connect error: {}", e)))?;
+            let stream: Box<dyn GossipStream> = match self.tls {
+                Some(ref tls) => Box::new(tls.connect(addr, stream).await?),
+                None => Box::new(stream),
+            };
+            Ok(stream)
+        })
+        .await
+        .map_err(|_| Error::Network("connection timeout".into()))?
+    }
+    pub(crate) async fn accept(&self, stream: TcpStream) -> Result<Box<dyn GossipStream>> {
+        match self.tls {
+            Some(ref tls) => Ok(Box::new(tls.accept(stream).await?)),
+            None => Ok(Box::new(stream)),
+        }
+    }
+    pub(crate) async fn write_frame_as<S: AsyncWrite + Unpin + ?Sized>(
+        &self,
+        stream: &mut S,
+        message: &NetworkMessage,
+        format: WireFormat,
+    ) -> Result<()> {
+        let data = self.seal(encode(message, format)?);
+        let len = (data.len() as u32).to_be_bytes();
+        stream
+            .write_all(&len)
+            .await
+            .map_err(|e| Error::Network(format!("write error: {}", e)))?;
+        stream
+            .write_all(&data)
+            .await
+            .map_err(|e| Error::Network(format!("write error: {}", e)))?;
+        stream
+            .flush()
+            .await
+            .map_err(|e| Error::Network(format!("write error: {}", e)))?;
+        Ok(())
+    }
+    pub(crate) async fn read_frame<S: AsyncRead + Unpin + ?Sized>(
+        &self,
+        stream: &mut S,
+    ) -> Result<Option<NetworkMessage>> {
+        Ok(self
+            .read_frame_with_format(stream)
+            .await?
+            .map(|(message, _)| message))
+    }
+    pub(crate) async fn read_frame_with_format<S: AsyncRead + Unpin + ?Sized>(
+        &self,
+        stream: &mut S,
+    ) -> Result<Option<(NetworkMessage, WireFormat)>> {
+        let mut len_buf = [0u8; 4];
+        match stream.read_exact(&mut len_buf).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(Error::Network(format!("read error: {}", e))),
+        }
+        let len = u32::from_be_bytes(len_buf) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(Error::Network("message too large".into()));
+        }
+        let mut buf = vec![0u8; len];
+        stream
+            .read_exact(&mut buf)
+            .await
+            .map_err(|e| Error::Network(format!("read error: {}", e)))?;
+        let payload = self.open(&buf)?;
+        decode(payload).map(Some)
+    }
+    fn seal(&self, payload: Vec<u8>) -> Vec<u8> {
+        let Some(ref key) = self.key else {
+            return payload;
+        };
+        let timestamp = unix_now().to_be_bytes();
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("system randomness is available");
+        let mut context = hmac::Context::with_key(key);
+        context.update(&timestamp);
+        context.update(&nonce);
+        context.update(&payload);
+        let tag = context.sign();
+        let mut frame = Vec::with_capacity(TIMESTAMP_LEN + NONCE_LEN + TAG_LEN + payload.len());
+        frame.extend_from_slice(&timestamp);
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(tag.as_ref());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+    fn open<'a>(&self, frame: &'a [u8]) -> Result<&'a [u8]> {
+        let Some(ref key) = self.key else {
+            return Ok(frame);
+        };
+        if frame.len() < TIMESTAMP_LEN + NONCE_LEN + TAG_LEN {
+            debug!("gossip frame too short for a cluster signature");
+            return Err(Error::Unauthorized);
+        }
+        let (timestamp, rest) = frame.split_at(TIMESTAMP_LEN);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (tag, payload) = rest.split_at(TAG_LEN);
+        let sent_at = u64::from_be_bytes(timestamp.try_into().expect("8-byte timestamp"));
+        let now = unix_now();
+        if now.abs_diff(sent_at) > MAX_CLOCK_SKEW_SECS {
+            debug!(
+                "gossip frame timestamp {} outside the replay window",
+                sent_at
+            );
+            return Err(Error::Unauthorized);
+        }
+        hmac::verify(key, &[timestamp, nonce, payload].concat(), tag).map_err(|_| {
+            debug!("gossip frame signature mismatch");
+            Error::Unauthorized
+        })?;
+        let nonce = nonce.try_into().expect("16-byte nonce");
+        if !self.seen.lock().unwrap().insert(sent_at, nonce, now) {
+            debug!("gossip frame replayed within the replay window");
+            return Err(Error::Unauthorized);
+        }
+        Ok(payload)
+    }
+}
+fn binary_options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(MAX_MESSAGE_SIZE as u64)
+}
+fn encode(message: &NetworkMessage, format: WireFormat) -> Result<Vec<u8>> {
+    match format {
+        WireFormat::Auto | WireFormat::Binary => {
+            let mut data = vec![BINARY_FORMAT_VERSION];
+            binary_options()
+                .serialize_into(&mut data, message)
+                .map_err(|e| Error::Network(format!("serialize error: {}", e)))?;
+            Ok(data)
+        }
+        WireFormat::Json => serde_json::to_vec(message)
+            .map_err(|e| Error::Network(format!("serialize error: {}", e))),
+    }
+}
+fn decode(payload: &[u8]) -> Result<(NetworkMessage, WireFormat)> {
+    match payload.first() {
+        Some(&BINARY_FORMAT_VERSION) => binary_options()
+            .deserialize(&payload[1..])
+            .map(|message| (message, WireFormat::Binary))
+            .map_err(|e| Error::Network(format!("deserialize error: {}", e))),
+        Some(b'{') | Some(b'"') => serde_json::from_slice(payload)
+            .map(|message| (message, WireFormat::Json))
+            .map_err(|e| Error::Network(format!("deserialize error: {}", e))),
+        Some(version) => Err(Error::Network(format!(
+            "unsupported wire format version {}",
+            version
+        ))),
+        None => Err(Error::Network("empty gossip frame".into())),
+    }
+}
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::GossipEnvelope;
+    use chrono::{DateTime, Utc};
+    use ironfish_core::{
+        ApiToken, GossipMessage, HeartbeatRequest, HeartbeatResponse, NodeBucket, NodeId, NodeInfo,
+        NodeMetrics, NodeWindow, TokenReplicaStatus, VoteRequest, VoteResponse,
+    };
+    use std::collections::HashMap;
+    fn node() -> NodeInfo {
+        NodeInfo {
+            id: NodeId::from_string("node-a"),
+            address: "127.0.0.1:8080".parse().unwrap(),
+            priority: 100,
+            started_at: at(),
+            version: "test".to_string(),
+        }
+    }
+    fn at() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+    fn token() -> ApiToken {
+        ApiToken {
+            id: uuid::Uuid::from_u128(1),
+            name: Some("ci".to_string()),
+            token_hash: "a".repeat(64),
+            created_at: at(),
+            expires_at: None,
+            last_used_at: Some(at()),
+            created_by_node: "node-a".to_string(),
+            revoked: false,
+            rate_limit: Some(60),
+            scopes: vec!["analyze".to_string()],
+            usage_count: 3,
+            last_used_ip: None,
+            daily_usage: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: Some(1_000_000),
+            node_window: Some(NodeWindow {
+                buckets: vec![NodeBucket {
+                    started_at: at(),
+                    nodes: 42,
+                }],
+            }),
+            revoked_at: None,
+        }
+    }
+    fn envelope() -> GossipEnvelope {
+        GossipEnvelope {
+            id: uuid::Uuid::from_u128(2),
+            ..GossipEnvelope::new(GossipMessage::TokenCreated(token()), node().id, 1)
+        }
+    }
+    fn variant(message: &NetworkMessage) -> &'static str {
+        match message {
+            NetworkMessage::Gossip(_) => "gossip",
+            NetworkMessage::SyncRequest { .. } => "sync_request",
+            NetworkMessage::SyncResponse { .. } => "sync_response",
+            NetworkMessage::Ping => "ping",
+            NetworkMessage::Pong => "pong",
+            NetworkMessage::DiscoveryRequest => "discovery_request",
+            NetworkMessage::DiscoveryResponse { .. } => "discovery_response",
+            NetworkMessage::VoteRequest(_) => "vote_request",
+            NetworkMessage::VoteResponse(_) => "vote_response",
+            NetworkMessage::Heartbeat(_) => "heartbeat",
+            NetworkMessage::HeartbeatResponse(_) => "heartbeat_response",
+            NetworkMessage::BullyElection { .. } => "bully_election",
+            NetworkMessage::BullyCoordinator { .. } => "bully_coordinator",
+            NetworkMessage::BullyAnswer { .. } => "bully_answer",
+            NetworkMessage::TokenStatusRequest { .. } => "token_status_request",
+            NetworkMessage::TokenStatusResponse { .. } => "token_status_response",
+            NetworkMessage::SyncSince { .. } => "sync_since",
+            NetworkMessage::TokenStatusFailed { .. } => "token_status_failed",
+        }
+    }
+    fn messages() -> Vec<NetworkMessage> {
+        let id = node().id;
+        vec![
+            NetworkMessage::Gossip(Box::new(GossipEnvelope {
+                relayed_by: Some(node().id),
+                ..envelope()
+            })),
+            NetworkMessage::SyncRequest { from_version: 42 },
+            NetworkMessage::SyncSince {
+                since: HashMap::from([(node().id, 42)]),
+            },
+            NetworkMessage::SyncResponse {
+                entries: vec![
+                    envelope(),
+                    GossipEnvelope {
+                        id: uuid::Uuid::from_u128(3),
+                        ..GossipEnvelope::new(
+                            GossipMessage::NodeMetrics(id.clone(), NodeMetrics::default()),
+                            id.clone(),
+                            2,
+                        )
+                    },
+                    GossipEnvelope {
+                        id: uuid::Uuid::from_u128(4),
+                        ..GossipEnvelope::new(
+                            GossipMessage::NodeDraining(id.clone(), true),
+                            id.clone(),
+                            3,
+                        )
+                    },
+                ],
+            },
+            NetworkMessage::Ping,
+            NetworkMessage::Pong,
+            NetworkMessage::DiscoveryRequest,
+            NetworkMessage::DiscoveryResponse {
+                nodes: vec![node()],
+                node: Some(node()),
+            },
+            NetworkMessage::VoteRequest(VoteRequest {
+                candidate_id: id.clone(),
+                term: 7,
+                priority: 100,
+            }),
+            NetworkMessage::VoteResponse(VoteResponse {
+                node_id: id.clone(),
+                term: 7,
+                vote_granted: true,
+            }),
+            NetworkMessage::Heartbeat(HeartbeatRequest {
+                leader_id: id.clone(),
+                term: 7,
+                commit_index: 3,
+            }),
+            NetworkMessage::HeartbeatResponse(HeartbeatResponse {
+                node_id: id.clone(),
+                term: 7,
+                success: true,
+                metrics: NodeMetrics::default(),
+            }),
+            NetworkMessage::BullyElection {
+                candidate_id: id.clone(),
+                priority: 100,
+            },
+            NetworkMessage::BullyCoordinator {
+                leader_id: id.clone(),
+                priority: 100,
+                term: 7,
+            },
+            NetworkMessage::BullyAnswer {
+                node_id: id,
+                ok: true,
+                term: 7,
+            },
+            NetworkMessage::TokenStatusRequest { id: token().id },
+            NetworkMessage::TokenStatusResponse {
+                status: TokenReplicaStatus::Revoked,
+            },
+            NetworkMessage::TokenStatusFailed {
+                error: "storage unavailable".to_string(),
+            },
+        ]
+    }
+    async fn round_trip(
+        writer: &GossipTransport,
+        reader: &GossipTransport,
+        message: &NetworkMessage,
+    ) -> (NetworkMessage, WireFormat, usize) {
+        let mut frame = Vec::new();
+        writer
+            .write_frame_as(&mut frame, message, writer.wire_format())
+            .await
+            .unwrap();
+        let (decoded, format) = reader
+            .read_frame_with_format(&mut frame.as_slice())
+            .await
+            .unwrap()
+            .unwrap();
+        (decoded, format, frame.len())
+    }
+    #[tokio::test]
+    async fn test_every_message_round_trips() {
+        let messages = messages();
+        let variants: std::collections::HashSet<_> = messages.iter().map(variant).collect();
+        assert_eq!(variants.len(), messages.len());
+        for secret in [None, Some("cluster-secret")] {
+            let binary = GossipTransport::new().with_secret(secret);
+            let json = binary.clone().with_wire_format(WireFormat::Json);
+            for message in &messages {
+                let expected = serde_json::to_value(message).unwrap();
+                let (decoded, format, binary_len) = round_trip(&binary, &binary, message).await;
+                assert_eq!(format, WireFormat::Binary, "{}", variant(message));
+                assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+                let (decoded, format, json_len) = round_trip(&json, &binary, message).await;
+                assert_eq!(format, WireFormat::Json, "{}", variant(message));
+                assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+                assert!(binary_len <= json_len, "{}", variant(message));
+            }
+        }
+    }
+    #[tokio::test]
+    async fn test_reads_legacy_json_frames() {
+        let message = NetworkMessage::Gossip(Box::new(envelope()));
+        let mut payload = serde_json::to_value(&message).unwrap();
+        payload["Gossip"]
+            .as_object_mut()
+            .unwrap()
+            .remove("relayed_by");
+        let payload = serde_json::to_vec(&payload).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+        let transport = GossipTransport::new();
+        let (decoded, format) = transport
+            .read_frame_with_format(&mut frame.as_slice())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(format, WireFormat::Json);
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&message).unwrap()
+        );
+        let frame = [0, 0, 0, 2, 9, 0];
+        let err = transport
+            .read_frame(&mut frame.as_slice())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("wire format version 9"), "{}", err);
+    }
+    #[test]
+    fn test_binary_schema_matches_format_version() {
+        let mut digest = ring::digest::Context::new(&ring::digest::SHA256);
+        for message in messages() {
+            digest.update(&encode(&message, WireFormat::Binary).unwrap());
+        }
+        let fingerprint: String = digest
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            (BINARY_FORMAT_VERSION, fingerprint.as_str()),
+            (
+                8,
+                "fa0e8ef0f33ed906bfd9e943ee9b217c18ab6e840765b6e338fa21214caf7a51"
+            ),
+            "the binary encoding changed: bump BINARY_FORMAT_VERSION and record the new fingerprint"
+        );
+    }
+    #[tokio::test]
+    async fn test_rejects_replayed_frames() {
+        let transport = GossipTransport::new().with_secret(Some("cluster-secret"));
+        let mut frame = Vec::new();
+        transport
+            .write_frame_as(&mut frame, &NetworkMessage::Ping, WireFormat::Binary)
+            .await
+            .unwrap();
+        let message = transport.read_frame(&mut frame.as_slice()).await.unwrap();
+        assert!(matches!(message, Some(NetworkMessage::Ping)));
+        let replay = transport.read_frame(&mut frame.as_slice()).await;
+        assert!(matches!(replay, Err(Error::Unauthorized)));
+        let receiver = transport.clone();
+        let mut fresh = Vec::new();
+        transport
+            .write_frame_as(&mut fresh, &NetworkMessage::Ping, WireFormat::Binary)
+            .await
+            .unwrap();
+        assert_ne!(frame, fresh);
+        assert!(receiver.read_frame(&mut fresh.as_slice()).await.is_ok());
+        assert!(matches!(
+            receiver.read_frame(&mut fresh.as_slice()).await,
+            Err(Error::Unauthorized)
+        ));
+    }
+    #[test]
+    fn test_seen_nonces_expire_with_the_clock_window() {
+        let mut seen = SeenNonces::default();
+        assert!(seen.insert(100, [1; NONCE_LEN], 100));
+        assert!(!seen.insert(100, [1; NONCE_LEN], 110));
+        assert!(seen.insert(131, [2; NONCE_LEN], 131));
+        assert_eq!(seen.nonces.len(), 1);
+        assert!(!seen.nonces.contains(&[1; NONCE_LEN]));
+    }
+    #[test]
+    fn test_negotiated_format_per_peer() {
+        let peer: SocketAddr = "127.0.0.1:8180".parse().unwrap();
+        let auto = GossipTransport::new();
+        assert_eq!(auto.format_for(peer), WireFormat::Binary);
+        auto.record_format(peer, WireFormat::Json);
+        assert_eq!(auto.format_for(peer), WireFormat::Json);
+        assert_eq!(
+            auto.format_for("127.0.0.1:8181".parse().unwrap()),
+            WireFormat::Binary
+        );
+        auto.record_format(peer, WireFormat::Binary);
+        assert_eq!(auto.format_for(peer), WireFormat::Binary);
+        let json = GossipTransport::new().with_wire_format(WireFormat::Json);
+        json.record_format(peer, WireFormat::Binary);
+        assert_eq!(json.format_for(peer), WireFormat::Json);
+    }
+}
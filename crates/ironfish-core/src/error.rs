@@ -3,14 +3,30 @@ use thiserror::Error;
 pub enum Error {
     #[error("invalid FEN: {0}")]
     InvalidFen(String),
+    #[error("castling rights '{0}' are only valid in chess960; set variant to \"chess960\"")]
+    VariantRequired(String),
+    #[error("invalid move {notation:?} at index {index}")]
+    InvalidMove { index: usize, notation: String },
+    #[error("illegal move: {0}")]
+    IllegalMove(String),
+    #[error("invalid search moves: {}", .0.join(", "))]
+    InvalidSearchMoves(Vec<String>),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
     #[error("engine error: {0}")]
     Engine(String),
     #[error("engine pool exhausted")]
     PoolExhausted,
+    #[error("all engines are busy")]
+    EngineBusy,
     #[error("analysis timeout")]
     AnalysisTimeout,
     #[error("analysis cancelled")]
     AnalysisCancelled,
+    #[error("server is shutting down")]
+    ShuttingDown,
+    #[error("node is draining and not accepting new analyses")]
+    NodeDraining,
     #[error("invalid token")]
     InvalidToken,
     #[error("token expired")]
@@ -19,12 +35,22 @@ pub enum Error {
     TokenNotFound,
     #[error("unauthorized")]
     Unauthorized,
+    #[error("unknown token scope: {0}")]
+    InvalidScope(String),
     #[error("rate limit exceeded")]
     RateLimitExceeded,
+    #[error("too many concurrent analyses, retry in {retry_after_secs}s")]
+    TooManyAnalyses { retry_after_secs: u64 },
+    #[error("hourly node budget exhausted, resets at {}", .resets_at.to_rfc3339())]
+    NodeBudgetExhausted {
+        resets_at: chrono::DateTime<chrono::Utc>,
+    },
     #[error("node not found: {0}")]
     NodeNotFound(String),
     #[error("not leader")]
     NotLeader,
+    #[error("no cluster leader is known")]
+    NoLeader,
     #[error("cluster unavailable")]
     ClusterUnavailable,
     #[error("consensus error: {0}")]
@@ -37,6 +63,8 @@ pub enum Error {
     Network(String),
     #[error("storage error: {0}")]
     Storage(String),
+    #[error("token store is read-only")]
+    StoreReadOnly,
     #[error("configuration error: {0}")]
     Config(String),
     #[error("io error: {0}")]
@@ -46,4 +74,73 @@ pub enum Error {
     #[error("internal error: {0}")]
     Internal(String),
 }
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidFen(_) => "invalid_fen",
+            Error::VariantRequired(_) => "variant_required",
+            Error::InvalidMove { .. } => "invalid_move",
+            Error::IllegalMove(_) => "illegal_move",
+            Error::InvalidSearchMoves(_) => "invalid_search_moves",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::Engine(_) => "engine_error",
+            Error::PoolExhausted => "engine_unavailable",
+            Error::EngineBusy => "engine_busy",
+            Error::AnalysisTimeout => "analysis_timeout",
+            Error::AnalysisCancelled => "analysis_cancelled",
+            Error::ShuttingDown => "shutting_down",
+            Error::NodeDraining => "node_draining",
+            Error::InvalidToken => "invalid_token",
+            Error::TokenExpired => "token_expired",
+            Error::TokenNotFound => "token_not_found",
+            Error::Unauthorized => "unauthorized",
+            Error::InvalidScope(_) => "invalid_scope",
+            Error::RateLimitExceeded => "rate_limited",
+            Error::TooManyAnalyses { .. } => "too_many_analyses",
+            Error::NodeBudgetExhausted { .. } => "node_budget_exhausted",
+            Error::NodeNotFound(_) => "node_not_found",
+            Error::NotLeader => "not_leader",
+            Error::NoLeader => "no_leader",
+            Error::ClusterUnavailable => "cluster_unavailable",
+            Error::Consensus(_) => "consensus",
+            Error::Discovery(_) => "discovery",
+            Error::Gossip(_) => "gossip",
+            Error::Network(_) => "network",
+            Error::Storage(_) => "storage",
+            Error::StoreReadOnly => "store_read_only",
+            Error::Config(_) => "config",
+            Error::Io(_) => "io",
+            Error::Serialization(_) => "serialization",
+            Error::Internal(_) => "internal",
+        }
+    }
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Error::InvalidFen(_)
+            | Error::VariantRequired(_)
+            | Error::InvalidMove { .. }
+            | Error::IllegalMove(_)
+            | Error::InvalidSearchMoves(_)
+            | Error::InvalidArgument(_)
+            | Error::InvalidScope(_)
+            | Error::Serialization(_) => 400,
+            Error::InvalidToken | Error::TokenExpired | Error::Unauthorized => 401,
+            Error::TokenNotFound | Error::NodeNotFound(_) => 404,
+            Error::AnalysisTimeout => 408,
+            Error::AnalysisCancelled => 409,
+            Error::RateLimitExceeded
+            | Error::TooManyAnalyses { .. }
+            | Error::NodeBudgetExhausted { .. } => 429,
+            Error::PoolExhausted
+            | Error::EngineBusy
+            | Error::ShuttingDown
+            | Error::NodeDraining
+            | Error::NotLeader
+            | Error::NoLeader
+            | Error::ClusterUnavailable
+            | Error::StoreReadOnly => 503,
+            _ => 500,
+        }
+    }
+}
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,6 +1,8 @@
 pub mod error;
+pub mod telemetry;
 pub mod traits;
 pub mod types;
 pub use error::{Error, Result};
+pub use telemetry::Telemetry;
 pub use traits::*;
 pub use types::*;
@@ -0,0 +1,98 @@
+use crate::Error;
+use metrics::{counter, gauge, histogram, Recorder};
+use std::sync::Arc;
+use std::time::Duration;
+#[derive(Clone, Default)]
+pub struct Telemetry {
+    recorder: Option<Arc<dyn Recorder + Send + Sync>>,
+}
+impl Telemetry {
+    pub fn new(recorder: Arc<dyn Recorder + Send + Sync>) -> Self {
+        Self {
+            recorder: Some(recorder),
+        }
+    }
+    fn record(&self, f: impl FnOnce()) {
+        if let Some(ref recorder) = self.recorder {
+            metrics::with_local_recorder(recorder.as_ref(), f);
+        }
+    }
+    pub fn analysis_finished(&self, duration: Duration, error: Option<&Error>) {
+        self.record(|| {
+            counter!("ironfish_analyses_total").increment(1);
+            histogram!("ironfish_analysis_duration_seconds").record(duration.as_secs_f64());
+        });
+        if let Some(e) = error {
+            self.error(e.code());
+        }
+    }
+    pub fn bestmove_finished(&self, error: Option<&Error>) {
+        self.record(|| counter!("ironfish_bestmove_requests_total").increment(1));
+        if let Some(e) = error {
+            self.error(e.code());
+        }
+    }
+    pub fn cache_lookup(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.record(|| counter!("ironfish_analysis_cache_total", "result" => result).increment(1));
+    }
+    pub fn error(&self, kind: &'static str) {
+        self.record(|| counter!("ironfish_errors_total", "type" => kind).increment(1));
+    }
+    pub fn gossip_message(&self, kind: &'static str) {
+        self.record(|| counter!("ironfish_gossip_messages_total", "type" => kind).increment(1));
+    }
+    pub fn gossip_duplicate(&self) {
+        self.record(|| counter!("ironfish_gossip_duplicates_total").increment(1));
+    }
+    pub fn gossip_rejected(&self) {
+        self.record(|| counter!("ironfish_gossip_rejected_total").increment(1));
+    }
+    pub fn request_finished(&self, protocol: &'static str, status: u16, duration: Duration) {
+        self.record(|| {
+            histogram!(
+                "ironfish_request_duration_seconds",
+                "protocol" => protocol,
+                "status" => status.to_string()
+            )
+            .record(duration.as_secs_f64());
+        });
+    }
+    pub fn ws_session_opened(&self) {
+        self.record(|| gauge!("ironfish_ws_sessions").increment(1.0));
+    }
+    pub fn ws_session_closed(&self) {
+        self.record(|| gauge!("ironfish_ws_sessions").decrement(1.0));
+    }
+    pub fn ws_session_rejected(&self, reason: &'static str) {
+        self.record(|| {
+            counter!("ironfish_ws_sessions_rejected_total", "reason" => reason).increment(1)
+        });
+    }
+    pub fn ws_message_dropped(&self) {
+        self.record(|| counter!("ironfish_ws_dropped_messages_total").increment(1));
+    }
+    pub fn ws_slow_consumer_disconnected(&self) {
+        self.record(|| counter!("ironfish_ws_slow_consumer_disconnects_total").increment(1));
+    }
+    pub fn set_engines(&self, available: u32, total: u32) {
+        self.record(|| {
+            gauge!("ironfish_engines_available").set(available as f64);
+            gauge!("ironfish_engines_total").set(total as f64);
+        });
+    }
+    pub fn set_engine_partitions(&self, reserved_available: u32, general_available: u32) {
+        self.record(|| {
+            gauge!("ironfish_engines_partition_available", "partition" => "reserved")
+                .set(reserved_available as f64);
+            gauge!("ironfish_engines_partition_available", "partition" => "general")
+                .set(general_available as f64);
+        });
+    }
+    pub fn set_cluster(&self, peers: usize, term: u64) {
+        self.record(|| {
+            gauge!("ironfish_cluster_peers").set(peers as f64);
+            gauge!("ironfish_cluster_term").set(term as f64);
+        });
+    }
+}
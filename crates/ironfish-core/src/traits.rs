@@ -16,7 +16,77 @@ pub trait TokenStore: Send + Sync {
     async fn update(&self, token: ApiToken) -> Result<()>;
     async fn delete(&self, id: &uuid::Uuid) -> Result<()>;
     async fn list(&self) -> Result<Vec<ApiToken>>;
+    async fn list_page(&self, query: &TokenListQuery) -> Result<TokenPage> {
+        Ok(query.paginate(self.list().await?))
+    }
     async fn revoke(&self, id: &uuid::Uuid) -> Result<()>;
+    fn health(&self) -> StoreHealth {
+        StoreHealth::Healthy
+    }
+    async fn recover(&self) -> Result<StoreHealth> {
+        Ok(self.health())
+    }
+    async fn record_usage(&self, id: &uuid::Uuid, delta: &UsageDelta) -> Result<()> {
+        if let Some(mut token) = self.get(id).await? {
+            token.apply_usage(delta);
+            self.update(token).await?;
+        }
+        Ok(())
+    }
+    async fn reserve_nodes(
+        &self,
+        id: &uuid::Uuid,
+        budget: u64,
+        nodes: u64,
+    ) -> Result<NodeReservation> {
+        let now = chrono::Utc::now();
+        let Some(mut token) = self.get(id).await? else {
+            return NodeWindow::default().reserve(now, budget, nodes);
+        };
+        let mut window = token.node_window.take().unwrap_or_default();
+        let reservation = window.reserve(now, budget, nodes)?;
+        token.node_window = Some(window);
+        self.update(token).await?;
+        Ok(reservation)
+    }
+    async fn settle_nodes(
+        &self,
+        id: &uuid::Uuid,
+        reservation: &NodeReservation,
+        nodes: u64,
+    ) -> Result<()> {
+        if let Some(mut token) = self.get(id).await? {
+            token
+                .node_window
+                .get_or_insert_with(NodeWindow::default)
+                .settle(chrono::Utc::now(), reservation, nodes);
+            self.update(token).await?;
+        }
+        Ok(())
+    }
+    async fn purge(&self, retention: &TokenRetention) -> Result<usize> {
+        let now = chrono::Utc::now();
+        let mut purged = 0;
+        for token in self.list().await? {
+            if retention.is_purgeable(&token, now) {
+                self.delete(&token.id).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
+#[async_trait]
+pub trait AuditLog: Send + Sync {
+    async fn record(&self, entry: AuditEntry) -> Result<()>;
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage>;
+    async fn prune(&self) -> Result<usize>;
+}
+#[async_trait]
+pub trait AnalysisHistory: Send + Sync {
+    async fn record(&self, record: AnalysisRecord) -> Result<()>;
+    async fn query(&self, query: &HistoryQuery) -> Result<HistoryPage>;
+    async fn prune(&self) -> Result<usize>;
 }
 #[async_trait]
 pub trait ClusterDiscovery: Send + Sync {
@@ -37,6 +107,9 @@ pub trait ConsensusProtocol: Send + Sync {
 #[async_trait]
 pub trait LoadBalancer: Send + Sync {
     async fn select_node(&self, exclude: &[NodeId]) -> Result<NodeId>;
+    async fn select_node_for_key(&self, _key: &str, exclude: &[NodeId]) -> Result<NodeId> {
+        self.select_node(exclude).await
+    }
     async fn update_metrics(&self, node_id: &NodeId, metrics: NodeMetrics) -> Result<()>;
     async fn mark_unhealthy(&self, node_id: &NodeId) -> Result<()>;
     async fn mark_healthy(&self, node_id: &NodeId) -> Result<()>;
@@ -55,4 +128,18 @@ pub enum GossipMessage {
     NodeJoined(NodeInfo),
     NodeLeft(NodeId),
     NodeMetrics(NodeId, NodeMetrics),
+    NodeDraining(NodeId, bool),
+}
+impl GossipMessage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GossipMessage::TokenCreated(_) => "token_created",
+            GossipMessage::TokenRevoked(_) => "token_revoked",
+            GossipMessage::TokenUpdated(_) => "token_updated",
+            GossipMessage::NodeJoined(_) => "node_joined",
+            GossipMessage::NodeLeft(_) => "node_left",
+            GossipMessage::NodeMetrics(..) => "node_metrics",
+            GossipMessage::NodeDraining(..) => "node_draining",
+        }
+    }
 }
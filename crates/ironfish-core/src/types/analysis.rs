@@ -1,4 +1,5 @@
-use super::Move;
+use super::{ChessPosition, Color, Move, Score, Variant};
+use crate::error::{Error, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -9,6 +10,26 @@ pub struct AnalysisRequest {
     pub depth: u8,
     pub multipv: u8,
     pub movetime: Option<u64>,
+    #[serde(default)]
+    pub nodes: Option<u64>,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default)]
+    pub skill_level: Option<u8>,
+    #[serde(default)]
+    pub moves: Vec<String>,
+    #[serde(default)]
+    pub search_moves: Vec<String>,
+    #[serde(default)]
+    pub infinite: bool,
+    #[serde(default)]
+    pub perspective: Perspective,
+    #[serde(default)]
+    pub notation: Notation,
+    #[serde(default)]
+    pub clamped: bool,
+    #[serde(default)]
+    pub include_history: bool,
 }
 impl AnalysisRequest {
     pub fn new(fen: impl Into<String>) -> Self {
@@ -18,6 +39,23 @@ impl AnalysisRequest {
             depth: 20,
             multipv: 1,
             movetime: None,
+            nodes: None,
+            variant: Variant::Standard,
+            skill_level: None,
+            moves: Vec::new(),
+            search_moves: Vec::new(),
+            infinite: false,
+            perspective: Perspective::White,
+            notation: Notation::Uci,
+            clamped: false,
+            include_history: false,
+        }
+    }
+    pub fn infinite(fen: impl Into<String>) -> Self {
+        Self {
+            depth: 0,
+            infinite: true,
+            ..Self::new(fen)
         }
     }
     pub fn with_depth(mut self, depth: u8) -> Self {
@@ -32,52 +70,391 @@ impl AnalysisRequest {
         self.movetime = Some(ms);
         self
     }
+    pub fn with_nodes(mut self, nodes: u64) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+    pub fn with_variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+    pub fn with_skill_level(mut self, level: u8) -> Self {
+        self.skill_level = Some(level);
+        self
+    }
+    pub fn with_moves(mut self, moves: Vec<String>) -> Self {
+        self.moves = moves;
+        self
+    }
+    pub fn with_search_moves(mut self, moves: Vec<String>) -> Self {
+        self.search_moves = moves;
+        self
+    }
+    pub fn with_perspective(mut self, perspective: Perspective) -> Self {
+        self.perspective = perspective;
+        self
+    }
+    pub fn with_notation(mut self, notation: Notation) -> Self {
+        self.notation = notation;
+        self
+    }
+    pub fn with_history(mut self, include: bool) -> Self {
+        self.include_history = include;
+        self
+    }
+    pub fn validate(&self) -> Result<ChessPosition> {
+        let position = ChessPosition::new(&self.fen);
+        position.validate_for(self.variant)?;
+        let position = position.apply_uci_moves(&self.moves)?;
+        let invalid: Vec<String> = self
+            .search_moves
+            .iter()
+            .filter(|uci| {
+                !Move::from_uci(uci)
+                    .filter(|_| uci.len() <= 5)
+                    .is_some_and(|mv| position.is_legal(&mv))
+            })
+            .cloned()
+            .collect();
+        if !invalid.is_empty() {
+            return Err(Error::InvalidSearchMoves(invalid));
+        }
+        Ok(position)
+    }
+    pub fn searches_move(&self, mv: &Move) -> bool {
+        self.search_moves.is_empty() || self.search_moves.iter().any(|m| *m == mv.to_uci())
+    }
+    pub fn flips_scores(&self, position: &ChessPosition) -> bool {
+        self.perspective == Perspective::White && position.side_to_move() == Some(Color::Black)
+    }
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Perspective {
+    #[default]
+    White,
+    SideToMove,
+}
+impl std::str::FromStr for Perspective {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "white" => Ok(Perspective::White),
+            "side_to_move" => Ok(Perspective::SideToMove),
+            other => Err(format!(
+                "unknown perspective '{}', expected \"white\" or \"side_to_move\"",
+                other
+            )),
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Notation {
+    #[default]
+    Uci,
+    San,
+    Both,
+}
+impl std::str::FromStr for Notation {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "uci" => Ok(Notation::Uci),
+            "san" => Ok(Notation::San),
+            "both" => Ok(Notation::Both),
+            other => Err(format!(
+                "unknown notation '{}', expected \"uci\", \"san\" or \"both\"",
+                other
+            )),
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisLimits {
+    pub max_depth: u32,
+    pub max_multipv: u32,
+    pub min_movetime_ms: u64,
+    pub max_movetime_ms: u64,
+    pub max_concurrent_analyses: u32,
+    pub max_game_plies: u32,
+    pub max_game_concurrency: u32,
+}
+impl Default for AnalysisLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 30,
+            max_multipv: 10,
+            min_movetime_ms: 10,
+            max_movetime_ms: 60_000,
+            max_concurrent_analyses: 256,
+            max_game_plies: 600,
+            max_game_concurrency: 4,
+        }
+    }
+}
+impl AnalysisLimits {
+    pub fn check(&self, depth: u32, multipv: u32, movetime: Option<u64>) -> Result<()> {
+        check_range("depth", depth.into(), 1, self.max_depth.into())?;
+        self.check_multipv(multipv)?;
+        match movetime {
+            Some(ms) => check_range("movetime", ms, self.min_movetime_ms, self.max_movetime_ms),
+            None => Ok(()),
+        }
+    }
+    pub fn check_search(&self, movetime: Option<u64>, nodes: Option<u64>) -> Result<()> {
+        if let Some(ms) = movetime {
+            check_range("movetime", ms, self.min_movetime_ms, self.max_movetime_ms)?;
+        }
+        match nodes {
+            Some(nodes) => check_range("nodes", nodes, 1, u64::MAX),
+            None => Ok(()),
+        }
+    }
+    pub fn check_game(&self, plies: usize) -> Result<()> {
+        check_range("moves", plies as u64, 1, self.max_game_plies.into())
+    }
+    pub fn check_multipv(&self, multipv: u32) -> Result<()> {
+        check_range("multipv", multipv.into(), 1, self.max_multipv.into())
+    }
+    pub fn default_multipv(&self, search_moves: &[String]) -> u8 {
+        search_moves
+            .len()
+            .clamp(1, self.max_multipv.clamp(1, u8::MAX.into()) as usize) as u8
+    }
+}
+fn check_range(name: &str, value: u64, min: u64, max: u64) -> Result<()> {
+    if value < min || value > max {
+        return Err(Error::InvalidArgument(format!(
+            "{} must be between {} and {}, got {}",
+            name, min, max, value
+        )));
+    }
+    Ok(())
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AnalysisResult {
     pub id: Uuid,
     pub fen: String,
-    pub best_move: Move,
+    #[serde(default)]
+    pub best_move: Option<Move>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_over: Option<GameOver>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_move_san: Option<String>,
     pub ponder: Option<Move>,
-    pub evaluation: Evaluation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ponder_san: Option<String>,
+    pub evaluation: Score,
+    #[serde(default)]
+    pub wdl: Option<Wdl>,
     pub principal_variations: Vec<PrincipalVariation>,
     pub depth_reached: u8,
     pub nodes_searched: u64,
     pub time_ms: u64,
+    #[serde(default)]
+    pub tbhits: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tablebase: Option<TablebaseResult>,
+    #[serde(default)]
+    pub stopped_by: StopReason,
+    #[serde(default)]
+    pub variant: Variant,
     pub completed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub cached: bool,
+    #[serde(default)]
+    pub clamped: bool,
+    #[serde(default)]
+    pub partial: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub score_history: Vec<ScoreHistoryEntry>,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Evaluation {
-    pub score_type: ScoreType,
-    pub value: i32,
+impl AnalysisResult {
+    pub fn flip_scores(&mut self) {
+        self.evaluation = self.evaluation.negated();
+        self.wdl = self.wdl.map(Wdl::flipped);
+        self.tablebase = self.tablebase.map(TablebaseResult::flipped);
+        for pv in &mut self.principal_variations {
+            pv.evaluation = pv.evaluation.negated();
+        }
+        for entry in &mut self.score_history {
+            entry.evaluation = entry.evaluation.negated();
+        }
+    }
+    pub fn resolve_tablebase(&mut self, in_tables: bool) {
+        if self.tbhits == 0 || self.tablebase.is_some() {
+            return;
+        }
+        let Some(wdl) = self.evaluation.tablebase_wdl(in_tables) else {
+            return;
+        };
+        self.evaluation = Score::Tablebase(wdl);
+        self.tablebase = Some(TablebaseResult { wdl, dtz: None });
+        for pv in &mut self.principal_variations {
+            if let Some(wdl) = pv.evaluation.tablebase_wdl(in_tables) {
+                pv.evaluation = Score::Tablebase(wdl);
+            }
+        }
+    }
+    pub fn resolve_game_over(&mut self, position: &ChessPosition) {
+        self.game_over = match self.best_move {
+            Some(_) => None,
+            None => Some(GameOver::detect(position, &self.evaluation)),
+        };
+    }
+    pub fn render_notation(&mut self, position: &ChessPosition, notation: Notation) {
+        if notation == Notation::Uci {
+            return;
+        }
+        let Some(best_move) = self.best_move.as_ref() else {
+            return;
+        };
+        self.best_move_san = best_move.to_san(position).ok();
+        self.ponder_san = self.ponder.as_ref().and_then(|ponder| {
+            let after = position.make_move(best_move).ok()?;
+            ponder.to_san(&after).ok()
+        });
+        for pv in &mut self.principal_variations {
+            pv.render_notation(position, notation);
+        }
+    }
 }
-impl Evaluation {
-    pub fn centipawns(cp: i32) -> Self {
-        Self {
-            score_type: ScoreType::Centipawns,
-            value: cp,
+pub const MAX_SCORE_HISTORY: usize = 128;
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScoreHistoryEntry {
+    pub depth: u8,
+    pub evaluation: Score,
+    pub nodes: u64,
+    pub time_ms: u64,
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    #[default]
+    Depth,
+    Time,
+    Nodes,
+    Cancelled,
+    Timeout,
+}
+impl StopReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopReason::Depth => "depth",
+            StopReason::Time => "time",
+            StopReason::Nodes => "nodes",
+            StopReason::Cancelled => "cancelled",
+            StopReason::Timeout => "timeout",
         }
     }
-    pub fn mate(moves: i32) -> Self {
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum GameOver {
+    Checkmate,
+    Stalemate,
+}
+impl GameOver {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameOver::Checkmate => "checkmate",
+            GameOver::Stalemate => "stalemate",
+        }
+    }
+    pub fn detect(position: &ChessPosition, evaluation: &Score) -> Self {
+        if position.is_checkmate() {
+            GameOver::Checkmate
+        } else if position.is_stalemate() {
+            GameOver::Stalemate
+        } else {
+            Self::from_evaluation(evaluation)
+        }
+    }
+    pub fn from_evaluation(evaluation: &Score) -> Self {
+        match evaluation {
+            Score::MateIn(0) => GameOver::Checkmate,
+            _ => GameOver::Stalemate,
+        }
+    }
+}
+pub const TABLEBASE_MAX_PIECES: usize = 7;
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TablebaseWdl {
+    Win,
+    Draw,
+    Loss,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TablebaseResult {
+    pub wdl: TablebaseWdl,
+    pub dtz: Option<i32>,
+}
+impl TablebaseResult {
+    pub fn flipped(self) -> Self {
         Self {
-            score_type: ScoreType::Mate,
-            value: moves,
+            wdl: match self.wdl {
+                TablebaseWdl::Win => TablebaseWdl::Loss,
+                TablebaseWdl::Draw => TablebaseWdl::Draw,
+                TablebaseWdl::Loss => TablebaseWdl::Win,
+            },
+            dtz: self.dtz.map(|dtz| -dtz),
         }
     }
 }
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum ScoreType {
-    Centipawns,
-    Mate,
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Wdl {
+    pub win: u32,
+    pub draw: u32,
+    pub loss: u32,
+}
+impl Wdl {
+    pub fn flipped(self) -> Self {
+        Self {
+            win: self.loss,
+            draw: self.draw,
+            loss: self.win,
+        }
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct PrincipalVariation {
     pub rank: u8,
     pub moves: Vec<Move>,
-    pub evaluation: Evaluation,
+    pub evaluation: Score,
     pub depth: u8,
+    #[serde(default)]
+    pub seldepth: Option<u8>,
+    #[serde(default)]
+    pub nodes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub san: Option<Vec<String>>,
+}
+impl PrincipalVariation {
+    pub fn render_notation(&mut self, position: &ChessPosition, notation: Notation) {
+        if notation == Notation::Uci {
+            return;
+        }
+        self.san = position.san_line(&self.moves).ok();
+        if notation == Notation::San && self.san.is_some() {
+            self.moves.clear();
+        }
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AnalysisProgress {
     pub id: Uuid,
     pub current_depth: u8,
@@ -85,30 +462,154 @@ pub struct AnalysisProgress {
     pub current_move: Option<Move>,
     pub nodes_per_second: u64,
     pub hash_full: u16,
-    pub evaluation: Option<Evaluation>,
+    #[serde(default)]
+    pub elapsed_ms: u64,
+    pub evaluation: Option<Score>,
+    #[serde(default)]
+    pub wdl: Option<Wdl>,
     pub principal_variations: Vec<PrincipalVariation>,
 }
+impl AnalysisProgress {
+    pub fn flip_scores(&mut self) {
+        self.evaluation = self.evaluation.as_ref().map(Score::negated);
+        self.wdl = self.wdl.map(Wdl::flipped);
+        for pv in &mut self.principal_variations {
+            pv.evaluation = pv.evaluation.negated();
+        }
+    }
+    pub fn render_notation(&mut self, position: &ChessPosition, notation: Notation) {
+        for pv in &mut self.principal_variations {
+            pv.render_notation(position, notation);
+        }
+    }
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BestMoveRequest {
     pub fen: String,
     pub movetime: Option<u64>,
+    #[serde(default)]
+    pub nodes: Option<u64>,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default)]
+    pub moves: Vec<String>,
 }
 impl BestMoveRequest {
     pub fn new(fen: impl Into<String>) -> Self {
         Self {
             fen: fen.into(),
             movetime: Some(1000),
+            nodes: None,
+            variant: Variant::Standard,
+            moves: Vec::new(),
         }
     }
+    pub fn validate(&self) -> Result<ChessPosition> {
+        let position = ChessPosition::new(&self.fen);
+        position.validate_for(self.variant)?;
+        position.apply_uci_moves(&self.moves)
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct BestMoveResponse {
-    pub best_move: Move,
+    pub best_move: Option<Move>,
     pub ponder: Option<Move>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_over: Option<GameOver>,
+    #[serde(default)]
+    pub partial: bool,
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::ScoreType;
+    fn engine_result(fen: &str, cp: i32, tbhits: u64) -> AnalysisResult {
+        AnalysisResult {
+            id: Uuid::new_v4(),
+            fen: fen.to_string(),
+            best_move: Some(Move::new("e1", "d2")),
+            game_over: None,
+            best_move_san: None,
+            ponder: None,
+            ponder_san: None,
+            evaluation: Score::Centipawns(cp),
+            wdl: None,
+            principal_variations: vec![PrincipalVariation {
+                rank: 1,
+                moves: vec![Move::new("e1", "d2")],
+                evaluation: Score::Centipawns(cp),
+                depth: 40,
+                seldepth: None,
+                nodes: None,
+                san: None,
+            }],
+            depth_reached: 40,
+            nodes_searched: 52000,
+            time_ms: 50,
+            tbhits,
+            tablebase: None,
+            stopped_by: StopReason::Depth,
+            variant: Variant::Standard,
+            completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: false,
+            score_history: Vec::new(),
+        }
+    }
+    #[test]
+    fn test_score_history_is_omitted_when_empty_and_flipped() {
+        let mut result = engine_result(&ChessPosition::starting().fen, 25, 0);
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json.get("score_history").is_none());
+        result.score_history = vec![ScoreHistoryEntry {
+            depth: 1,
+            evaluation: Score::Centipawns(25),
+            nodes: 20,
+            time_ms: 1,
+        }];
+        result.flip_scores();
+        assert_eq!(result.score_history[0].evaluation, Score::Centipawns(-25));
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["score_history"][0]["depth"], 1);
+    }
+    #[test]
+    fn test_tablebase_scores_are_exact() {
+        let endgame = ChessPosition::new("8/8/8/8/8/3k4/3P4/3K4 b - - 0 1");
+        let mut result = engine_result(&endgame.fen, 19960, 3187);
+        result.resolve_tablebase(false);
+        assert_eq!(result.evaluation, Score::Tablebase(TablebaseWdl::Win));
+        assert_eq!(result.principal_variations[0].evaluation.value(), 1);
+        assert_eq!(result.tablebase.unwrap().wdl, TablebaseWdl::Win);
+        result.flip_scores();
+        assert_eq!(result.evaluation, Score::Tablebase(TablebaseWdl::Loss));
+        assert_eq!(result.tablebase.unwrap().wdl, TablebaseWdl::Loss);
+        let mut draw = engine_result(&endgame.fen, 0, 12);
+        draw.resolve_tablebase(true);
+        assert_eq!(draw.tablebase.unwrap().wdl, TablebaseWdl::Draw);
+        assert_eq!(draw.evaluation, Score::Tablebase(TablebaseWdl::Draw));
+        let mut uncovered = engine_result(&endgame.fen, 0, 12);
+        uncovered.resolve_tablebase(false);
+        assert_eq!(uncovered.evaluation, Score::Centipawns(0));
+        assert!(uncovered.tablebase.is_none());
+        let mut untouched = engine_result(&endgame.fen, 19960, 0);
+        untouched.resolve_tablebase(true);
+        assert_eq!(untouched.evaluation, Score::Centipawns(19960));
+        assert!(untouched.tablebase.is_none());
+    }
+    #[test]
+    fn test_white_perspective_flips_black_to_move() {
+        let black =
+            ChessPosition::new("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        let request = AnalysisRequest::new(&black.fen);
+        assert!(request.flips_scores(&black));
+        assert!(!request.flips_scores(&ChessPosition::starting()));
+        let request = request.with_perspective("side_to_move".parse().unwrap());
+        assert!(!request.flips_scores(&black));
+        assert!("black".parse::<Perspective>().is_err());
+        assert_eq!(Score::MateIn(3).negated(), Score::MateIn(-3));
+    }
     #[test]
     fn test_analysis_request_builder() {
         let req = AnalysisRequest::new("startpos")
@@ -126,6 +627,20 @@ mod tests {
         assert_eq!(req.depth, 20);
         assert_eq!(req.multipv, 1);
         assert_eq!(req.movetime, None);
+        assert_eq!(req.nodes, None);
+    }
+    #[test]
+    fn test_stop_reason_serialization() {
+        assert_eq!(
+            serde_json::to_string(&StopReason::Time).unwrap(),
+            "\"time\""
+        );
+        assert_eq!(StopReason::default(), StopReason::Depth);
+        assert_eq!(StopReason::Nodes.as_str(), "nodes");
+        assert_eq!(
+            serde_json::to_string(&StopReason::Timeout).unwrap(),
+            "\"timeout\""
+        );
     }
     #[test]
     fn test_multipv_minimum() {
@@ -133,16 +648,92 @@ mod tests {
         assert_eq!(req.multipv, 1);
     }
     #[test]
+    fn test_analysis_limits_boundaries() {
+        let limits = AnalysisLimits::default();
+        assert!(limits.check(1, 1, None).is_ok());
+        assert!(limits.check(30, 10, Some(10)).is_ok());
+        assert!(limits.check(20, 1, Some(60_000)).is_ok());
+        assert!(limits.check_game(600).is_ok());
+        assert!(limits.check_search(None, None).is_ok());
+        assert!(limits.check_search(Some(100), Some(1)).is_ok());
+        let rejected = [
+            limits.check(0, 1, None),
+            limits.check(31, 1, None),
+            limits.check(20, 0, None),
+            limits.check(20, 11, None),
+            limits.check(20, 1, Some(9)),
+            limits.check(20, 1, Some(60_001)),
+            limits.check_game(0),
+            limits.check_game(601),
+            limits.check_search(Some(60_001), None),
+            limits.check_search(None, Some(0)),
+        ];
+        for result in rejected {
+            assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        }
+        assert_eq!(
+            limits.check(255, 1, None).unwrap_err().to_string(),
+            "invalid argument: depth must be between 1 and 30, got 255"
+        );
+    }
+    #[test]
     fn test_evaluation_centipawns() {
-        let eval = Evaluation::centipawns(150);
-        assert_eq!(eval.score_type, ScoreType::Centipawns);
-        assert_eq!(eval.value, 150);
+        let eval = Score::Centipawns(150);
+        assert_eq!(eval.score_type(), ScoreType::Centipawns);
+        assert_eq!(eval.to_pawns(), Some(1.5));
+        assert_eq!(eval.value(), 150);
     }
     #[test]
     fn test_evaluation_mate() {
-        let eval = Evaluation::mate(3);
-        assert_eq!(eval.score_type, ScoreType::Mate);
-        assert_eq!(eval.value, 3);
+        let eval = Score::MateIn(3);
+        assert_eq!(eval.score_type(), ScoreType::Mate);
+        assert!(eval.is_mate());
+        assert_eq!(eval.to_pawns(), None);
+        assert_eq!(eval.value(), 3);
+    }
+    #[test]
+    fn test_request_moves_validated() {
+        let start = ChessPosition::starting().fen;
+        let request = AnalysisRequest::new(&start).with_moves(vec!["e2e4".into(), "e7e5".into()]);
+        assert_eq!(
+            request.validate().unwrap().fen,
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+        let request = AnalysisRequest::new(&start).with_moves(vec!["e2e4".into(), "e2e4".into()]);
+        assert!(matches!(
+            request.validate(),
+            Err(crate::Error::InvalidMove { index: 1, ref notation }) if notation == "e2e4"
+        ));
+        let mut request = BestMoveRequest::new(&start);
+        request.moves = vec!["Nf3".into()];
+        assert!(matches!(
+            request.validate(),
+            Err(crate::Error::InvalidMove { index: 0, .. })
+        ));
+    }
+    #[test]
+    fn test_request_search_moves_validated() {
+        let start = ChessPosition::starting().fen;
+        let request = AnalysisRequest::new(&start)
+            .with_moves(vec!["e2e4".into()])
+            .with_search_moves(vec!["e7e5".into(), "c7c5".into()]);
+        assert!(request.validate().is_ok());
+        let request = AnalysisRequest::new(&start).with_search_moves(vec![
+            "e2e4".into(),
+            "e2e5".into(),
+            "Nf3".into(),
+            "d2d4".into(),
+        ]);
+        assert!(matches!(
+            request.validate(),
+            Err(crate::Error::InvalidSearchMoves(ref moves)) if moves == &["e2e5", "Nf3"]
+        ));
+        assert!(request.searches_move(&Move::from_uci("d2d4").unwrap()));
+        assert!(!request.searches_move(&Move::from_uci("g1f3").unwrap()));
+        let limits = AnalysisLimits::default();
+        assert_eq!(limits.default_multipv(&[]), 1);
+        assert_eq!(limits.default_multipv(&request.search_moves), 4);
+        assert_eq!(limits.default_multipv(&vec!["a2a3".to_string(); 20]), 10);
     }
     #[test]
     fn test_best_move_request() {
@@ -150,4 +741,38 @@ mod tests {
         assert_eq!(req.fen, "startpos");
         assert_eq!(req.movetime, Some(1000));
     }
+    #[test]
+    fn test_game_over_detection() {
+        let mated =
+            ChessPosition::new("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(
+            GameOver::detect(&mated, &Score::Centipawns(0)),
+            GameOver::Checkmate
+        );
+        let stalemate = ChessPosition::new("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert_eq!(
+            GameOver::detect(&stalemate, &Score::MateIn(0)),
+            GameOver::Stalemate
+        );
+        let unparsed = ChessPosition::new("not a fen");
+        assert_eq!(
+            GameOver::detect(&unparsed, &Score::MateIn(0)),
+            GameOver::Checkmate
+        );
+        assert_eq!(
+            GameOver::detect(&unparsed, &Score::Centipawns(0)),
+            GameOver::Stalemate
+        );
+    }
+    #[test]
+    fn test_render_notation_without_best_move() {
+        let mut result = engine_result("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 0, 0);
+        result.best_move = None;
+        result.game_over = Some(GameOver::Stalemate);
+        result.render_notation(&ChessPosition::new(&result.fen), Notation::San);
+        assert_eq!(result.best_move_san, None);
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json["best_move"].is_null());
+        assert_eq!(json["game_over"], "stalemate");
+    }
 }
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    TokenCreate,
+    TokenRevoke,
+    TokenUpdate,
+    TokenPurge,
+    ClusterJoin,
+    ClusterLeave,
+    CacheClear,
+    EnginePool,
+    AdminAuthFailure,
+    NodeDrain,
+    NodeResume,
+    ConfigReload,
+}
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::TokenCreate => "token_create",
+            AuditAction::TokenRevoke => "token_revoke",
+            AuditAction::TokenUpdate => "token_update",
+            AuditAction::TokenPurge => "token_purge",
+            AuditAction::ClusterJoin => "cluster_join",
+            AuditAction::ClusterLeave => "cluster_leave",
+            AuditAction::CacheClear => "cache_clear",
+            AuditAction::EnginePool => "engine_pool",
+            AuditAction::AdminAuthFailure => "admin_auth_failure",
+            AuditAction::NodeDrain => "node_drain",
+            AuditAction::NodeResume => "node_resume",
+            AuditAction::ConfigReload => "config_reload",
+        }
+    }
+}
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for AuditAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "token_create" => Ok(AuditAction::TokenCreate),
+            "token_revoke" => Ok(AuditAction::TokenRevoke),
+            "token_update" => Ok(AuditAction::TokenUpdate),
+            "token_purge" => Ok(AuditAction::TokenPurge),
+            "cluster_join" => Ok(AuditAction::ClusterJoin),
+            "cluster_leave" => Ok(AuditAction::ClusterLeave),
+            "cache_clear" => Ok(AuditAction::CacheClear),
+            "engine_pool" => Ok(AuditAction::EnginePool),
+            "admin_auth_failure" => Ok(AuditAction::AdminAuthFailure),
+            "node_drain" => Ok(AuditAction::NodeDrain),
+            "node_resume" => Ok(AuditAction::NodeResume),
+            "config_reload" => Ok(AuditAction::ConfigReload),
+            other => Err(format!("unknown audit action: {}", other)),
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure(String),
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    #[serde(default)]
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub source_ip: Option<String>,
+    pub node_id: String,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+impl AuditEntry {
+    pub fn new(action: AuditAction, actor: impl Into<String>, node_id: impl Into<String>) -> Self {
+        Self {
+            seq: 0,
+            timestamp: Utc::now(),
+            actor: actor.into(),
+            action,
+            target: None,
+            source_ip: None,
+            node_id: node_id.into(),
+            outcome: AuditOutcome::Success,
+            idempotency_key: None,
+        }
+    }
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+    pub fn with_source_ip(mut self, source_ip: Option<String>) -> Self {
+        self.source_ip = source_ip;
+        self
+    }
+    pub fn with_idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+    pub fn with_outcome(mut self, outcome: AuditOutcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+    pub fn failed(self, reason: impl Into<String>) -> Self {
+        self.with_outcome(AuditOutcome::Failure(reason.into()))
+    }
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub after: Option<u64>,
+    pub action: Option<AuditAction>,
+    pub limit: Option<usize>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_after: Option<u64>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditRetention {
+    pub max_age_days: Option<u32>,
+    pub max_entries: Option<usize>,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_audit_action_round_trip() {
+        for action in [
+            AuditAction::TokenCreate,
+            AuditAction::TokenRevoke,
+            AuditAction::TokenPurge,
+            AuditAction::ClusterJoin,
+            AuditAction::AdminAuthFailure,
+            AuditAction::NodeDrain,
+            AuditAction::ConfigReload,
+        ] {
+            let parsed: AuditAction = action.as_str().parse().unwrap();
+            assert_eq!(parsed, action);
+            let json = serde_json::to_string(&action).unwrap();
+            assert_eq!(json, format!("\"{}\"", action.as_str()));
+        }
+        assert!("bogus".parse::<AuditAction>().is_err());
+    }
+    #[test]
+    fn test_audit_entry_builder() {
+        let entry = AuditEntry::new(AuditAction::TokenRevoke, "admin:abc", "node-1")
+            .with_target("token-id")
+            .failed("not found");
+        assert_eq!(entry.target.as_deref(), Some("token-id"));
+        assert_eq!(entry.outcome, AuditOutcome::Failure("not found".into()));
+    }
+}
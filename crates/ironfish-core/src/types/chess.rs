@@ -1,4 +1,42 @@
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960,
+}
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::Chess960 => "chess960",
+        }
+    }
+    pub fn is_chess960(&self) -> bool {
+        *self == Variant::Chess960
+    }
+}
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for Variant {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Variant::Standard),
+            "chess960" | "frc" => Ok(Variant::Chess960),
+            other => Err(format!(
+                "unknown variant '{}', expected \"standard\" or \"chess960\"",
+                other
+            )),
+        }
+    }
+}
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ChessPosition {
     pub fen: String,
@@ -36,8 +74,121 @@ impl ChessPosition {
         }
         matches!(parts[1], "w" | "b")
     }
+    pub fn piece_count(&self) -> usize {
+        self.fen.split_whitespace().next().map_or(0, |placement| {
+            placement
+                .chars()
+                .filter(|c| c.is_ascii_alphabetic())
+                .count()
+        })
+    }
+    pub fn side_to_move(&self) -> Option<Color> {
+        self.fen
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.chars().next())
+            .and_then(Color::from_fen)
+    }
+    pub fn make_move(&self, mv: &Move) -> Result<ChessPosition> {
+        let board =
+            Board::from_fen(&self.fen).ok_or_else(|| Error::InvalidFen(self.fen.clone()))?;
+        let found = board
+            .find_uci(&mv.to_uci())
+            .ok_or_else(|| Error::IllegalMove(mv.to_uci()))?;
+        Ok(Self::new(board.play(found).to_fen()))
+    }
+    pub fn is_legal(&self, mv: &Move) -> bool {
+        self.make_move(mv).is_ok()
+    }
+    pub fn apply_uci_moves(&self, moves: &[String]) -> Result<ChessPosition> {
+        let mut position = self.clone();
+        for (index, uci) in moves.iter().enumerate() {
+            let invalid = || Error::InvalidMove {
+                index,
+                notation: uci.clone(),
+            };
+            let mv = Move::from_uci(uci)
+                .filter(|_| uci.len() <= 5)
+                .ok_or_else(invalid)?;
+            position = position.make_move(&mv).map_err(|e| match e {
+                Error::IllegalMove(_) => invalid(),
+                e => e,
+            })?;
+        }
+        Ok(position)
+    }
+    pub fn san_line(&self, moves: &[Move]) -> Result<Vec<String>> {
+        let mut board =
+            Board::from_fen(&self.fen).ok_or_else(|| Error::InvalidFen(self.fen.clone()))?;
+        let mut line = Vec::with_capacity(moves.len());
+        for mv in moves {
+            let found = board
+                .find_uci(&mv.to_uci())
+                .ok_or_else(|| Error::IllegalMove(mv.to_uci()))?;
+            line.push(board.san(found));
+            board = board.play(found);
+        }
+        Ok(line)
+    }
+    pub fn play(&self, notation: &str) -> Option<(Move, ChessPosition)> {
+        let board = Board::from_fen(&self.fen)?;
+        let mv = board
+            .find_uci(notation)
+            .or_else(|| board.find_san(notation))?;
+        Some((board.to_move(mv), Self::new(board.play(mv).to_fen())))
+    }
+    pub fn is_checkmate(&self) -> bool {
+        Board::from_fen(&self.fen).is_some_and(|b| b.in_check() && b.legal_moves().is_empty())
+    }
+    pub fn is_stalemate(&self) -> bool {
+        Board::from_fen(&self.fen).is_some_and(|b| !b.in_check() && b.legal_moves().is_empty())
+    }
+    pub fn position_key(&self) -> Option<String> {
+        let mut board = Board::from_fen(&self.fen)?;
+        if let Some(ep) = board.en_passant {
+            let capturable = board
+                .legal_moves()
+                .iter()
+                .any(|mv| mv.to == ep && board.squares[mv.from].is_some_and(|p| p.kind == 'p'));
+            if !capturable {
+                board.en_passant = None;
+            }
+        }
+        let fen = board.to_fen();
+        Some(fen.split_whitespace().take(4).collect::<Vec<_>>().join(" "))
+    }
+    pub fn castling(&self) -> &str {
+        self.fen.split_whitespace().nth(2).unwrap_or("-")
+    }
+    pub fn validate_for(&self, variant: Variant) -> Result<()> {
+        if !self.validate() {
+            return Err(Error::InvalidFen(self.fen.clone()));
+        }
+        let castling = self.castling();
+        if castling == "-" {
+            return Ok(());
+        }
+        let mut seen = Vec::with_capacity(castling.len());
+        for c in castling.chars() {
+            if seen.contains(&c) {
+                return Err(Error::InvalidFen(self.fen.clone()));
+            }
+            seen.push(c);
+        }
+        let standard = castling.chars().all(|c| "KQkq".contains(c));
+        let shredder = castling
+            .chars()
+            .all(|c| matches!(c, 'A'..='H' | 'a'..='h' | 'K' | 'Q' | 'k' | 'q'));
+        match variant {
+            _ if standard => Ok(()),
+            Variant::Standard if shredder => Err(Error::VariantRequired(castling.to_string())),
+            Variant::Chess960 if shredder => Ok(()),
+            _ => Err(Error::InvalidFen(self.fen.clone())),
+        }
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Move {
     pub from: String,
     pub to: String,
@@ -61,16 +212,23 @@ impl Move {
             None => format!("{}{}", self.from, self.to),
         }
     }
+    pub fn to_san(&self, position: &ChessPosition) -> Result<String> {
+        let mut line = position.san_line(std::slice::from_ref(self))?;
+        Ok(line.remove(0))
+    }
     pub fn from_uci(uci: &str) -> Option<Self> {
-        if uci.len() < 4 {
-            return None;
-        }
-        let from = uci[0..2].to_string();
-        let to = uci[2..4].to_string();
-        let promotion = uci.chars().nth(4);
+        let from = uci.get(0..2)?;
+        let to = uci.get(2..4)?;
+        parse_square(from)?;
+        parse_square(to)?;
+        let promotion = match uci.get(4..)? {
+            "" => None,
+            p @ ("q" | "r" | "b" | "n") => p.chars().next(),
+            _ => return None,
+        };
         Some(Self {
-            from,
-            to,
+            from: from.to_string(),
+            to: to.to_string(),
             promotion,
         })
     }
@@ -89,6 +247,589 @@ impl Color {
         }
     }
 }
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Piece {
+    color: Color,
+    kind: char,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BoardMove {
+    from: usize,
+    to: usize,
+    promotion: Option<char>,
+    castle: bool,
+}
+#[derive(Debug, Clone)]
+struct Board {
+    squares: [Option<Piece>; 64],
+    turn: Color,
+    castling: Vec<(Color, usize)>,
+    en_passant: Option<usize>,
+    halfmove: u32,
+    fullmove: u32,
+}
+fn square(file: usize, rank: usize) -> usize {
+    rank * 8 + file
+}
+fn parse_square(s: &str) -> Option<usize> {
+    let mut chars = s.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some(square(
+        file as usize - 'a' as usize,
+        rank as usize - '1' as usize,
+    ))
+}
+fn square_name(sq: usize) -> String {
+    format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1)
+}
+fn offset(sq: usize, (df, dr): (i8, i8)) -> Option<usize> {
+    let file = (sq % 8) as i8 + df;
+    let rank = (sq / 8) as i8 + dr;
+    ((0..8).contains(&file) && (0..8).contains(&rank)).then(|| square(file as usize, rank as usize))
+}
+impl Color {
+    fn opposite(self) -> Self {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+    fn back_rank(self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 7,
+        }
+    }
+    fn forward(self) -> i8 {
+        match self {
+            Color::White => 1,
+            Color::Black => -1,
+        }
+    }
+}
+impl Board {
+    fn from_fen(fen: &str) -> Option<Self> {
+        if !ChessPosition::new(fen).validate() {
+            return None;
+        }
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let mut squares = [None; 64];
+        for (i, rank) in parts[0].split('/').enumerate() {
+            let mut file = 0;
+            for c in rank.chars() {
+                if let Some(n) = c.to_digit(10) {
+                    file += n as usize;
+                } else {
+                    let color = if c.is_ascii_uppercase() {
+                        Color::White
+                    } else {
+                        Color::Black
+                    };
+                    squares[square(file, 7 - i)] = Some(Piece {
+                        color,
+                        kind: c.to_ascii_lowercase(),
+                    });
+                    file += 1;
+                }
+            }
+        }
+        let turn = Color::from_fen(parts[1].chars().next()?)?;
+        let mut board = Self {
+            squares,
+            turn,
+            castling: Vec::new(),
+            en_passant: parts.get(3).and_then(|s| parse_square(s)),
+            halfmove: parts.get(4).and_then(|s| s.parse().ok()).unwrap_or(0),
+            fullmove: parts.get(5).and_then(|s| s.parse().ok()).unwrap_or(1),
+        };
+        for c in parts[2].chars().filter(|c| *c != '-') {
+            if !matches!(c, 'A'..='H' | 'a'..='h' | 'K' | 'Q' | 'k' | 'q') {
+                return None;
+            }
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let king = board.king_square(color)?;
+            let rank = color.back_rank();
+            if king / 8 != rank {
+                continue;
+            }
+            let is_rook = |file: &usize| {
+                board.squares[square(*file, rank)] == Some(Piece { color, kind: 'r' })
+            };
+            let file = match c.to_ascii_lowercase() {
+                'k' => (king % 8 + 1..8).rev().find(is_rook),
+                'q' => (0..king % 8).find(is_rook),
+                f => Some(f as usize - 'a' as usize).filter(is_rook),
+            };
+            if let Some(file) = file {
+                if !board.castling.contains(&(color, file)) {
+                    board.castling.push((color, file));
+                }
+            }
+        }
+        Some(board)
+    }
+    fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.squares[square(file, rank)] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(match piece.color {
+                            Color::White => piece.kind.to_ascii_uppercase(),
+                            Color::Black => piece.kind,
+                        });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+        let mut castling = String::new();
+        for color in [Color::White, Color::Black] {
+            let Some(king) = self.king_square(color) else {
+                continue;
+            };
+            let mut files: Vec<usize> = self
+                .castling
+                .iter()
+                .filter(|(c, _)| *c == color)
+                .map(|(_, f)| *f)
+                .collect();
+            files.sort_by(|a, b| b.cmp(a));
+            for file in files {
+                let rank = color.back_rank();
+                let rook = Some(Piece { color, kind: 'r' });
+                let outermost = if file > king % 8 {
+                    (file + 1..8).all(|f| self.squares[square(f, rank)] != rook)
+                } else {
+                    (0..file).all(|f| self.squares[square(f, rank)] != rook)
+                };
+                let c = match (outermost, file > king % 8) {
+                    (true, true) => 'K',
+                    (true, false) => 'Q',
+                    (false, _) => (b'A' + file as u8) as char,
+                };
+                castling.push(match color {
+                    Color::White => c,
+                    Color::Black => c.to_ascii_lowercase(),
+                });
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            match self.turn {
+                Color::White => 'w',
+                Color::Black => 'b',
+            },
+            castling,
+            self.en_passant
+                .map(square_name)
+                .unwrap_or_else(|| "-".to_string()),
+            self.halfmove,
+            self.fullmove
+        )
+    }
+    fn king_square(&self, color: Color) -> Option<usize> {
+        self.squares
+            .iter()
+            .position(|p| *p == Some(Piece { color, kind: 'k' }))
+    }
+    fn is_attacked(&self, sq: usize, by: Color) -> bool {
+        let is = |target: Option<usize>, kinds: &str| {
+            target
+                .and_then(|t| self.squares[t])
+                .is_some_and(|p| p.color == by && kinds.contains(p.kind))
+        };
+        let pawn_rank = -by.forward();
+        if is(offset(sq, (1, pawn_rank)), "p") || is(offset(sq, (-1, pawn_rank)), "p") {
+            return true;
+        }
+        if KNIGHT_STEPS.iter().any(|s| is(offset(sq, *s), "n"))
+            || KING_STEPS.iter().any(|s| is(offset(sq, *s), "k"))
+        {
+            return true;
+        }
+        let slides = |directions: &[(i8, i8)], kinds: &str| {
+            directions.iter().any(|d| {
+                let mut current = sq;
+                while let Some(next) = offset(current, *d) {
+                    if let Some(piece) = self.squares[next] {
+                        return piece.color == by && kinds.contains(piece.kind);
+                    }
+                    current = next;
+                }
+                false
+            })
+        };
+        slides(&ROOK_DIRECTIONS, "rq") || slides(&BISHOP_DIRECTIONS, "bq")
+    }
+    fn in_check(&self) -> bool {
+        self.king_square(self.turn)
+            .is_some_and(|k| self.is_attacked(k, self.turn.opposite()))
+    }
+    fn pseudo_legal_moves(&self) -> Vec<BoardMove> {
+        let mut moves = Vec::new();
+        let us = self.turn;
+        let push = |moves: &mut Vec<BoardMove>, from: usize, to: usize| {
+            moves.push(BoardMove {
+                from,
+                to,
+                promotion: None,
+                castle: false,
+            })
+        };
+        for from in 0..64 {
+            let Some(piece) = self.squares[from].filter(|p| p.color == us) else {
+                continue;
+            };
+            let target_ok = |to: usize| self.squares[to].is_none_or(|p| p.color != us);
+            match piece.kind {
+                'p' => {
+                    let mut targets = Vec::new();
+                    if let Some(one) = offset(from, (0, us.forward())) {
+                        if self.squares[one].is_none() {
+                            targets.push(one);
+                            let start_rank = if us == Color::White { 1 } else { 6 };
+                            if from / 8 == start_rank {
+                                if let Some(two) = offset(one, (0, us.forward())) {
+                                    if self.squares[two].is_none() {
+                                        targets.push(two);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    for df in [-1, 1] {
+                        if let Some(to) = offset(from, (df, us.forward())) {
+                            let capture = self.squares[to].is_some_and(|p| p.color != us);
+                            if capture || self.en_passant == Some(to) {
+                                targets.push(to);
+                            }
+                        }
+                    }
+                    for to in targets {
+                        if to / 8 == us.opposite().back_rank() {
+                            for promotion in ['q', 'r', 'b', 'n'] {
+                                moves.push(BoardMove {
+                                    from,
+                                    to,
+                                    promotion: Some(promotion),
+                                    castle: false,
+                                });
+                            }
+                        } else {
+                            push(&mut moves, from, to);
+                        }
+                    }
+                }
+                'n' | 'k' => {
+                    let steps = if piece.kind == 'n' {
+                        &KNIGHT_STEPS
+                    } else {
+                        &KING_STEPS
+                    };
+                    for step in steps {
+                        if let Some(to) = offset(from, *step).filter(|t| target_ok(*t)) {
+                            push(&mut moves, from, to);
+                        }
+                    }
+                }
+                kind => {
+                    let directions: Vec<(i8, i8)> = match kind {
+                        'r' => ROOK_DIRECTIONS.to_vec(),
+                        'b' => BISHOP_DIRECTIONS.to_vec(),
+                        _ => [ROOK_DIRECTIONS, BISHOP_DIRECTIONS].concat(),
+                    };
+                    for d in directions {
+                        let mut current = from;
+                        while let Some(to) = offset(current, d) {
+                            if !target_ok(to) {
+                                break;
+                            }
+                            push(&mut moves, from, to);
+                            if self.squares[to].is_some() {
+                                break;
+                            }
+                            current = to;
+                        }
+                    }
+                }
+            }
+        }
+        moves.extend(self.castling_moves());
+        moves
+    }
+    fn castling_moves(&self) -> Vec<BoardMove> {
+        let us = self.turn;
+        let Some(king) = self.king_square(us) else {
+            return Vec::new();
+        };
+        let rank = us.back_rank();
+        if king / 8 != rank || self.in_check() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for &(_, file) in self.castling.iter().filter(|(c, _)| *c == us) {
+            let rook = square(file, rank);
+            let kingside = file > king % 8;
+            let king_to = square(if kingside { 6 } else { 2 }, rank);
+            let rook_to = square(if kingside { 5 } else { 3 }, rank);
+            let low = king.min(rook).min(king_to).min(rook_to);
+            let high = king.max(rook).max(king_to).max(rook_to);
+            let clear =
+                (low..=high).all(|sq| sq == king || sq == rook || self.squares[sq].is_none());
+            let safe = (king.min(king_to)..=king.max(king_to))
+                .all(|sq| !self.is_attacked(sq, us.opposite()));
+            if clear && safe {
+                moves.push(BoardMove {
+                    from: king,
+                    to: rook,
+                    promotion: None,
+                    castle: true,
+                });
+            }
+        }
+        moves
+    }
+    fn play(&self, mv: BoardMove) -> Board {
+        let mut next = self.clone();
+        let us = self.turn;
+        let rank = us.back_rank();
+        let piece = self.squares[mv.from].expect("move from an occupied square");
+        let captured = self.squares[mv.to];
+        next.en_passant = None;
+        if mv.castle {
+            let kingside = mv.to > mv.from;
+            next.squares[mv.from] = None;
+            next.squares[mv.to] = None;
+            next.squares[square(if kingside { 6 } else { 2 }, rank)] = Some(piece);
+            next.squares[square(if kingside { 5 } else { 3 }, rank)] = Some(Piece {
+                color: us,
+                kind: 'r',
+            });
+        } else {
+            if piece.kind == 'p' && Some(mv.to) == self.en_passant && captured.is_none() {
+                let victim = match us {
+                    Color::White => mv.to - 8,
+                    Color::Black => mv.to + 8,
+                };
+                next.squares[victim] = None;
+            }
+            if piece.kind == 'p' && mv.from.abs_diff(mv.to) == 16 {
+                next.en_passant = Some((mv.from + mv.to) / 2);
+            }
+            next.squares[mv.from] = None;
+            next.squares[mv.to] = Some(Piece {
+                color: us,
+                kind: mv.promotion.unwrap_or(piece.kind),
+            });
+        }
+        if piece.kind == 'k' {
+            next.castling.retain(|(c, _)| *c != us);
+        }
+        next.castling.retain(|&(c, file)| {
+            let sq = square(file, c.back_rank());
+            sq != mv.from && sq != mv.to
+        });
+        next.halfmove = if piece.kind == 'p' || (captured.is_some() && !mv.castle) {
+            0
+        } else {
+            self.halfmove + 1
+        };
+        if us == Color::Black {
+            next.fullmove += 1;
+        }
+        next.turn = us.opposite();
+        next
+    }
+    fn legal_moves(&self) -> Vec<BoardMove> {
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| {
+                let next = self.play(*mv);
+                next.king_square(self.turn)
+                    .is_some_and(|k| !next.is_attacked(k, next.turn))
+            })
+            .collect()
+    }
+    fn to_move(&self, mv: BoardMove) -> Move {
+        if mv.castle {
+            let standard_squares = mv.from % 8 == 4 && matches!(mv.to % 8, 0 | 7);
+            if standard_squares {
+                let file = if mv.to > mv.from { 6 } else { 2 };
+                return Move::new(square_name(mv.from), square_name(square(file, mv.from / 8)));
+            }
+        }
+        let mv_out = Move::new(square_name(mv.from), square_name(mv.to));
+        match mv.promotion {
+            Some(p) => mv_out.with_promotion(p),
+            None => mv_out,
+        }
+    }
+    fn san(&self, mv: BoardMove) -> String {
+        let piece = self.squares[mv.from].expect("move from an occupied square");
+        let mut san = if mv.castle {
+            if mv.to > mv.from { "O-O" } else { "O-O-O" }.to_string()
+        } else if piece.kind == 'p' {
+            let mut san = String::new();
+            if mv.from % 8 != mv.to % 8 {
+                san.push_str(&square_name(mv.from)[..1]);
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(promotion.to_ascii_uppercase());
+            }
+            san
+        } else {
+            let rivals: Vec<usize> = self
+                .legal_moves()
+                .into_iter()
+                .filter(|m| {
+                    !m.castle
+                        && m.to == mv.to
+                        && m.from != mv.from
+                        && self.squares[m.from] == Some(piece)
+                })
+                .map(|m| m.from)
+                .collect();
+            let from = square_name(mv.from);
+            let mut san = piece.kind.to_ascii_uppercase().to_string();
+            if rivals.iter().all(|r| r % 8 != mv.from % 8) {
+                if !rivals.is_empty() {
+                    san.push_str(&from[..1]);
+                }
+            } else if rivals.iter().all(|r| r / 8 != mv.from / 8) {
+                san.push_str(&from[1..]);
+            } else {
+                san.push_str(&from);
+            }
+            if self.squares[mv.to].is_some() {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+            san
+        };
+        let next = self.play(mv);
+        if next.in_check() {
+            san.push(if next.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+    fn find_uci(&self, uci: &str) -> Option<BoardMove> {
+        let mv = Move::from_uci(uci).filter(|_| uci.len() <= 5)?;
+        let from = parse_square(&mv.from)?;
+        let to = parse_square(&mv.to)?;
+        let promotion = mv.promotion.map(|p| p.to_ascii_lowercase());
+        self.legal_moves().into_iter().find(|m| {
+            if m.from != from || m.promotion != promotion {
+                return false;
+            }
+            if m.castle {
+                let king_to = square(if m.to > m.from { 6 } else { 2 }, from / 8);
+                m.to == to || (from % 8 == 4 && to == king_to)
+            } else {
+                m.to == to
+            }
+        })
+    }
+    fn find_san(&self, san: &str) -> Option<BoardMove> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal = self.legal_moves();
+        let castle = |kingside: bool| {
+            legal
+                .iter()
+                .copied()
+                .find(|m| m.castle && (m.to > m.from) == kingside)
+        };
+        match san {
+            "O-O" | "0-0" => return castle(true),
+            "O-O-O" | "0-0-0" => return castle(false),
+            _ => {}
+        }
+        let (body, promotion) = match san.split_once('=') {
+            Some((body, p)) => (body, Some(p.chars().next()?.to_ascii_lowercase())),
+            None => match san.chars().last() {
+                Some(c @ ('Q' | 'R' | 'B' | 'N')) => {
+                    (&san[..san.len() - 1], Some(c.to_ascii_lowercase()))
+                }
+                _ => (san, None),
+            },
+        };
+        let (kind, rest) = match body.chars().next()? {
+            c @ ('K' | 'Q' | 'R' | 'B' | 'N') => (c.to_ascii_lowercase(), &body[1..]),
+            _ => ('p', body),
+        };
+        let rest = rest.replace('x', "");
+        if rest.len() < 2 || !rest.is_ascii() {
+            return None;
+        }
+        let to = parse_square(&rest[rest.len() - 2..])?;
+        let hint = &rest[..rest.len() - 2];
+        let mut candidates = legal.into_iter().filter(|m| {
+            !m.castle
+                && m.to == to
+                && m.promotion == promotion
+                && self.squares[m.from].is_some_and(|p| p.kind == kind)
+                && hint.chars().all(|c| match c {
+                    'a'..='h' => m.from % 8 == c as usize - 'a' as usize,
+                    '1'..='8' => m.from / 8 == c as usize - '1' as usize,
+                    _ => false,
+                })
+        });
+        let found = candidates.next()?;
+        candidates.next().is_none().then_some(found)
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -126,6 +867,28 @@ mod tests {
         }
     }
     #[test]
+    fn test_chess960_castling_validation() {
+        let frc = ChessPosition::new("bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1");
+        assert!(frc.validate_for(Variant::Chess960).is_ok());
+        assert!(matches!(
+            frc.validate_for(Variant::Standard),
+            Err(Error::VariantRequired(ref c)) if c == "GEge"
+        ));
+        let standard = ChessPosition::starting();
+        assert!(standard.validate_for(Variant::Standard).is_ok());
+        assert!(standard.validate_for(Variant::Chess960).is_ok());
+        let bogus = ChessPosition::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KXkq - 0 1");
+        assert!(matches!(
+            bogus.validate_for(Variant::Chess960),
+            Err(Error::InvalidFen(_))
+        ));
+        let duplicated =
+            ChessPosition::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KKkq - 0 1");
+        assert!(duplicated.validate_for(Variant::Standard).is_err());
+        assert_eq!("chess960".parse::<Variant>(), Ok(Variant::Chess960));
+        assert!("crazyhouse".parse::<Variant>().is_err());
+    }
+    #[test]
     fn test_move_creation() {
         let mv = Move::new("e2", "e4");
         assert_eq!(mv.from, "e2");
@@ -154,6 +917,221 @@ mod tests {
         assert_eq!(mv_promo.promotion, Some('q'));
         assert!(Move::from_uci("e2").is_none());
         assert!(Move::from_uci("").is_none());
+        assert!(Move::from_uci("(none)").is_none());
+        assert!(Move::from_uci("0000").is_none());
+        assert!(Move::from_uci("e7e8qq").is_none());
+    }
+    #[test]
+    fn test_move_from_uci_non_ascii() {
+        for input in [
+            "é2e4",
+            "e2é4",
+            "e2e4é",
+            "♔♕",
+            "e♔2e4",
+            "e7e8♛",
+            "日本語の手",
+        ] {
+            assert!(Move::from_uci(input).is_none(), "parsed {:?}", input);
+        }
+        let alphabet: Vec<char> = "e2e4q♔é日\u{301}\u{0}".chars().collect();
+        for seed in 0..2000usize {
+            let input: String = (0..seed % 9)
+                .map(|i| alphabet[(seed * 31 + i * 7) % alphabet.len()])
+                .collect();
+            if let Some(mv) = Move::from_uci(&input) {
+                assert_eq!(mv.to_uci(), input);
+            }
+        }
+    }
+    #[test]
+    fn test_play_san_and_uci() {
+        let (mv, pos) = ChessPosition::starting().play("Nf3").unwrap();
+        assert_eq!(mv.to_uci(), "g1f3");
+        assert_eq!(
+            pos.fen,
+            "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1"
+        );
+        let pos = ChessPosition::new("4k3/8/8/3pP3/8/8/8/R3K2R w KQ d6 0 1");
+        let (mv, after) = pos.play("exd6").unwrap();
+        assert_eq!(mv.to_uci(), "e5d6");
+        assert_eq!(after.fen, "4k3/8/3P4/8/8/8/8/R3K2R b KQ - 0 1");
+        assert_eq!(
+            pos.play("e1c1").unwrap().1.fen,
+            "4k3/8/8/3pP3/8/8/8/2KR3R b - - 1 1"
+        );
+        let knights = ChessPosition::new("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+        assert!(knights.play("Nd2").is_none());
+        assert_eq!(knights.play("Nbd2").unwrap().0.to_uci(), "b1d2");
+        assert!(ChessPosition::starting().play("e5").is_none());
+        assert!(ChessPosition::starting().play("Ke2").is_none());
+    }
+    #[test]
+    fn test_move_to_san() {
+        let san = |fen: &str, uci: &str| {
+            Move::from_uci(uci)
+                .unwrap()
+                .to_san(&ChessPosition::new(fen))
+                .unwrap()
+        };
+        let castling = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san(castling, "e1g1"), "O-O");
+        assert_eq!(san(castling, "e1c1"), "O-O-O");
+        assert_eq!(san(castling, "e1h1"), "O-O");
+        assert_eq!(san("5k2/8/8/8/8/8/8/4K2R w K - 0 1", "e1g1"), "O-O+");
+        let frc = "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1";
+        assert_eq!(san(frc, "e1b1"), "O-O-O");
+        assert_eq!(san("8/P7/8/8/8/8/8/k3K3 w - - 0 1", "a7a8q"), "a8=Q+");
+        assert_eq!(san("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8n"), "axb8=N");
+        assert_eq!(san("k7/1P6/1K6/8/8/8/8/8 w - - 0 1", "b7b8r"), "b8=R+");
+        assert_eq!(san("k7/2P5/1K6/8/8/8/8/8 w - - 0 1", "c7c8q"), "c8=Q#");
+        let knights = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_eq!(san(knights, "b1d2"), "Nbd2");
+        assert_eq!(san(knights, "f1d2"), "Nfd2");
+        assert_eq!(san(knights, "f1g3"), "Ng3");
+        let stacked = "4k3/8/8/6N1/8/8/8/4K1N1 w - - 0 1";
+        assert_eq!(san(stacked, "g1f3"), "N1f3");
+        assert_eq!(san(stacked, "g5f3"), "N5f3");
+        let queens = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+        assert_eq!(san(queens, "a1b2"), "Qa1b2");
+        assert_eq!(san(queens, "a3b2"), "Q3b2");
+        assert_eq!(san(queens, "c1b2"), "Qcb2");
+        let fools = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2";
+        assert_eq!(san(fools, "d8h4"), "Qh4#");
+        let ep = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san(ep, "e5d6"), "exd6");
+        assert!(matches!(
+            Move::new("e2", "e5").to_san(&ChessPosition::starting()),
+            Err(Error::IllegalMove(_))
+        ));
+        let line: Vec<Move> = ["e2e4", "d7d5", "e4d5", "d8d5", "b1c3"]
+            .iter()
+            .map(|m| Move::from_uci(m).unwrap())
+            .collect();
+        assert_eq!(
+            ChessPosition::starting().san_line(&line).unwrap(),
+            ["e4", "d5", "exd5", "Qxd5", "Nc3"]
+        );
+    }
+    fn after(fen: &str, uci: &str) -> String {
+        ChessPosition::new(fen)
+            .make_move(&Move::from_uci(uci).unwrap())
+            .unwrap()
+            .fen
+    }
+    #[test]
+    fn test_make_move_counters_and_side() {
+        let start = ChessPosition::starting();
+        assert_eq!(start.side_to_move(), Some(Color::White));
+        let e4 = start.make_move(&Move::new("e2", "e4")).unwrap();
+        assert_eq!(
+            e4.fen,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        assert_eq!(e4.side_to_move(), Some(Color::Black));
+        let nf6 = e4.make_move(&Move::new("g8", "f6")).unwrap();
+        assert_eq!(
+            nf6.fen,
+            "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2"
+        );
+        assert!(matches!(
+            start.make_move(&Move::new("e2", "e5")),
+            Err(Error::IllegalMove(ref m)) if m == "e2e5"
+        ));
+        assert!(matches!(
+            ChessPosition::new("invalid").make_move(&Move::new("e2", "e4")),
+            Err(Error::InvalidFen(_))
+        ));
+        assert!(!start.is_legal(&Move::new("e7", "e5")));
+        assert!(!start.is_legal(&Move::new("e1", "e2")));
+    }
+    #[test]
+    fn test_castling_moves_and_rights() {
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 3 10";
+        assert_eq!(
+            after(fen, "e1g1"),
+            "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 b kq - 4 10"
+        );
+        assert_eq!(
+            after(fen, "e1c1"),
+            "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/2KR3R b kq - 4 10"
+        );
+        assert_eq!(
+            after(fen, "e1h1"),
+            "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 b kq - 4 10"
+        );
+        let open = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(after(open, "h1h5"), "r3k2r/8/8/7R/8/8/8/R3K3 b Qkq - 1 1");
+        assert_eq!(after(open, "a1a8"), "R3k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+        assert_eq!(after(open, "e1f1"), "r3k2r/8/8/8/8/8/8/R4K1R b kq - 1 1");
+        let attacked = ChessPosition::new("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1");
+        assert!(!attacked.is_legal(&Move::new("e1", "g1")));
+        assert!(attacked.is_legal(&Move::new("e1", "c1")));
+        let checked = ChessPosition::new("r3k2r/8/8/8/8/8/4r3/R3K2R w KQkq - 0 1");
+        assert!(!checked.is_legal(&Move::new("e1", "c1")));
+        let blocked = ChessPosition::new("r3k2r/8/8/8/8/8/8/RN2K2R w KQkq - 0 1");
+        assert!(!blocked.is_legal(&Move::new("e1", "c1")));
+        let frc = "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1";
+        assert_eq!(
+            after(frc, "e1g1"),
+            "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R3RK1 b kq - 1 1"
+        );
+        assert_eq!(
+            after(frc, "e1b1"),
+            "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/2KR2R1 b kq - 1 1"
+        );
+    }
+    #[test]
+    fn test_unknown_castling_characters_are_rejected() {
+        for castling in ["Kx", "K1", "Z", "KQkq!"] {
+            let position = ChessPosition::new(format!(
+                "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w {} - 0 1",
+                castling
+            ));
+            assert!(position.play("e1g1").is_none(), "{}", castling);
+            assert!(!position.is_checkmate());
+            assert!(position.position_key().is_none());
+        }
+    }
+    #[test]
+    fn test_en_passant() {
+        let fen = "4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1";
+        let pushed = after(fen, "d7d5");
+        assert_eq!(pushed, "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+        assert_eq!(after(&pushed, "e5d6"), "4k3/8/3P4/8/8/8/8/4K3 b - - 0 2");
+        let waited = after(&after(&pushed, "e1e2"), "e8e7");
+        assert!(!ChessPosition::new(waited).is_legal(&Move::new("e5", "d6")));
+        let pinned = ChessPosition::new("8/8/8/K2pP2r/8/8/8/4k3 w - d6 0 1");
+        assert!(!pinned.is_legal(&Move::new("e5", "d6")));
+        assert_eq!(
+            ChessPosition::new(&pushed).position_key().unwrap(),
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6"
+        );
+        assert_eq!(
+            pinned.position_key().unwrap(),
+            "8/8/8/K2pP2r/8/8/8/4k3 w - -"
+        );
+    }
+    #[test]
+    fn test_promotion() {
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(after(fen, "a7a8q"), "Qn2k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(after(fen, "a7b8n"), "1N2k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        let position = ChessPosition::new(fen);
+        assert!(!position.is_legal(&Move::new("a7", "a8")));
+        assert!(!position.is_legal(&Move::new("a7", "a8").with_promotion('k')));
+        let black = "4k3/8/8/8/8/8/p7/4K3 b - - 0 1";
+        assert_eq!(after(black, "a2a1r"), "4k3/8/8/8/8/8/8/r3K3 w - - 0 2");
+    }
+    #[test]
+    fn test_checkmate_and_stalemate() {
+        let mated =
+            ChessPosition::new("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert!(mated.is_checkmate());
+        assert!(!mated.is_stalemate());
+        let stalemate = ChessPosition::new("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(stalemate.is_stalemate());
+        assert!(!ChessPosition::starting().is_checkmate());
     }
     #[test]
     fn test_color_from_fen() {
@@ -1,8 +1,10 @@
+use super::StoreHealth;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use uuid::Uuid;
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct NodeId(pub String);
 impl NodeId {
     pub fn generate() -> Self {
@@ -18,14 +20,17 @@ impl std::fmt::Display for NodeId {
     }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct NodeInfo {
     pub id: NodeId,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub address: SocketAddr,
     pub priority: u32,
     pub started_at: DateTime<Utc>,
     pub version: String,
 }
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum NodeState {
     Starting,
     Joining,
@@ -35,7 +40,74 @@ pub enum NodeState {
     Leaving,
     Dead,
 }
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveReason {
+    Left,
+    Unreachable,
+    Undiscovered,
+    Removed,
+    Replaced,
+}
+impl LeaveReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaveReason::Left => "left",
+            LeaveReason::Unreachable => "unreachable",
+            LeaveReason::Undiscovered => "undiscovered",
+            LeaveReason::Removed => "removed",
+            LeaveReason::Replaced => "replaced",
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClusterEvent {
+    NodeJoined {
+        node: NodeInfo,
+    },
+    NodeLeft {
+        node_id: NodeId,
+        reason: LeaveReason,
+    },
+    NodeDraining {
+        node_id: NodeId,
+        draining: bool,
+    },
+    NodeUnhealthy {
+        node_id: NodeId,
+        reason: String,
+    },
+    NodeRecovered {
+        node_id: NodeId,
+    },
+    LeaderChanged {
+        old_leader: Option<NodeId>,
+        new_leader: Option<NodeId>,
+        term: u64,
+    },
+    TokenRevoked {
+        token_id: Uuid,
+    },
+}
+impl ClusterEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClusterEvent::NodeJoined { .. } => "node_joined",
+            ClusterEvent::NodeLeft { .. } => "node_left",
+            ClusterEvent::NodeDraining { draining: true, .. } => "node_draining",
+            ClusterEvent::NodeDraining {
+                draining: false, ..
+            } => "node_resumed",
+            ClusterEvent::NodeUnhealthy { .. } => "node_unhealthy",
+            ClusterEvent::NodeRecovered { .. } => "node_recovered",
+            ClusterEvent::LeaderChanged { .. } => "leader_changed",
+            ClusterEvent::TokenRevoked { .. } => "token_revoked",
+        }
+    }
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct NodeStatus {
     pub info: NodeInfo,
     pub state: NodeState,
@@ -43,8 +115,127 @@ pub struct NodeStatus {
     pub term: u64,
     pub cluster_size: usize,
     pub uptime_seconds: u64,
+    #[serde(default)]
+    pub draining: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<NodeMetrics>,
+    #[serde(default)]
+    pub metrics_stale: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen_ms: Option<u64>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DrainStatus {
+    pub node_id: NodeId,
+    pub draining: bool,
+    pub active_analyses: u32,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ConfigChange {
+    pub key: String,
+    pub from: String,
+    pub to: String,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ConfigReloadReport {
+    pub applied: Vec<ConfigChange>,
+    pub skipped: Vec<String>,
+}
+impl ConfigReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.skipped.is_empty()
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EngineSlotStatus {
+    pub index: usize,
+    pub busy_since: Option<DateTime<Utc>>,
+    pub last_command: Option<String>,
+    pub restarts: u64,
+    #[serde(default)]
+    pub warmed: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EnginePoolStatus {
+    pub size: usize,
+    pub available: usize,
+    #[serde(default)]
+    pub reserved: usize,
+    #[serde(default)]
+    pub reserved_available: usize,
+    #[serde(default)]
+    pub general_available: usize,
+    pub active: usize,
+    pub queue_depth: usize,
+    pub engines: Vec<EngineSlotStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_name: Option<String>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ActiveAnalysisStatus {
+    pub id: Uuid,
+    pub depth: Option<u8>,
+    pub elapsed_ms: u64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub token_id: Option<Uuid>,
+    pub subscriptions: Vec<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PeerStatus {
+    pub node_id: NodeId,
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub address: SocketAddr,
+    pub healthy: bool,
+    pub last_seen_ms: u64,
+    #[serde(default)]
+    pub queued: usize,
+    #[serde(default)]
+    pub dropped: u64,
+    #[serde(default)]
+    pub connect_failures: u32,
+    #[serde(default)]
+    pub backoff_ms: u64,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GossipStoreStatus {
+    pub entries: usize,
+    pub tombstones: usize,
+    pub oldest_entry_age_seconds: u64,
+    pub clock: u64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NodeDiagnostics {
+    pub node_id: NodeId,
+    pub state: NodeState,
+    pub term: u64,
+    pub leader_id: Option<NodeId>,
+    pub draining: bool,
+    pub uptime_seconds: u64,
+    pub memory_usage: f32,
+    pub engine_pool: EnginePoolStatus,
+    pub analyses: Vec<ActiveAnalysisStatus>,
+    pub ws_sessions: Vec<SessionSummary>,
+    pub peers: Vec<PeerStatus>,
+    #[serde(default)]
+    pub gossip: Option<GossipStoreStatus>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct NodeMetrics {
     pub cpu_usage: f32,
     pub memory_usage: f32,
@@ -54,6 +245,8 @@ pub struct NodeMetrics {
     pub total_requests: u64,
     pub engines_available: u32,
     pub engines_total: u32,
+    #[serde(default)]
+    pub draining: bool,
 }
 impl Default for NodeMetrics {
     fn default() -> Self {
@@ -66,10 +259,59 @@ impl Default for NodeMetrics {
             total_requests: 0,
             engines_available: 0,
             engines_total: 0,
+            draining: false,
         }
     }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthResponse {
+    pub status: String,
+    pub node_id: String,
+    pub version: String,
+    #[serde(default)]
+    pub token_store: StoreHealth,
+    #[serde(default)]
+    pub checks: Vec<HealthCheck>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skipped,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+impl HealthCheck {
+    pub fn passed(&self) -> bool {
+        self.status != CheckStatus::Fail
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MetricsResponse {
+    pub cpu_usage: f32,
+    pub memory_usage: f32,
+    pub active_analyses: u32,
+    pub queue_depth: u32,
+    pub engines_available: u32,
+    pub engines_total: u32,
+    #[serde(default)]
+    pub engines_restarted: u64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct ClusterStatus {
     pub nodes: Vec<NodeStatus>,
     pub leader: Option<NodeId>,
@@ -81,11 +323,14 @@ pub struct JoinRequest {
     pub node_info: NodeInfo,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct JoinResponse {
     pub accepted: bool,
     pub leader_id: Option<NodeId>,
     pub members: Vec<NodeInfo>,
     pub term: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HeartbeatRequest {
@@ -0,0 +1,274 @@
+use super::{ChessPosition, Move, Score, TokenContext, Variant};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+const EVAL_CEILING: i32 = 1000;
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Game {
+    pub start_fen: String,
+    #[serde(default)]
+    pub variant: Variant,
+    pub moves: Vec<String>,
+}
+#[derive(Debug, Clone)]
+pub struct PlayedMove {
+    pub notation: String,
+    pub mv: Move,
+    pub fen: String,
+}
+impl Game {
+    pub fn new(moves: Vec<String>) -> Self {
+        Self {
+            start_fen: ChessPosition::starting().fen,
+            variant: Variant::Standard,
+            moves,
+        }
+    }
+    pub fn with_start_fen(mut self, fen: impl Into<String>) -> Self {
+        self.start_fen = fen.into();
+        self
+    }
+    pub fn with_variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+    pub fn from_pgn(pgn: &str) -> Self {
+        let mut game = Self::new(Vec::new());
+        let mut movetext = String::new();
+        for line in pgn.lines() {
+            let line = line.trim();
+            match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                Some(tag) => {
+                    let (name, value) = tag.split_once(' ').unwrap_or((tag, ""));
+                    let value = value.trim().trim_matches('"');
+                    match name {
+                        "FEN" => game.start_fen = value.to_string(),
+                        "Variant" if value.to_lowercase().contains("960") => {
+                            game.variant = Variant::Chess960
+                        }
+                        _ => {}
+                    }
+                }
+                None => {
+                    movetext.push_str(line.split(';').next().unwrap_or(""));
+                    movetext.push(' ');
+                }
+            }
+        }
+        let mut depth = 0usize;
+        let mut in_comment = false;
+        let mut stripped = String::with_capacity(movetext.len());
+        for c in movetext.chars() {
+            match c {
+                '{' if !in_comment => in_comment = true,
+                '}' if in_comment => in_comment = false,
+                '(' if !in_comment => depth += 1,
+                ')' if !in_comment => depth = depth.saturating_sub(1),
+                _ if in_comment || depth > 0 => {}
+                c => stripped.push(c),
+            }
+            if matches!(c, '{' | '}' | '(' | ')') {
+                stripped.push(' ');
+            }
+        }
+        game.moves = stripped
+            .split_whitespace()
+            .filter(|token| !matches!(*token, "1-0" | "0-1" | "1/2-1/2" | "*"))
+            .map(|token| token.rsplit('.').next().unwrap_or(token))
+            .filter(|token| !token.is_empty() && !token.starts_with('$'))
+            .map(String::from)
+            .collect();
+        game
+    }
+    pub fn replay(&self) -> Result<Vec<PlayedMove>> {
+        let mut position = ChessPosition::new(&self.start_fen);
+        position.validate_for(self.variant)?;
+        let mut played = Vec::with_capacity(self.moves.len());
+        for (index, notation) in self.moves.iter().enumerate() {
+            let (mv, next) = position.play(notation).ok_or_else(|| Error::InvalidMove {
+                index,
+                notation: notation.clone(),
+            })?;
+            played.push(PlayedMove {
+                notation: notation.clone(),
+                mv,
+                fen: next.fen.clone(),
+            });
+            position = next;
+        }
+        Ok(played)
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MoveClassification {
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(default)]
+pub struct ClassificationThresholds {
+    pub inaccuracy: u32,
+    pub mistake: u32,
+    pub blunder: u32,
+}
+impl Default for ClassificationThresholds {
+    fn default() -> Self {
+        Self {
+            inaccuracy: 50,
+            mistake: 100,
+            blunder: 300,
+        }
+    }
+}
+impl ClassificationThresholds {
+    pub fn classify(&self, centipawn_loss: u32) -> MoveClassification {
+        match centipawn_loss {
+            0 => MoveClassification::Best,
+            loss if loss >= self.blunder => MoveClassification::Blunder,
+            loss if loss >= self.mistake => MoveClassification::Mistake,
+            loss if loss >= self.inaccuracy => MoveClassification::Inaccuracy,
+            _ => MoveClassification::Good,
+        }
+    }
+}
+impl Score {
+    pub fn as_centipawns(&self) -> i32 {
+        match *self {
+            Score::Centipawns(cp) => cp.clamp(-EVAL_CEILING, EVAL_CEILING),
+            Score::MateIn(moves) if moves > 0 => EVAL_CEILING,
+            Score::MateIn(_) => -EVAL_CEILING,
+            Score::Tablebase(_) => self.value().signum() * EVAL_CEILING,
+        }
+    }
+}
+pub fn centipawn_loss(before: &Score, after: &Score) -> u32 {
+    (before.as_centipawns() + after.as_centipawns()).max(0) as u32
+}
+#[derive(Debug, Clone)]
+pub struct GameAnalysisRequest {
+    pub id: Uuid,
+    pub game: Game,
+    pub depth: u8,
+    pub movetime: Option<u64>,
+    pub thresholds: ClassificationThresholds,
+    pub concurrency: usize,
+    pub token: TokenContext,
+}
+impl GameAnalysisRequest {
+    pub fn new(game: Game) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            game,
+            depth: 12,
+            movetime: None,
+            thresholds: ClassificationThresholds::default(),
+            concurrency: 1,
+            token: TokenContext::default(),
+        }
+    }
+    pub fn with_depth(mut self, depth: u8) -> Self {
+        self.depth = depth;
+        self
+    }
+    pub fn with_movetime(mut self, ms: u64) -> Self {
+        self.movetime = Some(ms);
+        self
+    }
+    pub fn with_thresholds(mut self, thresholds: ClassificationThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+    pub fn with_token(mut self, token: TokenContext) -> Self {
+        self.token = token;
+        self
+    }
+    pub fn positions(&self) -> usize {
+        self.game.moves.len() + 1
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MoveAnalysis {
+    pub ply: usize,
+    #[serde(rename = "move")]
+    pub mv: Move,
+    pub fen: String,
+    pub evaluation: Score,
+    pub best_move: Move,
+    pub centipawn_loss: u32,
+    pub classification: MoveClassification,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameAnalysis {
+    pub id: Uuid,
+    pub start_fen: String,
+    pub variant: Variant,
+    pub depth: u8,
+    pub moves: Vec<MoveAnalysis>,
+    #[serde(default)]
+    pub clamped: bool,
+    #[serde(default)]
+    pub nodes_searched: u64,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_pgn_parsing() {
+        let pgn = r#"[Event "Casual"]
+[White "A"]
+[Black "B"]
+
+1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 $1 3.Bb5 a6; Ruy Lopez
+4. Ba4 Nf6 5. O-O 1-0"#;
+        let game = Game::from_pgn(pgn);
+        assert_eq!(
+            game.moves,
+            vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O"]
+        );
+        let played = game.replay().unwrap();
+        assert_eq!(played[8].mv.to_uci(), "e1g1");
+        assert_eq!(
+            played[8].fen,
+            "r1bqkb1r/1ppp1ppp/p1n2n2/4p3/B3P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 3 5"
+        );
+    }
+    #[test]
+    fn test_replay_reports_offending_move() {
+        let game = Game::new(vec!["e2e4".into(), "e7e5".into(), "Ke3".into()]);
+        assert!(matches!(
+            game.replay(),
+            Err(Error::InvalidMove { index: 2, ref notation }) if notation == "Ke3"
+        ));
+        let game = Game::from_pgn("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n1. e4");
+        assert!(matches!(
+            game.replay(),
+            Err(Error::InvalidMove { index: 0, .. })
+        ));
+    }
+    #[test]
+    fn test_classification() {
+        let thresholds = ClassificationThresholds::default();
+        assert_eq!(thresholds.classify(0), MoveClassification::Best);
+        assert_eq!(thresholds.classify(20), MoveClassification::Good);
+        assert_eq!(thresholds.classify(60), MoveClassification::Inaccuracy);
+        assert_eq!(thresholds.classify(150), MoveClassification::Mistake);
+        assert_eq!(thresholds.classify(400), MoveClassification::Blunder);
+        let before = Score::Centipawns(40);
+        assert_eq!(centipawn_loss(&before, &Score::Centipawns(-40)), 0);
+        assert_eq!(centipawn_loss(&before, &Score::Centipawns(260)), 300);
+        assert_eq!(centipawn_loss(&before, &Score::MateIn(3)), 1040);
+        assert_eq!(centipawn_loss(&Score::MateIn(2), &Score::MateIn(-1)), 0);
+    }
+}
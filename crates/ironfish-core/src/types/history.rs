@@ -0,0 +1,55 @@
+use super::{AnalysisResult, Score};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AnalysisRecord {
+    #[serde(default)]
+    pub seq: u64,
+    pub id: Uuid,
+    pub fen: String,
+    pub depth: u8,
+    pub evaluation: Score,
+    pub time_ms: u64,
+    pub completed_at: DateTime<Utc>,
+    pub token_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+impl AnalysisRecord {
+    pub fn new(result: &AnalysisResult, token_id: Option<Uuid>) -> Self {
+        Self {
+            seq: 0,
+            id: result.id,
+            fen: result.fen.clone(),
+            depth: result.depth_reached,
+            evaluation: result.evaluation,
+            time_ms: result.time_ms,
+            completed_at: Utc::now(),
+            token_id,
+            idempotency_key: None,
+        }
+    }
+    pub fn with_idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    pub before: Option<u64>,
+    pub token_id: Option<Uuid>,
+    pub limit: Option<usize>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HistoryPage {
+    pub records: Vec<AnalysisRecord>,
+    pub next_before: Option<u64>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryRetention {
+    pub max_age_days: Option<u32>,
+    pub max_entries: Option<usize>,
+}
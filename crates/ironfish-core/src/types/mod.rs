@@ -1,8 +1,20 @@
 mod analysis;
+mod audit;
 mod chess;
 mod cluster;
+mod game;
+mod history;
+mod play;
+mod score;
 mod token;
+mod ws;
 pub use analysis::*;
+pub use audit::*;
 pub use chess::*;
 pub use cluster::*;
+pub use game::*;
+pub use history::*;
+pub use play::*;
+pub use score::*;
 pub use token::*;
+pub use ws::*;
@@ -0,0 +1,27 @@
+use super::{BestMoveRequest, GameOver, Move, Score};
+use serde::{Deserialize, Serialize};
+#[derive(Debug, Clone)]
+pub enum PlayCommand {
+    Position {
+        request: BestMoveRequest,
+        ponder: bool,
+    },
+    OpponentMove(String),
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlayUpdate {
+    Evaluation {
+        depth: u8,
+        evaluation: Score,
+        pv: Vec<Move>,
+        pondering: bool,
+    },
+    BestMove {
+        best_move: Option<Move>,
+        ponder: Option<Move>,
+        ponder_hit: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        game_over: Option<GameOver>,
+    },
+}
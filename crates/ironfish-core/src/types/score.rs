@@ -0,0 +1,235 @@
+use super::TablebaseWdl;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "openapi")]
+use std::borrow::Cow;
+use std::cmp::Ordering;
+#[cfg(feature = "openapi")]
+use utoipa::openapi::{RefOr, Schema};
+#[cfg(feature = "openapi")]
+use utoipa::{PartialSchema, ToSchema};
+const TABLEBASE_WIN_CP: i32 = 20_000 - 246;
+const MAX_CP: i32 = 30_000;
+const TABLEBASE_CP: i32 = 50_000;
+const MATE_CP: i32 = 100_000;
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub enum ScoreType {
+    Centipawns,
+    Mate,
+    Tablebase,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    MateIn(i32),
+    Tablebase(TablebaseWdl),
+}
+impl Score {
+    pub fn score_type(&self) -> ScoreType {
+        match self {
+            Score::Centipawns(_) => ScoreType::Centipawns,
+            Score::MateIn(_) => ScoreType::Mate,
+            Score::Tablebase(_) => ScoreType::Tablebase,
+        }
+    }
+    pub fn value(&self) -> i32 {
+        match *self {
+            Score::Centipawns(cp) => cp,
+            Score::MateIn(moves) => moves,
+            Score::Tablebase(TablebaseWdl::Win) => 1,
+            Score::Tablebase(TablebaseWdl::Draw) => 0,
+            Score::Tablebase(TablebaseWdl::Loss) => -1,
+        }
+    }
+    pub fn is_mate(&self) -> bool {
+        matches!(self, Score::MateIn(_))
+    }
+    pub fn to_pawns(&self) -> Option<f64> {
+        match *self {
+            Score::Centipawns(cp) => Some(cp as f64 / 100.0),
+            _ => None,
+        }
+    }
+    pub fn as_cp_clamped(&self) -> i32 {
+        match *self {
+            Score::Centipawns(cp) => cp.clamp(-MAX_CP, MAX_CP),
+            Score::MateIn(moves) if moves > 0 => MATE_CP - moves.min(MATE_CP - TABLEBASE_CP - 1),
+            Score::MateIn(moves) => -MATE_CP - moves.max(-(MATE_CP - TABLEBASE_CP - 1)),
+            Score::Tablebase(TablebaseWdl::Win) => TABLEBASE_CP,
+            Score::Tablebase(TablebaseWdl::Draw) => 0,
+            Score::Tablebase(TablebaseWdl::Loss) => -TABLEBASE_CP,
+        }
+    }
+    pub fn negated(&self) -> Self {
+        match *self {
+            Score::Centipawns(cp) => Score::Centipawns(-cp),
+            Score::MateIn(moves) => Score::MateIn(-moves),
+            Score::Tablebase(TablebaseWdl::Win) => Score::Tablebase(TablebaseWdl::Loss),
+            Score::Tablebase(TablebaseWdl::Draw) => Score::Tablebase(TablebaseWdl::Draw),
+            Score::Tablebase(TablebaseWdl::Loss) => Score::Tablebase(TablebaseWdl::Win),
+        }
+    }
+    pub fn tablebase_wdl(&self, in_tables: bool) -> Option<TablebaseWdl> {
+        match *self {
+            Score::Centipawns(cp) if cp >= TABLEBASE_WIN_CP => Some(TablebaseWdl::Win),
+            Score::Centipawns(cp) if cp <= -TABLEBASE_WIN_CP => Some(TablebaseWdl::Loss),
+            Score::Centipawns(0) if in_tables => Some(TablebaseWdl::Draw),
+            _ => None,
+        }
+    }
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Score::Centipawns(_) => 0,
+            Score::Tablebase(_) => 1,
+            Score::MateIn(_) => 2,
+        }
+    }
+}
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_cp_clamped()
+            .cmp(&other.as_cp_clamped())
+            .then_with(|| self.kind_rank().cmp(&other.kind_rank()))
+            .then_with(|| self.value().cmp(&other.value()))
+    }
+}
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+struct ScoreWire {
+    score_type: ScoreType,
+    value: i32,
+}
+impl Serialize for Score {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        ScoreWire {
+            score_type: self.score_type(),
+            value: self.value(),
+        }
+        .serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for Score {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let wire = ScoreWire::deserialize(deserializer)?;
+        Ok(match wire.score_type {
+            ScoreType::Centipawns => Score::Centipawns(wire.value),
+            ScoreType::Mate => Score::MateIn(wire.value),
+            ScoreType::Tablebase => Score::Tablebase(match wire.value.signum() {
+                1 => TablebaseWdl::Win,
+                0 => TablebaseWdl::Draw,
+                _ => TablebaseWdl::Loss,
+            }),
+        })
+    }
+}
+#[cfg(feature = "openapi")]
+impl PartialSchema for Score {
+    fn schema() -> RefOr<Schema> {
+        ScoreWire::schema()
+    }
+}
+#[cfg(feature = "openapi")]
+impl ToSchema for Score {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("Score")
+    }
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        ScoreWire::schemas(schemas);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn samples() -> Vec<Score> {
+        let mut scores = Vec::new();
+        for cp in (-40_000..=40_000).step_by(997) {
+            scores.push(Score::Centipawns(cp));
+        }
+        for cp in [-i32::MAX, -1, 0, 1, i32::MAX] {
+            scores.push(Score::Centipawns(cp));
+        }
+        for moves in (-60..=60).chain([i32::MIN + 1, i32::MAX]) {
+            scores.push(Score::MateIn(moves));
+        }
+        for wdl in [TablebaseWdl::Win, TablebaseWdl::Draw, TablebaseWdl::Loss] {
+            scores.push(Score::Tablebase(wdl));
+        }
+        scores
+    }
+    #[test]
+    fn test_mate_for_beats_centipawns_beats_mate_against() {
+        for score in samples() {
+            match score {
+                Score::MateIn(moves) if moves > 0 => {
+                    assert!(score > Score::Centipawns(i32::MAX));
+                    assert!(score > Score::Tablebase(TablebaseWdl::Win));
+                }
+                Score::MateIn(_) => {
+                    assert!(score < Score::Centipawns(i32::MIN));
+                    assert!(score < Score::Tablebase(TablebaseWdl::Loss));
+                }
+                _ => {
+                    assert!(score < Score::MateIn(i32::MAX));
+                    assert!(score > Score::MateIn(0));
+                }
+            }
+        }
+        assert!(Score::MateIn(1) > Score::MateIn(3));
+        assert!(Score::MateIn(-5) > Score::MateIn(-1));
+        assert!(Score::MateIn(-1) > Score::MateIn(0));
+        assert!(Score::Centipawns(35) > Score::Centipawns(-20));
+    }
+    #[test]
+    fn test_ordering_is_total_and_consistent() {
+        let scores = samples();
+        for a in &scores {
+            assert_eq!(a.cmp(a), Ordering::Equal);
+            assert_eq!(a.negated().negated(), *a);
+            for b in &scores {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                assert_eq!(a.cmp(b) == Ordering::Equal, a == b);
+                if a.as_cp_clamped() != b.as_cp_clamped() {
+                    assert_eq!(a.cmp(b), a.as_cp_clamped().cmp(&b.as_cp_clamped()));
+                }
+            }
+        }
+        let mut sorted = scores.clone();
+        sorted.sort();
+        assert!(sorted.windows(2).all(|w| w[0] <= w[1]));
+        for pair in sorted.windows(2) {
+            let mated = pair.contains(&Score::MateIn(0));
+            if !mated && pair[0] != pair[1] && pair[0].score_type() == pair[1].score_type() {
+                assert!(pair[0].negated() > pair[1].negated());
+            }
+        }
+    }
+    #[test]
+    fn test_serde_matches_legacy_shape() {
+        for score in samples() {
+            let json = serde_json::to_value(score).unwrap();
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "score_type": serde_json::to_value(score.score_type()).unwrap(),
+                    "value": score.value(),
+                })
+            );
+            assert_eq!(serde_json::from_value::<Score>(json).unwrap(), score);
+        }
+        let legacy: Score = serde_json::from_str(r#"{"score_type":"Mate","value":-3}"#).unwrap();
+        assert_eq!(legacy, Score::MateIn(-3));
+        let legacy: Score =
+            serde_json::from_str(r#"{"score_type":"Centipawns","value":3}"#).unwrap();
+        assert_eq!(legacy.to_pawns(), Some(0.03));
+        assert!(!legacy.is_mate());
+        let legacy: Score =
+            serde_json::from_str(r#"{"score_type":"Tablebase","value":-1}"#).unwrap();
+        assert_eq!(legacy, Score::Tablebase(TablebaseWdl::Loss));
+        assert!(serde_json::from_str::<Score>(r#"{"score_type":"Pawns","value":1}"#).is_err());
+    }
+}
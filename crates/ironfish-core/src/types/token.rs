@@ -1,6 +1,160 @@
-use chrono::{DateTime, Utc};
+use super::AnalysisRequest;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
+pub const SCOPE_ANALYZE: &str = "analyze";
+pub const SCOPE_BESTMOVE: &str = "bestmove";
+pub const SCOPE_WS: &str = "ws";
+pub const SCOPE_CLUSTER_READ: &str = "cluster:read";
+pub const SCOPE_CLUSTER_WRITE: &str = "cluster:write";
+pub const TOKEN_SCOPES: &[&str] = &[
+    SCOPE_ANALYZE,
+    SCOPE_BESTMOVE,
+    SCOPE_WS,
+    SCOPE_CLUSTER_READ,
+    SCOPE_CLUSTER_WRITE,
+];
+pub const TOKEN_USAGE_DAYS: usize = 30;
+pub const NODE_BUDGET_WINDOW_SECS: i64 = 3600;
+pub const NODE_BUDGET_BUCKET_SECS: i64 = 60;
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DailyUsage {
+    pub date: NaiveDate,
+    pub count: u64,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageDelta {
+    pub count: u64,
+    pub last_used_at: DateTime<Utc>,
+    pub last_used_ip: Option<String>,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NodeBucket {
+    pub started_at: DateTime<Utc>,
+    pub nodes: u64,
+}
+impl NodeBucket {
+    pub fn resets_at(&self) -> DateTime<Utc> {
+        self.started_at + Duration::seconds(NODE_BUDGET_WINDOW_SECS)
+    }
+    pub fn is_current(&self, now: DateTime<Utc>) -> bool {
+        now < self.resets_at()
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeReservation {
+    pub bucket: DateTime<Utc>,
+    pub nodes: u64,
+    pub remaining: u64,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NodeWindow {
+    #[serde(default)]
+    pub buckets: Vec<NodeBucket>,
+}
+impl NodeWindow {
+    pub fn bucket_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = now.timestamp();
+        DateTime::from_timestamp(secs - secs.rem_euclid(NODE_BUDGET_BUCKET_SECS), 0).unwrap_or(now)
+    }
+    pub fn used(&self, now: DateTime<Utc>) -> u64 {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.is_current(now))
+            .map(|bucket| bucket.nodes)
+            .sum()
+    }
+    pub fn resets_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.nodes > 0 && bucket.is_current(now))
+            .map(NodeBucket::resets_at)
+            .min()
+    }
+    pub fn reserve(
+        &mut self,
+        now: DateTime<Utc>,
+        budget: u64,
+        nodes: u64,
+    ) -> crate::Result<NodeReservation> {
+        self.buckets.retain(|bucket| bucket.is_current(now));
+        let used = self.used(now);
+        if used >= budget {
+            return Err(crate::Error::NodeBudgetExhausted {
+                resets_at: self
+                    .resets_at(now)
+                    .unwrap_or_else(|| now + Duration::seconds(NODE_BUDGET_BUCKET_SECS)),
+            });
+        }
+        let nodes = nodes.min(budget - used);
+        let bucket = Self::bucket_start(now);
+        self.add(bucket, nodes);
+        Ok(NodeReservation {
+            bucket,
+            nodes,
+            remaining: budget - used - nodes,
+        })
+    }
+    pub fn settle(&mut self, now: DateTime<Utc>, reservation: &NodeReservation, nodes: u64) {
+        if let Some(bucket) = self
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.started_at == reservation.bucket)
+        {
+            bucket.nodes = bucket.nodes.saturating_sub(reservation.nodes);
+        }
+        self.add(Self::bucket_start(now), nodes);
+        self.buckets
+            .retain(|bucket| bucket.nodes > 0 && bucket.is_current(now));
+    }
+    fn add(&mut self, started_at: DateTime<Utc>, nodes: u64) {
+        match self
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.started_at == started_at)
+        {
+            Some(bucket) => bucket.nodes = bucket.nodes.saturating_add(nodes),
+            None => {
+                self.buckets.push(NodeBucket { started_at, nodes });
+                self.buckets.sort_by_key(|bucket| bucket.started_at);
+            }
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NodeBudgetStatus {
+    pub budget_per_hour: u64,
+    pub used: u64,
+    pub remaining: u64,
+    pub resets_at: Option<DateTime<Utc>>,
+}
+impl NodeBudgetStatus {
+    pub fn new(budget_per_hour: u64, window: Option<&NodeWindow>, now: DateTime<Utc>) -> Self {
+        let used = window.map_or(0, |w| w.used(now));
+        Self {
+            budget_per_hour,
+            used,
+            remaining: budget_per_hour.saturating_sub(used),
+            resets_at: window.and_then(|w| w.resets_at(now)),
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TokenUsage {
+    pub id: Uuid,
+    pub usage_count: u64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+    pub daily: Vec<DailyUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_budget: Option<NodeBudgetStatus>,
+}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiToken {
     pub id: Uuid,
@@ -12,8 +166,63 @@ pub struct ApiToken {
     pub created_by_node: String,
     pub revoked: bool,
     pub rate_limit: Option<u32>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub usage_count: u64,
+    #[serde(default)]
+    pub last_used_ip: Option<String>,
+    #[serde(default)]
+    pub daily_usage: Vec<DailyUsage>,
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    #[serde(default)]
+    pub max_multipv: Option<u32>,
+    #[serde(default)]
+    pub node_budget_per_hour: Option<u64>,
+    #[serde(default)]
+    pub node_window: Option<NodeWindow>,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime<Utc>>,
 }
 impl ApiToken {
+    pub fn apply_usage(&mut self, delta: &UsageDelta) {
+        self.usage_count += delta.count;
+        if self.last_used_at.is_none_or(|t| t <= delta.last_used_at) {
+            self.last_used_at = Some(delta.last_used_at);
+            if delta.last_used_ip.is_some() {
+                self.last_used_ip = delta.last_used_ip.clone();
+            }
+        }
+        let date = delta.last_used_at.date_naive();
+        match self.daily_usage.iter_mut().find(|d| d.date == date) {
+            Some(day) => day.count += delta.count,
+            None => {
+                self.daily_usage.push(DailyUsage {
+                    date,
+                    count: delta.count,
+                });
+                self.daily_usage.sort_by_key(|d| d.date);
+            }
+        }
+        let excess = self.daily_usage.len().saturating_sub(TOKEN_USAGE_DAYS);
+        self.daily_usage.drain(..excess);
+    }
+    pub fn usage(&self) -> TokenUsage {
+        TokenUsage {
+            id: self.id,
+            usage_count: self.usage_count,
+            last_used_at: self.last_used_at,
+            last_used_ip: self.last_used_ip.clone(),
+            daily: self.daily_usage.clone(),
+            node_budget: self
+                .node_budget_per_hour
+                .map(|budget| NodeBudgetStatus::new(budget, self.node_window.as_ref(), Utc::now())),
+        }
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.is_empty() || self.scopes.iter().any(|s| s == scope)
+    }
     pub fn is_valid(&self) -> bool {
         if self.revoked {
             return false;
@@ -27,6 +236,7 @@ impl ApiToken {
     }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct TokenMetadata {
     pub id: Uuid,
     pub name: Option<String>,
@@ -34,6 +244,18 @@ pub struct TokenMetadata {
     pub expires_at: Option<DateTime<Utc>>,
     pub last_used_at: Option<DateTime<Utc>>,
     pub revoked: bool,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub usage_count: u64,
+    #[serde(default)]
+    pub last_used_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_multipv: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_budget_per_hour: Option<u64>,
 }
 impl From<&ApiToken> for TokenMetadata {
     fn from(token: &ApiToken) -> Self {
@@ -44,6 +266,89 @@ impl From<&ApiToken> for TokenMetadata {
             expires_at: token.expires_at,
             last_used_at: token.last_used_at,
             revoked: token.revoked,
+            scopes: token.scopes.clone(),
+            usage_count: token.usage_count,
+            last_used_ip: token.last_used_ip.clone(),
+            max_depth: token.max_depth,
+            max_multipv: token.max_multipv,
+            node_budget_per_hour: token.node_budget_per_hour,
+        }
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenContext {
+    pub id: Option<Uuid>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    #[serde(default)]
+    pub max_multipv: Option<u32>,
+    #[serde(default)]
+    pub node_budget_per_hour: Option<u64>,
+}
+impl TokenContext {
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+    pub fn service(node_id: &str) -> Self {
+        Self {
+            name: Some(format!("node:{}", node_id)),
+            scopes: vec![SCOPE_WS.to_string(), SCOPE_ANALYZE.to_string()],
+            ..Self::default()
+        }
+    }
+    pub fn is_anonymous(&self) -> bool {
+        self.id.is_none()
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.is_empty() || self.scopes.iter().any(|s| s == scope)
+    }
+    pub fn can_manage_tokens(&self) -> bool {
+        self.scopes.is_empty()
+    }
+    pub fn can_grant(&self, request: &CreateTokenRequest) -> bool {
+        fn within<T: PartialOrd>(limit: Option<T>, granted: Option<T>) -> bool {
+            match (limit, granted) {
+                (Some(limit), Some(granted)) => granted <= limit,
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+        }
+        self.can_manage_tokens()
+            && within(self.max_depth, request.max_depth)
+            && within(self.max_multipv, request.max_multipv)
+            && within(self.node_budget_per_hour, request.node_budget_per_hour)
+    }
+    pub fn clamp(&self, mut request: AnalysisRequest) -> AnalysisRequest {
+        if let Some(max) = self.max_depth {
+            let max = max.clamp(1, u8::MAX.into()) as u8;
+            if request.infinite || request.depth > max {
+                request.infinite = false;
+                request.depth = max;
+                request.clamped = true;
+            }
+        }
+        if let Some(max) = self.max_multipv {
+            let max = max.clamp(1, u8::MAX.into()) as u8;
+            if request.multipv > max {
+                request.multipv = max;
+                request.clamped = true;
+            }
+        }
+        request
+    }
+}
+impl From<&ApiToken> for TokenContext {
+    fn from(token: &ApiToken) -> Self {
+        Self {
+            id: Some(token.id),
+            name: token.name.clone(),
+            scopes: token.scopes.clone(),
+            max_depth: token.max_depth,
+            max_multipv: token.max_multipv,
+            node_budget_per_hour: token.node_budget_per_hour,
         }
     }
 }
@@ -52,10 +357,443 @@ pub struct CreateTokenRequest {
     pub name: Option<String>,
     pub expires_in_days: Option<u32>,
     pub rate_limit: Option<u32>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    #[serde(default)]
+    pub max_multipv: Option<u32>,
+    #[serde(default)]
+    pub node_budget_per_hour: Option<u64>,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenRetention {
+    pub expired_grace_days: u32,
+    pub revoked_retention_days: u32,
+}
+impl Default for TokenRetention {
+    fn default() -> Self {
+        Self {
+            expired_grace_days: 7,
+            revoked_retention_days: 30,
+        }
+    }
+}
+impl TokenRetention {
+    pub fn is_purgeable(&self, token: &ApiToken, now: DateTime<Utc>) -> bool {
+        if token.revoked {
+            let revoked_at = token
+                .revoked_at
+                .or(token.last_used_at)
+                .unwrap_or(token.created_at);
+            if now - revoked_at > Duration::days(self.revoked_retention_days.into()) {
+                return true;
+            }
+        }
+        token.expires_at.is_some_and(|expires_at| {
+            now - expires_at > Duration::days(self.expired_grace_days.into())
+        })
+    }
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenFilter {
+    pub revoked: Option<bool>,
+    pub expired: Option<bool>,
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+}
+impl TokenFilter {
+    pub fn matches(&self, token: &ApiToken, now: DateTime<Utc>) -> bool {
+        if self.revoked.is_some_and(|revoked| token.revoked != revoked) {
+            return false;
+        }
+        let expired = token.expires_at.is_some_and(|expires_at| expires_at <= now);
+        if self.expired.is_some_and(|wanted| expired != wanted) {
+            return false;
+        }
+        if let Some(needle) = &self.name_contains {
+            let needle = needle.to_lowercase();
+            if !token
+                .name
+                .as_deref()
+                .is_some_and(|name| name.to_lowercase().contains(&needle))
+            {
+                return false;
+            }
+        }
+        self.created_after
+            .is_none_or(|created_after| token.created_at > created_after)
+    }
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TokenOrder {
+    #[default]
+    CreatedAtDesc,
+    LastUsedDesc,
+}
+impl TokenOrder {
+    pub fn cursor(&self, token: &ApiToken) -> TokenCursor {
+        let at = match self {
+            TokenOrder::CreatedAtDesc => token.created_at.timestamp_millis(),
+            TokenOrder::LastUsedDesc => token
+                .last_used_at
+                .map(|t| t.timestamp_millis())
+                .unwrap_or(i64::MIN),
+        };
+        TokenCursor { at, id: token.id }
+    }
+}
+impl std::str::FromStr for TokenOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created_at_desc" => Ok(TokenOrder::CreatedAtDesc),
+            "last_used_desc" => Ok(TokenOrder::LastUsedDesc),
+            other => Err(format!("unknown token order: {}", other)),
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TokenCursor {
+    pub at: i64,
+    pub id: Uuid,
+}
+impl std::fmt::Display for TokenCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.at, self.id.simple())
+    }
+}
+impl std::str::FromStr for TokenCursor {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid token cursor: {}", s);
+        let (at, id) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Self {
+            at: at.parse().map_err(|_| invalid())?,
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+#[derive(Debug, Clone, Default)]
+pub struct TokenListQuery {
+    pub filter: TokenFilter,
+    pub order: TokenOrder,
+    pub after: Option<TokenCursor>,
+    pub limit: Option<usize>,
+}
+impl TokenListQuery {
+    pub fn paginate(&self, tokens: Vec<ApiToken>) -> TokenPage {
+        let now = Utc::now();
+        let mut tokens: Vec<(TokenCursor, ApiToken)> = tokens
+            .into_iter()
+            .filter(|token| self.filter.matches(token, now))
+            .map(|token| (self.order.cursor(&token), token))
+            .filter(|(cursor, _)| self.after.is_none_or(|after| *cursor < after))
+            .collect();
+        tokens.sort_by_key(|(cursor, _)| std::cmp::Reverse(*cursor));
+        let limit = self.limit.unwrap_or(usize::MAX).max(1);
+        let next_after = (tokens.len() > limit).then(|| tokens[limit - 1].0);
+        tokens.truncate(limit);
+        TokenPage {
+            tokens: tokens.into_iter().map(|(_, token)| token).collect(),
+            next_after,
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub struct TokenPage {
+    pub tokens: Vec<ApiToken>,
+    pub next_after: Option<TokenCursor>,
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum StoreHealth {
+    #[default]
+    Healthy,
+    Degraded,
+}
+impl StoreHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StoreHealth::Healthy => "healthy",
+            StoreHealth::Degraded => "degraded",
+        }
+    }
+    pub fn is_writable(&self) -> bool {
+        *self == StoreHealth::Healthy
+    }
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TokenReplicaStatus {
+    Revoked,
+    Present,
+    Unknown,
+    Unreachable,
+}
+impl TokenReplicaStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenReplicaStatus::Revoked => "revoked",
+            TokenReplicaStatus::Present => "present",
+            TokenReplicaStatus::Unknown => "unknown",
+            TokenReplicaStatus::Unreachable => "unreachable",
+        }
+    }
+    pub fn of(token: Option<&ApiToken>) -> Self {
+        match token {
+            Some(token) if token.revoked => TokenReplicaStatus::Revoked,
+            Some(_) => TokenReplicaStatus::Present,
+            None => TokenReplicaStatus::Unknown,
+        }
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TokenReplicaReport {
+    pub token_id: Uuid,
+    pub confirmed: bool,
+    pub nodes: BTreeMap<String, TokenReplicaStatus>,
+}
+impl TokenReplicaReport {
+    pub fn new(token_id: Uuid, nodes: BTreeMap<String, TokenReplicaStatus>) -> Self {
+        let confirmed = nodes.values().all(|status| {
+            matches!(
+                status,
+                TokenReplicaStatus::Revoked | TokenReplicaStatus::Unknown
+            )
+        });
+        Self {
+            token_id,
+            confirmed,
+            nodes,
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CreateTokenResponse {
     pub id: Uuid,
     pub token: String,
     pub expires_at: Option<DateTime<Utc>>,
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn token(expires_at: Option<DateTime<Utc>>, revoked_at: Option<DateTime<Utc>>) -> ApiToken {
+        ApiToken {
+            id: Uuid::new_v4(),
+            name: None,
+            token_hash: "hash".to_string(),
+            created_at: Utc::now() - Duration::days(365),
+            expires_at,
+            last_used_at: None,
+            created_by_node: "test".to_string(),
+            revoked: revoked_at.is_some(),
+            rate_limit: None,
+            scopes: Vec::new(),
+            usage_count: 0,
+            last_used_ip: None,
+            daily_usage: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+            node_window: None,
+            revoked_at,
+        }
+    }
+    #[test]
+    fn test_node_window_rolls_per_bucket() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut window = NodeWindow::default();
+        let first = window.reserve(start, 1000, 600).unwrap();
+        window.settle(start, &first, 600);
+        let later = start + Duration::minutes(30);
+        let second = window.reserve(later, 1000, 5000).unwrap();
+        assert_eq!((second.nodes, second.remaining), (400, 0));
+        window.settle(later, &second, 300);
+        assert_eq!(window.used(later), 900);
+        assert!(matches!(
+            window.reserve(later, 900, 1),
+            Err(crate::Error::NodeBudgetExhausted { resets_at }) if resets_at == first.bucket + Duration::hours(1)
+        ));
+        let rolled = first.bucket + Duration::hours(1);
+        assert_eq!(window.used(rolled), 300);
+        assert_eq!(window.reserve(rolled, 1000, 5000).unwrap().nodes, 700);
+        let legacy: NodeWindow =
+            serde_json::from_str(r#"{"started_at":"2024-01-01T00:00:00Z","nodes":5}"#).unwrap();
+        assert_eq!(legacy, NodeWindow::default());
+    }
+    #[test]
+    fn test_token_retention() {
+        let retention = TokenRetention::default();
+        let now = Utc::now();
+        assert!(!retention.is_purgeable(&token(None, None), now));
+        assert!(!retention.is_purgeable(&token(Some(now - Duration::days(3)), None), now));
+        assert!(retention.is_purgeable(&token(Some(now - Duration::days(8)), None), now));
+        assert!(!retention.is_purgeable(&token(None, Some(now - Duration::days(10))), now));
+        assert!(retention.is_purgeable(&token(None, Some(now - Duration::days(31))), now));
+        let mut recently_revoked = token(None, Some(now - Duration::days(1)));
+        recently_revoked.last_used_at = Some(now - Duration::days(60));
+        assert!(!retention.is_purgeable(&recently_revoked, now));
+        let mut used_after_revocation = token(None, Some(now - Duration::days(31)));
+        used_after_revocation.last_used_at = Some(now);
+        assert!(retention.is_purgeable(&used_after_revocation, now));
+    }
+    #[test]
+    fn test_apply_usage_keeps_a_bounded_daily_history() {
+        let mut token = token(None, None);
+        let start = Utc::now() - Duration::days(40);
+        for day in 0..40 {
+            for _ in 0..2 {
+                token.apply_usage(&UsageDelta {
+                    count: 3,
+                    last_used_at: start + Duration::days(day),
+                    last_used_ip: Some("10.0.0.1".to_string()),
+                });
+            }
+        }
+        token.apply_usage(&UsageDelta {
+            count: 1,
+            last_used_at: start,
+            last_used_ip: Some("10.0.0.2".to_string()),
+        });
+        assert_eq!(token.usage_count, 241);
+        assert_eq!(token.last_used_at, Some(start + Duration::days(39)));
+        assert_eq!(token.last_used_ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(token.daily_usage.len(), TOKEN_USAGE_DAYS);
+        assert_eq!(
+            token.daily_usage[0].date,
+            (start + Duration::days(10)).date_naive()
+        );
+        assert!(token.daily_usage.iter().all(|d| d.count == 6));
+    }
+    #[test]
+    fn test_token_context_clamps_requests() {
+        let context = TokenContext {
+            max_depth: Some(10),
+            max_multipv: Some(2),
+            ..TokenContext::default()
+        };
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let shallow = context.clamp(AnalysisRequest::new(fen).with_depth(8));
+        assert!(!shallow.clamped);
+        let deep = context.clamp(AnalysisRequest::new(fen).with_depth(30).with_multipv(4));
+        assert!(deep.clamped);
+        assert_eq!((deep.depth, deep.multipv), (10, 2));
+        let infinite = context.clamp(AnalysisRequest::infinite(fen));
+        assert!(infinite.clamped && !infinite.infinite);
+        assert_eq!(infinite.depth, 10);
+        assert!(
+            !TokenContext::default()
+                .clamp(AnalysisRequest::infinite(fen))
+                .clamped
+        );
+    }
+    #[test]
+    fn test_token_context_grants_only_narrower_tokens() {
+        let request = |max_depth| CreateTokenRequest {
+            name: None,
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        };
+        assert!(TokenContext::default().can_grant(&request(None)));
+        let capped = TokenContext {
+            max_depth: Some(10),
+            ..TokenContext::default()
+        };
+        assert!(capped.can_grant(&request(Some(8))));
+        assert!(!capped.can_grant(&request(Some(12))));
+        assert!(!capped.can_grant(&request(None)));
+        let scoped = TokenContext {
+            scopes: vec![SCOPE_BESTMOVE.to_string()],
+            ..TokenContext::default()
+        };
+        assert!(!scoped.can_manage_tokens());
+        assert!(!scoped.can_grant(&request(Some(1))));
+        assert!(!TokenContext::service("node-1").can_manage_tokens());
+    }
+    #[test]
+    fn test_token_list_query_filters_and_pages() {
+        let now = Utc::now();
+        let tokens: Vec<ApiToken> = (0..10)
+            .map(|i| {
+                let mut t = token(None, None);
+                t.name = Some(format!(
+                    "{}-token-{}",
+                    if i % 2 == 0 { "CI" } else { "dev" },
+                    i
+                ));
+                t.created_at = now - Duration::hours(i);
+                t.revoked = i == 4;
+                t.expires_at = (i == 6).then(|| now - Duration::days(1));
+                t
+            })
+            .collect();
+        let mut query = TokenListQuery {
+            filter: TokenFilter {
+                revoked: Some(false),
+                expired: Some(false),
+                name_contains: Some("ci".to_string()),
+                created_after: None,
+            },
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = query.paginate(tokens.clone());
+        let names: Vec<_> = first
+            .tokens
+            .iter()
+            .map(|t| t.name.clone().unwrap())
+            .collect();
+        assert_eq!(names, ["CI-token-0", "CI-token-2"]);
+        let cursor: TokenCursor = first.next_after.unwrap().to_string().parse().unwrap();
+        query.after = Some(cursor);
+        let second = query.paginate(tokens.clone());
+        assert_eq!(second.tokens.len(), 1);
+        assert_eq!(second.tokens[0].name.as_deref(), Some("CI-token-8"));
+        assert!(second.next_after.is_none());
+        let mut used = tokens.clone();
+        used[9].last_used_at = Some(now);
+        let query = TokenListQuery {
+            order: TokenOrder::LastUsedDesc,
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(query.paginate(used).tokens[0].id, tokens[9].id);
+        assert!("12.not-a-uuid".parse::<TokenCursor>().is_err());
+    }
+    #[test]
+    fn test_replica_report_confirmation() {
+        let revoked = token(None, Some(Utc::now()));
+        assert_eq!(
+            TokenReplicaStatus::of(Some(&revoked)),
+            TokenReplicaStatus::Revoked
+        );
+        assert_eq!(
+            TokenReplicaStatus::of(Some(&token(None, None))),
+            TokenReplicaStatus::Present
+        );
+        assert_eq!(TokenReplicaStatus::of(None), TokenReplicaStatus::Unknown);
+        let mut nodes = BTreeMap::from([
+            ("a".to_string(), TokenReplicaStatus::Revoked),
+            ("b".to_string(), TokenReplicaStatus::Unknown),
+        ]);
+        assert!(TokenReplicaReport::new(revoked.id, nodes.clone()).confirmed);
+        nodes.insert("c".to_string(), TokenReplicaStatus::Unreachable);
+        let report = TokenReplicaReport::new(revoked.id, nodes);
+        assert!(!report.confirmed);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["nodes"]["c"],
+            "unreachable"
+        );
+    }
+}
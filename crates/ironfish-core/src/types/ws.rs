@@ -0,0 +1,235 @@
+use super::{
+    AnalysisResult, BestMoveResponse, Notation, Perspective, PrincipalVariation, Score, Variant,
+    Wdl,
+};
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientMessage {
+    Hello {
+        #[serde(default)]
+        id: Option<String>,
+        version: String,
+    },
+    Auth {
+        id: String,
+        token: String,
+    },
+    Analyze {
+        id: String,
+        #[serde(default)]
+        fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
+        #[serde(default)]
+        search_moves: Vec<String>,
+        #[serde(default = "default_depth")]
+        depth: u8,
+        #[serde(default)]
+        multipv: Option<u8>,
+        movetime: Option<u64>,
+        #[serde(default)]
+        nodes: Option<u64>,
+        #[serde(default)]
+        variant: Variant,
+        #[serde(default)]
+        skill_level: Option<u8>,
+        #[serde(default)]
+        perspective: Perspective,
+        #[serde(default)]
+        notation: Notation,
+        #[serde(default)]
+        include_history: bool,
+    },
+    AnalyzeInfinite {
+        id: String,
+        #[serde(default)]
+        fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
+        #[serde(default)]
+        search_moves: Vec<String>,
+        #[serde(default)]
+        multipv: Option<u8>,
+        #[serde(default)]
+        variant: Variant,
+        #[serde(default)]
+        perspective: Perspective,
+        #[serde(default)]
+        notation: Notation,
+        #[serde(default)]
+        include_history: bool,
+    },
+    Cancel {
+        id: String,
+        analysis_id: Uuid,
+    },
+    Bestmove {
+        id: String,
+        #[serde(default)]
+        fen: String,
+        #[serde(default)]
+        startpos: bool,
+        #[serde(default)]
+        moves: Vec<String>,
+        movetime: Option<u64>,
+        #[serde(default)]
+        nodes: Option<u64>,
+        #[serde(default)]
+        variant: Variant,
+    },
+    Subscribe {
+        id: String,
+        topics: Vec<String>,
+    },
+    Unsubscribe {
+        id: String,
+        topics: Vec<String>,
+    },
+    Ping {
+        id: String,
+    },
+    Resume {
+        id: String,
+        resume_token: String,
+    },
+}
+fn default_depth() -> u8 {
+    20
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello {
+        protocol_version: String,
+        server_version: String,
+        features: WsFeatures,
+        limits: WsLimits,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    AuthResult {
+        id: String,
+        success: bool,
+        error: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    Resumed {
+        id: String,
+        analysis_ids: Vec<Uuid>,
+    },
+    AnalysisProgress {
+        analysis_id: Uuid,
+        current_depth: u8,
+        target_depth: u8,
+        evaluation: Option<Score>,
+        #[serde(default)]
+        wdl: Option<Wdl>,
+        principal_variations: Vec<PrincipalVariation>,
+        nodes_per_second: u64,
+        elapsed_ms: u64,
+    },
+    AnalysisComplete {
+        id: String,
+        result: Box<AnalysisResult>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    AnalysisCancelled {
+        analysis_id: Uuid,
+    },
+    BestmoveResult {
+        id: String,
+        result: BestMoveResponse,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    ClusterEvent {
+        event: serde_json::Value,
+    },
+    Event {
+        topic: String,
+        payload: serde_json::Value,
+    },
+    Subscribed {
+        id: String,
+        topics: Vec<String>,
+    },
+    Error {
+        id: Option<String>,
+        code: u16,
+        #[serde(default)]
+        error: String,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    Pong {
+        id: String,
+    },
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsFeatures {
+    pub streaming: bool,
+    pub infinite_analysis: bool,
+    pub topics: Vec<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsLimits {
+    pub max_depth: u32,
+    pub max_multipv: u32,
+    pub max_movetime_ms: u64,
+    pub max_concurrent_analyses: usize,
+    pub max_infinite_analysis_secs: u64,
+    pub auth_timeout_secs: u64,
+}
+impl ServerMessage {
+    pub fn error(id: Option<String>, error: &Error) -> Self {
+        ServerMessage::Error {
+            id,
+            code: error.status_code(),
+            error: error.code().to_string(),
+            message: error.to_string(),
+            details: match error {
+                Error::NodeBudgetExhausted { resets_at } => {
+                    Some(serde_json::json!({ "resets_at": resets_at }))
+                }
+                _ => None,
+            },
+            request_id: None,
+        }
+    }
+    pub fn with_request_id(mut self, connection_id: &Uuid) -> Self {
+        match self {
+            ServerMessage::Error {
+                id: Some(ref id),
+                ref mut request_id,
+                ..
+            }
+            | ServerMessage::AnalysisComplete {
+                ref id,
+                ref mut request_id,
+                ..
+            }
+            | ServerMessage::BestmoveResult {
+                ref id,
+                ref mut request_id,
+                ..
+            } if request_id.is_none() => *request_id = Some(ws_request_id(connection_id, id)),
+            _ => {}
+        }
+        self
+    }
+}
+pub fn ws_request_id(connection_id: &Uuid, message_id: &str) -> String {
+    format!("{}/{}", connection_id, message_id)
+}
@@ -1,27 +1,44 @@
-use crate::config::Config;
-use chrono::Utc;
+use crate::config::{Config, TokenStoreBackend};
+use crate::logging::LogHandles;
+use crate::metrics::MetricsCollector;
+use crate::reload::ServerReloader;
 use ironfish_api::ws::SessionManager;
-use ironfish_api::{ApiRouter, ApiState};
-use ironfish_auth::SledTokenStore;
-use ironfish_auth::TokenManager;
+use ironfish_api::{
+    serve_tls, spawn_webhook_notifier, AdminWrites, AnalysisForwarder, AnalysisJobs, AnalysisStore,
+    ApiRouter, ApiState, IdempotencyStore, LeaderForwarder, MetricsRegistry, PeerTls, ServerTls,
+    SledAnalysisHistory, Webhooks,
+};
+use ironfish_auth::{
+    spawn_token_sweeper, spawn_usage_flusher, SledAuditLog, TokenManager, UsageTracker,
+};
+use ironfish_auth::{RedisTokenStore, SledTokenStore};
 use ironfish_cluster::{
-    ClusterConfig, ClusterService, GossipEnvelope, MembershipManager, Node, NodeConfig,
+    ClusterConfig, ClusterService, ClusterStateStore, CpuAwareLoadBalancer, GossipRetention,
+    MembershipManager, Node, NodeConfig,
+};
+use ironfish_core::{
+    AnalysisHistory, AuditAction, AuditEntry, AuditLog, AuditRetention, GossipMessage,
+    HistoryRetention, TokenStore,
+};
+use ironfish_stockfish::{
+    AnalysisCache, AnalysisService, EnginePool, EnginePoolConfig, SyzygyTables,
 };
-use ironfish_core::GossipMessage;
-use ironfish_stockfish::{AnalysisService, EnginePool, EnginePoolConfig};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::signal;
-use tokio::sync::broadcast;
-use tracing::info;
+use tokio::sync::{broadcast, oneshot};
+use tracing::{debug, info, warn};
 pub struct Application {
     config: Config,
     state: Arc<ApiState>,
-    cluster: Option<Arc<ClusterService<SledTokenStore>>>,
+    cluster: Option<Arc<ClusterService<dyn TokenStore>>>,
     gossip_tx: broadcast::Sender<GossipMessage>,
+    audit: Option<Arc<SledAuditLog>>,
+    history: Option<Arc<SledAnalysisHistory>>,
+    tls: Option<ServerTls>,
 }
 impl Application {
-    pub async fn new(config: Config) -> anyhow::Result<Self> {
+    pub async fn new(config: Config, logging: Option<LogHandles>) -> anyhow::Result<Self> {
         let node_config = NodeConfig {
             id: if config.node.id == "auto" {
                 None
@@ -29,44 +46,199 @@ impl Application {
                 Some(config.node.id.clone())
             },
             bind_address: config.node.bind_address,
+            advertise_address: config.node.advertise_address,
             priority: config.node.priority,
             version: env!("CARGO_PKG_VERSION").to_string(),
         };
+        let tls = ServerTls::from_config(&config.server.tls)?;
         let node = Arc::new(Node::new(node_config));
+        if let Some(ref logging) = logging {
+            logging.set_node_id(&node.id().to_string());
+        }
         info!(node_id = %node.id(), "node initialized");
         let engine_config = EnginePoolConfig {
-            binary_path: config.stockfish.binary_path.clone(),
+            binary_path: config.engine_binary_path().to_string(),
+            kind: config.engine.kind,
+            args: config.engine.args.clone(),
             pool_size: config.stockfish.pool_size,
+            options: config.engine_options(),
+            restart_backoff: std::time::Duration::from_millis(config.stockfish.restart_backoff_ms),
+            acquire_timeout: std::time::Duration::from_millis(config.stockfish.acquire_timeout_ms),
+            max_queue: config.stockfish.max_queue,
+            warmup_depth: config.stockfish.warmup_depth,
+            warmup_timeout: std::time::Duration::from_secs(config.stockfish.warmup_timeout_secs),
+            new_game_policy: config.stockfish.ucinewgame,
+            reserved_engines: config.stockfish.reserved_engines,
+            reserved_fraction: config.stockfish.reserved_fraction,
+            fast_movetime_threshold: std::time::Duration::from_millis(
+                config.stockfish.fast_movetime_threshold_ms,
+            ),
+            ..Default::default()
         };
         let pool = Arc::new(EnginePool::new(engine_config).await?);
+        pool.spawn_reaper(std::time::Duration::from_secs(
+            config.stockfish.health_check_interval_secs.max(1),
+        ));
         info!(
             pool_size = config.stockfish.pool_size,
             "engine pool created"
         );
-        let analysis = Arc::new(AnalysisService::new(pool));
-        let data_dir = config.node.data_dir.join("tokens");
-        std::fs::create_dir_all(&data_dir)?;
-        let token_store = Arc::new(SledTokenStore::new(&data_dir)?);
+        let metrics = Arc::new(MetricsRegistry::new());
+        let mut analysis = AnalysisService::new(pool.clone())
+            .with_telemetry(metrics.telemetry())
+            .with_play_budget(std::time::Duration::from_secs(
+                config.stockfish.play_session_budget_secs,
+            ));
+        if let Some(path) = config.syzygy_path() {
+            let tablebases = SyzygyTables::scan(&path);
+            info!(tables = tablebases.len(), "syzygy tablebases indexed");
+            analysis = analysis.with_tablebases(tablebases);
+        }
+        if config.analysis_cache.enabled {
+            let capacity = config.analysis_cache.capacity;
+            let cache = if config.analysis_cache.persist {
+                let cache_dir = config.node.data_dir.join("analysis_cache");
+                std::fs::create_dir_all(&cache_dir)?;
+                AnalysisCache::persistent(capacity, &cache_dir)?
+            } else {
+                AnalysisCache::new(capacity)
+            };
+            analysis = analysis.with_cache(Arc::new(cache));
+        }
+        let analysis = Arc::new(analysis);
+        let token_store: Arc<dyn TokenStore> = match config.auth.store {
+            TokenStoreBackend::Sled => {
+                let data_dir = config.node.data_dir.join("tokens");
+                std::fs::create_dir_all(&data_dir)?;
+                Arc::new(
+                    SledTokenStore::new(&data_dir)?
+                        .with_failure_threshold(config.auth.store_failure_threshold),
+                )
+            }
+            TokenStoreBackend::Redis => {
+                let url = config.auth.redis_url.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("auth.store = \"redis\" requires auth.redis_url")
+                })?;
+                info!("using redis token store");
+                Arc::new(RedisTokenStore::new(url).await?)
+            }
+        };
         let secret = config.auth.token_secret.as_bytes();
         let token_manager = Arc::new(
             TokenManager::new(secret, node.id().to_string())
                 .with_default_ttl(config.auth.token_ttl_days),
         );
-        let membership = Arc::new(MembershipManager::new(node.clone()));
+        let membership = MembershipManager::new(node.clone())
+            .with_metrics_stale_after(config.node.metrics_stale_after())
+            .with_member_stale_after(config.cluster.member_stale_after())
+            .with_quorum_policy(config.cluster.quorum_policy);
+        let membership = Arc::new(match config.cluster.join_probe_timeout() {
+            Some(timeout) => membership.with_join_probe(timeout),
+            None => membership,
+        });
         let (gossip_tx, _) = broadcast::channel::<GossipMessage>(1024);
-        let ws_sessions = Arc::new(SessionManager::new(config.websocket.max_connections));
-        let state = Arc::new(
-            ApiState::new(
-                analysis,
-                token_store.clone(),
-                token_manager,
-                node.clone(),
-                membership.clone(),
-                ws_sessions,
-                config.websocket.clone(),
-            )
-            .with_gossip(gossip_tx.clone()),
+        let ws_sessions = Arc::new(
+            SessionManager::new(config.websocket.max_connections)
+                .with_max_sessions_per_token(config.websocket.max_sessions_per_token)
+                .with_telemetry(metrics.telemetry()),
         );
+        let audit = if config.audit.enabled {
+            let audit_dir = config.node.data_dir.join("audit");
+            std::fs::create_dir_all(&audit_dir)?;
+            let mut log = SledAuditLog::new(&audit_dir)?.with_retention(AuditRetention {
+                max_age_days: config.audit.retention_days,
+                max_entries: config.audit.max_entries,
+            });
+            if let Some(ref file) = config.audit.file {
+                log = log.with_file(file)?;
+            }
+            Some(Arc::new(log))
+        } else {
+            None
+        };
+        let history = if config.history.enabled {
+            let history_dir = config.node.data_dir.join("history");
+            std::fs::create_dir_all(&history_dir)?;
+            let history =
+                SledAnalysisHistory::new(&history_dir)?.with_retention(HistoryRetention {
+                    max_age_days: config.history.retention_days,
+                    max_entries: config.history.max_entries,
+                });
+            Some(Arc::new(history))
+        } else {
+            None
+        };
+        let mut state = ApiState::new(
+            analysis,
+            token_store.clone(),
+            token_manager,
+            node.clone(),
+            membership.clone(),
+            ws_sessions,
+            config.websocket.clone(),
+        )
+        .with_gossip(gossip_tx.clone())
+        .with_analysis_store(AnalysisStore::new(config.analysis_store.clone()))
+        .with_analysis_jobs(AnalysisJobs::new(config.analysis_jobs.clone()))
+        .with_idempotency(IdempotencyStore::new(config.idempotency.clone()))
+        .with_rate_limit(config.auth.rate_limit_per_minute)
+        .with_token_retention(config.auth.token_retention())
+        .with_trusted_proxies(config.server.trusted_proxies.clone())
+        .with_limits(config.limits)
+        .with_revocation_confirm_timeout(std::time::Duration::from_millis(
+            config.cluster.revocation_confirm_timeout_ms,
+        ))
+        .with_usage_tracker(Arc::new(
+            UsageTracker::new().with_flush_every(config.auth.usage_flush_requests),
+        ))
+        .with_metrics(metrics.clone());
+        if let Some(ref audit) = audit {
+            state = state.with_audit(audit.clone());
+        }
+        if let Some(ref history) = history {
+            state = state.with_history(history.clone());
+        }
+        if let Some(ref secret) = config.cluster.secret {
+            state = state.with_cluster_secret(secret.clone());
+        }
+        if !config.webhooks.hooks.is_empty() {
+            let webhooks = Webhooks::new(config.webhooks.clone(), node.id().to_string())?;
+            info!(
+                "delivering webhooks to {} endpoints",
+                config.webhooks.hooks.len()
+            );
+            state = state.with_webhooks(Arc::new(webhooks));
+        }
+        let load_balancer = Arc::new(CpuAwareLoadBalancer::new(
+            ironfish_cluster::LoadBalancerConfig {
+                stale_metrics_after: config.node.metrics_stale_after(),
+                ..config.load_balancer.balancer_config()
+            },
+        ));
+        state = state.with_config_reloader(Arc::new(ServerReloader::new(
+            config.clone(),
+            Some(pool),
+            load_balancer.clone(),
+            logging,
+        )));
+        if config.cluster.enabled {
+            state = state.with_forwarder(
+                AnalysisForwarder::new(
+                    config.forwarding.clone(),
+                    load_balancer.clone(),
+                    membership.clone(),
+                )
+                .with_secret(config.cluster.secret.as_deref())
+                .with_tls(PeerTls::from_config(&config.server.tls)?),
+            );
+            if config.cluster.admin_writes == AdminWrites::Leader {
+                state = state.with_leader_forwarder(
+                    LeaderForwarder::new()
+                        .with_secret(config.cluster.secret.as_deref())
+                        .with_tls(PeerTls::from_config(&config.server.tls)?),
+                );
+            }
+        }
         let cluster = if config.cluster.enabled {
             let cluster_config = ClusterConfig {
                 discovery_interval: std::time::Duration::from_millis(
@@ -78,14 +250,53 @@ impl Application {
                 health_check_interval: std::time::Duration::from_millis(
                     config.cluster.heartbeat_interval_ms,
                 ),
+                heartbeat_interval: std::time::Duration::from_millis(
+                    config.cluster.heartbeat_interval_ms,
+                ),
+                election_timeout_min: std::time::Duration::from_millis(
+                    config.cluster.election_timeout_ms,
+                ),
+                election_timeout_max: std::time::Duration::from_millis(
+                    config.cluster.election_timeout_max_ms,
+                ),
+                peer_ttl: std::time::Duration::from_secs(config.discovery.peer_ttl_secs),
+                multicast_enabled: config.discovery.multicast_enabled,
                 multicast_group: config.discovery.multicast_group.clone(),
                 multicast_port: config.discovery.multicast_port,
                 static_peers: config.discovery.static_peers.clone(),
+                seed_nodes: config.discovery.seed_nodes.clone(),
+                kubernetes: config.discovery.kubernetes.clone(),
+                dns: config.discovery.dns.clone(),
                 auto_join: true,
+                pending_write_limit: config.cluster.pending_write_limit,
+                peer_failure_threshold: config.cluster.peer_failure_threshold,
+                tls: config.cluster.tls.clone(),
+                secret: config.cluster.secret.clone(),
+                persisted_peer_ttl: std::time::Duration::from_secs(
+                    config.cluster.persisted_peer_ttl_secs,
+                ),
+                wire_format: config.cluster.wire_format,
+                gossip_retention: GossipRetention {
+                    entry_ttl: std::time::Duration::from_secs(config.cluster.gossip_entry_ttl_secs),
+                    tombstone_ttl: std::time::Duration::from_secs(
+                        config.cluster.gossip_tombstone_retention_secs,
+                    ),
+                    max_entries: config.cluster.gossip_max_entries,
+                },
             };
+            let state_dir = config.node.data_dir.join("cluster");
+            std::fs::create_dir_all(&state_dir)?;
+            let state_store = ClusterStateStore::new(&state_dir)?;
             match ClusterService::new(cluster_config, node, membership, token_store) {
                 Ok(service) => {
                     info!("cluster service initialized");
+                    let mut service = service
+                        .with_load_balancer(load_balancer)
+                        .with_telemetry(metrics.telemetry())
+                        .with_state_store(state_store);
+                    if let Some(ref audit) = audit {
+                        service = service.with_audit(audit.clone());
+                    }
                     Some(Arc::new(service))
                 }
                 Err(e) => {
@@ -96,11 +307,18 @@ impl Application {
         } else {
             None
         };
+        if let Some(ref cluster) = cluster {
+            state = state.with_network(cluster.network());
+        }
+        let state = Arc::new(state);
         Ok(Self {
             config,
             state,
             cluster,
             gossip_tx,
+            audit,
+            history,
+            tls,
         })
     }
     pub async fn run(self) -> anyhow::Result<()> {
@@ -108,45 +326,185 @@ impl Application {
             cluster.start().await?;
             info!("cluster service started");
             let cluster_clone = cluster.clone();
-            let node_id = self.state.node.id().clone();
             let mut gossip_rx = self.gossip_tx.subscribe();
             tokio::spawn(async move {
                 while let Ok(msg) = gossip_rx.recv().await {
-                    let envelope = GossipEnvelope {
-                        message: msg,
-                        origin: node_id.clone(),
-                        version: Utc::now().timestamp_millis() as u64,
-                        hops: 0,
-                    };
-                    if let Err(e) = cluster_clone.network().broadcast(envelope).await {
+                    if let Err(e) = cluster_clone.broadcast(msg).await {
                         tracing::debug!("gossip broadcast error: {}", e);
                     }
                 }
             });
         }
+        let state = self.state.clone();
+        let gossip_tx = self.cluster.is_some().then(|| self.gossip_tx.clone());
+        let interval = self.config.node.metrics_interval();
+        tokio::spawn(async move {
+            let mut collector = MetricsCollector::new();
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                timer.tick().await;
+                let metrics = collector.sample(&state);
+                state.node.update_metrics(metrics.clone());
+                if let Some(ref gossip_tx) = gossip_tx {
+                    let _ = gossip_tx
+                        .send(GossipMessage::NodeMetrics(state.node.id().clone(), metrics));
+                }
+            }
+        });
+        if self.config.websocket.enabled {
+            ironfish_api::ws::spawn_event_publisher(self.state.clone());
+        }
+        spawn_webhook_notifier(self.state.clone());
+        if let Some(ref audit) = self.audit {
+            let audit = audit.clone();
+            let interval = std::time::Duration::from_secs(self.config.audit.prune_interval_secs);
+            tokio::spawn(async move {
+                let mut timer = tokio::time::interval(interval);
+                loop {
+                    timer.tick().await;
+                    match audit.prune().await {
+                        Ok(removed) if removed > 0 => {
+                            debug!("audit pruner removed {} entries", removed)
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("audit prune failed: {}", e),
+                    }
+                }
+            });
+        }
+        if let Some(ref history) = self.history {
+            let history = history.clone();
+            let interval = std::time::Duration::from_secs(self.config.history.prune_interval_secs);
+            tokio::spawn(async move {
+                let mut timer = tokio::time::interval(interval);
+                loop {
+                    timer.tick().await;
+                    match history.prune().await {
+                        Ok(removed) if removed > 0 => {
+                            debug!("history pruner removed {} records", removed)
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("history prune failed: {}", e),
+                    }
+                }
+            });
+        }
+        spawn_token_sweeper(
+            self.state.token_store.clone(),
+            self.config.auth.token_retention(),
+            std::time::Duration::from_secs(self.config.auth.token_sweep_interval_secs),
+        )?;
+        spawn_usage_flusher(
+            self.state.token_usage.clone(),
+            self.state.token_store.clone(),
+            std::time::Duration::from_secs(self.config.auth.usage_flush_interval_secs),
+        );
+        let token_store = self.state.token_store.clone();
+        let cluster = self.cluster.clone();
+        let interval =
+            std::time::Duration::from_secs(self.config.auth.store_recovery_interval_secs);
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                timer.tick().await;
+                match token_store.recover().await {
+                    Ok(health) if health.is_writable() => {
+                        if let Some(ref cluster) = cluster {
+                            if let Err(e) = cluster.flush_pending_writes().await {
+                                debug!("pending token writes not flushed: {}", e);
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => debug!("token store still read-only: {}", e),
+                }
+            }
+        });
         let multiplex_service = ApiRouter::new(self.state.clone())
             .with_auth(self.config.auth.enabled)
+            .with_docs(self.config.server.docs_enabled)
             .build_multiplex_service();
         let make_service = axum::Router::new().fallback_service(multiplex_service);
         let http_addr = self.config.node.bind_address;
         let listener = TcpListener::bind(http_addr).await?;
-        info!(address = %http_addr, "REST/GraphQL/gRPC server listening");
-        let handle = tokio::spawn(async move {
-            axum::serve(listener, make_service)
-                .with_graceful_shutdown(shutdown_signal())
+        info!(
+            address = %http_addr,
+            tls = self.tls.is_some(),
+            "REST/GraphQL/gRPC server listening"
+        );
+        let (stop_listening, graceful) = oneshot::channel::<()>();
+        let graceful = async move {
+            let _ = graceful.await;
+        };
+        spawn_reloader(self.state.clone(), self.tls.clone());
+        let mut handle = match self.tls.clone() {
+            Some(tls) => tokio::spawn(serve_tls(listener, tls, make_service, graceful)),
+            None => tokio::spawn(async move {
+                axum::serve(
+                    listener,
+                    make_service.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+                )
+                .with_graceful_shutdown(graceful)
                 .await
                 .expect("Server error");
-        });
+            }),
+        };
         tokio::select! {
-            _ = handle => {},
+            _ = &mut handle => return Ok(()),
+            _ = shutdown_signal() => {},
         }
+        let drain_timeout = std::time::Duration::from_secs(self.config.node.shutdown_timeout_secs);
+        let deadline = tokio::time::Instant::now() + drain_timeout;
+        self.state.analysis.begin_shutdown();
         if let Some(ref cluster) = self.cluster {
-            let _ = cluster.stop().await;
+            if let Err(e) = cluster.stop().await {
+                warn!("failed to leave cluster: {}", e);
+            }
+        }
+        if !self.state.drain(drain_timeout).await {
+            warn!("drain timed out after {:?}", drain_timeout);
         }
+        let _ = stop_listening.send(());
+        if tokio::time::timeout_at(deadline, &mut handle)
+            .await
+            .is_err()
+        {
+            warn!("aborting connections still open after {:?}", drain_timeout);
+            handle.abort();
+        }
+        self.state.flush_token_usage().await;
         info!("server shutdown complete");
         Ok(())
     }
 }
+#[cfg(unix)]
+fn spawn_reloader(state: Arc<ApiState>, tls: Option<ServerTls>) {
+    tokio::spawn(async move {
+        let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("reload on SIGHUP unavailable: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            if let Some(ref tls) = tls {
+                if let Err(e) = tls.reload() {
+                    warn!("keeping previous tls certificates: {}", e);
+                }
+            }
+            let audit = AuditEntry::new(
+                AuditAction::ConfigReload,
+                "signal:SIGHUP",
+                state.node.id().to_string(),
+            );
+            let _ = state.reload_config(audit).await;
+        }
+    });
+}
+#[cfg(not(unix))]
+fn spawn_reloader(_state: Arc<ApiState>, _tls: Option<ServerTls>) {}
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
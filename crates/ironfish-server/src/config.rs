@@ -1,15 +1,32 @@
-use ironfish_api::WebSocketConfig;
-use serde::Deserialize;
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use ironfish_api::{
+    AdminWrites, AnalysisJobConfig, AnalysisStoreConfig, ForwardingConfig, IdempotencyConfig,
+    ServerTlsConfig, WebSocketConfig, WebhookConfig,
+};
+use ironfish_cluster::{
+    DnsDiscoveryConfig, GossipTlsConfig, KubernetesDiscoveryConfig, LoadBalanceStrategy,
+    QuorumPolicy, WireFormat,
+};
+use ironfish_core::{AnalysisLimits, TokenRetention};
+use ironfish_stockfish::{AnalysisCacheConfig, EngineKind, NewGamePolicy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
     pub node: NodeConfig,
     #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
     pub stockfish: StockfishConfig,
     #[serde(default)]
+    pub engine: EngineConfig,
+    #[serde(default)]
     pub cluster: ClusterConfig,
     #[serde(default)]
     pub discovery: DiscoveryConfig,
@@ -19,20 +36,72 @@ pub struct Config {
     pub load_balancer: LoadBalancerConfig,
     #[serde(default)]
     pub websocket: WebSocketConfig,
+    #[serde(default)]
+    pub analysis_store: AnalysisStoreConfig,
+    #[serde(default)]
+    pub analysis_jobs: AnalysisJobConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub analysis_cache: AnalysisCacheConfig,
+    #[serde(default)]
+    pub forwarding: ForwardingConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub limits: AnalysisLimits,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NodeConfig {
     #[serde(default = "default_node_id")]
     pub id: String,
     #[serde(default = "default_bind_address")]
     pub bind_address: SocketAddr,
+    #[serde(default = "default_advertise_address")]
+    pub advertise_address: Option<SocketAddr>,
     #[serde(default = "default_data_dir")]
     pub data_dir: PathBuf,
     #[serde(default = "default_priority")]
     pub priority: u32,
+    #[serde(default = "default_metrics_interval")]
+    pub metrics_interval_ms: u64,
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_secs: u64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub tls: ServerTlsConfig,
+    #[serde(default)]
+    pub docs_enabled: bool,
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EngineConfig {
+    #[serde(default)]
+    pub kind: EngineKind,
+    #[serde(default)]
+    pub binary_path: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub options: HashMap<String, String>,
 }
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct StockfishConfig {
     #[serde(default = "default_stockfish_path")]
     pub binary_path: String,
@@ -42,9 +111,44 @@ pub struct StockfishConfig {
     pub default_depth: u8,
     #[serde(default = "default_multipv")]
     pub default_multipv: u8,
+    #[serde(default)]
+    pub threads: Option<u32>,
+    #[serde(default)]
+    pub hash_mb: Option<u32>,
+    #[serde(default)]
+    pub skill_level: Option<u8>,
+    #[serde(default = "default_true")]
+    pub show_wdl: bool,
+    #[serde(default)]
+    pub syzygy_path: Option<String>,
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+    #[serde(default = "default_restart_backoff")]
+    pub restart_backoff_ms: u64,
+    #[serde(default = "default_engine_health_interval")]
+    pub health_check_interval_secs: u64,
+    #[serde(default = "default_acquire_timeout")]
+    pub acquire_timeout_ms: u64,
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+    #[serde(default = "default_play_session_budget")]
+    pub play_session_budget_secs: u64,
+    #[serde(default)]
+    pub warmup_depth: u8,
+    #[serde(default = "default_warmup_timeout")]
+    pub warmup_timeout_secs: u64,
+    #[serde(default)]
+    pub ucinewgame: NewGamePolicy,
+    #[serde(default)]
+    pub reserved_engines: usize,
+    #[serde(default)]
+    pub reserved_fraction: Option<f64>,
+    #[serde(default = "default_fast_movetime_threshold")]
+    pub fast_movetime_threshold_ms: u64,
 }
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ClusterConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -52,11 +156,42 @@ pub struct ClusterConfig {
     pub heartbeat_interval_ms: u64,
     #[serde(default = "default_election_timeout")]
     pub election_timeout_ms: u64,
+    #[serde(default = "default_election_timeout_max")]
+    pub election_timeout_max_ms: u64,
     #[serde(default = "default_gossip_interval")]
     pub gossip_interval_ms: u64,
+    #[serde(default = "default_pending_write_limit")]
+    pub pending_write_limit: usize,
+    #[serde(default = "default_peer_failure_threshold")]
+    pub peer_failure_threshold: u32,
+    #[serde(default)]
+    pub tls: GossipTlsConfig,
+    #[serde(default = "default_cluster_secret")]
+    pub secret: Option<String>,
+    #[serde(default = "default_persisted_peer_ttl")]
+    pub persisted_peer_ttl_secs: u64,
+    #[serde(default)]
+    pub admin_writes: AdminWrites,
+    #[serde(default)]
+    pub wire_format: WireFormat,
+    #[serde(default = "default_gossip_entry_ttl")]
+    pub gossip_entry_ttl_secs: u64,
+    #[serde(default = "default_gossip_tombstone_retention")]
+    pub gossip_tombstone_retention_secs: u64,
+    #[serde(default = "default_gossip_max_entries")]
+    pub gossip_max_entries: usize,
+    #[serde(default = "default_revocation_confirm_timeout")]
+    pub revocation_confirm_timeout_ms: u64,
+    #[serde(default)]
+    pub quorum_policy: QuorumPolicy,
+    #[serde(default = "default_member_stale_after")]
+    pub member_stale_after_ms: u64,
+    #[serde(default = "default_join_probe_timeout")]
+    pub join_probe_timeout_ms: u64,
 }
 #[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DiscoveryConfig {
     #[serde(default)]
     pub static_peers: Vec<String>,
@@ -68,9 +203,15 @@ pub struct DiscoveryConfig {
     pub multicast_group: String,
     #[serde(default = "default_multicast_port")]
     pub multicast_port: u16,
+    #[serde(default = "default_peer_ttl")]
+    pub peer_ttl_secs: u64,
+    #[serde(default)]
+    pub kubernetes: KubernetesDiscoveryConfig,
+    #[serde(default)]
+    pub dns: DnsDiscoveryConfig,
 }
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AuthConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -80,6 +221,36 @@ pub struct AuthConfig {
     pub rate_limit_per_minute: u32,
     #[serde(default = "default_token_secret")]
     pub token_secret: String,
+    #[serde(default = "default_store_failure_threshold")]
+    pub store_failure_threshold: u32,
+    #[serde(default = "default_store_recovery_interval")]
+    pub store_recovery_interval_secs: u64,
+    #[serde(default = "default_token_sweep_interval")]
+    pub token_sweep_interval_secs: u64,
+    #[serde(default = "default_expired_token_grace")]
+    pub expired_token_grace_days: u32,
+    #[serde(default = "default_revoked_token_retention")]
+    pub revoked_token_retention_days: u32,
+    #[serde(default = "default_usage_flush_requests")]
+    pub usage_flush_requests: u64,
+    #[serde(default = "default_usage_flush_interval")]
+    pub usage_flush_interval_secs: u64,
+    #[serde(default)]
+    pub store: TokenStoreBackend,
+    #[serde(default = "default_redis_url")]
+    pub redis_url: Option<String>,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenStoreBackend {
+    #[default]
+    Sled,
+    Redis,
+}
+fn default_redis_url() -> Option<String> {
+    std::env::var("IRONFISH_REDIS_URL")
+        .ok()
+        .filter(|s| !s.is_empty())
 }
 fn default_token_secret() -> String {
     let secret = std::env::var("IRONFISH_TOKEN_SECRET")
@@ -89,8 +260,8 @@ fn default_token_secret() -> String {
     }
     secret
 }
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LoadBalancerConfig {
     #[serde(default = "default_strategy")]
     pub strategy: String,
@@ -101,6 +272,106 @@ pub struct LoadBalancerConfig {
     #[serde(default = "default_latency_weight")]
     pub latency_weight: f32,
 }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_audit_retention_days")]
+    pub retention_days: Option<u32>,
+    #[serde(default = "default_audit_max_entries")]
+    pub max_entries: Option<usize>,
+    #[serde(default = "default_audit_prune_interval")]
+    pub prune_interval_secs: u64,
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_history_retention_days")]
+    pub retention_days: Option<u32>,
+    #[serde(default = "default_history_max_entries")]
+    pub max_entries: Option<usize>,
+    #[serde(default = "default_history_prune_interval")]
+    pub prune_interval_secs: u64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    #[serde(default = "default_otlp_enabled")]
+    pub otlp_enabled: bool,
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    #[serde(default)]
+    pub log_level: Option<String>,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(default = "default_log_format")]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub file: Option<LogFileConfig>,
+    #[serde(default)]
+    pub sampling: HashMap<String, u64>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Weekly,
+    Never,
+}
+fn default_log_format() -> LogFormat {
+    match std::env::var("IRONFISH_LOG_FORMAT").as_deref() {
+        Ok("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    }
+}
+fn default_log_max_files() -> usize {
+    5
+}
+fn default_otlp_enabled() -> bool {
+    std::env::var("IRONFISH_OTLP_ENABLED")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false)
+}
+fn default_otlp_endpoint() -> String {
+    std::env::var("IRONFISH_OTLP_ENDPOINT").unwrap_or_else(|_| "http://localhost:4317".to_string())
+}
+fn default_service_name() -> String {
+    "ironfish".to_string()
+}
+fn default_cluster_secret() -> Option<String> {
+    std::env::var("IRONFISH_CLUSTER_SECRET")
+        .ok()
+        .filter(|s| !s.is_empty())
+}
 fn default_node_id() -> String {
     std::env::var("IRONFISH_NODE_ID").unwrap_or_else(|_| "auto".to_string())
 }
@@ -110,6 +381,11 @@ fn default_bind_address() -> SocketAddr {
         .and_then(|s| s.parse().ok())
         .unwrap_or_else(|| "0.0.0.0:8080".parse().expect("valid default address"))
 }
+fn default_advertise_address() -> Option<SocketAddr> {
+    std::env::var("IRONFISH_ADVERTISE_ADDRESS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+}
 fn default_data_dir() -> PathBuf {
     PathBuf::from("/var/lib/ironfish")
 }
@@ -122,6 +398,33 @@ fn default_stockfish_path() -> String {
 fn default_pool_size() -> usize {
     4
 }
+fn default_metrics_interval() -> u64 {
+    1000
+}
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+fn default_restart_backoff() -> u64 {
+    1000
+}
+fn default_engine_health_interval() -> u64 {
+    10
+}
+fn default_acquire_timeout() -> u64 {
+    10_000
+}
+fn default_max_queue() -> usize {
+    64
+}
+fn default_play_session_budget() -> u64 {
+    1800
+}
+fn default_warmup_timeout() -> u64 {
+    10
+}
+fn default_fast_movetime_threshold() -> u64 {
+    1000
+}
 fn default_depth() -> u8 {
     20
 }
@@ -137,6 +440,9 @@ fn default_heartbeat_interval() -> u64 {
 fn default_election_timeout() -> u64 {
     5000
 }
+fn default_election_timeout_max() -> u64 {
+    10000
+}
 fn default_gossip_interval() -> u64 {
     5000
 }
@@ -146,12 +452,81 @@ fn default_multicast_group() -> String {
 fn default_multicast_port() -> u16 {
     7878
 }
+fn default_peer_ttl() -> u64 {
+    30
+}
 fn default_token_ttl() -> u32 {
     365
 }
 fn default_rate_limit() -> u32 {
     100
 }
+fn default_store_failure_threshold() -> u32 {
+    3
+}
+fn default_store_recovery_interval() -> u64 {
+    10
+}
+fn default_token_sweep_interval() -> u64 {
+    3600
+}
+fn default_expired_token_grace() -> u32 {
+    7
+}
+fn default_revoked_token_retention() -> u32 {
+    30
+}
+fn default_usage_flush_requests() -> u64 {
+    100
+}
+fn default_usage_flush_interval() -> u64 {
+    10
+}
+fn default_pending_write_limit() -> usize {
+    1024
+}
+fn default_peer_failure_threshold() -> u32 {
+    3
+}
+fn default_member_stale_after() -> u64 {
+    15000
+}
+fn default_join_probe_timeout() -> u64 {
+    0
+}
+fn default_persisted_peer_ttl() -> u64 {
+    86400
+}
+fn default_gossip_entry_ttl() -> u64 {
+    86400
+}
+fn default_gossip_tombstone_retention() -> u64 {
+    7 * 86400
+}
+fn default_gossip_max_entries() -> usize {
+    10_000
+}
+fn default_revocation_confirm_timeout() -> u64 {
+    5000
+}
+fn default_audit_retention_days() -> Option<u32> {
+    Some(90)
+}
+fn default_audit_max_entries() -> Option<usize> {
+    Some(100_000)
+}
+fn default_audit_prune_interval() -> u64 {
+    3600
+}
+fn default_history_retention_days() -> Option<u32> {
+    Some(30)
+}
+fn default_history_max_entries() -> Option<usize> {
+    Some(50_000)
+}
+fn default_history_prune_interval() -> u64 {
+    3600
+}
 fn default_strategy() -> String {
     "cpu_aware".to_string()
 }
@@ -169,8 +544,11 @@ impl Default for NodeConfig {
         Self {
             id: default_node_id(),
             bind_address: default_bind_address(),
+            advertise_address: default_advertise_address(),
             data_dir: default_data_dir(),
             priority: default_priority(),
+            metrics_interval_ms: default_metrics_interval(),
+            shutdown_timeout_secs: default_shutdown_timeout(),
         }
     }
 }
@@ -181,7 +559,135 @@ impl Default for StockfishConfig {
             pool_size: default_pool_size(),
             default_depth: default_depth(),
             default_multipv: default_multipv(),
+            threads: None,
+            hash_mb: None,
+            skill_level: None,
+            show_wdl: true,
+            syzygy_path: None,
+            options: HashMap::new(),
+            restart_backoff_ms: default_restart_backoff(),
+            health_check_interval_secs: default_engine_health_interval(),
+            acquire_timeout_ms: default_acquire_timeout(),
+            max_queue: default_max_queue(),
+            play_session_budget_secs: default_play_session_budget(),
+            warmup_depth: 0,
+            warmup_timeout_secs: default_warmup_timeout(),
+            ucinewgame: NewGamePolicy::default(),
+            reserved_engines: 0,
+            reserved_fraction: None,
+            fast_movetime_threshold_ms: default_fast_movetime_threshold(),
+        }
+    }
+}
+impl AuthConfig {
+    pub fn token_retention(&self) -> TokenRetention {
+        TokenRetention {
+            expired_grace_days: self.expired_token_grace_days,
+            revoked_retention_days: self.revoked_token_retention_days,
+        }
+    }
+}
+impl NodeConfig {
+    pub fn metrics_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.metrics_interval_ms.max(1))
+    }
+    pub fn metrics_stale_after(&self) -> std::time::Duration {
+        self.metrics_interval() * 3
+    }
+}
+impl ClusterConfig {
+    pub fn member_stale_after(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.member_stale_after_ms)
+    }
+    pub fn join_probe_timeout(&self) -> Option<std::time::Duration> {
+        (self.join_probe_timeout_ms > 0)
+            .then(|| std::time::Duration::from_millis(self.join_probe_timeout_ms))
+    }
+}
+impl LoadBalancerConfig {
+    pub fn balancer_config(&self) -> ironfish_cluster::LoadBalancerConfig {
+        let strategy = match self.strategy.as_str() {
+            "round_robin" => LoadBalanceStrategy::RoundRobin,
+            "least_connections" => LoadBalanceStrategy::LeastConnections,
+            "position_hash" => LoadBalanceStrategy::PositionHash,
+            _ => LoadBalanceStrategy::CpuAware,
+        };
+        ironfish_cluster::LoadBalancerConfig {
+            strategy,
+            cpu_weight: self.cpu_weight,
+            queue_weight: self.queue_weight,
+            latency_weight: self.latency_weight,
+            ..Default::default()
+        }
+    }
+}
+impl Config {
+    pub fn engine_binary_path(&self) -> &str {
+        match self.engine.binary_path {
+            Some(ref path) => path,
+            None => &self.stockfish.binary_path,
+        }
+    }
+    pub fn engine_options(&self) -> HashMap<String, String> {
+        let kind = self.engine.kind;
+        let mut options: HashMap<String, String> = kind
+            .default_options()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        merge_options(&mut options, self.stockfish.shared_options());
+        if kind == EngineKind::Stockfish {
+            merge_options(&mut options, self.stockfish.stockfish_options());
+            merge_options(&mut options, self.stockfish.options.clone());
+        }
+        merge_options(&mut options, self.engine.options.clone());
+        options
+    }
+    pub fn syzygy_path(&self) -> Option<String> {
+        self.engine_options()
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("SyzygyPath"))
+            .map(|(_, path)| path)
+            .filter(|path| !path.is_empty() && path != "<empty>")
+    }
+}
+fn merge_options(options: &mut HashMap<String, String>, overrides: HashMap<String, String>) {
+    for (name, value) in overrides {
+        options.retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+        options.insert(name, value);
+    }
+}
+impl StockfishConfig {
+    fn stockfish_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+        if let Some(hash_mb) = self.hash_mb {
+            options.insert("Hash".to_string(), hash_mb.to_string());
+        }
+        if let Some(skill_level) = self.skill_level {
+            options.insert("Skill Level".to_string(), skill_level.to_string());
+        }
+        options.insert("UCI_ShowWDL".to_string(), self.show_wdl.to_string());
+        options
+    }
+    fn shared_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+        if let Some(threads) = self.threads {
+            options.insert("Threads".to_string(), threads.to_string());
+        }
+        if let Some(ref path) = self.syzygy_path {
+            let separator = if cfg!(windows) { ";" } else { ":" };
+            let (found, missing): (Vec<&str>, Vec<&str>) = path
+                .split(separator)
+                .filter(|dir| !dir.is_empty())
+                .partition(|dir| Path::new(dir).is_dir());
+            for dir in missing {
+                warn!("syzygy tablebase directory {} not found, skipping it", dir);
+            }
+            if !found.is_empty() {
+                options.insert("SyzygyPath".to_string(), found.join(separator));
+            }
         }
+        options
     }
 }
 impl Default for ClusterConfig {
@@ -190,7 +696,22 @@ impl Default for ClusterConfig {
             enabled: true,
             heartbeat_interval_ms: default_heartbeat_interval(),
             election_timeout_ms: default_election_timeout(),
+            election_timeout_max_ms: default_election_timeout_max(),
             gossip_interval_ms: default_gossip_interval(),
+            pending_write_limit: default_pending_write_limit(),
+            peer_failure_threshold: default_peer_failure_threshold(),
+            tls: GossipTlsConfig::default(),
+            secret: default_cluster_secret(),
+            persisted_peer_ttl_secs: default_persisted_peer_ttl(),
+            admin_writes: AdminWrites::default(),
+            wire_format: WireFormat::default(),
+            gossip_entry_ttl_secs: default_gossip_entry_ttl(),
+            gossip_tombstone_retention_secs: default_gossip_tombstone_retention(),
+            gossip_max_entries: default_gossip_max_entries(),
+            revocation_confirm_timeout_ms: default_revocation_confirm_timeout(),
+            quorum_policy: QuorumPolicy::default(),
+            member_stale_after_ms: default_member_stale_after(),
+            join_probe_timeout_ms: default_join_probe_timeout(),
         }
     }
 }
@@ -205,6 +726,9 @@ impl Default for DiscoveryConfig {
             multicast_enabled: true,
             multicast_group: default_multicast_group(),
             multicast_port: default_multicast_port(),
+            peer_ttl_secs: default_peer_ttl(),
+            kubernetes: KubernetesDiscoveryConfig::default(),
+            dns: DnsDiscoveryConfig::default(),
         }
     }
 }
@@ -215,6 +739,15 @@ impl Default for AuthConfig {
             token_ttl_days: default_token_ttl(),
             rate_limit_per_minute: default_rate_limit(),
             token_secret: default_token_secret(),
+            store_failure_threshold: default_store_failure_threshold(),
+            store_recovery_interval_secs: default_store_recovery_interval(),
+            token_sweep_interval_secs: default_token_sweep_interval(),
+            expired_token_grace_days: default_expired_token_grace(),
+            revoked_token_retention_days: default_revoked_token_retention(),
+            usage_flush_requests: default_usage_flush_requests(),
+            usage_flush_interval_secs: default_usage_flush_interval(),
+            store: TokenStoreBackend::default(),
+            redis_url: default_redis_url(),
         }
     }
 }
@@ -228,16 +761,561 @@ impl Default for LoadBalancerConfig {
         }
     }
 }
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: default_audit_retention_days(),
+            max_entries: default_audit_max_entries(),
+            prune_interval_secs: default_audit_prune_interval(),
+            file: None,
+        }
+    }
+}
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: default_history_retention_days(),
+            max_entries: default_history_max_entries(),
+            prune_interval_secs: default_history_prune_interval(),
+        }
+    }
+}
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_enabled: default_otlp_enabled(),
+            otlp_endpoint: default_otlp_endpoint(),
+            service_name: default_service_name(),
+            log_level: None,
+        }
+    }
+}
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: default_log_format(),
+            file: None,
+            sampling: HashMap::new(),
+        }
+    }
+}
+impl TelemetryConfig {
+    pub fn log_filter(&self) -> Result<EnvFilter, tracing_subscriber::filter::ParseError> {
+        match self.log_level {
+            Some(ref level) => EnvFilter::try_new(level),
+            None => {
+                Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+            }
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    File(PathBuf),
+    Defaults(PathBuf),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<String>);
+impl std::fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid configuration ({} problems):", self.0.len())?;
+        for violation in &self.0 {
+            write!(f, "\n  - {}", violation)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ConfigErrors {}
+const REDACTED_KEYS: &[&str] = &["secret", "token_secret", "redis_url"];
 impl Config {
-    pub fn load() -> anyhow::Result<Self> {
-        let config_path =
-            std::env::var("IRONFISH_CONFIG").unwrap_or_else(|_| "config/default.toml".to_string());
-        if std::path::Path::new(&config_path).exists() {
-            let content = std::fs::read_to_string(&config_path)?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
+    pub fn load() -> anyhow::Result<(Self, ConfigSource)> {
+        let path = PathBuf::from(Self::path());
+        let (config, source) = if path.exists() {
+            (Self::read(&path)?, ConfigSource::File(path))
         } else {
-            Ok(Config::default())
+            (Config::default(), ConfigSource::Defaults(path))
+        };
+        config.validate()?;
+        Ok((config, source))
+    }
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut violations = Vec::new();
+        if self.stockfish.pool_size == 0 {
+            violations.push("stockfish.pool_size must be at least 1".to_string());
+        }
+        let binary_key = match self.engine.binary_path {
+            Some(_) => "engine.binary_path",
+            None => "stockfish.binary_path",
+        };
+        if !executable_on_path(self.engine_binary_path()) {
+            violations.push(format!(
+                "{} {} is not an executable file",
+                binary_key,
+                self.engine_binary_path()
+            ));
+        }
+        if self.engine.kind != EngineKind::Stockfish {
+            let kind = self.engine.kind.as_str();
+            if self.engine.binary_path.is_none() {
+                violations.push(format!(
+                    "engine.binary_path is required with engine.kind = \"{}\"",
+                    kind
+                ));
+            }
+            if !self.stockfish.options.is_empty() {
+                violations.push(format!(
+                    "stockfish.options do not apply to engine.kind = \"{}\"; set engine.options instead",
+                    kind
+                ));
+            }
+            if self.stockfish.hash_mb.is_some() {
+                violations.push(format!(
+                    "stockfish.hash_mb does not apply to engine.kind = \"{}\"; set engine.options instead",
+                    kind
+                ));
+            }
+            if self.stockfish.skill_level.is_some() {
+                violations.push(format!(
+                    "stockfish.skill_level does not apply to engine.kind = \"{}\"",
+                    kind
+                ));
+            }
+        }
+        let durations = [
+            ("node.metrics_interval_ms", self.node.metrics_interval_ms),
+            (
+                "node.shutdown_timeout_secs",
+                self.node.shutdown_timeout_secs,
+            ),
+            (
+                "stockfish.health_check_interval_secs",
+                self.stockfish.health_check_interval_secs,
+            ),
+            (
+                "stockfish.acquire_timeout_ms",
+                self.stockfish.acquire_timeout_ms,
+            ),
+            (
+                "stockfish.play_session_budget_secs",
+                self.stockfish.play_session_budget_secs,
+            ),
+            (
+                "stockfish.warmup_timeout_secs",
+                self.stockfish.warmup_timeout_secs,
+            ),
+            (
+                "cluster.heartbeat_interval_ms",
+                self.cluster.heartbeat_interval_ms,
+            ),
+            (
+                "cluster.election_timeout_ms",
+                self.cluster.election_timeout_ms,
+            ),
+            (
+                "cluster.gossip_interval_ms",
+                self.cluster.gossip_interval_ms,
+            ),
+            (
+                "cluster.persisted_peer_ttl_secs",
+                self.cluster.persisted_peer_ttl_secs,
+            ),
+            (
+                "cluster.gossip_entry_ttl_secs",
+                self.cluster.gossip_entry_ttl_secs,
+            ),
+            (
+                "cluster.gossip_tombstone_retention_secs",
+                self.cluster.gossip_tombstone_retention_secs,
+            ),
+            (
+                "cluster.revocation_confirm_timeout_ms",
+                self.cluster.revocation_confirm_timeout_ms,
+            ),
+            (
+                "cluster.member_stale_after_ms",
+                self.cluster.member_stale_after_ms,
+            ),
+            ("discovery.peer_ttl_secs", self.discovery.peer_ttl_secs),
+            (
+                "auth.store_recovery_interval_secs",
+                self.auth.store_recovery_interval_secs,
+            ),
+            (
+                "auth.token_sweep_interval_secs",
+                self.auth.token_sweep_interval_secs,
+            ),
+            (
+                "auth.usage_flush_interval_secs",
+                self.auth.usage_flush_interval_secs,
+            ),
+            ("audit.prune_interval_secs", self.audit.prune_interval_secs),
+            (
+                "history.prune_interval_secs",
+                self.history.prune_interval_secs,
+            ),
+            (
+                "websocket.auth_timeout_secs",
+                self.websocket.auth_timeout_secs,
+            ),
+            (
+                "websocket.ping_interval_secs",
+                self.websocket.ping_interval_secs,
+            ),
+            (
+                "websocket.metrics_interval_secs",
+                self.websocket.metrics_interval_secs,
+            ),
+            (
+                "websocket.slow_consumer_timeout_ms",
+                self.websocket.slow_consumer_timeout_ms,
+            ),
+            (
+                "websocket.token_revalidate_secs",
+                self.websocket.token_revalidate_secs,
+            ),
+            ("analysis_store.ttl_secs", self.analysis_store.ttl_secs),
+            ("idempotency.ttl_secs", self.idempotency.ttl_secs),
+            ("forwarding.timeout_secs", self.forwarding.timeout_secs),
+            (
+                "webhooks.initial_backoff_ms",
+                self.webhooks.initial_backoff_ms,
+            ),
+            ("webhooks.timeout_secs", self.webhooks.timeout_secs),
+        ];
+        for (key, value) in durations {
+            if value == 0 {
+                violations.push(format!("{} must be greater than 0", key));
+            }
         }
+        if self.cluster.election_timeout_ms <= self.cluster.heartbeat_interval_ms {
+            violations.push(format!(
+                "cluster.election_timeout_ms ({}) must be greater than cluster.heartbeat_interval_ms ({})",
+                self.cluster.election_timeout_ms, self.cluster.heartbeat_interval_ms
+            ));
+        }
+        if self.cluster.election_timeout_max_ms < self.cluster.election_timeout_ms {
+            violations.push(format!(
+                "cluster.election_timeout_max_ms ({}) must not be below cluster.election_timeout_ms ({})",
+                self.cluster.election_timeout_max_ms, self.cluster.election_timeout_ms
+            ));
+        }
+        if self.cluster.admin_writes == AdminWrites::Leader && self.cluster.secret.is_none() {
+            violations.push(
+                "cluster.admin_writes = \"leader\" requires cluster.secret to authenticate forwarded writes"
+                    .to_string(),
+            );
+        }
+        if self.discovery.multicast_enabled {
+            match self.discovery.multicast_group.parse::<std::net::IpAddr>() {
+                Ok(group) if group.is_multicast() => {}
+                _ => violations.push(format!(
+                    "discovery.multicast_group {} is not a multicast address",
+                    self.discovery.multicast_group
+                )),
+            }
+        }
+        let balancer = &self.load_balancer;
+        if !matches!(
+            balancer.strategy.as_str(),
+            "cpu_aware" | "round_robin" | "least_connections" | "position_hash"
+        ) {
+            violations.push(format!(
+                "load_balancer.strategy {} is not one of cpu_aware, round_robin, least_connections, position_hash",
+                balancer.strategy
+            ));
+        }
+        let weights = [
+            balancer.cpu_weight,
+            balancer.queue_weight,
+            balancer.latency_weight,
+        ];
+        let sum: f32 = weights.iter().sum();
+        if weights.iter().any(|w| *w < 0.0) || (sum - 1.0).abs() > 0.01 {
+            violations.push(format!(
+                "load_balancer weights must be non-negative and sum to 1.0, got {:.2}",
+                sum
+            ));
+        }
+        if self.auth.store == TokenStoreBackend::Redis && self.auth.redis_url.is_none() {
+            violations.push("auth.store = \"redis\" requires auth.redis_url".to_string());
+        }
+        if let Err(e) = self.telemetry.log_filter() {
+            violations.push(format!("telemetry.log_level: {}", e));
+        }
+        if let Err(e) = check_writable(&self.node.data_dir) {
+            violations.push(format!("node.data_dir: {}", e));
+        }
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigErrors(violations)),
+        }
+    }
+    pub fn effective_toml(&self) -> anyhow::Result<String> {
+        let mut value = toml::Value::try_from(self)?;
+        redact(&mut value);
+        Ok(toml::to_string_pretty(&value)?)
+    }
+    pub fn read_table() -> anyhow::Result<toml::Table> {
+        Self::read_table_at(Path::new(&Self::path()))
+    }
+    pub fn read_table_at(path: &Path) -> anyhow::Result<toml::Table> {
+        if path.exists() {
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(toml::Table::new())
+        }
+    }
+    pub fn from_table(table: toml::Table) -> anyhow::Result<Self> {
+        match table.is_empty() {
+            true => Ok(Config::default()),
+            false => Ok(table.try_into()?),
+        }
+    }
+    pub fn path() -> String {
+        std::env::var("IRONFISH_CONFIG").unwrap_or_else(|_| "config/default.toml".to_string())
+    }
+}
+fn executable_on_path(binary: &str) -> bool {
+    let path = Path::new(binary);
+    if path.components().count() > 1 {
+        return is_executable(path);
+    }
+    std::env::var_os("PATH").is_some_and(|dirs| {
+        std::env::split_paths(&dirs).any(|dir| is_executable(&dir.join(binary)))
+    })
+}
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+fn check_writable(dir: &Path) -> Result<(), String> {
+    let existing = dir
+        .ancestors()
+        .map(|p| match p.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => p,
+        })
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(format!("{} is not a directory", existing.display()));
+    }
+    let probe = existing.join(format!(".ironfish-write-check-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(format!("{} is not writable: {}", existing.display(), e)),
+    }
+}
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) && !value.is_table() {
+                    *value = toml::Value::String("<redacted>".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.stockfish.binary_path = std::env::current_exe()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        config.node.data_dir = std::env::temp_dir().join("ironfish-config-test");
+        config
+    }
+    #[test]
+    fn test_default_config_file_parses() {
+        let config: Config = toml::from_str(include_str!("../../../config/default.toml")).unwrap();
+        assert!(config.stockfish.pool_size >= 1);
+    }
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = toml::from_str::<Config>("[stockfish]\npool-size = 8\n").unwrap_err();
+        assert!(err.to_string().contains("pool-size"), "{}", err);
+        let err = toml::from_str::<Config>("[websocket]\nmax_sesions = 8\n").unwrap_err();
+        assert!(err.to_string().contains("max_sesions"), "{}", err);
+        let err = toml::from_str::<Config>("[stockfsh]\npool_size = 8\n").unwrap_err();
+        assert!(err.to_string().contains("stockfsh"), "{}", err);
+    }
+    #[test]
+    fn test_valid_config_passes() {
+        valid_config().validate().unwrap();
+    }
+    #[test]
+    fn test_validate_reports_every_violation() {
+        let mut config = valid_config();
+        config.stockfish.pool_size = 0;
+        config.cluster.heartbeat_interval_ms = 500;
+        config.cluster.election_timeout_ms = 400;
+        config.discovery.multicast_enabled = true;
+        config.discovery.multicast_group = "10.0.0.1".to_string();
+        config.load_balancer.cpu_weight = 0.9;
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.0.len(), 4, "{}", errors);
+        assert!(errors.0[0].contains("pool_size"));
+        assert!(errors.0[1].contains("election_timeout_ms"));
+        assert!(errors.0[2].contains("multicast_group"));
+        assert!(errors.0[3].contains("weights"));
+        assert!(errors
+            .to_string()
+            .starts_with("invalid configuration (4 problems):"));
+    }
+    #[test]
+    fn test_zero_intervals_and_timeouts_are_rejected() {
+        let mut config = valid_config();
+        config.node.metrics_interval_ms = 0;
+        config.auth.token_sweep_interval_secs = 0;
+        config.history.prune_interval_secs = 0;
+        config.websocket.ping_interval_secs = 0;
+        let errors = config.validate().unwrap_err();
+        assert_eq!(
+            errors.0,
+            vec![
+                "node.metrics_interval_ms must be greater than 0",
+                "auth.token_sweep_interval_secs must be greater than 0",
+                "history.prune_interval_secs must be greater than 0",
+                "websocket.ping_interval_secs must be greater than 0",
+            ]
+        );
+        let mut config = valid_config();
+        config.cluster.join_probe_timeout_ms = 0;
+        config.websocket.resume_grace_secs = 0;
+        config.websocket.max_infinite_analysis_secs = 0;
+        config.validate().unwrap();
+    }
+    #[test]
+    fn test_validate_cross_field_rules() {
+        let mut config = valid_config();
+        config.cluster.election_timeout_max_ms = config.cluster.election_timeout_ms - 1;
+        assert!(config.validate().unwrap_err().0[0].contains("election_timeout_max_ms"));
+        let mut config = valid_config();
+        config.cluster.admin_writes = AdminWrites::Leader;
+        config.cluster.secret = None;
+        assert!(config.validate().unwrap_err().0[0].contains("cluster.secret"));
+        config.cluster.secret = Some("cluster-secret".to_string());
+        assert!(config.validate().is_ok());
+        let mut config = valid_config();
+        config.auth.store = TokenStoreBackend::Redis;
+        assert!(config.validate().unwrap_err().0[0].contains("redis_url"));
+        let mut config = valid_config();
+        config.stockfish.binary_path = "/nonexistent/stockfish".to_string();
+        assert!(config.validate().unwrap_err().0[0].contains("binary_path"));
+        let mut config = valid_config();
+        config.load_balancer.strategy = "random".to_string();
+        assert!(config.validate().unwrap_err().0[0].contains("strategy"));
+    }
+    #[test]
+    fn test_engine_kind_selects_option_defaults() {
+        let mut config: Config =
+            toml::from_str("[engine]\nkind = \"uci\"\nargs = [\"--backend=blas\"]\n").unwrap();
+        assert_eq!(config.engine.kind, EngineKind::Uci);
+        assert_eq!(config.engine.args, vec!["--backend=blas".to_string()]);
+        config.stockfish.threads = Some(2);
+        config.stockfish.hash_mb = Some(64);
+        config.stockfish.skill_level = Some(5);
+        config.stockfish.options = HashMap::from([("Move Overhead".to_string(), "50".to_string())]);
+        config
+            .engine
+            .options
+            .insert("Ponder".to_string(), "false".to_string());
+        let uci = config.engine_options();
+        assert_eq!(uci.get("Threads").map(String::as_str), Some("2"));
+        assert_eq!(uci.get("Ponder").map(String::as_str), Some("false"));
+        assert!(!uci.contains_key("Hash"));
+        assert!(!uci.contains_key("Skill Level"));
+        assert!(!uci.contains_key("UCI_ShowWDL"));
+        assert!(!uci.contains_key("Move Overhead"));
+        config.engine.kind = EngineKind::Lc0;
+        let lc0 = config.engine_options();
+        assert_eq!(lc0.get("UCI_ShowWDL").map(String::as_str), Some("true"));
+        assert_eq!(lc0.get("SmartPruningFactor").map(String::as_str), Some("0"));
+        assert!(!lc0.contains_key("Hash"));
+        config.engine.kind = EngineKind::Stockfish;
+        config.stockfish.show_wdl = false;
+        let stockfish = config.engine_options();
+        assert_eq!(stockfish.get("Hash").map(String::as_str), Some("64"));
+        assert_eq!(
+            stockfish.get("Move Overhead").map(String::as_str),
+            Some("50")
+        );
+        assert_eq!(
+            stockfish.get("UCI_ShowWDL").map(String::as_str),
+            Some("false")
+        );
+        let tables = std::env::temp_dir();
+        config.stockfish.syzygy_path = Some(tables.display().to_string());
+        assert_eq!(config.syzygy_path(), Some(tables.display().to_string()));
+        config
+            .stockfish
+            .options
+            .insert("syzygypath".to_string(), "/srv/syzygy".to_string());
+        let stockfish = config.engine_options();
+        assert!(!stockfish.contains_key("SyzygyPath"));
+        assert_eq!(config.syzygy_path(), Some("/srv/syzygy".to_string()));
+        config.stockfish.options.remove("syzygypath");
+        config.stockfish.syzygy_path = None;
+        assert_eq!(config.engine_binary_path(), config.stockfish.binary_path);
+        config.engine.kind = EngineKind::Uci;
+        config.stockfish.binary_path = valid_config().stockfish.binary_path;
+        config.node.data_dir = valid_config().node.data_dir;
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.0.len(), 4, "{}", errors);
+        assert!(errors.0[0].contains("engine.binary_path is required"));
+        assert!(errors.0[1].contains("stockfish.options"));
+        assert!(errors.0[2].contains("stockfish.hash_mb"));
+        assert!(errors.0[3].contains("stockfish.skill_level"));
+        config.engine.binary_path = Some("/nonexistent/lc0".to_string());
+        assert!(config.validate().unwrap_err().0[0].contains("engine.binary_path /nonexistent/lc0"));
+        config.stockfish.binary_path = "/nonexistent/stockfish".to_string();
+        config.engine.binary_path = Some(valid_config().stockfish.binary_path);
+        config.stockfish.options.clear();
+        config.stockfish.hash_mb = None;
+        config.stockfish.skill_level = None;
+        config.validate().unwrap();
+        assert_eq!(
+            config.engine_binary_path(),
+            valid_config().stockfish.binary_path
+        );
+        let config: Config = toml::from_str("[engine]\nkind = \"lc0\"\n").unwrap();
+        assert_eq!(config.engine.kind, EngineKind::Lc0);
+        assert!(toml::from_str::<Config>("[engine]\nkind = \"leela\"\n").is_err());
+    }
+    #[test]
+    fn test_effective_toml_redacts_secrets() {
+        let mut config = valid_config();
+        config.cluster.secret = Some("cluster-secret".to_string());
+        config.auth.token_secret = "token-secret".to_string();
+        let rendered = config.effective_toml().unwrap();
+        assert!(!rendered.contains("cluster-secret"));
+        assert!(!rendered.contains("token-secret"));
+        assert!(rendered.contains("<redacted>"));
+        let reparsed: toml::Table = toml::from_str(&rendered).unwrap();
+        assert!(reparsed.contains_key("stockfish"));
     }
 }
@@ -0,0 +1,486 @@
+use crate::config::{LogFileConfig, LogFormat, LogRotation, LoggingConfig, TelemetryConfig};
+use chrono::{SecondsFormat, Utc};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::{info, Event, Level, Metadata, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+const RESERVED_KEYS: [&str; 5] = ["timestamp", "level", "target", "node_id", "spans"];
+#[derive(Clone)]
+pub struct LogHandles {
+    pub filter: LogFilterHandle,
+    pub sampler: LogSampler,
+    node_id: Arc<RwLock<String>>,
+}
+impl LogHandles {
+    pub fn set_node_id(&self, node_id: &str) {
+        *self.node_id.write().unwrap_or_else(|e| e.into_inner()) = node_id.to_string();
+    }
+}
+pub struct Logging {
+    pub handles: LogHandles,
+    pub tracer_provider: Option<SdkTracerProvider>,
+    pub file_guard: Option<WorkerGuard>,
+}
+pub fn bootstrap(writer: BoxMakeWriter) -> impl Subscriber + Send + Sync {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(writer)
+        .finish()
+}
+pub fn init(telemetry: &TelemetryConfig, logging: &LoggingConfig) -> anyhow::Result<Logging> {
+    let (writer, file_guard) = match logging.file {
+        Some(ref file) => {
+            let (file, guard) = tracing_appender::non_blocking(rolling_file(file)?);
+            (BoxMakeWriter::new(io::stdout.and(file)), Some(guard))
+        }
+        None => (BoxMakeWriter::new(io::stdout), None),
+    };
+    let tracer_provider = otlp_provider(telemetry)?;
+    let otel = tracer_provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(telemetry.service_name.clone()))
+    });
+    let (subscriber, handles) = subscriber(telemetry.log_filter()?, logging, writer);
+    subscriber.with(otel).init();
+    if telemetry.otlp_enabled {
+        info!("exporting spans to {}", telemetry.otlp_endpoint);
+    }
+    Ok(Logging {
+        handles,
+        tracer_provider,
+        file_guard,
+    })
+}
+fn rolling_file(config: &LogFileConfig) -> anyhow::Result<RollingFileAppender> {
+    let name = config
+        .path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("logging.file.path has no file name"))?;
+    let directory = config
+        .path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| std::path::Path::new("."));
+    let rotation = match config.rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Weekly => Rotation::WEEKLY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name.to_string_lossy());
+    let builder = match config.max_files {
+        0 => builder,
+        max_files => builder.max_log_files(max_files),
+    };
+    builder
+        .build(directory)
+        .map_err(|e| anyhow::anyhow!("logging.file.path {}: {}", config.path.display(), e))
+}
+pub fn subscriber(
+    filter: EnvFilter,
+    logging: &LoggingConfig,
+    writer: BoxMakeWriter,
+) -> (
+    impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    LogHandles,
+) {
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let sampler = LogSampler::new(&logging.sampling);
+    let node_id = Arc::new(RwLock::new(String::new()));
+    let output = match logging.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_target(true)
+            .with_thread_ids(false)
+            .with_file(false)
+            .with_line_number(false)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat {
+                node_id: node_id.clone(),
+            })
+            .with_writer(writer)
+            .boxed(),
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(sampler.clone())
+        .with(output);
+    let handles = LogHandles {
+        filter: filter_handle,
+        sampler,
+        node_id,
+    };
+    (subscriber, handles)
+}
+fn otlp_provider(telemetry: &TelemetryConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
+    if !telemetry.otlp_enabled {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&telemetry.otlp_endpoint)
+        .build()?;
+    Ok(Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(telemetry.service_name.clone())
+                    .build(),
+            )
+            .build(),
+    ))
+}
+struct JsonFormat {
+    node_id: Arc<RwLock<String>>,
+}
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        line.insert("level".to_string(), metadata.level().to_string().into());
+        line.insert("target".to_string(), metadata.target().into());
+        let node_id = self.node_id.read().unwrap_or_else(|e| e.into_inner());
+        if !node_id.is_empty() {
+            line.insert("node_id".to_string(), node_id.as_str().into());
+        }
+        event.record(&mut JsonVisitor(&mut line));
+        let spans: Vec<Value> = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let mut fields = span
+                    .extensions()
+                    .get::<FormattedFields<N>>()
+                    .and_then(|f| serde_json::from_str::<Map<String, Value>>(f).ok())
+                    .unwrap_or_default();
+                fields.insert("name".to_string(), span.name().into());
+                Value::Object(fields)
+            })
+            .collect();
+        if !spans.is_empty() {
+            line.insert("spans".to_string(), spans.into());
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let key = match field.name() {
+            name if RESERVED_KEYS.contains(&name) => format!("fields.{}", name),
+            name => name.to_string(),
+        };
+        self.0.insert(key, value);
+    }
+}
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+#[derive(Clone, Default)]
+pub struct LogSampler {
+    rules: Arc<RwLock<Vec<SampleRule>>>,
+}
+struct SampleRule {
+    target: String,
+    every: u64,
+    seen: AtomicU64,
+}
+impl LogSampler {
+    pub fn new(rules: &HashMap<String, u64>) -> Self {
+        let sampler = Self::default();
+        sampler.set_rules(rules);
+        sampler
+    }
+    pub fn set_rules(&self, rules: &HashMap<String, u64>) {
+        let mut rules: Vec<SampleRule> = rules
+            .iter()
+            .map(|(target, every)| SampleRule {
+                target: target.clone(),
+                every: *every,
+                seen: AtomicU64::new(0),
+            })
+            .collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.target.len()));
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+    }
+    fn keep(&self, metadata: &Metadata<'_>) -> bool {
+        if *metadata.level() <= Level::WARN {
+            return true;
+        }
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        match rules
+            .iter()
+            .find(|rule| in_target(metadata.target(), &rule.target))
+        {
+            Some(rule) if rule.every > 1 => {
+                rule.seen.fetch_add(1, Ordering::Relaxed) % rule.every == 0
+            }
+            _ => true,
+        }
+    }
+}
+impl<S: Subscriber> Layer<S> for LogSampler {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        self.keep(event.metadata())
+    }
+}
+fn in_target(target: &str, prefix: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use ironfish_api::ws::SessionManager;
+    use ironfish_api::{ApiRouter, ApiState, WebSocketConfig};
+    use ironfish_auth::{SledTokenStore, TokenManager};
+    use ironfish_cluster::{MembershipManager, Node, NodeConfig};
+    use ironfish_stockfish::AnalysisService;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Captured {
+        fn writer(&self) -> BoxMakeWriter {
+            let captured = self.clone();
+            BoxMakeWriter::new(move || captured.clone())
+        }
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+    fn config(format: LogFormat, sampling: &[(&str, u64)]) -> LoggingConfig {
+        LoggingConfig {
+            format,
+            file: None,
+            sampling: sampling
+                .iter()
+                .map(|(target, every)| (target.to_string(), *every))
+                .collect(),
+        }
+    }
+    #[tokio::test]
+    async fn test_json_lines_carry_node_id_and_request_span() {
+        let captured = Captured::default();
+        let (subscriber, handles) = subscriber(
+            EnvFilter::new("debug"),
+            &config(LogFormat::Json, &[]),
+            captured.writer(),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some("node-json".to_string()),
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            advertise_address: None,
+            priority: 100,
+            version: "test".to_string(),
+        }));
+        handles.set_node_id(&node.id().to_string());
+        let state = Arc::new(ApiState::new(
+            Arc::new(AnalysisService::new_mock()),
+            Arc::new(SledTokenStore::in_memory().unwrap()),
+            Arc::new(TokenManager::new(&TokenManager::generate_secret(), "test")),
+            node.clone(),
+            Arc::new(MembershipManager::new(node)),
+            Arc::new(SessionManager::new(8)),
+            WebSocketConfig::default(),
+        ));
+        let response = ApiRouter::new(state)
+            .with_auth(false)
+            .build_rest_router()
+            .oneshot(
+                Request::post("/_admin/node/drain")
+                    .header("x-request-id", "req-json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let lines: Vec<Value> = captured
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).expect("every line is JSON"))
+            .collect();
+        let in_request: Vec<&Value> = lines
+            .iter()
+            .filter(|line| {
+                line["spans"].as_array().is_some_and(|spans| {
+                    spans
+                        .iter()
+                        .any(|s| s["name"] == "request" && s["request_id"] == "req-json")
+                })
+            })
+            .collect();
+        assert!(!in_request.is_empty());
+        for line in in_request {
+            assert_eq!(line["node_id"], "node-json");
+            assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+            assert!(line["message"].is_string());
+        }
+    }
+    #[test]
+    fn test_bootstrap_keeps_lines_logged_before_config_is_loaded() {
+        let captured = Captured::default();
+        tracing::subscriber::with_default(bootstrap(captured.writer()), || {
+            tracing::warn!("syzygy tablebase directory /missing not found, skipping it");
+        });
+        let lines = captured.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("WARN"));
+        assert!(lines[0].contains("/missing not found"));
+    }
+    #[test]
+    fn test_sampling_thins_chatty_targets_but_keeps_warnings() {
+        let captured = Captured::default();
+        let (subscriber, handles) = subscriber(
+            EnvFilter::new("trace"),
+            &config(LogFormat::Json, &[("chatty", 10)]),
+            captured.writer(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..100 {
+                tracing::trace!(target: "chatty::engine", "received: info depth 1");
+            }
+            for _ in 0..3 {
+                tracing::warn!(target: "chatty::engine", "engine stalled");
+            }
+            for _ in 0..5 {
+                tracing::trace!(target: "chattyother", "not sampled");
+            }
+            handles.sampler.set_rules(&HashMap::new());
+            for _ in 0..4 {
+                tracing::trace!(target: "chatty::engine", "received: bestmove");
+            }
+        });
+        let lines = captured.lines();
+        let count = |text: &str| lines.iter().filter(|l| l.contains(text)).count();
+        assert_eq!(count("info depth 1"), 10);
+        assert_eq!(count("engine stalled"), 3);
+        assert_eq!(count("not sampled"), 5);
+        assert_eq!(count("bestmove"), 4);
+    }
+    #[test]
+    fn test_event_fields_cannot_replace_line_keys() {
+        let captured = Captured::default();
+        let (subscriber, handles) = subscriber(
+            EnvFilter::new("info"),
+            &config(LogFormat::Json, &[]),
+            captured.writer(),
+        );
+        handles.set_node_id("node-keys");
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                timestamp = "forged",
+                level = "TRACE",
+                node_id = "other",
+                "spoofed"
+            );
+        });
+        let line: Value = serde_json::from_str(&captured.lines()[0]).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["node_id"], "node-keys");
+        assert_ne!(line["timestamp"], "forged");
+        assert_eq!(line["fields.timestamp"], "forged");
+        assert_eq!(line["fields.level"], "TRACE");
+        assert_eq!(line["fields.node_id"], "other");
+    }
+    #[test]
+    fn test_log_file_gets_a_copy_of_stdout_lines() {
+        let dir = std::env::temp_dir().join(format!("ironfish-logs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let appender = rolling_file(&LogFileConfig {
+            path: dir.join("server.log"),
+            rotation: LogRotation::Never,
+            max_files: 2,
+        })
+        .unwrap();
+        let (file, guard) = tracing_appender::non_blocking(appender);
+        let captured = Captured::default();
+        let (subscriber, _) = subscriber(
+            EnvFilter::new("info"),
+            &config(LogFormat::Text, &[]),
+            BoxMakeWriter::new(captured.writer().and(file)),
+        );
+        tracing::subscriber::with_default(subscriber, || tracing::info!("to both"));
+        drop(guard);
+        let written = std::fs::read_to_string(dir.join("server.log")).unwrap();
+        assert!(written.contains("to both"));
+        assert_eq!(captured.lines().len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
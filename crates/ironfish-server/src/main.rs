@@ -1,26 +1,32 @@
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use ironfish_server::config;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 mod app;
-mod config;
+mod logging;
+mod metrics;
+mod reload;
 use app::Application;
-use config::Config;
+use config::{Config, ConfigSource};
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    init_logging();
-    let config = Config::load()?;
-    info!("loaded configuration");
-    let app = Application::new(config).await?;
+    let bootstrap = logging::bootstrap(BoxMakeWriter::new(std::io::stderr));
+    let (config, source) = tracing::subscriber::with_default(bootstrap, Config::load)?;
+    let logging = logging::init(&config.telemetry, &config.logging)?;
+    match source {
+        ConfigSource::File(path) => info!(path = %path.display(), "loaded configuration file"),
+        ConfigSource::Defaults(path) => warn!(
+            path = %path.display(),
+            "configuration file not found, using built-in defaults"
+        ),
+    }
+    let app = Application::new(config, Some(logging.handles)).await?;
     info!("application initialized");
-    app.run().await?;
-    Ok(())
-}
-fn init_logging() {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(true)
-        .with_thread_ids(false)
-        .with_file(false)
-        .with_line_number(false)
-        .init();
+    let result = app.run().await;
+    if let Some(provider) = logging.tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            warn!("failed to flush OTLP spans: {}", e);
+        }
+    }
+    drop(logging.file_guard);
+    result
 }
@@ -0,0 +1,42 @@
+use ironfish_api::ApiState;
+use ironfish_core::NodeMetrics;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+pub struct MetricsCollector {
+    system: System,
+    pid: Option<Pid>,
+    cpus: f32,
+}
+impl MetricsCollector {
+    pub fn new() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Self {
+            system: System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+            cpus: cpus as f32,
+        }
+    }
+    pub fn sample(&mut self, state: &ApiState) -> NodeMetrics {
+        self.system.refresh_memory();
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::nothing().with_cpu().with_memory(),
+        );
+        let (cpu, rss) = self
+            .system
+            .processes()
+            .values()
+            .filter(|p| {
+                Some(p.pid()) == self.pid || (p.parent().is_some() && p.parent() == self.pid)
+            })
+            .fold((0.0, 0), |(cpu, rss), p| {
+                (cpu + p.cpu_usage(), rss + p.memory())
+            });
+        let mut metrics = state.local_metrics();
+        metrics.cpu_usage = (cpu / (100.0 * self.cpus)).min(1.0);
+        metrics.memory_usage = rss as f32 / self.system.total_memory().max(1) as f32;
+        metrics
+    }
+}
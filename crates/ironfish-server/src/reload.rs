@@ -0,0 +1,392 @@
+use crate::config::Config;
+use crate::logging::LogHandles;
+use async_trait::async_trait;
+use ironfish_api::{ApiState, ConfigReloader};
+use ironfish_cluster::CpuAwareLoadBalancer;
+use ironfish_core::{ConfigChange, ConfigReloadReport, Error, Result};
+use ironfish_stockfish::EnginePool;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::warn;
+const RELOADABLE: &[&str] = &[
+    "auth.rate_limit_per_minute",
+    "load_balancer.cpu_weight",
+    "load_balancer.queue_weight",
+    "load_balancer.latency_weight",
+    "stockfish.pool_size",
+    "telemetry.log_level",
+];
+const RELOADABLE_PREFIXES: &[&str] = &["limits.", "logging.sampling"];
+pub struct ServerReloader {
+    path: PathBuf,
+    startup: toml::Table,
+    running: Mutex<Config>,
+    pool: Option<Arc<EnginePool>>,
+    load_balancer: Arc<CpuAwareLoadBalancer>,
+    logging: Option<LogHandles>,
+}
+impl ServerReloader {
+    pub fn new(
+        config: Config,
+        pool: Option<Arc<EnginePool>>,
+        load_balancer: Arc<CpuAwareLoadBalancer>,
+        logging: Option<LogHandles>,
+    ) -> Self {
+        Self {
+            path: PathBuf::new(),
+            startup: toml::Table::new(),
+            running: Mutex::new(config),
+            pool,
+            load_balancer,
+            logging,
+        }
+        .with_path(PathBuf::from(Config::path()))
+    }
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.startup = Config::read_table_at(&path).unwrap_or_else(|e| {
+            warn!("could not snapshot configuration for reloads: {}", e);
+            toml::Table::new()
+        });
+        self.path = path;
+        self
+    }
+}
+#[async_trait]
+impl ConfigReloader for ServerReloader {
+    async fn reload(&self, state: &ApiState) -> Result<ConfigReloadReport> {
+        let table = Config::read_table_at(&self.path).map_err(|e| Error::Config(e.to_string()))?;
+        let next = Config::from_table(table.clone()).map_err(|e| Error::Config(e.to_string()))?;
+        next.validate().map_err(|e| Error::Config(e.to_string()))?;
+        let filter = next
+            .telemetry
+            .log_filter()
+            .map_err(|e| Error::Config(format!("telemetry.log_level: {}", e)))?;
+        let mut running = self.running.lock().await;
+        let mut applied = Vec::new();
+        let pool_size = next.stockfish.pool_size;
+        if let Some(ref pool) = self.pool {
+            if running.stockfish.pool_size != pool_size {
+                pool.resize(pool_size).await.map_err(|e| {
+                    warn!("engine pool resize to {} failed: {}", pool_size, e);
+                    Error::Engine(format!(
+                        "stockfish.pool_size: resize to {} failed: {}",
+                        pool_size, e
+                    ))
+                })?;
+                changed(
+                    &mut applied,
+                    "stockfish.pool_size",
+                    running.stockfish.pool_size,
+                    pool_size,
+                );
+                running.stockfish.pool_size = pool_size;
+            }
+        }
+        let (from, to) = (running.limits, next.limits);
+        let limits_changed = [
+            changed(
+                &mut applied,
+                "limits.max_depth",
+                from.max_depth,
+                to.max_depth,
+            ),
+            changed(
+                &mut applied,
+                "limits.max_multipv",
+                from.max_multipv,
+                to.max_multipv,
+            ),
+            changed(
+                &mut applied,
+                "limits.min_movetime_ms",
+                from.min_movetime_ms,
+                to.min_movetime_ms,
+            ),
+            changed(
+                &mut applied,
+                "limits.max_movetime_ms",
+                from.max_movetime_ms,
+                to.max_movetime_ms,
+            ),
+            changed(
+                &mut applied,
+                "limits.max_concurrent_analyses",
+                from.max_concurrent_analyses,
+                to.max_concurrent_analyses,
+            ),
+            changed(
+                &mut applied,
+                "limits.max_game_plies",
+                from.max_game_plies,
+                to.max_game_plies,
+            ),
+            changed(
+                &mut applied,
+                "limits.max_game_concurrency",
+                from.max_game_concurrency,
+                to.max_game_concurrency,
+            ),
+        ];
+        if limits_changed.contains(&true) {
+            state.set_limits(to);
+        }
+        if changed(
+            &mut applied,
+            "auth.rate_limit_per_minute",
+            running.auth.rate_limit_per_minute,
+            next.auth.rate_limit_per_minute,
+        ) {
+            state
+                .rate_limiter
+                .set_default_per_minute(next.auth.rate_limit_per_minute);
+        }
+        let (from, to) = (&running.load_balancer, &next.load_balancer);
+        let weights_changed = [
+            changed(
+                &mut applied,
+                "load_balancer.cpu_weight",
+                from.cpu_weight,
+                to.cpu_weight,
+            ),
+            changed(
+                &mut applied,
+                "load_balancer.queue_weight",
+                from.queue_weight,
+                to.queue_weight,
+            ),
+            changed(
+                &mut applied,
+                "load_balancer.latency_weight",
+                from.latency_weight,
+                to.latency_weight,
+            ),
+        ];
+        if weights_changed.contains(&true) {
+            self.load_balancer
+                .set_weights(to.cpu_weight, to.queue_weight, to.latency_weight);
+        }
+        if let Some(ref logging) = self.logging {
+            let (from, to) = (&running.telemetry.log_level, &next.telemetry.log_level);
+            if from != to {
+                logging
+                    .filter
+                    .reload(filter)
+                    .map_err(|e| Error::Internal(e.to_string()))?;
+                applied.push(ConfigChange {
+                    key: "telemetry.log_level".to_string(),
+                    from: from.clone().unwrap_or_default(),
+                    to: to.clone().unwrap_or_default(),
+                });
+                running.telemetry.log_level = next.telemetry.log_level.clone();
+            }
+            let (from, to) = (&running.logging.sampling, &next.logging.sampling);
+            if from != to {
+                logging.sampler.set_rules(to);
+                applied.push(ConfigChange {
+                    key: "logging.sampling".to_string(),
+                    from: sampling_rules(from),
+                    to: sampling_rules(to),
+                });
+                running.logging.sampling = next.logging.sampling.clone();
+            }
+        }
+        running.limits = next.limits;
+        running.auth.rate_limit_per_minute = next.auth.rate_limit_per_minute;
+        running.load_balancer = next.load_balancer.clone();
+        let mut skipped = Vec::new();
+        diff_keys("", &self.startup, &table, &mut skipped);
+        skipped.retain(|key| {
+            !RELOADABLE_PREFIXES.iter().any(|p| key.starts_with(p))
+                && !RELOADABLE.contains(&key.as_str())
+        });
+        for key in &skipped {
+            warn!("config {} changed but requires a restart", key);
+        }
+        Ok(ConfigReloadReport { applied, skipped })
+    }
+}
+fn changed<T: PartialEq + Display>(
+    applied: &mut Vec<ConfigChange>,
+    key: &str,
+    from: T,
+    to: T,
+) -> bool {
+    if from == to {
+        return false;
+    }
+    applied.push(ConfigChange {
+        key: key.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+    });
+    true
+}
+fn sampling_rules(rules: &HashMap<String, u64>) -> String {
+    let mut rules: Vec<String> = rules
+        .iter()
+        .map(|(target, every)| format!("{}={}", target, every))
+        .collect();
+    rules.sort();
+    rules.join(",")
+}
+fn diff_keys(prefix: &str, before: &toml::Table, after: &toml::Table, keys: &mut Vec<String>) {
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let key = match prefix {
+            "" => name.clone(),
+            prefix => format!("{}.{}", prefix, name),
+        };
+        match (before.get(name), after.get(name)) {
+            (Some(toml::Value::Table(before)), Some(toml::Value::Table(after))) => {
+                diff_keys(&key, before, after, keys)
+            }
+            (Some(toml::Value::Table(before)), None) => {
+                diff_keys(&key, before, &toml::Table::new(), keys)
+            }
+            (None, Some(toml::Value::Table(after))) => {
+                diff_keys(&key, &toml::Table::new(), after, keys)
+            }
+            (before, after) if before != after => keys.push(key),
+            _ => {}
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ironfish_api::ws::SessionManager;
+    use ironfish_api::WebSocketConfig;
+    use ironfish_auth::{SledTokenStore, TokenManager};
+    use ironfish_cluster::{LoadBalancerConfig, MembershipManager, Node, NodeConfig};
+    use ironfish_stockfish::{AnalysisService, EnginePoolConfig};
+    use std::path::Path;
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ironfish-reload-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    fn api_state() -> ApiState {
+        let node = Arc::new(Node::new(NodeConfig {
+            id: Some("node-reload".to_string()),
+            bind_address: "127.0.0.1:0".parse().unwrap(),
+            advertise_address: None,
+            priority: 100,
+            version: "test".to_string(),
+        }));
+        ApiState::new(
+            Arc::new(AnalysisService::new_mock()),
+            Arc::new(SledTokenStore::in_memory().unwrap()),
+            Arc::new(TokenManager::new(&TokenManager::generate_secret(), "test")),
+            node.clone(),
+            Arc::new(MembershipManager::new(node)),
+            Arc::new(SessionManager::new(8)),
+            WebSocketConfig::default(),
+        )
+    }
+    fn write_config(path: &Path, binary: &str, extra: &str) -> Config {
+        let content = format!(
+            "[stockfish]\nbinary_path = \"{}\"\npool_size = 1\n{}",
+            binary, extra
+        );
+        std::fs::write(path, &content).unwrap();
+        toml::from_str(&content).unwrap()
+    }
+    fn reloader(config: Config, path: &Path, pool: Option<Arc<EnginePool>>) -> ServerReloader {
+        ServerReloader::new(
+            config,
+            pool,
+            Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default())),
+            None,
+        )
+        .with_path(path.to_path_buf())
+    }
+    #[tokio::test]
+    async fn test_reload_applies_added_tables_and_skips_restart_keys() {
+        let dir = scratch_dir("tables");
+        let path = dir.join("config.toml");
+        let binary = std::env::current_exe().unwrap().display().to_string();
+        let config = write_config(&path, &binary, "[node]\nbind_address = \"0.0.0.0:8080\"\n");
+        let reloader = reloader(config, &path, None);
+        let state = api_state();
+        write_config(
+            &path,
+            &binary,
+            "[node]\nbind_address = \"0.0.0.0:9090\"\n[limits]\nmax_depth = 12\n",
+        );
+        let report = reloader.reload(&state).await.unwrap();
+        let applied: Vec<&str> = report.applied.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(applied, vec!["limits.max_depth"]);
+        assert_eq!(report.skipped, vec!["node.bind_address"]);
+        assert_eq!(state.limits().max_depth, 12);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reload_reports_failed_pool_resize() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("resize");
+        let (path, broken) = (dir.join("config.toml"), dir.join("broken"));
+        let script = dir.join("engine.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n[ -e \"{}\" ] && exit 1\nwhile IFS= read -r line; do\n  case \"$line\" in\n    uci) echo \"uciok\" ;;\n    isready) echo \"readyok\" ;;\n    go*) echo \"bestmove e2e4\" ;;\n    quit) exit 0 ;;\n  esac\ndone\n",
+                broken.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let binary = script.display().to_string();
+        let pool = Arc::new(
+            EnginePool::new(EnginePoolConfig {
+                binary_path: binary.clone(),
+                pool_size: 1,
+                warmup_depth: 0,
+                ..EnginePoolConfig::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let config = write_config(&path, &binary, "");
+        let reloader = reloader(config, &path, Some(pool.clone()));
+        let state = api_state();
+        std::fs::write(&broken, "").unwrap();
+        std::fs::write(
+            &path,
+            format!("[stockfish]\nbinary_path = \"{}\"\npool_size = 2\n", binary),
+        )
+        .unwrap();
+        let err = reloader.reload(&state).await.unwrap_err();
+        assert!(err.to_string().contains("stockfish.pool_size"), "{}", err);
+        assert_eq!(pool.size(), 1);
+        std::fs::remove_file(&broken).unwrap();
+        let report = reloader.reload(&state).await.unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].key, "stockfish.pool_size");
+        assert_eq!(pool.size(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn test_diff_keys_reports_nested_changes_without_values() {
+        let before: toml::Table = toml::from_str(
+            "[node]\nbind_address = \"0.0.0.0:8080\"\n[cluster]\nsecret = \"old\"\n[limits]\nmax_depth = 30\n",
+        )
+        .unwrap();
+        let after: toml::Table = toml::from_str(
+            "[node]\nbind_address = \"0.0.0.0:9090\"\n[cluster]\nsecret = \"new\"\n[limits]\nmax_depth = 30\n[auth]\nenabled = false\n",
+        )
+        .unwrap();
+        let mut keys = Vec::new();
+        diff_keys("", &before, &after, &mut keys);
+        assert_eq!(
+            keys,
+            vec!["auth.enabled", "cluster.secret", "node.bind_address"]
+        );
+    }
+}
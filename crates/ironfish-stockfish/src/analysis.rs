@@ -1,22 +1,51 @@
+use crate::cache::AnalysisCache;
 use crate::engine::{BestMove, UciInfo};
-use crate::pool::EnginePool;
+use crate::limits::SearchLimits;
+use crate::play::PlaySession;
+use crate::pool::{EngineKind, EnginePool, PooledEngine, RequestClass};
+use crate::pv::{self, PvLines};
+use crate::tablebase::SyzygyTables;
+#[cfg(feature = "tablebase-probe")]
+use crate::tablebase::TablebaseProbe;
+use crate::uci::{EngineHandle, UciEngine};
 use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
 use ironfish_core::{
-    AnalysisProgress, AnalysisRequest, AnalysisResult, BestMoveRequest, BestMoveResponse,
-    ChessPosition, Error, Evaluation, Move, PrincipalVariation, Result,
+    centipawn_loss, AnalysisProgress, AnalysisRequest, AnalysisResult, BestMoveRequest,
+    BestMoveResponse, ChessPosition, Error, GameAnalysis, GameAnalysisRequest, GameOver, Move,
+    MoveAnalysis, Perspective, PlayCommand, PlayUpdate, PrincipalVariation, Result, Score,
+    ScoreHistoryEntry, StopReason, Telemetry, Variant, MAX_SCORE_HISTORY,
 };
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::time::{timeout, Duration};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, instrument};
+const SKILL_LEVEL_OPTION: &str = "Skill Level";
+const MAX_SKILL_LEVEL: u8 = 20;
+const STOP_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 pub struct AnalysisService {
     pool: Option<Arc<EnginePool>>,
     default_depth: u8,
     default_movetime: u64,
     analysis_timeout: Duration,
+    stop_drain_timeout: Duration,
+    play_budget: Duration,
     mock_mode: bool,
+    active: Arc<AtomicUsize>,
+    telemetry: Telemetry,
+    cache: Option<Arc<AnalysisCache>>,
+    tablebases: SyzygyTables,
+    #[cfg(feature = "tablebase-probe")]
+    probe: Option<Arc<dyn TablebaseProbe>>,
+    shutdown: CancellationToken,
+}
+struct ActiveGuard(Arc<AtomicUsize>);
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 impl AnalysisService {
     pub fn new(pool: Arc<EnginePool>) -> Self {
@@ -25,7 +54,16 @@ impl AnalysisService {
             default_depth: 20,
             default_movetime: 1000,
             analysis_timeout: Duration::from_secs(60),
+            stop_drain_timeout: STOP_DRAIN_TIMEOUT,
+            play_budget: Duration::from_secs(1800),
             mock_mode: false,
+            active: Arc::new(AtomicUsize::new(0)),
+            telemetry: Telemetry::default(),
+            cache: None,
+            tablebases: SyzygyTables::default(),
+            #[cfg(feature = "tablebase-probe")]
+            probe: None,
+            shutdown: CancellationToken::new(),
         }
     }
     pub fn new_mock() -> Self {
@@ -34,7 +72,16 @@ impl AnalysisService {
             default_depth: 20,
             default_movetime: 1000,
             analysis_timeout: Duration::from_secs(60),
+            stop_drain_timeout: STOP_DRAIN_TIMEOUT,
+            play_budget: Duration::from_secs(1800),
             mock_mode: true,
+            active: Arc::new(AtomicUsize::new(0)),
+            telemetry: Telemetry::default(),
+            cache: None,
+            tablebases: SyzygyTables::default(),
+            #[cfg(feature = "tablebase-probe")]
+            probe: None,
+            shutdown: CancellationToken::new(),
         }
     }
     pub fn with_default_depth(mut self, depth: u8) -> Self {
@@ -45,52 +92,381 @@ impl AnalysisService {
         self.default_movetime = ms;
         self
     }
+    pub fn with_play_budget(mut self, budget: Duration) -> Self {
+        self.play_budget = budget;
+        self
+    }
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+    pub fn with_cache(mut self, cache: Arc<AnalysisCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+    pub fn with_tablebases(mut self, tablebases: SyzygyTables) -> Self {
+        self.tablebases = tablebases;
+        self
+    }
+    #[cfg(feature = "tablebase-probe")]
+    pub fn with_tablebase_probe(mut self, probe: Arc<dyn TablebaseProbe>) -> Self {
+        self.probe = Some(probe);
+        self
+    }
+    pub fn cache(&self) -> Option<&Arc<AnalysisCache>> {
+        self.cache.as_ref()
+    }
+    pub fn active_analyses(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+    pub fn begin_shutdown(&self) {
+        self.shutdown.cancel();
+    }
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            while self.active_analyses() > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+    fn ensure_accepting(&self) -> Result<()> {
+        if self.is_shutting_down() {
+            return Err(Error::ShuttingDown);
+        }
+        Ok(())
+    }
+    async fn stop_on_shutdown(
+        &self,
+        engine: &dyn UciEngine,
+        collect: impl std::future::Future<Output = Result<AnalysisResult>>,
+    ) -> Result<AnalysisResult> {
+        tokio::pin!(collect);
+        tokio::select! {
+            result = &mut collect => result,
+            _ = self.shutdown.cancelled() => {
+                debug!("stopping search for shutdown");
+                let _ = engine.stop().await;
+                timeout(self.stop_drain_timeout, collect)
+                    .await
+                    .unwrap_or(Err(Error::AnalysisTimeout))
+            }
+        }
+    }
+    fn stop_reason(
+        &self,
+        limits: &SearchLimits,
+        depth_reached: u8,
+        info: &UciInfo,
+        time_ms: u64,
+    ) -> StopReason {
+        if self.is_shutting_down() {
+            StopReason::Cancelled
+        } else {
+            limits.stop_reason(depth_reached, info, time_ms)
+        }
+    }
+    fn track(&self) -> ActiveGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ActiveGuard(self.active.clone())
+    }
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.analysis_timeout = timeout;
         self
     }
-    async fn stop_and_drain(engine: &crate::engine::StockfishEngine) {
-        let _ = engine.stop().await;
+    pub fn with_stop_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_drain_timeout = timeout;
+        self
+    }
+    async fn salvage_bestmove(engine: &dyn UciEngine, pvs: &mut PvLines) -> Option<BestMove> {
         let drain_timeout = Duration::from_secs(10);
-        let _ = timeout(drain_timeout, async {
-            while let Ok(line) = engine.read_line().await {
-                if BestMove::parse(line.trim()).is_some() {
-                    break;
+        let drain = async {
+            engine.stop().await?;
+            engine.send_command("isready").await?;
+            let mut best = None;
+            loop {
+                let line = engine.read_line().await?;
+                let line = line.trim();
+                if line == "readyok" {
+                    return Ok::<_, Error>(best);
+                }
+                if best.is_some() {
+                    continue;
+                }
+                if let Some(info) = UciInfo::parse(line) {
+                    pvs.record(&info);
                 }
+                best = BestMove::parse(line);
             }
-        })
-        .await;
+        };
+        timeout(drain_timeout, drain).await.ok()?.ok()?
+    }
+    fn release<T>(pooled: &PooledEngine<'_>, result: &Result<T>) -> bool {
+        let abnormal = matches!(
+            result,
+            Err(Error::AnalysisTimeout) | Err(Error::AnalysisCancelled)
+        );
+        if abnormal {
+            pooled.mark_abnormal();
+        }
+        abnormal
+    }
+    fn validate_skill_level(&self, level: Option<u8>) -> Result<()> {
+        let kind = self.pool.as_ref().map(|pool| pool.kind());
+        match level {
+            Some(_) if kind.is_some_and(|kind| kind != EngineKind::Stockfish) => {
+                Err(Error::InvalidArgument(
+                    "skill level is only supported by stockfish engines".to_string(),
+                ))
+            }
+            Some(level) if level > MAX_SKILL_LEVEL => Err(Error::InvalidArgument(format!(
+                "skill level must be between 0 and {}, got {}",
+                MAX_SKILL_LEVEL, level
+            ))),
+            _ => Ok(()),
+        }
+    }
+    async fn prepare_engine(
+        engine: &EngineHandle,
+        variant: Variant,
+        skill_level: Option<u8>,
+    ) -> Result<()> {
+        if variant.is_chess960() {
+            engine.set_chess960(true).await?;
+        }
+        if let Some(level) = skill_level {
+            if let Err(e) = engine
+                .set_option(SKILL_LEVEL_OPTION, &level.to_string())
+                .await
+            {
+                Self::reset_engine(engine, variant, None).await;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+    async fn reset_engine(engine: &EngineHandle, variant: Variant, skill_level: Option<u8>) {
+        if variant.is_chess960() {
+            let _ = engine.set_chess960(false).await;
+        }
+        if skill_level.is_some() {
+            let default = engine
+                .configured_option(SKILL_LEVEL_OPTION)
+                .map(String::from)
+                .unwrap_or_else(|| MAX_SKILL_LEVEL.to_string());
+            let _ = engine.set_option(SKILL_LEVEL_OPTION, &default).await;
+        }
     }
 
     #[instrument(skip(self), fields(id = %request.id))]
     pub async fn analyze(&self, request: AnalysisRequest) -> Result<AnalysisResult> {
-        let position = ChessPosition::new(&request.fen);
-        if !position.validate() {
-            return Err(Error::InvalidFen(request.fen.clone()));
+        let started = std::time::Instant::now();
+        let result = self.run_request(request).await;
+        self.telemetry
+            .analysis_finished(started.elapsed(), result.as_ref().err());
+        result
+    }
+    async fn run_request(&self, request: AnalysisRequest) -> Result<AnalysisResult> {
+        self.ensure_accepting()?;
+        let position = request.validate()?;
+        self.validate_skill_level(request.skill_level)?;
+        if let Some(result) = self.probed(&request, &position) {
+            return Ok(self.normalized(result, &request, &position));
+        }
+        let cache_key = self.cache_key(&request, &position);
+        if let Some(result) = self.cached(cache_key.as_deref(), &request).await {
+            return Ok(self.normalized(result, &request, &position));
+        }
+        let result = self.run_engine_request(&request).await;
+        self.remember(cache_key, &result).await;
+        result.map(|result| self.normalized(result, &request, &position))
+    }
+    fn normalized(
+        &self,
+        mut result: AnalysisResult,
+        request: &AnalysisRequest,
+        position: &ChessPosition,
+    ) -> AnalysisResult {
+        Self::restrict_variations(&mut result.principal_variations, request);
+        result.resolve_tablebase(self.tablebases.covers(position));
+        result.resolve_game_over(position);
+        if request.flips_scores(position) {
+            result.flip_scores();
+        }
+        result.render_notation(position, request.notation);
+        result.clamped = request.clamped;
+        if !request.include_history {
+            result.score_history.clear();
         }
+        result
+    }
+    fn normalize_progress(
+        progress: &mut AnalysisProgress,
+        request: &AnalysisRequest,
+        position: Option<&ChessPosition>,
+    ) {
+        Self::restrict_variations(&mut progress.principal_variations, request);
+        let Some(position) = position else {
+            return;
+        };
+        if request.flips_scores(position) {
+            progress.flip_scores();
+        }
+        progress.render_notation(position, request.notation);
+    }
+    fn restrict_variations(variations: &mut Vec<PrincipalVariation>, request: &AnalysisRequest) {
+        if request.search_moves.is_empty() {
+            return;
+        }
+        variations.retain(|pv| pv.moves.first().is_some_and(|mv| request.searches_move(mv)));
+    }
+    async fn run_engine_request(&self, request: &AnalysisRequest) -> Result<AnalysisResult> {
+        let _active = self.track();
         if self.mock_mode {
-            return Ok(self.mock_analysis_result(&request));
+            return Ok(self.mock_analysis_result(request));
         }
         let pool = self
             .pool
             .as_ref()
             .ok_or_else(|| Error::Engine("no pool".into()))?;
-        let pooled = pool.acquire().await?;
+        let pooled = pool.acquire(pool.classify(request.movetime)).await?;
         let engine = pooled.engine();
-        engine.ensure_ready().await?;
+        Self::prepare_engine(engine, request.variant, request.skill_level).await?;
+        let result = self.run_analysis(request, engine).await;
+        Self::release(&pooled, &result);
+        Self::reset_engine(engine, request.variant, request.skill_level).await;
+        result
+    }
+    fn cache_key(&self, request: &AnalysisRequest, position: &ChessPosition) -> Option<String> {
+        match self.cache {
+            Some(_) if request.skill_level.is_none() && !request.infinite => {
+                Some(AnalysisCache::key(request, position))
+            }
+            _ => None,
+        }
+    }
+    #[cfg(feature = "tablebase-probe")]
+    fn probed(
+        &self,
+        request: &AnalysisRequest,
+        position: &ChessPosition,
+    ) -> Option<AnalysisResult> {
+        if request.skill_level.is_some() || request.infinite || !self.tablebases.covers(position) {
+            return None;
+        }
+        let hit = self.probe.as_ref()?.probe(position)?;
+        debug!("answering analysis {} from the tablebases", request.id);
+        let evaluation = Score::Tablebase(hit.tablebase.wdl);
+        Some(AnalysisResult {
+            id: request.id,
+            fen: request.fen.clone(),
+            best_move: hit.best_move.clone(),
+            game_over: None,
+            best_move_san: None,
+            ponder: None,
+            ponder_san: None,
+            evaluation,
+            tablebase: Some(hit.tablebase),
+            wdl: None,
+            principal_variations: hit
+                .best_move
+                .map(|mv| PrincipalVariation {
+                    rank: 1,
+                    moves: vec![mv],
+                    evaluation,
+                    depth: 0,
+                    seldepth: None,
+                    nodes: None,
+                    san: None,
+                })
+                .into_iter()
+                .collect(),
+            depth_reached: 0,
+            nodes_searched: 0,
+            tbhits: 1,
+            time_ms: 0,
+            stopped_by: StopReason::Depth,
+            variant: request.variant,
+            completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: false,
+            score_history: Vec::new(),
+        })
+    }
+    #[cfg(not(feature = "tablebase-probe"))]
+    fn probed(
+        &self,
+        _request: &AnalysisRequest,
+        _position: &ChessPosition,
+    ) -> Option<AnalysisResult> {
+        None
+    }
+    async fn cached(&self, key: Option<&str>, request: &AnalysisRequest) -> Option<AnalysisResult> {
+        let (cache, key) = self.cache.as_ref().zip(key)?;
+        let hit = cache.get(key, request.depth).await;
+        self.telemetry.cache_lookup(hit.is_some());
+        let mut result = hit?;
+        debug!("serving analysis {} from cache", request.id);
+        result.id = request.id;
+        result.fen = request.fen.clone();
+        result.time_ms = 0;
+        result.completed_at = Utc::now();
+        result.cached = true;
+        Some(result)
+    }
+    async fn remember(&self, key: Option<String>, result: &Result<AnalysisResult>) {
+        if let (Some(cache), Some(key), Ok(result)) = (&self.cache, key, result) {
+            if result.stopped_by != StopReason::Cancelled && !result.partial {
+                cache.insert(key, result).await;
+            }
+        }
+    }
+    fn cached_progress(result: &AnalysisResult, target_depth: u8) -> AnalysisProgress {
+        AnalysisProgress {
+            id: result.id,
+            current_depth: result.depth_reached,
+            target_depth,
+            current_move: None,
+            nodes_per_second: 0,
+            hash_full: 0,
+            elapsed_ms: 0,
+            evaluation: Some(result.evaluation),
+            wdl: result.wdl,
+            principal_variations: result.principal_variations.clone(),
+        }
+    }
+    #[instrument(name = "engine_search", skip_all, fields(depth = request.depth))]
+    async fn run_analysis(
+        &self,
+        request: &AnalysisRequest,
+        engine: &dyn UciEngine,
+    ) -> Result<AnalysisResult> {
         engine.set_multipv(request.multipv.max(1)).await?;
-        engine.set_position(&request.fen).await?;
-        engine.go_depth(request.depth).await?;
+        engine
+            .set_position_with_moves(&request.fen, &request.moves)
+            .await?;
+        let limits = SearchLimits::from_request(request);
+        let mut pvs = PvLines::default();
+        let start = std::time::Instant::now();
+        engine.go(&limits).await?;
         match timeout(
-            self.analysis_timeout,
-            self.collect_analysis(&request, engine),
+            limits.search_timeout(self.analysis_timeout),
+            self.stop_on_shutdown(
+                engine,
+                self.collect_analysis(request, &limits, engine, &mut pvs, start),
+            ),
         )
         .await
         {
             Ok(result) => result,
             Err(_) => {
-                Self::stop_and_drain(engine).await;
-                Err(Error::AnalysisTimeout)
+                self.timed_out_result(request, &limits, engine, &mut pvs, start)
+                    .await
             }
         }
     }
@@ -100,149 +476,186 @@ impl AnalysisService {
         progress_tx: mpsc::Sender<AnalysisProgress>,
         cancel: CancellationToken,
     ) -> Result<AnalysisResult> {
-        let position = ChessPosition::new(&request.fen);
-        if !position.validate() {
-            return Err(Error::InvalidFen(request.fen.clone()));
+        let started = std::time::Instant::now();
+        let result = self
+            .run_streaming_request(request, progress_tx, cancel)
+            .await;
+        self.telemetry
+            .analysis_finished(started.elapsed(), result.as_ref().err());
+        result
+    }
+    async fn run_streaming_request(
+        &self,
+        request: AnalysisRequest,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<AnalysisResult> {
+        self.ensure_accepting()?;
+        let position = request.validate()?;
+        self.validate_skill_level(request.skill_level)?;
+        let cache_key = self.cache_key(&request, &position);
+        let precomputed = match self.probed(&request, &position) {
+            Some(result) => Some(result),
+            None => self.cached(cache_key.as_deref(), &request).await,
+        };
+        if let Some(result) = precomputed {
+            let result = self.normalized(result, &request, &position);
+            let _ = progress_tx.try_send(Self::cached_progress(&result, request.depth));
+            return Ok(result);
         }
+        let result = self
+            .run_streaming_engine_request(&request, progress_tx, cancel)
+            .await;
+        self.remember(cache_key, &result).await;
+        result.map(|result| self.normalized(result, &request, &position))
+    }
+    async fn run_streaming_engine_request(
+        &self,
+        request: &AnalysisRequest,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<AnalysisResult> {
+        let _active = self.track();
         if self.mock_mode {
             return self
-                .mock_streaming_analysis(&request, progress_tx, cancel)
+                .mock_streaming_analysis(request, progress_tx, cancel)
                 .await;
         }
         let pool = self
             .pool
             .as_ref()
             .ok_or_else(|| Error::Engine("no pool".into()))?;
-        let pooled = pool.acquire().await?;
+        let pooled = pool.acquire(pool.classify(request.movetime)).await?;
         let engine = pooled.engine();
-        engine.ensure_ready().await?;
+        Self::prepare_engine(engine, request.variant, request.skill_level).await?;
+        let result = self
+            .run_analysis_streaming(request, engine, progress_tx, cancel)
+            .await;
+        if !Self::release(&pooled, &result) {
+            Self::reset_engine(engine, request.variant, request.skill_level).await;
+        }
+        result
+    }
+    #[instrument(name = "engine_search", skip_all, fields(depth = request.depth))]
+    async fn run_analysis_streaming(
+        &self,
+        request: &AnalysisRequest,
+        engine: &dyn UciEngine,
+        progress_tx: mpsc::Sender<AnalysisProgress>,
+        cancel: CancellationToken,
+    ) -> Result<AnalysisResult> {
         engine.set_multipv(request.multipv.max(1)).await?;
-        engine.set_position(&request.fen).await?;
-        engine.go_depth(request.depth).await?;
-        match timeout(
-            self.analysis_timeout,
-            self.collect_analysis_streaming(&request, engine, progress_tx, cancel),
-        )
-        .await
-        {
+        engine
+            .set_position_with_moves(&request.fen, &request.moves)
+            .await?;
+        let limits = SearchLimits::from_request(request);
+        let mut pvs = PvLines::default();
+        let start = std::time::Instant::now();
+        let collect = self.stop_on_shutdown(
+            engine,
+            self.collect_analysis_streaming(
+                request,
+                &limits,
+                engine,
+                progress_tx,
+                cancel,
+                &mut pvs,
+                start,
+            ),
+        );
+        engine.go(&limits).await?;
+        if request.infinite {
+            return collect.await;
+        }
+        match timeout(limits.search_timeout(self.analysis_timeout), collect).await {
             Ok(result) => result,
             Err(_) => {
-                Self::stop_and_drain(engine).await;
-                Err(Error::AnalysisTimeout)
+                self.timed_out_result(request, &limits, engine, &mut pvs, start)
+                    .await
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn collect_analysis_streaming(
         &self,
         request: &AnalysisRequest,
-        engine: &crate::engine::StockfishEngine,
+        limits: &SearchLimits,
+        engine: &dyn UciEngine,
         progress_tx: mpsc::Sender<AnalysisProgress>,
         cancel: CancellationToken,
+        pvs: &mut PvLines,
+        start: std::time::Instant,
     ) -> Result<AnalysisResult> {
-        let mut pvs: HashMap<u8, (UciInfo, Vec<String>)> = HashMap::new();
-        let mut final_info: Option<UciInfo> = None;
-        let start = std::time::Instant::now();
+        let position = request.validate().ok();
+        let mut stopping = false;
         let best = loop {
-            if cancel.is_cancelled() {
+            if !request.infinite && cancel.is_cancelled() {
                 let _ = engine.stop().await;
-                loop {
-                    let line = engine.read_line().await?;
-                    if BestMove::parse(line.trim()).is_some() {
-                        break;
+                let drain = async {
+                    loop {
+                        let line = engine.read_line().await?;
+                        if BestMove::parse(line.trim()).is_some() {
+                            return Ok::<_, Error>(());
+                        }
                     }
-                }
+                };
+                let _ = timeout(self.stop_drain_timeout, drain).await;
                 return Err(Error::AnalysisCancelled);
             }
-            let line = engine.read_line().await?;
+            let line = if stopping {
+                timeout(self.stop_drain_timeout, engine.read_line())
+                    .await
+                    .map_err(|_| Error::AnalysisTimeout)??
+            } else {
+                tokio::select! {
+                    line = engine.read_line() => line?,
+                    _ = cancel.cancelled() => {
+                        if request.infinite {
+                            let _ = engine.stop().await;
+                            stopping = true;
+                        }
+                        continue;
+                    }
+                }
+            };
             let line = line.trim().to_string();
             if let Some(info) = UciInfo::parse(&line) {
-                let pv_idx = info.multipv.unwrap_or(1);
-                if !info.pv.is_empty() {
-                    pvs.insert(pv_idx, (info.clone(), info.pv.clone()));
-                }
+                pvs.record(&info);
                 if !info.pv.is_empty() || info.score_cp.is_some() || info.score_mate.is_some() {
                     let eval = if let Some(mate) = info.score_mate {
-                        Some(Evaluation::mate(mate))
+                        Some(Score::MateIn(mate))
                     } else {
-                        info.score_cp.map(Evaluation::centipawns)
+                        info.score_cp.map(Score::Centipawns)
                     };
-                    let current_pvs: Vec<PrincipalVariation> = pvs
-                        .iter()
-                        .map(|(rank, (pv_info, moves))| {
-                            let moves: Vec<Move> =
-                                moves.iter().filter_map(|m| Move::from_uci(m)).collect();
-                            let pv_eval = if let Some(mate) = pv_info.score_mate {
-                                Evaluation::mate(mate)
-                            } else {
-                                Evaluation::centipawns(pv_info.score_cp.unwrap_or(0))
-                            };
-                            PrincipalVariation {
-                                rank: *rank,
-                                moves,
-                                evaluation: pv_eval,
-                                depth: pv_info.depth.unwrap_or(0),
-                            }
-                        })
-                        .collect();
-                    let progress = AnalysisProgress {
+                    let mut progress = AnalysisProgress {
                         id: request.id,
                         current_depth: info.depth.unwrap_or(0),
                         target_depth: request.depth,
                         current_move: info.currmove.as_ref().and_then(|m| Move::from_uci(m)),
                         nodes_per_second: info.nps.unwrap_or(0),
                         hash_full: info.hashfull.unwrap_or(0),
+                        elapsed_ms: start.elapsed().as_millis() as u64,
                         evaluation: eval,
-                        principal_variations: current_pvs,
+                        wdl: pv::wdl(&info),
+                        principal_variations: pvs.variations(),
                     };
+                    Self::normalize_progress(&mut progress, request, position.as_ref());
                     let _ = progress_tx.try_send(progress);
                 }
-                final_info = Some(info);
             }
             if let Some(bm) = BestMove::parse(&line) {
                 break bm;
             }
         };
-        let info = final_info.unwrap_or_default();
-        let elapsed = start.elapsed();
-        let best_move_parsed =
-            Move::from_uci(&best.mv).ok_or_else(|| Error::Engine("invalid bestmove".into()))?;
-        let ponder = best.ponder.as_ref().and_then(|p| Move::from_uci(p));
-        let evaluation = if let Some(mate) = info.score_mate {
-            Evaluation::mate(mate)
-        } else {
-            Evaluation::centipawns(info.score_cp.unwrap_or(0))
-        };
-        let mut principal_variations: Vec<PrincipalVariation> = pvs
-            .into_iter()
-            .map(|(rank, (pv_info, moves))| {
-                let moves: Vec<Move> = moves.iter().filter_map(|m| Move::from_uci(m)).collect();
-                let eval = if let Some(mate) = pv_info.score_mate {
-                    Evaluation::mate(mate)
-                } else {
-                    Evaluation::centipawns(pv_info.score_cp.unwrap_or(0))
-                };
-                PrincipalVariation {
-                    rank,
-                    moves,
-                    evaluation: eval,
-                    depth: pv_info.depth.unwrap_or(0),
-                }
-            })
-            .collect();
-        principal_variations.sort_by_key(|pv| pv.rank);
-        Ok(AnalysisResult {
-            id: request.id,
-            fen: request.fen.clone(),
-            best_move: best_move_parsed,
-            ponder,
-            evaluation,
-            principal_variations,
-            depth_reached: info.depth.unwrap_or(request.depth),
-            nodes_searched: info.nodes.unwrap_or(0),
-            time_ms: elapsed.as_millis() as u64,
-            completed_at: Utc::now(),
-        })
+        self.search_result(
+            request,
+            limits,
+            pvs,
+            &best,
+            start,
+            stopping.then_some(StopReason::Cancelled),
+        )
     }
 
     async fn mock_streaming_analysis(
@@ -251,11 +664,26 @@ impl AnalysisService {
         progress_tx: mpsc::Sender<AnalysisProgress>,
         cancel: CancellationToken,
     ) -> Result<AnalysisResult> {
-        for depth in [5, 10, 15, request.depth] {
+        let start = std::time::Instant::now();
+        let position = request.validate().ok();
+        let depths: Vec<u8> = if request.infinite {
+            (1..=u8::MAX).collect()
+        } else {
+            vec![5, 10, 15, request.depth]
+        };
+        let mut reached = request.depth;
+        for depth in depths {
+            if cancel.is_cancelled() && request.infinite {
+                break;
+            }
             if cancel.is_cancelled() {
                 return Err(Error::AnalysisCancelled);
             }
-            let progress = AnalysisProgress {
+            if self.is_shutting_down() {
+                break;
+            }
+            reached = depth;
+            let mut progress = AnalysisProgress {
                 id: request.id,
                 current_depth: depth,
                 target_depth: request.depth,
@@ -266,7 +694,9 @@ impl AnalysisService {
                 }),
                 nodes_per_second: 500000,
                 hash_full: 100,
-                evaluation: Some(Evaluation::centipawns(30)),
+                elapsed_ms: start.elapsed().as_millis() as u64,
+                evaluation: Some(Score::Centipawns(30)),
+                wdl: None,
                 principal_variations: vec![PrincipalVariation {
                     rank: 1,
                     moves: vec![
@@ -281,85 +711,137 @@ impl AnalysisService {
                             promotion: None,
                         },
                     ],
-                    evaluation: Evaluation::centipawns(30),
+                    evaluation: Score::Centipawns(30),
                     depth,
+                    seldepth: None,
+                    nodes: None,
+                    san: None,
                 }],
             };
+            if !request.search_moves.is_empty() {
+                progress.principal_variations = Self::mock_search_variations(request, depth);
+                progress.current_move = progress
+                    .principal_variations
+                    .first()
+                    .map(|pv| pv.moves[0].clone());
+            }
+            Self::normalize_progress(&mut progress, request, position.as_ref());
             let _ = progress_tx.try_send(progress);
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
-        Ok(self.mock_analysis_result(request))
+        let mut result = self.mock_analysis_result(request);
+        if request.infinite {
+            result.depth_reached = reached;
+            result.stopped_by = StopReason::Cancelled;
+            result.score_history = Self::mock_score_history(reached);
+        }
+        Ok(result)
     }
 
     async fn collect_analysis(
         &self,
         request: &AnalysisRequest,
-        engine: &crate::engine::StockfishEngine,
+        limits: &SearchLimits,
+        engine: &dyn UciEngine,
+        pvs: &mut PvLines,
+        start: std::time::Instant,
     ) -> Result<AnalysisResult> {
-        let mut pvs: HashMap<u8, (UciInfo, Vec<String>)> = HashMap::new();
-        let mut final_info: Option<UciInfo> = None;
-        let start = std::time::Instant::now();
         let best = loop {
             let line = engine.read_line().await?;
             let line = line.trim();
             if let Some(info) = UciInfo::parse(line) {
-                let pv_idx = info.multipv.unwrap_or(1);
-                if !info.pv.is_empty() {
-                    pvs.insert(pv_idx, (info.clone(), info.pv.clone()));
-                }
-                final_info = Some(info);
+                pvs.record(&info);
             }
             if let Some(bm) = BestMove::parse(line) {
                 break bm;
             }
         };
-        let info = final_info.unwrap_or_default();
+        self.search_result(request, limits, pvs, &best, start, None)
+    }
+    fn search_result(
+        &self,
+        request: &AnalysisRequest,
+        limits: &SearchLimits,
+        pvs: &PvLines,
+        best: &BestMove,
+        start: std::time::Instant,
+        stopped_by: Option<StopReason>,
+    ) -> Result<AnalysisResult> {
+        let info = pvs.summary();
         let elapsed = start.elapsed();
-        let best_move_parsed =
-            Move::from_uci(&best.mv).ok_or_else(|| Error::Engine("invalid bestmove".into()))?;
+        let best_move_parsed = Self::parse_best_move(best)?;
         let ponder = best.ponder.as_ref().and_then(|p| Move::from_uci(p));
-        let evaluation = if let Some(mate) = info.score_mate {
-            Evaluation::mate(mate)
-        } else {
-            Evaluation::centipawns(info.score_cp.unwrap_or(0))
-        };
-        let mut principal_variations: Vec<PrincipalVariation> = pvs
-            .into_iter()
-            .map(|(rank, (pv_info, moves))| {
-                let moves: Vec<Move> = moves.iter().filter_map(|m| Move::from_uci(m)).collect();
-                let eval = if let Some(mate) = pv_info.score_mate {
-                    Evaluation::mate(mate)
-                } else {
-                    Evaluation::centipawns(pv_info.score_cp.unwrap_or(0))
-                };
-                PrincipalVariation {
-                    rank,
-                    moves,
-                    evaluation: eval,
-                    depth: pv_info.depth.unwrap_or(0),
-                }
-            })
-            .collect();
-        principal_variations.sort_by_key(|pv| pv.rank);
+        let evaluation = pvs.evaluation();
+        let principal_variations = pvs.variations();
+        let depth_reached = pvs.depth_reached().unwrap_or(request.depth);
+        let time_ms = elapsed.as_millis() as u64;
         Ok(AnalysisResult {
             id: request.id,
             fen: request.fen.clone(),
             best_move: best_move_parsed,
+            game_over: None,
             ponder,
             evaluation,
+            tablebase: None,
+            best_move_san: None,
+            ponder_san: None,
+            wdl: pvs.wdl(),
             principal_variations,
-            depth_reached: info.depth.unwrap_or(request.depth),
+            depth_reached,
             nodes_searched: info.nodes.unwrap_or(0),
-            time_ms: elapsed.as_millis() as u64,
+            tbhits: info.tbhits.unwrap_or(0),
+            time_ms,
+            stopped_by: stopped_by
+                .unwrap_or_else(|| self.stop_reason(limits, depth_reached, &info, time_ms)),
+            variant: request.variant,
             completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: stopped_by == Some(StopReason::Timeout),
+            score_history: pvs.score_history(),
         })
     }
+    fn parse_best_move(best: &BestMove) -> Result<Option<Move>> {
+        match &best.mv {
+            Some(mv) => Move::from_uci(mv)
+                .map(Some)
+                .ok_or_else(|| Error::Engine("invalid bestmove".into())),
+            None => Ok(None),
+        }
+    }
+    async fn timed_out_result(
+        &self,
+        request: &AnalysisRequest,
+        limits: &SearchLimits,
+        engine: &dyn UciEngine,
+        pvs: &mut PvLines,
+        start: std::time::Instant,
+    ) -> Result<AnalysisResult> {
+        match Self::salvage_bestmove(engine, pvs).await {
+            Some(best) => {
+                debug!(best_move = ?best.mv, "returning partial result after timeout");
+                self.search_result(
+                    request,
+                    limits,
+                    pvs,
+                    &best,
+                    start,
+                    Some(StopReason::Timeout),
+                )
+            }
+            None => Err(Error::AnalysisTimeout),
+        }
+    }
     #[instrument(skip(self))]
     pub async fn best_move(&self, request: BestMoveRequest) -> Result<BestMoveResponse> {
-        let position = ChessPosition::new(&request.fen);
-        if !position.validate() {
-            return Err(Error::InvalidFen(request.fen.clone()));
-        }
+        let result = self.run_best_move_request(request).await;
+        self.telemetry.bestmove_finished(result.as_ref().err());
+        result
+    }
+    async fn run_best_move_request(&self, request: BestMoveRequest) -> Result<BestMoveResponse> {
+        self.ensure_accepting()?;
+        let position = request.validate()?;
+        let _active = self.track();
         if self.mock_mode {
             return Ok(self.mock_best_move_result());
         }
@@ -367,16 +849,47 @@ impl AnalysisService {
             .pool
             .as_ref()
             .ok_or_else(|| Error::Engine("no pool".into()))?;
-        let pooled = pool.acquire().await?;
+        let pooled = pool.acquire(RequestClass::Fast).await?;
         let engine = pooled.engine();
-        engine.ensure_ready().await?;
-        engine.set_position(&request.fen).await?;
-        let movetime = request.movetime.unwrap_or(self.default_movetime);
-        engine.go_movetime(movetime).await?;
+        Self::prepare_engine(engine, request.variant, None).await?;
+        let result = self.run_best_move(&request, &position, engine).await;
+        Self::release(&pooled, &result);
+        Self::reset_engine(engine, request.variant, None).await;
+        result
+    }
+    #[instrument(name = "engine_search", skip_all)]
+    async fn run_best_move(
+        &self,
+        request: &BestMoveRequest,
+        position: &ChessPosition,
+        engine: &dyn UciEngine,
+    ) -> Result<BestMoveResponse> {
+        engine
+            .set_position_with_moves(&request.fen, &request.moves)
+            .await?;
+        let mut limits = SearchLimits::new();
+        if let Some(nodes) = request.nodes {
+            limits = limits.with_nodes(nodes);
+        }
+        let movetime = match (request.movetime, request.nodes) {
+            (Some(ms), _) => Some(ms),
+            (None, Some(_)) => None,
+            (None, None) => Some(self.default_movetime),
+        };
+        if let Some(ms) = movetime {
+            limits = limits.with_movetime(ms);
+        }
+        engine.go(&limits).await?;
+        let search_timeout = limits.search_timeout(self.analysis_timeout);
         let mut best_move: Option<BestMove> = None;
-        let search_result = timeout(Duration::from_millis(movetime + 5000), async {
+        let mut partial = false;
+        let mut pvs = PvLines::default();
+        let search_result = timeout(search_timeout, async {
             loop {
                 let line = engine.read_line().await?;
+                if let Some(info) = UciInfo::parse(line.trim()) {
+                    pvs.record(&info);
+                }
                 if let Some(bm) = BestMove::parse(line.trim()) {
                     best_move = Some(bm);
                     break;
@@ -388,38 +901,235 @@ impl AnalysisService {
         match search_result {
             Ok(inner) => inner?,
             Err(_) => {
-                Self::stop_and_drain(engine).await;
-                return Err(Error::AnalysisTimeout);
+                best_move = Self::salvage_bestmove(engine, &mut pvs).await;
+                if best_move.is_none() {
+                    return Err(Error::AnalysisTimeout);
+                }
+                partial = true;
             }
         }
         let best = best_move.ok_or_else(|| Error::Engine("no bestmove received".into()))?;
-        let mv =
-            Move::from_uci(&best.mv).ok_or_else(|| Error::Engine("invalid bestmove".into()))?;
+        let mv = Self::parse_best_move(&best)?;
         let ponder = best.ponder.as_ref().and_then(|p| Move::from_uci(p));
-        debug!(best_move = %best.mv, "best move found");
+        debug!(best_move = ?best.mv, "best move found");
+        let game_over = match mv {
+            Some(_) => None,
+            None => Some(GameOver::detect(position, &pvs.evaluation())),
+        };
         Ok(BestMoveResponse {
             best_move: mv,
             ponder,
+            game_over,
+            partial,
+        })
+    }
+    #[instrument(skip_all)]
+    pub async fn play(
+        &self,
+        commands: mpsc::Receiver<PlayCommand>,
+        updates: mpsc::Sender<PlayUpdate>,
+    ) -> Result<()> {
+        self.ensure_accepting()?;
+        let _active = self.track();
+        if self.mock_mode {
+            return Self::mock_play(commands, updates).await;
+        }
+        let pool = self
+            .pool
+            .as_ref()
+            .ok_or_else(|| Error::Engine("no pool".into()))?;
+        let pooled = pool.acquire(RequestClass::Deep).await?;
+        let engine = pooled.engine();
+        let mut session = PlaySession::new(engine, updates, self.default_movetime);
+        let deadline = tokio::time::Instant::now() + self.play_budget;
+        let result = session.run(commands, deadline, self.shutdown.clone()).await;
+        Self::release(&pooled, &result);
+        if session.uses_chess960() {
+            let _ = engine.set_chess960(false).await;
+        }
+        result
+    }
+    async fn mock_play(
+        mut commands: mpsc::Receiver<PlayCommand>,
+        updates: mpsc::Sender<PlayUpdate>,
+    ) -> Result<()> {
+        let mut pondering = None;
+        while let Some(command) = commands.recv().await {
+            let ponder_hit = match command {
+                PlayCommand::Position { request, ponder } => {
+                    request.validate()?;
+                    pondering = ponder.then(|| "e7e5".to_string());
+                    false
+                }
+                PlayCommand::OpponentMove(mv) => pondering.as_deref() == Some(mv.as_str()),
+            };
+            let _ = updates
+                .send(PlayUpdate::Evaluation {
+                    depth: 10,
+                    evaluation: Score::Centipawns(30),
+                    pv: vec![Move::new("e2", "e4"), Move::new("e7", "e5")],
+                    pondering: false,
+                })
+                .await;
+            let _ = updates
+                .send(PlayUpdate::BestMove {
+                    best_move: Some(Move::new("e2", "e4")),
+                    ponder: Some(Move::new("e7", "e5")),
+                    ponder_hit,
+                    game_over: None,
+                })
+                .await;
+        }
+        Ok(())
+    }
+    #[instrument(skip(self), fields(id = %request.id, plies = request.game.moves.len()))]
+    pub async fn analyze_game(&self, request: GameAnalysisRequest) -> Result<GameAnalysis> {
+        self.ensure_accepting()?;
+        let played = request.game.replay()?;
+        let fens: Vec<String> = std::iter::once(request.game.start_fen.clone())
+            .chain(played.iter().map(|p| p.fen.clone()))
+            .collect();
+        let line: Vec<String> = played.iter().map(|p| p.mv.to_uci()).collect();
+        let template = AnalysisRequest::new(request.game.start_fen.clone())
+            .with_depth(request.depth)
+            .with_variant(request.game.variant)
+            .with_perspective(Perspective::SideToMove);
+        let template = match request.movetime {
+            Some(ms) => template.with_movetime(ms),
+            None => template,
+        };
+        let template = request.token.clamp(template);
+        let concurrency = self
+            .pool
+            .as_ref()
+            .map_or(1, |p| p.size())
+            .min(request.concurrency)
+            .max(1);
+        let positions: Vec<(Score, Option<Move>, u64)> = futures::stream::iter(fens)
+            .enumerate()
+            .map(|(ply, fen)| self.evaluate_position(fen, &line[..ply], &template))
+            .buffered(concurrency)
+            .try_collect()
+            .await?;
+        let moves = played
+            .into_iter()
+            .enumerate()
+            .map(|(i, ply)| {
+                let (before, best_move, _) = &positions[i];
+                let best_move = best_move.clone().unwrap_or_else(|| ply.mv.clone());
+                let evaluation = positions[i + 1].0;
+                let loss = if ply.mv == best_move {
+                    0
+                } else {
+                    centipawn_loss(before, &evaluation)
+                };
+                MoveAnalysis {
+                    ply: i + 1,
+                    mv: ply.mv,
+                    fen: ply.fen,
+                    evaluation,
+                    best_move,
+                    centipawn_loss: loss,
+                    classification: request.thresholds.classify(loss),
+                }
+            })
+            .collect();
+        Ok(GameAnalysis {
+            id: request.id,
+            start_fen: request.game.start_fen.clone(),
+            variant: request.game.variant,
+            depth: template.depth,
+            moves,
+            clamped: template.clamped,
+            nodes_searched: positions.iter().map(|(_, _, nodes)| nodes).sum(),
         })
     }
+    async fn evaluate_position(
+        &self,
+        fen: String,
+        moves: &[String],
+        template: &AnalysisRequest,
+    ) -> Result<(Score, Option<Move>, u64)> {
+        let position = ChessPosition::new(fen);
+        if position.is_checkmate() {
+            return Ok((Score::MateIn(0), None, 0));
+        }
+        if position.is_stalemate() {
+            return Ok((Score::Centipawns(0), None, 0));
+        }
+        let analysis = AnalysisRequest {
+            id: uuid::Uuid::new_v4(),
+            ..template.clone()
+        }
+        .with_moves(moves.to_vec());
+        let result = self.analyze(analysis).await?;
+        Ok((result.evaluation, result.best_move, result.nodes_searched))
+    }
+    pub fn is_mock(&self) -> bool {
+        self.mock_mode
+    }
+    pub async fn probe(&self, limit: Duration) -> Result<()> {
+        match self.pool {
+            Some(ref pool) => pool.probe(limit).await,
+            None if self.mock_mode => Ok(()),
+            None => Err(Error::PoolExhausted),
+        }
+    }
     pub fn pool(&self) -> Option<&EnginePool> {
         self.pool.as_ref().map(|p| p.as_ref())
     }
+    fn mock_search_variations(request: &AnalysisRequest, depth: u8) -> Vec<PrincipalVariation> {
+        request
+            .search_moves
+            .iter()
+            .filter_map(|uci| Move::from_uci(uci))
+            .take(request.multipv.max(1).into())
+            .enumerate()
+            .map(|(index, mv)| PrincipalVariation {
+                rank: index as u8 + 1,
+                moves: vec![mv],
+                evaluation: Score::Centipawns(30 - 10 * index as i32),
+                depth,
+                seldepth: None,
+                nodes: None,
+                san: None,
+            })
+            .collect()
+    }
+    fn mock_score_history(depth: u8) -> Vec<ScoreHistoryEntry> {
+        let first = depth.saturating_sub(MAX_SCORE_HISTORY as u8 - 1).max(1);
+        (first..=depth)
+            .map(|d| {
+                let swing = (depth - d) as i32 * 4;
+                ScoreHistoryEntry {
+                    depth: d,
+                    evaluation: Score::Centipawns(if d % 2 == 0 { 30 + swing } else { 30 - swing }),
+                    nodes: 10000 * d as u64 / depth as u64,
+                    time_ms: 100 * d as u64 / depth as u64,
+                }
+            })
+            .collect()
+    }
     fn mock_analysis_result(&self, request: &AnalysisRequest) -> AnalysisResult {
-        AnalysisResult {
+        let mut result = AnalysisResult {
             id: request.id,
             fen: request.fen.clone(),
-            best_move: Move {
+            best_move: Some(Move {
                 from: "e2".to_string(),
                 to: "e4".to_string(),
                 promotion: None,
-            },
+            }),
+            game_over: None,
             ponder: Some(Move {
                 from: "e7".to_string(),
                 to: "e5".to_string(),
                 promotion: None,
             }),
-            evaluation: Evaluation::centipawns(30),
+            evaluation: Score::Centipawns(30),
+            tablebase: None,
+            best_move_san: None,
+            ponder_san: None,
+            wdl: None,
             principal_variations: vec![PrincipalVariation {
                 rank: 1,
                 moves: vec![
@@ -434,27 +1144,527 @@ impl AnalysisService {
                         promotion: None,
                     },
                 ],
-                evaluation: Evaluation::centipawns(30),
+                evaluation: Score::Centipawns(30),
                 depth: request.depth,
+                seldepth: None,
+                nodes: None,
+                san: None,
             }],
             depth_reached: request.depth,
             nodes_searched: 10000,
+            tbhits: 0,
             time_ms: 100,
+            stopped_by: if self.is_shutting_down() {
+                StopReason::Cancelled
+            } else {
+                StopReason::Depth
+            },
+            variant: request.variant,
             completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: false,
+            score_history: Self::mock_score_history(request.depth),
+        };
+        if !request.search_moves.is_empty() {
+            result.principal_variations = Self::mock_search_variations(request, request.depth);
+            if let Some(pv) = result.principal_variations.first() {
+                result.best_move = pv.moves.first().cloned();
+                result.ponder = None;
+            }
         }
+        result
     }
     fn mock_best_move_result(&self) -> BestMoveResponse {
         BestMoveResponse {
-            best_move: Move {
+            best_move: Some(Move {
                 from: "e2".to_string(),
                 to: "e4".to_string(),
                 promotion: None,
-            },
+            }),
+            game_over: None,
             ponder: Some(Move {
                 from: "e7".to_string(),
                 to: "e5".to_string(),
                 promotion: None,
             }),
+            partial: false,
+        }
+    }
+}
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::pool::EnginePoolConfig;
+    #[cfg(feature = "tablebase-probe")]
+    use crate::tablebase::TablebaseProbeResult;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    #[cfg(feature = "tablebase-probe")]
+    #[tokio::test]
+    async fn test_tablebase_probe_answers_covered_positions_without_search() {
+        struct Won;
+        impl TablebaseProbe for Won {
+            fn probe(&self, _position: &ChessPosition) -> Option<TablebaseProbeResult> {
+                Some(TablebaseProbeResult {
+                    tablebase: ironfish_core::TablebaseResult {
+                        wdl: ironfish_core::TablebaseWdl::Win,
+                        dtz: Some(3),
+                    },
+                    best_move: Some(Move::new("d1", "e1")),
+                })
+            }
+        }
+        let service = AnalysisService::new_mock()
+            .with_tablebases(SyzygyTables::from_names(["KPvK"]))
+            .with_tablebase_probe(Arc::new(Won));
+        let result = service
+            .analyze(AnalysisRequest::new("8/8/8/8/8/3k4/3P4/3K4 w - - 0 1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            result.evaluation,
+            Score::Tablebase(ironfish_core::TablebaseWdl::Win)
+        );
+        assert_eq!(result.tablebase.unwrap().dtz, Some(3));
+        assert_eq!(result.best_move, Some(Move::new("d1", "e1")));
+        assert_eq!(result.depth_reached, 0);
+        let searched = service
+            .analyze(AnalysisRequest::new(ChessPosition::starting().fen))
+            .await
+            .unwrap();
+        assert!(searched.tablebase.is_none());
+        assert!(searched.depth_reached > 0);
+    }
+    fn scripted_engine(dir: &std::path::Path) -> (PathBuf, PathBuf) {
+        let log = dir.join("commands.log");
+        let script = dir.join("engine.sh");
+        let body = format!(
+            r#"#!/bin/sh
+while IFS= read -r line; do
+  echo "$line" >> "{}"
+  case "$line" in
+    uci) echo "uciok" ;;
+    isready) echo "readyok" ;;
+    "setoption name Bogus"*) echo "No such option: Bogus" ;;
+    "go depth 30 movetime 300") sleep 0.3; echo "info depth 14 seldepth 19 multipv 1 score cp 30 nodes 5000 time 300 pv d2d4 d7d5"; echo "info depth 15 currmove g1f3 currmovenumber 2"; echo "bestmove d2d4 ponder d7d5" ;;
+    "go depth 30 nodes 800") echo "info depth 9 multipv 1 score cp 18 nodes 800 time 4 pv c2c4"; echo "bestmove c2c4" ;;
+    "go infinite") searching=1; echo "info depth 7 multipv 1 score cp 41 nodes 900 pv e2e4 e7e5" ;;
+    stop) [ -n "$searching" ] && echo "bestmove e2e4 ponder e7e5"; searching= ;;
+    go*) echo "info depth 1 score cp 12 nodes 20 pv f1g1"; echo "bestmove f1g1" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#,
+            log.display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (script, log)
+    }
+    #[tokio::test]
+    async fn test_chess960_option_sequencing() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let service = AnalysisService::new(Arc::new(pool));
+        let fen = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1";
+        let request = AnalysisRequest::new(fen)
+            .with_depth(1)
+            .with_variant(Variant::Chess960);
+        let result = service.analyze(request).await.unwrap();
+        assert_eq!(result.variant, Variant::Chess960);
+        assert_eq!(result.best_move.unwrap().to_uci(), "f1g1");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands: Vec<String> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        let position = |cmd: &str| commands.iter().position(|c| c.starts_with(cmd)).unwrap();
+        let enable = position("setoption name UCI_Chess960 value true");
+        let set_position = position("position fen");
+        let go = position("go depth 1");
+        let disable = position("setoption name UCI_Chess960 value false");
+        assert!(enable < set_position);
+        assert!(set_position < go);
+        assert!(go < disable);
+        let standard = AnalysisRequest::new(fen).with_depth(1);
+        assert!(matches!(
+            service.analyze(standard).await,
+            Err(Error::VariantRequired(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_position_with_moves() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let service = AnalysisService::new(Arc::new(pool));
+        let moves = vec!["e2e4".to_string(), "e7e5".to_string()];
+        let request = AnalysisRequest::new(ChessPosition::starting().fen)
+            .with_depth(1)
+            .with_moves(moves.clone());
+        service.analyze(request).await.unwrap();
+        let illegal = AnalysisRequest::new(ChessPosition::starting().fen)
+            .with_depth(1)
+            .with_moves(vec!["e2e4".into(), "e2e5".into()]);
+        assert!(matches!(
+            service.analyze(illegal).await,
+            Err(Error::InvalidMove { index: 1, ref notation }) if notation == "e2e5"
+        ));
+        let mut best = BestMoveRequest::new("4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        best.moves = vec!["e1g1".into()];
+        best.movetime = Some(10);
+        service.best_move(best).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged_commands(&log);
+        assert!(commands.contains(&"position startpos moves e2e4 e7e5".to_string()));
+        assert!(commands
+            .contains(&"position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1 moves e1g1".to_string()));
+        assert!(!commands.iter().any(|c| c.contains("e2e5")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    fn logged_commands(log: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+    #[tokio::test]
+    async fn test_engine_options_applied_and_skill_override() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let config = EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        }
+        .with_option("Threads", 8)
+        .with_option("Hash", 512)
+        .with_option("Skill Level", 15);
+        let service = AnalysisService::new(Arc::new(EnginePool::new(config).await.unwrap()));
+        let request = AnalysisRequest::new(ChessPosition::starting().fen)
+            .with_depth(1)
+            .with_skill_level(3);
+        service.analyze(request).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged_commands(&log);
+        let position = |cmd: &str| commands.iter().position(|c| c == cmd).unwrap();
+        let uci = position("uci");
+        let threads = position("setoption name Threads value 8");
+        let hash = position("setoption name Hash value 512");
+        let go = position("go depth 1");
+        let skill = position("setoption name Skill Level value 3");
+        let restored = commands
+            .iter()
+            .rposition(|c| c == "setoption name Skill Level value 15")
+            .unwrap();
+        assert!(uci < threads && uci < hash);
+        assert!(threads < skill && skill < go && go < restored);
+        let invalid = AnalysisRequest::new(ChessPosition::starting().fen).with_skill_level(21);
+        assert!(matches!(
+            service.analyze(invalid).await,
+            Err(Error::InvalidArgument(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_movetime_and_nodes_limits() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let service = AnalysisService::new(Arc::new(pool)).with_timeout(Duration::from_millis(100));
+        let fen = ChessPosition::starting().fen;
+        let result = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(30).with_movetime(300))
+            .await
+            .unwrap();
+        assert_eq!(result.best_move.unwrap().to_uci(), "d2d4");
+        assert_eq!(result.depth_reached, 14);
+        assert_eq!(result.stopped_by, StopReason::Time);
+        let result = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(30).with_nodes(800))
+            .await
+            .unwrap();
+        assert_eq!(result.best_move.unwrap().to_uci(), "c2c4");
+        assert_eq!(result.depth_reached, 9);
+        assert_eq!(result.stopped_by, StopReason::Nodes);
+        let result = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(1))
+            .await
+            .unwrap();
+        assert_eq!(result.depth_reached, 1);
+        assert_eq!(result.stopped_by, StopReason::Depth);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged_commands(&log);
+        assert!(commands.contains(&"go depth 30 movetime 300".to_string()));
+        assert!(commands.contains(&"go depth 30 nodes 800".to_string()));
+        assert!(commands.contains(&"go depth 1".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_infinite_analysis_returns_best_line_on_cancel() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let service =
+            Arc::new(AnalysisService::new(Arc::new(pool)).with_timeout(Duration::from_millis(100)));
+        let (tx, mut rx) = mpsc::channel(8);
+        let cancel = CancellationToken::new();
+        let task = {
+            let service = service.clone();
+            let cancel = cancel.clone();
+            let request = AnalysisRequest::infinite(ChessPosition::starting().fen);
+            tokio::spawn(async move { service.analyze_streaming(request, tx, cancel).await })
+        };
+        let progress = rx.recv().await.unwrap();
+        assert_eq!(progress.current_depth, 7);
+        assert_eq!(progress.target_depth, 0);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!task.is_finished());
+        cancel.cancel();
+        let result = task.await.unwrap().unwrap();
+        assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+        assert_eq!(result.ponder.unwrap().to_uci(), "e7e5");
+        assert_eq!(result.depth_reached, 7);
+        assert_eq!(result.nodes_searched, 900);
+        assert_eq!(result.principal_variations[0].moves.len(), 2);
+        assert_eq!(result.stopped_by, StopReason::Cancelled);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged_commands(&log);
+        let go = commands.iter().position(|c| c == "go infinite").unwrap();
+        assert_eq!(commands[go + 1], "stop");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_infinite_analysis_gives_up_on_an_engine_that_ignores_stop() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (log, script) = (dir.join("commands.log"), dir.join("engine.sh"));
+        let body = format!(
+            r#"#!/bin/sh
+while IFS= read -r line; do
+  echo "$line" >> "{}"
+  case "$line" in
+    uci) echo "uciok" ;;
+    isready) [ -z "$searching" ] && echo "readyok" ;;
+    "go infinite") searching=1; echo "info depth 7 multipv 1 score cp 41 nodes 900 pv e2e4 e7e5" ;;
+    go*) echo "info depth 1 score cp 12 nodes 20 pv f1g1"; echo "bestmove f1g1" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#,
+            log.display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let service = Arc::new(
+            AnalysisService::new(Arc::new(pool))
+                .with_stop_drain_timeout(Duration::from_millis(200)),
+        );
+        let (tx, mut rx) = mpsc::channel(8);
+        let cancel = CancellationToken::new();
+        let task = {
+            let service = service.clone();
+            let cancel = cancel.clone();
+            let request = AnalysisRequest::infinite(ChessPosition::starting().fen);
+            tokio::spawn(async move { service.analyze_streaming(request, tx, cancel).await })
+        };
+        rx.recv().await.unwrap();
+        cancel.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(Error::AnalysisTimeout)));
+        let result = service
+            .analyze(AnalysisRequest::new(ChessPosition::starting().fen).with_depth(1))
+            .await
+            .unwrap();
+        assert_eq!(result.best_move.unwrap().to_uci(), "f1g1");
+        let starts = logged_commands(&log).iter().filter(|c| *c == "uci").count();
+        assert_eq!(starts, 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_analyze_game_mock() {
+        let service = AnalysisService::new_mock();
+        let game = ironfish_core::Game::new(
+            ["f3", "e5", "g4", "Qh4#"]
+                .iter()
+                .map(|m| m.to_string())
+                .collect(),
+        );
+        let analysis = service
+            .analyze_game(GameAnalysisRequest::new(game))
+            .await
+            .unwrap();
+        assert_eq!(analysis.moves.len(), 4);
+        assert_eq!(analysis.moves[0].mv.to_uci(), "f2f3");
+        assert_eq!(analysis.moves[3].mv.to_uci(), "d8h4");
+        assert_eq!(analysis.moves[3].evaluation, Score::MateIn(0));
+        assert_eq!(analysis.moves[3].centipawn_loss, 0);
+        assert_eq!(
+            analysis.moves[3].classification,
+            ironfish_core::MoveClassification::Best
+        );
+        let bad = ironfish_core::Game::new(vec!["e4".into(), "e4".into()]);
+        assert!(matches!(
+            service.analyze_game(GameAnalysisRequest::new(bad)).await,
+            Err(Error::InvalidMove { index: 1, .. })
+        ));
+    }
+    #[tokio::test]
+    async fn test_analysis_cache() {
+        let service = AnalysisService::new_mock().with_cache(Arc::new(AnalysisCache::new(16)));
+        let fen = ChessPosition::starting().fen;
+        let first = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(12))
+            .await
+            .unwrap();
+        assert!(!first.cached);
+        let request =
+            AnalysisRequest::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 5")
+                .with_depth(10);
+        let hit = service.analyze(request.clone()).await.unwrap();
+        assert!(hit.cached);
+        assert_eq!(hit.id, request.id);
+        assert_eq!(hit.fen, request.fen);
+        assert_eq!(hit.depth_reached, 12);
+        let deeper = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(16))
+            .await
+            .unwrap();
+        assert!(!deeper.cached);
+        let weakened = service
+            .analyze(AnalysisRequest::new(&fen).with_depth(8).with_skill_level(3))
+            .await
+            .unwrap();
+        assert!(!weakened.cached);
+        let (tx, mut rx) = mpsc::channel(8);
+        let streamed = service
+            .analyze_streaming(
+                AnalysisRequest::new(&fen).with_depth(16),
+                tx,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert!(streamed.cached);
+        let progress = rx.recv().await.unwrap();
+        assert_eq!(progress.current_depth, 16);
+        assert!(rx.recv().await.is_none());
+        assert_eq!(service.cache().unwrap().clear().await, 1);
+    }
+    #[tokio::test]
+    async fn test_active_analyses_tracked() {
+        let service = Arc::new(AnalysisService::new_mock());
+        let (tx, mut rx) = mpsc::channel(1);
+        let task = {
+            let service = service.clone();
+            let request = AnalysisRequest::new(ChessPosition::starting().fen).with_depth(20);
+            tokio::spawn(async move {
+                service
+                    .analyze_streaming(request, tx, CancellationToken::new())
+                    .await
+            })
+        };
+        rx.recv().await.unwrap();
+        assert_eq!(service.active_analyses(), 1);
+        while rx.recv().await.is_some() {}
+        task.await.unwrap().unwrap();
+        assert_eq!(service.active_analyses(), 0);
+    }
+    #[tokio::test]
+    async fn test_shutdown_stops_in_flight_analysis() {
+        let service = Arc::new(AnalysisService::new_mock());
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = {
+            let service = service.clone();
+            let request = AnalysisRequest::new(ChessPosition::starting().fen).with_depth(20);
+            tokio::spawn(async move {
+                service
+                    .analyze_streaming(request, tx, CancellationToken::new())
+                    .await
+            })
+        };
+        rx.recv().await.unwrap();
+        service.begin_shutdown();
+        assert!(service.wait_idle(Duration::from_secs(2)).await);
+        let result = task.await.unwrap().unwrap();
+        assert_eq!(result.stopped_by, StopReason::Cancelled);
+        let rejected = service
+            .analyze(AnalysisRequest::new(ChessPosition::starting().fen))
+            .await;
+        assert!(matches!(rejected, Err(Error::ShuttingDown)));
+    }
+    #[tokio::test]
+    async fn test_engine_options_reapplied_on_restart() {
+        let dir = std::env::temp_dir().join(format!("ironfish-engine-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (script, log) = scripted_engine(&dir);
+        let path = script.display().to_string();
+        let options = vec![("Threads".to_string(), "4".to_string())];
+        let engine = crate::engine::ProcessEngine::with_options(&path, options)
+            .await
+            .unwrap();
+        engine.restart().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let applied = logged_commands(&log)
+            .iter()
+            .filter(|c| *c == "setoption name Threads value 4")
+            .count();
+        assert_eq!(applied, 2);
+        let bogus = EnginePoolConfig {
+            binary_path: path,
+            pool_size: 1,
+            ..Default::default()
+        }
+        .with_option("Bogus", 1);
+        match EnginePool::new(bogus).await {
+            Err(Error::Engine(message)) => assert!(message.contains("Bogus")),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("unknown option should fail"),
         }
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
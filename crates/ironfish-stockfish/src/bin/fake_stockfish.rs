@@ -0,0 +1,131 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Stdout, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+const DEFAULT_SCRIPT: &str = "info depth 1 seldepth 1 multipv 1 score cp 20 nodes 20 nps 2000 time 10 pv e2e4\nbestmove e2e4";
+type Output = Arc<Mutex<Stdout>>;
+struct Options {
+    script: Option<PathBuf>,
+    log: Option<PathBuf>,
+}
+impl Options {
+    fn parse() -> Self {
+        let mut options = Self {
+            script: std::env::var_os("FAKE_STOCKFISH_SCRIPT").map(PathBuf::from),
+            log: std::env::var_os("FAKE_STOCKFISH_LOG").map(PathBuf::from),
+        };
+        let mut args = std::env::args_os().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--script") => options.script = args.next().map(PathBuf::from),
+                Some("--log") => options.log = args.next().map(PathBuf::from),
+                _ => {}
+            }
+        }
+        options
+    }
+    fn script(&self) -> String {
+        match self.script {
+            Some(ref path) => std::fs::read_to_string(path).unwrap_or_default(),
+            None => DEFAULT_SCRIPT.to_string(),
+        }
+    }
+}
+struct Search {
+    stopped: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+impl Search {
+    fn spawn(script: String, out: Output) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let flag = stopped.clone();
+        let handle = thread::spawn(move || run_script(&script, &flag, &out));
+        Self { stopped, handle }
+    }
+    fn stop(self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.handle.join();
+    }
+}
+fn emit(out: &Output, line: &str) {
+    let mut out = out.lock().unwrap();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+fn pause(duration: Duration, stopped: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stopped.load(Ordering::SeqCst) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        thread::sleep(remaining.min(Duration::from_millis(5)));
+    }
+}
+fn run_script(script: &str, stopped: &AtomicBool, out: &Output) {
+    for line in script.lines().map(str::trim) {
+        let (directive, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match directive {
+            "" => {}
+            "sleep" => pause(
+                Duration::from_millis(argument.parse().unwrap_or(0)),
+                stopped,
+            ),
+            "wait" => {
+                while !stopped.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+            "exit" => std::process::exit(argument.parse().unwrap_or(1)),
+            "bestmove" => emit(out, line),
+            _ if stopped.load(Ordering::SeqCst) => {}
+            _ => emit(out, line),
+        }
+    }
+}
+fn main() {
+    let options = Options::parse();
+    let out: Output = Arc::new(Mutex::new(std::io::stdout()));
+    let mut log: Option<File> = options
+        .log
+        .as_ref()
+        .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+    let mut search: Option<Search> = None;
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let command = line.trim();
+        if let Some(ref mut log) = log {
+            let _ = writeln!(log, "{}", command);
+        }
+        match command.split_whitespace().next().unwrap_or("") {
+            "uci" => {
+                emit(&out, "id name Fake Stockfish");
+                emit(&out, "id author IronFish");
+                emit(
+                    &out,
+                    "option name MultiPV type spin default 1 min 1 max 500",
+                );
+                emit(&out, "uciok");
+            }
+            "isready" => emit(&out, "readyok"),
+            "go" => {
+                if let Some(previous) = search.take() {
+                    previous.stop();
+                }
+                search = Some(Search::spawn(options.script(), out.clone()));
+            }
+            "stop" => {
+                if let Some(running) = search.take() {
+                    running.stop();
+                }
+            }
+            "quit" => break,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use ironfish_core::{AnalysisRequest, AnalysisResult, ChessPosition, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::sync::Mutex;
+use tracing::warn;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisCacheConfig {
+    pub enabled: bool,
+    pub capacity: usize,
+    pub persist: bool,
+}
+impl Default for AnalysisCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: 4096,
+            persist: false,
+        }
+    }
+}
+struct CacheInner {
+    entries: HashMap<String, (u64, AnalysisResult)>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+impl CacheInner {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((used, _)) = self.entries.get_mut(key) {
+            self.recency.remove(used);
+            *used = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+    fn evict(&mut self) -> Option<String> {
+        let (_, key) = self.recency.pop_first()?;
+        self.entries.remove(&key);
+        Some(key)
+    }
+}
+pub struct AnalysisCache {
+    inner: Mutex<CacheInner>,
+    capacity: usize,
+    tree: Option<sled::Tree>,
+}
+impl AnalysisCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            }),
+            capacity: capacity.max(1),
+            tree: None,
+        }
+    }
+    pub fn persistent(capacity: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(|e| Error::Storage(e.to_string()))?;
+        Self::from_db(capacity, &db)
+    }
+    fn from_db(capacity: usize, db: &sled::Db) -> Result<Self> {
+        let tree = db
+            .open_tree("analysis_cache")
+            .map_err(|e| Error::Storage(e.to_string()))?;
+        let mut cache = Self::new(capacity);
+        let inner = cache.inner.get_mut();
+        for item in tree.iter() {
+            let (key, value) = item.map_err(|e| Error::Storage(e.to_string()))?;
+            let key = String::from_utf8_lossy(&key).into_owned();
+            match serde_json::from_slice::<AnalysisResult>(&value) {
+                Ok(result) if inner.entries.len() < cache.capacity => {
+                    inner.tick += 1;
+                    inner.recency.insert(inner.tick, key.clone());
+                    inner.entries.insert(key, (inner.tick, result));
+                }
+                _ => {
+                    let _ = tree.remove(key.as_bytes());
+                }
+            }
+        }
+        cache.tree = Some(tree);
+        Ok(cache)
+    }
+    pub fn key(request: &AnalysisRequest, position: &ChessPosition) -> String {
+        let board = position
+            .position_key()
+            .unwrap_or_else(|| position.fen.clone());
+        let key = format!("{}|{}|{}", board, request.variant, request.multipv.max(1));
+        if request.search_moves.is_empty() {
+            return key;
+        }
+        let mut search_moves = request.search_moves.clone();
+        search_moves.sort();
+        search_moves.dedup();
+        format!("{}|{}", key, search_moves.join(" "))
+    }
+    pub async fn get(&self, key: &str, depth: u8) -> Option<AnalysisResult> {
+        let mut inner = self.inner.lock().await;
+        let result = match inner.entries.get(key) {
+            Some((_, result)) if result.depth_reached >= depth => result.clone(),
+            _ => return None,
+        };
+        inner.touch(key);
+        Some(result)
+    }
+    pub async fn insert(&self, key: String, result: &AnalysisResult) {
+        let mut inner = self.inner.lock().await;
+        if inner
+            .entries
+            .get(&key)
+            .is_some_and(|(_, cached)| cached.depth_reached > result.depth_reached)
+        {
+            inner.touch(&key);
+            return;
+        }
+        if !inner.entries.contains_key(&key) {
+            while inner.entries.len() >= self.capacity {
+                match inner.evict() {
+                    Some(evicted) => self.remove_persisted(&evicted),
+                    None => break,
+                }
+            }
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((used, _)) = inner.entries.insert(key.clone(), (tick, result.clone())) {
+            inner.recency.remove(&used);
+        }
+        inner.recency.insert(tick, key.clone());
+        if let Some(ref tree) = self.tree {
+            let persisted = serde_json::to_vec(result)
+                .map_err(|e| e.to_string())
+                .and_then(|value| {
+                    tree.insert(key.as_bytes(), value)
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = persisted {
+                warn!("failed to persist cached analysis: {}", e);
+            }
+        }
+    }
+    pub async fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().await;
+        let cleared = inner.entries.len();
+        inner.entries.clear();
+        inner.recency.clear();
+        if let Some(ref tree) = self.tree {
+            if let Err(e) = tree.clear() {
+                warn!("failed to clear persisted analysis cache: {}", e);
+            }
+        }
+        cleared
+    }
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.entries.len()
+    }
+    pub async fn is_empty(&self) -> bool {
+        self.inner.lock().await.entries.is_empty()
+    }
+    fn remove_persisted(&self, key: &str) {
+        if let Some(ref tree) = self.tree {
+            let _ = tree.remove(key.as_bytes());
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use ironfish_core::{Move, Score, StopReason, Variant};
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    fn result(depth: u8) -> AnalysisResult {
+        AnalysisResult {
+            id: uuid::Uuid::new_v4(),
+            fen: START_FEN.to_string(),
+            best_move: Some(Move::new("e2", "e4")),
+            game_over: None,
+            ponder: None,
+            evaluation: Score::Centipawns(20),
+            tablebase: None,
+            best_move_san: None,
+            ponder_san: None,
+            wdl: None,
+            principal_variations: Vec::new(),
+            depth_reached: depth,
+            nodes_searched: 1000,
+            tbhits: 0,
+            time_ms: 5,
+            stopped_by: StopReason::Depth,
+            variant: Variant::Standard,
+            completed_at: Utc::now(),
+            cached: false,
+            clamped: false,
+            partial: false,
+            score_history: Vec::new(),
+        }
+    }
+    fn key(fen: &str, multipv: u8) -> String {
+        let request = AnalysisRequest::new(fen).with_multipv(multipv);
+        AnalysisCache::key(&request, &ChessPosition::new(fen))
+    }
+    #[test]
+    fn test_key_normalizes_fen() {
+        let later = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3";
+        assert_eq!(key(START_FEN, 1), key(later, 1));
+        assert_eq!(
+            key(
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                1
+            ),
+            key(
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+                1
+            )
+        );
+        assert_ne!(key(START_FEN, 1), key(START_FEN, 3));
+        let restricted = |moves: &[&str]| {
+            let request = AnalysisRequest::new(START_FEN)
+                .with_search_moves(moves.iter().map(|m| m.to_string()).collect());
+            AnalysisCache::key(&request, &ChessPosition::new(START_FEN))
+        };
+        assert_ne!(key(START_FEN, 1), restricted(&["e2e4"]));
+        assert_eq!(restricted(&["e2e4", "d2d4"]), restricted(&["d2d4", "e2e4"]));
+    }
+    #[tokio::test]
+    async fn test_depth_and_eviction() {
+        let cache = AnalysisCache::new(2);
+        cache.insert("a".to_string(), &result(12)).await;
+        assert!(cache.get("a", 10).await.is_some());
+        assert!(cache.get("a", 12).await.is_some());
+        assert!(cache.get("a", 14).await.is_none());
+        cache.insert("a".to_string(), &result(8)).await;
+        assert_eq!(cache.get("a", 1).await.unwrap().depth_reached, 12);
+        cache.insert("b".to_string(), &result(10)).await;
+        assert!(cache.get("a", 1).await.is_some());
+        cache.insert("c".to_string(), &result(10)).await;
+        assert_eq!(cache.len().await, 2);
+        assert!(cache.get("b", 1).await.is_none());
+        assert!(cache.get("a", 1).await.is_some());
+        assert_eq!(cache.clear().await, 2);
+        assert!(cache.is_empty().await);
+    }
+    #[tokio::test]
+    async fn test_persistent_reload() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        {
+            let cache = AnalysisCache::from_db(1, &db).unwrap();
+            cache.insert("a".to_string(), &result(15)).await;
+            cache.insert("b".to_string(), &result(15)).await;
+        }
+        let cache = AnalysisCache::from_db(8, &db).unwrap();
+        assert!(cache.get("a", 15).await.is_none());
+        assert_eq!(cache.get("b", 15).await.unwrap().depth_reached, 15);
+        cache.clear().await;
+        let cache = AnalysisCache::from_db(8, &db).unwrap();
+        assert!(cache.is_empty().await);
+    }
+}
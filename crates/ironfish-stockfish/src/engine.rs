@@ -1,54 +1,57 @@
+use crate::uci::UciEngine;
+use async_trait::async_trait;
 use ironfish_core::{Error, Result};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tokio::sync::Mutex;
 use tracing::{debug, trace};
-pub struct StockfishEngine {
+pub struct ProcessEngine {
     stdin: Arc<Mutex<ChildStdin>>,
     stdout: Arc<Mutex<BufReader<ChildStdout>>>,
-    ready: AtomicBool,
     _process: Arc<Mutex<Child>>,
     binary_path: String,
+    args: Vec<String>,
+    options: Vec<(String, String)>,
+    name: std::sync::Mutex<Option<String>>,
 }
-impl StockfishEngine {
+#[deprecated(note = "use ProcessEngine")]
+pub type StockfishEngine = ProcessEngine;
+impl ProcessEngine {
     pub async fn new(binary_path: &str) -> Result<Self> {
-        let mut process = Command::new(binary_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .kill_on_drop(true)
-            .spawn()
-            .map_err(|e| Error::Engine(format!("failed to spawn stockfish: {}", e)))?;
-        let stdin = process
-            .stdin
-            .take()
-            .ok_or_else(|| Error::Engine("failed to get stdin".into()))?;
-        let stdout = process
-            .stdout
-            .take()
-            .ok_or_else(|| Error::Engine("failed to get stdout".into()))?;
+        Self::with_options(binary_path, Vec::new()).await
+    }
+    pub async fn with_options(binary_path: &str, options: Vec<(String, String)>) -> Result<Self> {
+        Self::with_args(binary_path, Vec::new(), options).await
+    }
+    pub async fn with_args(
+        binary_path: &str,
+        args: Vec<String>,
+        options: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let (process, stdin, stdout) = Self::spawn(binary_path, &args)?;
         let engine = Self {
             stdin: Arc::new(Mutex::new(stdin)),
             stdout: Arc::new(Mutex::new(BufReader::new(stdout))),
-            ready: AtomicBool::new(false),
             _process: Arc::new(Mutex::new(process)),
             binary_path: binary_path.to_string(),
+            args,
+            options,
+            name: std::sync::Mutex::new(None),
         };
         engine.initialize().await?;
         Ok(engine)
     }
-    pub async fn restart(&self) -> Result<()> {
-        debug!("restarting stockfish engine");
-        let mut process = Command::new(&self.binary_path)
+    fn spawn(binary_path: &str, args: &[String]) -> Result<(Child, ChildStdin, ChildStdout)> {
+        let mut process = Command::new(binary_path)
+            .args(args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
             .kill_on_drop(true)
             .spawn()
-            .map_err(|e| Error::Engine(format!("failed to spawn stockfish: {}", e)))?;
+            .map_err(|e| Error::Engine(format!("failed to spawn {}: {}", binary_path, e)))?;
         let stdin = process
             .stdin
             .take()
@@ -57,32 +60,34 @@ impl StockfishEngine {
             .stdout
             .take()
             .ok_or_else(|| Error::Engine("failed to get stdout".into()))?;
-        {
-            let mut p_guard = self._process.lock().await;
-            *p_guard = process;
-        }
-        {
-            let mut in_guard = self.stdin.lock().await;
-            *in_guard = stdin;
-        }
-        {
-            let mut out_guard = self.stdout.lock().await;
-            *out_guard = BufReader::new(stdout);
-        }
-        self.ready.store(false, Ordering::SeqCst);
-        self.initialize().await?;
-        Ok(())
+        Ok((process, stdin, stdout))
     }
     async fn initialize(&self) -> Result<()> {
         self.send_command("uci").await?;
-        self.wait_for("uciok").await?;
+        loop {
+            let line = self.read_line().await?;
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("id name ") {
+                *self.name.lock().unwrap() = Some(name.trim().to_string());
+            } else if line.starts_with("uciok") {
+                break;
+            }
+        }
+        for (name, value) in &self.options {
+            self.set_option(name, value).await?;
+        }
         self.send_command("isready").await?;
         self.wait_for("readyok").await?;
-        self.ready.store(true, Ordering::SeqCst);
-        debug!("stockfish engine initialized");
+        debug!(
+            name = self.name().as_deref().unwrap_or("unknown"),
+            "engine initialized"
+        );
         Ok(())
     }
-    pub async fn send_command(&self, cmd: &str) -> Result<()> {
+}
+#[async_trait]
+impl UciEngine for ProcessEngine {
+    async fn send_command(&self, cmd: &str) -> Result<()> {
         trace!("sending command: {}", cmd);
         let mut stdin = self.stdin.lock().await;
         stdin
@@ -95,56 +100,46 @@ impl StockfishEngine {
             .map_err(|e| Error::Engine(format!("flush failed: {}", e)))?;
         Ok(())
     }
-    pub async fn read_line(&self) -> Result<String> {
+    async fn read_line(&self) -> Result<String> {
         let mut stdout = self.stdout.lock().await;
         let mut line = String::new();
-        stdout
+        let read = stdout
             .read_line(&mut line)
             .await
             .map_err(|e| Error::Engine(format!("read failed: {}", e)))?;
+        if read == 0 {
+            return Err(Error::Engine("engine closed its output".into()));
+        }
         trace!("received: {}", line.trim());
         Ok(line)
     }
-    async fn wait_for(&self, expected: &str) -> Result<()> {
-        loop {
-            let line = self.read_line().await?;
-            if line.trim().starts_with(expected) {
-                return Ok(());
-            }
+    async fn restart(&self) -> Result<()> {
+        debug!("restarting engine {}", self.binary_path);
+        let (process, stdin, stdout) = Self::spawn(&self.binary_path, &self.args)?;
+        {
+            let mut p_guard = self._process.lock().await;
+            *p_guard = process;
         }
+        {
+            let mut in_guard = self.stdin.lock().await;
+            *in_guard = stdin;
+        }
+        {
+            let mut out_guard = self.stdout.lock().await;
+            *out_guard = BufReader::new(stdout);
+        }
+        self.initialize().await?;
+        Ok(())
     }
-    pub async fn set_position(&self, fen: &str) -> Result<()> {
-        let cmd = if fen == "startpos" {
-            "position startpos".to_string()
-        } else {
-            format!("position fen {}", fen)
-        };
-        self.send_command(&cmd).await
-    }
-    pub async fn go_depth(&self, depth: u8) -> Result<()> {
-        self.send_command(&format!("go depth {}", depth)).await
-    }
-    pub async fn go_movetime(&self, ms: u64) -> Result<()> {
-        self.send_command(&format!("go movetime {}", ms)).await
-    }
-    pub async fn set_multipv(&self, n: u8) -> Result<()> {
-        self.send_command(&format!("setoption name MultiPV value {}", n))
+    async fn kill(&self) -> Result<()> {
+        self._process
+            .lock()
             .await
+            .kill()
+            .await
+            .map_err(|e| Error::Engine(format!("kill failed: {}", e)))
     }
-    pub async fn stop(&self) -> Result<()> {
-        self.send_command("stop").await
-    }
-    pub async fn quit(&self) -> Result<()> {
-        self.send_command("quit").await
-    }
-    pub fn is_ready(&self) -> bool {
-        self.ready.load(Ordering::SeqCst)
-    }
-    pub async fn ensure_ready(&self) -> Result<()> {
-        self.send_command("isready").await?;
-        self.wait_for("readyok").await
-    }
-    pub async fn is_running(&self) -> bool {
+    async fn is_running(&self) -> bool {
         let mut process = self._process.lock().await;
         match process.try_wait() {
             Ok(Some(_)) => false,
@@ -152,12 +147,20 @@ impl StockfishEngine {
             Err(_) => false,
         }
     }
+    fn name(&self) -> Option<String> {
+        self.name.lock().unwrap().clone()
+    }
 }
-impl Drop for StockfishEngine {
+impl Drop for ProcessEngine {
     fn drop(&mut self) {
-        debug!("dropping stockfish engine");
+        debug!("dropping engine process");
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Lower,
+    Upper,
+}
 #[derive(Debug, Clone, Default)]
 pub struct UciInfo {
     pub depth: Option<u8>,
@@ -165,11 +168,15 @@ pub struct UciInfo {
     pub multipv: Option<u8>,
     pub score_cp: Option<i32>,
     pub score_mate: Option<i32>,
+    pub bound: Option<Bound>,
+    pub wdl: Option<(u32, u32, u32)>,
     pub nodes: Option<u64>,
     pub nps: Option<u64>,
+    pub tbhits: Option<u64>,
     pub time: Option<u64>,
     pub pv: Vec<String>,
     pub currmove: Option<String>,
+    pub currmovenumber: Option<u32>,
     pub hashfull: Option<u16>,
 }
 impl UciInfo {
@@ -189,11 +196,22 @@ impl UciInfo {
                     Some("mate") => info.score_mate = parts.next().and_then(|s| s.parse().ok()),
                     _ => {}
                 },
+                "lowerbound" => info.bound = Some(Bound::Lower),
+                "upperbound" => info.bound = Some(Bound::Upper),
+                "wdl" => {
+                    let mut next = || parts.next().and_then(|s| s.parse().ok());
+                    info.wdl = match (next(), next(), next()) {
+                        (Some(w), Some(d), Some(l)) => Some((w, d, l)),
+                        _ => None,
+                    };
+                }
                 "nodes" => info.nodes = parts.next().and_then(|s| s.parse().ok()),
                 "nps" => info.nps = parts.next().and_then(|s| s.parse().ok()),
+                "tbhits" => info.tbhits = parts.next().and_then(|s| s.parse().ok()),
                 "time" => info.time = parts.next().and_then(|s| s.parse().ok()),
                 "hashfull" => info.hashfull = parts.next().and_then(|s| s.parse().ok()),
                 "currmove" => info.currmove = parts.next().map(|s| s.to_string()),
+                "currmovenumber" => info.currmovenumber = parts.next().and_then(|s| s.parse().ok()),
                 "pv" => {
                     for m in parts {
                         info.pv.push(m.to_string());
@@ -208,7 +226,7 @@ impl UciInfo {
 }
 #[derive(Debug, Clone)]
 pub struct BestMove {
-    pub mv: String,
+    pub mv: Option<String>,
     pub ponder: Option<String>,
 }
 impl BestMove {
@@ -217,7 +235,10 @@ impl BestMove {
             return None;
         }
         let parts: Vec<&str> = line.split_whitespace().collect();
-        let mv = parts.get(1)?.to_string();
+        let mv = match *parts.get(1)? {
+            "(none)" | "0000" => None,
+            mv => Some(mv.to_string()),
+        };
         let ponder = parts
             .iter()
             .position(|&s| s == "ponder")
@@ -266,6 +287,61 @@ mod tests {
         assert_eq!(info.multipv, Some(2));
     }
     #[test]
+    fn test_uci_info_parse_bounds() {
+        let info =
+            UciInfo::parse("info depth 18 score cp 35 lowerbound nodes 900 pv e2e4").unwrap();
+        assert_eq!(info.score_cp, Some(35));
+        assert_eq!(info.bound, Some(Bound::Lower));
+        assert_eq!(info.nodes, Some(900));
+        assert_eq!(info.pv, vec!["e2e4"]);
+        let info = UciInfo::parse("info depth 18 score mate -4 upperbound").unwrap();
+        assert_eq!(info.score_mate, Some(-4));
+        assert_eq!(info.bound, Some(Bound::Upper));
+        let info = UciInfo::parse("info depth 18 score cp 35 nodes 900").unwrap();
+        assert_eq!(info.bound, None);
+    }
+    #[test]
+    fn test_uci_info_parse_wdl() {
+        let line =
+            "info depth 22 seldepth 30 multipv 2 score cp 18 wdl 512 488 0 nodes 4000 pv d2d4";
+        let info = UciInfo::parse(line).unwrap();
+        assert_eq!(info.wdl, Some((512, 488, 0)));
+        assert_eq!(info.multipv, Some(2));
+        assert_eq!(info.score_cp, Some(18));
+        assert_eq!(info.nodes, Some(4000));
+        assert_eq!(info.pv, vec!["d2d4"]);
+        let info = UciInfo::parse("info depth 22 score cp 18 upperbound wdl 40 900 60").unwrap();
+        assert_eq!(info.bound, Some(Bound::Upper));
+        assert_eq!(info.wdl, Some((40, 900, 60)));
+        assert_eq!(UciInfo::parse("info wdl 1 2").unwrap().wdl, None);
+    }
+    #[test]
+    fn test_uci_info_parse_tbhits() {
+        let line =
+            "info depth 40 score cp 19960 nodes 52000 nps 1000000 tbhits 3187 time 52 pv e1d2";
+        let info = UciInfo::parse(line).unwrap();
+        assert_eq!(info.tbhits, Some(3187));
+        assert_eq!(info.score_cp, Some(19960));
+        assert_eq!(info.time, Some(52));
+        assert_eq!(
+            UciInfo::parse("info depth 1 nodes 20").unwrap().tbhits,
+            None
+        );
+    }
+    #[test]
+    fn test_uci_info_parse_currmovenumber() {
+        let line = "info depth 25 currmove e2e4 currmovenumber 1";
+        let info = UciInfo::parse(line).unwrap();
+        assert_eq!(info.depth, Some(25));
+        assert_eq!(info.currmove.as_deref(), Some("e2e4"));
+        assert_eq!(info.currmovenumber, Some(1));
+        assert!(info.pv.is_empty());
+        let line = "info depth 25 currmove g1f3 currmovenumber 3 multipv 2";
+        let info = UciInfo::parse(line).unwrap();
+        assert_eq!(info.currmovenumber, Some(3));
+        assert_eq!(info.multipv, Some(2));
+    }
+    #[test]
     fn test_uci_info_not_info_line() {
         let line = "bestmove e2e4";
         assert!(UciInfo::parse(line).is_none());
@@ -276,17 +352,25 @@ mod tests {
     fn test_bestmove_parse() {
         let line = "bestmove e2e4";
         let bm = BestMove::parse(line).unwrap();
-        assert_eq!(bm.mv, "e2e4");
+        assert_eq!(bm.mv.as_deref(), Some("e2e4"));
         assert_eq!(bm.ponder, None);
     }
     #[test]
     fn test_bestmove_parse_with_ponder() {
         let line = "bestmove e2e4 ponder e7e5";
         let bm = BestMove::parse(line).unwrap();
-        assert_eq!(bm.mv, "e2e4");
+        assert_eq!(bm.mv.as_deref(), Some("e2e4"));
         assert_eq!(bm.ponder, Some("e7e5".to_string()));
     }
     #[test]
+    fn test_bestmove_parse_none() {
+        let bm = BestMove::parse("bestmove (none)").unwrap();
+        assert_eq!(bm.mv, None);
+        assert_eq!(bm.ponder, None);
+        let bm = BestMove::parse("bestmove 0000").unwrap();
+        assert_eq!(bm.mv, None);
+    }
+    #[test]
     fn test_bestmove_not_bestmove_line() {
         let line = "info depth 10";
         assert!(BestMove::parse(line).is_none());
@@ -1,6 +1,22 @@
 mod analysis;
+mod cache;
 mod engine;
+mod limits;
+mod play;
 mod pool;
+mod pv;
+mod tablebase;
+mod uci;
 pub use analysis::AnalysisService;
+pub use cache::{AnalysisCache, AnalysisCacheConfig};
+pub use engine::ProcessEngine;
+#[allow(deprecated)]
 pub use engine::StockfishEngine;
-pub use pool::{EnginePool, EnginePoolConfig};
+pub use limits::SearchLimits;
+pub use pool::{
+    EngineFactory, EngineKind, EnginePool, EnginePoolConfig, NewGamePolicy, RequestClass,
+};
+pub use tablebase::SyzygyTables;
+#[cfg(feature = "tablebase-probe")]
+pub use tablebase::{TablebaseProbe, TablebaseProbeResult};
+pub use uci::{EngineHandle, UciEngine};
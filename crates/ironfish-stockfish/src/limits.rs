@@ -0,0 +1,183 @@
+use crate::engine::UciInfo;
+use ironfish_core::{AnalysisRequest, StopReason};
+use std::time::Duration;
+const MOVETIME_MARGIN: Duration = Duration::from_secs(5);
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    pub search_moves: Vec<String>,
+}
+impl SearchLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_depth(mut self, depth: u8) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+    pub fn with_movetime(mut self, ms: u64) -> Self {
+        self.movetime = Some(ms);
+        self
+    }
+    pub fn with_nodes(mut self, nodes: u64) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+    pub fn with_search_moves(mut self, moves: Vec<String>) -> Self {
+        self.search_moves = moves;
+        self
+    }
+    pub fn from_request(request: &AnalysisRequest) -> Self {
+        let limits = Self::new().with_search_moves(request.search_moves.clone());
+        if request.infinite {
+            return limits;
+        }
+        Self {
+            depth: Some(request.depth),
+            movetime: request.movetime,
+            nodes: request.nodes,
+            ..limits
+        }
+    }
+    pub fn is_unbounded(&self) -> bool {
+        self.depth.is_none() && self.movetime.is_none() && self.nodes.is_none()
+    }
+    pub fn search_timeout(&self, fallback: Duration) -> Duration {
+        match self.movetime {
+            Some(ms) => Duration::from_millis(ms) + MOVETIME_MARGIN,
+            None => fallback,
+        }
+    }
+    pub fn to_go_command(&self) -> String {
+        let mut cmd = String::from("go");
+        if self.is_unbounded() {
+            cmd.push_str(" infinite");
+        }
+        if let Some(depth) = self.depth {
+            cmd.push_str(&format!(" depth {}", depth));
+        }
+        if let Some(nodes) = self.nodes {
+            cmd.push_str(&format!(" nodes {}", nodes));
+        }
+        if let Some(movetime) = self.movetime {
+            cmd.push_str(&format!(" movetime {}", movetime));
+        }
+        if !self.search_moves.is_empty() {
+            cmd.push_str(" searchmoves ");
+            cmd.push_str(&self.search_moves.join(" "));
+        }
+        cmd
+    }
+    pub fn stop_reason(&self, depth_reached: u8, info: &UciInfo, elapsed_ms: u64) -> StopReason {
+        if self.depth.is_some_and(|d| depth_reached >= d) {
+            return StopReason::Depth;
+        }
+        if self
+            .nodes
+            .is_some_and(|n| info.nodes.is_some_and(|searched| searched >= n))
+        {
+            return StopReason::Nodes;
+        }
+        if let Some(movetime) = self.movetime {
+            if info.time.unwrap_or(0).max(elapsed_ms) >= movetime {
+                return StopReason::Time;
+            }
+        }
+        if self.nodes.is_some() {
+            StopReason::Nodes
+        } else if self.movetime.is_some() {
+            StopReason::Time
+        } else {
+            StopReason::Depth
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_go_command_depth_only() {
+        let limits = SearchLimits::new().with_depth(20);
+        assert_eq!(limits.to_go_command(), "go depth 20");
+    }
+    #[test]
+    fn test_go_command_compound() {
+        let limits = SearchLimits::new().with_depth(30).with_movetime(5000);
+        assert_eq!(limits.to_go_command(), "go depth 30 movetime 5000");
+        let limits = SearchLimits::new()
+            .with_depth(30)
+            .with_nodes(2_000_000)
+            .with_movetime(5000);
+        assert_eq!(
+            limits.to_go_command(),
+            "go depth 30 nodes 2000000 movetime 5000"
+        );
+    }
+    #[test]
+    fn test_go_command_nodes_only() {
+        let limits = SearchLimits::new().with_nodes(2_000_000);
+        assert_eq!(limits.to_go_command(), "go nodes 2000000");
+    }
+    #[test]
+    fn test_go_command_unbounded() {
+        assert_eq!(SearchLimits::new().to_go_command(), "go infinite");
+    }
+    #[test]
+    fn test_go_command_search_moves() {
+        let limits = SearchLimits::new()
+            .with_depth(18)
+            .with_search_moves(vec!["e2e4".into(), "d2d4".into()]);
+        assert_eq!(limits.to_go_command(), "go depth 18 searchmoves e2e4 d2d4");
+        let request = AnalysisRequest::infinite("startpos").with_search_moves(vec!["g1f3".into()]);
+        assert_eq!(
+            SearchLimits::from_request(&request).to_go_command(),
+            "go infinite searchmoves g1f3"
+        );
+    }
+    #[test]
+    fn test_from_request() {
+        let request = AnalysisRequest::new("startpos")
+            .with_depth(25)
+            .with_movetime(3000)
+            .with_nodes(100);
+        let limits = SearchLimits::from_request(&request);
+        assert_eq!(limits.depth, Some(25));
+        assert_eq!(limits.movetime, Some(3000));
+        assert_eq!(limits.nodes, Some(100));
+    }
+    #[test]
+    fn test_search_timeout() {
+        let fallback = Duration::from_secs(60);
+        let limits = SearchLimits::new().with_depth(30);
+        assert_eq!(limits.search_timeout(fallback), fallback);
+        let limits = limits.with_movetime(90_000);
+        assert_eq!(limits.search_timeout(fallback), Duration::from_secs(95));
+        let limits = SearchLimits::new().with_movetime(500);
+        assert_eq!(limits.search_timeout(fallback), Duration::from_millis(5500));
+    }
+    #[test]
+    fn test_stop_reason() {
+        let limits = SearchLimits::new().with_depth(30).with_movetime(5000);
+        let info = UciInfo {
+            depth: Some(30),
+            time: Some(2000),
+            ..Default::default()
+        };
+        assert_eq!(limits.stop_reason(30, &info, 2000), StopReason::Depth);
+        let info = UciInfo {
+            depth: Some(22),
+            time: Some(5001),
+            ..Default::default()
+        };
+        assert_eq!(limits.stop_reason(22, &info, 5001), StopReason::Time);
+        let limits = SearchLimits::new().with_depth(30).with_nodes(1000);
+        let info = UciInfo {
+            depth: Some(12),
+            nodes: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(limits.stop_reason(12, &info, 10), StopReason::Nodes);
+    }
+}
@@ -0,0 +1,513 @@
+use crate::engine::{BestMove, UciInfo};
+use crate::limits::SearchLimits;
+use crate::uci::UciEngine;
+use ironfish_core::{
+    BestMoveRequest, ChessPosition, Error, GameOver, Move, PlayCommand, PlayUpdate, Result, Score,
+};
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Search {
+    Idle,
+    Thinking,
+    Pondering(String),
+}
+pub(crate) struct PlaySession<'a> {
+    engine: &'a dyn UciEngine,
+    updates: mpsc::Sender<PlayUpdate>,
+    default_movetime: u64,
+    game: Option<BestMoveRequest>,
+    ponder: bool,
+    search: Search,
+    queued: Option<String>,
+    ponder_hit: bool,
+    depth: u8,
+    terminal: Option<Score>,
+    chess960: bool,
+}
+impl<'a> PlaySession<'a> {
+    pub(crate) fn new(
+        engine: &'a dyn UciEngine,
+        updates: mpsc::Sender<PlayUpdate>,
+        default_movetime: u64,
+    ) -> Self {
+        Self {
+            engine,
+            updates,
+            default_movetime,
+            game: None,
+            ponder: false,
+            search: Search::Idle,
+            queued: None,
+            ponder_hit: false,
+            depth: 0,
+            terminal: None,
+            chess960: false,
+        }
+    }
+    pub(crate) fn uses_chess960(&self) -> bool {
+        self.chess960
+    }
+    pub(crate) async fn run(
+        &mut self,
+        mut commands: mpsc::Receiver<PlayCommand>,
+        deadline: Instant,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        let (line_tx, mut lines) = mpsc::channel(64);
+        let engine = self.engine;
+        let session = self.drive(&mut commands, &mut lines, deadline, shutdown);
+        tokio::pin!(session);
+        tokio::select! {
+            biased;
+            result = &mut session => result,
+            _ = forward_lines(engine, line_tx) => session.await,
+        }
+    }
+    async fn drive(
+        &mut self,
+        commands: &mut mpsc::Receiver<PlayCommand>,
+        lines: &mut mpsc::Receiver<Result<String>>,
+        deadline: Instant,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(command) => self.handle(command, lines).await?,
+                    None => return self.halt(lines).await,
+                },
+                line = lines.recv() => self.handle_line(next_line(line)?).await?,
+                _ = tokio::time::sleep_until(deadline) => {
+                    debug!("play session exhausted its time budget");
+                    self.halt(lines).await?;
+                    return Err(Error::AnalysisTimeout);
+                }
+                _ = shutdown.cancelled() => {
+                    self.halt(lines).await?;
+                    return Err(Error::ShuttingDown);
+                }
+            }
+        }
+    }
+    async fn handle(
+        &mut self,
+        command: PlayCommand,
+        lines: &mut mpsc::Receiver<Result<String>>,
+    ) -> Result<()> {
+        match command {
+            PlayCommand::Position { request, ponder } => {
+                request.validate()?;
+                self.halt(lines).await?;
+                let chess960 = request.variant.is_chess960();
+                if chess960 != self.chess960 {
+                    self.engine.set_chess960(chess960).await?;
+                    self.chess960 = chess960;
+                }
+                self.ponder = ponder;
+                self.queued = None;
+                self.game = Some(request);
+                self.think().await
+            }
+            PlayCommand::OpponentMove(mv) => self.opponent_move(mv, lines).await,
+        }
+    }
+    async fn opponent_move(
+        &mut self,
+        mv: String,
+        lines: &mut mpsc::Receiver<Result<String>>,
+    ) -> Result<()> {
+        if self.game.is_none() {
+            return Err(Error::InvalidArgument(
+                "send a position before the first opponent move".into(),
+            ));
+        }
+        match &self.search {
+            Search::Thinking if self.queued.is_some() => {
+                return Err(Error::InvalidArgument(
+                    "opponent moved twice while the engine is still thinking".into(),
+                ))
+            }
+            Search::Thinking => {
+                debug!(mv = %mv, "queueing opponent move until the engine replies");
+                self.queued = Some(mv);
+                return Ok(());
+            }
+            Search::Pondering(expected) if *expected == mv => {
+                debug!(mv = %mv, "ponder hit");
+                self.engine.ponderhit().await?;
+                self.search = Search::Thinking;
+                self.ponder_hit = true;
+                return Ok(());
+            }
+            _ => self.halt(lines).await?,
+        }
+        self.play_opponent_move(mv).await
+    }
+    async fn play_opponent_move(&mut self, mv: String) -> Result<()> {
+        if let Some(game) = self.game.as_mut() {
+            game.moves.push(mv);
+            game.validate()?;
+        }
+        self.think().await
+    }
+    async fn think(&mut self) -> Result<()> {
+        let game = self
+            .game
+            .as_ref()
+            .ok_or_else(|| Error::InvalidArgument("no position set".into()))?;
+        self.engine
+            .set_position_with_moves(&game.fen, &game.moves)
+            .await?;
+        self.engine
+            .go(&search_limits(game, self.default_movetime))
+            .await?;
+        self.search = Search::Thinking;
+        self.ponder_hit = false;
+        self.depth = 0;
+        self.terminal = None;
+        Ok(())
+    }
+    async fn start_pondering(&mut self, ponder: String) -> Result<()> {
+        let Some(game) = self.game.as_mut() else {
+            return Ok(());
+        };
+        game.moves.push(ponder.clone());
+        self.engine
+            .set_position_with_moves(&game.fen, &game.moves)
+            .await?;
+        self.engine
+            .go_ponder(&search_limits(game, self.default_movetime))
+            .await?;
+        self.search = Search::Pondering(ponder);
+        self.depth = 0;
+        Ok(())
+    }
+    async fn handle_line(&mut self, line: String) -> Result<()> {
+        let line = line.trim();
+        if let Some(info) = UciInfo::parse(line) {
+            self.report(&info);
+            return Ok(());
+        }
+        let Some(best) = BestMove::parse(line) else {
+            return Ok(());
+        };
+        if self.search != Search::Thinking {
+            return Ok(());
+        }
+        let Some(mv) = best.mv else {
+            self.search = Search::Idle;
+            self.queued = None;
+            let game_over = self.game_over();
+            let _ = self
+                .updates
+                .send(PlayUpdate::BestMove {
+                    best_move: None,
+                    ponder: None,
+                    ponder_hit: self.ponder_hit,
+                    game_over: Some(game_over),
+                })
+                .await;
+            return Ok(());
+        };
+        let best_move =
+            Move::from_uci(&mv).ok_or_else(|| Error::Engine("invalid bestmove".into()))?;
+        if let Some(game) = self.game.as_mut() {
+            game.moves.push(mv);
+        }
+        let ponder = best.ponder.as_ref().and_then(|p| Move::from_uci(p));
+        let update = PlayUpdate::BestMove {
+            best_move: Some(best_move),
+            ponder,
+            ponder_hit: self.ponder_hit,
+            game_over: None,
+        };
+        self.search = Search::Idle;
+        if self.updates.send(update).await.is_err() {
+            return Ok(());
+        }
+        if let Some(queued) = self.queued.take() {
+            return self.play_opponent_move(queued).await;
+        }
+        match best.ponder {
+            Some(ponder) if self.ponder => self.start_pondering(ponder).await,
+            _ => Ok(()),
+        }
+    }
+    fn game_over(&self) -> GameOver {
+        let evaluation = self.terminal.unwrap_or(Score::Centipawns(0));
+        self.game
+            .as_ref()
+            .and_then(|game| {
+                ChessPosition::new(&game.fen)
+                    .apply_uci_moves(&game.moves)
+                    .ok()
+            })
+            .map(|position| GameOver::detect(&position, &evaluation))
+            .unwrap_or_else(|| GameOver::from_evaluation(&evaluation))
+    }
+    fn report(&mut self, info: &UciInfo) {
+        let depth = info.depth.unwrap_or(0);
+        let evaluation = match info.score_mate {
+            Some(mate) => Score::MateIn(mate),
+            None => Score::Centipawns(info.score_cp.unwrap_or(0)),
+        };
+        if info.pv.is_empty() && (info.score_mate.is_some() || info.score_cp.is_some()) {
+            self.terminal = Some(evaluation);
+        }
+        if info.pv.is_empty() || info.multipv.unwrap_or(1) != 1 || depth <= self.depth {
+            return;
+        }
+        self.depth = depth;
+        let _ = self.updates.try_send(PlayUpdate::Evaluation {
+            depth,
+            evaluation,
+            pv: info.pv.iter().filter_map(|m| Move::from_uci(m)).collect(),
+            pondering: matches!(self.search, Search::Pondering(_)),
+        });
+    }
+    async fn halt(&mut self, lines: &mut mpsc::Receiver<Result<String>>) -> Result<()> {
+        if self.search == Search::Idle {
+            return Ok(());
+        }
+        self.engine.stop().await?;
+        let stopped = timeout(STOP_TIMEOUT, async {
+            loop {
+                let line = next_line(lines.recv().await)?;
+                if BestMove::parse(line.trim()).is_some() {
+                    return Ok::<(), Error>(());
+                }
+            }
+        })
+        .await;
+        match stopped {
+            Ok(result) => result?,
+            Err(_) => return Err(Error::AnalysisTimeout),
+        }
+        if let (Search::Pondering(_), Some(game)) = (&self.search, self.game.as_mut()) {
+            game.moves.pop();
+        }
+        self.search = Search::Idle;
+        Ok(())
+    }
+}
+fn search_limits(game: &BestMoveRequest, default_movetime: u64) -> SearchLimits {
+    let mut limits = SearchLimits::new();
+    if let Some(nodes) = game.nodes {
+        limits = limits.with_nodes(nodes);
+    }
+    match (game.movetime, game.nodes) {
+        (Some(ms), _) => limits.with_movetime(ms),
+        (None, Some(_)) => limits,
+        (None, None) => limits.with_movetime(default_movetime),
+    }
+}
+fn next_line(line: Option<Result<String>>) -> Result<String> {
+    line.unwrap_or_else(|| Err(Error::Engine("engine closed its output".into())))
+}
+async fn forward_lines(engine: &dyn UciEngine, lines: mpsc::Sender<Result<String>>) {
+    loop {
+        let line = engine.read_line().await;
+        let failed = line.is_err();
+        if lines.send(line).await.is_err() || failed {
+            return;
+        }
+    }
+}
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::{AnalysisService, EnginePool, EnginePoolConfig};
+    use ironfish_core::{BestMoveRequest, ChessPosition, Error, PlayCommand, PlayUpdate};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use tokio::time::Duration;
+    async fn pondering_pool(
+        dir: &std::path::Path,
+        think_secs: f32,
+    ) -> (Arc<EnginePool>, std::path::PathBuf) {
+        let log = dir.join("commands.log");
+        let script = dir.join("engine.sh");
+        let body = format!(
+            r#"#!/bin/sh
+while IFS= read -r line; do
+  echo "$line" >> "{}"
+  case "$line" in
+    uci) echo "uciok" ;;
+    isready) echo "readyok" ;;
+    "go ponder"*) pondering=1; echo "info depth 3 score cp 5 nodes 100 pv d7d5 c2c4" ;;
+    ponderhit) pondering=; echo "info depth 12 score cp 20 nodes 5000 pv c2c4 e7e6"; echo "bestmove c2c4 ponder e7e6" ;;
+    stop) [ -n "$pondering" ] && echo "bestmove c2c4 ponder e7e6"; pondering= ;;
+    go*) sleep {}; echo "info depth 8 score cp 25 nodes 900 pv d2d4 d7d5"; echo "bestmove d2d4 ponder d7d5" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#,
+            log.display(),
+            think_secs
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        (Arc::new(pool), log)
+    }
+    async fn next(updates: &mut mpsc::Receiver<PlayUpdate>) -> PlayUpdate {
+        tokio::time::timeout(Duration::from_secs(5), updates.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+    fn best_move(update: PlayUpdate) -> (String, Option<String>, bool) {
+        match update {
+            PlayUpdate::BestMove {
+                best_move,
+                ponder,
+                ponder_hit,
+                ..
+            } => (
+                best_move.unwrap().to_uci(),
+                ponder.map(|m| m.to_uci()),
+                ponder_hit,
+            ),
+            other => panic!("expected a best move, got {:?}", other),
+        }
+    }
+    fn evaluation(update: PlayUpdate) -> (u8, bool) {
+        match update {
+            PlayUpdate::Evaluation {
+                depth, pondering, ..
+            } => (depth, pondering),
+            other => panic!("expected an evaluation, got {:?}", other),
+        }
+    }
+    #[tokio::test]
+    async fn test_play_session_ponders_and_releases_engine() {
+        let dir = std::env::temp_dir().join(format!("ironfish-play-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, log) = pondering_pool(&dir, 0.0).await;
+        let service = Arc::new(AnalysisService::new(pool.clone()));
+        let (commands, command_rx) = mpsc::channel(8);
+        let (update_tx, mut updates) = mpsc::channel(32);
+        let session = {
+            let service = service.clone();
+            tokio::spawn(async move { service.play(command_rx, update_tx).await })
+        };
+        let mut request = BestMoveRequest::new(ChessPosition::starting().fen);
+        request.movetime = Some(50);
+        commands
+            .send(PlayCommand::Position {
+                request,
+                ponder: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(evaluation(next(&mut updates).await), (8, false));
+        assert_eq!(
+            best_move(next(&mut updates).await),
+            ("d2d4".into(), Some("d7d5".into()), false)
+        );
+        assert_eq!(evaluation(next(&mut updates).await), (3, true));
+        assert_eq!(pool.available(), 0);
+        commands
+            .send(PlayCommand::OpponentMove("d7d5".into()))
+            .await
+            .unwrap();
+        assert_eq!(evaluation(next(&mut updates).await), (12, false));
+        assert_eq!(
+            best_move(next(&mut updates).await),
+            ("c2c4".into(), Some("e7e6".into()), true)
+        );
+        assert_eq!(evaluation(next(&mut updates).await), (3, true));
+        commands
+            .send(PlayCommand::OpponentMove("g8f6".into()))
+            .await
+            .unwrap();
+        assert_eq!(evaluation(next(&mut updates).await), (8, false));
+        assert!(!best_move(next(&mut updates).await).2);
+        drop(commands);
+        session.await.unwrap().unwrap();
+        assert_eq!(pool.available(), 1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged(&log);
+        let ponder = |moves: &str| {
+            commands
+                .iter()
+                .position(|c| c == &format!("position startpos moves {}", moves))
+                .unwrap()
+        };
+        let first = ponder("d2d4 d7d5");
+        assert_eq!(commands[first + 1], "go ponder movetime 50");
+        assert_eq!(commands[first + 2], "ponderhit");
+        let missed = ponder("d2d4 d7d5 c2c4 g8f6");
+        assert_eq!(commands[missed - 1], "stop");
+        assert_eq!(commands[missed + 1], "go movetime 50");
+        let budgeted =
+            AnalysisService::new(pool.clone()).with_play_budget(Duration::from_millis(100));
+        let (commands, command_rx) = mpsc::channel(8);
+        let (update_tx, _updates) = mpsc::channel(32);
+        commands
+            .send(PlayCommand::Position {
+                request: BestMoveRequest::new(ChessPosition::starting().fen),
+                ponder: true,
+            })
+            .await
+            .unwrap();
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), budgeted.play(command_rx, update_tx))
+                .await
+                .unwrap();
+        assert!(matches!(result, Err(Error::AnalysisTimeout)));
+        assert_eq!(pool.available(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_opponent_move_while_thinking_is_queued() {
+        let dir = std::env::temp_dir().join(format!("ironfish-play-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, log) = pondering_pool(&dir, 0.3).await;
+        let service = AnalysisService::new(pool.clone());
+        let (commands, command_rx) = mpsc::channel(8);
+        let (update_tx, mut updates) = mpsc::channel(32);
+        let mut request = BestMoveRequest::new(ChessPosition::starting().fen);
+        request.movetime = Some(50);
+        commands
+            .send(PlayCommand::Position {
+                request,
+                ponder: false,
+            })
+            .await
+            .unwrap();
+        commands
+            .send(PlayCommand::OpponentMove("e7e5".into()))
+            .await
+            .unwrap();
+        let session = tokio::spawn(async move { service.play(command_rx, update_tx).await });
+        assert_eq!(evaluation(next(&mut updates).await), (8, false));
+        assert_eq!(best_move(next(&mut updates).await).0, "d2d4");
+        assert_eq!(evaluation(next(&mut updates).await), (8, false));
+        assert_eq!(best_move(next(&mut updates).await).0, "d2d4");
+        drop(commands);
+        session.await.unwrap().unwrap();
+        assert!(logged(&log)
+            .iter()
+            .any(|c| c == "position startpos moves d2d4 e7e5"));
+        assert_eq!(pool.available(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    fn logged(log: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
@@ -1,106 +1,1199 @@
-use crate::engine::StockfishEngine;
-use ironfish_core::{Error, Result};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::engine::ProcessEngine;
+use crate::uci::{EngineHandle, UciEngine};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use ironfish_core::{EnginePoolStatus, EngineSlotStatus, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Semaphore, SemaphorePermit};
-use tracing::{debug, info, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit, TryAcquireError};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, instrument, warn};
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewGamePolicy {
+    #[default]
+    Always,
+    PerPositionChange,
+    Never,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineKind {
+    #[default]
+    Stockfish,
+    Lc0,
+    Uci,
+}
+impl EngineKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stockfish => "stockfish",
+            Self::Lc0 => "lc0",
+            Self::Uci => "uci",
+        }
+    }
+    pub fn default_options(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Stockfish => &[("UCI_ShowWDL", "true")],
+            Self::Lc0 => &[("UCI_ShowWDL", "true"), ("SmartPruningFactor", "0")],
+            Self::Uci => &[],
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestClass {
+    Fast,
+    Deep,
+}
 #[derive(Debug, Clone)]
 pub struct EnginePoolConfig {
     pub binary_path: String,
+    pub kind: EngineKind,
+    pub args: Vec<String>,
     pub pool_size: usize,
+    pub options: HashMap<String, String>,
+    pub restart_backoff: Duration,
+    pub reset_timeout: Duration,
+    pub acquire_timeout: Duration,
+    pub max_queue: usize,
+    pub warmup_depth: u8,
+    pub warmup_timeout: Duration,
+    pub new_game_policy: NewGamePolicy,
+    pub reserved_engines: usize,
+    pub reserved_fraction: Option<f64>,
+    pub fast_movetime_threshold: Duration,
 }
 impl Default for EnginePoolConfig {
     fn default() -> Self {
         Self {
             binary_path: "/usr/bin/stockfish".to_string(),
+            kind: EngineKind::Stockfish,
+            args: Vec::new(),
             pool_size: 4,
+            options: HashMap::new(),
+            restart_backoff: Duration::from_secs(1),
+            reset_timeout: Duration::from_secs(5),
+            acquire_timeout: Duration::from_secs(10),
+            max_queue: 64,
+            warmup_depth: 0,
+            warmup_timeout: Duration::from_secs(10),
+            new_game_policy: NewGamePolicy::Always,
+            reserved_engines: 0,
+            reserved_fraction: None,
+            fast_movetime_threshold: Duration::from_secs(1),
         }
     }
 }
+impl EnginePoolConfig {
+    pub fn with_option(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.options.insert(name.into(), value.to_string());
+        self
+    }
+    fn reserved(&self) -> usize {
+        let reserved = match self.reserved_fraction {
+            Some(fraction) => (self.pool_size as f64 * fraction.clamp(0.0, 1.0)).ceil() as usize,
+            None => self.reserved_engines,
+        };
+        reserved.min(self.pool_size.saturating_sub(1))
+    }
+    fn sorted_options(&self) -> Vec<(String, String)> {
+        let mut options: Vec<(String, String)> = self
+            .options
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        options.sort();
+        options
+    }
+}
+#[async_trait]
+pub trait EngineFactory: Send + Sync {
+    async fn spawn(&self) -> Result<Arc<dyn UciEngine>>;
+}
+struct ProcessEngineFactory {
+    binary_path: String,
+    args: Vec<String>,
+    options: Vec<(String, String)>,
+}
+#[async_trait]
+impl EngineFactory for ProcessEngineFactory {
+    async fn spawn(&self) -> Result<Arc<dyn UciEngine>> {
+        let engine =
+            ProcessEngine::with_args(&self.binary_path, self.args.clone(), self.options.clone())
+                .await?;
+        Ok(Arc::new(engine))
+    }
+}
+#[derive(Default)]
+struct RestartState {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+struct Slot {
+    id: usize,
+    engine: Arc<EngineHandle>,
+    restart_state: Mutex<RestartState>,
+    abnormal: AtomicBool,
+    claimed: AtomicBool,
+    busy_since: std::sync::Mutex<Option<DateTime<Utc>>>,
+    restarts: AtomicU64,
+    warmed: AtomicBool,
+}
+impl Slot {
+    fn new(id: usize, engine: EngineHandle) -> Self {
+        Self {
+            id,
+            engine: Arc::new(engine),
+            restart_state: Mutex::default(),
+            abnormal: AtomicBool::new(false),
+            claimed: AtomicBool::new(false),
+            busy_since: std::sync::Mutex::default(),
+            restarts: AtomicU64::new(0),
+            warmed: AtomicBool::new(false),
+        }
+    }
+    fn claim(&self) -> bool {
+        self.claimed
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+    fn release(&self) {
+        self.claimed.store(false, Ordering::SeqCst);
+    }
+}
 pub struct EnginePool {
-    engines: Vec<Arc<StockfishEngine>>,
+    slots: std::sync::RwLock<Vec<Arc<Slot>>>,
+    factory: Box<dyn EngineFactory>,
+    kind: EngineKind,
+    options: Vec<(String, String)>,
+    new_game_policy: NewGamePolicy,
+    resize_lock: Mutex<()>,
+    next_slot_id: AtomicUsize,
+    restart_backoff: Duration,
+    reset_timeout: Duration,
+    acquire_timeout: Duration,
+    max_queue: usize,
+    waiting: AtomicUsize,
+    restarts: AtomicU64,
+    resets: AtomicU64,
     semaphore: Arc<Semaphore>,
+    reserved: Semaphore,
+    reserved_size: usize,
+    fast_movetime_threshold: Duration,
     next_engine: AtomicUsize,
     active_count: AtomicUsize,
 }
 impl EnginePool {
     pub async fn new(config: EnginePoolConfig) -> Result<Self> {
         info!(
-            "creating engine pool with {} engines from {}",
-            config.pool_size, config.binary_path
+            "creating {} engine pool with {} engines from {}",
+            config.kind.as_str(),
+            config.pool_size,
+            config.binary_path
         );
-        let mut engines = Vec::with_capacity(config.pool_size);
+        let factory = ProcessEngineFactory {
+            binary_path: config.binary_path.clone(),
+            args: config.args.clone(),
+            options: config.sorted_options(),
+        };
+        Self::with_factory(config, factory).await
+    }
+    pub async fn with_factory(
+        config: EnginePoolConfig,
+        factory: impl EngineFactory + 'static,
+    ) -> Result<Self> {
+        let reserved = config.reserved();
+        if reserved > 0 {
+            info!("reserving {} engines for fast requests", reserved);
+        }
+        let options = config.sorted_options();
+        let mut slots = Vec::with_capacity(config.pool_size);
         for i in 0..config.pool_size {
-            match StockfishEngine::new(&config.binary_path).await {
+            match factory.spawn().await {
                 Ok(engine) => {
                     debug!("engine {} initialized", i);
-                    engines.push(Arc::new(engine));
+                    let engine = EngineHandle::new(engine, options.clone(), config.new_game_policy);
+                    slots.push(Arc::new(Slot::new(i, engine)));
                 }
                 Err(e) => {
                     warn!("failed to create engine {}: {}", i, e);
-                    for engine in &engines {
-                        let _ = engine.quit().await;
+                    for slot in &slots {
+                        let _ = slot.engine.quit().await;
                     }
                     return Err(e);
                 }
             }
         }
-        Ok(Self {
-            engines,
-            semaphore: Arc::new(Semaphore::new(config.pool_size)),
+        let pool = Self {
+            slots: std::sync::RwLock::new(slots),
+            factory: Box::new(factory),
+            kind: config.kind,
+            options,
+            new_game_policy: config.new_game_policy,
+            resize_lock: Mutex::new(()),
+            next_slot_id: AtomicUsize::new(config.pool_size),
+            restart_backoff: config.restart_backoff,
+            reset_timeout: config.reset_timeout,
+            acquire_timeout: config.acquire_timeout,
+            max_queue: config.max_queue,
+            waiting: AtomicUsize::new(0),
+            restarts: AtomicU64::new(0),
+            resets: AtomicU64::new(0),
+            semaphore: Arc::new(Semaphore::new(config.pool_size - reserved)),
+            reserved: Semaphore::new(reserved),
+            reserved_size: reserved,
+            fast_movetime_threshold: config.fast_movetime_threshold,
             next_engine: AtomicUsize::new(0),
             active_count: AtomicUsize::new(0),
-        })
+        };
+        if config.warmup_depth > 0 {
+            pool.warm_up(config.warmup_depth, config.warmup_timeout)
+                .await;
+        }
+        Ok(pool)
     }
-    pub async fn acquire(&self) -> Result<PooledEngine<'_>> {
-        let permit = self
-            .semaphore
-            .acquire()
-            .await
-            .map_err(|_| Error::PoolExhausted)?;
-        let idx = self.next_engine.fetch_add(1, Ordering::SeqCst) % self.engines.len();
-        let engine = Arc::clone(&self.engines[idx]);
-        if !engine.is_running().await {
-            warn!("engine {} is dead, restarting", idx);
-            engine.restart().await?;
+    fn slots(&self) -> Vec<Arc<Slot>> {
+        self.slots.read().unwrap().clone()
+    }
+    fn claim_slot(&self) -> Option<Arc<Slot>> {
+        let slots = self.slots.read().unwrap();
+        let start = self.next_engine.fetch_add(1, Ordering::SeqCst);
+        (0..slots.len())
+            .map(|offset| &slots[(start + offset) % slots.len()])
+            .find(|slot| slot.claim())
+            .cloned()
+    }
+    async fn warm_up(&self, depth: u8, limit: Duration) {
+        let started = Instant::now();
+        let slots = self.slots();
+        join_all(slots.iter().map(|slot| async move {
+            match timeout(limit, slot.engine.warm_up(depth)).await {
+                Ok(Ok(())) => slot.warmed.store(true, Ordering::SeqCst),
+                Ok(Err(e)) => warn!("engine {} warm-up failed: {}", slot.id, e),
+                Err(_) => {
+                    warn!(
+                        "engine {} did not finish warming up in {:?}",
+                        slot.id, limit
+                    );
+                    let _ = slot.engine.stop().await;
+                }
+            }
+        }))
+        .await;
+        info!(
+            "warmed {} of {} engines to depth {} in {:?}",
+            self.warmed(),
+            slots.len(),
+            depth,
+            started.elapsed()
+        );
+    }
+    pub fn classify(&self, movetime: Option<u64>) -> RequestClass {
+        match movetime {
+            Some(ms) if Duration::from_millis(ms) <= self.fast_movetime_threshold => {
+                RequestClass::Fast
+            }
+            _ => RequestClass::Deep,
         }
-        self.active_count.fetch_add(1, Ordering::SeqCst);
-        Ok(PooledEngine {
-            engine,
+    }
+    fn try_permit(&self, class: RequestClass) -> Result<Option<SemaphorePermit<'_>>> {
+        let permit = match class {
+            RequestClass::Fast => match self.reserved.try_acquire() {
+                Err(TryAcquireError::NoPermits) => self.semaphore.try_acquire(),
+                permit => permit,
+            },
+            RequestClass::Deep => match self.semaphore.try_acquire() {
+                Err(TryAcquireError::NoPermits) => {
+                    self.reserved.try_acquire_many(2).map(|mut pair| {
+                        let permit = pair.split(1).expect("pair holds two permits");
+                        drop(pair);
+                        permit
+                    })
+                }
+                permit => permit,
+            },
+        };
+        match permit {
+            Ok(permit) => Ok(Some(permit)),
+            Err(TryAcquireError::NoPermits) => Ok(None),
+            Err(TryAcquireError::Closed) => Err(Error::PoolExhausted),
+        }
+    }
+    #[instrument(name = "pool_acquire", skip_all, fields(class = ?class))]
+    pub async fn acquire(&self, class: RequestClass) -> Result<PooledEngine<'_>> {
+        let permit = match self.try_permit(class)? {
+            Some(permit) => permit,
+            None => self.wait_for_permit(class).await?,
+        };
+        let slot = self.claim_slot().ok_or(Error::PoolExhausted)?;
+        let pooled = PooledEngine {
+            engine: Arc::clone(&slot.engine),
+            slot,
             permit,
             pool: self,
+        };
+        self.active_count.fetch_add(1, Ordering::SeqCst);
+        self.revive(&pooled.slot).await?;
+        self.reset_for_request(&pooled.slot).await?;
+        *pooled.slot.busy_since.lock().unwrap() = Some(Utc::now());
+        Ok(pooled)
+    }
+    async fn wait_for_permit(&self, class: RequestClass) -> Result<SemaphorePermit<'_>> {
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_queue {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            debug!("engine queue is full");
+            return Err(Error::EngineBusy);
+        }
+        let _waiting = Waiting(&self.waiting);
+        let permit = async {
+            match class {
+                RequestClass::Fast => tokio::select! {
+                    biased;
+                    permit = self.reserved.acquire() => permit,
+                    permit = self.semaphore.acquire() => permit,
+                },
+                RequestClass::Deep => self.semaphore.acquire().await,
+            }
+        };
+        match timeout(self.acquire_timeout, permit).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(Error::PoolExhausted),
+            Err(_) => {
+                debug!("timed out waiting for an engine");
+                Err(Error::EngineBusy)
+            }
+        }
+    }
+    async fn reset_for_request(&self, slot: &Slot) -> Result<()> {
+        if slot.abnormal.swap(false, Ordering::SeqCst) {
+            warn!("engine {} was released abnormally, restarting", slot.id);
+        } else {
+            match timeout(self.reset_timeout, slot.engine.reset_for_request()).await {
+                Ok(Ok(())) => {
+                    self.resets.fetch_add(1, Ordering::SeqCst);
+                    return Ok(());
+                }
+                Ok(Err(e)) => warn!("engine {} reset failed, restarting: {}", slot.id, e),
+                Err(_) => warn!("engine {} did not settle after reset, restarting", slot.id),
+            }
+        }
+        let mut state = slot.restart_state.lock().await;
+        let result = self.restart(slot, &mut state).await;
+        if result.is_err() {
+            slot.abnormal.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+    async fn revive(&self, slot: &Slot) -> Result<()> {
+        let mut state = slot.restart_state.lock().await;
+        if slot.engine.is_running().await {
+            return Ok(());
+        }
+        warn!("engine {} is dead, restarting", slot.id);
+        self.restart(slot, &mut state).await
+    }
+    async fn restart(&self, slot: &Slot, state: &mut RestartState) -> Result<()> {
+        if let Some(retry_at) = state.retry_at {
+            if Instant::now() < retry_at {
+                return Err(Error::Engine(format!(
+                    "engine {} is down, next restart in {}ms",
+                    slot.id,
+                    retry_at
+                        .saturating_duration_since(Instant::now())
+                        .as_millis()
+                )));
+            }
+        }
+        match slot.engine.restart().await {
+            Ok(()) => {
+                *state = RestartState::default();
+                slot.warmed.store(false, Ordering::SeqCst);
+                self.restarts.fetch_add(1, Ordering::SeqCst);
+                slot.restarts.fetch_add(1, Ordering::SeqCst);
+                info!("engine {} restarted", slot.id);
+                Ok(())
+            }
+            Err(e) => {
+                state.failures += 1;
+                let backoff = self
+                    .restart_backoff
+                    .saturating_mul(1 << (state.failures - 1).min(16))
+                    .min(MAX_RESTART_BACKOFF);
+                state.retry_at = Some(Instant::now() + backoff);
+                warn!("engine {} restart failed: {}", slot.id, e);
+                Err(e)
+            }
+        }
+    }
+    pub async fn probe(&self, limit: Duration) -> Result<()> {
+        if self.size() == 0 {
+            return Err(Error::PoolExhausted);
+        }
+        let _permit = match self.try_permit(RequestClass::Fast)? {
+            Some(permit) => permit,
+            None => return Ok(()),
+        };
+        let slot = self.claim_slot().ok_or(Error::PoolExhausted)?;
+        let result = timeout(limit, async {
+            self.revive(&slot).await?;
+            slot.engine.ensure_ready().await
+        })
+        .await
+        .unwrap_or_else(|_| {
+            slot.abnormal.store(true, Ordering::SeqCst);
+            Err(Error::Engine(format!(
+                "engine {} did not answer isready within {}ms",
+                slot.id,
+                limit.as_millis()
+            )))
+        });
+        slot.release();
+        result
+    }
+    pub async fn resize(&self, size: usize) -> Result<()> {
+        if size == 0 {
+            return Err(Error::InvalidArgument(
+                "engine pool needs at least one engine".to_string(),
+            ));
+        }
+        if size <= self.reserved_size {
+            return Err(Error::InvalidArgument(format!(
+                "engine pool needs more than the {} engines reserved for fast requests",
+                self.reserved_size
+            )));
+        }
+        let _resizing = self.resize_lock.lock().await;
+        let current = self.size();
+        for _ in current..size {
+            let engine = EngineHandle::new(
+                self.factory.spawn().await?,
+                self.options.clone(),
+                self.new_game_policy,
+            );
+            let id = self.next_slot_id.fetch_add(1, Ordering::SeqCst);
+            self.slots
+                .write()
+                .unwrap()
+                .push(Arc::new(Slot::new(id, engine)));
+            self.semaphore.add_permits(1);
+            info!("engine {} added to the pool", id);
+        }
+        for _ in size..current {
+            let permit = self
+                .semaphore
+                .acquire()
+                .await
+                .map_err(|_| Error::PoolExhausted)?;
+            let slot = self.claim_slot().ok_or(Error::PoolExhausted)?;
+            self.slots
+                .write()
+                .unwrap()
+                .retain(|other| !Arc::ptr_eq(other, &slot));
+            permit.forget();
+            let _ = slot.engine.quit().await;
+            info!("engine {} retired from the pool", slot.id);
+        }
+        Ok(())
+    }
+    pub fn spawn_reaper(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let pool = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            timer.tick().await;
+            loop {
+                timer.tick().await;
+                let Some(pool) = pool.upgrade() else {
+                    break;
+                };
+                for slot in pool.slots() {
+                    if let Err(e) = pool.revive(&slot).await {
+                        debug!("reaper could not revive engine {}: {}", slot.id, e);
+                    }
+                }
+            }
         })
     }
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::SeqCst)
+    }
+    pub fn resets(&self) -> u64 {
+        self.resets.load(Ordering::SeqCst)
+    }
+    pub fn warmed(&self) -> usize {
+        self.slots()
+            .iter()
+            .filter(|slot| slot.warmed.load(Ordering::SeqCst))
+            .count()
+    }
     pub fn available(&self) -> usize {
+        self.semaphore.available_permits() + self.reserved.available_permits()
+    }
+    pub fn reserved(&self) -> usize {
+        self.reserved_size
+    }
+    pub fn reserved_available(&self) -> usize {
+        self.reserved.available_permits()
+    }
+    pub fn general_available(&self) -> usize {
         self.semaphore.available_permits()
     }
     pub fn size(&self) -> usize {
-        self.engines.len()
+        self.slots.read().unwrap().len()
     }
     pub fn active(&self) -> usize {
         self.active_count.load(Ordering::SeqCst)
     }
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+    pub fn kind(&self) -> EngineKind {
+        self.kind
+    }
+    pub fn engine_name(&self) -> Option<String> {
+        self.slots().iter().find_map(|slot| slot.engine.name())
+    }
+    pub fn status(&self) -> EnginePoolStatus {
+        let slots = self.slots();
+        EnginePoolStatus {
+            size: slots.len(),
+            available: self.available(),
+            reserved: self.reserved(),
+            reserved_available: self.reserved_available(),
+            general_available: self.general_available(),
+            active: self.active(),
+            queue_depth: self.queue_depth(),
+            engines: slots
+                .iter()
+                .enumerate()
+                .map(|(index, slot)| EngineSlotStatus {
+                    index,
+                    name: slot.engine.name(),
+                    busy_since: *slot.busy_since.lock().unwrap(),
+                    last_command: slot.engine.last_command(),
+                    restarts: slot.restarts.load(Ordering::SeqCst),
+                    warmed: slot.warmed.load(Ordering::SeqCst),
+                })
+                .collect(),
+            engine_name: slots.iter().find_map(|slot| slot.engine.name()),
+        }
+    }
     pub async fn shutdown(&self) -> Result<()> {
         info!("shutting down engine pool");
-        for engine in &self.engines {
-            let _ = engine.quit().await;
+        for slot in self.slots() {
+            let _ = slot.engine.quit().await;
         }
         Ok(())
     }
 }
+struct Waiting<'a>(&'a AtomicUsize);
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 pub struct PooledEngine<'a> {
-    engine: Arc<StockfishEngine>,
+    engine: Arc<EngineHandle>,
+    slot: Arc<Slot>,
     #[allow(dead_code)]
     permit: SemaphorePermit<'a>,
     pool: &'a EnginePool,
 }
 impl<'a> PooledEngine<'a> {
-    pub fn engine(&self) -> &StockfishEngine {
-        &self.engine
+    pub fn engine(&self) -> &EngineHandle {
+        self.engine.as_ref()
+    }
+    pub fn mark_abnormal(&self) {
+        self.slot.abnormal.store(true, Ordering::SeqCst);
     }
 }
 impl Drop for PooledEngine<'_> {
     fn drop(&mut self) {
         self.pool.active_count.fetch_sub(1, Ordering::SeqCst);
+        *self.slot.busy_since.lock().unwrap() = None;
+        self.slot.release();
+    }
+}
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    fn engine_script(dir: &std::path::Path) -> String {
+        let script = dir.join("engine.sh");
+        let body = r#"#!/bin/sh
+while IFS= read -r line; do
+  case "$line" in
+    uci) echo "uciok" ;;
+    isready) echo "readyok" ;;
+    go*) echo "info depth 1 score cp 12 nodes 20 pv e2e4"; echo "bestmove e2e4" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#;
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script.display().to_string()
+    }
+    fn recording_script(dir: &std::path::Path) -> (String, std::path::PathBuf) {
+        let log = dir.join("commands.log");
+        let script = dir.join("recording.sh");
+        let body = format!(
+            r#"#!/bin/sh
+while IFS= read -r line; do
+  echo "$line" >> "{}"
+  case "$line" in
+    uci) echo "uciok" ;;
+    isready) [ -z "$hung" ] && echo "readyok" ;;
+    "go infinite") hung=1 ;;
+    go*) echo "info depth 1 score cp 12 nodes 20 pv e2e4"; echo "bestmove e2e4" ;;
+    quit) exit 0 ;;
+  esac
+done
+"#,
+            log.display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (script.display().to_string(), log)
+    }
+    fn logged_commands(log: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(log)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+    async fn recording_pool(dir: &std::path::Path) -> (EnginePool, std::path::PathBuf) {
+        recording_pool_with(dir, EnginePoolConfig::default()).await
+    }
+    async fn recording_pool_with(
+        dir: &std::path::Path,
+        config: EnginePoolConfig,
+    ) -> (EnginePool, std::path::PathBuf) {
+        let (script, log) = recording_script(dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script,
+            pool_size: 1,
+            reset_timeout: Duration::from_millis(200),
+            ..config
+        })
+        .await
+        .unwrap();
+        (pool, log)
+    }
+    #[tokio::test]
+    async fn test_engine_reset_between_requests() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, log) = recording_pool(&dir).await;
+        {
+            let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+            let engine = pooled.engine();
+            engine.set_multipv(3).await.unwrap();
+            engine.go_depth(1).await.unwrap();
+            while !engine.read_line().await.unwrap().starts_with("bestmove") {}
+        }
+        drop(pool.acquire(RequestClass::Deep).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let reset = [
+            "stop",
+            "isready",
+            "ucinewgame",
+            "setoption name MultiPV value 1",
+            "isready",
+        ];
+        let mut expected = vec!["uci", "isready"];
+        expected.extend(reset);
+        expected.extend(["setoption name MultiPV value 3", "go depth 1"]);
+        expected.extend(reset);
+        assert_eq!(logged_commands(&log), expected);
+        assert_eq!(pool.resets(), 2);
+        assert_eq!(pool.restarts(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_warm_up_runs_before_first_request() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = EnginePoolConfig {
+            warmup_depth: 8,
+            ..Default::default()
+        };
+        let (pool, log) = recording_pool_with(&dir, config).await;
+        assert_eq!(
+            logged_commands(&log),
+            ["uci", "isready", "position startpos", "go depth 8"]
+        );
+        assert_eq!(pool.warmed(), 1);
+        assert!(pool.status().engines[0].warmed);
+        pool.acquire(RequestClass::Deep)
+            .await
+            .unwrap()
+            .mark_abnormal();
+        drop(pool.acquire(RequestClass::Deep).await.unwrap());
+        assert!(!pool.status().engines[0].warmed);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_new_game_policy_controls_hash_clearing() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let other = "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1";
+        let moves = ["e2e4".to_string()];
+        for (policy, expected) in [
+            (NewGamePolicy::Always, 3),
+            (NewGamePolicy::PerPositionChange, 1),
+            (NewGamePolicy::Never, 0),
+        ] {
+            let config = EnginePoolConfig {
+                new_game_policy: policy,
+                ..Default::default()
+            };
+            let (pool, log) = recording_pool_with(&dir, config).await;
+            for (fen, moves) in [
+                ("startpos", &[][..]),
+                ("startpos", &moves[..]),
+                (other, &[]),
+            ] {
+                let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+                pooled
+                    .engine()
+                    .set_position_with_moves(fen, moves)
+                    .await
+                    .unwrap();
+                pooled.engine().ensure_ready().await.unwrap();
+            }
+            let commands = logged_commands(&log);
+            let cleared = commands.iter().filter(|c| *c == "ucinewgame").count();
+            assert_eq!(cleared, expected, "{:?}", policy);
+            std::fs::remove_file(&log).unwrap();
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_abnormal_release_restarts_engine() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, log) = recording_pool(&dir).await;
+        pool.acquire(RequestClass::Deep)
+            .await
+            .unwrap()
+            .mark_abnormal();
+        let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+        pooled.engine().ensure_ready().await.unwrap();
+        drop(pooled);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let commands = logged_commands(&log);
+        assert_eq!(commands.iter().filter(|c| *c == "uci").count(), 2);
+        assert_eq!(commands.iter().filter(|c| *c == "ucinewgame").count(), 1);
+        assert_eq!(pool.restarts(), 1);
+        assert_eq!(pool.resets(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_status_reports_engine_slots() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, _) = recording_pool(&dir).await;
+        let status = pool.status();
+        assert_eq!((status.size, status.available, status.active), (1, 1, 0));
+        assert!(status.engines[0].busy_since.is_none());
+        assert_eq!(status.engines[0].last_command.as_deref(), None);
+        pool.acquire(RequestClass::Deep)
+            .await
+            .unwrap()
+            .mark_abnormal();
+        let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+        pooled.engine().go_depth(1).await.unwrap();
+        let status = pool.status();
+        assert_eq!((status.available, status.active), (0, 1));
+        assert!(status.engines[0].busy_since.is_some());
+        assert_eq!(
+            status.engines[0].last_command.as_deref(),
+            Some("go depth 1")
+        );
+        assert_eq!(status.engines[0].restarts, 1);
+        drop(pooled);
+        assert!(pool.status().engines[0].busy_since.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_resize_waits_for_busy_engines() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, _) = recording_pool(&dir).await;
+        let pool = Arc::new(pool);
+        pool.resize(3).await.unwrap();
+        assert_eq!((pool.size(), pool.available()), (3, 3));
+        let held: Vec<_> = vec![
+            pool.acquire(RequestClass::Deep).await.unwrap(),
+            pool.acquire(RequestClass::Deep).await.unwrap(),
+        ];
+        let shrink = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move { pool.resize(1).await }
+        });
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.size() != 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!shrink.is_finished());
+        for pooled in &held {
+            assert!(pooled.engine().is_running().await);
+        }
+        drop(held);
+        shrink.await.unwrap().unwrap();
+        assert_eq!((pool.size(), pool.available()), (1, 1));
+        pool.acquire(RequestClass::Deep)
+            .await
+            .unwrap()
+            .engine()
+            .ensure_ready()
+            .await
+            .unwrap();
+        assert!(pool.resize(0).await.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_unsettled_engine_restarted_on_acquire() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, _) = recording_pool(&dir).await;
+        pool.acquire(RequestClass::Deep)
+            .await
+            .unwrap()
+            .engine()
+            .send_command("go infinite")
+            .await
+            .unwrap();
+        let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+        pooled.engine().ensure_ready().await.unwrap();
+        assert_eq!(pool.restarts(), 1);
+        assert_eq!(pool.resets(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_probe_detects_hung_engine() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pool, _) = recording_pool(&dir).await;
+        pool.probe(Duration::from_millis(200)).await.unwrap();
+        let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+        pooled.engine().send_command("go infinite").await.unwrap();
+        pool.probe(Duration::from_millis(200)).await.unwrap();
+        drop(pooled);
+        assert!(pool.probe(Duration::from_millis(200)).await.is_err());
+        pool.acquire(RequestClass::Deep).await.unwrap();
+        assert_eq!(pool.restarts(), 1);
+        pool.probe(Duration::from_millis(200)).await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_waiters_served_in_order_and_time_out() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = Arc::new(
+            EnginePool::new(EnginePoolConfig {
+                binary_path: engine_script(&dir),
+                pool_size: 1,
+                acquire_timeout: Duration::from_millis(200),
+                max_queue: 3,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let held = pool.acquire(RequestClass::Deep).await.unwrap();
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for id in 0..3 {
+            waiters.push({
+                let pool = pool.clone();
+                let order = order.clone();
+                tokio::spawn(async move {
+                    let _pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+                    order.lock().unwrap().push(id);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                })
+            });
+            while pool.queue_depth() <= id {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert!(matches!(
+            pool.acquire(RequestClass::Deep).await,
+            Err(Error::EngineBusy)
+        ));
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        assert_eq!(pool.queue_depth(), 0);
+        let _held = pool.acquire(RequestClass::Deep).await.unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            pool.acquire(RequestClass::Deep).await,
+            Err(Error::EngineBusy)
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(pool.queue_depth(), 0);
+        assert_eq!(pool.available(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_reserved_engine_serves_fast_requests() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = Arc::new(
+            EnginePool::new(EnginePoolConfig {
+                binary_path: engine_script(&dir),
+                pool_size: 2,
+                reserved_engines: 1,
+                fast_movetime_threshold: Duration::from_millis(500),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        assert_eq!(pool.classify(Some(200)), RequestClass::Fast);
+        assert_eq!(pool.classify(Some(5000)), RequestClass::Deep);
+        assert_eq!(pool.classify(None), RequestClass::Deep);
+        let deep = pool.acquire(RequestClass::Deep).await.unwrap();
+        let status = pool.status();
+        assert_eq!(
+            (
+                status.reserved,
+                status.reserved_available,
+                status.general_available
+            ),
+            (1, 1, 0)
+        );
+        let waiting = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move { pool.acquire(RequestClass::Deep).await.is_ok() }
+        });
+        while pool.queue_depth() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let started = Instant::now();
+        let fast = pool.acquire(RequestClass::Fast).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+        drop(fast);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(deep);
+        assert!(waiting.await.unwrap());
+        let deep = pool.acquire(RequestClass::Deep).await.unwrap();
+        let fast = pool.acquire(RequestClass::Fast).await.unwrap();
+        assert_eq!(pool.available(), 0);
+        drop(deep);
+        let borrowed = pool.acquire(RequestClass::Fast).await.unwrap();
+        assert_eq!(pool.general_available(), 0);
+        drop((fast, borrowed));
+        assert!(pool.resize(1).await.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_deep_requests_keep_one_reserved_engine() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: engine_script(&dir),
+            pool_size: 4,
+            reserved_fraction: Some(0.5),
+            acquire_timeout: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(pool.reserved(), 2);
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            held.push(pool.acquire(RequestClass::Deep).await.unwrap());
+        }
+        assert_eq!(pool.reserved_available(), 1);
+        assert!(matches!(
+            pool.acquire(RequestClass::Deep).await,
+            Err(Error::EngineBusy)
+        ));
+        held.push(pool.acquire(RequestClass::Fast).await.unwrap());
+        assert_eq!(pool.available(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_analyze_after_engine_killed() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = Arc::new(
+            EnginePool::new(EnginePoolConfig {
+                binary_path: engine_script(&dir),
+                pool_size: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        pool.slots()[0].engine.kill().await.unwrap();
+        let service = crate::AnalysisService::new(pool.clone());
+        let request =
+            ironfish_core::AnalysisRequest::new(ironfish_core::ChessPosition::starting().fen)
+                .with_depth(1);
+        let result = service.analyze(request).await.unwrap();
+        assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+        assert_eq!(pool.restarts(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_reaper_restarts_dead_engine() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = Arc::new(
+            EnginePool::new(EnginePoolConfig {
+                binary_path: engine_script(&dir),
+                pool_size: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let reaper = pool.spawn_reaper(Duration::from_millis(20));
+        pool.slots()[0].engine.kill().await.unwrap();
+        for _ in 0..100 {
+            if pool.restarts() > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(pool.restarts(), 1);
+        assert!(pool.slots()[0].engine.is_running().await);
+        reaper.abort();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[tokio::test]
+    async fn test_failed_restart_backs_off() {
+        let dir = std::env::temp_dir().join(format!("ironfish-pool-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = engine_script(&dir);
+        let pool = EnginePool::new(EnginePoolConfig {
+            binary_path: script.clone(),
+            pool_size: 1,
+            restart_backoff: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        pool.slots()[0].engine.kill().await.unwrap();
+        std::fs::remove_file(&script).unwrap();
+        assert!(matches!(
+            pool.acquire(RequestClass::Deep).await,
+            Err(Error::Engine(_))
+        ));
+        match pool.acquire(RequestClass::Deep).await {
+            Err(Error::Engine(message)) => assert!(message.contains("next restart")),
+            _ => panic!("restart should be backing off"),
+        }
+        assert_eq!(pool.restarts(), 0);
+        assert_eq!(pool.available(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    #[derive(Default)]
+    struct MemoryEngine {
+        output: std::sync::Mutex<std::collections::VecDeque<String>>,
+        commands: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+    #[async_trait]
+    impl UciEngine for MemoryEngine {
+        async fn send_command(&self, cmd: &str) -> Result<()> {
+            self.commands.lock().unwrap().push(cmd.to_string());
+            let mut output = self.output.lock().unwrap();
+            match cmd {
+                "isready" => output.push_back("readyok".to_string()),
+                cmd if cmd.starts_with("go") => {
+                    output.push_back("info depth 1 score cp 30 nodes 10 pv d2d4".to_string());
+                    output.push_back("bestmove d2d4".to_string());
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        async fn read_line(&self) -> Result<String> {
+            self.output
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or_else(|| Error::Engine("engine closed its output".into()))
+        }
+        async fn restart(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn kill(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn is_running(&self) -> bool {
+            true
+        }
+        fn name(&self) -> Option<String> {
+            Some("Memory Engine".to_string())
+        }
+    }
+    struct MemoryFactory(Arc<std::sync::Mutex<Vec<String>>>);
+    #[async_trait]
+    impl EngineFactory for MemoryFactory {
+        async fn spawn(&self) -> Result<Arc<dyn UciEngine>> {
+            Ok(Arc::new(MemoryEngine {
+                commands: Arc::clone(&self.0),
+                ..Default::default()
+            }))
+        }
+    }
+    #[tokio::test]
+    async fn test_pool_drives_engines_through_the_trait() {
+        let commands = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let config = EnginePoolConfig {
+            kind: EngineKind::Uci,
+            pool_size: 1,
+            new_game_policy: NewGamePolicy::Never,
+            ..Default::default()
+        };
+        let pool = EnginePool::with_factory(config, MemoryFactory(Arc::clone(&commands)))
+            .await
+            .unwrap();
+        assert_eq!(pool.kind(), EngineKind::Uci);
+        {
+            let pooled = pool.acquire(RequestClass::Deep).await.unwrap();
+            let engine = pooled.engine();
+            engine.set_position("startpos").await.unwrap();
+            engine.go_depth(1).await.unwrap();
+            while !engine.read_line().await.unwrap().starts_with("bestmove") {}
+        }
+        assert_eq!(
+            commands.lock().unwrap().as_slice(),
+            [
+                "stop",
+                "isready",
+                "setoption name MultiPV value 1",
+                "isready",
+                "position startpos",
+                "go depth 1",
+            ]
+        );
+        let status = pool.status();
+        assert_eq!(
+            status.engines[0].last_command.as_deref(),
+            Some("go depth 1")
+        );
+        assert_eq!(status.engine_name.as_deref(), Some("Memory Engine"));
+        assert_eq!(status.engines[0].name.as_deref(), Some("Memory Engine"));
+        assert_eq!(pool.engine_name().as_deref(), Some("Memory Engine"));
     }
 }
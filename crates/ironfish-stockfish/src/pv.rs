@@ -0,0 +1,249 @@
+use crate::engine::UciInfo;
+use ironfish_core::{Move, PrincipalVariation, Score, ScoreHistoryEntry, Wdl, MAX_SCORE_HISTORY};
+use std::collections::HashMap;
+#[derive(Debug, Default)]
+pub(crate) struct PvLines {
+    lines: HashMap<u8, UciInfo>,
+    depth: Option<u8>,
+    nodes: Option<u64>,
+    time: Option<u64>,
+    tbhits: Option<u64>,
+    terminal: Option<Score>,
+    history: Vec<ScoreHistoryEntry>,
+}
+impl PvLines {
+    pub(crate) fn record(&mut self, info: &UciInfo) {
+        self.nodes = self.nodes.max(info.nodes);
+        self.time = self.time.max(info.time);
+        self.tbhits = self.tbhits.max(info.tbhits);
+        if info.pv.is_empty() {
+            if info.score_mate.is_some() || info.score_cp.is_some() {
+                self.terminal = Some(evaluation(info));
+            }
+            return;
+        }
+        let rank = info.multipv.unwrap_or(1);
+        if self.lines.get(&rank).is_some_and(|kept| {
+            kept.depth > info.depth || (info.bound.is_some() && kept.bound.is_none())
+        }) {
+            return;
+        }
+        if rank == 1 {
+            self.depth = self.depth.max(info.depth);
+            self.record_history(info);
+        }
+        self.lines.insert(rank, info.clone());
+    }
+    fn record_history(&mut self, info: &UciInfo) {
+        let Some(depth) = info.depth.filter(|_| info.bound.is_none()) else {
+            return;
+        };
+        let entry = ScoreHistoryEntry {
+            depth,
+            evaluation: evaluation(info),
+            nodes: info.nodes.unwrap_or(0),
+            time_ms: info.time.unwrap_or(0),
+        };
+        match self.history.last_mut() {
+            Some(last) if last.depth == depth => *last = entry,
+            Some(last) if last.depth > depth => {}
+            _ => {
+                if self.history.len() == MAX_SCORE_HISTORY {
+                    self.history.remove(0);
+                }
+                self.history.push(entry);
+            }
+        }
+    }
+    pub(crate) fn score_history(&self) -> Vec<ScoreHistoryEntry> {
+        self.history.clone()
+    }
+    pub(crate) fn depth_reached(&self) -> Option<u8> {
+        self.depth
+    }
+    pub(crate) fn evaluation(&self) -> Score {
+        self.lines
+            .get(&1)
+            .map(evaluation)
+            .or(self.terminal)
+            .unwrap_or(Score::Centipawns(0))
+    }
+    pub(crate) fn wdl(&self) -> Option<Wdl> {
+        self.lines.get(&1).and_then(wdl)
+    }
+    pub(crate) fn summary(&self) -> UciInfo {
+        UciInfo {
+            depth: self.depth,
+            nodes: self.nodes,
+            time: self.time,
+            tbhits: self.tbhits,
+            ..Default::default()
+        }
+    }
+    pub(crate) fn variations(&self) -> Vec<PrincipalVariation> {
+        let mut variations: Vec<PrincipalVariation> = self
+            .lines
+            .iter()
+            .map(|(rank, info)| PrincipalVariation {
+                rank: *rank,
+                moves: info.pv.iter().filter_map(|m| Move::from_uci(m)).collect(),
+                evaluation: evaluation(info),
+                depth: info.depth.unwrap_or(0),
+                seldepth: info.seldepth,
+                nodes: info.nodes,
+                san: None,
+            })
+            .collect();
+        variations.sort_by_key(|pv| pv.rank);
+        variations
+    }
+}
+fn evaluation(info: &UciInfo) -> Score {
+    match info.score_mate {
+        Some(mate) => Score::MateIn(mate),
+        None => Score::Centipawns(info.score_cp.unwrap_or(0)),
+    }
+}
+pub(crate) fn wdl(info: &UciInfo) -> Option<Wdl> {
+    info.wdl.map(|(win, draw, loss)| Wdl { win, draw, loss })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn replay(lines: &[&str]) -> PvLines {
+        let mut pvs = PvLines::default();
+        for line in lines {
+            pvs.record(&UciInfo::parse(line).unwrap());
+        }
+        pvs
+    }
+    #[test]
+    fn test_shallower_research_does_not_overwrite() {
+        let pvs = replay(&[
+            "info depth 18 seldepth 24 multipv 1 score cp 35 nodes 900000 pv e2e4 e7e5",
+            "info depth 18 seldepth 22 multipv 2 score cp 20 nodes 900000 pv d2d4 d7d5",
+            "info depth 19 seldepth 27 multipv 1 score cp 41 nodes 1500000 pv e2e4 c7c5",
+            "info depth 17 seldepth 21 multipv 2 score cp 5 nodes 1600000 pv c2c4",
+        ]);
+        let variations = pvs.variations();
+        assert_eq!(variations.len(), 2);
+        assert_eq!(variations[0].depth, 19);
+        assert_eq!(variations[0].seldepth, Some(27));
+        assert_eq!(variations[0].nodes, Some(1500000));
+        assert_eq!(variations[0].moves[1].to_uci(), "c7c5");
+        assert_eq!(variations[1].depth, 18);
+        assert_eq!(variations[1].seldepth, Some(22));
+        assert_eq!(variations[1].moves[0].to_uci(), "d2d4");
+        assert_eq!(variations[1].evaluation.value(), 20);
+        assert_eq!(pvs.evaluation(), Score::Centipawns(41));
+    }
+    #[test]
+    fn test_terminal_score_without_pv() {
+        let pvs = replay(&["info depth 0 score mate 0"]);
+        assert!(pvs.variations().is_empty());
+        assert_eq!(pvs.evaluation(), Score::MateIn(0));
+        let pvs = replay(&[
+            "info depth 0 score cp 0",
+            "info depth 10 multipv 1 score cp 30 nodes 900 pv e2e4",
+        ]);
+        assert_eq!(pvs.evaluation(), Score::Centipawns(30));
+    }
+    #[test]
+    fn test_equal_depth_keeps_latest_line() {
+        let pvs = replay(&[
+            "info depth 12 multipv 1 score cp 10 nodes 5000 pv g1f3",
+            "info depth 12 multipv 1 score cp 25 nodes 7000 pv e2e4",
+        ]);
+        let variations = pvs.variations();
+        assert_eq!(variations[0].moves[0].to_uci(), "e2e4");
+        assert_eq!(pvs.evaluation(), Score::Centipawns(25));
+    }
+    #[test]
+    fn test_bound_scores_do_not_replace_exact_lines() {
+        let pvs = replay(&[
+            "info depth 20 multipv 1 score cp 30 wdl 90 880 30 nodes 1000 pv e2e4 e7e5",
+            "info depth 21 multipv 1 score cp 55 lowerbound wdl 200 790 10 nodes 2000 pv e2e4",
+            "info depth 21 multipv 2 score cp -80 upperbound nodes 2100 pv a2a3",
+        ]);
+        assert_eq!(pvs.evaluation(), Score::Centipawns(30));
+        assert_eq!(pvs.depth_reached(), Some(20));
+        assert_eq!(
+            pvs.wdl(),
+            Some(Wdl {
+                win: 90,
+                draw: 880,
+                loss: 30
+            })
+        );
+        assert_eq!(pvs.variations()[1].evaluation.value(), -80);
+        let pvs = replay(&[
+            "info depth 21 multipv 1 score cp 55 lowerbound nodes 2000 pv e2e4",
+            "info depth 21 multipv 1 score cp 41 nodes 2500 pv e2e4 c7c5",
+        ]);
+        assert_eq!(pvs.evaluation(), Score::Centipawns(41));
+        assert_eq!(pvs.variations()[0].moves.len(), 2);
+    }
+    #[test]
+    fn test_score_history_keeps_one_exact_entry_per_depth() {
+        let pvs = replay(&[
+            "info depth 1 multipv 1 score cp 12 nodes 20 time 1 pv e2e4",
+            "info depth 1 multipv 2 score cp 5 nodes 25 time 1 pv d2d4",
+            "info depth 2 multipv 1 score cp 40 lowerbound nodes 80 time 2 pv e2e4",
+            "info depth 2 multipv 1 score cp 31 nodes 120 time 3 pv e2e4 e7e5",
+            "info depth 2 multipv 1 score cp 28 nodes 150 time 4 pv g1f3",
+            "info depth 3 currmove d2d4 currmovenumber 2",
+            "info depth 3 multipv 1 score mate 5 nodes 900 time 9 pv e2e4",
+        ]);
+        let history = pvs.score_history();
+        assert_eq!(
+            history,
+            vec![
+                ScoreHistoryEntry {
+                    depth: 1,
+                    evaluation: Score::Centipawns(12),
+                    nodes: 20,
+                    time_ms: 1,
+                },
+                ScoreHistoryEntry {
+                    depth: 2,
+                    evaluation: Score::Centipawns(28),
+                    nodes: 150,
+                    time_ms: 4,
+                },
+                ScoreHistoryEntry {
+                    depth: 3,
+                    evaluation: Score::MateIn(5),
+                    nodes: 900,
+                    time_ms: 9,
+                },
+            ]
+        );
+    }
+    #[test]
+    fn test_score_history_is_monotone_and_capped() {
+        let mut pvs = PvLines::default();
+        for depth in (1..=u8::MAX).chain([3, 7]) {
+            let line = format!("info depth {} multipv 1 score cp 10 nodes 1 pv e2e4", depth);
+            pvs.record(&UciInfo::parse(&line).unwrap());
+        }
+        let history = pvs.score_history();
+        assert_eq!(history.len(), MAX_SCORE_HISTORY);
+        assert!(history.windows(2).all(|w| w[0].depth < w[1].depth));
+        assert_eq!(history.last().unwrap().depth, u8::MAX);
+    }
+    #[test]
+    fn test_depth_reached_ignores_currmove_lines() {
+        let pvs = replay(&[
+            "info depth 20 seldepth 30 multipv 1 score cp 30 nodes 4000000 time 900 pv e2e4",
+            "info depth 21 currmove d2d4 currmovenumber 2",
+            "info depth 9 seldepth 12 multipv 2 score cp 3 nodes 4100000 pv d2d4",
+            "info nodes 4200000 time 1000 nps 4200000",
+        ]);
+        assert_eq!(pvs.depth_reached(), Some(20));
+        let summary = pvs.summary();
+        assert_eq!(summary.depth, Some(20));
+        assert_eq!(summary.nodes, Some(4200000));
+        assert_eq!(summary.time, Some(1000));
+        assert_eq!(PvLines::default().depth_reached(), None);
+    }
+}
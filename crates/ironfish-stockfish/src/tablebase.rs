@@ -0,0 +1,113 @@
+use ironfish_core::{ChessPosition, TABLEBASE_MAX_PIECES};
+#[cfg(feature = "tablebase-probe")]
+use ironfish_core::{Move, TablebaseResult};
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::warn;
+const PIECE_ORDER: [char; 6] = ['K', 'Q', 'R', 'B', 'N', 'P'];
+#[derive(Debug, Clone, Default)]
+pub struct SyzygyTables {
+    tables: HashSet<String>,
+}
+impl SyzygyTables {
+    pub fn scan(path: &str) -> Self {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut tables = HashSet::new();
+        for dir in path.split(separator).filter(|dir| !dir.is_empty()) {
+            let entries = match std::fs::read_dir(Path::new(dir)) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("cannot read syzygy tablebase directory {}: {}", dir, e);
+                    continue;
+                }
+            };
+            tables.extend(entries.filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_suffix(".rtbw").map(str::to_string)
+            }));
+        }
+        Self { tables }
+    }
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            tables: names.into_iter().map(Into::into).collect(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+    pub fn covers(&self, position: &ChessPosition) -> bool {
+        if self.tables.is_empty()
+            || position.castling() != "-"
+            || position.piece_count() > TABLEBASE_MAX_PIECES
+        {
+            return false;
+        }
+        let Some(placement) = position.fen.split_whitespace().next() else {
+            return false;
+        };
+        let white = material(placement, char::is_ascii_uppercase);
+        let black = material(placement, char::is_ascii_lowercase);
+        self.tables.contains(&format!("{}v{}", white, black))
+            || self.tables.contains(&format!("{}v{}", black, white))
+    }
+}
+fn material(placement: &str, side: fn(&char) -> bool) -> String {
+    let pieces: Vec<char> = placement
+        .chars()
+        .filter(side)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    PIECE_ORDER
+        .iter()
+        .flat_map(|kind| pieces.iter().filter(move |piece| *piece == kind))
+        .collect()
+}
+#[cfg(feature = "tablebase-probe")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TablebaseProbeResult {
+    pub tablebase: TablebaseResult,
+    pub best_move: Option<Move>,
+}
+#[cfg(feature = "tablebase-probe")]
+pub trait TablebaseProbe: Send + Sync {
+    fn probe(&self, position: &ChessPosition) -> Option<TablebaseProbeResult>;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_tables_cover_matching_material() {
+        let tables = SyzygyTables::from_names(["KPvK", "KRvKN"]);
+        assert!(tables.covers(&ChessPosition::new("8/8/8/8/8/3k4/3P4/3K4 b - - 0 1")));
+        assert!(tables.covers(&ChessPosition::new("8/8/8/8/8/3K4/3p4/3k4 w - - 0 1")));
+        assert!(tables.covers(&ChessPosition::new("8/8/8/8/2n5/3k4/8/R2K4 w - - 0 1")));
+        assert!(!tables.covers(&ChessPosition::new("8/8/8/8/8/3k4/3Q4/3K4 b - - 0 1")));
+        assert!(!tables.covers(&ChessPosition::new("4k3/8/8/8/8/8/3P4/R3K3 w Q - 0 1")));
+        assert!(!tables.covers(&ChessPosition::starting()));
+        assert!(
+            !SyzygyTables::default().covers(&ChessPosition::new("8/8/8/8/8/3k4/3P4/3K4 b - - 0 1"))
+        );
+    }
+    #[test]
+    fn test_scan_reads_wdl_tables_and_skips_missing_directories() {
+        let dir = std::env::temp_dir().join(format!("ironfish-syzygy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["KPvK.rtbw", "KPvK.rtbz", "KQvK.rtbz", "README"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let path = format!("{}{}/does/not/exist", dir.display(), separator);
+        let tables = SyzygyTables::scan(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tables.len(), 1);
+        assert!(tables.covers(&ChessPosition::new("8/8/8/8/8/3k4/3P4/3K4 b - - 0 1")));
+    }
+}
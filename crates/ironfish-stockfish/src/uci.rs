@@ -0,0 +1,202 @@
+use crate::limits::SearchLimits;
+use crate::pool::NewGamePolicy;
+use async_trait::async_trait;
+use ironfish_core::{ChessPosition, Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+fn position_command(fen: &str) -> String {
+    if fen == "startpos" || fen == ChessPosition::starting().fen {
+        "position startpos".to_string()
+    } else {
+        format!("position fen {}", fen)
+    }
+}
+fn with_moves(mut cmd: String, moves: &[String]) -> String {
+    if !moves.is_empty() {
+        cmd.push_str(" moves ");
+        cmd.push_str(&moves.join(" "));
+    }
+    cmd
+}
+#[async_trait]
+pub trait UciEngine: Send + Sync {
+    async fn send_command(&self, cmd: &str) -> Result<()>;
+    async fn read_line(&self) -> Result<String>;
+    async fn restart(&self) -> Result<()>;
+    async fn kill(&self) -> Result<()>;
+    async fn is_running(&self) -> bool;
+    fn name(&self) -> Option<String>;
+    async fn wait_for(&self, expected: &str) -> Result<()> {
+        loop {
+            let line = self.read_line().await?;
+            if line.trim().starts_with(expected) {
+                return Ok(());
+            }
+        }
+    }
+    async fn set_position(&self, fen: &str) -> Result<()> {
+        self.set_position_with_moves(fen, &[]).await
+    }
+    async fn set_position_with_moves(&self, fen: &str, moves: &[String]) -> Result<()> {
+        self.send_command(&with_moves(position_command(fen), moves))
+            .await
+    }
+    async fn go_depth(&self, depth: u8) -> Result<()> {
+        self.send_command(&format!("go depth {}", depth)).await
+    }
+    async fn go_movetime(&self, ms: u64) -> Result<()> {
+        self.send_command(&format!("go movetime {}", ms)).await
+    }
+    async fn go_infinite(&self) -> Result<()> {
+        self.send_command("go infinite").await
+    }
+    async fn go(&self, limits: &SearchLimits) -> Result<()> {
+        self.send_command(&limits.to_go_command()).await
+    }
+    async fn go_ponder(&self, limits: &SearchLimits) -> Result<()> {
+        let command = limits.to_go_command();
+        let command = command.strip_prefix("go").unwrap_or(&command);
+        self.send_command(&format!("go ponder{}", command)).await
+    }
+    async fn ponderhit(&self) -> Result<()> {
+        self.send_command("ponderhit").await
+    }
+    async fn set_option(&self, name: &str, value: &str) -> Result<()> {
+        self.send_command(&format!("setoption name {} value {}", name, value))
+            .await?;
+        self.send_command("isready").await?;
+        let mut unknown = false;
+        loop {
+            let line = self.read_line().await?;
+            let line = line.trim();
+            if line.starts_with("No such option") {
+                unknown = true;
+            } else if line.starts_with("readyok") {
+                break;
+            }
+        }
+        if unknown {
+            return Err(Error::Engine(format!("unknown engine option: {}", name)));
+        }
+        Ok(())
+    }
+    async fn set_multipv(&self, n: u8) -> Result<()> {
+        self.send_command(&format!("setoption name MultiPV value {}", n))
+            .await
+    }
+    async fn set_chess960(&self, enabled: bool) -> Result<()> {
+        self.send_command(&format!("setoption name UCI_Chess960 value {}", enabled))
+            .await
+    }
+    async fn stop(&self) -> Result<()> {
+        self.send_command("stop").await
+    }
+    async fn quit(&self) -> Result<()> {
+        self.send_command("quit").await
+    }
+    async fn ensure_ready(&self) -> Result<()> {
+        self.send_command("isready").await?;
+        self.wait_for("readyok").await
+    }
+    async fn drain(&self) -> Result<()> {
+        self.send_command("stop").await?;
+        self.ensure_ready().await
+    }
+    async fn new_game(&self) -> Result<()> {
+        self.send_command("ucinewgame").await
+    }
+    async fn warm_up(&self, depth: u8) -> Result<()> {
+        self.set_position("startpos").await?;
+        self.go_depth(depth).await?;
+        self.wait_for("bestmove").await
+    }
+}
+pub struct EngineHandle {
+    engine: Arc<dyn UciEngine>,
+    options: Vec<(String, String)>,
+    new_game_policy: NewGamePolicy,
+    ready: AtomicBool,
+    last_command: Mutex<Option<String>>,
+    root: Mutex<Option<String>>,
+}
+impl EngineHandle {
+    pub fn new(
+        engine: Arc<dyn UciEngine>,
+        options: Vec<(String, String)>,
+        new_game_policy: NewGamePolicy,
+    ) -> Self {
+        Self {
+            engine,
+            options,
+            new_game_policy,
+            ready: AtomicBool::new(true),
+            last_command: Mutex::new(None),
+            root: Mutex::new(None),
+        }
+    }
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+    pub fn last_command(&self) -> Option<String> {
+        self.last_command.lock().unwrap().clone()
+    }
+    pub fn configured_option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn new_game_policy(&self) -> NewGamePolicy {
+        self.new_game_policy
+    }
+    pub async fn reset_for_request(&self) -> Result<()> {
+        self.drain().await?;
+        if self.new_game_policy == NewGamePolicy::Always {
+            self.new_game().await?;
+        }
+        self.set_multipv(1).await?;
+        self.ensure_ready().await
+    }
+}
+#[async_trait]
+impl UciEngine for EngineHandle {
+    async fn send_command(&self, cmd: &str) -> Result<()> {
+        *self.last_command.lock().unwrap() = Some(cmd.to_string());
+        self.engine.send_command(cmd).await
+    }
+    async fn read_line(&self) -> Result<String> {
+        self.engine.read_line().await
+    }
+    async fn restart(&self) -> Result<()> {
+        self.ready.store(false, Ordering::SeqCst);
+        *self.root.lock().unwrap() = None;
+        self.engine.restart().await?;
+        self.ready.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+    async fn kill(&self) -> Result<()> {
+        self.ready.store(false, Ordering::SeqCst);
+        self.engine.kill().await
+    }
+    async fn is_running(&self) -> bool {
+        self.engine.is_running().await
+    }
+    fn name(&self) -> Option<String> {
+        self.engine.name()
+    }
+    async fn set_position_with_moves(&self, fen: &str, moves: &[String]) -> Result<()> {
+        let root = position_command(fen);
+        let previous = self.root.lock().unwrap().take();
+        if self.new_game_policy == NewGamePolicy::PerPositionChange
+            && previous.is_some_and(|previous| previous != root)
+        {
+            self.new_game().await?;
+        }
+        *self.root.lock().unwrap() = Some(root.clone());
+        self.send_command(&with_moves(root, moves)).await
+    }
+    async fn new_game(&self) -> Result<()> {
+        *self.root.lock().unwrap() = None;
+        self.send_command("ucinewgame").await
+    }
+}
@@ -0,0 +1,312 @@
+#![cfg(unix)]
+use ironfish_core::{
+    AnalysisRequest, BestMoveRequest, ChessPosition, Error, GameOver, Score, StopReason,
+};
+use ironfish_stockfish::{AnalysisService, EngineKind, EnginePool, EnginePoolConfig};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+const QUICK: &str =
+    "info depth 1 seldepth 1 multipv 1 score cp 20 nodes 20 time 1 pv e2e4\nbestmove e2e4";
+struct FakeEngine {
+    dir: PathBuf,
+    script: PathBuf,
+    log: PathBuf,
+}
+impl FakeEngine {
+    fn new(script: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("ironfish-fake-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = Self {
+            script: dir.join("script"),
+            log: dir.join("commands.log"),
+            dir,
+        };
+        engine.set_script(script);
+        let wrapper = engine.dir.join("engine.sh");
+        let body = format!(
+            "#!/bin/sh\nexec {} --script {} --log {}\n",
+            env!("CARGO_BIN_EXE_fake-stockfish"),
+            engine.script.display(),
+            engine.log.display()
+        );
+        std::fs::write(&wrapper, body).unwrap();
+        std::fs::set_permissions(&wrapper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        engine
+    }
+    fn set_script(&self, script: &str) {
+        std::fs::write(&self.script, script).unwrap();
+    }
+    fn config(&self) -> EnginePoolConfig {
+        EnginePoolConfig {
+            binary_path: self.dir.join("engine.sh").display().to_string(),
+            pool_size: 1,
+            ..Default::default()
+        }
+    }
+    fn uci_config(&self) -> EnginePoolConfig {
+        EnginePoolConfig {
+            binary_path: env!("CARGO_BIN_EXE_fake-stockfish").to_string(),
+            kind: EngineKind::Uci,
+            args: vec![
+                "--script".to_string(),
+                self.script.display().to_string(),
+                "--log".to_string(),
+                self.log.display().to_string(),
+            ],
+            pool_size: 1,
+            ..Default::default()
+        }
+    }
+    async fn service(&self, timeout: Duration) -> AnalysisService {
+        let pool = EnginePool::new(self.config()).await.unwrap();
+        AnalysisService::new(Arc::new(pool)).with_timeout(timeout)
+    }
+    fn commands(&self) -> Vec<String> {
+        std::fs::read_to_string(&self.log)
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+impl Drop for FakeEngine {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+fn request(depth: u8) -> AnalysisRequest {
+    AnalysisRequest::new(ChessPosition::starting().fen).with_depth(depth)
+}
+#[tokio::test]
+async fn test_timeout_returns_partial_result() {
+    let fake = FakeEngine::new(
+        "info depth 7 multipv 1 score cp 15 nodes 10 pv d2d4\nsleep 10000\nbestmove d2d4",
+    );
+    let service = fake.service(Duration::from_millis(200)).await;
+    let started = Instant::now();
+    let result = service.analyze(request(20)).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(result.partial);
+    assert_eq!(result.stopped_by, StopReason::Timeout);
+    assert_eq!(result.best_move.unwrap().to_uci(), "d2d4");
+    assert_eq!(result.depth_reached, 7);
+    let commands = fake.commands();
+    let go = commands.iter().rposition(|c| c == "go depth 20").unwrap();
+    assert_eq!(commands[go + 1], "stop");
+    assert_eq!(commands[go + 2], "isready");
+    fake.set_script(QUICK);
+    let result = service.analyze(request(20)).await.unwrap();
+    assert!(!result.partial);
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+    assert_eq!(service.pool().unwrap().restarts(), 0);
+}
+#[tokio::test]
+async fn test_streaming_and_bestmove_timeouts_salvage_bestmove() {
+    let fake = FakeEngine::new(
+        "info depth 3 multipv 1 score cp 15 nodes 10 pv g1f3\nsleep 10000\nbestmove g1f3",
+    );
+    let service = fake.service(Duration::from_millis(200)).await;
+    let (tx, _rx) = mpsc::channel(8);
+    let result = service
+        .analyze_streaming(request(20), tx, CancellationToken::new())
+        .await
+        .unwrap();
+    assert!(result.partial);
+    assert_eq!(result.best_move.unwrap().to_uci(), "g1f3");
+    let mut best_move = BestMoveRequest::new(ChessPosition::starting().fen);
+    best_move.movetime = None;
+    best_move.nodes = Some(1_000_000);
+    let response = service.best_move(best_move).await.unwrap();
+    assert!(response.partial);
+    assert_eq!(response.best_move.unwrap().to_uci(), "g1f3");
+    assert_eq!(service.pool().unwrap().restarts(), 0);
+}
+#[tokio::test]
+async fn test_missing_bestmove_times_out() {
+    let fake = FakeEngine::new("info depth 1 multipv 1 score cp 15 nodes 10 pv e2e4");
+    let service = fake.service(Duration::from_millis(200)).await;
+    let started = Instant::now();
+    assert!(matches!(
+        service.analyze(request(20)).await,
+        Err(Error::AnalysisTimeout)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    fake.set_script(QUICK);
+    let result = service.analyze(request(1)).await.unwrap();
+    assert_eq!(result.depth_reached, 1);
+}
+#[tokio::test]
+async fn test_crash_mid_search_restarts_engine() {
+    let fake = FakeEngine::new("info depth 1 multipv 1 score cp 15 nodes 10 pv e2e4\nexit 3");
+    let service = fake.service(Duration::from_secs(5)).await;
+    assert!(matches!(
+        service.analyze(request(10)).await,
+        Err(Error::Engine(_))
+    ));
+    fake.set_script(QUICK);
+    let result = service.analyze(request(1)).await.unwrap();
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+    assert_eq!(service.pool().unwrap().restarts(), 1);
+    assert_eq!(
+        fake.commands()
+            .iter()
+            .filter(|c| c.as_str() == "uci")
+            .count(),
+        2
+    );
+}
+#[tokio::test]
+async fn test_multipv_collects_interleaved_lines() {
+    let fake = FakeEngine::new(
+        "info string NNUE evaluation using nn-test.nnue
+this is not uci
+info depth 1 seldepth 1 multipv 1 score cp 40 nodes 30 time 1 pv d2d4
+info depth 1 seldepth 1 multipv 2 score cp 30 nodes 30 time 1 pv e2e4
+info depth 1 seldepth 1 multipv 3 score cp 10 nodes 30 time 1 pv g1f3
+info depth 2 currmove e2e4 currmovenumber 1
+info depth 2 seldepth 3 multipv 1 score cp 35 nodes 120 time 3 pv e2e4 e7e5
+info depth 2 seldepth 3 multipv 1 score cp 60 lowerbound nodes 150 time 4 pv d2d4
+info depth 2 seldepth 3 multipv 2 score cp 25 nodes 160 time 4 pv d2d4 d7d5
+info depth 2 seldepth 2 multipv 3 score cp 5 nodes 170 time 5 pv g1f3 g8f6
+info depth 3 seldepth 4 multipv 1 score cp 32 wdl 120 800 80 nodes 400 time 9 pv e2e4 e7e5 g1f3
+info depth 3 seldepth 4 multipv 2 score cp 28 nodes 420 time 9 pv d2d4 d7d5 c2c4
+info depth 3 seldepth 4 multipv 3 score mate 12 upperbound nodes 430 time 9 pv c2c4
+info depth 3 seldepth 4 multipv 3 score cp 8 nodes 450 time 10 pv g1f3 g8f6 d2d4
+\u{0}garbage\u{7}
+bestmove e2e4 ponder e7e5",
+    );
+    let service = fake.service(Duration::from_secs(5)).await;
+    let result = service.analyze(request(3).with_multipv(3)).await.unwrap();
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+    assert_eq!(result.ponder.unwrap().to_uci(), "e7e5");
+    assert_eq!(result.depth_reached, 3);
+    assert_eq!(result.nodes_searched, 450);
+    assert_eq!(result.evaluation.value(), 32);
+    assert!(result.wdl.is_some());
+    let lines: Vec<_> = result
+        .principal_variations
+        .iter()
+        .map(|pv| {
+            (
+                pv.rank,
+                pv.depth,
+                pv.evaluation.value(),
+                pv.moves.iter().map(|m| m.to_uci()).collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            (
+                1,
+                3,
+                32,
+                vec!["e2e4".to_string(), "e7e5".into(), "g1f3".into()]
+            ),
+            (
+                2,
+                3,
+                28,
+                vec!["d2d4".to_string(), "d7d5".into(), "c2c4".into()]
+            ),
+            (
+                3,
+                3,
+                8,
+                vec!["g1f3".to_string(), "g8f6".into(), "d2d4".into()]
+            ),
+        ]
+    );
+    assert!(fake
+        .commands()
+        .contains(&"setoption name MultiPV value 3".to_string()));
+}
+#[tokio::test]
+async fn test_cancel_mid_search() {
+    let fake = FakeEngine::new(
+        "info depth 1 multipv 1 score cp 15 nodes 10 pv e2e4\nwait stop\nbestmove e2e4",
+    );
+    let service = Arc::new(fake.service(Duration::from_secs(10)).await);
+    let (tx, mut rx) = mpsc::channel(8);
+    let cancel = CancellationToken::new();
+    let task = {
+        let service = service.clone();
+        let cancel = cancel.clone();
+        tokio::spawn(async move { service.analyze_streaming(request(20), tx, cancel).await })
+    };
+    let progress = rx.recv().await.unwrap();
+    assert_eq!(progress.current_depth, 1);
+    let started = Instant::now();
+    cancel.cancel();
+    assert!(matches!(task.await.unwrap(), Err(Error::AnalysisCancelled)));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    let commands = fake.commands();
+    let go = commands.iter().rposition(|c| c == "go depth 20").unwrap();
+    assert_eq!(commands[go + 1], "stop");
+    fake.set_script(QUICK);
+    let result = service.analyze(request(1)).await.unwrap();
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+}
+#[tokio::test]
+async fn test_bestmove_none_in_terminal_positions() {
+    let mated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+    let fake = FakeEngine::new("info depth 0 score mate 0\nbestmove (none)");
+    let service = fake.service(Duration::from_secs(5)).await;
+    let result = service
+        .analyze(AnalysisRequest::new(mated).with_depth(10))
+        .await
+        .unwrap();
+    assert!(result.best_move.is_none());
+    assert!(result.ponder.is_none());
+    assert_eq!(result.game_over, Some(GameOver::Checkmate));
+    assert_eq!(result.evaluation, Score::MateIn(0));
+    assert!(result.principal_variations.is_empty());
+    let response = service
+        .best_move(BestMoveRequest::new(mated))
+        .await
+        .unwrap();
+    assert!(response.best_move.is_none());
+    assert!(!response.partial);
+    assert_eq!(response.game_over, Some(GameOver::Checkmate));
+    let stalemate = "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1";
+    fake.set_script("info depth 0 score cp 0\nbestmove (none)");
+    let result = service
+        .analyze(AnalysisRequest::new(stalemate).with_depth(10))
+        .await
+        .unwrap();
+    assert!(result.best_move.is_none());
+    assert_eq!(result.game_over, Some(GameOver::Stalemate));
+    let response = service
+        .best_move(BestMoveRequest::new(stalemate))
+        .await
+        .unwrap();
+    assert_eq!(response.game_over, Some(GameOver::Stalemate));
+    assert_eq!(service.pool().unwrap().restarts(), 0);
+    fake.set_script(QUICK);
+    let result = service.analyze(request(1)).await.unwrap();
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+    assert_eq!(result.game_over, None);
+}
+#[tokio::test]
+async fn test_generic_uci_engine_with_args() {
+    let fake = FakeEngine::new(QUICK);
+    let pool = EnginePool::new(fake.uci_config()).await.unwrap();
+    assert_eq!(pool.kind(), EngineKind::Uci);
+    assert_eq!(pool.engine_name().as_deref(), Some("Fake Stockfish"));
+    let status = pool.status();
+    assert_eq!(status.engine_name.as_deref(), Some("Fake Stockfish"));
+    assert_eq!(status.engines[0].name.as_deref(), Some("Fake Stockfish"));
+    let service = AnalysisService::new(Arc::new(pool)).with_timeout(Duration::from_secs(5));
+    let result = service.analyze(request(1)).await.unwrap();
+    assert_eq!(result.best_move.unwrap().to_uci(), "e2e4");
+    assert!(matches!(
+        service.analyze(request(1).with_skill_level(3)).await,
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(!fake.commands().iter().any(|c| c.contains("Skill Level")));
+}
@@ -1,5 +1,13 @@
-use crate::helpers::TestServer;
+use crate::helpers::{CaptureLayer, TestServer, TEST_CLUSTER_SECRET};
+use axum::http::{HeaderMap, StatusCode};
+use futures_util::{Stream, StreamExt};
+use ironfish_api::{WebhookConfig, WebhookEndpoint, WebhookEvent, SIGNATURE_HEADER};
+use ironfish_core::{AnalysisLimits, TokenStore};
 use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+use tracing_subscriber::layer::SubscriberExt;
 #[tokio::test]
 async fn test_health_endpoint() {
     let server = TestServer::new().await;
@@ -10,6 +18,41 @@ async fn test_health_endpoint() {
     assert!(body["node_id"].is_string());
 }
 #[tokio::test]
+async fn test_readiness_reports_broken_token_store() {
+    let server = TestServer::new().await;
+    server.token_store.set_broken(true);
+    let resp = server.get("/v1/health").await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["status"], "degraded");
+    let checks = body["checks"].as_array().expect("checks");
+    let check = |name: &str| {
+        checks
+            .iter()
+            .find(|c| c["name"] == name)
+            .expect(name)
+            .clone()
+    };
+    assert_eq!(check("token_store")["status"], "fail");
+    assert_eq!(
+        check("token_store")["detail"],
+        "storage error: token database is unavailable"
+    );
+    assert!(check("token_store")["latency_ms"].is_u64());
+    assert_eq!(check("engine_pool")["status"], "pass");
+    assert_eq!(check("engine_pool")["detail"], "mock");
+    assert_eq!(check("cluster")["status"], "skipped");
+    assert_eq!(server.get("/health").await.status(), 200);
+    server.token_store.set_broken(false);
+    assert_eq!(server.get("/ready").await.status(), 503);
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+    let resp = server.get("/ready").await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["status"], "healthy");
+    assert_eq!(body["checks"][1]["status"], "pass");
+}
+#[tokio::test]
 async fn test_analyze_endpoint_mock() {
     let server = TestServer::new().await;
     let body = json!({
@@ -27,6 +70,140 @@ async fn test_analyze_endpoint_mock() {
     assert!(result["best_move"].is_object());
     assert!(result["evaluation"].is_object());
 }
+async fn next_sse_event<B: AsRef<[u8]>>(
+    stream: &mut (impl Stream<Item = reqwest::Result<B>> + Unpin),
+    buffer: &mut String,
+) -> Option<(String, serde_json::Value)> {
+    loop {
+        while let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            let mut event = "message".to_string();
+            let mut data = String::new();
+            for line in block.lines() {
+                if let Some(name) = line.strip_prefix("event:") {
+                    event = name.trim().to_string();
+                } else if let Some(chunk) = line.strip_prefix("data:") {
+                    data.push_str(chunk.trim());
+                }
+            }
+            if !data.is_empty() {
+                return Some((event, serde_json::from_str(&data).expect("event json")));
+            }
+        }
+        let chunk = stream.next().await?.expect("chunk");
+        buffer.push_str(std::str::from_utf8(chunk.as_ref()).expect("utf-8"));
+    }
+}
+#[tokio::test]
+async fn test_analyze_stream_sse() {
+    let server = TestServer::new().await;
+    let resp = reqwest::Client::new()
+        .get(server.url("/v1/analyze/stream?startpos=true&depth=20&multipv=1"))
+        .bearer_auth(&server.token)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "text/event-stream");
+    let mut stream = resp.bytes_stream();
+    let mut buffer = String::new();
+    let mut progress = 0;
+    let complete = loop {
+        match next_sse_event(&mut stream, &mut buffer).await {
+            Some((event, data)) if event == "progress" => {
+                assert!(data["current_depth"].as_u64().unwrap() > 0);
+                progress += 1;
+            }
+            Some((event, data)) => {
+                assert_eq!(event, "complete");
+                break data;
+            }
+            None => panic!("stream ended without a complete event"),
+        }
+    };
+    assert!(progress >= 2, "only {} progress events", progress);
+    assert!(complete["best_move"].is_object());
+    assert!(next_sse_event(&mut stream, &mut buffer).await.is_none());
+    let resp = reqwest::Client::new()
+        .get(server.url("/v1/analyze/stream?fen=invalid"))
+        .bearer_auth(&server.token)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 400);
+    let resp = reqwest::Client::new()
+        .get(server.url("/v1/analyze/stream?startpos=true&depth=20"))
+        .bearer_auth(&server.token)
+        .send()
+        .await
+        .expect("request");
+    let mut stream = resp.bytes_stream();
+    let (_, first) = next_sse_event(&mut stream, &mut String::new())
+        .await
+        .expect("progress");
+    let id: uuid::Uuid = first["id"].as_str().unwrap().parse().unwrap();
+    drop(stream);
+    let mut status = "";
+    for _ in 0..50 {
+        status = match server.state.analyses.get(&id).await {
+            Some(entry) => entry.status(),
+            None => "",
+        };
+        if status == "cancelled" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, "cancelled");
+}
+#[tokio::test]
+async fn test_request_id_round_trips_into_analysis_spans() {
+    let capture = CaptureLayer::default();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+    let server = TestServer::new().await;
+    let resp = reqwest::Client::new()
+        .post(server.url("/v1/analyze"))
+        .bearer_auth(&server.token)
+        .header("x-request-id", "req-abc")
+        .json(&json!({ "startpos": true, "depth": 10 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-request-id"], "req-abc");
+    let analyze = capture.named("analyze");
+    assert_eq!(analyze.len(), 1);
+    assert_eq!(analyze[0].fields["request_id"], "req-abc");
+    assert_eq!(analyze[0].fields["path"], "/v1/analyze");
+    let http_spans: Vec<_> = capture
+        .named("request")
+        .into_iter()
+        .filter(|s| s.fields.contains_key("uri"))
+        .collect();
+    assert!(!http_spans.is_empty());
+    assert!(http_spans
+        .iter()
+        .all(|s| s.fields.contains_key("request_id")));
+    assert!(http_spans
+        .iter()
+        .any(|s| s.fields.get("request_id").map(String::as_str) == Some("req-abc")));
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": "invalid", "depth": 10 }))
+        .await;
+    assert_eq!(resp.status(), 400);
+    let generated = resp.headers()["x-request-id"]
+        .to_str()
+        .expect("header")
+        .to_string();
+    assert!(uuid::Uuid::parse_str(&generated).is_ok());
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["request_id"], generated.as_str());
+    assert!(capture
+        .named("request")
+        .iter()
+        .any(|s| s.fields.get("request_id") == Some(&generated)));
+}
 #[tokio::test]
 async fn test_analyze_invalid_fen() {
     let server = TestServer::new().await;
@@ -38,6 +215,441 @@ async fn test_analyze_invalid_fen() {
     assert_eq!(resp.status(), 400);
 }
 #[tokio::test]
+async fn test_analyze_enforces_parameter_limits() {
+    let server = TestServer::new().await;
+    let cases = [
+        (json!({ "startpos": true, "depth": 255 }), "depth"),
+        (json!({ "startpos": true, "depth": 0 }), "depth"),
+        (json!({ "startpos": true, "multipv": 0 }), "multipv"),
+        (json!({ "startpos": true, "multipv": 11 }), "multipv"),
+        (json!({ "startpos": true, "movetime": 9 }), "movetime"),
+        (json!({ "startpos": true, "movetime": 60001 }), "movetime"),
+    ];
+    for (body, field) in cases {
+        let resp = server.post_json("/v1/analyze", &body).await;
+        assert_eq!(resp.status(), 400);
+        let error: serde_json::Value = resp.json().await.expect("json");
+        assert_eq!(error["error"]["code"], "invalid_argument");
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("invalid argument: {} must be between", field)));
+    }
+    let body = json!({ "startpos": true, "depth": 30, "multipv": 10, "movetime": 10 });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+}
+#[tokio::test]
+async fn test_malformed_requests_use_error_envelope() {
+    let server = TestServer::new().await;
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .header("Authorization", format!("Bearer {}", server.token))
+        .header("Content-Type", "application/json")
+        .body("{\"startpos\": tru")
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 400);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "invalid_argument");
+    assert!(error["error"]["message"].is_string());
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .header("Authorization", format!("Bearer {}", server.token))
+        .body("{\"startpos\": true}")
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 415);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "invalid_argument");
+    let resp = server
+        .post_json("/v1/analyze?async=maybe", &json!({ "startpos": true }))
+        .await;
+    assert_eq!(resp.status(), 400);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "invalid_argument");
+    assert!(error["error"]["message"].is_string());
+}
+#[tokio::test]
+async fn test_analyze_compound_limits_mock() {
+    let server = TestServer::new().await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 30,
+        "movetime": 5000,
+        "nodes": 2000000
+    });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["stopped_by"], "depth");
+}
+#[tokio::test]
+async fn test_analyze_reports_white_perspective_mock() {
+    let server = TestServer::new().await;
+    let black = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+    let cases = [
+        (json!({ "fen": black, "depth": 10 }), -30),
+        (
+            json!({ "fen": black, "depth": 10, "perspective": "white" }),
+            -30,
+        ),
+        (
+            json!({ "fen": black, "depth": 10, "perspective": "side_to_move" }),
+            30,
+        ),
+        (
+            json!({ "startpos": true, "moves": ["e2e4"], "depth": 10 }),
+            -30,
+        ),
+        (json!({ "startpos": true, "depth": 10 }), 30),
+    ];
+    for (body, expected) in cases {
+        let resp = server.post_json("/v1/analyze", &body).await;
+        assert_eq!(resp.status(), 200);
+        let result: serde_json::Value = resp.json().await.expect("json");
+        assert_eq!(result["evaluation"]["value"], expected, "{}", body);
+        for pv in result["principal_variations"].as_array().unwrap() {
+            assert_eq!(pv["evaluation"]["value"], expected, "{}", body);
+        }
+    }
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({ "fen": black, "perspective": "black" }),
+        )
+        .await;
+    assert!(resp.status().is_client_error());
+}
+#[tokio::test]
+async fn test_analyze_san_notation_mock() {
+    let server = TestServer::new().await;
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "startpos": true, "depth": 10 }))
+        .await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert!(result.get("best_move_san").is_none());
+    assert!(result["principal_variations"][0].get("san").is_none());
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({ "startpos": true, "depth": 10, "notation": "both" }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["best_move_san"], "e4");
+    assert_eq!(result["ponder_san"], "e5");
+    assert_eq!(result["best_move"]["from"], "e2");
+    let pv = &result["principal_variations"][0];
+    assert_eq!(pv["san"], json!(["e4", "e5"]));
+    assert_eq!(pv["moves"][0]["to"], "e4");
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({ "startpos": true, "depth": 10, "notation": "san" }),
+        )
+        .await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let pv = &result["principal_variations"][0];
+    assert_eq!(pv["san"], json!(["e4", "e5"]));
+    assert_eq!(pv["moves"], json!([]));
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({ "startpos": true, "notation": "pgn" }),
+        )
+        .await;
+    assert!(resp.status().is_client_error());
+    let query = json!({
+        "query": "{ analyze(fen: \"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\", depth: 10, notation: \"san\") { bestMoveSan principalVariations { san } } }"
+    });
+    let resp = server.post_json("/graphql", &query).await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["data"]["analyze"]["bestMoveSan"], "e4");
+    assert_eq!(
+        result["data"]["analyze"]["principalVariations"][0]["san"],
+        json!(["e4", "e5"])
+    );
+}
+#[tokio::test]
+async fn test_analyze_score_history_mock() {
+    let server = TestServer::new().await;
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "startpos": true, "depth": 10 }))
+        .await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert!(result.get("score_history").is_none());
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({
+                "fen": "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+                "depth": 10,
+                "include_history": true
+            }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let history = result["score_history"].as_array().expect("score history");
+    assert_eq!(history.len(), 10);
+    let depths: Vec<u64> = history
+        .iter()
+        .map(|entry| entry["depth"].as_u64().unwrap())
+        .collect();
+    assert_eq!(depths, (1..=10).collect::<Vec<u64>>());
+    assert!(history
+        .windows(2)
+        .all(|w| w[0]["nodes"].as_u64() <= w[1]["nodes"].as_u64()));
+    let last = history.last().unwrap();
+    assert_eq!(last["evaluation"], result["evaluation"]);
+    assert_eq!(last["evaluation"]["value"], -30);
+}
+#[tokio::test]
+async fn test_analyze_chess960_mock() {
+    let server = TestServer::new().await;
+    let fen = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1";
+    let resp = server
+        .post_json(
+            "/v1/analyze",
+            &json!({ "fen": fen, "depth": 10, "variant": "chess960" }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["variant"], "chess960");
+    assert_eq!(result["fen"], fen);
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": fen, "depth": 10 }))
+        .await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "variant_required");
+}
+#[tokio::test]
+async fn test_analyze_then_get_analysis() {
+    let server = TestServer::new().await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 12
+    });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let id = created["id"].as_str().expect("id");
+    let resp = server.get(&format!("/v1/analyze/{}", id)).await;
+    assert_eq!(resp.status(), 200);
+    let stored: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(stored, created);
+    let resp = server
+        .get(&format!("/v1/analyze/{}", uuid::Uuid::new_v4()))
+        .await;
+    assert_eq!(resp.status(), 404);
+    let resp = server.get("/v1/analyze/not-a-uuid").await;
+    assert_eq!(resp.status(), 400);
+}
+async fn poll_analysis(server: &TestServer, id: &str) -> reqwest::Response {
+    for _ in 0..100 {
+        let resp = server.get(&format!("/v1/analyze/{}", id)).await;
+        if resp.status() != 202 {
+            return resp;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("analysis {} still pending", id);
+}
+#[tokio::test]
+async fn test_async_analysis_job() {
+    let server = TestServer::new().await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 20
+    });
+    let resp = server.post_json("/v1/analyze?async=true", &body).await;
+    assert_eq!(resp.status(), 202);
+    let job: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(job["status"], "queued");
+    let id = job["id"].as_str().expect("id").to_string();
+    let mut saw_progress = false;
+    for _ in 0..100 {
+        let resp = server.get(&format!("/v1/analyze/{}", id)).await;
+        if resp.status() != 202 {
+            break;
+        }
+        let status: serde_json::Value = resp.json().await.expect("json");
+        if status["status"] == "running" && status["progress"]["current_depth"].is_u64() {
+            saw_progress = true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(saw_progress);
+    let resp = poll_analysis(&server, &id).await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["id"], id.as_str());
+    assert!(result["best_move"].is_object());
+    let resp = server.delete(&format!("/v1/analyze/{}", id)).await;
+    assert_eq!(resp.status(), 409);
+    let resp = server
+        .post_json("/v1/analyze?async=true", &json!({"fen": "invalid-fen"}))
+        .await;
+    assert_eq!(resp.status(), 400);
+}
+#[tokio::test]
+async fn test_async_analysis_cancel() {
+    let server = TestServer::new().await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 20
+    });
+    let resp = server.post_json("/v1/analyze?async=true", &body).await;
+    let job: serde_json::Value = resp.json().await.expect("json");
+    let id = job["id"].as_str().expect("id").to_string();
+    let resp = server.delete(&format!("/v1/analyze/{}", id)).await;
+    assert_eq!(resp.status(), 202);
+    let resp = poll_analysis(&server, &id).await;
+    assert_eq!(resp.status(), 410);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "analysis_cancelled");
+    let resp = server
+        .delete(&format!("/v1/analyze/{}", uuid::Uuid::new_v4()))
+        .await;
+    assert_eq!(resp.status(), 404);
+}
+#[tokio::test]
+async fn test_analyze_forwarded_to_peer() {
+    let peer = TestServer::new().await;
+    let server = TestServer::forwarding_to(&peer).await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 10
+    });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-ironfish-node"], peer.node_id.as_str());
+    assert_eq!(
+        resp.headers()["x-ironfish-forwarded-from"],
+        server.node_id.as_str()
+    );
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let id = result["id"].as_str().expect("id");
+    let resp = server.get(&format!("/v1/analyze/{}", id)).await;
+    assert_eq!(resp.status(), 200);
+    let resp = peer.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.headers()["x-ironfish-node"], peer.node_id.as_str());
+    assert!(!resp.headers().contains_key("x-ironfish-forwarded-from"));
+    let resp = reqwest::Client::new()
+        .post(server.url("/v1/analyze"))
+        .header("x-ironfish-forwarded-from", "spoofed")
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.headers()["x-ironfish-node"], peer.node_id.as_str());
+    let resp = reqwest::Client::new()
+        .post(server.url("/v1/analyze"))
+        .header("x-ironfish-forwarded-from", peer.node_id.as_str())
+        .header("x-ironfish-forwarded-secret", TEST_CLUSTER_SECRET)
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.headers()["x-ironfish-node"], server.node_id.as_str());
+    let mut request = ironfish_core::AnalysisRequest::new(
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    )
+    .with_depth(8);
+    request.clamped = true;
+    let (result, forwarded) = server
+        .state
+        .analyze_routed(request.clone(), None, None)
+        .await
+        .expect("forwarded analysis");
+    assert_eq!(forwarded.expect("peer").to_string(), peer.node_id);
+    assert_eq!(result.id, request.id);
+    assert!(result.clamped);
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": "invalid" }))
+        .await;
+    assert_eq!(resp.status(), 400);
+}
+#[tokio::test]
+async fn test_analyze_search_moves() {
+    let server = TestServer::new().await;
+    let body = json!({ "startpos": true, "depth": 10, "search_moves": ["d2d4", "c2c4"] });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let variations = result["principal_variations"].as_array().expect("pvs");
+    assert_eq!(variations.len(), 2);
+    for pv in variations {
+        let first = &pv["moves"][0];
+        let first = format!(
+            "{}{}",
+            first["from"].as_str().unwrap(),
+            first["to"].as_str().unwrap()
+        );
+        assert!(
+            first == "d2d4" || first == "c2c4",
+            "unexpected line {}",
+            first
+        );
+    }
+    assert_eq!(result["best_move"]["from"], "d2");
+    let body = json!({ "startpos": true, "search_moves": ["e2e4", "e2e5", "x"] });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 400);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "invalid_search_moves");
+    assert_eq!(
+        error["error"]["details"]["search_moves"],
+        json!(["e2e5", "x"])
+    );
+}
+#[tokio::test]
+async fn test_analyze_game_mock() {
+    let server = TestServer::new().await;
+    let body = json!({
+        "pgn": "[Event \"Test\"]\n\n1. e4 e5 2. Nf3 {main line} Nc6 *",
+        "depth": 8
+    });
+    let resp = server.post_json("/v1/analyze/game", &body).await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let moves = result["moves"].as_array().expect("moves");
+    assert_eq!(moves.len(), 4);
+    assert_eq!(moves[0]["move"]["from"], "e2");
+    assert_eq!(moves[0]["classification"], "best");
+    assert!(moves[2]["centipawn_loss"].is_number());
+    assert_eq!(
+        moves[3]["fen"],
+        "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"
+    );
+    let body = json!({ "moves": ["e2e4", "e7e5", "e1e3"] });
+    let resp = server.post_json("/v1/analyze/game", &body).await;
+    assert_eq!(resp.status(), 400);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "invalid_move");
+    assert_eq!(error["error"]["details"]["move_index"], 2);
+    assert_eq!(error["error"]["details"]["move"], "e1e3");
+    server.state.set_limits(AnalysisLimits {
+        max_game_plies: 2,
+        ..AnalysisLimits::default()
+    });
+    for body in [
+        json!({ "moves": ["e2e4", "e7e5", "g1f3"] }),
+        json!({ "moves": ["e2e4"], "depth": 99 }),
+        json!({ "moves": ["e2e4"], "fen": "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w Kx - 0 1" }),
+    ] {
+        let resp = server.post_json("/v1/analyze/game", &body).await;
+        assert_eq!(resp.status(), 400, "{}", body);
+    }
+}
+#[tokio::test]
 async fn test_bestmove_endpoint_mock() {
     let server = TestServer::new().await;
     let body = json!({
@@ -49,6 +661,39 @@ async fn test_bestmove_endpoint_mock() {
     assert!(result["best_move"].is_object());
 }
 #[tokio::test]
+async fn test_concurrent_analysis_limit() {
+    let server = TestServer::new().await;
+    server.state.admission.set_limit(1);
+    let body = json!({ "startpos": true, "depth": 10 });
+    let (first, second) = tokio::join!(
+        server.post_json("/v1/analyze?async=true", &body),
+        server.post_json("/v1/analyze?async=true", &body),
+    );
+    let mut statuses = [first.status().as_u16(), second.status().as_u16()];
+    statuses.sort();
+    assert_eq!(statuses, [202, 429]);
+    let job: serde_json::Value = match first.status().as_u16() {
+        202 => first.json().await.expect("json"),
+        _ => second.json().await.expect("json"),
+    };
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 429);
+    let retry_after: u64 = resp.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after >= 1);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "too_many_analyses");
+    let resp = server.post_json("/v1/bestmove", &body).await;
+    assert_eq!(resp.status(), 429);
+    let resp = poll_analysis(&server, job["id"].as_str().unwrap()).await;
+    assert_eq!(resp.status(), 200);
+    let resp = server.post_json("/v1/analyze", &body).await;
+    assert_eq!(resp.status(), 200);
+}
+#[tokio::test]
 async fn test_token_create_and_list() {
     let server = TestServer::new().await;
     let create_body = json!({ "name": "test-api-token" });
@@ -72,6 +717,23 @@ async fn test_cluster_status() {
     assert!(status["healthy"].is_boolean());
 }
 #[tokio::test]
+async fn test_node_diagnostics() {
+    let server = TestServer::new().await;
+    let running = uuid::Uuid::new_v4();
+    server.state.analyses.begin(running).await;
+    let resp = server.get("/_admin/diagnostics").await;
+    assert_eq!(resp.status(), 200);
+    let diagnostics: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(diagnostics["node_id"], server.node_id);
+    assert_eq!(diagnostics["draining"], false);
+    assert_eq!(diagnostics["engine_pool"]["size"], 0);
+    assert_eq!(diagnostics["analyses"][0]["id"], running.to_string());
+    assert!(diagnostics["analyses"][0]["elapsed_ms"].is_u64());
+    assert!(diagnostics["ws_sessions"].as_array().unwrap().is_empty());
+    assert!(diagnostics["peers"].as_array().unwrap().is_empty());
+    assert!(diagnostics["uptime_seconds"].is_u64());
+}
+#[tokio::test]
 async fn test_graphql_query() {
     let server = TestServer::new().await;
     let body = json!({
@@ -83,6 +745,31 @@ async fn test_graphql_query() {
     assert!(result["data"]["clusterStatus"].is_object());
 }
 #[tokio::test]
+async fn test_graphql_start_analysis_checks_limits() {
+    let server = TestServer::new().await;
+    for (depth, accepted) in [(10, true), (300, false)] {
+        let body = json!({
+            "query": format!(
+                r#"mutation {{ startAnalysis(fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", depth: {}) }}"#,
+                depth
+            )
+        });
+        let result: serde_json::Value = server
+            .post_json("/graphql", &body)
+            .await
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(result["data"]["startAnalysis"].is_string(), accepted);
+        if !accepted {
+            assert!(result["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("depth must be between"));
+        }
+    }
+}
+#[tokio::test]
 async fn test_graphql_bestmove() {
     let server = TestServer::new().await;
     let body = json!({
@@ -94,10 +781,76 @@ async fn test_graphql_bestmove() {
     assert!(result["data"]["bestMove"]["bestMove"].is_object());
 }
 #[tokio::test]
+async fn test_analysis_cache_and_clear() {
+    let server = TestServer::with_cache().await;
+    let body = json!({ "startpos": true, "moves": ["e2e4"], "depth": 12 });
+    let resp = server.post_json("/v1/analyze", &body).await;
+    let first: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(first["cached"], false);
+    let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": fen, "depth": 10 }))
+        .await;
+    let second: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(second["cached"], true);
+    assert_eq!(second["fen"], fen);
+    assert_eq!(second["depth_reached"], 12);
+    assert_ne!(second["id"], first["id"]);
+    let text = server.get("/metrics").await.text().await.expect("text");
+    assert!(text.contains("ironfish_analysis_cache_total{result=\"hit\"} 1"));
+    assert!(text.contains("ironfish_analysis_cache_total{result=\"miss\"} 1"));
+    let resp = reqwest::Client::new()
+        .post(server.url("/_admin/cache/clear"))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 401);
+    let resp = server
+        .admin_post_json("/_admin/cache/clear", &json!({}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let cleared: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(cleared["cleared"], 1);
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": fen, "depth": 10 }))
+        .await;
+    let third: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(third["cached"], false);
+}
+#[tokio::test]
 async fn test_metrics_endpoint() {
     let server = TestServer::new().await;
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": fen, "depth": 10 }))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let resp = server
+        .post_json("/v1/bestmove", &json!({ "fen": fen }))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let resp = server
+        .post_json("/v1/analyze", &json!({ "fen": "invalid" }))
+        .await;
+    assert_eq!(resp.status(), 400);
     let resp = server.get("/metrics").await;
-    assert!(resp.status() == 200 || resp.status() == 404);
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let text = resp.text().await.expect("text");
+    assert!(text.contains("ironfish_analyses_total 2"));
+    assert!(text.contains("ironfish_bestmove_requests_total 1"));
+    assert!(text.contains("ironfish_errors_total{type=\"invalid_fen\"} 1"));
+    assert!(text.contains("ironfish_analysis_duration_seconds_bucket"));
+    assert!(text.contains("ironfish_request_duration_seconds_bucket"));
+    assert!(text.contains("protocol=\"rest\""));
+    assert!(text.contains("ironfish_cluster_peers 0"));
+    let resp = server.get("/v1/metrics").await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert!(body["engines_total"].is_number());
 }
 #[tokio::test]
 async fn test_admin_auth_required() {
@@ -139,6 +892,116 @@ async fn test_admin_create_token_with_auth() {
     assert!(token_str.starts_with("iff_"));
 }
 #[tokio::test]
+async fn test_admin_writes_forwarded_to_leader() {
+    let leader = TestServer::new().await;
+    leader
+        .state
+        .node
+        .set_state(ironfish_core::NodeState::Leader);
+    let follower = TestServer::following(&leader).await;
+    let resp = follower
+        .admin_post_json("/_admin/tokens", &json!({ "name": "via-follower" }))
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-ironfish-node"], leader.node_id.as_str());
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let id: uuid::Uuid = created["id"].as_str().unwrap().parse().unwrap();
+    assert!(leader.token_store.get(&id).await.unwrap().is_some());
+    assert!(follower.token_store.get(&id).await.unwrap().is_none());
+    let resp = follower.admin_get("/_admin/tokens").await;
+    assert_eq!(resp.status(), 200);
+    assert!(!resp.headers().contains_key("x-ironfish-node"));
+    let resp = follower
+        .admin_delete(&format!("/_admin/tokens/{}", id))
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert!(leader.token_store.get(&id).await.unwrap().unwrap().revoked);
+    let resp = follower
+        .admin_post_json("/_admin/cluster/leave", &json!({}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let audit: serde_json::Value = leader
+        .admin_get("/_admin/audit?action=cluster_leave")
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(audit["entries"][0]["target"], follower.node_id.as_str());
+    let resp = follower
+        .post_json(
+            "/graphql",
+            &json!({ "query": "mutation { ...write } fragment write on MutationRoot { createToken { id } }" }),
+        )
+        .await;
+    assert_eq!(resp.headers()["x-ironfish-node"], leader.node_id.as_str());
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let id: uuid::Uuid = result["data"]["createToken"]["id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(leader.token_store.get(&id).await.unwrap().is_some());
+    assert!(follower.token_store.get(&id).await.unwrap().is_none());
+    let resp = follower
+        .post_json(
+            "/graphql",
+            &json!({ "query": "{ clusterStatus { term } }" }),
+        )
+        .await;
+    assert!(!resp.headers().contains_key("x-ironfish-node"));
+    follower.state.node.set_leader(None);
+    let resp = follower.admin_post_json("/_admin/tokens", &json!({})).await;
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.headers()["retry-after"], "2");
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "no_leader");
+}
+#[tokio::test]
+async fn test_forwarded_leave_requires_cluster_secret() {
+    let leader = TestServer::new().await;
+    leader
+        .state
+        .node
+        .set_state(ironfish_core::NodeState::Leader);
+    let victim = ironfish_core::NodeId::from_string("victim");
+    leader
+        .membership
+        .add_member(ironfish_core::NodeInfo {
+            id: victim.clone(),
+            address: "127.0.0.1:9".parse().unwrap(),
+            priority: 100,
+            started_at: chrono::Utc::now(),
+            version: "test".to_string(),
+        })
+        .await;
+    for secret in [None, Some("wrong-secret")] {
+        let mut request = reqwest::Client::new()
+            .post(leader.url("/_admin/cluster/leave"))
+            .header("x-admin-key", &leader.admin_key)
+            .header("x-ironfish-forwarded-from", "victim")
+            .json(&json!({}));
+        if let Some(secret) = secret {
+            request = request.header("x-ironfish-forwarded-secret", secret);
+        }
+        let resp = request.send().await.expect("request");
+        assert_eq!(resp.status(), 401);
+        let body: serde_json::Value = resp.json().await.expect("json");
+        assert_eq!(body["error"]["code"], "invalid_cluster_secret");
+        assert!(leader.membership.get_member(&victim).await.is_some());
+    }
+    let resp = reqwest::Client::new()
+        .post(leader.url("/_admin/cluster/leave"))
+        .header("x-admin-key", &leader.admin_key)
+        .header("x-ironfish-forwarded-from", "victim")
+        .header("x-ironfish-forwarded-secret", TEST_CLUSTER_SECRET)
+        .json(&json!({}))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    assert!(leader.membership.get_member(&victim).await.is_none());
+}
+#[tokio::test]
 async fn test_api_auth_required() {
     let server = TestServer::with_auth().await;
     let body = json!({
@@ -174,3 +1037,835 @@ async fn test_health_no_auth_required() {
         .expect("request");
     assert_eq!(resp.status(), 200);
 }
+#[tokio::test]
+async fn test_openapi_spec() {
+    let server = TestServer::with_auth().await;
+    let resp = reqwest::Client::new()
+        .get(server.url("/openapi.json"))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    let spec: serde_json::Value = resp.json().await.expect("json");
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    let analyze = &spec["paths"]["/v1/analyze"]["post"];
+    assert!(analyze["requestBody"].is_object());
+    assert!(analyze["responses"]["429"].is_object());
+    assert!(spec["paths"]["/_admin/tokens"]["get"].is_object());
+    assert!(spec["components"]["securitySchemes"]["admin_key"].is_object());
+    let page = server.get("/docs").await.text().await.expect("text");
+    assert!(!page.contains("SwaggerUIBundle"));
+}
+#[tokio::test]
+async fn test_analysis_history_is_scoped_to_token() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "name": "other" }))
+        .await;
+    let other: serde_json::Value = resp.json().await.expect("json");
+    let other_id = other["id"].as_str().unwrap().to_string();
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 10
+    });
+    for _ in 0..2 {
+        let resp = server.post_json("/v1/analyze", &body).await;
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = reqwest::Client::new()
+        .post(server.url("/v1/analyze"))
+        .header(
+            "Authorization",
+            format!("Bearer {}", other["token"].as_str().unwrap()),
+        )
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    let resp = server.get("/v1/analyses?limit=1").await;
+    assert_eq!(resp.status(), 200);
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let records = page["records"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["depth"], 10);
+    let own_id = records[0]["token_id"].as_str().unwrap().to_string();
+    assert_ne!(own_id, other_id);
+    let resp = server
+        .get(&format!(
+            "/v1/analyses?before={}",
+            page["next_before"].as_u64().unwrap()
+        ))
+        .await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(page["records"].as_array().unwrap().len(), 1);
+    assert_eq!(page["records"][0]["token_id"], own_id.as_str());
+    assert!(page["next_before"].is_null());
+    let resp = server
+        .get(&format!("/v1/analyses?token_id={}", other_id))
+        .await;
+    assert_eq!(resp.status(), 403);
+    let resp = server
+        .admin_get(&format!("/_admin/analyses?token_id={}", other_id))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let page: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(page["records"].as_array().unwrap().len(), 1);
+    assert_eq!(page["records"][0]["token_id"], other_id.as_str());
+    let resp = server.admin_get("/_admin/analyses").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(page["records"].as_array().unwrap().len(), 3);
+}
+#[tokio::test]
+async fn test_audit_records_token_lifecycle() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "name": "audited" }))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token_id = created["id"].as_str().unwrap().to_string();
+    let resp = server
+        .admin_delete(&format!("/_admin/tokens/{}", token_id))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let resp = server.admin_get("/_admin/audit").await;
+    assert_eq!(resp.status(), 200);
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let entries: Vec<&serde_json::Value> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["target"] == token_id.as_str())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["action"], "token_create");
+    assert_eq!(entries[1]["action"], "token_revoke");
+    assert!(entries[0]["seq"].as_u64() < entries[1]["seq"].as_u64());
+    assert_eq!(entries[0]["outcome"]["status"], "success");
+    assert!(entries[0]["actor"].as_str().unwrap().starts_with("admin:"));
+    let resp = server.admin_get("/_admin/audit?action=token_revoke").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    assert!(page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|e| e["action"] == "token_revoke"));
+}
+#[tokio::test]
+async fn test_audit_records_engine_pool_resize() {
+    let server = TestServer::with_auth().await;
+    let resp = reqwest::Client::new()
+        .post(server.url("/_admin/engine/pool"))
+        .json(&json!({ "size": 2 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 401);
+    let resp = server
+        .admin_post_json("/_admin/engine/pool", &json!({ "size": 2 }))
+        .await;
+    assert_eq!(resp.status(), 409);
+    let resp = server.admin_get("/_admin/audit?action=engine_pool").await;
+    assert_eq!(resp.status(), 200);
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let entries = page["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["action"], "engine_pool");
+    assert_eq!(
+        entries[0]["target"],
+        format!("{}:size=2", server.node_id).as_str()
+    );
+    assert_eq!(entries[0]["outcome"]["status"], "failure");
+    assert!(entries[0]["actor"].as_str().unwrap().starts_with("admin:"));
+}
+#[tokio::test]
+async fn test_audit_records_graphql_token_mutations() {
+    let server = TestServer::with_auth().await;
+    let body = json!({ "query": "mutation { createToken { id } }" });
+    let resp = server.post_json("/graphql", &body).await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    let token_id = result["data"]["createToken"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let body = json!({
+        "query": format!(r#"mutation {{ revokeToken(id: "{}") }}"#, token_id)
+    });
+    let resp = server.post_json("/graphql", &body).await;
+    assert_eq!(resp.status(), 200);
+    let resp = server.admin_get("/_admin/audit").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let entries: Vec<&serde_json::Value> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["target"] == token_id.as_str())
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["action"], "token_create");
+    assert_eq!(entries[1]["action"], "token_revoke");
+    assert!(entries[0]["actor"].as_str().unwrap().starts_with("token:"));
+}
+#[tokio::test]
+async fn test_graphql_token_management_rejects_narrower_callers() {
+    async fn create(server: &TestServer, body: serde_json::Value) -> String {
+        let resp = server.admin_post_json("/_admin/tokens", &body).await;
+        let created: serde_json::Value = resp.json().await.expect("json");
+        created["token"].as_str().unwrap().to_string()
+    }
+    async fn graphql(server: &TestServer, token: &str, query: &str) -> serde_json::Value {
+        reqwest::Client::new()
+            .post(server.url("/graphql"))
+            .bearer_auth(token)
+            .json(&json!({ "query": query }))
+            .send()
+            .await
+            .expect("request")
+            .json()
+            .await
+            .expect("json")
+    }
+    async fn token_count(server: &TestServer) -> usize {
+        let resp = server.admin_get("/_admin/tokens").await;
+        let tokens: Vec<serde_json::Value> = resp.json().await.expect("json");
+        tokens.len()
+    }
+    let server = TestServer::with_auth().await;
+    let scoped = create(&server, json!({ "scopes": ["bestmove"] })).await;
+    let capped = create(&server, json!({ "max_depth": 10 })).await;
+    let before = token_count(&server).await;
+    let revoke = format!(
+        r#"mutation {{ revokeToken(id: "{}") }}"#,
+        uuid::Uuid::new_v4()
+    );
+    for query in [
+        "mutation { createToken { id } }",
+        revoke.as_str(),
+        "{ tokenList { id } }",
+        "{ tokens { edges { node { id } } } }",
+    ] {
+        let result = graphql(&server, &scoped, query).await;
+        assert_eq!(
+            result["errors"][0]["extensions"]["code"], "FORBIDDEN",
+            "{}",
+            query
+        );
+    }
+    let result = graphql(&server, &capped, "mutation { createToken { id } }").await;
+    assert_eq!(result["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    let result = graphql(
+        &server,
+        &capped,
+        "mutation { createToken(input: { maxDepth: 8 }) { id } }",
+    )
+    .await;
+    assert!(
+        result["data"]["createToken"]["id"].is_string(),
+        "{}",
+        result
+    );
+    assert_eq!(token_count(&server).await, before + 1);
+}
+#[tokio::test]
+async fn test_audit_records_admin_auth_failure() {
+    let server = TestServer::with_auth().await;
+    let resp = reqwest::Client::new()
+        .get(server.url("/_admin/tokens"))
+        .header("X-Admin-Key", "wrong-key")
+        .header("X-Forwarded-For", "198.51.100.7")
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 401);
+    let resp = server
+        .admin_get("/_admin/audit?action=admin_auth_failure")
+        .await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let entries = page["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["target"], "GET /_admin/tokens");
+    assert_eq!(entries[0]["outcome"]["status"], "failure");
+    assert_eq!(entries[0]["source_ip"], "127.0.0.1");
+}
+#[tokio::test]
+async fn test_purge_removes_expired_tokens() {
+    let server = TestServer::with_auth().await;
+    let manager = ironfish_auth::TokenManager::new(b"purge-secret", "test");
+    let (mut expired, _) = manager
+        .create(ironfish_core::CreateTokenRequest {
+            name: Some("expired".into()),
+            expires_in_days: Some(1),
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .expect("token");
+    expired.expires_at = Some(chrono::Utc::now() - chrono::Duration::days(30));
+    server.token_store.create(expired.clone()).await.unwrap();
+    let resp = server.admin_delete("/_admin/tokens/expired").await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["purged"], 1);
+    assert!(server.token_store.get(&expired.id).await.unwrap().is_none());
+    let resp = server.admin_get("/_admin/tokens").await;
+    let tokens: Vec<serde_json::Value> = resp.json().await.expect("json");
+    assert_eq!(tokens.len(), 1);
+    let resp = server.admin_get("/_admin/audit?action=token_purge").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(page["entries"][0]["target"], "1");
+}
+async fn insert_bulk_tokens(server: &TestServer, count: i64) {
+    let manager = ironfish_auth::TokenManager::new(b"bulk-secret", "test");
+    let now = chrono::Utc::now();
+    for i in 0..count {
+        let (mut token, _) = manager
+            .create(ironfish_core::CreateTokenRequest {
+                name: Some(format!("bulk-{}", i)),
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .expect("token");
+        token.created_at = now - chrono::Duration::seconds(i);
+        token.revoked = i % 5 == 0;
+        server.token_store.create(token).await.unwrap();
+    }
+}
+#[tokio::test]
+async fn test_graphql_tokens_connection_pages() {
+    let server = TestServer::new().await;
+    insert_bulk_tokens(&server, 250).await;
+    let mut names = Vec::new();
+    let mut after = serde_json::Value::Null;
+    loop {
+        let body = json!({
+            "query": "query($after: String) { tokens(first: 60, after: $after, filter: { revoked: false, nameContains: \"BULK\" }, orderBy: CREATED_AT_DESC) { edges { cursor node { name revoked } } pageInfo { hasNextPage endCursor } } }",
+            "variables": { "after": after },
+        });
+        let resp = server.post_json("/graphql", &body).await;
+        let result: serde_json::Value = resp.json().await.expect("json");
+        let connection = &result["data"]["tokens"];
+        let edges = connection["edges"].as_array().expect("edges");
+        assert!(edges.len() <= 60);
+        for edge in edges {
+            assert_eq!(edge["node"]["revoked"], false);
+            names.push(edge["node"]["name"].as_str().unwrap().to_string());
+        }
+        if connection["pageInfo"]["hasNextPage"] != true {
+            break;
+        }
+        after = connection["pageInfo"]["endCursor"].clone();
+    }
+    let expected: Vec<String> = (0..250)
+        .filter(|i| i % 5 != 0)
+        .map(|i| format!("bulk-{}", i))
+        .collect();
+    assert_eq!(names, expected);
+    let body = json!({ "query": "{ tokenList { name } }" });
+    let resp = server.post_json("/graphql", &body).await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert!(result["data"]["tokenList"].as_array().unwrap().len() >= 250);
+    let body = json!({ "query": r#"{ tokens(after: "nope") { edges { cursor } } }"# });
+    let resp = server.post_json("/graphql", &body).await;
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert!(result["errors"].is_array());
+}
+#[tokio::test]
+async fn test_admin_tokens_filters_and_pages() {
+    let server = TestServer::with_auth().await;
+    insert_bulk_tokens(&server, 250).await;
+    let resp = server.admin_get("/_admin/tokens").await;
+    assert!(resp.headers().get("x-ironfish-next-cursor").is_none());
+    let tokens: Vec<serde_json::Value> = resp.json().await.expect("json");
+    assert_eq!(tokens.len(), 251);
+    let mut names = Vec::new();
+    let mut path = "/_admin/tokens?name_contains=bulk&limit=100".to_string();
+    loop {
+        let resp = server.admin_get(&path).await;
+        assert_eq!(resp.status(), 200);
+        let next = resp
+            .headers()
+            .get("x-ironfish-next-cursor")
+            .map(|v| v.to_str().unwrap().to_string());
+        let page: Vec<serde_json::Value> = resp.json().await.expect("json");
+        assert!(page.len() <= 100);
+        names.extend(page.iter().map(|t| t["name"].as_str().unwrap().to_string()));
+        match next {
+            Some(cursor) => {
+                path = format!(
+                    "/_admin/tokens?name_contains=bulk&limit=100&after={}",
+                    cursor
+                )
+            }
+            None => break,
+        }
+    }
+    let expected: Vec<String> = (0..250).map(|i| format!("bulk-{}", i)).collect();
+    assert_eq!(names, expected);
+    let resp = server
+        .admin_get("/_admin/tokens?revoked=true&order_by=last_used_desc")
+        .await;
+    let revoked: Vec<serde_json::Value> = resp.json().await.expect("json");
+    assert_eq!(revoked.len(), 50);
+    let resp = server.admin_get("/_admin/tokens?order_by=oldest").await;
+    assert_eq!(resp.status(), 400);
+    let resp = server.admin_get("/_admin/tokens?after=garbage").await;
+    assert_eq!(resp.status(), 400);
+}
+#[tokio::test]
+async fn test_token_usage_is_counted() {
+    let server = TestServer::with_auth().await;
+    for _ in 0..3 {
+        assert_eq!(server.get("/v1/metrics").await.status(), 200);
+    }
+    let resp = server.admin_get("/_admin/tokens").await;
+    let tokens: Vec<serde_json::Value> = resp.json().await.expect("json");
+    let token = tokens
+        .iter()
+        .find(|t| t["name"] == "test-token")
+        .expect("test token");
+    assert_eq!(token["usage_count"], 3);
+    assert_eq!(token["last_used_ip"], "127.0.0.1");
+    let resp = server
+        .admin_get(&format!(
+            "/_admin/tokens/{}/usage",
+            token["id"].as_str().unwrap()
+        ))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let usage: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(usage["usage_count"], 3);
+    assert_eq!(usage["daily"].as_array().unwrap().len(), 1);
+    assert_eq!(usage["daily"][0]["count"], 3);
+    let resp = server
+        .admin_get(&format!("/_admin/tokens/{}/usage", uuid::Uuid::new_v4()))
+        .await;
+    assert_eq!(resp.status(), 404);
+}
+#[tokio::test]
+async fn test_token_store_read_only_rejects_writes() {
+    let server = TestServer::new().await;
+    server.token_store.mark_read_only();
+    let health: serde_json::Value = server.get("/v1/health").await.json().await.expect("json");
+    assert_eq!(health["token_store"], "degraded");
+    let resp = server
+        .post_json("/_admin/tokens", &json!({ "name": "blocked" }))
+        .await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "store_read_only");
+    let resp = server.get("/_admin/tokens").await;
+    assert_eq!(resp.status(), 200);
+    server.token_store.recover().await.expect("recover");
+    let health: serde_json::Value = server.get("/v1/health").await.json().await.expect("json");
+    assert_eq!(health["token_store"], "healthy");
+    let resp = server
+        .post_json("/_admin/tokens", &json!({ "name": "allowed" }))
+        .await;
+    assert_eq!(resp.status(), 200);
+}
+#[tokio::test]
+async fn test_token_scopes_enforced() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json(
+            "/_admin/tokens",
+            &json!({ "name": "bestmove-only", "scopes": ["bestmove"] }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .bearer_auth(token)
+        .json(&json!({ "fen": fen, "depth": 10 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 403);
+    let resp = client
+        .post(server.url("/v1/bestmove"))
+        .bearer_auth(token)
+        .json(&json!({ "fen": fen }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    let resp = client
+        .post(server.url("/graphql"))
+        .bearer_auth(token)
+        .json(&json!({ "query": "{ recentAnalyses { records { id } } }" }))
+        .send()
+        .await
+        .expect("request");
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "scopes": ["everything"] }))
+        .await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "invalid_scope");
+}
+#[tokio::test]
+async fn test_token_compute_caps_enforced() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json(
+            "/_admin/tokens",
+            &json!({ "name": "free-tier", "max_depth": 8, "node_budget_per_hour": 10000 }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .bearer_auth(token)
+        .json(&json!({ "fen": fen, "depth": 20 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()[ironfish_api::rest::NODE_BUDGET_REMAINING_HEADER],
+        "0"
+    );
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["clamped"], true);
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .bearer_auth(token)
+        .json(&json!({ "fen": fen, "depth": 6 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().contains_key("retry-after"));
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "node_budget_exhausted");
+    assert!(body["error"]["details"]["resets_at"].is_string());
+    let resp = client
+        .post(server.url("/v1/bestmove"))
+        .bearer_auth(token)
+        .json(&json!({ "fen": fen }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    let resp = server
+        .admin_get(&format!(
+            "/_admin/tokens/{}/usage",
+            created["id"].as_str().unwrap()
+        ))
+        .await;
+    let usage: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(usage["node_budget"]["budget_per_hour"], 10000);
+    assert_eq!(usage["node_budget"]["remaining"], 0);
+}
+#[tokio::test]
+async fn test_token_compute_caps_enforced_for_games() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json(
+            "/_admin/tokens",
+            &json!({ "name": "game-tier", "max_depth": 8, "node_budget_per_hour": 10000 }),
+        )
+        .await;
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let client = reqwest::Client::new();
+    let body = json!({ "moves": ["e2e4"], "depth": 12 });
+    let resp = client
+        .post(server.url("/v1/analyze/game"))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()[ironfish_api::rest::NODE_BUDGET_REMAINING_HEADER],
+        "0"
+    );
+    let result: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(result["clamped"], true);
+    assert_eq!(result["depth"], 8);
+    assert_eq!(result["nodes_searched"], 20000);
+    let resp = client
+        .post(server.url("/v1/analyze/game"))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().contains_key("retry-after"));
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "node_budget_exhausted");
+    let usage: serde_json::Value = server
+        .admin_get(&format!(
+            "/_admin/tokens/{}/usage",
+            created["id"].as_str().unwrap()
+        ))
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(usage["node_budget"]["used"], 20000);
+}
+#[tokio::test]
+async fn test_node_budget_skips_cache_hits() {
+    let server = TestServer::with_cache().await;
+    let resp = server
+        .admin_post_json(
+            "/_admin/tokens",
+            &json!({ "name": "cached", "node_budget_per_hour": 1_000_000_000u64 }),
+        )
+        .await;
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let usage_path = format!("/_admin/tokens/{}/usage", created["id"].as_str().unwrap());
+    let client = reqwest::Client::new();
+    let mut used = Vec::new();
+    for _ in 0..2 {
+        let resp = client
+            .post(server.url("/v1/analyze"))
+            .bearer_auth(token)
+            .json(&json!({ "startpos": true, "depth": 8 }))
+            .send()
+            .await
+            .expect("request");
+        assert_eq!(resp.status(), 200);
+        let usage: serde_json::Value = server.admin_get(&usage_path).await.json().await.unwrap();
+        used.push(usage["node_budget"]["used"].as_u64().unwrap());
+    }
+    assert!(used[0] > 0);
+    assert_eq!(used[0], used[1]);
+}
+#[tokio::test]
+async fn test_token_rate_limit_enforced() {
+    let server = TestServer::with_auth().await;
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "rate_limit": 2 }))
+        .await;
+    let created: serde_json::Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let client = reqwest::Client::new();
+    let body = json!({ "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" });
+    for _ in 0..2 {
+        let resp = client
+            .post(server.url("/v1/bestmove"))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("request");
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = client
+        .post(server.url("/v1/bestmove"))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 429);
+    let retry_after: u64 = resp.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+    let resp = client
+        .get(server.url("/v1/health"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 200);
+    let resp = server.post_json("/v1/bestmove", &body).await;
+    assert_eq!(resp.status(), 200);
+}
+#[tokio::test]
+async fn test_webhooks_signed_and_retried() {
+    let received: Received = Arc::default();
+    let receiver = axum::Router::new()
+        .route(
+            "/hook",
+            axum::routing::post(
+                |axum::extract::State(received): axum::extract::State<Received>,
+                 headers: HeaderMap,
+                 body: String| async move {
+                    let mut received = received.lock().unwrap();
+                    received.push((headers, body));
+                    match received.len() {
+                        1 => StatusCode::INTERNAL_SERVER_ERROR,
+                        _ => StatusCode::OK,
+                    }
+                },
+            ),
+        )
+        .with_state(received.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+    let server = TestServer::with_webhooks(WebhookConfig {
+        hooks: vec![WebhookEndpoint {
+            url: format!("http://{}/hook", receiver_addr),
+            events: vec![WebhookEvent::AnalysisCompleted],
+            secret: Some("hook-secret".to_string()),
+        }],
+        initial_backoff_ms: 10,
+        ..Default::default()
+    })
+    .await;
+    let body = json!({"startpos": true, "depth": 5});
+    let resp = server.post_json("/v1/analyze?async=true", &body).await;
+    assert_eq!(resp.status(), 202);
+    let job: serde_json::Value = resp.json().await.expect("json");
+    for _ in 0..200 {
+        if received.lock().unwrap().len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].1, received[1].1);
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"hook-secret");
+    for (headers, body) in &received {
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        let tag: Vec<u8> = (7..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).unwrap())
+            .collect();
+        assert!(signature.starts_with("sha256="));
+        assert!(ring::hmac::verify(&key, body.as_bytes(), &tag).is_ok());
+        assert_eq!(headers["x-ironfish-event"], "analysis.completed");
+    }
+    let payload: serde_json::Value = serde_json::from_str(&received[0].1).unwrap();
+    assert_eq!(payload["event"], "analysis.completed");
+    assert_eq!(payload["node_id"], server.node_id.as_str());
+    assert_eq!(payload["data"]["analysis_id"], job["id"]);
+    assert!(payload["data"]["result"]["best_move"].is_object());
+    let mut deliveries = json!({});
+    for _ in 0..100 {
+        let resp = server.admin_get("/_admin/webhooks/deliveries").await;
+        assert_eq!(resp.status(), 200);
+        deliveries = resp.json().await.expect("json");
+        if deliveries["deliveries"].as_array().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let deliveries = deliveries["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(deliveries[0]["attempt"], 2);
+    assert_eq!(deliveries[1]["status"], "retrying");
+    assert_eq!(deliveries[1]["http_status"], 500);
+    let resp = TestServer::with_auth()
+        .await
+        .admin_get("/_admin/webhooks/deliveries")
+        .await;
+    assert_eq!(resp.status(), 503);
+}
+#[tokio::test]
+async fn test_idempotent_analyze_runs_once() {
+    let server = TestServer::with_auth().await;
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 12
+    });
+    let client = reqwest::Client::new();
+    let requests = (0..5).map(|_| {
+        client
+            .post(server.url("/v1/analyze"))
+            .header("Authorization", format!("Bearer {}", server.token))
+            .header("Idempotency-Key", "analyze-once")
+            .json(&body)
+            .send()
+    });
+    let mut bodies = Vec::new();
+    let mut replayed = 0;
+    for resp in futures_util::future::join_all(requests).await {
+        let resp = resp.expect("request");
+        assert_eq!(resp.status(), 200);
+        if resp.headers().get("idempotency-replayed").is_some() {
+            assert_eq!(resp.headers()["idempotency-replayed"], "true");
+            replayed += 1;
+        }
+        bodies.push(resp.bytes().await.expect("body"));
+    }
+    assert_eq!(replayed, 4);
+    assert!(bodies.iter().all(|b| b == &bodies[0]));
+    let resp = server.get("/v1/analyses").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let records = page["records"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["idempotency_key"], "analyze-once");
+    let resp = client
+        .post(server.url("/v1/analyze"))
+        .header("Authorization", format!("Bearer {}", server.token))
+        .header("Idempotency-Key", "analyze-once")
+        .json(&json!({ "fen": body["fen"], "depth": 8 }))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(resp.status(), 422);
+    let error: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(error["error"]["code"], "idempotency_key_reused");
+}
+#[tokio::test]
+async fn test_idempotent_token_creation() {
+    let server = TestServer::with_auth().await;
+    let create = || {
+        reqwest::Client::new()
+            .post(server.url("/_admin/tokens"))
+            .header("X-Admin-Key", &server.admin_key)
+            .header("Idempotency-Key", "create-ci-token")
+            .json(&json!({ "name": "ci" }))
+            .send()
+    };
+    let first = create().await.expect("request");
+    assert_eq!(first.status(), 200);
+    assert!(first.headers().get("idempotency-replayed").is_none());
+    let first: serde_json::Value = first.json().await.expect("json");
+    let second = create().await.expect("request");
+    assert_eq!(second.headers()["idempotency-replayed"], "true");
+    let second: serde_json::Value = second.json().await.expect("json");
+    assert!(first["token"].is_string());
+    assert_eq!(second["id"], first["id"]);
+    assert_eq!(second["expires_at"], first["expires_at"]);
+    assert!(second.get("token").is_none());
+    let tokens = server.token_store.list().await.unwrap();
+    assert_eq!(
+        tokens
+            .iter()
+            .filter(|t| t.name.as_deref() == Some("ci"))
+            .count(),
+        1
+    );
+    let resp = server.admin_get("/_admin/audit?action=token_create").await;
+    let page: serde_json::Value = resp.json().await.expect("json");
+    let entries: Vec<&serde_json::Value> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["target"] == first["id"])
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["idempotency_key"], "create-ci-token");
+}
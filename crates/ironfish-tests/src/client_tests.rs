@@ -0,0 +1,192 @@
+use crate::helpers::TestServer;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures_util::StreamExt;
+use ironfish_client::{AnalysisProgressEvent, ClientError, IronfishClient, RetryPolicy};
+use ironfish_core::{AnalysisRequest, BestMoveRequest, CreateTokenRequest, Variant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+async fn rate_limited_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let router = Router::new().route(
+        "/v1/health",
+        get(move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    return (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [("retry-after", "0")],
+                        Json(serde_json::json!({"error": "rate limit exceeded"})),
+                    )
+                        .into_response();
+                }
+                Json(serde_json::json!({
+                    "status": "healthy",
+                    "node_id": "stub",
+                    "version": "test",
+                    "token_store": "healthy"
+                }))
+                .into_response()
+            }
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.expect("serve");
+    });
+    (format!("http://{}", addr), hits)
+}
+#[tokio::test]
+async fn test_client_analyze_and_best_move() {
+    let server = TestServer::new().await;
+    let client = IronfishClient::new(server.url(""), Some(server.token.clone()));
+    let result = client
+        .analyze(&AnalysisRequest::new(START_FEN).with_depth(10))
+        .await
+        .expect("analyze");
+    assert_eq!(result.fen, START_FEN);
+    assert_eq!(result.variant, Variant::Standard);
+    let best = client
+        .best_move(&BestMoveRequest {
+            fen: START_FEN.to_string(),
+            movetime: Some(100),
+            nodes: None,
+            variant: Variant::Standard,
+            moves: Vec::new(),
+        })
+        .await
+        .expect("best move");
+    assert!(!best.best_move.unwrap().to_uci().is_empty());
+    let health = client.health().await.expect("health");
+    assert_eq!(health.status, "healthy");
+    assert!(client.cluster_status().await.is_ok());
+}
+#[tokio::test]
+async fn test_client_maps_structured_errors() {
+    let server = TestServer::new().await;
+    let client = IronfishClient::new(server.url(""), Some(server.token.clone()))
+        .with_retry_policy(RetryPolicy::none());
+    let err = client
+        .analyze(&AnalysisRequest::new("invalid-fen"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::InvalidFen(ref fen) if fen == "invalid-fen"));
+    let frc = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1";
+    let err = client
+        .analyze(&AnalysisRequest::new(frc))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("VARIANT_REQUIRED"));
+    assert!(matches!(
+        client.validate_fen(frc, Variant::Standard),
+        Err(ClientError::VariantRequired(_))
+    ));
+    assert!(client.validate_fen(frc, Variant::Chess960).is_ok());
+    server.token_store.mark_read_only();
+    let err = client
+        .admin(server.admin_key.clone())
+        .create_token(&CreateTokenRequest {
+            name: Some("blocked".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::StoreReadOnly));
+}
+#[tokio::test]
+async fn test_client_unauthorized() {
+    let server = TestServer::with_auth().await;
+    let client = IronfishClient::new(server.url(""), Some("iff_not-a-real-token".into()));
+    let err = client
+        .analyze(&AnalysisRequest::new(START_FEN))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Unauthorized(_)));
+    let err = client.admin("wrong-key").list_tokens().await.unwrap_err();
+    assert!(matches!(err, ClientError::Unauthorized(_)));
+    let admin = client.admin(server.admin_key.clone());
+    let created = admin
+        .create_token(&CreateTokenRequest {
+            name: Some("client-token".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .await
+        .expect("create token");
+    assert!(admin
+        .list_tokens()
+        .await
+        .expect("list tokens")
+        .iter()
+        .any(|t| t.id == created.id));
+    let client = IronfishClient::new(server.url(""), Some(created.token));
+    assert!(client
+        .analyze(&AnalysisRequest::new(START_FEN))
+        .await
+        .is_ok());
+    admin.revoke_token(&created.id).await.expect("revoke");
+    let err = client
+        .analyze(&AnalysisRequest::new(START_FEN))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Unauthorized(_)));
+}
+#[tokio::test]
+async fn test_client_retries_rate_limited() {
+    let (endpoint, hits) = rate_limited_server(2).await;
+    let client = IronfishClient::new(endpoint, None)
+        .with_retry_policy(RetryPolicy::default().with_base_delay(Duration::from_millis(10)));
+    let health = client.health().await.expect("health after retries");
+    assert_eq!(health.node_id, "stub");
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+    let (endpoint, hits) = rate_limited_server(usize::MAX).await;
+    let client = IronfishClient::new(endpoint, None)
+        .with_retry_policy(RetryPolicy::default().with_max_retries(1));
+    let err = client.health().await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::RateLimited { retry_after: Some(d) } if d.is_zero()
+    ));
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+}
+#[tokio::test]
+async fn test_client_analyze_streaming() {
+    let server = TestServer::new().await;
+    let client = IronfishClient::new(server.url(""), Some(server.token.clone()));
+    let request = AnalysisRequest::new(START_FEN).with_depth(20);
+    let stream = client
+        .analyze_streaming(&request)
+        .await
+        .expect("open stream");
+    let events: Vec<_> = tokio::time::timeout(Duration::from_secs(10), stream.collect())
+        .await
+        .expect("stream timeout");
+    let events: Vec<AnalysisProgressEvent> = events
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .expect("stream events");
+    assert!(events.iter().any(
+        |e| matches!(e, AnalysisProgressEvent::Progress { current_depth, .. } if *current_depth > 0)
+    ));
+    match events.last() {
+        Some(AnalysisProgressEvent::Complete(result)) => assert_eq!(result.fen, START_FEN),
+        other => panic!("expected completion, got {:?}", other),
+    }
+}
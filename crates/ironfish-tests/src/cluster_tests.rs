@@ -1,10 +1,63 @@
+use crate::helpers::{TestCluster, TEST_ADMIN_KEY};
+use async_trait::async_trait;
 use chrono::Utc;
+use ironfish_api::MetricsRegistry;
+use ironfish_auth::{SledTokenStore, TokenManager};
 use ironfish_cluster::{
-    discovery::StaticDiscovery, CpuAwareLoadBalancer, GossipService, LoadBalancerConfig,
-    MembershipManager, Node, NodeConfig,
+    consensus::RaftConsensus, discovery::StaticDiscovery, ClusterConfig, ClusterService,
+    ClusterStateStore, CpuAwareLoadBalancer, GossipService, LoadBalancerConfig, MembershipManager,
+    Node, NodeConfig, PendingWrites, QuorumPolicy,
 };
-use ironfish_core::{ClusterDiscovery, LoadBalancer, NodeId, NodeInfo, NodeMetrics};
+use ironfish_core::{
+    ApiToken, ClusterDiscovery, ClusterEvent, ConsensusProtocol, CreateTokenRequest, Error,
+    GossipMessage, JoinRequest, LeaveReason, LoadBalancer, NodeId, NodeInfo, NodeMetrics,
+    NodeState, Result, StoreHealth, TokenReplicaStatus, TokenStore,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+struct FlakyStore {
+    inner: SledTokenStore,
+    failing: AtomicBool,
+}
+impl FlakyStore {
+    fn check(&self) -> Result<()> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(Error::Storage("read-only file system".into()));
+        }
+        Ok(())
+    }
+}
+#[async_trait]
+impl TokenStore for FlakyStore {
+    async fn create(&self, token: ApiToken) -> Result<()> {
+        self.check()?;
+        self.inner.create(token).await
+    }
+    async fn get(&self, id: &Uuid) -> Result<Option<ApiToken>> {
+        self.inner.get(id).await
+    }
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ApiToken>> {
+        self.inner.get_by_hash(hash).await
+    }
+    async fn update(&self, token: ApiToken) -> Result<()> {
+        self.check()?;
+        self.inner.update(token).await
+    }
+    async fn delete(&self, id: &Uuid) -> Result<()> {
+        self.check()?;
+        self.inner.delete(id).await
+    }
+    async fn list(&self) -> Result<Vec<ApiToken>> {
+        self.inner.list().await
+    }
+    async fn revoke(&self, id: &Uuid) -> Result<()> {
+        self.check()?;
+        self.inner.revoke(id).await
+    }
+}
 #[tokio::test]
 async fn test_node_creation_with_auto_id() {
     let config = NodeConfig::default();
@@ -16,6 +69,7 @@ async fn test_node_creation_with_custom_id() {
     let config = NodeConfig {
         id: Some("custom-node-id".to_string()),
         bind_address: "127.0.0.1:8080".parse().unwrap(),
+        advertise_address: None,
         priority: 150,
         version: "1.0.0".to_string(),
     };
@@ -37,10 +91,59 @@ async fn test_membership_add_and_remove() {
     manager.add_member(peer.clone()).await;
     let peers = manager.list_members().await;
     assert_eq!(peers.len(), 1);
-    manager.remove_member(&peer.id).await;
+    manager.remove_member(&peer.id, LeaveReason::Removed).await;
     let peers = manager.list_members().await;
     assert_eq!(peers.len(), 0);
 }
+#[tokio::test(start_paused = true)]
+async fn test_event_bus_reports_join_and_leader_changes_in_order() {
+    let node = cluster_node("bus-a");
+    let membership = MembershipManager::new(node.clone());
+    let mut events = membership.subscribe_events();
+    let peer = NodeInfo {
+        id: NodeId::from_string("bus-b"),
+        address: "127.0.0.1:9".parse().unwrap(),
+        priority: 100,
+        started_at: Utc::now(),
+        version: "test".to_string(),
+    };
+    membership.add_member(peer.clone()).await;
+    let raft = RaftConsensus::new(node.clone())
+        .with_election_timeout_range(Duration::from_millis(100), Duration::from_millis(200));
+    raft.append_entries(ironfish_core::HeartbeatRequest {
+        leader_id: peer.id.clone(),
+        term: 1,
+        commit_index: 0,
+    })
+    .await
+    .unwrap();
+    raft.start().await.unwrap();
+    let mut received = Vec::new();
+    while received.len() < 4 {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("cluster event")
+            .unwrap();
+        received.push(event);
+    }
+    raft.stop().await.unwrap();
+    assert!(matches!(&received[0], ClusterEvent::NodeJoined { node } if node.id == peer.id));
+    assert!(matches!(
+        &received[1],
+        ClusterEvent::LeaderChanged { old_leader: None, new_leader: Some(leader), term: 1 }
+            if *leader == peer.id
+    ));
+    assert!(matches!(
+        &received[2],
+        ClusterEvent::LeaderChanged { old_leader: Some(old), new_leader: None, term: 2 }
+            if *old == peer.id
+    ));
+    assert!(matches!(
+        &received[3],
+        ClusterEvent::LeaderChanged { old_leader: None, new_leader: Some(leader), term: 2 }
+            if leader == node.id()
+    ));
+}
 #[tokio::test]
 async fn test_static_discovery_valid_peers() {
     let peers = vec![
@@ -183,3 +286,879 @@ async fn test_membership_cluster_status() {
     assert_eq!(status.nodes.len(), 1);
     assert!(status.healthy);
 }
+fn leader_node(id: &str) -> Arc<Node> {
+    let node = Arc::new(Node::new(NodeConfig {
+        id: Some(id.to_string()),
+        bind_address: "127.0.0.1:7000".parse().unwrap(),
+        advertise_address: None,
+        priority: 100,
+        version: "test".to_string(),
+    }));
+    node.set_state(NodeState::Leader);
+    node
+}
+fn join_request(id: &str, address: &str) -> JoinRequest {
+    JoinRequest {
+        node_info: NodeInfo {
+            id: NodeId::from_string(id),
+            address: address.parse().unwrap(),
+            priority: 100,
+            started_at: Utc::now(),
+            version: "test".to_string(),
+        },
+    }
+}
+#[tokio::test]
+async fn test_membership_rejoin_replaces_stale_node() {
+    let manager = MembershipManager::new(leader_node("leader"));
+    let response = manager
+        .join(join_request("peer-old", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    assert!(response.accepted);
+    assert!(response.reason.is_none());
+    let mut events = manager.subscribe_events();
+    let response = manager
+        .join(join_request("peer-new", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    assert!(response.accepted);
+    assert_eq!(response.members.len(), 1);
+    assert_eq!(response.members[0].id, NodeId::from_string("peer-new"));
+    match events.recv().await.unwrap() {
+        ClusterEvent::NodeLeft { node_id, reason } => {
+            assert_eq!(node_id, NodeId::from_string("peer-old"));
+            assert_eq!(reason, LeaveReason::Replaced);
+        }
+        other => panic!("expected NodeLeft, got {:?}", other),
+    }
+    assert!(matches!(
+        events.recv().await.unwrap(),
+        ClusterEvent::NodeJoined { .. }
+    ));
+    manager
+        .join(join_request("peer-new", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    assert!(events.try_recv().is_err());
+    assert_eq!(manager.list_members().await.len(), 1);
+    assert!(!manager.is_member(&NodeId::from_string("peer-old")).await);
+}
+#[tokio::test]
+async fn test_discovered_peers_do_not_replace_joined_members() {
+    let manager = MembershipManager::new(leader_node("leader"));
+    let discovered = join_request("127.0.0.1:7001", "127.0.0.1:7001").node_info;
+    manager.add_member(discovered.clone()).await;
+    manager
+        .join(join_request("peer-real", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    manager.add_member(discovered.clone()).await;
+    let members = manager.list_members().await;
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].id, NodeId::from_string("peer-real"));
+    assert_eq!(
+        manager.resolve(discovered).await.id,
+        NodeId::from_string("peer-real")
+    );
+    let other = join_request("peer-other", "127.0.0.1:7002").node_info;
+    assert_eq!(manager.resolve(other.clone()).await.id, other.id);
+}
+#[tokio::test]
+async fn test_membership_rejects_invalid_joins() {
+    let follower = Arc::new(Node::new(NodeConfig::default()));
+    let response = MembershipManager::new(follower)
+        .join(join_request("peer", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    assert!(!response.accepted);
+    assert!(response.reason.unwrap().contains("not the cluster leader"));
+    let api_address = |gossip: std::net::SocketAddr| format!("127.0.0.1:{}", gossip.port() - 100);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let open = api_address(listener.local_addr().unwrap());
+    let closed = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        api_address(listener.local_addr().unwrap())
+    };
+    let manager =
+        MembershipManager::new(leader_node("leader")).with_join_probe(Duration::from_millis(500));
+    for (request, reason) in [
+        (join_request("", "127.0.0.1:7001"), "empty"),
+        (
+            join_request("leader", "127.0.0.1:7001"),
+            "belongs to the leader",
+        ),
+        (
+            join_request("peer", "127.0.0.1:7000"),
+            "belongs to the leader",
+        ),
+        (join_request("peer", "0.0.0.0:7001"), "not routable"),
+        (join_request("peer", &closed), "unreachable"),
+    ] {
+        let response = manager.join(request).await.unwrap();
+        assert!(!response.accepted);
+        assert!(response.reason.unwrap().contains(reason));
+    }
+    assert!(manager.list_members().await.is_empty());
+    let response = manager.join(join_request("peer", &open)).await.unwrap();
+    assert!(response.accepted);
+}
+#[tokio::test(start_paused = true)]
+async fn test_membership_quorum_health_transitions() {
+    let manager = MembershipManager::new(leader_node("leader"))
+        .with_member_stale_after(Duration::from_millis(200));
+    for (id, address) in [("peer-1", "127.0.0.1:7001"), ("peer-2", "127.0.0.1:7002")] {
+        manager.join(join_request(id, address)).await.unwrap();
+    }
+    let status = manager.cluster_status().await;
+    assert!(status.healthy);
+    assert!(status.nodes[1..].iter().all(|n| n.last_seen_ms.is_some()));
+    tokio::time::advance(Duration::from_millis(300)).await;
+    assert!(!manager.cluster_status().await.healthy);
+    manager
+        .update_member_metrics(&NodeId::from_string("peer-1"), NodeMetrics::default())
+        .await;
+    assert!(manager.cluster_status().await.healthy);
+    manager
+        .remove_member(&NodeId::from_string("peer-2"), LeaveReason::Removed)
+        .await;
+    assert!(manager.cluster_status().await.healthy);
+    tokio::time::advance(Duration::from_millis(300)).await;
+    assert!(!manager.cluster_status().await.healthy);
+    let disabled = MembershipManager::new(leader_node("leader"))
+        .with_member_stale_after(Duration::ZERO)
+        .with_quorum_policy(QuorumPolicy::Disabled);
+    disabled
+        .join(join_request("peer-1", "127.0.0.1:7001"))
+        .await
+        .unwrap();
+    assert!(disabled.cluster_status().await.healthy);
+    assert!(QuorumPolicy::Majority.satisfied(2, 3));
+    assert!(!QuorumPolicy::Majority.satisfied(2, 4));
+    assert!(!QuorumPolicy::All.satisfied(2, 3));
+}
+#[tokio::test]
+async fn test_pending_writes_flush_after_recovery() {
+    let store = FlakyStore {
+        inner: SledTokenStore::in_memory().unwrap(),
+        failing: AtomicBool::new(true),
+    };
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let (token, _) = manager
+        .create(CreateTokenRequest {
+            name: Some("replicated".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .unwrap();
+    let pending = PendingWrites::new(2);
+    pending
+        .submit(&GossipMessage::TokenCreated(token.clone()), &store)
+        .await
+        .unwrap();
+    pending
+        .submit(&GossipMessage::TokenRevoked(token.id), &store)
+        .await
+        .unwrap();
+    assert_eq!(pending.len().await, 2);
+    assert_eq!(store.health(), StoreHealth::Healthy);
+    assert!(store.get(&token.id).await.unwrap().is_none());
+    assert!(pending.flush(&store).await.is_err());
+    assert_eq!(pending.len().await, 2);
+    store.failing.store(false, Ordering::SeqCst);
+    assert_eq!(pending.flush(&store).await.unwrap(), 2);
+    assert!(pending.is_empty().await);
+    assert!(store.get(&token.id).await.unwrap().unwrap().revoked);
+}
+#[tokio::test]
+async fn test_pending_writes_bounded_without_evicting_revocations() {
+    let store = FlakyStore {
+        inner: SledTokenStore::in_memory().unwrap(),
+        failing: AtomicBool::new(true),
+    };
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let token = || {
+        manager
+            .create(CreateTokenRequest {
+                name: None,
+                expires_in_days: None,
+                rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
+            })
+            .unwrap()
+            .0
+    };
+    let pending = PendingWrites::new(2);
+    let revoked: Vec<ApiToken> = (0..3).map(|_| token()).collect();
+    for created in &revoked {
+        store.inner.create(created.clone()).await.unwrap();
+    }
+    pending
+        .submit(&GossipMessage::TokenCreated(token()), &store)
+        .await
+        .unwrap();
+    for created in &revoked {
+        pending
+            .submit(&GossipMessage::TokenRevoked(created.id), &store)
+            .await
+            .unwrap();
+        pending
+            .submit(&GossipMessage::TokenRevoked(created.id), &store)
+            .await
+            .unwrap();
+    }
+    pending
+        .submit(&GossipMessage::TokenCreated(token()), &store)
+        .await
+        .unwrap();
+    assert_eq!(pending.len().await, 3);
+    store.failing.store(false, Ordering::SeqCst);
+    assert_eq!(pending.flush(&store).await.unwrap(), 3);
+    for created in &revoked {
+        assert!(store.get(&created.id).await.unwrap().unwrap().revoked);
+    }
+    assert_eq!(store.list().await.unwrap().len(), 3);
+}
+fn free_udp_port() -> u16 {
+    std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|s| s.local_addr())
+        .map(|a| a.port())
+        .unwrap()
+}
+fn cluster_node(id: &str) -> Arc<Node> {
+    let gossip_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .unwrap();
+    Arc::new(Node::new(NodeConfig {
+        id: Some(id.to_string()),
+        bind_address: format!("127.0.0.1:{}", gossip_port - 100).parse().unwrap(),
+        advertise_address: None,
+        priority: 100,
+        version: "test".to_string(),
+    }))
+}
+fn cluster_config(multicast_port: u16, static_peers: Vec<String>) -> ClusterConfig {
+    ClusterConfig {
+        discovery_interval: Duration::from_millis(100),
+        gossip_interval: Duration::from_millis(100),
+        multicast_port,
+        static_peers,
+        ..Default::default()
+    }
+}
+#[tokio::test]
+async fn test_late_joiner_receives_tokens_via_sync() {
+    let multicast_port = free_udp_port();
+    let node_a = cluster_node("node-a");
+    let store_a = Arc::new(SledTokenStore::in_memory().unwrap());
+    let cluster_a = ClusterService::new(
+        cluster_config(multicast_port, vec![]),
+        node_a.clone(),
+        Arc::new(MembershipManager::new(node_a.clone())),
+        store_a,
+    )
+    .unwrap();
+    cluster_a.start().await.unwrap();
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let (token, _) = manager
+        .create(CreateTokenRequest {
+            name: Some("before-join".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .unwrap();
+    cluster_a
+        .broadcast_token_created(token.clone())
+        .await
+        .unwrap();
+    let node_b = cluster_node("node-b");
+    let store_b = Arc::new(SledTokenStore::in_memory().unwrap());
+    let cluster_b = ClusterService::new(
+        cluster_config(multicast_port, vec![node_a.info().address.to_string()]),
+        node_b.clone(),
+        Arc::new(MembershipManager::new(node_b.clone())),
+        store_b.clone(),
+    )
+    .unwrap();
+    cluster_b.start().await.unwrap();
+    let mut replicated = None;
+    for _ in 0..50 {
+        replicated = store_b.get(&token.id).await.unwrap();
+        if replicated.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(replicated.map(|t| t.name), Some(token.name.clone()));
+    let synced = cluster_b.gossip().entries_since(&HashMap::new()).await;
+    assert!(synced.iter().any(|e| e.origin == *node_a.id()));
+    cluster_b.stop().await.unwrap();
+    cluster_a.stop().await.unwrap();
+}
+#[tokio::test]
+async fn test_node_metrics_are_gossiped() {
+    let multicast_port = free_udp_port();
+    let node_a = cluster_node("metrics-a");
+    node_a.update_metrics(NodeMetrics {
+        cpu_usage: 0.42,
+        queue_depth: 7,
+        ..Default::default()
+    });
+    let cluster_a = ClusterService::new(
+        cluster_config(multicast_port, vec![]),
+        node_a.clone(),
+        Arc::new(MembershipManager::new(node_a.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_a.start().await.unwrap();
+    let node_b = cluster_node("metrics-b");
+    let membership_b = Arc::new(MembershipManager::new(node_b.clone()));
+    membership_b.add_member(node_a.info().clone()).await;
+    let cluster_b = ClusterService::new(
+        cluster_config(multicast_port, vec![node_a.info().address.to_string()]),
+        node_b.clone(),
+        membership_b.clone(),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_b.start().await.unwrap();
+    let mut reported = None;
+    for _ in 0..50 {
+        cluster_a
+            .broadcast(GossipMessage::NodeMetrics(
+                node_a.id().clone(),
+                node_a.metrics(),
+            ))
+            .await
+            .unwrap();
+        reported = membership_b
+            .cluster_status()
+            .await
+            .nodes
+            .into_iter()
+            .find(|n| n.info.id == *node_a.id() && n.metrics.is_some());
+        if reported.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let reported = reported.expect("metrics from node a");
+    let metrics = reported.metrics.unwrap();
+    assert_eq!(metrics.cpu_usage, 0.42);
+    assert_eq!(metrics.queue_depth, 7);
+    assert!(!reported.metrics_stale);
+    cluster_b.stop().await.unwrap();
+    cluster_a.stop().await.unwrap();
+}
+async fn wait_for_membership(membership: &MembershipManager, peer: &NodeId, expected: bool) {
+    for _ in 0..50 {
+        if membership.is_member(peer).await == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("membership of {} never became {}", peer, expected);
+}
+#[tokio::test]
+async fn test_health_check_evicts_and_recovers_peers() {
+    let node_a = cluster_node("node-a");
+    let node_b = cluster_node("node-b");
+    let static_peer = node_b.info().address.to_string();
+    let static_id = NodeId::from_string(&static_peer);
+    let membership_a = Arc::new(MembershipManager::new(node_a.clone()));
+    let cluster_a = ClusterService::new(
+        ClusterConfig {
+            health_check_interval: Duration::from_millis(100),
+            peer_failure_threshold: 2,
+            ..cluster_config(free_udp_port(), vec![static_peer])
+        },
+        node_a.clone(),
+        membership_a.clone(),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_a.start().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    assert!(!membership_a.is_member(&static_id).await);
+    assert_eq!(membership_a.cluster_status().await.nodes.len(), 1);
+    let cluster_b = ClusterService::new(
+        cluster_config(free_udp_port(), vec![]),
+        node_b.clone(),
+        Arc::new(MembershipManager::new(node_b.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_b.start().await.unwrap();
+    wait_for_membership(&membership_a, &static_id, true).await;
+    cluster_b.stop().await.unwrap();
+    wait_for_membership(&membership_a, &static_id, false).await;
+    cluster_a.stop().await.unwrap();
+}
+async fn wait_for_single_leader(nodes: &[Arc<Node>]) -> Arc<Node> {
+    for _ in 0..100 {
+        let leaders: Vec<_> = nodes.iter().filter(|n| n.is_leader()).collect();
+        if let [leader] = leaders[..] {
+            if nodes
+                .iter()
+                .all(|n| n.leader().as_ref() == Some(leader.id()))
+            {
+                return leader.clone();
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("cluster never converged on a single leader");
+}
+#[tokio::test]
+async fn test_cluster_elects_single_leader_and_reelects() {
+    let nodes: Vec<_> = ["raft-a", "raft-b", "raft-c"]
+        .iter()
+        .map(|id| cluster_node(id))
+        .collect();
+    let mut clusters = Vec::new();
+    for node in &nodes {
+        let peers = nodes
+            .iter()
+            .filter(|n| n.id() != node.id())
+            .map(|n| n.info().address.to_string())
+            .collect();
+        let cluster = ClusterService::new(
+            ClusterConfig {
+                heartbeat_interval: Duration::from_millis(50),
+                election_timeout_min: Duration::from_millis(300),
+                election_timeout_max: Duration::from_millis(600),
+                ..cluster_config(free_udp_port(), peers)
+            },
+            node.clone(),
+            Arc::new(MembershipManager::new(node.clone())),
+            Arc::new(SledTokenStore::in_memory().unwrap()),
+        )
+        .unwrap();
+        cluster.start().await.unwrap();
+        clusters.push(cluster);
+    }
+    let leader = wait_for_single_leader(&nodes).await;
+    assert_eq!(leader.id().0, "raft-c");
+    let term = leader.term();
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert_eq!(wait_for_single_leader(&nodes).await.id(), leader.id());
+    let index = nodes.iter().position(|n| n.id() == leader.id()).unwrap();
+    clusters.remove(index).stop().await.unwrap();
+    let survivors: Vec<_> = nodes
+        .iter()
+        .filter(|n| n.id() != leader.id())
+        .cloned()
+        .collect();
+    let successor = wait_for_single_leader(&survivors).await;
+    assert_eq!(successor.id().0, "raft-b");
+    assert!(successor.term() > term);
+    for cluster in clusters {
+        cluster.stop().await.unwrap();
+    }
+}
+#[tokio::test]
+async fn test_mismatched_cluster_secrets_do_not_exchange_gossip() {
+    let secret_config = |secret: &str, static_peers: Vec<String>| ClusterConfig {
+        secret: Some(secret.to_string()),
+        ..cluster_config(free_udp_port(), static_peers)
+    };
+    let node_a = cluster_node("secret-a");
+    let cluster_a = ClusterService::new(
+        secret_config("alpha", vec![]),
+        node_a.clone(),
+        Arc::new(MembershipManager::new(node_a.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_a.start().await.unwrap();
+    let mut peers = Vec::new();
+    for (id, secret) in [("secret-b", "alpha"), ("secret-c", "beta")] {
+        let node = cluster_node(id);
+        let store = Arc::new(SledTokenStore::in_memory().unwrap());
+        let cluster = ClusterService::new(
+            secret_config(secret, vec![node_a.info().address.to_string()]),
+            node.clone(),
+            Arc::new(MembershipManager::new(node.clone())),
+            store.clone(),
+        )
+        .unwrap();
+        cluster.start().await.unwrap();
+        peers.push((cluster, store));
+    }
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let (token, _) = manager
+        .create(CreateTokenRequest {
+            name: Some("shared-secret".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .unwrap();
+    cluster_a
+        .broadcast_token_created(token.clone())
+        .await
+        .unwrap();
+    let (_, ref store_b) = peers[0];
+    let mut replicated = None;
+    for _ in 0..50 {
+        replicated = store_b.get(&token.id).await.unwrap();
+        if replicated.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(replicated.is_some());
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (ref cluster_c, ref store_c) = peers[1];
+    assert!(store_c.get(&token.id).await.unwrap().is_none());
+    assert!(cluster_c
+        .gossip()
+        .entries_since(&HashMap::new())
+        .await
+        .is_empty());
+    for (cluster, _) in peers {
+        cluster.stop().await.unwrap();
+    }
+    cluster_a.stop().await.unwrap();
+}
+#[tokio::test]
+async fn test_gossip_is_processed_once_per_node() {
+    let multicast_port = free_udp_port();
+    let ids = ["dedup-a", "dedup-b", "dedup-c"];
+    let mut nodes: Vec<(Arc<Node>, ClusterService<_>, MetricsRegistry)> = Vec::new();
+    for id in ids {
+        let node = cluster_node(id);
+        let static_peers = nodes
+            .iter()
+            .map(|(n, _, _)| n.info().address.to_string())
+            .collect();
+        let metrics = MetricsRegistry::new();
+        let cluster = ClusterService::new(
+            cluster_config(multicast_port, static_peers),
+            node.clone(),
+            Arc::new(MembershipManager::new(node.clone())),
+            Arc::new(SledTokenStore::in_memory().unwrap()),
+        )
+        .unwrap()
+        .with_telemetry(metrics.telemetry());
+        cluster.start().await.unwrap();
+        nodes.push((node, cluster, metrics));
+    }
+    for _ in 0..50 {
+        let mut meshed = true;
+        for (node, cluster, _) in &nodes {
+            let peers = cluster.network().healthy_peers().await;
+            meshed &= ids
+                .iter()
+                .filter(|id| **id != node.id().0)
+                .all(|id| peers.iter().any(|p| p.id.0 == *id));
+        }
+        if meshed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let (token, _) = manager
+        .create(CreateTokenRequest {
+            name: Some("dedup".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .unwrap();
+    nodes[0].1.broadcast_token_created(token).await.unwrap();
+    for _ in 0..100 {
+        let settled = nodes[1..].iter().all(|(_, _, metrics)| {
+            let rendered = metrics.render();
+            rendered.contains("ironfish_gossip_messages_total{type=\"token_created\"}")
+                && rendered.contains("ironfish_gossip_duplicates_total ")
+        });
+        if settled {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    for (_, _, metrics) in &nodes[1..] {
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("ironfish_gossip_messages_total{type=\"token_created\"} 1\n"),
+            "{}",
+            rendered
+        );
+    }
+    assert!(!nodes[0].2.render().contains("type=\"token_created\""));
+    for (_, cluster, _) in nodes {
+        cluster.stop().await.unwrap();
+    }
+}
+#[tokio::test]
+async fn test_restarted_node_restores_persisted_peers() {
+    let node_a = cluster_node("persist-a");
+    let cluster_a = ClusterService::new(
+        cluster_config(free_udp_port(), vec![]),
+        node_a.clone(),
+        Arc::new(MembershipManager::new(node_a.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap();
+    cluster_a.start().await.unwrap();
+    let node_b = cluster_node("persist-b");
+    let state = ClusterStateStore::in_memory().unwrap();
+    let cluster_b = ClusterService::new(
+        cluster_config(free_udp_port(), vec![node_a.info().address.to_string()]),
+        node_b.clone(),
+        Arc::new(MembershipManager::new(node_b.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap()
+    .with_state_store(state.clone());
+    cluster_b.start().await.unwrap();
+    let manager = TokenManager::new(&TokenManager::generate_secret(), "test");
+    let (token, _) = manager
+        .create(CreateTokenRequest {
+            name: Some("persisted".into()),
+            expires_in_days: None,
+            rate_limit: None,
+            scopes: Vec::new(),
+            max_depth: None,
+            max_multipv: None,
+            node_budget_per_hour: None,
+        })
+        .unwrap();
+    cluster_a.broadcast_token_created(token).await.unwrap();
+    for _ in 0..50 {
+        let peers = state.load_peers(Duration::from_secs(60)).unwrap();
+        if peers.iter().any(|p| p.address == node_a.info().address)
+            && !state.gossip_watermarks().unwrap().is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    cluster_b.stop().await.unwrap();
+    assert!(!state.gossip_watermarks().unwrap().is_empty());
+    let node_b = cluster_node("persist-b");
+    let restarted = ClusterService::new(
+        cluster_config(free_udp_port(), vec![]),
+        node_b.clone(),
+        Arc::new(MembershipManager::new(node_b.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap()
+    .with_state_store(state.clone());
+    restarted.start().await.unwrap();
+    let peers = restarted.network().peers().await;
+    assert!(peers.iter().any(|p| p.address == node_a.info().address));
+    restarted.stop().await.unwrap();
+    let node_b = cluster_node("persist-b");
+    let stale = ClusterService::new(
+        ClusterConfig {
+            persisted_peer_ttl: Duration::ZERO,
+            ..cluster_config(free_udp_port(), vec![])
+        },
+        node_b.clone(),
+        Arc::new(MembershipManager::new(node_b.clone())),
+        Arc::new(SledTokenStore::in_memory().unwrap()),
+    )
+    .unwrap()
+    .with_state_store(state);
+    stale.start().await.unwrap();
+    assert_eq!(stale.peer_count().await, 0);
+    stale.stop().await.unwrap();
+    cluster_a.stop().await.unwrap();
+}
+#[tokio::test]
+async fn test_cluster_token_replication() {
+    let mut cluster = TestCluster::start(3).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    let resp = cluster
+        .node(0)
+        .admin_post_json(
+            "/_admin/tokens",
+            &serde_json::json!({ "name": "replicated-token" }),
+        )
+        .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let token = body["token"].as_str().unwrap();
+    for node in &cluster.nodes {
+        let mut replicated = false;
+        for _ in 0..30 {
+            let tokens = node.token_store.list().await.unwrap();
+            if tokens
+                .iter()
+                .any(|t| t.name.as_deref() == Some("replicated-token"))
+            {
+                replicated = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(replicated, "token never reached {}", node.node_id);
+        let resp = node
+            .post_json(
+                "/v1/analyze",
+                token,
+                &serde_json::json!({
+                    "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                    "depth": 5
+                }),
+            )
+            .await;
+        assert_eq!(resp.status(), 200);
+    }
+    cluster.stop().await;
+}
+#[tokio::test]
+async fn test_cluster_revocation_confirmed() {
+    let mut cluster = TestCluster::start(3).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    let resp = cluster
+        .node(0)
+        .admin_post_json(
+            "/_admin/tokens",
+            &serde_json::json!({ "name": "confirmed" }),
+        )
+        .await;
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+    for node in &cluster.nodes {
+        for _ in 0..30 {
+            if node.token_store.get(&id).await.unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    let resp = reqwest::Client::new()
+        .delete(
+            cluster
+                .node(0)
+                .url(&format!("/_admin/tokens/{}?confirm_cluster=true", id)),
+        )
+        .header("X-Admin-Key", TEST_ADMIN_KEY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["confirmed"], true, "{}", body);
+    let nodes = body["nodes"].as_object().unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert!(nodes.values().all(|status| status == "revoked"), "{}", body);
+    let report = cluster.node(1).state().token_replicas(id).await;
+    assert!(report.confirmed);
+    assert!(report
+        .nodes
+        .values()
+        .all(|status| *status == TokenReplicaStatus::Revoked));
+    cluster.stop().await;
+}
+#[tokio::test]
+async fn test_cluster_revocation_report_retries_until_deadline() {
+    let mut cluster = TestCluster::start(3).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    let resp = cluster
+        .node(0)
+        .admin_post_json("/_admin/tokens", &serde_json::json!({ "name": "stale" }))
+        .await;
+    let body: serde_json::Value = resp.json().await.unwrap();
+    let id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+    for node in &cluster.nodes {
+        for _ in 0..30 {
+            if node.token_store.get(&id).await.unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    cluster.stop_node(2).await;
+    cluster.node(0).token_store.revoke(&id).await.unwrap();
+    let started = std::time::Instant::now();
+    let report = cluster.node(0).state().token_replicas(id).await;
+    let elapsed = started.elapsed();
+    let status = |idx: usize| {
+        report
+            .nodes
+            .get(&cluster.node(idx).addr.to_string())
+            .copied()
+    };
+    assert!(!report.confirmed, "{:?}", report);
+    assert_eq!(
+        report.nodes.get("cluster-node-1").copied(),
+        Some(TokenReplicaStatus::Revoked)
+    );
+    assert_eq!(status(1), Some(TokenReplicaStatus::Present));
+    assert_eq!(status(2), Some(TokenReplicaStatus::Unreachable));
+    assert!(
+        elapsed >= Duration::from_millis(800) && elapsed < Duration::from_secs(3),
+        "report took {:?}",
+        elapsed
+    );
+    cluster.stop().await;
+}
+#[tokio::test]
+async fn test_readiness_checks_node_state() {
+    let mut cluster = TestCluster::start(2).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    let body: serde_json::Value = reqwest::get(cluster.node(0).url("/v1/health"))
+        .await
+        .expect("health")
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(body["status"], "healthy");
+    assert_eq!(body["checks"][2]["name"], "cluster");
+    assert_eq!(body["checks"][2]["status"], "pass");
+    cluster
+        .node(1)
+        .state()
+        .node
+        .set_state(ironfish_core::NodeState::Starting);
+    let resp = reqwest::get(cluster.node(1).url("/ready"))
+        .await
+        .expect("ready");
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = resp.json().await.expect("json");
+    assert_eq!(body["checks"][2]["status"], "fail");
+    assert_eq!(body["checks"][2]["detail"], "node state is starting");
+    cluster.stop().await;
+}
+#[tokio::test]
+async fn test_cluster_node_failure_recovery() {
+    let mut cluster = TestCluster::start(3).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    cluster.stop_node(1).await;
+    assert!(cluster.node(0).health().await);
+    assert!(!cluster.node(1).health().await);
+    assert!(cluster.node(2).health().await);
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    assert_eq!(cluster.node(0).state().membership.member_count().await, 2);
+    cluster.restart_node(1).await;
+    assert!(cluster.wait_for_convergence(Duration::from_secs(3)).await);
+    assert!(cluster.node(1).health().await);
+    for node in &cluster.nodes {
+        assert_eq!(node.state().membership.member_count().await, 3);
+    }
+    cluster.stop().await;
+}
@@ -59,6 +59,7 @@ async fn test_cluster_analysis_distribution() {
             .await
             .expect("analyze");
         assert_eq!(resp.status(), 200);
+        assert!(resp.headers().contains_key("x-ironfish-node"));
     }
     cluster.stop().await;
 }
@@ -113,6 +114,59 @@ async fn test_cluster_node_failure_recovery() {
 }
 #[tokio::test]
 #[serial]
+async fn test_cluster_nodes_report_cpu_usage() {
+    let cluster = DockerCluster::start(2).await;
+    assert!(cluster.wait_healthy(120).await, "cluster not healthy");
+    let client = reqwest::Client::new();
+    let token_resp = client
+        .post(format!("{}/_admin/tokens", cluster.nodes[0]))
+        .header("X-Admin-Key", TEST_ADMIN_KEY)
+        .json(&json!({ "name": "metrics-test" }))
+        .send()
+        .await
+        .expect("create token");
+    let token_data: serde_json::Value = token_resp.json().await.expect("json");
+    let token = token_data["token"]
+        .as_str()
+        .expect("token string")
+        .to_string();
+    let body = json!({
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 40,
+        "movetime": 6000
+    });
+    let mut searches = Vec::new();
+    for node_url in [&cluster.nodes[0], &cluster.nodes[0], &cluster.nodes[1]] {
+        let request = client
+            .post(format!("{}/v1/analyze", node_url))
+            .bearer_auth(&token)
+            .json(&body)
+            .send();
+        searches.push(tokio::spawn(request));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+    let mut cpu = Vec::new();
+    for node_url in &cluster.nodes {
+        let metrics: serde_json::Value = client
+            .get(format!("{}/v1/metrics", node_url))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("metrics")
+            .json()
+            .await
+            .expect("json");
+        cpu.push(metrics["cpu_usage"].as_f64().expect("cpu_usage"));
+    }
+    assert!(cpu.iter().all(|c| *c > 0.0), "cpu usage: {:?}", cpu);
+    assert_ne!(cpu[0], cpu[1]);
+    for search in searches {
+        let _ = search.await;
+    }
+    cluster.stop().await;
+}
+#[tokio::test]
+#[serial]
 async fn test_cluster_graphql_across_nodes() {
     let cluster = DockerCluster::start(3).await;
     assert!(cluster.wait_healthy(120).await, "cluster not healthy");
@@ -166,27 +220,47 @@ async fn test_multiplexed_grpc() {
     let cluster = DockerCluster::start(1).await;
     assert!(cluster.wait_healthy(60).await, "cluster not healthy");
     let addr = cluster.nodes[0].clone();
+    let token_resp = reqwest::Client::new()
+        .post(format!("{}/_admin/tokens", addr))
+        .header("X-Admin-Key", TEST_ADMIN_KEY)
+        .json(&json!({ "name": "grpc-token" }))
+        .send()
+        .await
+        .expect("create token");
+    assert_eq!(token_resp.status(), 200);
+    let token_data: serde_json::Value = token_resp.json().await.expect("json");
+    let token = token_data["token"].as_str().expect("token string");
     let mut client = ChessAnalysisClient::connect(addr)
         .await
         .expect("failed to connect grpc");
-    let request = tonic::Request::new(AnalyzeRequest {
-        fen: "startpos".to_string(),
+    let request = || AnalyzeRequest {
+        fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
         depth: 10,
         multipv: 1,
         movetime_ms: None,
-    });
-    let response: Result<_, tonic::Status> = client.analyze(request).await;
-
-    if let Err(status) = response {
-        assert!(
-            matches!(
-                status.code(),
-                tonic::Code::Unauthenticated | tonic::Code::Internal
-            ),
-            "Expected Unauthenticated or Internal, got: {:?}",
-            status
-        );
-    }
+        nodes: None,
+        variant: String::new(),
+        startpos: false,
+        moves: Vec::new(),
+        search_moves: Vec::new(),
+        include_history: false,
+        perspective: String::new(),
+    };
+    let status = client
+        .analyze(tonic::Request::new(request()))
+        .await
+        .expect_err("analysis without a token");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let mut authorized = tonic::Request::new(request());
+    authorized.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().expect("metadata"),
+    );
+    let response = client
+        .analyze(authorized)
+        .await
+        .expect("authorized analysis");
+    assert!(response.into_inner().best_move.is_some());
 
     cluster.stop().await;
 }
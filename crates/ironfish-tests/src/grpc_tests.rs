@@ -0,0 +1,507 @@
+use crate::helpers::{TestServer, TEST_CLUSTER_SECRET};
+use ironfish_api::proto::chess_analysis_client::ChessAnalysisClient;
+use ironfish_api::proto::cluster_admin_client::ClusterAdminClient;
+use ironfish_api::proto::{
+    play_request, play_update, AnalyzeRequest, JoinRequest, LeaveRequest, PlayPosition,
+    PlayRequest, PlayUpdate,
+};
+use std::time::Duration;
+use tonic::transport::Channel;
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+async fn connect(server: &TestServer) -> ChessAnalysisClient<Channel> {
+    ChessAnalysisClient::connect(server.url(""))
+        .await
+        .expect("grpc connect")
+}
+fn analyze_request(fen: &str) -> AnalyzeRequest {
+    AnalyzeRequest {
+        fen: fen.to_string(),
+        depth: 20,
+        multipv: 1,
+        movetime_ms: None,
+        nodes: None,
+        variant: String::new(),
+        startpos: false,
+        moves: Vec::new(),
+        search_moves: Vec::new(),
+        include_history: false,
+        perspective: String::new(),
+    }
+}
+#[tokio::test]
+async fn test_grpc_stream_analysis_progress() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let mut stream = client
+        .stream_analysis(analyze_request(START_FEN))
+        .await
+        .expect("stream")
+        .into_inner();
+    let mut updates = Vec::new();
+    while let Some(update) = stream.message().await.expect("update") {
+        updates.push(update);
+    }
+    let (last, progress) = updates.split_last().expect("updates");
+    assert!(progress.len() >= 2);
+    assert!(progress.iter().all(|u| u.result.is_none()));
+    assert!(progress.iter().all(|u| u.id == last.id));
+    assert!(progress[0].evaluation.is_some());
+    assert!(!progress[0].principal_variations.is_empty());
+    assert!(progress[0].nodes_per_second > 0);
+    let result = last.result.as_ref().expect("final result");
+    assert_eq!(result.fen, START_FEN);
+    assert!(result.best_move.is_some());
+    assert_eq!(last.target_depth, 20);
+}
+#[tokio::test]
+async fn test_grpc_stream_analysis_follows_perspective() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let black = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+    let mut scores = Vec::new();
+    for perspective in ["", "side_to_move"] {
+        let mut stream = client
+            .stream_analysis(AnalyzeRequest {
+                depth: 10,
+                perspective: perspective.to_string(),
+                ..analyze_request(black)
+            })
+            .await
+            .expect("stream")
+            .into_inner();
+        let mut values = Vec::new();
+        while let Some(update) = stream.message().await.expect("update") {
+            let evaluation = match update.result {
+                Some(result) => result.evaluation,
+                None => update.evaluation,
+            };
+            values.push(evaluation.expect("evaluation").value);
+        }
+        scores.push(values);
+    }
+    assert_eq!(scores[0].last(), Some(&-30));
+    assert_eq!(scores[1].last(), Some(&30));
+    let flipped: Vec<i32> = scores[1].iter().map(|value| -value).collect();
+    assert_eq!(scores[0], flipped);
+    let status = client
+        .stream_analysis(AnalyzeRequest {
+            perspective: "black".to_string(),
+            ..analyze_request(black)
+        })
+        .await
+        .expect_err("unknown perspective");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+#[tokio::test]
+async fn test_grpc_analyze_score_history_is_opt_in() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let response = client
+        .analyze(analyze_request(START_FEN))
+        .await
+        .expect("analyze")
+        .into_inner();
+    assert!(response.score_history.is_empty());
+    let response = client
+        .analyze(AnalyzeRequest {
+            include_history: true,
+            ..analyze_request(START_FEN)
+        })
+        .await
+        .expect("analyze")
+        .into_inner();
+    assert_eq!(response.score_history.len(), 20);
+    assert!(response
+        .score_history
+        .windows(2)
+        .all(|w| w[0].depth < w[1].depth));
+    assert_eq!(response.score_history[19].depth, response.depth_reached);
+    assert_eq!(response.score_history[19].evaluation, response.evaluation);
+}
+#[tokio::test]
+async fn test_grpc_analyze_enforces_parameter_limits() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let requests = [
+        AnalyzeRequest {
+            depth: 256,
+            ..analyze_request(START_FEN)
+        },
+        AnalyzeRequest {
+            multipv: 0,
+            ..analyze_request(START_FEN)
+        },
+        AnalyzeRequest {
+            movetime_ms: Some(60_001),
+            ..analyze_request(START_FEN)
+        },
+    ];
+    for request in requests {
+        let status = client.analyze(request).await.expect_err("out of range");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("must be between"));
+    }
+    let status = client
+        .stream_analysis(AnalyzeRequest {
+            depth: 0,
+            ..analyze_request(START_FEN)
+        })
+        .await
+        .expect_err("out of range");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+#[tokio::test]
+async fn test_grpc_stream_analysis_invalid_fen() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let status = client
+        .stream_analysis(analyze_request("not a fen"))
+        .await
+        .expect_err("invalid fen");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+#[tokio::test]
+async fn test_grpc_request_id_metadata_round_trips() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let mut request = tonic::Request::new(analyze_request(START_FEN));
+    request
+        .metadata_mut()
+        .insert("x-request-id", "grpc-req-1".parse().expect("metadata"));
+    let response = client.analyze(request).await.expect("analyze");
+    assert_eq!(
+        response.metadata().get("x-request-id").expect("id"),
+        "grpc-req-1"
+    );
+    let status = client
+        .analyze(analyze_request("not a fen"))
+        .await
+        .expect_err("invalid fen");
+    assert!(status.metadata().get("x-request-id").is_some());
+}
+#[tokio::test]
+async fn test_grpc_stream_analysis_disconnect_cancels() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let mut stream = client
+        .stream_analysis(analyze_request(START_FEN))
+        .await
+        .expect("stream")
+        .into_inner();
+    let first = stream.message().await.expect("update").expect("progress");
+    drop(stream);
+    let path = format!("/v1/analyze/{}", first.id);
+    let mut status = 0;
+    for _ in 0..50 {
+        status = server.get(&path).await.status().as_u16();
+        if status != 202 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, 410);
+}
+fn play_best_move(update: PlayUpdate) -> (String, bool) {
+    match update.update {
+        Some(play_update::Update::BestMove(best)) => {
+            (best.best_move.expect("best move").to, best.ponder_hit)
+        }
+        other => panic!("expected a best move, got {:?}", other),
+    }
+}
+#[tokio::test]
+async fn test_grpc_play_session_streams_best_moves() {
+    let server = TestServer::new().await;
+    let mut client = connect(&server).await;
+    let (commands, command_rx) = tokio::sync::mpsc::channel(8);
+    let requests = futures_util::stream::unfold(command_rx, |mut rx| async move {
+        rx.recv().await.map(|request| (request, rx))
+    });
+    commands
+        .send(PlayRequest {
+            command: Some(play_request::Command::Position(PlayPosition {
+                startpos: true,
+                movetime_ms: Some(100),
+                ponder: true,
+                ..Default::default()
+            })),
+        })
+        .await
+        .expect("send position");
+    let mut updates = client
+        .play_session(requests)
+        .await
+        .expect("play session")
+        .into_inner();
+    let first = updates
+        .message()
+        .await
+        .expect("update")
+        .expect("evaluation");
+    assert!(matches!(
+        first.update,
+        Some(play_update::Update::Evaluation(ref e)) if e.depth > 0 && !e.pv.is_empty()
+    ));
+    let update = updates.message().await.expect("update").expect("best move");
+    assert_eq!(play_best_move(update), ("e4".to_string(), false));
+    commands
+        .send(PlayRequest {
+            command: Some(play_request::Command::OpponentMove("e7e5".into())),
+        })
+        .await
+        .expect("send move");
+    updates
+        .message()
+        .await
+        .expect("update")
+        .expect("evaluation");
+    let update = updates.message().await.expect("update").expect("best move");
+    assert_eq!(play_best_move(update), ("e4".to_string(), true));
+    commands
+        .send(PlayRequest { command: None })
+        .await
+        .expect("send empty");
+    let status = updates.message().await.expect_err("empty command");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    let (commands, command_rx) = tokio::sync::mpsc::channel(1);
+    let requests = futures_util::stream::unfold(command_rx, |mut rx| async move {
+        rx.recv().await.map(|request| (request, rx))
+    });
+    commands
+        .send(PlayRequest {
+            command: Some(play_request::Command::Position(PlayPosition {
+                fen: "not a fen".into(),
+                ..Default::default()
+            })),
+        })
+        .await
+        .expect("send position");
+    let mut updates = client
+        .play_session(requests)
+        .await
+        .expect("play session")
+        .into_inner();
+    let status = updates.message().await.expect_err("invalid fen");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    for (movetime_ms, nodes) in [(Some(60_001), None), (None, Some(0))] {
+        let (commands, command_rx) = tokio::sync::mpsc::channel(1);
+        let requests = futures_util::stream::unfold(command_rx, |mut rx| async move {
+            rx.recv().await.map(|request| (request, rx))
+        });
+        commands
+            .send(PlayRequest {
+                command: Some(play_request::Command::Position(PlayPosition {
+                    startpos: true,
+                    movetime_ms,
+                    nodes,
+                    ..Default::default()
+                })),
+            })
+            .await
+            .expect("send position");
+        let mut updates = client
+            .play_session(requests)
+            .await
+            .expect("play session")
+            .into_inner();
+        let status = updates.message().await.expect_err("limits");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+#[tokio::test]
+async fn test_grpc_play_session_holds_an_admission_permit() {
+    let server = TestServer::new().await;
+    server.state.admission.set_limit(1);
+    let mut client = connect(&server).await;
+    let (commands, command_rx) = tokio::sync::mpsc::channel(8);
+    let requests = futures_util::stream::unfold(command_rx, |mut rx| async move {
+        rx.recv().await.map(|request| (request, rx))
+    });
+    let updates = client
+        .play_session(requests)
+        .await
+        .expect("play session")
+        .into_inner();
+    assert_eq!(server.state.admission.in_flight(), 1);
+    let status = client
+        .play_session(futures_util::stream::empty())
+        .await
+        .expect_err("second session");
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    let status = client
+        .analyze(analyze_request(START_FEN))
+        .await
+        .expect_err("analysis during session");
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    drop(commands);
+    drop(updates);
+    for _ in 0..50 {
+        if server.state.admission.in_flight() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(server.state.admission.in_flight(), 0);
+    client
+        .analyze(analyze_request(START_FEN))
+        .await
+        .expect("analysis after session");
+}
+fn authorized<T>(message: T, token: &str, admin_key: Option<&str>) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().expect("metadata"),
+    );
+    if let Some(key) = admin_key {
+        request
+            .metadata_mut()
+            .insert("x-admin-key", key.parse().expect("metadata"));
+    }
+    request
+}
+#[tokio::test]
+async fn test_grpc_requires_token_when_auth_enabled() {
+    let server = TestServer::with_auth().await;
+    let mut client = connect(&server).await;
+    let status = client
+        .analyze(analyze_request(START_FEN))
+        .await
+        .expect_err("missing token");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let status = client
+        .analyze(authorized(
+            analyze_request(START_FEN),
+            "iff_invalid_token",
+            None,
+        ))
+        .await
+        .expect_err("invalid token");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let response = client
+        .analyze(authorized(analyze_request(START_FEN), &server.token, None))
+        .await
+        .expect("authorized analysis")
+        .into_inner();
+    assert!(response.best_move.is_some());
+}
+#[tokio::test]
+async fn test_grpc_cluster_admin_requires_admin_key() {
+    use ironfish_api::proto::cluster_admin_client::ClusterAdminClient;
+    use ironfish_api::proto::Empty;
+    let server = TestServer::with_auth().await;
+    let mut client = ClusterAdminClient::connect(server.url(""))
+        .await
+        .expect("grpc connect");
+    let status = client
+        .get_status(authorized(Empty {}, &server.token, None))
+        .await
+        .expect_err("missing admin key");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let status = client
+        .get_status(authorized(Empty {}, &server.token, Some("wrong-key")))
+        .await
+        .expect_err("wrong admin key");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let response = client
+        .get_status(authorized(Empty {}, &server.token, Some(&server.admin_key)))
+        .await
+        .expect("cluster status")
+        .into_inner();
+    assert!(response.healthy);
+    let diagnostics = client
+        .get_diagnostics(authorized(Empty {}, &server.token, Some(&server.admin_key)))
+        .await
+        .expect("diagnostics")
+        .into_inner();
+    assert_eq!(diagnostics.node_id, server.node_id);
+    assert!(!diagnostics.draining);
+    assert!(diagnostics.engine_pool.is_some());
+}
+#[tokio::test]
+async fn test_rest_grpc_and_reflection_share_one_port() {
+    use futures_util::StreamExt;
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+    let server = TestServer::with_auth().await;
+    let resp = reqwest::get(server.url("/v1/health"))
+        .await
+        .expect("health");
+    assert_eq!(resp.status(), 200);
+    let mut client = connect(&server).await;
+    let response = client
+        .analyze(authorized(analyze_request(START_FEN), &server.token, None))
+        .await
+        .expect("analysis over the shared port")
+        .into_inner();
+    assert!(response.best_move.is_some());
+    let channel = Channel::from_shared(server.url(""))
+        .expect("channel")
+        .connect()
+        .await
+        .expect("reflection connect");
+    let mut reflection = ServerReflectionClient::new(channel);
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = reflection
+        .server_reflection_info(futures_util::stream::iter(vec![request]))
+        .await
+        .expect("reflection")
+        .into_inner();
+    let response = responses
+        .next()
+        .await
+        .expect("reflection response")
+        .expect("reflection ok");
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!("unexpected reflection response");
+    };
+    let services: Vec<&str> = list.service.iter().map(|s| s.name.as_str()).collect();
+    assert!(services.contains(&"chess.ChessAnalysis"));
+    assert!(services.contains(&"chess.ClusterAdmin"));
+}
+#[tokio::test]
+async fn test_grpc_cluster_writes_forwarded_to_leader() {
+    let leader = TestServer::new().await;
+    leader
+        .state
+        .node
+        .set_state(ironfish_core::NodeState::Leader);
+    let follower = TestServer::following(&leader).await;
+    let mut client = ClusterAdminClient::connect(follower.url(""))
+        .await
+        .expect("grpc connect");
+    let mut join = tonic::Request::new(JoinRequest {
+        node_id: "joiner".to_string(),
+        address: "127.0.0.1:9".to_string(),
+        priority: 100,
+    });
+    join.metadata_mut()
+        .insert("x-cluster-secret", TEST_CLUSTER_SECRET.parse().unwrap());
+    let joined = client.join_cluster(join).await.expect("join").into_inner();
+    assert!(joined.accepted);
+    let joiner = ironfish_core::NodeId::from_string("joiner");
+    assert!(leader.membership.get_member(&joiner).await.is_some());
+    assert!(follower.membership.get_member(&joiner).await.is_none());
+    let left = client
+        .leave_cluster(LeaveRequest {
+            node_id: "joiner".to_string(),
+        })
+        .await
+        .expect("leave")
+        .into_inner();
+    assert!(left.success);
+    assert!(leader.membership.get_member(&joiner).await.is_none());
+    follower.state.node.set_leader(None);
+    let status = client
+        .leave_cluster(LeaveRequest {
+            node_id: "joiner".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+}
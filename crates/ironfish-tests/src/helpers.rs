@@ -1,80 +1,381 @@
+use async_trait::async_trait;
 use ironfish_api::ws::SessionManager;
-use ironfish_api::{ApiRouter, ApiState, WebSocketConfig};
-use ironfish_auth::{SledTokenStore, TokenManager};
-use ironfish_cluster::{MembershipManager, Node, NodeConfig};
-use ironfish_core::TokenStore;
-use ironfish_stockfish::{AnalysisService, EnginePool, EnginePoolConfig};
+use ironfish_api::{
+    AnalysisForwarder, ApiRouter, ApiState, ConfigReloader, ForwardingConfig, LeaderForwarder,
+    MetricsRegistry, SledAnalysisHistory, WebSocketConfig, WebhookConfig, Webhooks,
+};
+use ironfish_auth::{SledAuditLog, SledTokenStore, TokenManager};
+use ironfish_cluster::{
+    ClusterConfig, ClusterService, ClusterStateStore, CpuAwareLoadBalancer, LoadBalancerConfig,
+    MembershipManager, Node, NodeConfig,
+};
+use ironfish_core::{
+    AnalysisLimits, ApiToken, ConfigChange, ConfigReloadReport, Error, GossipMessage, NodeId,
+    NodeInfo, NodeMetrics, Result, StoreHealth, TokenStore,
+};
+use ironfish_stockfish::{AnalysisCache, AnalysisService, EnginePool, EnginePoolConfig};
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use uuid::Uuid;
 pub const TEST_ADMIN_KEY: &str = "test-admin-secret-key-12345";
+pub const TEST_CLUSTER_SECRET: &str = "test-cluster-secret";
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    tokens: RwLock<HashMap<Uuid, ApiToken>>,
+    read_only: AtomicBool,
+    broken: AtomicBool,
+}
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn mark_read_only(&self) {
+        self.read_only.store(true, Ordering::SeqCst);
+    }
+    pub fn set_broken(&self, broken: bool) {
+        self.broken.store(broken, Ordering::SeqCst);
+    }
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only.load(Ordering::SeqCst) {
+            return Err(Error::StoreReadOnly);
+        }
+        Ok(())
+    }
+}
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn create(&self, token: ApiToken) -> Result<()> {
+        self.ensure_writable()?;
+        self.tokens.write().await.insert(token.id, token);
+        Ok(())
+    }
+    async fn get(&self, id: &Uuid) -> Result<Option<ApiToken>> {
+        if self.broken.load(Ordering::SeqCst) {
+            return Err(Error::Storage("token database is unavailable".to_string()));
+        }
+        Ok(self.tokens.read().await.get(id).cloned())
+    }
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ApiToken>> {
+        if self.broken.load(Ordering::SeqCst) {
+            return Err(Error::Storage("token database is unavailable".to_string()));
+        }
+        Ok(self
+            .tokens
+            .read()
+            .await
+            .values()
+            .find(|t| t.token_hash == hash)
+            .cloned())
+    }
+    async fn update(&self, token: ApiToken) -> Result<()> {
+        self.create(token).await
+    }
+    async fn delete(&self, id: &Uuid) -> Result<()> {
+        self.ensure_writable()?;
+        self.tokens.write().await.remove(id);
+        Ok(())
+    }
+    async fn list(&self) -> Result<Vec<ApiToken>> {
+        Ok(self.tokens.read().await.values().cloned().collect())
+    }
+    async fn revoke(&self, id: &Uuid) -> Result<()> {
+        self.ensure_writable()?;
+        if let Some(token) = self.tokens.write().await.get_mut(id) {
+            let now = chrono::Utc::now();
+            token.revoked = true;
+            token.revoked_at = Some(now);
+            token.last_used_at = Some(now);
+        }
+        Ok(())
+    }
+    fn health(&self) -> StoreHealth {
+        if self.read_only.load(Ordering::SeqCst) {
+            StoreHealth::Degraded
+        } else {
+            StoreHealth::Healthy
+        }
+    }
+    async fn recover(&self) -> Result<StoreHealth> {
+        self.read_only.store(false, Ordering::SeqCst);
+        Ok(StoreHealth::Healthy)
+    }
+}
 pub struct TestServer {
     pub addr: SocketAddr,
     pub token: String,
     pub admin_key: String,
+    pub token_store: Arc<MemoryTokenStore>,
+    pub node_id: String,
+    pub membership: Arc<MembershipManager>,
+    pub state: Arc<ApiState>,
+    secret: Vec<u8>,
     _handle: tokio::task::JoinHandle<()>,
 }
+pub struct TestServerBuilder<'a> {
+    stockfish: bool,
+    auth: bool,
+    cache: bool,
+    peer: Option<&'a TestServer>,
+    leader: Option<&'a TestServer>,
+    ws_config: WebSocketConfig,
+    reloader: Option<Arc<dyn ConfigReloader>>,
+    webhooks: Option<WebhookConfig>,
+}
+impl<'a> TestServerBuilder<'a> {
+    pub fn with_stockfish(mut self, enabled: bool) -> Self {
+        self.stockfish = enabled;
+        self
+    }
+    pub fn with_auth(mut self, enabled: bool) -> Self {
+        self.auth = enabled;
+        self
+    }
+    pub fn with_cache(mut self) -> Self {
+        self.cache = true;
+        self
+    }
+    pub fn forwarding_to(mut self, peer: &'a TestServer) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+    pub fn following(mut self, leader: &'a TestServer) -> Self {
+        self.leader = Some(leader);
+        self
+    }
+    pub fn with_ws_config(mut self, ws_config: WebSocketConfig) -> Self {
+        self.ws_config = ws_config;
+        self
+    }
+    pub fn with_reloader(mut self, reloader: Arc<dyn ConfigReloader>) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+    pub fn with_webhooks(mut self, webhooks: WebhookConfig) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+    pub async fn start(self) -> TestServer {
+        TestServer::start(self).await
+    }
+}
 impl TestServer {
+    pub fn builder<'a>() -> TestServerBuilder<'a> {
+        TestServerBuilder {
+            stockfish: false,
+            auth: false,
+            cache: false,
+            peer: None,
+            leader: None,
+            ws_config: WebSocketConfig::default(),
+            reloader: None,
+            webhooks: None,
+        }
+    }
     pub async fn new() -> Self {
-        Self::with_config(false, false).await
+        Self::builder().start().await
     }
     pub async fn with_stockfish(enable_stockfish: bool) -> Self {
-        Self::with_config(enable_stockfish, false).await
+        Self::builder()
+            .with_stockfish(enable_stockfish)
+            .start()
+            .await
     }
     pub async fn with_auth() -> Self {
-        Self::with_config(false, true).await
+        Self::builder().with_auth(true).start().await
     }
     pub async fn with_config(enable_stockfish: bool, enable_auth: bool) -> Self {
+        Self::builder()
+            .with_stockfish(enable_stockfish)
+            .with_auth(enable_auth)
+            .start()
+            .await
+    }
+    pub async fn with_cache() -> Self {
+        Self::builder().with_auth(true).with_cache().start().await
+    }
+    pub async fn with_ws_config(ws_config: WebSocketConfig) -> Self {
+        Self::builder().with_ws_config(ws_config).start().await
+    }
+    pub async fn with_config_file(path: impl Into<PathBuf>) -> Self {
+        let reloader = Arc::new(LimitsFileReloader { path: path.into() });
+        Self::builder()
+            .with_auth(true)
+            .with_reloader(reloader)
+            .start()
+            .await
+    }
+    pub async fn forwarding_to(peer: &TestServer) -> Self {
+        Self::builder().forwarding_to(peer).start().await
+    }
+    pub async fn following(leader: &TestServer) -> Self {
+        Self::builder().following(leader).start().await
+    }
+    pub async fn with_webhooks(webhooks: WebhookConfig) -> Self {
+        Self::builder()
+            .with_auth(true)
+            .with_webhooks(webhooks)
+            .start()
+            .await
+    }
+    async fn start(options: TestServerBuilder<'_>) -> Self {
+        let TestServerBuilder {
+            stockfish: enable_stockfish,
+            auth: enable_auth,
+            cache: enable_cache,
+            peer,
+            leader,
+            ws_config,
+            reloader,
+            webhooks,
+        } = options;
         if enable_auth {
             std::env::set_var("IRONFISH_ADMIN_KEY", TEST_ADMIN_KEY);
         }
         let node_config = NodeConfig {
             id: Some(format!("test-node-{}", uuid::Uuid::new_v4())),
             bind_address: "127.0.0.1:0".parse().unwrap(),
+            advertise_address: None,
             priority: 100,
             version: "test".to_string(),
         };
         let node = Arc::new(Node::new(node_config));
+        let metrics = Arc::new(MetricsRegistry::new());
         let analysis = if enable_stockfish {
             let engine_config = EnginePoolConfig {
                 binary_path: std::env::var("STOCKFISH_PATH")
                     .unwrap_or_else(|_| "/usr/local/bin/stockfish".to_string()),
                 pool_size: 1,
+                ..Default::default()
             };
             let pool = Arc::new(EnginePool::new(engine_config).await.expect("engine pool"));
-            Arc::new(AnalysisService::new(pool))
+            AnalysisService::new(pool)
+        } else {
+            AnalysisService::new_mock()
+        };
+        let analysis = analysis.with_telemetry(metrics.telemetry());
+        let analysis = if enable_cache {
+            analysis.with_cache(Arc::new(AnalysisCache::new(64)))
         } else {
-            Arc::new(AnalysisService::new_mock())
+            analysis
+        };
+        let analysis = Arc::new(analysis);
+        let token_store = Arc::new(MemoryTokenStore::new());
+        let secret = match peer {
+            Some(peer) => peer.secret.clone(),
+            None => TokenManager::generate_secret(),
         };
-        let temp_dir = tempfile::tempdir().expect("temp dir");
-        let token_store = Arc::new(SledTokenStore::new(temp_dir.path()).expect("token store"));
-        let secret = TokenManager::generate_secret();
         let token_manager = Arc::new(TokenManager::new(&secret, "test"));
         let membership = Arc::new(MembershipManager::new(node.clone()));
-        let ws_config = WebSocketConfig::default();
-        let ws_sessions = Arc::new(SessionManager::new(ws_config.max_connections));
-        let state = Arc::new(ApiState::new(
+        let ws_sessions = Arc::new(
+            SessionManager::new(ws_config.max_connections)
+                .with_max_sessions_per_token(ws_config.max_sessions_per_token)
+                .with_telemetry(metrics.telemetry()),
+        );
+        let audit = Arc::new(SledAuditLog::in_memory().expect("audit log"));
+        let mut state = ApiState::new(
             analysis,
             token_store.clone(),
             token_manager.clone(),
-            node,
-            membership,
+            node.clone(),
+            membership.clone(),
             ws_sessions,
             ws_config,
-        ));
-        let router = ApiRouter::new(state.clone())
+        )
+        .with_audit(audit)
+        .with_history(Arc::new(
+            SledAnalysisHistory::in_memory().expect("analysis history"),
+        ))
+        .with_metrics(metrics)
+        .with_cluster_secret(TEST_CLUSTER_SECRET);
+        if let Some(peer) = peer {
+            let peer_id = NodeId::from_string(&peer.node_id);
+            membership
+                .add_member(NodeInfo {
+                    id: peer_id.clone(),
+                    address: peer.addr,
+                    priority: 100,
+                    started_at: chrono::Utc::now(),
+                    version: "test".to_string(),
+                })
+                .await;
+            let load_balancer = Arc::new(CpuAwareLoadBalancer::new(LoadBalancerConfig::default()));
+            load_balancer.add_node(peer_id).await;
+            node.update_metrics(NodeMetrics {
+                cpu_usage: 1.0,
+                ..Default::default()
+            });
+            state = state.with_forwarder(
+                AnalysisForwarder::new(
+                    ForwardingConfig::default(),
+                    load_balancer,
+                    membership.clone(),
+                )
+                .with_secret(Some(TEST_CLUSTER_SECRET)),
+            );
+        }
+        if let Some(leader) = leader {
+            let leader_id = NodeId::from_string(&leader.node_id);
+            membership
+                .add_member(NodeInfo {
+                    id: leader_id.clone(),
+                    address: leader.addr,
+                    priority: 100,
+                    started_at: chrono::Utc::now(),
+                    version: "test".to_string(),
+                })
+                .await;
+            node.set_leader(Some(leader_id));
+            state = state.with_leader_forwarder(
+                LeaderForwarder::new().with_secret(Some(TEST_CLUSTER_SECRET)),
+            );
+        }
+        if let Some(reloader) = reloader {
+            state = state.with_config_reloader(reloader);
+        }
+        if let Some(webhooks) = webhooks {
+            let webhooks = Webhooks::new(webhooks, node.id().to_string()).expect("webhooks");
+            state = state.with_webhooks(Arc::new(webhooks));
+        }
+        let node_id = node.id().to_string();
+        let state = Arc::new(state);
+        ironfish_api::ws::spawn_event_publisher(state.clone());
+        ironfish_api::spawn_webhook_notifier(state.clone());
+        let service = ApiRouter::new(state.clone())
             .with_auth(enable_auth)
-            .build_rest_router();
+            .build_multiplex_service();
+        let router = axum::Router::new().fallback_service(service);
         let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
         let addr = listener.local_addr().expect("local addr");
         let handle = tokio::spawn(async move {
-            axum::serve(listener, router).await.expect("serve");
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .expect("serve");
         });
         let (api_token, response) = token_manager
             .create(ironfish_core::CreateTokenRequest {
                 name: Some("test-token".into()),
                 expires_in_days: None,
                 rate_limit: None,
+                scopes: Vec::new(),
+                max_depth: None,
+                max_multipv: None,
+                node_budget_per_hour: None,
             })
             .expect("create token");
         let _ = token_store.create(api_token).await;
@@ -82,6 +383,11 @@ impl TestServer {
             addr,
             token: response.token,
             admin_key: TEST_ADMIN_KEY.to_string(),
+            token_store,
+            node_id,
+            membership: state.membership.clone(),
+            state,
+            secret,
             _handle: handle,
         }
     }
@@ -109,6 +415,34 @@ impl TestServer {
                 tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
             >,
         >,
+    ) {
+        use futures_util::StreamExt;
+        let (sink, mut stream) = self.ws_connect_raw(token).await;
+        let hello = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .expect("hello timeout")
+            .expect("stream ended")
+            .expect("ws error");
+        let hello: serde_json::Value =
+            serde_json::from_str(hello.to_text().expect("text hello")).expect("parse hello");
+        assert_eq!(hello["type"], "hello");
+        (sink, stream)
+    }
+    pub async fn ws_connect_raw(
+        &self,
+        token: Option<&str>,
+    ) -> (
+        futures_util::stream::SplitSink<
+            tokio_tungstenite::WebSocketStream<
+                tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+            >,
+            tokio_tungstenite::tungstenite::Message,
+        >,
+        futures_util::stream::SplitStream<
+            tokio_tungstenite::WebSocketStream<
+                tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+            >,
+        >,
     ) {
         use futures_util::StreamExt;
         let url = self.ws_url(token);
@@ -135,6 +469,14 @@ impl TestServer {
             .await
             .expect("request")
     }
+    pub async fn delete(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(self.url(path))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await
+            .expect("request")
+    }
     pub async fn admin_get(&self, path: &str) -> reqwest::Response {
         reqwest::Client::new()
             .get(self.url(path))
@@ -166,6 +508,350 @@ impl TestServer {
             .expect("request")
     }
 }
+pub struct LimitsFileReloader {
+    path: PathBuf,
+}
+#[async_trait]
+impl ConfigReloader for LimitsFileReloader {
+    async fn reload(&self, state: &ApiState) -> Result<ConfigReloadReport> {
+        #[derive(serde::Deserialize)]
+        struct LimitsFile {
+            #[serde(default)]
+            limits: AnalysisLimits,
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        let file: LimitsFile =
+            toml::from_str(&content).map_err(|e| Error::Config(e.to_string()))?;
+        let from = serde_json::to_value(state.limits())?;
+        let to = serde_json::to_value(file.limits)?;
+        let mut report = ConfigReloadReport::default();
+        for (key, value) in to.as_object().into_iter().flatten() {
+            if from[key] != *value {
+                report.applied.push(ConfigChange {
+                    key: format!("limits.{}", key),
+                    from: from[key].to_string(),
+                    to: value.to_string(),
+                });
+            }
+        }
+        state.set_limits(file.limits);
+        Ok(report)
+    }
+}
+#[derive(Debug, Clone)]
+pub struct CapturedSpan {
+    pub name: String,
+    pub fields: HashMap<String, String>,
+}
+#[derive(Clone, Default)]
+pub struct CaptureLayer {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+}
+impl CaptureLayer {
+    pub fn spans(&self) -> Vec<CapturedSpan> {
+        self.spans.lock().expect("capture lock").clone()
+    }
+    pub fn named(&self, name: &str) -> Vec<CapturedSpan> {
+        self.spans()
+            .into_iter()
+            .filter(|s| s.name == name)
+            .collect()
+    }
+}
+#[derive(Clone, Default)]
+struct SpanFields(HashMap<String, String>);
+impl Visit for SpanFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<SpanFields>().cloned())
+            .unwrap_or_default();
+        attrs.record(&mut fields);
+        self.spans.lock().expect("capture lock").push(CapturedSpan {
+            name: span.name().to_string(),
+            fields: fields.0.clone(),
+        });
+        span.extensions_mut().insert(fields);
+    }
+}
+fn free_cluster_addr() -> SocketAddr {
+    loop {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map(|a| a.port())
+            .expect("free port");
+        if port <= u16::MAX - 100 && std::net::TcpListener::bind(("127.0.0.1", port + 100)).is_ok()
+        {
+            return SocketAddr::from(([127, 0, 0, 1], port));
+        }
+    }
+}
+fn ports_released(addr: SocketAddr) -> bool {
+    let gossip = SocketAddr::new(addr.ip(), addr.port() + 100);
+    std::net::TcpListener::bind(addr).is_ok() && std::net::TcpListener::bind(gossip).is_ok()
+}
+struct NodeRuntime {
+    state: Arc<ApiState>,
+    cluster: Arc<ClusterService<SledTokenStore>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+pub struct ClusterNode {
+    pub node_id: String,
+    pub addr: SocketAddr,
+    pub token_store: Arc<SledTokenStore>,
+    cluster_state: ClusterStateStore,
+    runtime: Option<NodeRuntime>,
+}
+impl ClusterNode {
+    pub fn is_running(&self) -> bool {
+        self.runtime.is_some()
+    }
+    pub fn state(&self) -> &Arc<ApiState> {
+        &self.runtime.as_ref().expect("node is stopped").state
+    }
+    pub fn cluster(&self) -> &Arc<ClusterService<SledTokenStore>> {
+        &self.runtime.as_ref().expect("node is stopped").cluster
+    }
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+    pub async fn health(&self) -> bool {
+        reqwest::Client::new()
+            .get(self.url("/v1/health"))
+            .timeout(Duration::from_secs(1))
+            .send()
+            .await
+            .map(|r| r.status().is_success())
+            .unwrap_or(false)
+    }
+    pub async fn post_json<T: serde::Serialize>(
+        &self,
+        path: &str,
+        token: &str,
+        body: &T,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(self.url(path))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(body)
+            .send()
+            .await
+            .expect("request")
+    }
+    pub async fn admin_post_json<T: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(self.url(path))
+            .header("X-Admin-Key", TEST_ADMIN_KEY)
+            .json(body)
+            .send()
+            .await
+            .expect("request")
+    }
+}
+pub struct TestCluster {
+    pub nodes: Vec<ClusterNode>,
+    secret: Vec<u8>,
+    data_dir: PathBuf,
+}
+impl TestCluster {
+    pub async fn start(node_count: usize) -> Self {
+        std::env::set_var("IRONFISH_ADMIN_KEY", TEST_ADMIN_KEY);
+        let data_dir = std::env::temp_dir().join(format!("ironfish-cluster-{}", Uuid::new_v4()));
+        let mut nodes = Vec::new();
+        for i in 0..node_count {
+            let node_dir = data_dir.join(format!("node{}", i + 1));
+            nodes.push(ClusterNode {
+                node_id: format!("cluster-node-{}", i + 1),
+                addr: free_cluster_addr(),
+                token_store: Arc::new(
+                    SledTokenStore::new(node_dir.join("tokens")).expect("token store"),
+                ),
+                cluster_state: ClusterStateStore::new(node_dir.join("cluster"))
+                    .expect("cluster state"),
+                runtime: None,
+            });
+        }
+        let mut cluster = Self {
+            nodes,
+            secret: TokenManager::generate_secret(),
+            data_dir,
+        };
+        for i in 0..node_count {
+            cluster.launch(i).await;
+        }
+        cluster
+    }
+    pub fn node(&self, idx: usize) -> &ClusterNode {
+        &self.nodes[idx]
+    }
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> bool {
+        let running: Vec<_> = self.nodes.iter().filter(|n| n.is_running()).collect();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut converged = true;
+            for node in &running {
+                if node.state().membership.member_count().await != running.len() {
+                    converged = false;
+                    break;
+                }
+            }
+            if converged {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    pub async fn stop_node(&mut self, idx: usize) {
+        let node = &mut self.nodes[idx];
+        let Some(runtime) = node.runtime.take() else {
+            return;
+        };
+        runtime.cluster.stop().await.expect("stop cluster");
+        for task in runtime.tasks {
+            task.abort();
+            let _ = task.await;
+        }
+        for _ in 0..100 {
+            if ports_released(node.addr) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("ports of {} were never released", node.node_id);
+    }
+    pub async fn restart_node(&mut self, idx: usize) {
+        self.stop_node(idx).await;
+        self.launch(idx).await;
+    }
+    pub async fn stop(&mut self) {
+        for i in 0..self.nodes.len() {
+            self.stop_node(i).await;
+        }
+    }
+    async fn launch(&mut self, idx: usize) {
+        let static_peers = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != idx)
+            .map(|(_, n)| n.addr.to_string())
+            .collect();
+        let node = &mut self.nodes[idx];
+        let local = Arc::new(Node::new(NodeConfig {
+            id: Some(node.node_id.clone()),
+            bind_address: node.addr,
+            advertise_address: None,
+            priority: 100,
+            version: "test".to_string(),
+        }));
+        let membership = Arc::new(MembershipManager::new(local.clone()));
+        let cluster = ClusterService::new(
+            ClusterConfig {
+                discovery_interval: Duration::from_millis(100),
+                gossip_interval: Duration::from_millis(100),
+                health_check_interval: Duration::from_millis(100),
+                peer_failure_threshold: 2,
+                multicast_enabled: false,
+                static_peers,
+                ..Default::default()
+            },
+            local.clone(),
+            membership.clone(),
+            node.token_store.clone(),
+        )
+        .expect("cluster service")
+        .with_state_store(node.cluster_state.clone());
+        let cluster = Arc::new(cluster);
+        cluster.start().await.expect("start cluster");
+        let metrics = Arc::new(MetricsRegistry::new());
+        let ws_config = WebSocketConfig::default();
+        let (gossip_tx, _) = broadcast::channel::<GossipMessage>(1024);
+        let state = Arc::new(
+            ApiState::new(
+                Arc::new(AnalysisService::new_mock().with_telemetry(metrics.telemetry())),
+                node.token_store.clone(),
+                Arc::new(TokenManager::new(&self.secret, node.node_id.clone())),
+                local.clone(),
+                membership,
+                Arc::new(SessionManager::new(ws_config.max_connections)),
+                ws_config,
+            )
+            .with_gossip(gossip_tx.clone())
+            .with_network(cluster.network())
+            .with_revocation_confirm_timeout(Duration::from_secs(1))
+            .with_metrics(metrics),
+        );
+        let mut gossip_rx = gossip_tx.subscribe();
+        let broadcaster = cluster.clone();
+        let gossip_task = tokio::spawn(async move {
+            while let Ok(msg) = gossip_rx.recv().await {
+                let _ = broadcaster.broadcast(msg).await;
+            }
+        });
+        let service = ApiRouter::new(state.clone())
+            .with_auth(true)
+            .build_multiplex_service();
+        let router = axum::Router::new().fallback_service(service);
+        let mut attempts = 0;
+        let listener = loop {
+            match TcpListener::bind(node.addr).await {
+                Ok(listener) => break listener,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                Err(e) => panic!("bind {}: {}", node.addr, e),
+            }
+        };
+        let server_task = tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .expect("serve");
+        });
+        node.runtime = Some(NodeRuntime {
+            state,
+            cluster,
+            tasks: vec![gossip_task, server_task],
+        });
+    }
+}
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            if let Some(runtime) = node.runtime.take() {
+                for task in runtime.tasks {
+                    task.abort();
+                }
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
 pub struct DockerCluster {
     pub nodes: Vec<String>,
 }
@@ -1,9 +1,13 @@
 #[cfg(test)]
 mod api_tests;
 #[cfg(test)]
+mod client_tests;
+#[cfg(test)]
 mod cluster_tests;
 #[cfg(test)]
 mod docker_tests;
+#[cfg(test)]
+mod grpc_tests;
 pub mod helpers;
 #[cfg(test)]
 mod ws_tests;
@@ -1,5 +1,6 @@
 use crate::helpers::TestServer;
 use futures_util::{SinkExt, StreamExt};
+use ironfish_core::TokenStore;
 use serde_json::{json, Value};
 use tokio_tungstenite::tungstenite::Message;
 
@@ -137,6 +138,67 @@ async fn test_ws_analyze_streaming() {
     assert!(got_complete, "should have received complete message");
 }
 
+#[tokio::test]
+async fn test_ws_analyze_includes_score_history() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({
+            "type": "analyze",
+            "id": "h1",
+            "startpos": true,
+            "depth": 12,
+            "include_history": true
+        }),
+    )
+    .await;
+    loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] != "analysis_complete" {
+            continue;
+        }
+        let history = resp["result"]["score_history"]
+            .as_array()
+            .expect("score history");
+        assert_eq!(history.len(), 12);
+        assert_eq!(history[0]["depth"], 1);
+        assert_eq!(history[11]["depth"], 12);
+        assert_eq!(history[11]["evaluation"], resp["result"]["evaluation"]);
+        break;
+    }
+}
+
+#[tokio::test]
+async fn test_ws_messages_carry_request_ids() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "bad", "fen": "invalid", "depth": 5}),
+    )
+    .await;
+    let error = recv_json(&mut stream).await;
+    assert_eq!(error["type"], "error");
+    let request_id = error["request_id"].as_str().expect("request id");
+    let (connection_id, message_id) = request_id.split_once('/').expect("separator");
+    assert!(uuid::Uuid::parse_str(connection_id).is_ok());
+    assert_eq!(message_id, "bad");
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a1", "startpos": true, "depth": 5}),
+    )
+    .await;
+    loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] == "analysis_complete" {
+            assert_eq!(resp["request_id"], format!("{}/a1", connection_id));
+            break;
+        }
+        assert!(resp.get("request_id").is_none());
+    }
+}
+
 #[tokio::test]
 async fn test_ws_cancel_analysis() {
     let server = TestServer::new().await;
@@ -189,6 +251,84 @@ async fn test_ws_cancel_analysis() {
     assert!(got_cancelled, "Expected to receive analysis_cancelled");
 }
 
+async fn recv_infinite_result(
+    stream: &mut futures_util::stream::SplitStream<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    >,
+) -> (Value, u64) {
+    let mut deepest = 0;
+    loop {
+        let resp = recv_json(stream).await;
+        match resp["type"].as_str() {
+            Some("analysis_progress") => {
+                assert_eq!(resp["target_depth"], 0);
+                deepest = deepest.max(resp["current_depth"].as_u64().unwrap());
+            }
+            Some("analysis_complete") => return (resp, deepest),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ws_infinite_analysis_runs_until_cancelled() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze_infinite", "id": "inf", "startpos": true}),
+    )
+    .await;
+    let mut analysis_id = None;
+    while analysis_id.is_none() {
+        let resp = recv_json(&mut stream).await;
+        assert_eq!(resp["type"], "analysis_progress");
+        if resp["current_depth"].as_u64().unwrap() >= 6 {
+            analysis_id = resp["analysis_id"].as_str().map(String::from);
+        }
+    }
+    send_json(
+        &mut sink,
+        json!({"type": "cancel", "id": "c1", "analysis_id": analysis_id.unwrap()}),
+    )
+    .await;
+    let (resp, deepest) = recv_infinite_result(&mut stream).await;
+    assert_eq!(resp["id"], "inf");
+    assert_eq!(resp["result"]["stopped_by"], "cancelled");
+    assert!(resp["result"]["depth_reached"].as_u64().unwrap() >= deepest.max(6));
+    assert!(resp["result"]["best_move"].is_object());
+}
+
+#[tokio::test]
+async fn test_ws_infinite_analysis_capped_by_max_duration() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        max_infinite_analysis_secs: 1,
+        ..Default::default()
+    })
+    .await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    let started = tokio::time::Instant::now();
+    send_json(
+        &mut sink,
+        json!({"type": "analyze_infinite", "id": "capped", "startpos": true, "multipv": 2}),
+    )
+    .await;
+    let (resp, deepest) = recv_infinite_result(&mut stream).await;
+    assert!(started.elapsed() >= tokio::time::Duration::from_secs(1));
+    assert_eq!(resp["result"]["stopped_by"], "time");
+    assert!(deepest >= 5);
+    send_json(
+        &mut sink,
+        json!({"type": "analyze_infinite", "id": "wide", "startpos": true, "multipv": 99}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["error"], "invalid_argument");
+}
+
 #[tokio::test]
 async fn test_ws_bestmove() {
     let server = TestServer::new().await;
@@ -238,6 +378,38 @@ async fn test_ws_cluster_subscribe() {
     assert_eq!(resp["topics"], json!(["cluster"]));
 }
 
+#[tokio::test]
+async fn test_ws_cluster_event_published() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "subscribe", "id": "s1", "topics": ["cluster"]}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "subscribed");
+    server
+        .membership
+        .add_member(ironfish_core::NodeInfo {
+            id: ironfish_core::NodeId::from_string("peer-1"),
+            address: "127.0.0.1:9999".parse().unwrap(),
+            priority: 100,
+            started_at: chrono::Utc::now(),
+            version: "test".to_string(),
+        })
+        .await;
+    let event = loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] == "event" {
+            break resp;
+        }
+    };
+    assert_eq!(event["topic"], "cluster");
+    assert_eq!(event["payload"]["event"], "node_joined");
+    assert_eq!(event["payload"]["node_id"], "peer-1");
+}
+
 #[tokio::test]
 async fn test_ws_ping_pong() {
     let server = TestServer::new().await;
@@ -258,6 +430,191 @@ async fn test_ws_invalid_message() {
     let resp = recv_json(&mut stream).await;
     assert_eq!(resp["type"], "error");
     assert_eq!(resp["code"], 400);
+    assert_eq!(resp["error"], "invalid_message");
+    send_json(&mut sink, json!({"type": "frobnicate", "id": "u1"})).await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "u1");
+    assert_eq!(resp["code"], 400);
+    assert_eq!(resp["error"], "unknown_message_type");
+    assert!(resp["message"].as_str().unwrap().contains("frobnicate"));
+}
+
+#[tokio::test]
+async fn test_ws_invalid_fields_report_details() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a1", "startpos": true, "depth": "20"}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "invalid_message");
+    assert_eq!(resp["id"], "a1");
+    assert_eq!(resp["details"]["type"], "analyze");
+    assert_eq!(resp["details"]["field"], "depth");
+    assert_eq!(resp["details"]["expected"], "u8");
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a2", "startpos": true, "dpeth": 20}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "invalid_message");
+    assert_eq!(resp["id"], "a2");
+    assert_eq!(resp["details"]["field"], "dpeth");
+    assert!(resp["message"].as_str().unwrap().contains("dpeth"));
+}
+
+#[tokio::test]
+async fn test_ws_oversized_message_rejected() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        max_message_size_bytes: 256,
+        ..Default::default()
+    })
+    .await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "ping", "padding": "x".repeat(512)}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["code"], 413);
+    assert_eq!(resp["error"], "message_too_large");
+    assert_eq!(resp["details"]["limit"], 256);
+    match tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await {
+        Ok(Some(Ok(Message::Close(Some(frame))))) => assert_eq!(u16::from(frame.code), 1009),
+        other => panic!("expected close 1009, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_ws_hello_precedes_auth_timeout() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        auth_timeout_secs: 1,
+        ..Default::default()
+    })
+    .await;
+    let (_sink, mut stream) = server.ws_connect_raw(None).await;
+    let hello = recv_json(&mut stream).await;
+    let greeted = tokio::time::Instant::now();
+    assert_eq!(hello["type"], "hello");
+    assert_eq!(hello["protocol_version"], "1.0");
+    assert!(hello["server_version"].is_string());
+    assert_eq!(hello["features"]["streaming"], true);
+    assert_eq!(hello["features"]["topics"], json!(["cluster", "metrics"]));
+    assert_eq!(hello["limits"]["max_depth"], 30);
+    assert_eq!(hello["limits"]["max_concurrent_analyses"], 4);
+    assert_eq!(hello["limits"]["auth_timeout_secs"], 1);
+    match tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => {
+            let resp: Value = serde_json::from_str(&text).expect("parse");
+            assert_eq!(resp["error"], "auth_timeout");
+        }
+        Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => {}
+        other => panic!("expected auth timeout, got {:?}", other),
+    }
+    assert!(greeted.elapsed() >= tokio::time::Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn test_ws_client_hello_version_negotiation() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(&mut sink, json!({"type": "hello", "version": "1.0"})).await;
+    send_json(&mut sink, json!({"type": "ping", "id": "p1"})).await;
+    assert_eq!(recv_json(&mut stream).await["type"], "pong");
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "hello", "id": "h1", "version": "2.0"}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "h1");
+    assert_eq!(resp["error"], "unsupported_protocol_version");
+    assert_eq!(expect_close_code(&mut stream).await, 1002);
+}
+
+#[tokio::test]
+async fn test_ws_analyze_rejects_out_of_range_parameters() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a1", "startpos": true, "multipv": 0}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "a1");
+    assert_eq!(resp["code"], 400);
+    assert_eq!(
+        resp["message"],
+        "invalid argument: multipv must be between 1 and 10, got 0"
+    );
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a2", "startpos": true, "depth": 31}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["code"], 400);
+    assert_eq!(resp["id"], "a2");
+}
+
+#[tokio::test]
+async fn test_config_reload_applies_limits_without_dropping_ws() {
+    let dir = std::env::temp_dir().join(format!("ironfish-reload-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ironfish.toml");
+    std::fs::write(&path, "[limits]\nmax_depth = 30\n").unwrap();
+    let server = TestServer::with_config_file(&path).await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let resp = server
+        .post_json("/v1/analyze", &json!({"fen": fen, "depth": 25}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    std::fs::write(&path, "[limits]\nmax_depth = 20\n").unwrap();
+    let resp = server
+        .admin_post_json("/_admin/config/reload", &json!({}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let report: Value = resp.json().await.expect("json");
+    assert_eq!(
+        report["applied"],
+        json!([{"key": "limits.max_depth", "from": "30", "to": "20"}])
+    );
+    let resp = server
+        .post_json("/v1/analyze", &json!({"fen": fen, "depth": 25}))
+        .await;
+    assert_eq!(resp.status(), 400);
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a1", "startpos": true, "depth": 25}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "a1");
+    assert_eq!(resp["code"], 400);
+    send_json(&mut sink, json!({"type": "ping", "id": "p1"})).await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "pong");
+    let audit: Value = server
+        .admin_get("/_admin/audit?action=config_reload")
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(audit["entries"][0]["target"], server.node_id.as_str());
+    assert_eq!(audit["entries"][0]["outcome"]["status"], "success");
+    let _ = std::fs::remove_dir_all(&dir);
 }
 
 #[tokio::test]
@@ -379,7 +736,8 @@ async fn test_ws_invalid_fen_returns_error() {
             Ok(Some(Ok(Message::Text(text)))) => {
                 let resp: Value = serde_json::from_str(&text).expect("parse");
                 if resp["type"] == "error" {
-                    assert_eq!(resp["code"], 500);
+                    assert_eq!(resp["code"], 400);
+                    assert_eq!(resp["error"], "invalid_fen");
                     assert!(resp["message"].as_str().unwrap().contains("invalid FEN"));
                     break;
                 }
@@ -393,84 +751,230 @@ async fn test_ws_invalid_fen_returns_error() {
 }
 
 #[tokio::test]
-async fn test_ws_subscribe_and_unsubscribe() {
+async fn test_ws_analyze_from_move_list() {
     let server = TestServer::new().await;
     let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
     send_json(
         &mut sink,
-        json!({"type": "subscribe", "id": "s1", "topics": ["cluster", "metrics"]}),
+        json!({
+            "type": "analyze",
+            "id": "m1",
+            "startpos": true,
+            "moves": ["e2e4", "e7e5"],
+            "depth": 5,
+            "multipv": 1
+        }),
+    )
+    .await;
+    loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] == "analysis_complete" {
+            assert_eq!(resp["id"], "m1");
+            break;
+        }
+        assert_ne!(resp["type"], "error", "unexpected error: {}", resp);
+    }
+    send_json(
+        &mut sink,
+        json!({
+            "type": "analyze",
+            "id": "m2",
+            "startpos": true,
+            "moves": ["e2e4", "e2e5"],
+            "depth": 5
+        }),
     )
     .await;
     let resp = recv_json(&mut stream).await;
-    assert_eq!(resp["type"], "subscribed");
-    let topics = resp["topics"].as_array().unwrap();
-    assert_eq!(topics.len(), 2);
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "m2");
+    assert_eq!(resp["code"], 400);
+    assert!(resp["message"].as_str().unwrap().contains("e2e5"));
+}
 
+#[tokio::test]
+async fn test_ws_analyze_search_moves() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
     send_json(
         &mut sink,
-        json!({"type": "unsubscribe", "id": "u1", "topics": ["metrics"]}),
+        json!({
+            "type": "analyze",
+            "id": "s1",
+            "startpos": true,
+            "moves": ["e2e4"],
+            "search_moves": ["c7c5", "e7e6", "c7c6"],
+            "depth": 5
+        }),
     )
     .await;
+    let result = loop {
+        let resp = recv_json(&mut stream).await;
+        assert_ne!(resp["type"], "error", "unexpected error: {}", resp);
+        if resp["type"] == "analysis_complete" {
+            break resp["result"].clone();
+        }
+        assert_eq!(resp["principal_variations"][0]["moves"][0]["from"], "c7");
+    };
+    let variations = result["principal_variations"].as_array().unwrap();
+    assert_eq!(variations.len(), 3);
+    assert_eq!(variations[1]["moves"][0]["to"], "e6");
 
     send_json(
         &mut sink,
-        json!({"type": "subscribe", "id": "s2", "topics": ["cluster"]}),
+        json!({
+            "type": "analyze",
+            "id": "s2",
+            "startpos": true,
+            "search_moves": ["e2e4", "e7e5"],
+            "depth": 5
+        }),
     )
     .await;
     let resp = recv_json(&mut stream).await;
-    assert_eq!(resp["type"], "subscribed");
-    assert_eq!(resp["topics"], json!(["cluster"]));
-}
-
-#[tokio::test]
-async fn test_ws_multiple_pings() {
-    let server = TestServer::new().await;
-    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
-    for i in 0..5 {
-        send_json(&mut sink, json!({"type": "ping", "id": format!("p{}", i)})).await;
-    }
-    for i in 0..5 {
-        let resp = recv_json(&mut stream).await;
-        assert_eq!(resp["type"], "pong");
-        assert_eq!(resp["id"], format!("p{}", i));
-    }
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "s2");
+    assert_eq!(resp["code"], 400);
+    assert_eq!(resp["error"], "invalid_search_moves");
+    assert!(resp["message"].as_str().unwrap().contains("e7e5"));
 }
 
 #[tokio::test]
-async fn test_ws_analyze_progress_has_evaluation() {
+async fn test_ws_drain_on_shutdown() {
     let server = TestServer::new().await;
     let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
     send_json(
         &mut sink,
         json!({
             "type": "analyze",
-            "id": "a1",
+            "id": "d1",
             "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
-            "depth": 20,
-            "multipv": 1
+            "depth": 20
         }),
     )
     .await;
-    let timeout = tokio::time::Duration::from_secs(10);
-    let deadline = tokio::time::Instant::now() + timeout;
-    let mut progress_count = 0;
-    let mut complete_result: Option<Value> = None;
-    while tokio::time::Instant::now() < deadline {
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "analysis_progress");
+    let drained = server
+        .state
+        .drain(tokio::time::Duration::from_secs(5))
+        .await;
+    assert!(drained);
+    let mut finished = false;
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+    let close_frame = loop {
         match tokio::time::timeout_at(deadline, stream.next()).await {
             Ok(Some(Ok(Message::Text(text)))) => {
                 let resp: Value = serde_json::from_str(&text).expect("parse");
                 match resp["type"].as_str() {
-                    Some("analysis_progress") => {
-                        progress_count += 1;
-                        assert!(resp["target_depth"].as_u64().unwrap() == 20);
-                        assert!(resp["nodes_per_second"].is_u64());
-                        if resp["evaluation"].is_object() {
-                            let eval = &resp["evaluation"];
-                            assert!(eval["score_type"].is_string());
-                            assert!(eval["value"].is_i64());
-                        }
-                        if resp["principal_variations"].is_array() {
-                            let pvs = resp["principal_variations"].as_array().unwrap();
+                    Some("analysis_complete") => {
+                        assert_eq!(resp["result"]["stopped_by"], "cancelled");
+                        finished = true;
+                    }
+                    Some("analysis_cancelled") => finished = true,
+                    _ => {}
+                }
+            }
+            Ok(Some(Ok(Message::Close(frame)))) => break frame,
+            Ok(Some(Ok(_))) => {}
+            other => panic!("connection did not close cleanly: {:?}", other),
+        }
+    };
+    assert!(finished);
+    let frame = close_frame.expect("close frame");
+    assert_eq!(u16::from(frame.code), 1001);
+    assert_eq!(frame.reason.as_str(), "server shutting down");
+    let response = reqwest::Client::new()
+        .post(server.url("/v1/analyze"))
+        .bearer_auth(&server.token)
+        .json(&json!({"fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "shutting_down");
+}
+
+#[tokio::test]
+async fn test_ws_subscribe_and_unsubscribe() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "subscribe", "id": "s1", "topics": ["cluster", "metrics"]}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "subscribed");
+    let topics = resp["topics"].as_array().unwrap();
+    assert_eq!(topics.len(), 2);
+
+    send_json(
+        &mut sink,
+        json!({"type": "unsubscribe", "id": "u1", "topics": ["metrics"]}),
+    )
+    .await;
+
+    send_json(
+        &mut sink,
+        json!({"type": "subscribe", "id": "s2", "topics": ["cluster"]}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "subscribed");
+    assert_eq!(resp["topics"], json!(["cluster"]));
+}
+
+#[tokio::test]
+async fn test_ws_multiple_pings() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    for i in 0..5 {
+        send_json(&mut sink, json!({"type": "ping", "id": format!("p{}", i)})).await;
+    }
+    for i in 0..5 {
+        let resp = recv_json(&mut stream).await;
+        assert_eq!(resp["type"], "pong");
+        assert_eq!(resp["id"], format!("p{}", i));
+    }
+}
+
+#[tokio::test]
+async fn test_ws_analyze_progress_has_evaluation() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({
+            "type": "analyze",
+            "id": "a1",
+            "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "depth": 20,
+            "multipv": 1
+        }),
+    )
+    .await;
+    let timeout = tokio::time::Duration::from_secs(10);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut progress_count = 0;
+    let mut complete_result: Option<Value> = None;
+    while tokio::time::Instant::now() < deadline {
+        match tokio::time::timeout_at(deadline, stream.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                let resp: Value = serde_json::from_str(&text).expect("parse");
+                match resp["type"].as_str() {
+                    Some("analysis_progress") => {
+                        progress_count += 1;
+                        assert!(resp["target_depth"].as_u64().unwrap() == 20);
+                        assert!(resp["nodes_per_second"].is_u64());
+                        if resp["evaluation"].is_object() {
+                            let eval = &resp["evaluation"];
+                            assert!(eval["score_type"].is_string());
+                            assert!(eval["value"].is_i64());
+                        }
+                        if resp["principal_variations"].is_array() {
+                            let pvs = resp["principal_variations"].as_array().unwrap();
                             if !pvs.is_empty() {
                                 assert!(pvs[0]["rank"].is_u64());
                                 assert!(pvs[0]["moves"].is_array());
@@ -525,7 +1029,8 @@ async fn test_ws_bestmove_invalid_fen() {
             Ok(Some(Ok(Message::Text(text)))) => {
                 let resp: Value = serde_json::from_str(&text).expect("parse");
                 if resp["type"] == "error" {
-                    assert_eq!(resp["code"], 500);
+                    assert_eq!(resp["code"], 400);
+                    assert_eq!(resp["error"], "invalid_fen");
                     assert!(resp["message"].as_str().unwrap().contains("invalid FEN"));
                     break;
                 }
@@ -657,3 +1162,662 @@ async fn test_ws_analyze_with_defaults() {
         "should receive analysis_complete with default depth/multipv"
     );
 }
+
+#[tokio::test]
+async fn test_ws_token_scopes_enforced() {
+    let server = TestServer::new().await;
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "scopes": ["ws", "bestmove"] }))
+        .await;
+    let created: Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let (mut sink, mut stream) = server.ws_connect(Some(token)).await;
+    send_json(
+        &mut sink,
+        json!({
+            "type": "analyze",
+            "id": "a1",
+            "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "depth": 10,
+            "multipv": 1
+        }),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["id"], "a1");
+    assert_eq!(resp["code"], 403);
+    send_json(
+        &mut sink,
+        json!({
+            "type": "bestmove",
+            "id": "b1",
+            "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        }),
+    )
+    .await;
+    let resp = loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] != "pong" {
+            break resp;
+        }
+    };
+    assert_eq!(resp["type"], "bestmove_result");
+    assert_eq!(resp["id"], "b1");
+}
+
+#[tokio::test]
+async fn test_ws_cluster_topics_require_cluster_read() {
+    let server = TestServer::new().await;
+    let resp = server
+        .admin_post_json("/_admin/tokens", &json!({ "scopes": ["ws"] }))
+        .await;
+    let created: Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let (mut sink, mut stream) = server.ws_connect(Some(token)).await;
+    for (id, topic) in [("s1", "cluster"), ("s2", "metrics")] {
+        send_json(
+            &mut sink,
+            json!({"type": "subscribe", "id": id, "topics": [topic]}),
+        )
+        .await;
+        let resp = recv_json(&mut stream).await;
+        assert_eq!(resp["type"], "error");
+        assert_eq!(resp["id"], id);
+        assert_eq!(resp["code"], 403);
+        assert_eq!(resp["error"], "missing_scope");
+    }
+    let resp = server
+        .admin_post_json(
+            "/_admin/tokens",
+            &json!({ "scopes": ["ws", "cluster:read"] }),
+        )
+        .await;
+    let created: Value = resp.json().await.expect("json");
+    let token = created["token"].as_str().unwrap();
+    let (mut sink, mut stream) = server.ws_connect(Some(token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "subscribe", "id": "s3", "topics": ["cluster", "metrics"]}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "subscribed");
+    assert_eq!(resp["id"], "s3");
+}
+
+#[tokio::test]
+async fn test_ws_event_publisher_stops_when_sessions_close() {
+    let server = TestServer::new().await;
+    let tasks = ironfish_api::ws::spawn_event_publisher(server.state.clone());
+    assert!(!tasks.is_empty());
+    server
+        .state
+        .ws_sessions
+        .close_all(tokio::time::Duration::from_secs(1))
+        .await;
+    for task in tasks {
+        tokio::time::timeout(tokio::time::Duration::from_secs(2), task)
+            .await
+            .expect("publisher task stopped")
+            .expect("publisher task");
+    }
+}
+
+async fn expect_close_code(
+    stream: &mut futures_util::stream::SplitStream<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    >,
+) -> u16 {
+    let timeout = tokio::time::Duration::from_secs(5);
+    loop {
+        let msg = tokio::time::timeout(timeout, stream.next())
+            .await
+            .expect("recv timeout")
+            .expect("stream ended")
+            .expect("ws error");
+        match msg {
+            Message::Close(Some(frame)) => return u16::from(frame.code),
+            Message::Text(_) => continue,
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ws_node_session_limit() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        max_connections: 2,
+        ..Default::default()
+    })
+    .await;
+    let mut open = Vec::new();
+    for i in 0..2 {
+        let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+        send_json(&mut sink, json!({"type": "ping", "id": i.to_string()})).await;
+        assert_eq!(recv_json(&mut stream).await["type"], "pong");
+        open.push((sink, stream));
+    }
+    let (_sink, mut stream) = server.ws_connect_raw(Some(&server.token)).await;
+    assert_eq!(expect_close_code(&mut stream).await, 1013);
+}
+
+#[tokio::test]
+async fn test_ws_token_session_limit() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        max_sessions_per_token: 2,
+        ..Default::default()
+    })
+    .await;
+    let mut open = Vec::new();
+    for i in 0..2 {
+        let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+        send_json(&mut sink, json!({"type": "ping", "id": i.to_string()})).await;
+        assert_eq!(recv_json(&mut stream).await["type"], "pong");
+        open.push((sink, stream));
+    }
+    let (_sink, mut stream) = server.ws_connect_raw(Some(&server.token)).await;
+    assert_eq!(expect_close_code(&mut stream).await, 1013);
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "auth", "id": "1", "token": server.token}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "auth_result");
+    assert_eq!(resp["success"], false);
+    assert_eq!(expect_close_code(&mut stream).await, 1013);
+    drop(open.pop());
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(&mut sink, json!({"type": "ping", "id": "again"})).await;
+    assert_eq!(recv_json(&mut stream).await["type"], "pong");
+}
+
+#[tokio::test]
+async fn test_ws_stalled_reader_does_not_block_analysis() {
+    use ironfish_api::ws::session::WsSession;
+    use ironfish_api::ws::SessionSender;
+    let server = TestServer::new().await;
+    let config = ironfish_api::WebSocketConfig {
+        slow_consumer_timeout_ms: 100,
+        ..Default::default()
+    };
+    let (tx, _rx) = tokio::sync::mpsc::channel(1);
+    let sender = SessionSender::new(tx, &config, Default::default());
+    let mut session = WsSession::new(
+        uuid::Uuid::new_v4(),
+        sender.clone(),
+        server.state.clone(),
+        4,
+    );
+    session.authenticated = true;
+    let request: ironfish_core::ClientMessage = serde_json::from_value(json!({
+        "type": "analyze",
+        "id": "stalled",
+        "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "depth": 20,
+        "multipv": 1
+    }))
+    .expect("analyze message");
+    session.handle_message(request).await;
+    let analysis_id = *session
+        .active_analyses
+        .lock()
+        .await
+        .keys()
+        .next()
+        .expect("analysis started");
+    let finished = tokio::time::timeout(tokio::time::Duration::from_secs(10), async {
+        loop {
+            if let Some(entry) = server.state.analyses.get(&analysis_id).await {
+                if entry.is_finished() {
+                    return entry;
+                }
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("analysis should finish despite the stalled reader");
+    assert_eq!(finished.status(), "complete");
+    assert!(sender.dropped() > 0);
+    tokio::time::timeout(
+        tokio::time::Duration::from_secs(2),
+        sender.stalled().cancelled(),
+    )
+    .await
+    .expect("stalled session should be flagged for disconnect");
+}
+
+#[tokio::test]
+async fn test_drain_finishes_in_flight_and_refuses_new_analyses() {
+    let server = TestServer::new().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    let analyze = |id: &str| {
+        json!({
+            "type": "analyze",
+            "id": id,
+            "startpos": true,
+            "depth": 20
+        })
+    };
+    send_json(&mut sink, analyze("before")).await;
+    let progress = recv_json(&mut stream).await;
+    assert_eq!(progress["type"], "analysis_progress");
+
+    let resp = server
+        .admin_post_json("/_admin/node/drain", &json!({}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    let drained: Value = resp.json().await.expect("json");
+    assert_eq!(drained["draining"], true);
+    assert_eq!(drained["active_analyses"], 1);
+
+    send_json(&mut sink, analyze("after")).await;
+    let mut refused = false;
+    let mut completed = false;
+    while !(refused && completed) {
+        let resp = recv_json(&mut stream).await;
+        match resp["type"].as_str() {
+            Some("error") => {
+                assert_eq!(resp["id"], "after");
+                assert_eq!(resp["code"], 503);
+                assert_eq!(resp["error"], "node_draining");
+                refused = true;
+            }
+            Some("analysis_complete") => {
+                assert_eq!(resp["id"], "before");
+                completed = true;
+            }
+            _ => {}
+        }
+    }
+
+    let resp = server
+        .post_json("/v1/analyze", &json!({"startpos": true, "depth": 10}))
+        .await;
+    assert_eq!(resp.status(), 503);
+    let body: Value = resp.json().await.expect("json");
+    assert_eq!(body["error"]["code"], "node_draining");
+    let body: Value = server
+        .post_json(
+            "/graphql",
+            &json!({ "query": "mutation { startAnalysis(fen: \"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\") }" }),
+        )
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert!(body["data"].is_null());
+    assert!(body["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("draining"));
+    let resp = server.get("/health").await;
+    assert_eq!(resp.status(), 503);
+    let body: Value = resp.json().await.expect("json");
+    assert_eq!(body["status"], "draining");
+    let health: Value = server.get("/v1/health").await.json().await.expect("json");
+    assert_eq!(health["status"], "draining");
+    let status: Value = server
+        .admin_get("/_admin/cluster/status")
+        .await
+        .json()
+        .await
+        .expect("json");
+    assert_eq!(status["nodes"][0]["draining"], true);
+
+    let resp = server
+        .admin_post_json("/_admin/node/resume", &json!({}))
+        .await;
+    let resumed: Value = resp.json().await.expect("json");
+    assert_eq!(resumed["draining"], false);
+    let resp = server
+        .post_json("/v1/analyze", &json!({"startpos": true, "depth": 10}))
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(server.get("/health").await.status(), 200);
+}
+
+async fn start_detached_analysis(server: &TestServer) -> (String, String) {
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "auth", "id": "1", "token": server.token}),
+    )
+    .await;
+    let auth = recv_json(&mut stream).await;
+    assert_eq!(auth["success"], true);
+    let resume_token = auth["resume_token"]
+        .as_str()
+        .expect("resume token")
+        .to_string();
+    send_json(
+        &mut sink,
+        json!({"type": "analyze_infinite", "id": "inf", "startpos": true}),
+    )
+    .await;
+    let progress = recv_json(&mut stream).await;
+    assert_eq!(progress["type"], "analysis_progress");
+    let analysis_id = progress["analysis_id"].as_str().unwrap().to_string();
+    sink.close().await.expect("close");
+    drop(stream);
+    tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+        while server.state.ws_sessions.detached_count().await == 0 {
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("session detached");
+    (resume_token, analysis_id)
+}
+
+#[tokio::test]
+async fn test_ws_resume_reattaches_running_analysis() {
+    let server = TestServer::new().await;
+    let (resume_token, analysis_id) = start_detached_analysis(&server).await;
+
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "resume", "id": "r1", "resume_token": resume_token}),
+    )
+    .await;
+    let mut resumed = false;
+    let mut replayed = false;
+    while !(resumed && replayed) {
+        let resp = recv_json(&mut stream).await;
+        match resp["type"].as_str() {
+            Some("resumed") => {
+                assert_eq!(resp["id"], "r1");
+                assert_eq!(resp["analysis_ids"], json!([analysis_id]));
+                resumed = true;
+            }
+            Some("analysis_progress") => {
+                assert_eq!(resp["analysis_id"], analysis_id.as_str());
+                replayed = true;
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+    assert_eq!(server.state.ws_sessions.detached_count().await, 0);
+
+    send_json(
+        &mut sink,
+        json!({"type": "cancel", "id": "c1", "analysis_id": analysis_id}),
+    )
+    .await;
+    let (resp, _) = recv_infinite_result(&mut stream).await;
+    assert_eq!(resp["id"], "inf");
+    assert_eq!(resp["result"]["stopped_by"], "cancelled");
+
+    send_json(
+        &mut sink,
+        json!({"type": "resume", "id": "r2", "resume_token": resume_token}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "resume_not_found");
+}
+
+#[tokio::test]
+async fn test_ws_unclaimed_analyses_cancelled_after_grace() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        resume_grace_secs: 1,
+        ..Default::default()
+    })
+    .await;
+    let (resume_token, _) = start_detached_analysis(&server).await;
+    assert_eq!(server.state.analysis.active_analyses(), 1);
+
+    tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+        while server.state.analysis.active_analyses() > 0 {
+            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("analysis cancelled after grace period");
+    assert_eq!(server.state.ws_sessions.detached_count().await, 0);
+
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "resume", "id": "r1", "resume_token": resume_token}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["code"], 404);
+    assert_eq!(resp["error"], "resume_not_found");
+}
+
+#[tokio::test]
+async fn test_ws_detached_analyses_count_against_session_limits() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        max_sessions_per_token: 1,
+        max_analyses_per_session: 1,
+        ..Default::default()
+    })
+    .await;
+    let (resume_token, analysis_id) = start_detached_analysis(&server).await;
+
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a1", "startpos": true, "depth": 5}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["code"], 429);
+    assert_eq!(resp["error"], "too_many_sessions");
+
+    send_json(
+        &mut sink,
+        json!({"type": "resume", "id": "r1", "resume_token": resume_token}),
+    )
+    .await;
+    loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] == "resumed" {
+            assert_eq!(resp["analysis_ids"], json!([analysis_id]));
+            break;
+        }
+    }
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "a2", "startpos": true, "depth": 5}),
+    )
+    .await;
+    loop {
+        let resp = recv_json(&mut stream).await;
+        if resp["type"] == "error" {
+            assert_eq!(resp["id"], "a2");
+            assert_eq!(resp["error"], "too_many_analyses");
+            break;
+        }
+    }
+}
+
+async fn test_token_id(server: &TestServer) -> String {
+    let tokens: Vec<Value> = server
+        .admin_get("/_admin/tokens")
+        .await
+        .json()
+        .await
+        .expect("json");
+    let token = tokens
+        .iter()
+        .find(|t| t["name"] == "test-token")
+        .expect("test token");
+    token["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_ws_session_closed_when_token_revoked() {
+    let server = TestServer::with_auth().await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(&mut sink, json!({"type": "ping", "id": "p1"})).await;
+    assert_eq!(recv_json(&mut stream).await["type"], "pong");
+    let id = test_token_id(&server).await;
+    let resp = server.admin_delete(&format!("/_admin/tokens/{}", id)).await;
+    assert_eq!(resp.status(), 200);
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "error");
+    assert_eq!(resp["code"], 401);
+    assert_eq!(resp["error"], "token_revoked");
+    assert_eq!(expect_close_code(&mut stream).await, 1008);
+}
+
+#[tokio::test]
+async fn test_ws_analyze_refused_after_token_revoked() {
+    let server = TestServer::with_auth().await;
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "auth", "id": "a1", "token": server.token}),
+    )
+    .await;
+    assert_eq!(recv_json(&mut stream).await["success"], true);
+    let id = uuid::Uuid::parse_str(&test_token_id(&server).await).unwrap();
+    server.token_store.revoke(&id).await.unwrap();
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "x1", "startpos": true, "depth": 5}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "token_revoked");
+    assert_eq!(expect_close_code(&mut stream).await, 1008);
+}
+
+#[tokio::test]
+async fn test_ws_session_revalidates_token_periodically() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        token_revalidate_secs: 1,
+        ..Default::default()
+    })
+    .await;
+    let (_sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    let id = uuid::Uuid::parse_str(&test_token_id(&server).await).unwrap();
+    server.token_store.revoke(&id).await.unwrap();
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "token_revoked");
+    assert_eq!(expect_close_code(&mut stream).await, 1008);
+}
+
+#[tokio::test]
+async fn test_ws_session_fails_closed_when_token_store_keeps_failing() {
+    let server = TestServer::with_ws_config(ironfish_api::WebSocketConfig {
+        token_revalidate_secs: 1,
+        token_revalidate_max_failures: 2,
+        ..Default::default()
+    })
+    .await;
+    let (_sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    server.token_store.set_broken(true);
+    let started = tokio::time::Instant::now();
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "token_revoked");
+    assert!(started.elapsed() >= tokio::time::Duration::from_millis(1500));
+    assert_eq!(expect_close_code(&mut stream).await, 1008);
+}
+
+#[tokio::test]
+async fn test_ws_service_token_is_single_use_and_bound_to_request() {
+    let server = TestServer::new().await;
+    let request_id = uuid::Uuid::new_v4();
+    let token = server
+        .state
+        .token_manager
+        .issue_service_token(chrono::Duration::seconds(10), None, request_id)
+        .unwrap();
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "auth", "id": "1", "token": token}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["success"], true);
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "other", "startpos": true, "depth": 5}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["error"], "relay_request_mismatch");
+    let (mut sink, mut stream) = server.ws_connect(None).await;
+    send_json(
+        &mut sink,
+        json!({"type": "auth", "id": "1", "token": token}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "auth_result");
+    assert_eq!(resp["success"], false);
+}
+
+#[tokio::test]
+async fn test_ws_analysis_relayed_to_peer() {
+    let peer = TestServer::new().await;
+    let server = TestServer::forwarding_to(&peer).await;
+    let (mut sink, mut stream) = server.ws_connect(Some(&server.token)).await;
+    send_json(
+        &mut sink,
+        json!({"type": "analyze", "id": "r1", "startpos": true, "depth": 20}),
+    )
+    .await;
+    let mut progress_ids = Vec::new();
+    let complete = loop {
+        let resp = recv_json(&mut stream).await;
+        match resp["type"].as_str() {
+            Some("analysis_progress") => progress_ids.push(resp["analysis_id"].clone()),
+            Some("analysis_complete") => break resp,
+            other => panic!("unexpected message: {:?}", other),
+        }
+    };
+    assert!(
+        progress_ids.len() >= 2,
+        "only {} progress events",
+        progress_ids.len()
+    );
+    assert_eq!(complete["id"], "r1");
+    let id = complete["result"]["id"].clone();
+    assert!(progress_ids.iter().all(|progress| *progress == id));
+    let id = uuid::Uuid::parse_str(id.as_str().unwrap()).unwrap();
+    assert_eq!(
+        server.state.analyses.get(&id).await.unwrap().status(),
+        "complete"
+    );
+    assert!(peer.state.analyses.get(&id).await.is_none());
+    assert_eq!(peer.state.analyses.len().await, 1);
+
+    send_json(
+        &mut sink,
+        json!({"type": "analyze_infinite", "id": "r2", "startpos": true}),
+    )
+    .await;
+    let resp = recv_json(&mut stream).await;
+    assert_eq!(resp["type"], "analysis_progress");
+    assert_eq!(peer.state.analysis.active_analyses(), 1);
+    send_json(
+        &mut sink,
+        json!({"type": "cancel", "id": "c1", "analysis_id": resp["analysis_id"]}),
+    )
+    .await;
+    let (resp, _) = recv_infinite_result(&mut stream).await;
+    assert_eq!(resp["id"], "r2");
+    assert_eq!(resp["result"]["stopped_by"], "cancelled");
+    for _ in 0..50 {
+        if peer.state.analysis.active_analyses() == 0 {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(peer.state.analysis.active_analyses(), 0);
+    assert!(peer.state.analyses.running().await.is_empty());
+}